@@ -0,0 +1,21 @@
+// Ambient light projected into spherical harmonics, from the frame uniform buffer.
+//
+// The coefficients are already convolved with the cosine lobe and divided by
+// pi, so the result multiplies the color of a diffuse surface.
+//
+// Must match sh_basis.
+vec3 evaluateAmbient(vec3 normal) {
+    float x = normal.x;
+    float y = normal.y;
+    float z = normal.z;
+    vec3 ambient = ubo.ambient[0].rgb * 0.282095
+        + ubo.ambient[1].rgb * 0.488603 * y
+        + ubo.ambient[2].rgb * 0.488603 * z
+        + ubo.ambient[3].rgb * 0.488603 * x
+        + ubo.ambient[4].rgb * 1.092548 * x * y
+        + ubo.ambient[5].rgb * 1.092548 * y * z
+        + ubo.ambient[6].rgb * 0.315392 * (3.0 * z * z - 1.0)
+        + ubo.ambient[7].rgb * 1.092548 * x * z
+        + ubo.ambient[8].rgb * 0.546274 * (x * x - y * y);
+    return max(ambient, vec3(0.0));
+}
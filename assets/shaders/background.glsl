@@ -0,0 +1,10 @@
+// Uniforms of the gradient and the grid drawn in the background.
+//
+// Must match BackgroundUniforms.
+layout(set = 1, binding = 0) uniform BackgroundUniforms {
+    vec4 bottomColor;
+    vec4 topColor;
+    vec4 gridColor;
+    float gridSpacing;
+    float gridFadeDistance;
+} background;
@@ -0,0 +1,77 @@
+// Fragment shader of the bilateral upsample, shared by bilateral_upsample.frag
+// and bilateral_upsample_ms.frag.
+//
+// Define MULTISAMPLED before including it when the depth buffer is multisampled.
+
+#include "frame.glsl"
+
+layout(set = 1, binding = 0) uniform sampler2D hdrColor;
+layout(set = 1, binding = 1) uniform sampler2D halfResEffect;
+
+#ifdef MULTISAMPLED
+layout(set = 1, binding = 2) uniform sampler2DMS depthBuffer;
+
+ivec2 depthSize() {
+    return textureSize(depthBuffer);
+}
+
+float loadDepth(ivec2 coords) {
+    return texelFetch(depthBuffer, coords, 0).r;
+}
+#else
+layout(set = 1, binding = 2) uniform sampler2D depthBuffer;
+
+ivec2 depthSize() {
+    return textureSize(depthBuffer, 0);
+}
+
+float loadDepth(ivec2 coords) {
+    return texelFetch(depthBuffer, coords, 0).r;
+}
+#endif
+
+layout(location = 0) in vec2 fragCoords;
+
+layout(location = 0) out vec4 outColor;
+
+// How fast the weight of a texel falls with the relative difference between
+// its distance to the camera and the one of the pixel.
+const float DEPTH_SHARPNESS = 32.0;
+
+// Distance to the camera of the surface seen through the pixel `coords`.
+float viewDistance(ivec2 coords) {
+    vec2 ndc = (vec2(coords) + 0.5) / vec2(depthSize()) * 2.0 - 1.0;
+    vec4 position = ubo.inverseViewProj * vec4(ndc, loadDepth(coords), 1.0);
+    return length(position.xyz / position.w - ubo.cameraPosition.xyz);
+}
+
+void main() {
+    ivec2 size = depthSize();
+    ivec2 halfSize = textureSize(halfResEffect, 0);
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    float distance = viewDistance(pixel);
+
+    // Position of the pixel among the centers of the half resolution texels
+    vec2 halfPosition = (vec2(pixel) + 0.5) * vec2(halfSize) / vec2(size) - 0.5;
+    ivec2 base = ivec2(floor(halfPosition));
+    vec2 fraction = halfPosition - vec2(base);
+
+    // Bilinear weights of the 4 closest texels, lowered for the texels whose
+    // surface is at another distance so the effect does not bleed across edges
+    vec3 effect = vec3(0.0);
+    float totalWeight = 0.0;
+    for (int y = 0; y < 2; y++) {
+        for (int x = 0; x < 2; x++) {
+            ivec2 texel = clamp(base + ivec2(x, y), ivec2(0), halfSize - 1);
+            ivec2 source = min((texel * size) / halfSize, size - 1);
+            float difference = abs(viewDistance(source) - distance) / max(distance, 0.0001);
+            vec2 bilinear = mix(1.0 - fraction, fraction, vec2(x, y));
+            float weight = bilinear.x * bilinear.y / (1.0 + DEPTH_SHARPNESS * difference);
+            effect += texelFetch(halfResEffect, texel, 0).rgb * weight;
+            totalWeight += weight;
+        }
+    }
+    effect /= max(totalWeight, 0.0001);
+
+    outColor = vec4(texture(hdrColor, fragCoords).rgb + effect, 1.0);
+}
@@ -0,0 +1,37 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_GOOGLE_include_directive : require
+#ifdef RAY_QUERY_SHADOWS
+#extension GL_EXT_ray_query : require
+#endif
+
+#include "frame.glsl"
+#include "material.glsl"
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragCoords;
+layout(location = 2) in vec3 fragWorldPosition;
+
+struct Material {
+    uint textureIndex;
+};
+
+layout(set = 2, binding = 0) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(set = 2, binding = 1) uniform sampler2D textures[];
+
+layout(push_constant) uniform PushConstants {
+    uint materialIndex;
+} pushConstants;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    Material material = materials[pushConstants.materialIndex];
+    vec4 color = texture(textures[nonuniformEXT(material.textureIndex)], fragCoords);
+    color = applyMaterial(color*vec4(fragColor, 1.0), fragWorldPosition);
+    outColor = vec4(applyFog(color.rgb, fragWorldPosition), color.a);
+}
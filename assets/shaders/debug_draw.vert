@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 1) in vec4 vColor;
+
+layout(location = 0) out vec4 fragColor;
+
+void main() {
+    // Lines are given in world space.
+    gl_Position = ubo.proj * ubo.view * vec4(vPosition, 1.0);
+    fragColor = vColor;
+}
@@ -0,0 +1,59 @@
+// Fragment shader of the decals, shared by decal.frag and decal_ms.frag.
+//
+// Define MULTISAMPLED before including it when the depth attachment is multisampled.
+
+#include "frame.glsl"
+#include "decal_uniforms.glsl"
+
+#ifdef MULTISAMPLED
+layout(input_attachment_index = 0, set = 1, binding = 0) uniform subpassInputMS depthInput;
+
+float loadDepth() {
+    return subpassLoad(depthInput, 0).r;
+}
+#else
+layout(input_attachment_index = 0, set = 1, binding = 0) uniform subpassInput depthInput;
+
+float loadDepth() {
+    return subpassLoad(depthInput).r;
+}
+#endif
+layout(set = 1, binding = 1) uniform sampler2D decalTexture;
+
+layout(location = 0) in vec4 fragClipPosition;
+layout(location = 1) flat in uint fragDecalIndex;
+
+layout(location = 0) out vec4 outColor;
+
+// Surfaces whose normal is further than this from the projection axis are
+// not affected, the texture would be too stretched.
+const float MIN_FACING = 0.3;
+
+void main() {
+    float depth = loadDepth();
+
+    vec2 ndc = fragClipPosition.xy / fragClipPosition.w;
+    vec4 position = ubo.inverseViewProj * vec4(ndc, depth, 1.0);
+    vec3 worldPosition = position.xyz / position.w;
+    // Computed before any discard so the derivatives are defined.
+    vec3 normal = normalize(cross(dFdx(worldPosition), dFdy(worldPosition)));
+
+    // Nothing was drawn there.
+    if (depth >= 1.0) {
+        discard;
+    }
+
+    Decal decal = decalUbo.decals[fragDecalIndex];
+    vec3 localPosition = (decal.worldToDecal * vec4(worldPosition, 1.0)).xyz;
+    if (any(greaterThan(abs(localPosition), vec3(0.5)))) {
+        discard;
+    }
+
+    vec3 axis = normalize(decal.decalToWorld[1].xyz);
+    if (abs(dot(normal, axis)) < MIN_FACING) {
+        discard;
+    }
+
+    vec4 color = texture(decalTexture, localPosition.xz + 0.5) * decal.color;
+    outColor = vec4(applyFog(color.rgb, worldPosition), color.a);
+}
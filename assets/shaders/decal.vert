@@ -0,0 +1,30 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+#include "decal_uniforms.glsl"
+
+// Corners of the triangles of the unit box, wound counter clockwise when
+// seen from the outside. Bits 0, 1 and 2 of a corner are its x, y and z.
+const uint BOX_CORNERS[36] = uint[](
+    1, 3, 7, 1, 7, 5,
+    0, 6, 2, 0, 4, 6,
+    2, 6, 7, 2, 7, 3,
+    0, 5, 4, 0, 1, 5,
+    4, 5, 7, 4, 7, 6,
+    0, 3, 1, 0, 2, 3
+);
+
+layout(location = 0) out vec4 fragClipPosition;
+layout(location = 1) flat out uint fragDecalIndex;
+
+void main() {
+    uint corner = BOX_CORNERS[gl_VertexIndex];
+    vec3 localPosition = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1) - 0.5;
+    vec4 worldPosition = decalUbo.decals[gl_InstanceIndex].decalToWorld * vec4(localPosition, 1.0);
+
+    gl_Position = ubo.proj * ubo.view * worldPosition;
+    fragClipPosition = gl_Position;
+    fragDecalIndex = gl_InstanceIndex;
+}
@@ -0,0 +1,15 @@
+// Decals projected on the scene.
+
+// Must match MAX_DECALS.
+const uint MAX_DECALS = 16;
+
+// Must match DecalUniform.
+struct Decal {
+    mat4 decalToWorld;
+    mat4 worldToDecal;
+    vec4 color;
+};
+
+layout(set = 1, binding = 2) uniform Decals {
+    Decal decals[MAX_DECALS];
+} decalUbo;
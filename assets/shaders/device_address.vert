@@ -0,0 +1,56 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_EXT_buffer_reference : require
+#extension GL_GOOGLE_include_directive : require
+
+// Vertices are laid out as in the Vertex struct: position, color, coords and normal.
+const uint VERTEX_SIZE = 11;
+
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer Vertices {
+    float data[];
+};
+
+#include "frame.glsl"
+
+layout(push_constant) uniform PushConstants {
+    layout(offset = 8) Vertices vertices;
+} pc;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec2 fragCoords;
+layout(location = 2) out vec3 fragWorldPosition;
+layout(location = 3) out vec3 fragWorldNormal;
+
+// With the LIGHTMAP keyword, the second UV set of the model is passed to the material.
+#ifdef LIGHTMAP
+layout(location = 4) in vec2 vLightmapCoords;
+layout(location = 4) out vec2 fragLightmapCoords;
+#endif
+
+// With the CLIP_PLANES keyword, the vertices are clipped by the planes of the frame.
+#ifdef CLIP_PLANES
+out float gl_ClipDistance[MAX_CLIP_PLANES];
+#endif
+
+void main() {
+    uint base = gl_VertexIndex * VERTEX_SIZE;
+    vec3 position = vec3(pc.vertices.data[base], pc.vertices.data[base + 1], pc.vertices.data[base + 2]);
+    vec3 color = vec3(pc.vertices.data[base + 3], pc.vertices.data[base + 4], pc.vertices.data[base + 5]);
+    vec2 coords = vec2(pc.vertices.data[base + 6], pc.vertices.data[base + 7]);
+    vec3 normal = vec3(pc.vertices.data[base + 8], pc.vertices.data[base + 9], pc.vertices.data[base + 10]);
+
+    vec4 worldPosition = ubo.model * vec4(position, 1.0);
+    gl_Position = ubo.proj * ubo.view * worldPosition;
+    fragColor = color;
+    fragCoords = coords;
+    fragWorldPosition = worldPosition.xyz;
+    fragWorldNormal = mat3(ubo.model) * normal;
+#ifdef LIGHTMAP
+    fragLightmapCoords = vLightmapCoords;
+#endif
+#ifdef CLIP_PLANES
+    for (uint i = 0; i < MAX_CLIP_PLANES; i++) {
+        gl_ClipDistance[i] = i < ubo.clipPlaneCount ? dot(ubo.clipPlanes[i], worldPosition) : 1.0;
+    }
+#endif
+}
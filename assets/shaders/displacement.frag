@@ -0,0 +1,40 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+// Must match PLANE_SIZE.
+const float PLANE_SIZE = 6.0;
+// Must match HEIGHT_SCALE in displacement.tese.
+const float HEIGHT_SCALE = 0.3;
+const vec3 LIGHT_DIRECTION = normalize(vec3(1.0, 2.0, 1.0));
+const vec3 LOW_COLOR = vec3(0.25, 0.35, 0.15);
+const vec3 HIGH_COLOR = vec3(0.55, 0.5, 0.4);
+
+layout(set = 1, binding = 0) uniform sampler2D heightMap;
+
+layout(location = 0) in vec2 fragCoords;
+layout(location = 1) in vec3 fragWorldPosition;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec2 texelSize = 1.0 / vec2(textureSize(heightMap, 0));
+    float left = texture(heightMap, fragCoords - vec2(texelSize.x, 0.0)).r;
+    float right = texture(heightMap, fragCoords + vec2(texelSize.x, 0.0)).r;
+    float down = texture(heightMap, fragCoords - vec2(0.0, texelSize.y)).r;
+    float up = texture(heightMap, fragCoords + vec2(0.0, texelSize.y)).r;
+
+    // Central differences of the height in world units.
+    float dx = (right - left) * HEIGHT_SCALE / (2.0 * texelSize.x * PLANE_SIZE);
+    float dz = (up - down) * HEIGHT_SCALE / (2.0 * texelSize.y * PLANE_SIZE);
+    vec3 normal = normalize(vec3(-dx, 1.0, -dz));
+
+    float height = texture(heightMap, fragCoords).r;
+    vec3 albedo = mix(LOW_COLOR, HIGH_COLOR, height);
+    float diffuse = max(dot(normal, LIGHT_DIRECTION), 0.0);
+
+    vec3 color = albedo * (0.2 + 0.8 * diffuse);
+    outColor = vec4(applyFog(color, fragWorldPosition), 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 1) in vec2 vCoords;
+
+layout(location = 0) out vec2 tescCoords;
+
+void main() {
+    gl_Position = vec4(vPosition, 1.0);
+    tescCoords = vCoords;
+}
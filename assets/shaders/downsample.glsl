@@ -0,0 +1,145 @@
+// Single pass downsampler generating the levels of the bloom chain from the
+// HDR image.
+//
+// Each work group reduces a tile of 64x64 texels of the HDR image to the first
+// 6 levels, then the last group to finish reduces the sixth level to the next
+// 6 levels. The 256 invocations of a group hold the texels of a level in Morton
+// order, so the 4 texels of a 2x2 block are held by consecutive invocations,
+// which form a quad of a subgroup. With the SUBGROUP_QUAD keyword the blocks
+// are averaged with quad operations, and through shared memory otherwise.
+
+// Must match MAX_BLOOM_LEVELS.
+const uint MAX_LEVELS = 12;
+
+// Number of levels generated from a tile by a work group.
+const uint TILE_LEVELS = 6;
+
+layout(local_size_x = 256) in;
+
+layout(binding = 0) uniform sampler2D source;
+
+// The last level of the first tiles is read by another group.
+layout(binding = 1, rgba16f) uniform coherent image2D levels[MAX_LEVELS];
+
+layout(binding = 2) coherent buffer Counter {
+    uint finishedGroups;
+} counter;
+
+layout(push_constant) uniform PushConstants {
+    uint levelCount;
+} pc;
+
+shared vec4 blocks[256];
+shared bool isLastGroup;
+
+// Get the coordinates of the texel at index in Morton order, in a block of 16x16 texels.
+ivec2 mortonCoords(uint index) {
+    uvec2 coords = uvec2(index, index >> 1) & 0x55u;
+    coords = (coords | (coords >> 1)) & 0x33u;
+    coords = (coords | (coords >> 2)) & 0x0fu;
+    return ivec2(coords);
+}
+
+void storeTexel(uint level, ivec2 coords, vec4 value) {
+    if (level < pc.levelCount && all(lessThan(coords, imageSize(levels[level])))) {
+        imageStore(levels[level], coords, value);
+    }
+}
+
+// Get the average of the 2x2 texels of the level above level covered by the
+// texel at coords.
+vec4 loadTexel(uint level, ivec2 coords) {
+    if (level == 0) {
+        // A bilinear sample between 4 texels averages them
+        vec2 size = vec2(textureSize(source, 0));
+        return textureLod(source, (vec2(coords) * 2.0 + 1.0) / size, 0.0);
+    }
+
+    ivec2 lastCoords = imageSize(levels[level - 1]) - 1;
+    ivec2 sourceCoords = coords * 2;
+    return 0.25 * (imageLoad(levels[level - 1], min(sourceCoords, lastCoords))
+        + imageLoad(levels[level - 1], min(sourceCoords + ivec2(1, 0), lastCoords))
+        + imageLoad(levels[level - 1], min(sourceCoords + ivec2(0, 1), lastCoords))
+        + imageLoad(levels[level - 1], min(sourceCoords + ivec2(1, 1), lastCoords)));
+}
+
+// Average the 2x2 blocks of the count values held by the first invocations.
+//
+// The averages are held by the first count / 4 invocations. It must be called
+// by all the invocations of the group.
+vec4 averageBlocks(vec4 value, uint index, uint count) {
+#ifdef SUBGROUP_QUAD
+    if (index < count) {
+        value += subgroupQuadSwapHorizontal(value);
+        value += subgroupQuadSwapVertical(value);
+        if (index % 4 == 0) {
+            blocks[index / 4] = 0.25 * value;
+        }
+    }
+    barrier();
+    value = blocks[index];
+#else
+    if (index < count) {
+        blocks[index] = value;
+    }
+    barrier();
+    if (index < count / 4) {
+        value = 0.25 * (blocks[index * 4] + blocks[index * 4 + 1] + blocks[index * 4 + 2] + blocks[index * 4 + 3]);
+    }
+#endif
+    // The blocks are written again by the next level
+    barrier();
+    return value;
+}
+
+// Generate the TILE_LEVELS levels from firstLevel of tile.
+void downsampleTile(uint firstLevel, uvec2 tile) {
+    uint index = gl_LocalInvocationIndex;
+
+    // Each invocation generates 2x2 texels of the first level, the group 32x32
+    ivec2 blockCoords = ivec2(tile * 32) + mortonCoords(index) * 2;
+    vec4 value = vec4(0.0);
+    for (int i = 0; i < 4; i++) {
+        ivec2 coords = blockCoords + ivec2(i & 1, i >> 1);
+        vec4 texel = loadTexel(firstLevel, coords);
+        storeTexel(firstLevel, coords, texel);
+        value += texel;
+    }
+    value *= 0.25;
+    storeTexel(firstLevel + 1, ivec2(tile * 16) + mortonCoords(index), value);
+
+    uint count = 256;
+    for (uint level = firstLevel + 2; level < firstLevel + TILE_LEVELS; level++) {
+        value = averageBlocks(value, index, count);
+        count /= 4;
+        uint size = 32u >> (level - firstLevel);
+        if (index < count) {
+            storeTexel(level, ivec2(tile * size) + mortonCoords(index), value);
+        }
+    }
+}
+
+void main() {
+    downsampleTile(0, gl_WorkGroupID.xy);
+    if (pc.levelCount <= TILE_LEVELS) {
+        return;
+    }
+
+    // Count the finished groups once the last level of the tile is visible
+    if (gl_LocalInvocationIndex == 0) {
+        memoryBarrierImage();
+        uint groupCount = gl_NumWorkGroups.x * gl_NumWorkGroups.y;
+        isLastGroup = atomicAdd(counter.finishedGroups, 1) == groupCount - 1;
+        memoryBarrier();
+    }
+    barrier();
+    if (!isLastGroup) {
+        return;
+    }
+
+    // Reset the counter for the next frame
+    if (gl_LocalInvocationIndex == 0) {
+        counter.finishedGroups = 0;
+    }
+    downsampleTile(TILE_LEVELS, uvec2(0));
+}
@@ -0,0 +1,25 @@
+// Bindings shared by the automatic exposure passes.
+
+// Must match HISTOGRAM_BINS.
+const uint HISTOGRAM_BINS = 256;
+
+layout(binding = 0, rgba16f) uniform readonly image2D hdrImage;
+
+layout(binding = 1) buffer Histogram {
+    uint bins[HISTOGRAM_BINS];
+} histogram;
+
+// Must match ExposureData.
+layout(binding = 2) buffer Exposure {
+    float exposure;
+    float averageLuminance;
+} exposure;
+
+// Must match ExposureUniforms.
+layout(binding = 3) uniform ExposureUniforms {
+    float minLogLuminance;
+    float logLuminanceRange;
+    float timeDelta;
+    float adaptationSpeed;
+    float compensation;
+} ubo;
@@ -0,0 +1,35 @@
+// Average of the histogram adapting the exposure, one invocation per bin.
+//
+// The weighted bins are summed with the helpers of subgroup.glsl, using
+// subgroup operations with the SUBGROUP_ARITHMETIC keyword.
+
+#include "exposure.glsl"
+
+#define WORKGROUP_SIZE HISTOGRAM_BINS
+#include "subgroup.glsl"
+
+layout(local_size_x = WORKGROUP_SIZE) in;
+
+void main() {
+    uint bin = gl_LocalInvocationIndex;
+    uint count = histogram.bins[bin];
+    // Clear the histogram for the next frame
+    histogram.bins[bin] = 0;
+
+    float weightedSum = workgroupAdd(float(count) * float(bin));
+
+    if (bin == 0) {
+        // Black pixels of the first bin are ignored
+        ivec2 size = imageSize(hdrImage);
+        float pixelCount = float(size.x * size.y) - float(count);
+        float averageBin = weightedSum / max(pixelCount, 1.0) - 1.0;
+        float logAverage = averageBin / float(HISTOGRAM_BINS - 2) * ubo.logLuminanceRange + ubo.minLogLuminance;
+        float averageLuminance = exp2(logAverage);
+
+        float adaptation = 1.0 - exp(-ubo.timeDelta * ubo.adaptationSpeed);
+        float adaptedLuminance = mix(exposure.averageLuminance, averageLuminance, adaptation);
+        exposure.averageLuminance = adaptedLuminance;
+        // Map the average luminance to middle gray
+        exposure.exposure = exp2(ubo.compensation) * 0.18 / adaptedLuminance;
+    }
+}
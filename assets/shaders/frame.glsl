@@ -0,0 +1,111 @@
+// Uniform buffer of the frame, shared by all the shaders.
+//
+// The frame is bound in set 0, the material in set 1 and the resources of the
+// object drawn in set 2. Passes without material or object resources put their
+// own set right after the frame set.
+//
+// Must match UniformBufferObject.
+
+// Must match FogMode.
+const uint FOG_MODE_NONE = 0;
+const uint FOG_MODE_LINEAR = 1;
+const uint FOG_MODE_EXPONENTIAL = 2;
+
+// Must match MAX_SHADOWED_SPOT_LIGHTS.
+const uint MAX_SHADOWED_SPOT_LIGHTS = 32;
+
+// Must match MAX_CLIP_PLANES.
+const uint MAX_CLIP_PLANES = 4;
+
+// Spot lights casting shadows in the shadow atlas.
+//
+// Must match SpotLightsUniform.
+struct SpotLights {
+    mat4 viewProj[MAX_SHADOWED_SPOT_LIGHTS];
+    // Scale and offset from the coordinates of the light to its tile in the atlas.
+    vec4 atlasTransform[MAX_SHADOWED_SPOT_LIGHTS];
+    // Position of the light and its range.
+    vec4 position[MAX_SHADOWED_SPOT_LIGHTS];
+    // Axis of the cone and the cosine of its half angle.
+    vec4 direction[MAX_SHADOWED_SPOT_LIGHTS];
+    // Radius of the light in x, widening the penumbrae of PCSS.
+    vec4 softness[MAX_SHADOWED_SPOT_LIGHTS];
+    uint count;
+};
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 cameraPosition;
+    vec4 fogColor;
+    uint fogMode;
+    float fogStart;
+    float fogEnd;
+    float fogDensity;
+    float fogHeightDensity;
+    float fogHeightFalloff;
+    float fogHeightBase;
+    mat4 inverseViewProj;
+    mat4 inverseView;
+    mat4 inverseProj;
+    // Size in pixels of the rendered image.
+    vec2 screenSize;
+    // Time in seconds animating the scene.
+    float time;
+    uint lightCount;
+    // Irradiance of the ambient light divided by pi, in spherical harmonics.
+    vec4 ambient[9];
+    // View projection of the shadow map of the sun.
+    mat4 shadowViewProj;
+    // Transform from world space to the space of the model, in which its
+    // acceleration structures are built.
+    mat4 inverseModel;
+    // Direction pointing towards the sun.
+    vec4 sunDirection;
+    SpotLights spotLights;
+    // Filter of the shadow maps, one of the SHADOW_FILTER_* constants.
+    uint shadowFilter;
+    // Width in shadow map coordinates of the penumbra of the sun per unit of
+    // depth between a receiver and its blocker.
+    float sunPenumbraScale;
+    // Number of rays traced towards the sun by the ray query shadows.
+    uint sunShadowSamples;
+    // Tangent of the angular radius of the sun.
+    float sunSoftness;
+    // World space planes clipping the geometry, keeping the side of their normal.
+    vec4 clipPlanes[MAX_CLIP_PLANES];
+    uint clipPlaneCount;
+} ubo;
+
+// Blend the lit color of a fragment at worldPosition with the fog color.
+//
+// The distance fog is combined with the height fog, whose density decreases
+// exponentially with the altitude above fogHeightBase.
+vec3 applyFog(vec3 color, vec3 worldPosition) {
+    vec3 ray = worldPosition - ubo.cameraPosition.xyz;
+    float rayLength = length(ray);
+
+    float distanceFog = 0.0;
+    if (ubo.fogMode == FOG_MODE_LINEAR) {
+        distanceFog = clamp((rayLength - ubo.fogStart) / (ubo.fogEnd - ubo.fogStart), 0.0, 1.0);
+    } else if (ubo.fogMode == FOG_MODE_EXPONENTIAL) {
+        distanceFog = 1.0 - exp(-ubo.fogDensity * rayLength);
+    }
+
+    float heightFog = 0.0;
+    if (ubo.fogHeightDensity > 0.0) {
+        // Integral of the density along the ray.
+        float falloff = ubo.fogHeightFalloff;
+        float cameraDensity = ubo.fogHeightDensity
+            * exp(-falloff * (ubo.cameraPosition.y - ubo.fogHeightBase));
+        float rise = ray.y * falloff;
+        float amount = abs(rise) > 0.0001
+            ? cameraDensity * rayLength * (1.0 - exp(-rise)) / rise
+            : cameraDensity * rayLength;
+        heightFog = 1.0 - exp(-amount);
+    }
+
+    float fog = 1.0 - (1.0 - distanceFog) * (1.0 - heightFog);
+    return mix(color, ubo.fogColor.rgb, fog);
+}
@@ -0,0 +1,34 @@
+// AMD FidelityFX Super Resolution 1.0, ported from ffx_fsr1.h without its
+// packed half precision paths.
+//
+// FSR expects perceptual colors between 0 and 1, so the HDR colors are mapped
+// there with a reversible tonemap before the upscaling, and mapped back after
+// the sharpening.
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(binding = 0) uniform sampler2D source;
+layout(binding = 1, rgba16f) uniform writeonly image2D destination;
+
+layout(push_constant) uniform PushConstants {
+    // Strength of the sharpening, from 0 to 1 for the maximum.
+    float sharpness;
+} pc;
+
+float max3(vec3 color) {
+    return max(color.r, max(color.g, color.b));
+}
+
+vec3 tonemapReversible(vec3 color) {
+    return color / (1.0 + max3(color));
+}
+
+vec3 tonemapInverse(vec3 color) {
+    return color / max(1.0 - max3(color), 1.0 / 32768.0);
+}
+
+// Get the texel at coords, clamped to the edges of the source.
+vec3 loadSource(ivec2 coords) {
+    coords = clamp(coords, ivec2(0), textureSize(source, 0) - 1);
+    return texelFetch(source, coords, 0).rgb;
+}
@@ -0,0 +1,11 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) out vec2 fragCoords;
+
+void main() {
+    // Full screen triangle.
+    vec2 coords = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(coords * 2.0 - 1.0, 0.0, 1.0);
+    fragCoords = coords;
+}
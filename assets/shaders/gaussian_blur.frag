@@ -0,0 +1,28 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+// Distance in texture coordinates between the texels blurred, along the
+// direction of the pass. Must match GaussianBlur.
+layout(push_constant) uniform Blur {
+    vec2 direction;
+} blur;
+
+layout(location = 0) in vec2 fragCoords;
+
+layout(location = 0) out vec4 outColor;
+
+// Offsets and weights of 9 Gaussian taps merged in pairs by linear filtering.
+const float OFFSETS[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float WEIGHTS[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+    vec4 color = texture(source, fragCoords) * WEIGHTS[0];
+    for (int i = 1; i < 3; i++) {
+        vec2 offset = blur.direction * OFFSETS[i];
+        color += texture(source, fragCoords + offset) * WEIGHTS[i];
+        color += texture(source, fragCoords - offset) * WEIGHTS[i];
+    }
+    outColor = color;
+}
@@ -0,0 +1,35 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+const vec3 LIGHT_DIRECTION = normalize(vec3(1.0, 2.0, 1.0));
+
+struct Material {
+    uint textureIndex;
+};
+
+layout(set = 2, binding = 0) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(set = 2, binding = 1) uniform sampler2D textures[];
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec3 fragColor;
+layout(location = 2) in vec2 fragCoords;
+layout(location = 3) in vec3 fragWorldPosition;
+layout(location = 4) flat in uint fragMaterial;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // The material differs between the instances of a draw
+    Material material = materials[nonuniformEXT(fragMaterial)];
+    vec3 albedo = texture(textures[nonuniformEXT(material.textureIndex)], fragCoords).rgb;
+    float diffuse = max(dot(normalize(fragNormal), LIGHT_DIRECTION), 0.0);
+    vec3 color = albedo * fragColor * (0.2 + 0.8 * diffuse);
+    outColor = vec4(applyFog(color, fragWorldPosition), 1.0);
+}
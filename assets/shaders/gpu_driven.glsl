@@ -0,0 +1,56 @@
+// Instances culled, sorted by level of detail and drawn by the GPU.
+//
+// GPU_DRIVEN_SET must be defined to the set of the pass, and DRAWS_ACCESS may
+// be defined to readonly for the stages which only read the draws.
+
+#ifndef DRAWS_ACCESS
+#define DRAWS_ACCESS
+#endif
+
+// Must match GPU_DRIVEN_LOD_COUNT.
+#define LOD_COUNT 3
+
+// Must match GPU_DRIVEN_MAX_INSTANCES.
+#define MAX_INSTANCES 4096
+
+// Must match GpuInstance.
+struct Instance {
+    // Center and radius of the bounding sphere, also the position and the
+    // scale of the mesh of radius 1.
+    vec4 sphere;
+    vec4 color;
+    // Index of the material in the bindless material buffer.
+    uint material;
+};
+
+struct DrawIndexedIndirectCommand {
+    uint indexCount;
+    uint instanceCount;
+    uint firstIndex;
+    int vertexOffset;
+    uint firstInstance;
+};
+
+// Must match GpuDrivenUniforms.
+layout(set = GPU_DRIVEN_SET, binding = 0) uniform GpuDrivenUniforms {
+    vec4 frustumPlanes[6];
+    vec4 cameraPosition;
+    // Distances from which the second and the third levels of detail are used.
+    vec4 lodDistances;
+    // First index, index count and vertex offset of each level of detail.
+    uvec4 lods[LOD_COUNT];
+    uint instanceCount;
+} gpuDriven;
+
+layout(std430, set = GPU_DRIVEN_SET, binding = 1) readonly buffer Instances {
+    Instance instances[];
+};
+
+layout(std430, set = GPU_DRIVEN_SET, binding = 2) DRAWS_ACCESS buffer Draws {
+    // Read by vkCmdDrawIndexedIndirectCountKHR.
+    uint drawCount;
+    uint lodInstanceCounts[LOD_COUNT];
+    DrawIndexedIndirectCommand draws[LOD_COUNT];
+    // Indices of the visible instances, MAX_INSTANCES per level of detail.
+    uint visibleInstances[];
+};
@@ -0,0 +1,32 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+#define GPU_DRIVEN_SET 1
+#define DRAWS_ACCESS readonly
+#include "gpu_driven.glsl"
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 1) in vec3 vColor;
+layout(location = 2) in vec2 vCoords;
+layout(location = 3) in vec3 vNormal;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec3 fragColor;
+layout(location = 2) out vec2 fragCoords;
+layout(location = 3) out vec3 fragWorldPosition;
+layout(location = 4) flat out uint fragMaterial;
+
+void main() {
+    // The instance index starts at the range of the visible instances of the draw
+    Instance instance = instances[visibleInstances[gl_InstanceIndex]];
+    vec3 worldPosition = instance.sphere.xyz + vPosition * instance.sphere.w;
+    gl_Position = ubo.proj * ubo.view * vec4(worldPosition, 1.0);
+    fragNormal = vNormal;
+    fragColor = vColor * instance.color.rgb;
+    fragCoords = vCoords;
+    fragWorldPosition = worldPosition;
+    fragMaterial = instance.material;
+}
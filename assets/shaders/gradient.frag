@@ -0,0 +1,18 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+#include "background.glsl"
+
+layout(location = 0) in vec2 fragNdc;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec4 farPoint = ubo.inverseViewProj * vec4(fragNdc, 1.0, 1.0);
+    vec3 direction = normalize(farPoint.xyz / farPoint.w - ubo.cameraPosition.xyz);
+
+    float t = smoothstep(-1.0, 1.0, direction.y);
+    outColor = vec4(mix(background.bottomColor.rgb, background.topColor.rgb, t), 1.0);
+}
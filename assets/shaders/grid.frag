@@ -0,0 +1,63 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+#include "background.glsl"
+
+// Number of lines between two major lines.
+const float MAJOR_LINE_INTERVAL = 10.0;
+
+const vec3 X_AXIS_COLOR = vec3(0.9, 0.15, 0.15);
+const vec3 Z_AXIS_COLOR = vec3(0.15, 0.3, 0.9);
+
+layout(location = 0) in vec2 fragNdc;
+
+layout(location = 0) out vec4 outColor;
+
+// Get the coverage of the lines of a grid of cells of size 1 at coord,
+// about one pixel wide whatever the distance.
+float gridCoverage(vec2 coord) {
+    vec2 width = fwidth(coord);
+    vec2 distanceToLine = abs(fract(coord - 0.5) - 0.5) / width;
+    return 1.0 - min(min(distanceToLine.x, distanceToLine.y), 1.0);
+}
+
+void main() {
+    // Intersect the view ray with the ground plane
+    vec4 nearPoint = ubo.inverseViewProj * vec4(fragNdc, 0.0, 1.0);
+    vec4 farPoint = ubo.inverseViewProj * vec4(fragNdc, 1.0, 1.0);
+    vec3 origin = nearPoint.xyz / nearPoint.w;
+    vec3 ray = farPoint.xyz / farPoint.w - origin;
+    float t = -origin.y / ray.y;
+    if (t <= 0.0 || t >= 1.0) {
+        discard;
+    }
+    vec3 position = origin + t * ray;
+
+    vec2 coord = position.xz / background.gridSpacing;
+    float minor = gridCoverage(coord);
+    float major = gridCoverage(coord / MAJOR_LINE_INTERVAL);
+
+    vec3 color = background.gridColor.rgb;
+    float alpha = max(minor * 0.5, major);
+
+    // Highlight the axes over the other lines
+    vec2 axisWidth = fwidth(position.xz);
+    float xAxis = 1.0 - min(abs(position.z) / axisWidth.y, 1.0);
+    float zAxis = 1.0 - min(abs(position.x) / axisWidth.x, 1.0);
+    color = mix(color, X_AXIS_COLOR, xAxis);
+    color = mix(color, Z_AXIS_COLOR, zAxis);
+    alpha = max(alpha, max(xAxis, zAxis));
+
+    // Fade the lines in the distance, where they would alias
+    float distanceToCamera = length(position - ubo.cameraPosition.xyz);
+    alpha *= 1.0 - smoothstep(0.0, background.gridFadeDistance, distanceToCamera);
+    if (alpha <= 0.0) {
+        discard;
+    }
+
+    vec4 clipPosition = ubo.proj * ubo.view * vec4(position, 1.0);
+    gl_FragDepth = clipPosition.z / clipPosition.w;
+    outColor = vec4(color, alpha);
+}
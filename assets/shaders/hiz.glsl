@@ -0,0 +1,32 @@
+// Reduction of a depth level into a level of the hierarchical depth buffer.
+//
+// Each texel of the destination keeps the furthest depth of the texels it
+// covers in x, so a box behind it is hidden by all of them, and the closest
+// depth in y, so a ray in front of it crosses none of them. The destination is
+// half the size of the source, rounded down, so the last row and column also
+// cover the remaining row or column of an odd source.
+//
+// The shaders including it declare `destination` and define `sourceSize` and
+// `loadDepth`, returning the furthest and closest depths of a source texel.
+
+// Must match HIZ_GROUP_SIZE.
+layout(local_size_x = 8, local_size_y = 8) in;
+
+void main() {
+    ivec2 size = imageSize(destination);
+    ivec2 coords = ivec2(gl_GlobalInvocationID.xy);
+    if (coords.x >= size.x || coords.y >= size.y) {
+        return;
+    }
+
+    ivec2 first = coords * 2;
+    ivec2 last = mix(first + 1, sourceSize() - 1, equal(coords, size - 1));
+    vec2 depth = vec2(0.0, 1.0);
+    for (int y = first.y; y <= last.y; y++) {
+        for (int x = first.x; x <= last.x; x++) {
+            vec2 source = loadDepth(ivec2(x, y));
+            depth = vec2(max(depth.x, source.x), min(depth.y, source.y));
+        }
+    }
+    imageStore(destination, coords, vec4(depth, 0.0, 0.0));
+}
@@ -0,0 +1,99 @@
+// Occlusion culling of the draws of the objects against the depth pyramid.
+//
+// The occluded objects are counted with one atomic per subgroup with the
+// SUBGROUP_ARITHMETIC keyword, and one per object otherwise.
+
+// Must match HIZ_CULL_GROUP_SIZE.
+layout(local_size_x = 64) in;
+
+struct DrawIndexedIndirectCommand {
+    uint indexCount;
+    uint instanceCount;
+    uint firstIndex;
+    int vertexOffset;
+    uint firstInstance;
+};
+
+layout(set = 0, binding = 0) uniform HiZUniforms {
+    // View projection of the frame the pyramid was built from.
+    mat4 viewProj;
+    // Size of the depth buffer the pyramid was built from.
+    vec2 depthSize;
+    uint objectCount;
+    uint levelCount;
+} ubo;
+
+layout(set = 0, binding = 1) uniform sampler2D pyramid;
+
+// Minimum and maximum corners of the bounds of each object.
+layout(set = 0, binding = 2) readonly buffer Bounds {
+    vec4 bounds[];
+};
+
+layout(set = 0, binding = 3) buffer Draws {
+    DrawIndexedIndirectCommand draws[];
+};
+
+layout(set = 0, binding = 4) buffer Stats {
+    uint occludedCount;
+};
+
+void main() {
+    uint index = gl_GlobalInvocationID.x;
+    if (index >= ubo.objectCount || draws[index].instanceCount == 0) {
+        return;
+    }
+
+    // Screen space bounds of the box, with the depth of its closest point
+    vec3 screenMin = vec3(1.0);
+    vec3 screenMax = vec3(0.0);
+    for (int i = 0; i < 8; i++) {
+        vec3 corner = mix(bounds[index * 2].xyz, bounds[index * 2 + 1].xyz,
+                          vec3(i & 1, (i >> 1) & 1, (i >> 2) & 1));
+        vec4 clip = ubo.viewProj * vec4(corner, 1.0);
+        // Boxes crossing the near plane are kept
+        if (clip.w <= 0.0) {
+            return;
+        }
+        vec3 ndc = clip.xyz / clip.w;
+        vec3 screen = vec3(ndc.xy * 0.5 + 0.5, ndc.z);
+        screenMin = min(screenMin, screen);
+        screenMax = max(screenMax, screen);
+    }
+    screenMin.xy = clamp(screenMin.xy, 0.0, 1.0);
+    screenMax.xy = clamp(screenMax.xy, 0.0, 1.0);
+
+    // Pick the level where the box covers at most two texels along each side.
+    // Level 0 is half the size of the depth buffer.
+    vec2 pixels = (screenMax.xy - screenMin.xy) * ubo.depthSize;
+    int level = int(ceil(log2(max(max(pixels.x, pixels.y), 1.0)))) - 1;
+    level = clamp(level, 0, int(ubo.levelCount) - 1);
+
+    ivec2 levelSize = textureSize(pyramid, level);
+    float scale = exp2(float(level + 1));
+    ivec2 first = min(ivec2(screenMin.xy * ubo.depthSize / scale), levelSize - 1);
+    ivec2 last = min(ivec2(screenMax.xy * ubo.depthSize / scale), levelSize - 1);
+    float depth = 0.0;
+    for (int y = first.y; y <= last.y; y++) {
+        for (int x = first.x; x <= last.x; x++) {
+            depth = max(depth, texelFetch(pyramid, ivec2(x, y), level).r);
+        }
+    }
+
+    bool occluded = screenMin.z > depth;
+    if (occluded) {
+        draws[index].instanceCount = 0;
+    }
+
+#ifdef SUBGROUP_ARITHMETIC
+    // A single atomic per subgroup, the invocations returning early being inactive
+    uint subgroupOccluded = subgroupAdd(occluded ? 1 : 0);
+    if (subgroupElect() && subgroupOccluded > 0) {
+        atomicAdd(occludedCount, subgroupOccluded);
+    }
+#else
+    if (occluded) {
+        atomicAdd(occludedCount, 1);
+    }
+#endif
+}
@@ -0,0 +1,63 @@
+// Fragment shader of the light shafts, shared by light_shafts.frag and light_shafts_half.frag.
+//
+// Define HALF_RESOLUTION before including it to write the shafts alone, which
+// are then added to the HDR image by the bilateral upsample.
+
+#include "frame.glsl"
+
+const int SAMPLE_COUNT = 64;
+const float PI = 3.14159265359;
+
+// Must match LightShaftUniforms.
+layout(set = 1, binding = 0) uniform LightShaftUniforms {
+    vec4 sunDirection;
+    vec2 sunCoords;
+    float sunVisibility;
+    float density;
+    float decay;
+    float weight;
+    float intensity;
+    float threshold;
+    float anisotropy;
+} shafts;
+
+layout(set = 1, binding = 1) uniform sampler2D hdrColor;
+
+layout(location = 0) in vec2 fragCoords;
+
+layout(location = 0) out vec4 outColor;
+
+// Henyey-Greenstein phase function, scaled so it is 1 when scattering is isotropic.
+float phase(float cosTheta, float g) {
+    float g2 = g * g;
+    return (1.0 - g2) / pow(1.0 + g2 - 2.0 * g * cosTheta, 1.5);
+}
+
+// Part of color bright enough to emit shafts.
+vec3 brightPart(vec3 color) {
+    float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    return color * max(luminance - shafts.threshold, 0.0) / max(luminance, 0.0001);
+}
+
+void main() {
+    vec2 delta = (shafts.sunCoords - fragCoords) * shafts.density / float(SAMPLE_COUNT);
+    vec2 coords = fragCoords;
+    float illumination = 1.0;
+    vec3 shafts = vec3(0.0);
+    for (int i = 0; i < SAMPLE_COUNT; i++) {
+        coords += delta;
+        shafts += brightPart(texture(hdrColor, coords).rgb) * illumination * shafts.weight;
+        illumination *= shafts.decay;
+    }
+
+    vec4 farPosition = ubo.inverseViewProj * vec4(fragCoords * 2.0 - 1.0, 1.0, 1.0);
+    vec3 viewDirection = normalize(farPosition.xyz / farPosition.w - ubo.cameraPosition.xyz);
+    float scattering = phase(dot(viewDirection, shafts.sunDirection.xyz), shafts.anisotropy);
+
+    vec3 scattered = shafts * scattering * shafts.intensity * shafts.sunVisibility;
+#ifdef HALF_RESOLUTION
+    outColor = vec4(scattered, 1.0);
+#else
+    outColor = vec4(texture(hdrColor, fragCoords).rgb + scattered, 1.0);
+#endif
+}
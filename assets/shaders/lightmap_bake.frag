@@ -0,0 +1,40 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "lightmap_bake.glsl"
+
+// Distance the surface is moved along its normal before being compared with
+// the visibility maps, so it does not shadow itself.
+const float NORMAL_OFFSET = 0.01;
+const float DEPTH_BIAS = 0.002;
+
+layout(location = 0) in vec3 fragWorldPosition;
+layout(location = 1) in vec3 fragWorldNormal;
+
+layout(set = 0, binding = 1) uniform sampler2DArray visibility;
+
+layout(location = 0) out vec4 outIrradiance;
+
+void main() {
+    vec3 normal = normalize(fragWorldNormal);
+    vec3 position = fragWorldPosition + normal * NORMAL_OFFSET;
+    ivec2 size = textureSize(visibility, 0).xy;
+
+    vec3 irradiance = vec3(0.0);
+    for (uint i = 0; i < MAX_DIRECTIONS; i++) {
+        float cosTheta = dot(normal, bake.directions[i].xyz);
+        if (cosTheta <= 0.0) {
+            continue;
+        }
+        vec3 light = (bake.viewProjs[i] * vec4(position, 1.0)).xyz;
+        ivec2 texel = clamp(ivec2((light.xy * 0.5 + 0.5) * size), ivec2(0), size - 1);
+        float occluder = texelFetch(visibility, ivec3(texel, i), 0).r;
+        if (light.z - DEPTH_BIAS <= occluder) {
+            irradiance += bake.radiances[i].rgb * cosTheta;
+        }
+    }
+
+    // Texels left untouched keep the alpha of 1 they are cleared to.
+    outIrradiance = vec4(irradiance, 0.0);
+}
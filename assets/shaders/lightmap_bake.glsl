@@ -0,0 +1,15 @@
+// Uniforms of the lightmap bake.
+//
+// Must match BAKE_DIRECTION_COUNT.
+const uint MAX_DIRECTIONS = 49;
+
+// Must match BakeUniforms.
+layout(set = 0, binding = 0) uniform BakeUniforms {
+    mat4 model;
+    // Orthographic view projection of the visibility map of each direction.
+    mat4 viewProjs[MAX_DIRECTIONS];
+    // Directions pointing toward the light.
+    vec4 directions[MAX_DIRECTIONS];
+    // Light received from each direction.
+    vec4 radiances[MAX_DIRECTIONS];
+} bake;
@@ -0,0 +1,19 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "lightmap_bake.glsl"
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 3) in vec3 vNormal;
+layout(location = 4) in vec2 vLightmapCoords;
+
+layout(location = 0) out vec3 fragWorldPosition;
+layout(location = 1) out vec3 fragWorldNormal;
+
+void main() {
+    // Rasterize the triangles where they are in the lightmap.
+    gl_Position = vec4(vLightmapCoords * 2.0 - 1.0, 0.0, 1.0);
+    fragWorldPosition = (bake.model * vec4(vPosition, 1.0)).xyz;
+    fragWorldNormal = mat3(bake.model) * vNormal;
+}
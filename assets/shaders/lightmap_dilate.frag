@@ -0,0 +1,35 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Distance in texels up to which the charts are extended.
+const int DILATE_RADIUS = 2;
+
+layout(set = 0, binding = 2) uniform sampler2D baked;
+
+layout(location = 0) out vec4 outColor;
+
+// Fill the texels not covered by the bake with the average of their covered
+// neighbours, whose alpha is 0.
+void main() {
+    ivec2 size = textureSize(baked, 0);
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec4 center = texelFetch(baked, texel, 0);
+    if (center.a < 0.5) {
+        outColor = vec4(center.rgb, 1.0);
+        return;
+    }
+
+    vec3 sum = vec3(0.0);
+    float count = 0.0;
+    for (int y = -DILATE_RADIUS; y <= DILATE_RADIUS; y++) {
+        for (int x = -DILATE_RADIUS; x <= DILATE_RADIUS; x++) {
+            ivec2 neighbour = clamp(texel + ivec2(x, y), ivec2(0), size - 1);
+            vec4 value = texelFetch(baked, neighbour, 0);
+            if (value.a < 0.5) {
+                sum += value.rgb;
+                count += 1.0;
+            }
+        }
+    }
+    outColor = vec4(count > 0.0 ? sum / count : vec3(0.0), 1.0);
+}
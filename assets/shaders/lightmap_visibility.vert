@@ -0,0 +1,15 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "lightmap_bake.glsl"
+
+layout(location = 0) in vec3 vPosition;
+
+layout(push_constant) uniform PushConstants {
+    uint direction;
+} pc;
+
+void main() {
+    gl_Position = bake.viewProjs[pc.direction] * bake.model * vec4(vPosition, 1.0);
+}
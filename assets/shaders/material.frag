@@ -0,0 +1,30 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+#ifdef RAY_QUERY_SHADOWS
+#extension GL_EXT_ray_query : require
+#endif
+
+#include "frame.glsl"
+#include "material.glsl"
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragCoords;
+layout(location = 2) in vec3 fragWorldPosition;
+
+// With the REFLECTION_PROBES keyword, the probes blended for the model are reflected.
+#ifdef REFLECTION_PROBES
+#include "probe.glsl"
+#endif
+
+layout(binding = 1) uniform sampler2D texSampler;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec4 color = applyMaterial(texture(texSampler, fragCoords)*vec4(fragColor, 1.0), fragWorldPosition);
+#ifdef REFLECTION_PROBES
+    color.rgb = applyReflections(color.rgb, fragWorldPosition, surfaceNormal(), material.roughness);
+#endif
+    outColor = vec4(applyFog(color.rgb, fragWorldPosition), color.a);
+}
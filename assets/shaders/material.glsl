@@ -0,0 +1,96 @@
+// Parameters of the material instance of the drawn mesh.
+//
+// Must match MaterialParameters.
+layout(set = 1, binding = 0) uniform MaterialParameters {
+    vec4 tint;
+    float roughness;
+    float emissiveStrength;
+} material;
+
+// World space normal of the fragment, for the keywords lighting it.
+#if defined(REFLECTION_PROBES) || defined(SH_AMBIENT) || defined(SHADOWS) || defined(SPOT_SHADOWS) \
+    || defined(RAY_QUERY_SHADOWS)
+layout(location = 3) in vec3 fragWorldNormal;
+
+// Get the unit world space normal of the fragment.
+//
+// With the DOUBLE_SIDED keyword, the normal of the back faces is flipped so
+// they are lit like the front faces.
+vec3 surfaceNormal() {
+    vec3 normal = normalize(fragWorldNormal);
+#ifdef DOUBLE_SIDED
+    return gl_FrontFacing ? normal : -normal;
+#else
+    return normal;
+#endif
+}
+#endif
+
+#if defined(SHADOWS) || defined(SPOT_SHADOWS)
+#include "shadow_filter.glsl"
+#endif
+
+// With the SHADOWS keyword, the color is darkened in the shadow of the sun.
+#ifdef SHADOWS
+#include "shadow.glsl"
+#endif
+
+// With the RAY_QUERY_SHADOWS keyword, the shadows of the sun and of the spot
+// lights are traced against the acceleration structures of the model instead.
+#ifdef RAY_QUERY_SHADOWS
+#include "ray_query_shadow.glsl"
+#endif
+
+// With the SPOT_SHADOWS keyword, the color is lit by the spot lights of the shadow atlas.
+#ifdef SPOT_SHADOWS
+#include "spot_shadow.glsl"
+#endif
+
+// With the SH_AMBIENT keyword, the color is lit by the ambient light of the frame.
+#ifdef SH_AMBIENT
+#include "ambient.glsl"
+#endif
+
+// With the LIGHTMAP keyword, the color is lit by the lightmap baked for the model.
+#ifdef LIGHTMAP
+layout(set = 1, binding = 1) uniform sampler2D lightmap;
+
+layout(location = 4) in vec2 fragLightmapCoords;
+#endif
+
+// Apply the tint and the emission of the material instance to the color of its base material.
+//
+// With the ALPHA_TEST keyword, the fragments whose alpha is below 0.5 are discarded.
+// With the LIGHTMAP, SH_AMBIENT, SHADOWS, RAY_QUERY_SHADOWS or SPOT_SHADOWS keyword, the color is
+// multiplied by the light reaching worldPosition but the emission is not. The lightmap already holds the light of the
+// sky and the shadows of the sun, so the ambient light and the shadow map are
+// ignored with it. The spot lights are added to it.
+vec4 applyMaterial(vec4 color, vec3 worldPosition) {
+    color *= material.tint;
+#ifdef ALPHA_TEST
+    if (color.a < 0.5) {
+        discard;
+    }
+#endif
+    vec3 emission = color.rgb * material.emissiveStrength;
+#ifdef SPOT_SHADOWS
+    vec3 spotLight = color.rgb * evaluateSpotLights(worldPosition, surfaceNormal());
+#else
+    vec3 spotLight = vec3(0.0);
+#endif
+#ifdef LIGHTMAP
+    color.rgb *= texture(lightmap, fragLightmapCoords).rgb;
+#else
+#ifdef SH_AMBIENT
+    color.rgb *= evaluateAmbient(surfaceNormal());
+#endif
+#ifdef SHADOWS
+    float shadow = sampleShadow(worldPosition, surfaceNormal());
+    color.rgb *= mix(SHADOW_DARKENING, 1.0, shadow);
+#elif defined(RAY_QUERY_SHADOWS)
+    float shadow = traceSunShadow(worldPosition, surfaceNormal());
+    color.rgb *= mix(RAY_QUERY_SHADOW_DARKENING, 1.0, shadow);
+#endif
+#endif
+    return vec4(color.rgb + spotLight + emission, color.a);
+}
@@ -0,0 +1,11 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Tint of the mirror blended over the reflection, the alpha being its opacity.
+const vec4 MIRROR_TINT = vec4(0.6, 0.7, 0.8, 0.2);
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = MIRROR_TINT;
+}
@@ -0,0 +1,12 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+// The vertices of the mirror are already in world space.
+layout(location = 0) in vec3 vPosition;
+
+#include "frame.glsl"
+
+void main() {
+    gl_Position = ubo.proj * ubo.view * vec4(vPosition, 1.0);
+}
@@ -0,0 +1,26 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "motion_blur.glsl"
+
+const int SAMPLE_COUNT = 12;
+
+layout(binding = 1) uniform sampler2D hdrColor;
+layout(binding = 2) uniform sampler2D velocityMap;
+
+layout(location = 0) in vec2 fragCoords;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec2 velocity = texture(velocityMap, fragCoords).xy * ubo.intensity;
+
+    // Average samples centered on the pixel along its motion
+    vec3 color = vec3(0.0);
+    for (int i = 0; i < SAMPLE_COUNT; i++) {
+        float offset = float(i) / float(SAMPLE_COUNT - 1) - 0.5;
+        color += texture(hdrColor, fragCoords + velocity * offset).rgb;
+    }
+    outColor = vec4(color / float(SAMPLE_COUNT), 1.0);
+}
@@ -0,0 +1,11 @@
+// Uniforms shared by the velocity and motion blur passes.
+
+// Must match MotionBlurUniforms.
+layout(binding = 0) uniform MotionBlurUniforms {
+    mat4 viewProj;
+    mat4 previousViewProj;
+    mat4 model;
+    mat4 previousModel;
+    mat4 skyReprojection;
+    float intensity;
+} ubo;
@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 3) in vec3 vNormal;
+
+#include "frame.glsl"
+
+layout(location = 0) out vec3 geomNormal;
+
+void main() {
+    // Positions and normals are output in world space, the geometry shader projects them.
+    gl_Position = ubo.model * vec4(vPosition, 1.0);
+    geomNormal = normalize(mat3(transpose(inverse(ubo.model))) * vNormal);
+}
@@ -0,0 +1,21 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+#include "object.glsl"
+
+const vec3 LIGHT_DIRECTION = normalize(vec3(1.0, 2.0, 1.0));
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec3 fragColor;
+layout(location = 2) in vec3 fragWorldPosition;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float diffuse = max(dot(normalize(fragNormal), LIGHT_DIRECTION), 0.0);
+    vec3 color = fragColor * object.tint.rgb;
+    color = color * (0.2 + 0.8 * diffuse) + color * object.emissiveStrength;
+    outColor = vec4(applyFog(color, fragWorldPosition), 1.0);
+}
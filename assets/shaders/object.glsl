@@ -0,0 +1,8 @@
+// Uniforms of the object drawn, bound at its dynamic offset.
+//
+// Must match ObjectUniforms.
+layout(set = 1, binding = 0) uniform ObjectUniforms {
+    mat4 model;
+    vec4 tint;
+    float emissiveStrength;
+} object;
@@ -0,0 +1,23 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+#include "object.glsl"
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 1) in vec3 vColor;
+layout(location = 2) in vec2 vCoords;
+layout(location = 3) in vec3 vNormal;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec3 fragColor;
+layout(location = 2) out vec3 fragWorldPosition;
+
+void main() {
+    vec4 worldPosition = object.model * vec4(vPosition, 1.0);
+    gl_Position = ubo.proj * ubo.view * worldPosition;
+    fragNormal = mat3(object.model) * vNormal;
+    fragColor = vColor;
+    fragWorldPosition = worldPosition.xyz;
+}
@@ -0,0 +1,12 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+layout(location = 0) in vec3 vPosition;
+
+void main() {
+    // Boxes are given in the space of the model.
+    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(vPosition, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Color of the outline of the selected object.
+const vec3 OUTLINE_COLOR = vec3(1.0, 0.6, 0.1);
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = vec4(OUTLINE_COLOR, 1.0);
+}
@@ -0,0 +1,23 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 3) in vec3 vNormal;
+
+#include "frame.glsl"
+
+// Width in pixels of the outline.
+const float OUTLINE_WIDTH = 3.0;
+
+void main() {
+    vec4 position = ubo.proj * ubo.view * ubo.model * vec4(vPosition, 1.0);
+
+    // Push the vertex along its normal projected on the screen, scaled by w so
+    // the width is the same in pixels at any distance.
+    vec2 normal = (ubo.proj * ubo.view * ubo.model * vec4(vNormal, 0.0)).xy;
+    if (dot(normal, normal) > 0.0) {
+        position.xy += normalize(normal) * OUTLINE_WIDTH * 2.0 / ubo.screenSize * position.w;
+    }
+    gl_Position = position;
+}
@@ -0,0 +1,21 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+layout(location = 0) in vec2 fragCorner;
+layout(location = 1) in float fragAge;
+layout(location = 2) in vec3 fragWorldPosition;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // Round particles fading out as they age, from yellow to red
+    float alpha = (1.0 - smoothstep(0.5, 1.0, length(fragCorner))) * (1.0 - fragAge);
+    if (alpha < 0.05) {
+        discard;
+    }
+    vec3 color = mix(vec3(1.0, 0.8, 0.3), vec3(0.8, 0.2, 0.1), fragAge);
+    outColor = vec4(applyFog(color, fragWorldPosition), alpha);
+}
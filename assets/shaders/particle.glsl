@@ -0,0 +1,8 @@
+// Particle simulated on the GPU.
+
+struct Particle {
+    // World position and age in seconds.
+    vec4 positionAge;
+    // Velocity and lifetime in seconds.
+    vec4 velocityLifetime;
+};
@@ -0,0 +1,41 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+#include "particle.glsl"
+
+layout(set = 1, binding = 0) readonly buffer Particles {
+    Particle particles[];
+};
+
+// Indices of the particles sorted back to front.
+layout(set = 1, binding = 1) readonly buffer SortedIndices {
+    uint sortedIndices[];
+};
+
+layout(location = 0) out vec2 fragCorner;
+layout(location = 1) out float fragAge;
+layout(location = 2) out vec3 fragWorldPosition;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, 1.0), vec2(-1.0, -1.0), vec2(1.0, 1.0),
+    vec2(1.0, 1.0), vec2(-1.0, -1.0), vec2(1.0, -1.0)
+);
+
+void main() {
+    // One instance per particle, whose quad faces the camera
+    Particle particle = particles[sortedIndices[gl_InstanceIndex]];
+    vec2 corner = CORNERS[gl_VertexIndex];
+    float age = particle.positionAge.w / particle.velocityLifetime.w;
+    float size = mix(0.03, 0.1, age);
+
+    vec3 right = vec3(ubo.view[0][0], ubo.view[1][0], ubo.view[2][0]);
+    vec3 up = vec3(ubo.view[0][1], ubo.view[1][1], ubo.view[2][1]);
+    vec3 position = particle.positionAge.xyz + (right * corner.x + up * corner.y) * size;
+
+    gl_Position = ubo.proj * ubo.view * vec4(position, 1.0);
+    fragCorner = corner;
+    fragAge = age;
+    fragWorldPosition = position;
+}
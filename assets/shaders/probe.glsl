@@ -0,0 +1,66 @@
+// Reflection probes blended for the drawn object, in the object set.
+//
+// Each probe is a cubemap stored as six layers of the texture array, whose
+// mip levels are downsampled from the first one. Must match probe.rs.
+layout(set = 2, binding = 0) uniform ProbeUniforms {
+    uvec2 indices;
+    vec2 weights;
+} probeUniforms;
+
+layout(set = 2, binding = 1) uniform sampler2DArray probeFaces;
+
+// Forward and up directions of the faces of a probe, in the order of the layers.
+const vec3 FACE_FORWARDS[6] = vec3[](
+    vec3(1.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0),
+    vec3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0),
+    vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0)
+);
+const vec3 FACE_UPS[6] = vec3[](
+    vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0),
+    vec3(0.0, 0.0, -1.0), vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)
+);
+
+// Sample the probe at index probe in direction at the mip level lod.
+//
+// The face is the one of the major axis of the direction. The top of a face
+// is its up direction and its right the cross product of forward and up, as
+// the capture cameras see it.
+vec3 sampleProbe(uint probe, vec3 direction, float lod) {
+    vec3 absDirection = abs(direction);
+    uint face;
+    if (absDirection.x >= absDirection.y && absDirection.x >= absDirection.z) {
+        face = direction.x > 0.0 ? 0u : 1u;
+    } else if (absDirection.y >= absDirection.z) {
+        face = direction.y > 0.0 ? 2u : 3u;
+    } else {
+        face = direction.z > 0.0 ? 4u : 5u;
+    }
+
+    vec3 forward = FACE_FORWARDS[face];
+    vec3 up = FACE_UPS[face];
+    vec3 right = cross(forward, up);
+    vec2 uv = vec2(dot(direction, right), -dot(direction, up)) / dot(direction, forward);
+    return textureLod(probeFaces, vec3(uv * 0.5 + 0.5, probe * 6u + face), lod).rgb;
+}
+
+// Blend the reflection of the probes with the lit color of a fragment at
+// worldPosition whose surface has the given normal and roughness.
+//
+// Rough surfaces sample the blurrier mip levels and reflect less. All the
+// surfaces reflect more at grazing angles, following Schlick's approximation
+// of the Fresnel factor.
+vec3 applyReflections(vec3 color, vec3 worldPosition, vec3 normal, float roughness) {
+    if (probeUniforms.weights.x + probeUniforms.weights.y == 0.0) {
+        return color;
+    }
+
+    vec3 viewDirection = normalize(worldPosition - ubo.cameraPosition.xyz);
+    vec3 direction = reflect(viewDirection, normal);
+    float lod = roughness * float(textureQueryLevels(probeFaces) - 1);
+    vec3 reflection = sampleProbe(probeUniforms.indices.x, direction, lod) * probeUniforms.weights.x
+        + sampleProbe(probeUniforms.indices.y, direction, lod) * probeUniforms.weights.y;
+
+    float fresnel = 0.04 + 0.96 * pow(1.0 - max(dot(-viewDirection, normal), 0.0), 5.0);
+    return mix(color, reflection, fresnel * (1.0 - roughness));
+}
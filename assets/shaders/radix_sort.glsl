@@ -0,0 +1,132 @@
+// Least significant digit radix sort of 32 bit keys with 32 bit values.
+//
+// Each of the 8 passes sorts the elements by a digit of 4 bits of the keys,
+// in three stages selected by the specialization constant 1:
+// - Count: each work group counts the digits of its block of 256 elements.
+// - Scan: a single work group scans the counts, digit major, into the offset
+//   of each digit of each block in the output.
+// - Scatter: each work group ranks its elements among the elements of its
+//   block with the same digit and writes them at the offset of their digit
+//   plus their rank. Equal digits keep their order so the sort is stable.
+//
+// The ranks and offsets are prefix sums of subgroup.glsl.
+
+#define WORKGROUP_SIZE 256
+#include "subgroup.glsl"
+
+// Must match RADIX_BITS.
+const uint RADIX_BITS = 4;
+const uint RADIX = 1 << RADIX_BITS;
+
+// Must match SortStage.
+const uint STAGE_COUNT = 0;
+const uint STAGE_SCAN = 1;
+const uint STAGE_SCATTER = 2;
+
+layout(constant_id = 1) const uint STAGE = STAGE_COUNT;
+
+layout(local_size_x = WORKGROUP_SIZE) in;
+
+layout(binding = 0) readonly buffer KeysIn {
+    uint keysIn[];
+};
+
+layout(binding = 1) readonly buffer ValuesIn {
+    uint valuesIn[];
+};
+
+layout(binding = 2) writeonly buffer KeysOut {
+    uint keysOut[];
+};
+
+layout(binding = 3) writeonly buffer ValuesOut {
+    uint valuesOut[];
+};
+
+// Count of each digit in each block, digit major, replaced by their offsets.
+layout(binding = 4) buffer Histogram {
+    uint histogram[];
+};
+
+layout(push_constant) uniform PushConstants {
+    uint count;
+    // Position of the lowest bit of the digit of the pass.
+    uint shift;
+} pc;
+
+shared uint digitCounts[RADIX];
+
+uint blockCount() {
+    return (pc.count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+}
+
+// Get the digit of the key at index, or RADIX past the last element.
+uint digitAt(uint index) {
+    return index < pc.count ? (keysIn[index] >> pc.shift) & (RADIX - 1) : RADIX;
+}
+
+void countDigits() {
+    uint block = gl_WorkGroupID.x;
+    uint invocation = gl_LocalInvocationIndex;
+    if (invocation < RADIX) {
+        digitCounts[invocation] = 0;
+    }
+    barrier();
+
+    uint digit = digitAt(block * WORKGROUP_SIZE + invocation);
+    if (digit < RADIX) {
+        atomicAdd(digitCounts[digit], 1);
+    }
+    barrier();
+
+    if (invocation < RADIX) {
+        histogram[invocation * blockCount() + block] = digitCounts[invocation];
+    }
+}
+
+void scanCounts() {
+    uint size = RADIX * blockCount();
+    uint carry = 0;
+    for (uint first = 0; first < size; first += WORKGROUP_SIZE) {
+        uint index = first + scanIndex();
+        uint count = index < size ? histogram[index] : 0;
+        uint total;
+        uint offset = workgroupExclusiveAdd(count, total);
+        if (index < size) {
+            histogram[index] = carry + offset;
+        }
+        carry += total;
+    }
+}
+
+void scatter() {
+    uint block = gl_WorkGroupID.x;
+    // Elements are loaded in the order of the scans so their ranks keep their order
+    uint index = block * WORKGROUP_SIZE + scanIndex();
+    uint digit = digitAt(index);
+
+    uint rank = 0;
+    for (uint d = 0; d < RADIX; d++) {
+        uint total;
+        uint digitRank = workgroupExclusiveAdd(digit == d ? 1 : 0, total);
+        if (digit == d) {
+            rank = digitRank;
+        }
+    }
+
+    if (digit < RADIX) {
+        uint target = histogram[digit * blockCount() + block] + rank;
+        keysOut[target] = keysIn[index];
+        valuesOut[target] = valuesIn[index];
+    }
+}
+
+void main() {
+    if (STAGE == STAGE_COUNT) {
+        countDigits();
+    } else if (STAGE == STAGE_SCAN) {
+        scanCounts();
+    } else {
+        scatter();
+    }
+}
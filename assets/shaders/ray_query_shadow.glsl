@@ -0,0 +1,91 @@
+// Shadows traced with ray queries against the acceleration structures of the
+// model, in the material set.
+//
+// The structures are built in the space of the model, which ubo.inverseModel
+// moves the rays to. Each light traces its number of rays towards points
+// spread over its emitter, whose size is its softness, so the shadows get
+// penumbrae. Must match ray_tracing.rs. Needs GL_EXT_ray_query.
+layout(set = 1, binding = 4) uniform accelerationStructureEXT topLevel;
+
+// Distance the origin of the rays is moved along the normal, so the surface
+// does not shadow itself.
+const float RAY_QUERY_NORMAL_OFFSET = 0.01;
+
+// Distance the rays towards the sun travel.
+const float RAY_QUERY_SUN_DISTANCE = 1000.0;
+
+// Fraction of the color kept in the shadow of the sun, like SHADOW_DARKENING.
+const float RAY_QUERY_SHADOW_DARKENING = 0.4;
+
+const float GOLDEN_ANGLE = 2.39996323;
+
+// Return whether nothing is hit from origin to origin + direction * tMax, in world space.
+bool traceVisibility(vec3 origin, vec3 direction, float tMax) {
+    // The direction is not normalized, so tMax still ends the ray at the same point
+    vec3 modelOrigin = (ubo.inverseModel * vec4(origin, 1.0)).xyz;
+    vec3 modelDirection = mat3(ubo.inverseModel) * direction;
+    rayQueryEXT query;
+    rayQueryInitializeEXT(
+        query, topLevel, gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsOpaqueEXT, 0xff,
+        modelOrigin, 0.0, modelDirection, tMax
+    );
+    while (rayQueryProceedEXT(query)) {
+    }
+    return rayQueryGetIntersectionTypeEXT(query, true) == gl_RayQueryCommittedIntersectionNoneEXT;
+}
+
+// Get the sample i of count on the unit disk, spread by the golden angle and
+// rotated by angle. A single sample is at the center.
+vec2 diskSample(uint i, uint count, float angle) {
+    float radius = count > 1 ? sqrt((float(i) + 0.5) / float(count)) : 0.0;
+    float theta = float(i) * GOLDEN_ANGLE + angle;
+    return radius * vec2(cos(theta), sin(theta));
+}
+
+// Get an angle changing between neighbor pixels, which rotates the samples so
+// their banding turns into noise.
+float pixelAngle() {
+    // Interleaved gradient noise
+    float noise = fract(52.9829189 * fract(dot(gl_FragCoord.xy, vec2(0.06711056, 0.00583715))));
+    return noise * 6.28318531;
+}
+
+// Get the fraction of the rays from origin reaching the disk of radius
+// `radius` around target, facing origin. The rays end on the disk.
+float traceDisk(vec3 origin, vec3 target, float radius, uint count) {
+    vec3 axis = normalize(target - origin);
+    vec3 up = abs(axis.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, axis));
+    vec3 bitangent = cross(axis, tangent);
+
+    float angle = pixelAngle();
+    float visible = 0.0;
+    for (uint i = 0; i < count; i++) {
+        vec2 offset = diskSample(i, count, angle) * radius;
+        vec3 point = target + tangent * offset.x + bitangent * offset.y;
+        visible += traceVisibility(origin, point - origin, 1.0) ? 1.0 : 0.0;
+    }
+    return visible / float(count);
+}
+
+// Get the fraction of the sun light reaching worldPosition.
+//
+// ubo.sunShadowSamples rays are traced towards the disk of the sun, whose
+// angular radius has the tangent ubo.sunSoftness.
+float traceSunShadow(vec3 worldPosition, vec3 normal) {
+    vec3 origin = worldPosition + normal * RAY_QUERY_NORMAL_OFFSET;
+    vec3 target = origin + ubo.sunDirection.xyz * RAY_QUERY_SUN_DISTANCE;
+    float radius = ubo.sunSoftness * RAY_QUERY_SUN_DISTANCE;
+    return traceDisk(origin, target, radius, max(ubo.sunShadowSamples, 1));
+}
+
+// Get the fraction of the light of the spot light `light` reaching worldPosition.
+//
+// The number of rays in ubo.spotLights.softness[light].y are traced towards
+// the disk of the light, whose radius is ubo.spotLights.softness[light].x.
+float traceSpotShadow(uint light, vec3 worldPosition, vec3 normal) {
+    vec3 origin = worldPosition + normal * RAY_QUERY_NORMAL_OFFSET;
+    vec4 softness = ubo.spotLights.softness[light];
+    uint count = max(uint(softness.y), 1);
+    return traceDisk(origin, ubo.spotLights.position[light].xyz, softness.x, count);
+}
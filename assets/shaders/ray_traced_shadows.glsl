@@ -0,0 +1,57 @@
+// Raygen shader of the ray-traced shadows, shared by ray_traced_shadows.rgen
+// and ray_traced_shadows_ms.rgen.
+//
+// Define MULTISAMPLED before including it when the depth buffer is multisampled.
+
+// Must match RayTracedShadowUniforms.
+layout(set = 0, binding = 0) uniform RayTracedShadowUniforms {
+    mat4 inverseModelViewProj;
+    vec4 sunDirection;
+    float darkness;
+    float bias;
+} shadows;
+
+layout(set = 0, binding = 1) uniform accelerationStructureEXT topLevel;
+
+#ifdef MULTISAMPLED
+layout(set = 0, binding = 2) uniform sampler2DMS depthBuffer;
+
+float loadDepth(ivec2 coords) {
+    return texelFetch(depthBuffer, coords, 0).r;
+}
+#else
+layout(set = 0, binding = 2) uniform sampler2D depthBuffer;
+
+float loadDepth(ivec2 coords) {
+    return texelFetch(depthBuffer, coords, 0).r;
+}
+#endif
+
+layout(set = 0, binding = 3, rgba16f) uniform image2D hdrColor;
+
+// 1 when the ray reached the sun, set by ray_traced_shadows.rmiss.
+layout(location = 0) rayPayloadEXT float visibility;
+
+// Distance the rays travel towards the sun in the space of the model.
+const float MAX_DISTANCE = 10000.0;
+
+void main() {
+    ivec2 pixel = ivec2(gl_LaunchIDEXT.xy);
+    float depth = loadDepth(pixel);
+    // Nothing was drawn there.
+    if (depth >= 1.0) {
+        return;
+    }
+
+    vec2 ndc = (vec2(pixel) + 0.5) / vec2(gl_LaunchSizeEXT.xy) * 2.0 - 1.0;
+    vec4 position = shadows.inverseModelViewProj * vec4(ndc, depth, 1.0);
+    vec3 origin = position.xyz / position.w + shadows.sunDirection.xyz * shadows.bias;
+
+    visibility = 0.0;
+    uint flags = gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsSkipClosestHitShaderEXT | gl_RayFlagsOpaqueEXT;
+    traceRayEXT(topLevel, flags, 0xff, 0, 0, 0, origin, 0.0, shadows.sunDirection.xyz, MAX_DISTANCE, 0);
+
+    vec4 color = imageLoad(hdrColor, pixel);
+    float shade = mix(1.0 - shadows.darkness, 1.0, visibility);
+    imageStore(hdrColor, pixel, vec4(color.rgb * shade, color.a));
+}
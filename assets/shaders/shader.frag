@@ -1,13 +1,18 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
 
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec2 fragCoords;
+layout(location = 2) in vec3 fragWorldPosition;
 
 layout(binding = 1) uniform sampler2D texSampler;
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    outColor = texture(texSampler, fragCoords)*vec4(fragColor, 1.0);
+    vec4 color = texture(texSampler, fragCoords)*vec4(fragColor, 1.0);
+    outColor = vec4(applyFog(color.rgb, fragWorldPosition), color.a);
 }
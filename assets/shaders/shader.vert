@@ -1,21 +1,43 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
 
 layout(location = 0) in vec3 vPosition;
 layout(location = 1) in vec3 vColor;
 layout(location = 2) in vec2 vCoords;
+layout(location = 3) in vec3 vNormal;
 
-layout(binding = 0) uniform UniformBufferObject {
-    mat4 model;
-    mat4 view;
-    mat4 proj;
-} ubo;
+#include "frame.glsl"
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec2 fragCoords;
+layout(location = 2) out vec3 fragWorldPosition;
+layout(location = 3) out vec3 fragWorldNormal;
+
+// With the LIGHTMAP keyword, the second UV set of the model is passed to the material.
+#ifdef LIGHTMAP
+layout(location = 4) in vec2 vLightmapCoords;
+layout(location = 4) out vec2 fragLightmapCoords;
+#endif
+
+// With the CLIP_PLANES keyword, the vertices are clipped by the planes of the frame.
+#ifdef CLIP_PLANES
+out float gl_ClipDistance[MAX_CLIP_PLANES];
+#endif
 
 void main() {
-    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(vPosition, 1.0);
+    vec4 worldPosition = ubo.model * vec4(vPosition, 1.0);
+    gl_Position = ubo.proj * ubo.view * worldPosition;
     fragColor = vColor;
     fragCoords = vCoords;
+    fragWorldPosition = worldPosition.xyz;
+    fragWorldNormal = mat3(ubo.model) * vNormal;
+#ifdef LIGHTMAP
+    fragLightmapCoords = vLightmapCoords;
+#endif
+#ifdef CLIP_PLANES
+    for (uint i = 0; i < MAX_CLIP_PLANES; i++) {
+        gl_ClipDistance[i] = i < ubo.clipPlaneCount ? dot(ubo.clipPlanes[i], worldPosition) : 1.0;
+    }
+#endif
 }
@@ -0,0 +1,45 @@
+// Shadow map of the sun, in the material set, and its sampling.
+//
+// The map stores the depth of the closest caster seen from the sun, which
+// ubo.shadowViewProj projects to, or with the variance filter the blurred
+// moments of the depth. Must match shadow.rs. Needs shadow_filter.glsl.
+layout(set = 1, binding = 2) uniform sampler2D shadowMap;
+
+// Distance the receiver is moved along its normal before the comparison, so
+// it does not shadow itself.
+const float SHADOW_NORMAL_OFFSET = 0.02;
+const float SHADOW_DEPTH_BIAS = 0.001;
+
+// Fraction of the color kept in the shadow, since the sun is the only light.
+const float SHADOW_DARKENING = 0.4;
+
+// Get the fraction of the sun light reaching worldPosition.
+//
+// It is filtered over 3x3 texels, or with PCSS over a radius growing with
+// the distance between the receiver and its blockers, scaled by
+// ubo.sunPenumbraScale, or bounded from the moments of the variance filter.
+float sampleShadow(vec3 worldPosition, vec3 normal) {
+    vec4 light = ubo.shadowViewProj * vec4(worldPosition + normal * SHADOW_NORMAL_OFFSET, 1.0);
+    vec3 coords = vec3(light.xy * 0.5 + 0.5, light.z);
+    if (any(lessThan(coords, vec3(0.0))) || any(greaterThan(coords, vec3(1.0)))) {
+        return 1.0;
+    }
+    if (ubo.shadowFilter == SHADOW_FILTER_VARIANCE) {
+        return chebyshevUpperBound(texture(shadowMap, coords.xy).rg, coords.z);
+    }
+
+    ivec2 size = textureSize(shadowMap, 0);
+    float depth = coords.z - SHADOW_DEPTH_BIAS;
+    if (ubo.shadowFilter == SHADOW_FILTER_PCSS) {
+        // The orthographic depth is linear, so the penumbra grows with the
+        // depth between the receiver and the blockers.
+        float texelScale = ubo.sunPenumbraScale * float(size.x);
+        float blocker = findBlockerDepth(shadowMap, coords.xy, depth, depth * texelScale, ivec2(0), size - 1);
+        if (blocker < 0.0) {
+            return 1.0;
+        }
+        float radius = (depth - blocker) * texelScale;
+        return filterShadowDisk(shadowMap, coords.xy, depth, radius, ivec2(0), size - 1);
+    }
+    return filterShadow(shadowMap, coords.xy, depth, ivec2(0), size - 1);
+}
@@ -0,0 +1,12 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+layout(location = 0) in vec3 vPosition;
+
+#include "frame.glsl"
+
+// The index of the spot light whose tile is drawn is given as the first instance.
+void main() {
+    gl_Position = ubo.spotLights.viewProj[gl_InstanceIndex] * ubo.model * vec4(vPosition, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+layout(location = 0) in vec3 vPosition;
+
+#include "frame.glsl"
+
+void main() {
+    gl_Position = ubo.shadowViewProj * ubo.model * vec4(vPosition, 1.0);
+}
@@ -0,0 +1,93 @@
+// Filtering shared by the shadow maps.
+
+// Must match ShadowFilter.
+const uint SHADOW_FILTER_PCF = 0;
+const uint SHADOW_FILTER_PCSS = 1;
+const uint SHADOW_FILTER_VARIANCE = 2;
+
+// Smallest variance of the moments, which hides the acne of flat receivers.
+const float VSM_MIN_VARIANCE = 0.00002;
+
+// Fraction of the upper bound of the light cut off to reduce the light leaking
+// where the casters overlap.
+const float VSM_LIGHT_BLEEDING_REDUCTION = 0.3;
+
+// Largest radius in texels of the blocker search and of the filter of PCSS.
+const float PCSS_MAX_RADIUS = 24.0;
+
+const int PCSS_SAMPLE_COUNT = 16;
+
+// Poisson disk in the unit circle sampled by the blocker search and the filter of PCSS.
+const vec2 PCSS_POISSON_DISK[PCSS_SAMPLE_COUNT] = vec2[](
+    vec2(-0.9420, -0.3991), vec2(0.9456, -0.7689), vec2(-0.0942, -0.9294), vec2(0.3450, 0.2939),
+    vec2(-0.9159, 0.4577), vec2(-0.8154, -0.8791), vec2(-0.3828, 0.2768), vec2(0.9748, 0.7565),
+    vec2(0.4432, -0.9751), vec2(0.5374, -0.4737), vec2(-0.2650, -0.4190), vec2(0.7920, 0.1909),
+    vec2(-0.2419, 0.9971), vec2(-0.8141, 0.9144), vec2(0.1998, 0.7864), vec2(0.1438, -0.1410)
+);
+
+// Fetch the depth of the closest caster of shadowMap at the texel of uv
+// offset by offset texels, clamped to the tile from tileMin to tileMax so the
+// filters do not read the tiles next to it in an atlas.
+float fetchOccluder(sampler2D shadowMap, vec2 uv, vec2 offset, ivec2 tileMin, ivec2 tileMax) {
+    ivec2 texel = ivec2(uv * textureSize(shadowMap, 0) + offset);
+    return texelFetch(shadowMap, clamp(texel, tileMin, tileMax), 0).r;
+}
+
+// Get the fraction of the 3x3 texels of shadowMap around uv whose caster is
+// not closer than depth.
+float filterShadow(sampler2D shadowMap, vec2 uv, float depth, ivec2 tileMin, ivec2 tileMax) {
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            float occluder = fetchOccluder(shadowMap, uv, vec2(x, y), tileMin, tileMax);
+            lit += depth <= occluder ? 1.0 : 0.0;
+        }
+    }
+    return lit / 9.0;
+}
+
+// Get the average depth of the casters closer than depth within radius texels
+// of uv, or a negative depth if there are none.
+float findBlockerDepth(
+    sampler2D shadowMap, vec2 uv, float depth, float radius, ivec2 tileMin, ivec2 tileMax
+) {
+    radius = clamp(radius, 1.0, PCSS_MAX_RADIUS);
+    float blockerSum = 0.0;
+    float blockerCount = 0.0;
+    for (int i = 0; i < PCSS_SAMPLE_COUNT; i++) {
+        vec2 offset = PCSS_POISSON_DISK[i] * radius;
+        float occluder = fetchOccluder(shadowMap, uv, offset, tileMin, tileMax);
+        if (occluder < depth) {
+            blockerSum += occluder;
+            blockerCount += 1.0;
+        }
+    }
+    return blockerCount > 0.0 ? blockerSum / blockerCount : -1.0;
+}
+
+// Get the fraction of the texels of shadowMap within radius texels of uv
+// whose caster is not closer than depth, the variable filter of PCSS.
+float filterShadowDisk(
+    sampler2D shadowMap, vec2 uv, float depth, float radius, ivec2 tileMin, ivec2 tileMax
+) {
+    radius = clamp(radius, 1.0, PCSS_MAX_RADIUS);
+    float lit = 0.0;
+    for (int i = 0; i < PCSS_SAMPLE_COUNT; i++) {
+        vec2 offset = PCSS_POISSON_DISK[i] * radius;
+        float occluder = fetchOccluder(shadowMap, uv, offset, tileMin, tileMax);
+        lit += depth <= occluder ? 1.0 : 0.0;
+    }
+    return lit / float(PCSS_SAMPLE_COUNT);
+}
+
+// Get the upper bound of the fraction of the light reaching depth given the
+// blurred moments of the depth of the casters, from Chebyshev's inequality.
+float chebyshevUpperBound(vec2 moments, float depth) {
+    if (depth <= moments.x) {
+        return 1.0;
+    }
+    float variance = max(moments.y - moments.x * moments.x, VSM_MIN_VARIANCE);
+    float distance = depth - moments.x;
+    float pMax = variance / (variance + distance * distance);
+    return clamp((pMax - VSM_LIGHT_BLEEDING_REDUCTION) / (1.0 - VSM_LIGHT_BLEEDING_REDUCTION), 0.0, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// First two moments of the depth of the caster, for variance shadow maps.
+layout(location = 0) out vec2 outMoments;
+
+void main() {
+    float depth = gl_FragCoord.z;
+    // Bias the second moment by the depth variation over the pixel, which
+    // reduces the acne on slopes.
+    float dx = dFdx(depth);
+    float dy = dFdy(depth);
+    outMoments = vec2(depth, depth * depth + 0.25 * (dx * dx + dy * dy));
+}
@@ -0,0 +1,66 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+// Scale applied to the luminance before tone mapping.
+//
+// Must match EXPOSURE in sky.rs.
+const float EXPOSURE = 0.08;
+// Cosine of the angular radius of the sun disk.
+const float SUN_DISK_COS = 0.9995;
+
+layout(set = 1, binding = 0) uniform SkyUniforms {
+    vec4 sunDirection;
+    // Coefficients A to E of the Perez function for Y, x and y.
+    vec4 perez[5];
+    vec4 zenith;
+} sky;
+
+layout(location = 0) in vec2 fragNdc;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 perezFunction(float cosTheta, float gamma) {
+    vec3 a = sky.perez[0].xyz;
+    vec3 b = sky.perez[1].xyz;
+    vec3 c = sky.perez[2].xyz;
+    vec3 d = sky.perez[3].xyz;
+    vec3 e = sky.perez[4].xyz;
+    float cosGamma = cos(gamma);
+    return (1.0 + a * exp(b / cosTheta)) * (1.0 + c * exp(d * gamma) + e * cosGamma * cosGamma);
+}
+
+vec3 yxyToRgb(vec3 yxy) {
+    float luminance = yxy.x;
+    vec3 xyz = vec3(
+        yxy.y / yxy.z * luminance,
+        luminance,
+        (1.0 - yxy.y - yxy.z) / yxy.z * luminance);
+    return mat3(
+        3.2406, -0.9689, 0.0557,
+        -1.5372, 1.8758, -0.2040,
+        -0.4986, 0.0415, 1.0570) * xyz;
+}
+
+void main() {
+    vec4 farPoint = ubo.inverseViewProj * vec4(fragNdc, 1.0, 1.0);
+    vec3 direction = normalize(farPoint.xyz / farPoint.w - ubo.cameraPosition.xyz);
+    vec3 sunDirection = sky.sunDirection.xyz;
+
+    // The model is not defined below the horizon so the horizon color is repeated.
+    float cosTheta = max(direction.y, 0.01);
+    float cosGamma = dot(direction, sunDirection);
+    float gamma = acos(clamp(cosGamma, -1.0, 1.0));
+
+    vec3 yxy = sky.zenith.xyz * perezFunction(cosTheta, gamma);
+    yxy.x = 1.0 - exp(-yxy.x * EXPOSURE);
+    vec3 color = max(yxyToRgb(yxy), vec3(0.0));
+
+    if (cosGamma > SUN_DISK_COS && direction.y > 0.0) {
+        color += vec3(1.0, 0.95, 0.85);
+    }
+
+    outColor = vec4(color, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "motion_blur.glsl"
+
+layout(location = 0) in vec2 fragCoords;
+
+layout(location = 0) out vec2 outVelocity;
+
+void main() {
+    vec2 current = fragCoords * 2.0 - 1.0;
+    vec4 previousPosition = ubo.skyReprojection * vec4(current, 1.0, 1.0);
+    vec2 previous = previousPosition.xy / previousPosition.w;
+    outVelocity = (current - previous) * 0.5;
+}
@@ -0,0 +1,98 @@
+// Shadow atlas of the spot lights, in the material set, and the light of the
+// spot lights casting shadows in it.
+//
+// Each light draws its casters in a tile of the atlas, which
+// ubo.spotLights.viewProj projects to before ubo.spotLights.atlasTransform
+// scales and offsets the coordinates to the tile. Must match shadow_atlas.rs.
+// Needs shadow_filter.glsl.
+layout(set = 1, binding = 3) uniform sampler2D shadowAtlas;
+
+// Distance the receiver is moved along its normal before the comparison, so
+// it does not shadow itself.
+const float SPOT_SHADOW_NORMAL_OFFSET = 0.02;
+const float SPOT_SHADOW_DEPTH_BIAS = 0.0005;
+
+// Distance from a spot light to the near plane of its projection.
+//
+// Must match NEAR in shadow_atlas.rs.
+const float SPOT_SHADOW_NEAR = 0.05;
+
+// Fraction of the cosine of the cone angle over which the light fades out at its edge.
+const float SPOT_LIGHT_SOFTNESS = 0.1;
+const float SPOT_LIGHT_INTENSITY = 1.5;
+
+// Get the distance along the axis of the spot light `light` of the normalized depth in its tile.
+float linearizeSpotDepth(uint light, float depth) {
+    float far = ubo.spotLights.position[light].w;
+    return SPOT_SHADOW_NEAR * far / (far - depth * (far - SPOT_SHADOW_NEAR));
+}
+
+// Get the fraction of the light of the spot light `light` reaching worldPosition.
+//
+// It is filtered over 3x3 texels of its tile, or with PCSS over a radius
+// growing with the distance between the receiver and its blockers, scaled by
+// the softness of the light. The atlas has no moments, so the variance filter
+// falls back to the 3x3 filter.
+float sampleSpotShadow(uint light, vec3 worldPosition, vec3 normal) {
+    vec4 transform = ubo.spotLights.atlasTransform[light];
+    vec4 clip = ubo.spotLights.viewProj[light]
+        * vec4(worldPosition + normal * SPOT_SHADOW_NORMAL_OFFSET, 1.0);
+    vec3 coords = vec3(clip.xy / clip.w * 0.5 + 0.5, clip.z / clip.w);
+    if (clip.w <= 0.0 || any(lessThan(coords, vec3(0.0))) || any(greaterThan(coords, vec3(1.0)))) {
+        return 1.0;
+    }
+
+    vec2 size = vec2(textureSize(shadowAtlas, 0));
+    ivec2 tileMin = ivec2(transform.zw * size);
+    ivec2 tileMax = tileMin + ivec2(transform.xy * size) - 1;
+    vec2 uv = coords.xy * transform.xy + transform.zw;
+    float depth = coords.z - SPOT_SHADOW_DEPTH_BIAS;
+    if (ubo.shadowFilter == SHADOW_FILTER_PCSS) {
+        // Size in texels of one world unit at one unit from the light
+        float tanAngle = tan(acos(ubo.spotLights.direction[light].w));
+        float texelScale = ubo.spotLights.softness[light].x * transform.x * size.x / (2.0 * tanAngle);
+        float receiver = linearizeSpotDepth(light, depth);
+        float searchRadius = texelScale * (receiver - SPOT_SHADOW_NEAR) / (receiver * SPOT_SHADOW_NEAR);
+        float blocker = findBlockerDepth(shadowAtlas, uv, depth, searchRadius, tileMin, tileMax);
+        if (blocker < 0.0) {
+            return 1.0;
+        }
+        blocker = linearizeSpotDepth(light, blocker);
+        float radius = texelScale * (receiver - blocker) / (receiver * blocker);
+        return filterShadowDisk(shadowAtlas, uv, depth, radius, tileMin, tileMax);
+    }
+    return filterShadow(shadowAtlas, uv, depth, tileMin, tileMax);
+}
+
+// Get the light of the shadowed spot lights reaching worldPosition, whose
+// surface faces normal.
+//
+// With the RAY_QUERY_SHADOWS keyword, their shadows are traced instead of
+// read from the atlas.
+float evaluateSpotLights(vec3 worldPosition, vec3 normal) {
+    float light = 0.0;
+    for (uint i = 0; i < ubo.spotLights.count; i++) {
+        vec4 position = ubo.spotLights.position[i];
+        vec4 direction = ubo.spotLights.direction[i];
+        vec3 toLight = position.xyz - worldPosition;
+        float distance = length(toLight);
+        if (distance >= position.w) {
+            continue;
+        }
+
+        vec3 lightDirection = toLight / distance;
+        float cosAngle = dot(-lightDirection, direction.xyz);
+        float cone = smoothstep(direction.w, mix(direction.w, 1.0, SPOT_LIGHT_SOFTNESS), cosAngle);
+        float falloff = 1.0 - distance / position.w;
+        float diffuse = max(dot(normal, lightDirection), 0.0);
+        if (cone * diffuse > 0.0) {
+#ifdef RAY_QUERY_SHADOWS
+            float shadow = traceSpotShadow(i, worldPosition, normal);
+#else
+            float shadow = sampleSpotShadow(i, worldPosition, normal);
+#endif
+            light += cone * falloff * falloff * diffuse * shadow;
+        }
+    }
+    return light * SPOT_LIGHT_INTENSITY;
+}
@@ -0,0 +1,22 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+layout(set = 1, binding = 0) uniform sampler2D atlas;
+
+layout(location = 0) in vec2 fragCoords;
+layout(location = 1) in vec4 fragColor;
+layout(location = 2) in vec3 fragWorldPosition;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec4 color = texture(atlas, fragCoords) * fragColor;
+    // Do not write the depth of the transparent parts of the quad.
+    if (color.a < 0.05) {
+        discard;
+    }
+    outColor = vec4(applyFog(color.rgb, fragWorldPosition), color.a);
+}
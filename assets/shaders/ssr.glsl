@@ -0,0 +1,221 @@
+// Fragment shader of the screen-space reflections, shared by ssr.frag and ssr_ms.frag.
+//
+// Define MULTISAMPLED before including it when the depth buffer is
+// multisampled, and REFLECTION_PROBES to fall back to the probes bound in set 2
+// where the ray leaves the screen.
+
+#include "frame.glsl"
+
+#ifdef REFLECTION_PROBES
+#include "probe.glsl"
+#endif
+
+// Must match SsrUniforms.
+layout(set = 1, binding = 0) uniform SsrUniforms {
+    uint maxSteps;
+    float maxDistance;
+    float thickness;
+    float roughness;
+    float intensity;
+    // 0 until the history holds a reflected frame.
+    uint historyValid;
+} ssr;
+
+layout(set = 1, binding = 1) uniform sampler2D hdrColor;
+
+#ifdef MULTISAMPLED
+layout(set = 1, binding = 2) uniform sampler2DMS depthBuffer;
+
+ivec2 depthSize() {
+    return textureSize(depthBuffer);
+}
+
+float loadDepth(ivec2 coords) {
+    return texelFetch(depthBuffer, coords, 0).r;
+}
+#else
+layout(set = 1, binding = 2) uniform sampler2D depthBuffer;
+
+ivec2 depthSize() {
+    return textureSize(depthBuffer, 0);
+}
+
+float loadDepth(ivec2 coords) {
+    return texelFetch(depthBuffer, coords, 0).r;
+}
+#endif
+
+// Furthest depth in x and closest depth in y, see hiz.glsl.
+layout(set = 1, binding = 3) uniform sampler2D depthPyramid;
+// Motion of the surfaces since the previous frame in texture coordinates, see velocity.frag.
+layout(set = 1, binding = 4) uniform sampler2D velocityBuffer;
+// Output of the pass in the previous frame.
+layout(set = 1, binding = 5) uniform sampler2D history;
+
+layout(location = 0) in vec2 fragCoords;
+
+layout(location = 0) out vec4 outColor;
+
+// Part of a cell the ray moves past its boundary, so the next cell is entered.
+const float CROSS_OFFSET = 0.01;
+
+// Fraction of the screen on each edge where the reflections fade out.
+const float EDGE_FADE = 0.1;
+
+// Position in view space of the surface seen through the pixel `coords`.
+vec3 viewPosition(ivec2 coords) {
+    vec2 ndc = (vec2(coords) + 0.5) / vec2(depthSize()) * 2.0 - 1.0;
+    vec4 position = ubo.inverseProj * vec4(ndc, loadDepth(coords), 1.0);
+    return position.xyz / position.w;
+}
+
+// Project a position in view space to texture coordinates and depth.
+vec3 project(vec3 position) {
+    vec4 clip = ubo.proj * vec4(position, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    return vec3(ndc.xy * 0.5 + 0.5, ndc.z);
+}
+
+// Distance to the camera along the view axis of the depth `depth`.
+float linearDepth(float depth) {
+    vec4 position = ubo.inverseProj * vec4(0.0, 0.0, depth, 1.0);
+    return -position.z / position.w;
+}
+
+// Normal in view space of the surface seen through the pixel `coords`.
+//
+// It is reconstructed from the neighbor on each axis whose depth is the
+// closest, so the edges of objects do not bend it. It faces the camera.
+vec3 viewNormal(ivec2 coords, vec3 position) {
+    ivec2 maxCoords = depthSize() - 1;
+    vec3 left = viewPosition(max(coords - ivec2(1, 0), ivec2(0)));
+    vec3 right = viewPosition(min(coords + ivec2(1, 0), maxCoords));
+    vec3 down = viewPosition(max(coords - ivec2(0, 1), ivec2(0)));
+    vec3 up = viewPosition(min(coords + ivec2(0, 1), maxCoords));
+    vec3 dx = abs(right.z - position.z) < abs(position.z - left.z) ? right - position : position - left;
+    vec3 dy = abs(up.z - position.z) < abs(position.z - down.z) ? up - position : position - down;
+    vec3 normal = normalize(cross(dx, dy));
+    return dot(normal, position) > 0.0 ? -normal : normal;
+}
+
+// March from origin along direction, both in texture coordinates and depth,
+// through the closest depths of the pyramid.
+//
+// The ray covers whole cells at once while it stays in front of their closest
+// depth and moves up a level after each of them. When it reaches the closest
+// depth of a cell, it moves to that depth and down a level. In the first level,
+// it hits the surface of the depth buffer if it is less than the thickness
+// behind it. Return whether it hit and the coordinates of the hit.
+bool trace(vec3 origin, vec3 direction, out vec2 hitCoords) {
+    hitCoords = vec2(0.0);
+    float pixelLength = length(direction.xy * vec2(depthSize()));
+    if (pixelLength < 1.0) {
+        return false;
+    }
+
+    int maxLevel = textureQueryLevels(depthPyramid) - 1;
+    int level = 0;
+    // Skip the pixel of the origin
+    float t = 1.0 / pixelLength;
+    for (uint i = 0; i < ssr.maxSteps; i++) {
+        vec3 position = origin + direction * t;
+        if (t >= 1.0 || any(lessThan(position.xy, vec2(0.0))) || any(greaterThanEqual(position.xy, vec2(1.0)))) {
+            return false;
+        }
+
+        vec2 size = vec2(textureSize(depthPyramid, level));
+        vec2 cell = floor(position.xy * size);
+        vec2 boundary = (cell + 0.5 + sign(direction.xy) * (0.5 + CROSS_OFFSET)) / size;
+        float exitX = direction.x != 0.0 ? (boundary.x - origin.x) / direction.x : 1e30;
+        float exitY = direction.y != 0.0 ? (boundary.y - origin.y) / direction.y : 1e30;
+        float exit = min(exitX, exitY);
+        float closest = texelFetch(depthPyramid, ivec2(cell), level).y;
+
+        if (position.z < closest) {
+            float reach = direction.z > 0.0 ? (closest - origin.z) / direction.z : 1e30;
+            if (reach >= exit) {
+                // In front of the whole cell
+                t = exit;
+                level = min(level + 1, maxLevel);
+                continue;
+            }
+            t = reach;
+        }
+        if (level > 0) {
+            level--;
+            continue;
+        }
+
+        position = origin + direction * t;
+        ivec2 pixel = min(ivec2(position.xy * vec2(depthSize())), depthSize() - 1);
+        float sceneDepth = linearDepth(loadDepth(pixel));
+        float rayDepth = linearDepth(position.z);
+        if (rayDepth >= sceneDepth && rayDepth - sceneDepth < ssr.thickness) {
+            hitCoords = position.xy;
+            return true;
+        }
+        // Behind the surface or in front of the depths of the full resolution
+        t = max(t, exit);
+    }
+    return false;
+}
+
+// Reflection of the probes in the direction `viewDirection`, in view space.
+vec3 probeReflection(vec3 viewDirection, vec3 fallback) {
+#ifdef REFLECTION_PROBES
+    if (probeUniforms.weights.x + probeUniforms.weights.y == 0.0) {
+        return fallback;
+    }
+    vec3 direction = mat3(ubo.inverseView) * viewDirection;
+    float lod = ssr.roughness * float(textureQueryLevels(probeFaces) - 1);
+    return sampleProbe(probeUniforms.indices.x, direction, lod) * probeUniforms.weights.x
+        + sampleProbe(probeUniforms.indices.y, direction, lod) * probeUniforms.weights.y;
+#else
+    return fallback;
+#endif
+}
+
+void main() {
+    vec3 color = texture(hdrColor, fragCoords).rgb;
+    ivec2 pixel = min(ivec2(gl_FragCoord.xy), depthSize() - 1);
+    float depth = loadDepth(pixel);
+    // Nothing was drawn there.
+    if (depth >= 1.0) {
+        outColor = vec4(color, 1.0);
+        return;
+    }
+
+    vec3 position = viewPosition(pixel);
+    vec3 normal = viewNormal(pixel, position);
+    vec3 viewDirection = normalize(position);
+    vec3 reflected = reflect(viewDirection, normal);
+
+    // Clip the ray to the near plane, where it can no longer be projected
+    vec4 near = ubo.inverseProj * vec4(0.0, 0.0, 0.0, 1.0);
+    float nearZ = near.z / near.w;
+    float rayLength = ssr.maxDistance;
+    if (reflected.z > 0.0) {
+        rayLength = min(rayLength, (nearZ - position.z) / reflected.z * 0.99);
+    }
+    vec3 origin = project(position);
+    vec3 end = project(position + reflected * rayLength);
+
+    // Reflections already in the previous frame are found where the hit
+    // surface was then, with the reflections it had
+    vec3 fallback = probeReflection(reflected, color);
+    vec3 reflection = fallback;
+    vec2 hitCoords;
+    if (ssr.historyValid != 0 && trace(origin, end - origin, hitCoords)) {
+        vec2 previousCoords = hitCoords - texture(velocityBuffer, hitCoords).xy;
+        vec2 edges = min(previousCoords, 1.0 - previousCoords);
+        float confidence = clamp(min(edges.x, edges.y) / EDGE_FADE, 0.0, 1.0);
+        // Rays coming back toward the camera hit the back of surfaces
+        confidence *= clamp(1.0 - reflected.z * 2.0, 0.0, 1.0);
+        vec3 hit = texture(history, previousCoords).rgb;
+        reflection = mix(fallback, hit, confidence);
+    }
+
+    float fresnel = 0.04 + 0.96 * pow(1.0 - max(dot(-viewDirection, normal), 0.0), 5.0);
+    float weight = fresnel * (1.0 - ssr.roughness) * ssr.intensity;
+    outColor = vec4(mix(color, reflection, clamp(weight, 0.0, 1.0)), 1.0);
+}
@@ -0,0 +1,27 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_EXT_multiview : require
+
+// Must match EYE_COUNT in stereo.rs.
+#define EYE_COUNT 2
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 1) in vec3 vColor;
+layout(location = 2) in vec2 vCoords;
+
+layout(binding = 0) uniform StereoUniforms {
+    mat4 model;
+    mat4 views[EYE_COUNT];
+    mat4 projs[EYE_COUNT];
+} ubo;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec2 fragCoords;
+
+void main() {
+    // The draw is broadcast to each eye, gl_ViewIndex is the eye being rendered.
+    mat4 viewProj = ubo.projs[gl_ViewIndex] * ubo.views[gl_ViewIndex];
+    gl_Position = viewProj * ubo.model * vec4(vPosition, 1.0);
+    fragColor = vColor;
+    fragCoords = vCoords;
+}
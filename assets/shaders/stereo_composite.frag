@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 fragCoords;
+
+// One layer per eye.
+layout(set = 1, binding = 0) uniform sampler2DArray eyes;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // Left eye on the left half of the screen, right eye on the right half.
+    float eye = step(0.5, fragCoords.x);
+    vec2 coords = vec2(fragCoords.x * 2.0 - eye, fragCoords.y);
+    outColor = texture(eyes, vec3(coords, eye));
+}
@@ -0,0 +1,143 @@
+// Work group reductions and prefix sums.
+//
+// The shader including it must define WORKGROUP_SIZE, the number of
+// invocations of its work groups, a power of two. The helpers must be called
+// by all the invocations of the work group, in uniform control flow.
+//
+// With the SUBGROUP_ARITHMETIC keyword each subgroup reduces or scans its
+// values with subgroup operations, then the results of the subgroups are
+// combined through shared memory, with two barriers in place of one per step
+// of a tree through shared memory otherwise. The shader must then enable
+// GL_KHR_shader_subgroup_basic and GL_KHR_shader_subgroup_arithmetic, and
+// the subgroup size is given as the specialization constant 0.
+
+#ifdef SUBGROUP_ARITHMETIC
+
+// Must match SubgroupProperties::specialization_constants.
+layout(constant_id = 0) const uint SUBGROUP_SIZE = 32;
+
+const uint MAX_SUBGROUPS = (WORKGROUP_SIZE + SUBGROUP_SIZE - 1) / SUBGROUP_SIZE;
+
+shared float subgroupSums[MAX_SUBGROUPS];
+shared uint subgroupOffsets[MAX_SUBGROUPS];
+shared float workgroupSum;
+shared uint workgroupTotal;
+
+// Get the index of the invocation in the order of the prefix sums.
+//
+// Invocations are ordered by subgroup, which may differ from
+// gl_LocalInvocationIndex.
+uint scanIndex() {
+    return gl_SubgroupID * gl_SubgroupSize + gl_SubgroupInvocationID;
+}
+
+// Get the sum of value over the work group.
+float workgroupAdd(float value) {
+    float sum = subgroupAdd(value);
+    if (subgroupElect()) {
+        subgroupSums[gl_SubgroupID] = sum;
+    }
+    barrier();
+
+    // The first subgroup sums the sums of all the subgroups
+    if (gl_SubgroupID == 0) {
+        float total = 0.0;
+        for (uint i = gl_SubgroupInvocationID; i < gl_NumSubgroups; i += gl_SubgroupSize) {
+            total += subgroupSums[i];
+        }
+        total = subgroupAdd(total);
+        if (subgroupElect()) {
+            workgroupSum = total;
+        }
+    }
+    barrier();
+
+    return workgroupSum;
+}
+
+// Get the sum of value over the invocations before this one in the order of
+// scanIndex, and the sum over the work group in total.
+uint workgroupExclusiveAdd(uint value, out uint total) {
+    uint prefix = subgroupExclusiveAdd(value);
+    if (gl_SubgroupInvocationID == gl_SubgroupSize - 1) {
+        subgroupOffsets[gl_SubgroupID] = prefix + value;
+    }
+    barrier();
+
+    // The first subgroup scans the totals of all the subgroups
+    if (gl_SubgroupID == 0) {
+        uint carry = 0;
+        for (uint first = 0; first < gl_NumSubgroups; first += gl_SubgroupSize) {
+            uint i = first + gl_SubgroupInvocationID;
+            uint subgroupTotal = i < gl_NumSubgroups ? subgroupOffsets[i] : 0;
+            uint offset = carry + subgroupExclusiveAdd(subgroupTotal);
+            if (i < gl_NumSubgroups) {
+                subgroupOffsets[i] = offset;
+            }
+            carry += subgroupAdd(subgroupTotal);
+        }
+        if (subgroupElect()) {
+            workgroupTotal = carry;
+        }
+    }
+    barrier();
+
+    total = workgroupTotal;
+    uint result = subgroupOffsets[gl_SubgroupID] + prefix;
+    // The offsets are overwritten by the next scan
+    barrier();
+    return result;
+}
+
+#else
+
+shared float reductionValues[WORKGROUP_SIZE];
+shared uint scanValues[WORKGROUP_SIZE];
+
+// Get the index of the invocation in the order of the prefix sums.
+uint scanIndex() {
+    return gl_LocalInvocationIndex;
+}
+
+// Get the sum of value over the work group.
+float workgroupAdd(float value) {
+    uint index = gl_LocalInvocationIndex;
+    reductionValues[index] = value;
+    barrier();
+
+    for (uint stride = WORKGROUP_SIZE / 2; stride > 0; stride >>= 1) {
+        if (index < stride) {
+            reductionValues[index] += reductionValues[index + stride];
+        }
+        barrier();
+    }
+
+    float total = reductionValues[0];
+    // The values are overwritten by the next reduction
+    barrier();
+    return total;
+}
+
+// Get the sum of value over the invocations before this one in the order of
+// scanIndex, and the sum over the work group in total.
+uint workgroupExclusiveAdd(uint value, out uint total) {
+    uint index = gl_LocalInvocationIndex;
+    scanValues[index] = value;
+    barrier();
+
+    // Inclusive scan, doubling the distance of the added value at each step
+    for (uint offset = 1; offset < WORKGROUP_SIZE; offset <<= 1) {
+        uint previous = index >= offset ? scanValues[index - offset] : 0;
+        barrier();
+        scanValues[index] += previous;
+        barrier();
+    }
+
+    total = scanValues[WORKGROUP_SIZE - 1];
+    uint result = scanValues[index] - value;
+    // The values are overwritten by the next scan
+    barrier();
+    return result;
+}
+
+#endif
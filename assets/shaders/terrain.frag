@@ -0,0 +1,35 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+// Must match LAYER_COUNT.
+const int LAYER_COUNT = 4;
+// Number of times the layer textures are repeated along each side of the terrain.
+const float LAYER_TILING = 32.0;
+const vec3 LIGHT_DIRECTION = normalize(vec3(1.0, 2.0, 1.0));
+
+layout(set = 1, binding = 0) uniform sampler2D splatMap;
+layout(set = 1, binding = 1) uniform sampler2D layers[LAYER_COUNT];
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec2 fragCoords;
+layout(location = 2) in vec3 fragWorldPosition;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec4 weights = texture(splatMap, fragCoords);
+    weights /= max(dot(weights, vec4(1.0)), 0.0001);
+
+    vec2 layerCoords = fragCoords * LAYER_TILING;
+    vec3 albedo = texture(layers[0], layerCoords).rgb * weights.r
+        + texture(layers[1], layerCoords).rgb * weights.g
+        + texture(layers[2], layerCoords).rgb * weights.b
+        + texture(layers[3], layerCoords).rgb * weights.a;
+
+    float diffuse = max(dot(normalize(fragNormal), LIGHT_DIRECTION), 0.0);
+    vec3 color = albedo * (0.2 + 0.8 * diffuse);
+    outColor = vec4(applyFog(color, fragWorldPosition), 1.0);
+}
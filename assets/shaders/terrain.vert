@@ -0,0 +1,21 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+layout(location = 0) in vec3 vPosition;
+layout(location = 1) in vec3 vNormal;
+layout(location = 2) in vec2 vCoords;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec2 fragCoords;
+layout(location = 2) out vec3 fragWorldPosition;
+
+void main() {
+    // Terrain vertices are already in world space.
+    gl_Position = ubo.proj * ubo.view * vec4(vPosition, 1.0);
+    fragNormal = vNormal;
+    fragCoords = vCoords;
+    fragWorldPosition = vPosition;
+}
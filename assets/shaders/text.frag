@@ -0,0 +1,17 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(binding = 0) uniform sampler2D atlas;
+
+layout(location = 0) in vec2 fragCoords;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // The outline is at 0.5. Smooth it over about a pixel whatever the text size.
+    float signedDistance = texture(atlas, fragCoords).r;
+    float width = fwidth(signedDistance);
+    float alpha = smoothstep(0.5 - width, 0.5 + width, signedDistance);
+    outColor = vec4(fragColor.rgb, fragColor.a * alpha);
+}
@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 vPosition;
+layout(location = 1) in vec2 vCoords;
+layout(location = 2) in vec4 vColor;
+
+layout(location = 0) out vec2 fragCoords;
+layout(location = 1) out vec4 fragColor;
+
+void main() {
+    // Text quads are laid out in normalized device coordinates on the CPU.
+    gl_Position = vec4(vPosition, 0.0, 1.0);
+    fragCoords = vCoords;
+    fragColor = vColor;
+}
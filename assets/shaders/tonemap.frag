@@ -0,0 +1,71 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(binding = 0) uniform sampler2D hdrColor;
+
+// Must match ExposureData.
+layout(binding = 1) readonly buffer Exposure {
+    float exposure;
+    float averageLuminance;
+} exposure;
+
+layout(binding = 2) uniform sampler3D colorLut;
+
+layout(location = 0) in vec2 fragCoords;
+
+layout(location = 0) out vec4 outColor;
+
+// With the BLOOM keyword, the levels of the bloom chain are blended with the HDR image.
+#ifdef BLOOM
+layout(binding = 3) uniform sampler2D bloomChain;
+
+// Fraction of the color replaced by the light spread around it.
+const float BLOOM_INTENSITY = 0.04;
+
+// Average the levels of the chain but the first one, which spreads the light too little.
+vec3 bloom() {
+    int levelCount = textureQueryLevels(bloomChain);
+    vec3 color = vec3(0.0);
+    for (int level = 1; level < levelCount; level++) {
+        color += textureLod(bloomChain, fragCoords, float(level)).rgb;
+    }
+    return color / float(max(levelCount - 1, 1));
+}
+#endif
+
+// Fit of the ACES filmic curve by Krzysztof Narkowicz.
+vec3 aces(vec3 x) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+vec3 linearToSrgb(vec3 color) {
+    return mix(12.92 * color, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+vec3 srgbToLinear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+// Look the color up in the LUT, between the centers of the first and last texels.
+//
+// LUTs are authored for display so the lookup happens on sRGB encoded colors,
+// the swapchain encodes the linear result again.
+vec3 grade(vec3 color) {
+    float size = float(textureSize(colorLut, 0).x);
+    vec3 coords = linearToSrgb(color) * ((size - 1.0) / size) + 0.5 / size;
+    return srgbToLinear(texture(colorLut, coords).rgb);
+}
+
+void main() {
+    vec3 color = texture(hdrColor, fragCoords).rgb;
+#ifdef BLOOM
+    color = mix(color, bloom(), BLOOM_INTENSITY);
+#endif
+    color *= exposure.exposure;
+    outColor = vec4(grade(aces(color)), 1.0);
+}
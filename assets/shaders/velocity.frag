@@ -0,0 +1,14 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec4 currentPosition;
+layout(location = 1) in vec4 previousPosition;
+
+layout(location = 0) out vec2 outVelocity;
+
+void main() {
+    // From normalized device coordinates to texture coordinates
+    vec2 current = currentPosition.xy / currentPosition.w;
+    vec2 previous = previousPosition.xy / previousPosition.w;
+    outVelocity = (current - previous) * 0.5;
+}
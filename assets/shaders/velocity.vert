@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "motion_blur.glsl"
+
+layout(location = 0) in vec3 vPosition;
+
+layout(location = 0) out vec4 currentPosition;
+layout(location = 1) out vec4 previousPosition;
+
+void main() {
+    currentPosition = ubo.viewProj * ubo.model * vec4(vPosition, 1.0);
+    previousPosition = ubo.previousViewProj * ubo.previousModel * vec4(vPosition, 1.0);
+    gl_Position = currentPosition;
+}
@@ -0,0 +1,52 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+// Must match MAX_LEVELS in virtual_texture.rs.
+const uint MAX_LEVELS = 16;
+
+layout(set = 1, binding = 0) uniform sampler2D virtualTexture;
+
+// Must match VirtualTexture::write_page_table.
+layout(std430, set = 1, binding = 1) readonly buffer PageTable {
+    uint size;
+    uint pageSize;
+    // First level of the mip tail, always resident.
+    uint tailFirstLod;
+    uint levelOffsets[MAX_LEVELS];
+    // Whether each page is resident, level after level.
+    uint resident[];
+} pageTable;
+
+// Pages sampled this frame, cleared before the pass and read back by the CPU.
+layout(std430, set = 1, binding = 2) writeonly buffer Feedback {
+    uint requested[];
+} feedback;
+
+layout(location = 0) in vec2 fragCoords;
+layout(location = 1) in vec3 fragWorldPosition;
+
+layout(location = 0) out vec4 outColor;
+
+uint pageIndex(uint level, vec2 uv) {
+    uint pages = max(max(pageTable.size >> level, 1u) / pageTable.pageSize, 1u);
+    uvec2 page = min(uvec2(clamp(uv, 0.0, 1.0) * float(pages)), uvec2(pages - 1u));
+    return pageTable.levelOffsets[level] + page.y * pages + page.x;
+}
+
+void main() {
+    uint tail = pageTable.tailFirstLod;
+    uint level = min(uint(max(textureQueryLod(virtualTexture, fragCoords).y, 0.0)), tail);
+    if (level < tail) {
+        feedback.requested[pageIndex(level, fragCoords)] = 1u;
+    }
+
+    // Fall back to the finest resident level, the mip tail at worst.
+    while (level < tail && pageTable.resident[pageIndex(level, fragCoords)] == 0u) {
+        level++;
+    }
+    vec3 color = textureLod(virtualTexture, fragCoords, float(level)).rgb;
+    outColor = vec4(applyFog(color, fragWorldPosition), 1.0);
+}
@@ -0,0 +1,25 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+// Ground plane covered by the virtual texture, generated from the vertex index.
+const float PLANE_SIZE = 12.0;
+const float PLANE_HEIGHT = -1.2;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0),
+    vec2(0.0, 1.0), vec2(1.0, 0.0), vec2(1.0, 1.0)
+);
+
+layout(location = 0) out vec2 fragCoords;
+layout(location = 1) out vec3 fragWorldPosition;
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec3 position = vec3((corner.x - 0.5) * PLANE_SIZE, PLANE_HEIGHT, (corner.y - 0.5) * PLANE_SIZE);
+    gl_Position = ubo.proj * ubo.view * vec4(position, 1.0);
+    fragCoords = corner;
+    fragWorldPosition = position;
+}
@@ -0,0 +1,47 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame.glsl"
+
+// World size covered by one repetition of the normal map.
+const float NORMAL_MAP_TILE_SIZE = 2.0;
+// Offset of the reflection lookup per unit of normal perturbation.
+const float DISTORTION = 0.03;
+// Reflectance of water at normal incidence.
+const float F0 = 0.02;
+const vec3 WATER_COLOR = vec3(0.02, 0.12, 0.18);
+const vec3 LIGHT_DIRECTION = normalize(vec3(1.0, 2.0, 1.0));
+
+layout(set = 1, binding = 0) uniform sampler2D reflectionMap;
+layout(set = 1, binding = 1) uniform sampler2D normalMap;
+
+layout(location = 0) in vec3 fragPosition;
+layout(location = 1) in vec4 fragClipPosition;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // Two layers of the normal map scrolling in different directions.
+    vec2 coords = fragPosition.xz / NORMAL_MAP_TILE_SIZE;
+    vec3 normal0 = texture(normalMap, coords + ubo.time * vec2(0.02, 0.01)).rgb * 2.0 - 1.0;
+    vec3 normal1 = texture(normalMap, coords * 1.7 - ubo.time * vec2(0.015, 0.025)).rgb * 2.0 - 1.0;
+    // The normal map is in tangent space with z up.
+    vec3 tangentNormal = normalize(normal0 + normal1);
+    vec3 normal = normalize(vec3(tangentNormal.x, tangentNormal.z, tangentNormal.y));
+
+    // The reflection was rendered with the same projection so it is sampled at the
+    // screen position of the fragment.
+    vec2 screenCoords = fragClipPosition.xy / fragClipPosition.w * 0.5 + 0.5;
+    vec3 reflection = texture(reflectionMap, screenCoords + normal.xz * DISTORTION).rgb;
+
+    vec3 viewDirection = normalize(ubo.cameraPosition.xyz - fragPosition);
+    float cosTheta = max(dot(viewDirection, normal), 0.0);
+    float fresnel = F0 + (1.0 - F0) * pow(1.0 - cosTheta, 5.0);
+
+    vec3 halfway = normalize(viewDirection + LIGHT_DIRECTION);
+    float specular = pow(max(dot(normal, halfway), 0.0), 64.0);
+
+    vec3 color = mix(WATER_COLOR, reflection, fresnel) + specular * 0.5;
+    outColor = vec4(applyFog(color, fragPosition), 1.0);
+}
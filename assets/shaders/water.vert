@@ -0,0 +1,26 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+// Must match WATER_LEVEL.
+const float WATER_LEVEL = -0.05;
+const float WATER_SIZE = 8.0;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+#include "frame.glsl"
+
+layout(location = 0) out vec3 fragPosition;
+layout(location = 1) out vec4 fragClipPosition;
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex] * WATER_SIZE * 0.5;
+    vec3 position = vec3(corner.x, WATER_LEVEL, corner.y);
+
+    gl_Position = ubo.proj * ubo.view * vec4(position, 1.0);
+    fragPosition = position;
+    fragClipPosition = gl_Position;
+}
@@ -29,19 +29,26 @@ fn compile_shaders() {
         .map(Result::unwrap)
         .filter(|dir| dir.file_type().unwrap().is_file())
         .filter(|dir| dir.path().extension() != Some(OsStr::new("spv")))
+        // Included files are compiled as part of the shaders including them
+        .filter(|dir| dir.path().extension() != Some(OsStr::new("glsl")))
         .for_each(|dir| {
             let path = dir.path();
             let name = path.file_name().unwrap().to_str().unwrap();
             let output_name = format!("{}.spv", &name);
             println!("Found file {:?}.\nCompiling...", path.as_os_str());
 
-            let result = Command::new("glslangValidator")
-                .current_dir(&shader_dir_path)
-                .arg("-V")
-                .arg(&path)
-                .arg("-o")
-                .arg(output_name)
-                .output();
+            let mut command = Command::new("glslangValidator");
+            command.current_dir(&shader_dir_path).arg("-V");
+            let source = fs::read_to_string(&path).unwrap();
+            // Ray tracing and EXT mesh shaders need SPIR-V 1.4, the ray
+            // queries are only used by shader variants
+            if source.contains("GL_EXT_ray_tracing") || source.contains("GL_EXT_mesh_shader") {
+                command.arg("--target-env").arg("spirv1.4");
+            // Subgroup operations need SPIR-V 1.3
+            } else if source.contains("GL_KHR_shader_subgroup") {
+                command.arg("--target-env").arg("vulkan1.1");
+            }
+            let result = command.arg(&path).arg("-o").arg(output_name).output();
 
             handle_program_result(result);
         })
@@ -0,0 +1,147 @@
+use crate::sky::{SkyParameters, SkyUniforms};
+use cgmath::Vector3;
+use std::f32::consts::PI;
+
+/// Number of coefficients of the spherical harmonics up to the second order.
+pub const SH_COEFFICIENT_COUNT: usize = 9;
+
+/// Number of rows of the latitude-longitude grid the environment is sampled on.
+/// It has twice as many columns.
+const PROJECTION_RESOLUTION: usize = 32;
+
+/// Fraction of the light of the horizon reflected by the ground below it.
+const GROUND_REFLECTANCE: f32 = 0.3;
+
+/// Convolution of each band with the clamped cosine lobe, divided by pi.
+const COSINE_LOBE: [f32; 3] = [1.0, 2.0 / 3.0, 0.25];
+
+/// Coefficients of the spherical harmonics of a color, one RGB color per basis function.
+pub type ShCoefficients = [[f32; 3]; SH_COEFFICIENT_COUNT];
+
+/// Evaluate the real spherical harmonics basis functions up to the second order
+/// in the unit `direction`.
+///
+/// The order matches `evaluateAmbient` in `ambient.glsl`.
+pub fn sh_basis(direction: Vector3<f32>) -> [f32; SH_COEFFICIENT_COUNT] {
+    let Vector3 { x, y, z } = direction;
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3.0 * z * z - 1.0),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+/// Project the radiance of an environment into spherical harmonics.
+///
+/// `radiance` gives the linear RGB light coming from a unit direction. It is
+/// sampled at the center of the cells of a latitude-longitude grid around the
+/// y axis, each sample weighted by the solid angle of its cell.
+pub fn project_radiance<F>(radiance: F) -> ShCoefficients
+where
+    F: Fn(Vector3<f32>) -> [f32; 3],
+{
+    let rows = PROJECTION_RESOLUTION;
+    let columns = 2 * PROJECTION_RESOLUTION;
+    let (d_theta, d_phi) = (PI / rows as f32, 2.0 * PI / columns as f32);
+
+    let mut coefficients = [[0.0; 3]; SH_COEFFICIENT_COUNT];
+    for row in 0..rows {
+        let theta = (row as f32 + 0.5) * d_theta;
+        let solid_angle = theta.sin() * d_theta * d_phi;
+        for column in 0..columns {
+            let phi = (column as f32 + 0.5) * d_phi;
+            let direction = Vector3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            let color = radiance(direction);
+            for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction).iter()) {
+                for (channel, value) in coefficient.iter_mut().zip(color.iter()) {
+                    *channel += value * basis * solid_angle;
+                }
+            }
+        }
+    }
+    coefficients
+}
+
+/// Get the irradiance divided by pi of the environment projected into `radiance`,
+/// as laid out in the frame uniform buffer.
+///
+/// Evaluating the result in a normal gives the factor by which the ambient
+/// light multiplies the color of a diffuse surface.
+pub fn irradiance_uniform(radiance: &ShCoefficients) -> [[f32; 4]; SH_COEFFICIENT_COUNT] {
+    let mut uniform = [[0.0; 4]; SH_COEFFICIENT_COUNT];
+    for (i, (coefficient, [r, g, b])) in uniform.iter_mut().zip(radiance.iter()).enumerate() {
+        let band = match i {
+            0 => 0,
+            1..=3 => 1,
+            _ => 2,
+        };
+        let lobe = COSINE_LOBE[band];
+        *coefficient = [r * lobe, g * lobe, b * lobe, 0.0];
+    }
+    uniform
+}
+
+/// Ambient light of the sky projected into spherical harmonics.
+///
+/// The Preetham sky is projected on the cpu, with the ground below the
+/// horizon reflecting part of its light, and the result is evaluated per pixel
+/// by the `SH_AMBIENT` variant of the material. The sky is projected again only
+/// when the sun moves.
+pub struct ShAmbient {
+    parameters: SkyParameters,
+    sun_direction: Option<Vector3<f32>>,
+    irradiance: [[f32; 4]; SH_COEFFICIENT_COUNT],
+}
+
+impl ShAmbient {
+    pub fn new(parameters: SkyParameters) -> Self {
+        ShAmbient {
+            parameters,
+            sun_direction: None,
+            irradiance: [[0.0; 4]; SH_COEFFICIENT_COUNT],
+        }
+    }
+}
+
+impl ShAmbient {
+    /// Get the irradiance coefficients to write in the frame uniform buffer.
+    pub fn irradiance(&self) -> [[f32; 4]; SH_COEFFICIENT_COUNT] {
+        self.irradiance
+    }
+
+    /// Project the sky again if the sun moved since the last update.
+    ///
+    /// `time` is the time in seconds animating the scene.
+    pub fn update(&mut self, time: f32) {
+        let sun_direction = self.parameters.sun_direction(time);
+        if self.sun_direction == Some(sun_direction) {
+            return;
+        }
+        self.sun_direction = Some(sun_direction);
+
+        let sky = SkyUniforms::new(sun_direction, self.parameters.turbidity);
+        let radiance = project_radiance(|direction| {
+            let [r, g, b] = sky.radiance(direction);
+            if direction.y < 0.0 {
+                [
+                    r * GROUND_REFLECTANCE,
+                    g * GROUND_REFLECTANCE,
+                    b * GROUND_REFLECTANCE,
+                ]
+            } else {
+                [r, g, b]
+            }
+        });
+        self.irradiance = irradiance_uniform(&radiance);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{
+    buffer::Buffer, command_encoder::CommandEncoder, frame_layout::PASS_SET, gpu_device::Device,
+    lifetime, std140::std140_struct,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::mem::size_of;
+
+/// What is drawn behind the scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundMode {
+    /// The clear color.
+    Color,
+    /// Vertical gradient between two colors, following the view direction.
+    Gradient,
+    /// Procedural sky of `Sky`.
+    Sky,
+    /// Infinite grid on the ground plane over the clear color.
+    Grid,
+}
+
+impl BackgroundMode {
+    /// Get the mode from its name, as used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "color" => Some(BackgroundMode::Color),
+            "gradient" => Some(BackgroundMode::Gradient),
+            "sky" | "skybox" => Some(BackgroundMode::Sky),
+            "grid" => Some(BackgroundMode::Grid),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters of the background.
+#[derive(Clone, Copy, Debug)]
+pub struct BackgroundParameters {
+    pub mode: BackgroundMode,
+    /// Linear color the image is cleared with.
+    pub clear_color: [f32; 3],
+    /// Color of the gradient when looking straight down.
+    pub bottom_color: [f32; 3],
+    /// Color of the gradient when looking straight up.
+    pub top_color: [f32; 3],
+    pub grid_color: [f32; 3],
+    /// Distance between two lines of the grid.
+    pub grid_spacing: f32,
+    /// Distance from the camera at which the grid fully fades out.
+    pub grid_fade_distance: f32,
+}
+
+impl Default for BackgroundParameters {
+    fn default() -> Self {
+        BackgroundParameters {
+            mode: BackgroundMode::Color,
+            clear_color: [0.0, 0.0, 0.0],
+            bottom_color: [0.05, 0.05, 0.06],
+            top_color: [0.35, 0.4, 0.5],
+            grid_color: [0.5, 0.5, 0.5],
+            grid_spacing: 0.5,
+            grid_fade_distance: 30.0,
+        }
+    }
+}
+
+std140_struct! {
+    /// Uniforms of the background shaders as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct BackgroundUniforms {
+        pub bottom_color: [f32; 4],
+        pub top_color: [f32; 4],
+        pub grid_color: [f32; 4],
+        pub grid_spacing: f32,
+        pub grid_fade_distance: f32,
+    }
+}
+
+impl BackgroundUniforms {
+    pub fn new(parameters: &BackgroundParameters) -> Self {
+        let extend = |[r, g, b]: [f32; 3]| [r, g, b, 1.0];
+        BackgroundUniforms {
+            bottom_color: extend(parameters.bottom_color),
+            top_color: extend(parameters.top_color),
+            grid_color: extend(parameters.grid_color),
+            grid_spacing: parameters.grid_spacing,
+            grid_fade_distance: parameters.grid_fade_distance,
+        }
+    }
+}
+
+/// Gradient or grid drawn with a full screen triangle.
+///
+/// The gradient is drawn first without depth test, like the sky. The grid is
+/// drawn after the opaque geometry: it intersects the view rays with the
+/// ground plane, writes the depth of the intersection so the scene hides it
+/// and is blended over the clear color. The X axis is highlighted in red and
+/// the Z axis in blue.
+///
+/// The parameters do not change, so the uniform buffer is written once and
+/// shared by the swapchain images.
+pub struct Background {
+    parameters: BackgroundParameters,
+    uniform_buffer: Buffer,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Background {
+    /// Create the descriptor set of the background and write its uniforms.
+    ///
+    /// `uniform_buffer` must be a host visible and coherent buffer of at
+    /// least the size of `BackgroundUniforms`. The mode of `parameters` must
+    /// be `Gradient` or `Grid`.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(device: &Device, parameters: BackgroundParameters, uniform_buffer: Buffer) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        uniform_buffer.write(device, 0, &[BackgroundUniforms::new(&parameters)]);
+
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer)
+            .offset(0)
+            .range(size_of::<BackgroundUniforms>() as _)
+            .build();
+        let buffer_infos = [buffer_info];
+        let ubo_write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&buffer_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[ubo_write], &[]) };
+
+        Background {
+            parameters,
+            uniform_buffer,
+            layout,
+            pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [ubo_binding];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: 1,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Background {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn mode(&self) -> BackgroundMode {
+        self.parameters.mode
+    }
+
+    /// Set the pipeline used to draw the background.
+    ///
+    /// The background takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the draw of the background with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`. The gradient
+    /// must be recorded before any other draw of the render pass and the grid
+    /// after the opaque geometry.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_descriptor_sets(self.pipeline_layout, PASS_SET, &[self.set], &[]);
+        unsafe {
+            encoder
+                .device()
+                .cmd_draw(encoder.command_buffer(), 3, 1, 0, 0)
+        };
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.uniform_buffer.destroy(device);
+    }
+}
@@ -0,0 +1,402 @@
+use crate::{gpu_device::Device, sync2};
+use ash::{version::DeviceV1_0, vk};
+
+/// Stages of the pipeline and the accesses they make to a resource, on one
+/// side of a barrier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+impl Access {
+    pub fn new(stage: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        Access { stage, access }
+    }
+
+    /// No previous access, for resources whose content is not needed.
+    pub fn none() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+        )
+    }
+
+    pub fn transfer_read() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ,
+        )
+    }
+
+    pub fn transfer_write() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        )
+    }
+
+    pub fn compute_read() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        )
+    }
+
+    pub fn compute_write() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        )
+    }
+
+    /// Reads of fragment shaders, such as sampling a texture.
+    pub fn fragment_read() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        )
+    }
+
+    pub fn color_attachment() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        )
+    }
+
+    pub fn indirect_read() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::DRAW_INDIRECT,
+            vk::AccessFlags::INDIRECT_COMMAND_READ,
+        )
+    }
+
+    pub fn host_read() -> Self {
+        Self::new(vk::PipelineStageFlags::HOST, vk::AccessFlags::HOST_READ)
+    }
+
+    /// Combine the stages and accesses of `self` and `other`.
+    pub fn and(self, other: Access) -> Self {
+        Self::new(self.stage | other.stage, self.access | other.access)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BarrierKind {
+    Memory,
+    Buffer {
+        buffer: vk::Buffer,
+        queue_families: (u32, u32),
+    },
+    Image {
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        layouts: (vk::ImageLayout, vk::ImageLayout),
+        queue_families: (u32, u32),
+    },
+}
+
+/// Dependency of the accesses `dst` on the accesses `src`, to the whole
+/// memory, a buffer or an image, recorded in a batch with `cmd_barriers`.
+///
+/// Only the writes of `src` are made available, reads only need an execution
+/// dependency.
+#[derive(Clone, Copy, Debug)]
+pub struct Barrier {
+    src: Access,
+    dst: Access,
+    kind: BarrierKind,
+}
+
+impl Barrier {
+    /// Global barrier, for all the resources accessed by `src` and `dst`.
+    pub fn memory(src: Access, dst: Access) -> Self {
+        Barrier {
+            src,
+            dst,
+            kind: BarrierKind::Memory,
+        }
+    }
+
+    /// Barrier to the whole of `buffer`.
+    pub fn buffer(buffer: vk::Buffer, src: Access, dst: Access) -> Self {
+        Barrier {
+            src,
+            dst,
+            kind: BarrierKind::Buffer {
+                buffer,
+                queue_families: (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+            },
+        }
+    }
+
+    /// Barrier making `write` wait for the reads of `buffer` at `read_stage`.
+    ///
+    /// A write after a read only needs an execution dependency, so no access
+    /// is made available.
+    pub fn buffer_read_to_write(
+        buffer: vk::Buffer,
+        read_stage: vk::PipelineStageFlags,
+        write: Access,
+    ) -> Self {
+        Self::buffer(
+            buffer,
+            Access::new(read_stage, vk::AccessFlags::empty()),
+            write,
+        )
+    }
+
+    /// Barrier to `range` of `image`, transitioning it from `old_layout` to `new_layout`.
+    pub fn image(
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+        src: Access,
+        dst: Access,
+    ) -> Self {
+        Barrier {
+            src,
+            dst,
+            kind: BarrierKind::Image {
+                image,
+                range,
+                layouts: (old_layout, new_layout),
+                queue_families: (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+            },
+        }
+    }
+
+    /// Barrier making `range` of `image`, sampled by fragment and compute
+    /// shaders, a color attachment.
+    pub fn image_sampled_to_attachment(image: vk::Image, range: vk::ImageSubresourceRange) -> Self {
+        Self::image(
+            image,
+            range,
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            Access::new(
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            Access::color_attachment(),
+        )
+    }
+
+    /// Barrier making `range` of `image`, written as a color attachment,
+    /// sampled by fragment shaders.
+    pub fn image_attachment_to_sampled(image: vk::Image, range: vk::ImageSubresourceRange) -> Self {
+        Self::image(
+            image,
+            range,
+            (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Access::color_attachment(),
+            Access::fragment_read(),
+        )
+    }
+
+    /// First half of the transfer of the ownership of a buffer or an image
+    /// from the queue family `src_family` to `dst_family`, recorded on a
+    /// queue of `src_family` after the accesses `src`.
+    ///
+    /// The transfer completes once `acquire` is recorded with the same
+    /// barrier on a queue of `dst_family`, waiting for a semaphore signaled
+    /// after the release. Images keep the layouts of the barrier.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the barrier is a memory barrier, which has no owner.
+    pub fn release(self, (src_family, dst_family): (u32, u32)) -> Self {
+        Barrier {
+            src: self.src,
+            dst: Access::new(
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::AccessFlags::empty(),
+            ),
+            kind: self.with_queue_families(src_family, dst_family),
+        }
+    }
+
+    /// Second half of the transfer of the ownership from the queue family
+    /// `src_family` to `dst_family`, recorded on a queue of `dst_family`
+    /// before the accesses `dst`. See `release`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the barrier is a memory barrier, which has no owner.
+    pub fn acquire(self, (src_family, dst_family): (u32, u32)) -> Self {
+        Barrier {
+            src: Access::none(),
+            dst: self.dst,
+            kind: self.with_queue_families(src_family, dst_family),
+        }
+    }
+
+    /// Get the accesses on both sides, keeping only the writes of `src`.
+    pub fn masks(&self) -> (Access, Access) {
+        let src = Access::new(self.src.stage, self.src.access & write_accesses());
+        (src, self.dst)
+    }
+
+    /// Get the Vulkan barrier of a buffer barrier, or `None` for other barriers.
+    pub fn buffer_barrier(&self) -> Option<vk::BufferMemoryBarrier> {
+        let (src, dst) = self.masks();
+        match self.kind {
+            BarrierKind::Buffer {
+                buffer,
+                queue_families: (src_family, dst_family),
+            } => Some(
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(src.access)
+                    .dst_access_mask(dst.access)
+                    .src_queue_family_index(src_family)
+                    .dst_queue_family_index(dst_family)
+                    .buffer(buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Get the Vulkan barrier of an image barrier, or `None` for other barriers.
+    pub fn image_barrier(&self) -> Option<vk::ImageMemoryBarrier> {
+        let (src, dst) = self.masks();
+        match self.kind {
+            BarrierKind::Image {
+                image,
+                range,
+                layouts: (old_layout, new_layout),
+                queue_families: (src_family, dst_family),
+            } => Some(
+                vk::ImageMemoryBarrier::builder()
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_queue_family_index(src_family)
+                    .dst_queue_family_index(dst_family)
+                    .image(image)
+                    .subresource_range(range)
+                    .src_access_mask(src.access)
+                    .dst_access_mask(dst.access)
+                    .build(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn with_queue_families(self, src_family: u32, dst_family: u32) -> BarrierKind {
+        match self.kind {
+            BarrierKind::Memory => panic!("Memory barriers cannot transfer ownership."),
+            BarrierKind::Buffer { buffer, .. } => BarrierKind::Buffer {
+                buffer,
+                queue_families: (src_family, dst_family),
+            },
+            BarrierKind::Image {
+                image,
+                range,
+                layouts,
+                ..
+            } => BarrierKind::Image {
+                image,
+                range,
+                layouts,
+                queue_families: (src_family, dst_family),
+            },
+        }
+    }
+}
+
+/// Record `barriers` with a single pipeline barrier.
+///
+/// With synchronization2, each barrier keeps its own stages. Otherwise,
+/// since Vulkan 1.0 pipeline barriers only have one source and one
+/// destination stage mask, the stages of all the barriers are combined.
+/// Nothing is recorded when `barriers` is empty.
+pub fn cmd_barriers(device: &Device, command_buffer: vk::CommandBuffer, barriers: &[Barrier]) {
+    if barriers.is_empty() {
+        return;
+    }
+
+    let mut src_stage = vk::PipelineStageFlags::empty();
+    let mut dst_stage = vk::PipelineStageFlags::empty();
+    let mut memory_barriers = Vec::new();
+    let mut buffer_barriers = Vec::new();
+    let mut image_barriers = Vec::new();
+    for barrier in barriers {
+        let (src, dst) = barrier.masks();
+        src_stage |= src.stage;
+        dst_stage |= dst.stage;
+        let masks = ((src.stage, src.access), (dst.stage, dst.access));
+        if let Some(buffer_barrier) = barrier.buffer_barrier() {
+            buffer_barriers.push((masks, buffer_barrier));
+        } else if let Some(image_barrier) = barrier.image_barrier() {
+            image_barriers.push((masks, image_barrier));
+        } else {
+            memory_barriers.push(masks);
+        }
+    }
+
+    if sync2::cmd_pipeline_barrier2(
+        command_buffer,
+        &memory_barriers,
+        &buffer_barriers,
+        &image_barriers,
+    ) {
+        return;
+    }
+
+    // Stage masks cannot be empty
+    if src_stage.is_empty() {
+        src_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
+    }
+    if dst_stage.is_empty() {
+        dst_stage = vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+    }
+    let memory_barriers = memory_barriers
+        .iter()
+        .map(|((_, src_access), (_, dst_access))| {
+            vk::MemoryBarrier::builder()
+                .src_access_mask(*src_access)
+                .dst_access_mask(*dst_access)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let buffer_barriers = buffer_barriers
+        .iter()
+        .map(|(_, barrier)| *barrier)
+        .collect::<Vec<_>>();
+    let image_barriers = image_barriers
+        .iter()
+        .map(|(_, barrier)| *barrier)
+        .collect::<Vec<_>>();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &memory_barriers,
+            &buffer_barriers,
+            &image_barriers,
+        )
+    };
+}
+
+/// Get the accesses that write to memory.
+///
+/// Only writes must be made available before another access, reads only
+/// need an execution dependency.
+pub fn write_accesses() -> vk::AccessFlags {
+    vk::AccessFlags::SHADER_WRITE
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+        | vk::AccessFlags::TRANSFER_WRITE
+        | vk::AccessFlags::HOST_WRITE
+        | vk::AccessFlags::MEMORY_WRITE
+}
@@ -0,0 +1,350 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    frame_layout::PASS_SET,
+    gpu_device::Device,
+    lifetime,
+    material::{MaterialInstance, MaterialInstances},
+    objects::{object_stride, ObjectUniforms},
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix};
+use std::mem::size_of;
+
+/// Vertex which can be moved to world space when it is batched.
+pub trait BatchVertex: Copy {
+    /// Get the vertex transformed by `model`, with its normal transformed by `normal_matrix`.
+    fn transformed(&self, model: &Matrix4<f32>, normal_matrix: &Matrix3<f32>) -> Self;
+}
+
+/// Vertices and indices of a mesh shared by static objects.
+pub struct StaticMesh<V> {
+    pub vertices: Vec<V>,
+    pub indices: Vec<u32>,
+}
+
+/// Object which never moves, drawn with a mesh of the batcher.
+#[derive(Clone, Copy, Debug)]
+pub struct StaticObject {
+    /// Index of the mesh of the object.
+    pub mesh: usize,
+    pub material: MaterialInstance,
+    pub model: Matrix4<f32>,
+}
+
+/// Indexed draw of static geometry.
+#[derive(Clone, Copy, Debug)]
+pub struct StaticDraw {
+    pub model: Matrix4<f32>,
+    pub material: MaterialInstance,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+}
+
+/// Geometry of the static objects and the draws to record.
+pub struct StaticGeometry<V> {
+    pub vertices: Vec<V>,
+    pub indices: Vec<u32>,
+    pub draws: Vec<StaticDraw>,
+}
+
+/// Merge the `objects` sharing a material in a single draw.
+///
+/// The vertices of each object are copied and transformed to world space, so
+/// the draws of a material are merged in one draw with an identity model
+/// matrix. The geometry takes as much memory as all the objects together
+/// rather than their meshes, in exchange for one draw per material.
+pub fn batch<V: BatchVertex>(
+    meshes: &[StaticMesh<V>],
+    objects: &[StaticObject],
+) -> StaticGeometry<V> {
+    let mut objects = objects.to_vec();
+    objects.sort_by_key(|object| object.material.index());
+
+    let mut geometry = StaticGeometry {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        draws: Vec::new(),
+    };
+    for object in &objects {
+        let mesh = &meshes[object.mesh];
+        let first_vertex = geometry.vertices.len() as u32;
+        let normal_matrix = normal_matrix(&object.model);
+        geometry.vertices.extend(
+            mesh.vertices
+                .iter()
+                .map(|vertex| vertex.transformed(&object.model, &normal_matrix)),
+        );
+
+        let first_index = geometry.indices.len() as u32;
+        geometry
+            .indices
+            .extend(mesh.indices.iter().map(|index| first_vertex + index));
+        let index_count = mesh.indices.len() as u32;
+
+        match geometry.draws.last_mut() {
+            Some(draw) if draw.material == object.material => draw.index_count += index_count,
+            _ => geometry.draws.push(StaticDraw {
+                model: Matrix4::identity(),
+                material: object.material,
+                first_index,
+                index_count,
+                vertex_offset: 0,
+            }),
+        }
+    }
+    geometry
+}
+
+/// Draw each of the `objects` with its own draw of its mesh and its model matrix.
+///
+/// The meshes are stored once, which is the behavior without batching.
+pub fn unbatched<V: Copy>(meshes: &[StaticMesh<V>], objects: &[StaticObject]) -> StaticGeometry<V> {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let ranges = meshes
+        .iter()
+        .map(|mesh| {
+            let range = (indices.len() as u32, vertices.len() as i32);
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+            range
+        })
+        .collect::<Vec<_>>();
+
+    let draws = objects
+        .iter()
+        .map(|object| {
+            let (first_index, vertex_offset) = ranges[object.mesh];
+            StaticDraw {
+                model: object.model,
+                material: object.material,
+                first_index,
+                index_count: meshes[object.mesh].indices.len() as _,
+                vertex_offset,
+            }
+        })
+        .collect();
+
+    StaticGeometry {
+        vertices,
+        indices,
+        draws,
+    }
+}
+
+/// Get the matrix transforming the normals of a mesh transformed by `model`.
+fn normal_matrix(model: &Matrix4<f32>) -> Matrix3<f32> {
+    let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+    linear
+        .invert()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or(linear)
+}
+
+/// Static objects drawn from a vertex and an index buffer uploaded once.
+///
+/// The objects are drawn with the object shaders, with the model matrix and
+/// the material parameters of each draw in a uniform buffer written once and
+/// bound at its dynamic offset. Batched or not, the draws are recorded once
+/// with the command buffers.
+pub struct StaticBatches {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    uniform_buffer: Buffer,
+    draws: Vec<StaticDraw>,
+    stride: vk::DeviceSize,
+    layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl StaticBatches {
+    /// Create the descriptor set of the draws and write their uniforms.
+    ///
+    /// `vertex_buffer` and `index_buffer` must hold the geometry of `draws`.
+    /// `uniform_buffer` must be a host visible and coherent buffer of
+    /// `StaticBatches::uniform_buffer_size` bytes for the number of draws.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(
+        device: &Device,
+        (vertex_buffer, index_buffer): (Buffer, Buffer),
+        uniform_buffer: Buffer,
+        draws: Vec<StaticDraw>,
+        (material_instances, min_offset_alignment): (&MaterialInstances, vk::DeviceSize),
+    ) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let descriptor_pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let stride = object_stride(min_offset_alignment);
+        let size = Self::uniform_buffer_size(draws.len(), min_offset_alignment);
+        unsafe {
+            let data_ptr = device
+                .map_memory(uniform_buffer.memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap() as *mut u8;
+            for (index, draw) in draws.iter().enumerate() {
+                let parameters = material_instances.parameters(draw.material);
+                let uniforms = ObjectUniforms {
+                    model: draw.model,
+                    tint: parameters.tint,
+                    emissive_strength: parameters.emissive_strength,
+                };
+                let draw_ptr = data_ptr.add(index * stride as usize);
+                (draw_ptr as *mut ObjectUniforms).write_unaligned(uniforms);
+            }
+            device.unmap_memory(uniform_buffer.memory);
+        }
+
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer)
+            .offset(0)
+            .range(size_of::<ObjectUniforms>() as _)
+            .build();
+        let buffer_infos = [buffer_info];
+        let ubo_write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .buffer_info(&buffer_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[ubo_write], &[]) };
+
+        StaticBatches {
+            vertex_buffer,
+            index_buffer,
+            uniform_buffer,
+            draws,
+            stride,
+            layout,
+            descriptor_pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [ubo_binding];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    /// Get the size of the uniform buffer of `draw_count` draws.
+    ///
+    /// `min_offset_alignment` is the `minUniformBufferOffsetAlignment` limit of the device.
+    pub fn uniform_buffer_size(
+        draw_count: usize,
+        min_offset_alignment: vk::DeviceSize,
+    ) -> vk::DeviceSize {
+        object_stride(min_offset_alignment) * draw_count.max(1) as vk::DeviceSize
+    }
+}
+
+impl StaticBatches {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn draw_count(&self) -> usize {
+        self.draws.len()
+    }
+
+    /// Set the pipeline used to draw the static objects.
+    ///
+    /// The batches take ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the draws with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[self.vertex_buffer.buffer], &[0]);
+        encoder.bind_index_buffer(self.index_buffer.buffer, 0, vk::IndexType::UINT32);
+        for (index, draw) in self.draws.iter().enumerate() {
+            encoder.bind_descriptor_sets(
+                self.pipeline_layout,
+                PASS_SET,
+                &[self.set],
+                &[(self.stride * index as vk::DeviceSize) as _],
+            );
+            unsafe {
+                encoder.device().cmd_draw_indexed(
+                    encoder.command_buffer(),
+                    draw.index_count,
+                    1,
+                    draw.first_index,
+                    draw.vertex_offset,
+                    0,
+                )
+            };
+        }
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.descriptor_pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.vertex_buffer.destroy(device);
+        self.index_buffer.destroy(device);
+        self.uniform_buffer.destroy(device);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{camera::Camera, gpu_timer::GpuFrameStats};
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Number of frames rendered before the measures start, so the caches and
+/// the pipelines are warm.
+const WARMUP_FRAMES: u32 = 60;
+
+/// Fixed number of frames rendered along a scripted camera path, with the
+/// times of each of them written to reports.
+///
+/// The camera only depends on the index of the frame and the frames are
+/// deterministic while benchmarking, so every run renders the same images
+/// and the reports can be compared across commits and GPUs.
+pub struct Benchmark {
+    frame_count: u32,
+    output: PathBuf,
+    /// Number of frames rendered, including the warmup.
+    frame: u32,
+    samples: Vec<FrameSample>,
+    /// Sample of the last frame rendered to each swapchain image, whose GPU
+    /// results are not read yet.
+    pending: Vec<Option<usize>>,
+}
+
+/// Measures of a frame.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameSample {
+    /// Time since the previous frame on the CPU, in milliseconds.
+    pub cpu_milliseconds: f32,
+    pub gpu_milliseconds: Option<f32>,
+    pub draws: usize,
+    pub primitives: Option<u64>,
+}
+
+/// Minimum, average and 99th percentile of a measure.
+#[derive(Clone, Copy, Debug)]
+struct Summary {
+    min: f64,
+    avg: f64,
+    p99: f64,
+}
+
+impl Summary {
+    fn new(values: impl Iterator<Item = f64>) -> Option<Self> {
+        let mut values = values.collect::<Vec<_>>();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let p99_index = ((values.len() as f64 * 0.99).ceil() as usize).max(1) - 1;
+        Some(Summary {
+            min: values[0],
+            avg: values.iter().sum::<f64>() / values.len() as f64,
+            p99: values[p99_index],
+        })
+    }
+
+    fn to_json(self) -> String {
+        format!(
+            "{{ \"min\": {:.4}, \"avg\": {:.4}, \"p99\": {:.4} }}",
+            self.min, self.avg, self.p99
+        )
+    }
+}
+
+impl Benchmark {
+    /// Create a benchmark measuring `frame_count` frames rendered to
+    /// `image_count` swapchain images.
+    ///
+    /// The reports are written to `output` with the `csv` and `json` extensions.
+    pub fn new(frame_count: u32, output: PathBuf, image_count: usize) -> Self {
+        Benchmark {
+            frame_count,
+            output,
+            frame: 0,
+            samples: Vec::with_capacity(frame_count as _),
+            pending: vec![None; image_count],
+        }
+    }
+
+    /// Check if all the frames were measured.
+    pub fn is_done(&self) -> bool {
+        self.frame >= WARMUP_FRAMES + self.frame_count
+    }
+
+    /// Get the progress of the measured frames along the path, from 0 to 1.
+    fn progress(&self) -> f32 {
+        self.frame.saturating_sub(WARMUP_FRAMES) as f32 / self.frame_count.max(1) as f32
+    }
+
+    /// Get the camera of the next frame.
+    ///
+    /// The camera orbits once around the model during the measured frames,
+    /// moving up and down and closer and further twice.
+    pub fn camera(&self) -> Camera {
+        let angle = 2.0 * PI * self.progress();
+        Camera::new(
+            angle,
+            (60.0 + 20.0 * (2.0 * angle).sin()).to_radians(),
+            3.0 + 0.75 * (2.0 * angle).cos(),
+        )
+    }
+
+    /// Record the measures of the next frame, rendered to the swapchain image
+    /// `image_index`.
+    ///
+    /// `gpu` are the GPU results of the previous frame rendered to the image.
+    pub fn record(
+        &mut self,
+        image_index: usize,
+        cpu_milliseconds: f32,
+        draws: usize,
+        gpu: Option<GpuFrameStats>,
+    ) {
+        self.resolve(image_index, gpu);
+        if self.frame >= WARMUP_FRAMES && !self.is_done() {
+            self.pending[image_index] = Some(self.samples.len());
+            self.samples.push(FrameSample {
+                cpu_milliseconds,
+                gpu_milliseconds: None,
+                draws,
+                primitives: None,
+            });
+        }
+        self.frame += 1;
+    }
+
+    /// Set the GPU results of the last frame rendered to the swapchain image `image_index`.
+    pub fn resolve(&mut self, image_index: usize, gpu: Option<GpuFrameStats>) {
+        if image_index >= self.pending.len() {
+            self.pending.resize(image_index + 1, None);
+        }
+        if let (Some(sample), Some(gpu)) = (self.pending[image_index].take(), gpu) {
+            let sample = &mut self.samples[sample];
+            sample.gpu_milliseconds = Some(gpu.milliseconds);
+            sample.primitives = gpu.primitives;
+        }
+    }
+
+    /// Write the measures of each frame to the CSV report and their summary
+    /// to the JSON report, with the name of the device.
+    pub fn write_reports(&self, device_name: &str) -> io::Result<()> {
+        let csv_path = self.output.with_extension("csv");
+        self.write_csv(&csv_path)?;
+        let json_path = self.output.with_extension("json");
+        self.write_json(&json_path, device_name)?;
+        log::info!(
+            "Benchmark reports written to {} and {}.",
+            csv_path.display(),
+            json_path.display()
+        );
+        Ok(())
+    }
+
+    fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "frame,cpu_ms,gpu_ms,draws,primitives")?;
+        for (frame, sample) in self.samples.iter().enumerate() {
+            writeln!(
+                file,
+                "{},{:.4},{},{},{}",
+                frame,
+                sample.cpu_milliseconds,
+                sample
+                    .gpu_milliseconds
+                    .map_or(String::new(), |time| format!("{:.4}", time)),
+                sample.draws,
+                sample
+                    .primitives
+                    .map_or(String::new(), |primitives| primitives.to_string())
+            )?;
+        }
+        file.flush()
+    }
+
+    fn write_json(&self, path: &Path, device_name: &str) -> io::Result<()> {
+        let summary_json =
+            |summary: Option<Summary>| summary.map_or("null".into(), Summary::to_json);
+        let average_json = |values: Vec<f64>| {
+            if values.is_empty() {
+                "null".into()
+            } else {
+                format!("{:.1}", values.iter().sum::<f64>() / values.len() as f64)
+            }
+        };
+
+        let cpu = Summary::new(self.samples.iter().map(|s| s.cpu_milliseconds as f64));
+        let gpu = Summary::new(
+            self.samples
+                .iter()
+                .filter_map(|s| s.gpu_milliseconds.map(f64::from)),
+        );
+        let draws = self.samples.iter().map(|s| s.draws as f64).collect();
+        let primitives = self
+            .samples
+            .iter()
+            .filter_map(|s| s.primitives.map(|primitives| primitives as f64))
+            .collect();
+
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{{")?;
+        writeln!(file, "  \"device\": \"{}\",", escape_json(device_name))?;
+        writeln!(file, "  \"frames\": {},", self.samples.len())?;
+        writeln!(file, "  \"cpu_ms\": {},", summary_json(cpu))?;
+        writeln!(file, "  \"gpu_ms\": {},", summary_json(gpu))?;
+        writeln!(file, "  \"draws\": {},", average_json(draws))?;
+        writeln!(file, "  \"primitives\": {}", average_json(primitives))?;
+        writeln!(file, "}}")?;
+        file.flush()
+    }
+}
+
+/// Escape the quotes, backslashes and control characters of `text` for a JSON string.
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
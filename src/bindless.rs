@@ -0,0 +1,291 @@
+use crate::{buffer::Buffer, context::*, gpu_device::Device, lifetime, texture::Texture};
+use ash::{version::DeviceV1_0, vk, Instance};
+use std::{
+    ffi::{c_void, CStr},
+    mem::size_of,
+};
+
+/// Maximum number of textures that can be registered in the global texture array.
+pub const MAX_BINDLESS_TEXTURES: u32 = 1024;
+
+/// Maximum number of materials that can be stored in the material buffer.
+pub const MAX_MATERIALS: u32 = 256;
+
+const MATERIAL_BUFFER_BINDING: u32 = 0;
+const TEXTURES_BINDING: u32 = 1;
+
+/// Material as laid out in the material storage buffer.
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub struct MaterialData {
+    pub texture_index: u32,
+}
+
+impl MaterialData {
+    /// Size in bytes of the storage buffer holding all the materials.
+    pub fn buffer_size() -> vk::DeviceSize {
+        (size_of::<MaterialData>() as u32 * MAX_MATERIALS) as _
+    }
+}
+
+/// Get the device extensions required for the bindless texture array.
+pub fn required_device_extensions() -> [&'static CStr; 2] {
+    [
+        vk::ExtDescriptorIndexingFn::name(),
+        vk::KhrMaintenance3Fn::name(),
+    ]
+}
+
+/// Check if `device` supports the descriptor indexing features used by
+/// the bindless texture array.
+///
+/// Vulkan 1.1 must be supported by the instance and the device since the
+/// features are queried using `vkGetPhysicalDeviceFeatures2`.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let extensions_supported = required_device_extensions()
+        .iter()
+        .all(|ext| is_device_extension_supported(instance, device, ext));
+    if !extensions_supported {
+        return false;
+    }
+
+    let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut indexing_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { get_physical_device_features2(instance, device, &mut features) };
+
+    indexing_features.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+        && indexing_features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+        && indexing_features.descriptor_binding_partially_bound == vk::TRUE
+        && indexing_features.descriptor_binding_variable_descriptor_count == vk::TRUE
+        && indexing_features.runtime_descriptor_array == vk::TRUE
+}
+
+/// Build the descriptor indexing features to enable at device creation.
+pub fn required_features() -> vk::PhysicalDeviceDescriptorIndexingFeaturesEXT {
+    vk::PhysicalDeviceDescriptorIndexingFeaturesEXT::builder()
+        .shader_sampled_image_array_non_uniform_indexing(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_variable_descriptor_count(true)
+        .runtime_descriptor_array(true)
+        .build()
+}
+
+/// Global texture array indexed from the shaders.
+///
+/// All textures are registered in a single update-after-bind descriptor set
+/// alongside a storage buffer containing the materials. Shaders receive the
+/// index of the material to use and fetch the index of the texture from it,
+/// so we only need one descriptor set whatever the number of materials.
+pub struct BindlessTextures {
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    material_buffer: Buffer,
+    texture_count: u32,
+    material_count: u32,
+}
+
+impl BindlessTextures {
+    /// Create the descriptor set containing the texture array.
+    ///
+    /// `material_buffer` must be a host visible storage buffer of at least
+    /// `MaterialData::buffer_size()` bytes.
+    pub fn new(device: &Device, material_buffer: Buffer) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = Self::allocate_descriptor_set(device, pool, layout);
+
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(material_buffer.buffer)
+            .offset(0)
+            .range(MaterialData::buffer_size())
+            .build();
+        let buffer_infos = [buffer_info];
+        let material_buffer_write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(MATERIAL_BUFFER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[material_buffer_write], &[]) };
+
+        BindlessTextures {
+            layout,
+            pool,
+            set,
+            material_buffer,
+            texture_count: 0,
+            material_count: 0,
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let material_buffer_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(MATERIAL_BUFFER_BINDING)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let textures_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(TEXTURES_BINDING)
+            .descriptor_count(MAX_BINDLESS_TEXTURES)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [material_buffer_binding, textures_binding];
+
+        // The texture array must be the last binding since its size is variable.
+        let binding_flags = [
+            vk::DescriptorBindingFlagsEXT::empty(),
+            vk::DescriptorBindingFlagsEXT::UPDATE_AFTER_BIND
+                | vk::DescriptorBindingFlagsEXT::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlagsEXT::VARIABLE_DESCRIPTOR_COUNT,
+        ];
+        let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT::builder()
+            .binding_flags(&binding_flags)
+            .build();
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL_EXT)
+            .bindings(&bindings)
+            .push_next(&mut binding_flags_info)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let material_buffer_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+        };
+        let textures_pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: MAX_BINDLESS_TEXTURES,
+        };
+        let pool_sizes = [material_buffer_pool_size, textures_pool_size];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND_EXT)
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    fn allocate_descriptor_set(
+        device: &Device,
+        pool: vk::DescriptorPool,
+        layout: vk::DescriptorSetLayout,
+    ) -> vk::DescriptorSet {
+        let descriptor_counts = [MAX_BINDLESS_TEXTURES];
+        let mut variable_count_info =
+            vk::DescriptorSetVariableDescriptorCountAllocateInfoEXT::builder()
+                .descriptor_counts(&descriptor_counts)
+                .build();
+
+        let layouts = [layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts)
+            .push_next(&mut variable_count_info)
+            .build();
+
+        unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+    }
+}
+
+impl BindlessTextures {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn set(&self) -> vk::DescriptorSet {
+        self.set
+    }
+
+    /// Register `texture` in the texture array.
+    ///
+    /// # Returns
+    ///
+    /// The index of the texture in the array.
+    ///
+    /// # Panics
+    ///
+    /// Panic if `texture` has no sampler or if the array is full.
+    pub fn add_texture(&mut self, device: &Device, texture: Texture) -> u32 {
+        if self.texture_count == MAX_BINDLESS_TEXTURES {
+            panic!("Bindless texture array is full.");
+        }
+        let index = self.texture_count;
+        self.set_texture(device, index, texture);
+        self.texture_count += 1;
+        index
+    }
+
+    /// Write `texture` at `index` in the texture array.
+    ///
+    /// It replaces the texture added at `index` when it is reloaded, so the
+    /// set must not be in use.
+    pub fn set_texture(&self, device: &Device, index: u32, texture: Texture) {
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(
+                texture
+                    .sampler
+                    .expect("Bindless textures require a sampler"),
+            )
+            .build();
+        let image_infos = [image_info];
+
+        let texture_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(TEXTURES_BINDING)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[texture_write], &[]) };
+    }
+
+    /// Write `material` in the material buffer.
+    ///
+    /// # Returns
+    ///
+    /// The index of the material to pass to the shaders.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the material buffer is full.
+    pub fn add_material(&mut self, device: &Device, material: MaterialData) -> u32 {
+        if self.material_count == MAX_MATERIALS {
+            panic!("Material buffer is full.");
+        }
+        let index = self.material_count;
+
+        let offset = (size_of::<MaterialData>() * index as usize) as vk::DeviceSize;
+        self.material_buffer.write(device, offset, &[material]);
+
+        self.material_count += 1;
+        index
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            self.material_buffer.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    exposure::HDR_FORMAT,
+    gpu_device::Device,
+    lifetime,
+    texture::Texture,
+    tracked_image::{ImageState, TrackedImage},
+};
+use ash::{version::DeviceV1_0, vk};
+use std::mem::size_of;
+
+/// Format of the levels of the downsampled HDR image.
+pub const BLOOM_FORMAT: vk::Format = HDR_FORMAT;
+
+/// Maximum number of levels of the chain, two tiles of 6 levels.
+///
+/// Must match `MAX_LEVELS` in `downsample.glsl`.
+pub const MAX_BLOOM_LEVELS: u32 = 12;
+
+/// Width and height of the tile of the HDR image reduced by each work group.
+const TILE_SIZE: u32 = 64;
+
+const SOURCE_BINDING: u32 = 0;
+const LEVELS_BINDING: u32 = 1;
+const COUNTER_BINDING: u32 = 2;
+
+/// Get the size of the first level of the chain of an HDR image of `extent`.
+pub fn chain_extent(extent: vk::Extent2D) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width / 2).max(1),
+        height: (extent.height / 2).max(1),
+    }
+}
+
+/// Get the number of levels of the chain of an HDR image of `extent`, down
+/// to a single texel or to `MAX_BLOOM_LEVELS`.
+pub fn chain_level_count(extent: vk::Extent2D) -> u32 {
+    let extent = chain_extent(extent);
+    let size = extent.width.max(extent.height);
+    (32 - size.leading_zeros()).min(MAX_BLOOM_LEVELS)
+}
+
+/// Bloom blending the light of the bright parts of the HDR image around them.
+///
+/// After the exposure passes, a single compute dispatch downsamples the HDR
+/// image into a chain of half resolution levels, in place of a blit or a
+/// dispatch per level. Each work group reduces a tile of the HDR image to
+/// the first 6 levels, then the last group to finish, found with an atomic
+/// counter, reduces the sixth level to the next 6 levels. The invocations of
+/// a group hold the texels in Morton order, so the 2x2 blocks are averaged
+/// with the quad operations of subgroups where they are supported, and
+/// through shared memory otherwise.
+///
+/// The tonemapper then blends the average of the levels with the HDR image.
+/// HDR images wider or higher than 4096 texels are not fully downsampled.
+pub struct Bloom {
+    hdr_extent: vk::Extent2D,
+    chain: Option<Texture>,
+    level_views: Vec<vk::ImageView>,
+    sampler: vk::Sampler,
+    counter_buffer: Buffer,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Bloom {
+    /// Create the descriptor set of the downsampler.
+    ///
+    /// `counter_buffer` must be a storage buffer holding a zeroed integer.
+    ///
+    /// The chain must be set using `set_chain` and the pipeline using
+    /// `set_pipeline` before recording the dispatch.
+    pub fn new(device: &Device, counter_buffer: Buffer) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let counter_infos = [vk::DescriptorBufferInfo::builder()
+            .buffer(counter_buffer.buffer)
+            .offset(0)
+            .range(size_of::<u32>() as _)
+            .build()];
+        let descriptor_writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(COUNTER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&counter_infos)
+            .build()];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        // Bilinear samples average 2x2 texels of the HDR image
+        let sampler = {
+            let sampler_info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(MAX_BLOOM_LEVELS as _)
+                .build();
+            unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+        };
+
+        Bloom {
+            hdr_extent: vk::Extent2D::default(),
+            chain: None,
+            level_views: Vec::new(),
+            sampler,
+            counter_buffer,
+            layout,
+            pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type, descriptor_count| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(descriptor_count)
+                .descriptor_type(descriptor_type)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        };
+        let bindings = [
+            binding(
+                SOURCE_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                1,
+            ),
+            binding(
+                LEVELS_BINDING,
+                vk::DescriptorType::STORAGE_IMAGE,
+                MAX_BLOOM_LEVELS,
+            ),
+            binding(COUNTER_BINDING, vk::DescriptorType::STORAGE_BUFFER, 1),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: MAX_BLOOM_LEVELS,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Bloom {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the chain with the sampler the tonemapper reads it with.
+    ///
+    /// It is in the `GENERAL` layout after the dispatch. It is only valid
+    /// after `set_chain` and is owned by the bloom.
+    pub fn output(&self) -> Texture {
+        Texture {
+            sampler: Some(self.sampler),
+            ..self.chain.unwrap()
+        }
+    }
+
+    /// Set the HDR image downsampled into `chain`.
+    ///
+    /// `hdr` must be in the `GENERAL` layout and readable by compute shaders
+    /// when the dispatch is recorded. `chain` must be a `BLOOM_FORMAT`
+    /// texture of `chain_extent(extent)` with `chain_level_count(extent)`
+    /// levels and the `STORAGE` and `SAMPLED` usages. The bloom takes
+    /// ownership of it.
+    pub fn set_chain(
+        &mut self,
+        device: &Device,
+        hdr: &Texture,
+        extent: vk::Extent2D,
+        chain: Texture,
+    ) {
+        self.hdr_extent = extent;
+
+        let level_count = chain_level_count(extent);
+        self.level_views = (0..level_count)
+            .map(|level| {
+                let create_info = vk::ImageViewCreateInfo::builder()
+                    .image(chain.image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(BLOOM_FORMAT)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: level,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .build();
+                unsafe { device.create_image_view(&create_info, None).unwrap() }
+            })
+            .collect();
+
+        let source_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(hdr.view)
+            .sampler(self.sampler)
+            .build()];
+        // The levels past the last one are never written, they repeat it so
+        // the whole array is valid
+        let level_infos = (0..MAX_BLOOM_LEVELS as usize)
+            .map(|level| {
+                vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::GENERAL)
+                    .image_view(self.level_views[level.min(self.level_views.len() - 1)])
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.set)
+                .dst_binding(SOURCE_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&source_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.set)
+                .dst_binding(LEVELS_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&level_infos)
+                .build(),
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        self.chain = Some(chain);
+    }
+
+    /// Set the compute pipeline downsampling the HDR image.
+    ///
+    /// `pipeline_layout` must have the layout of the bloom and a push constant
+    /// range of one integer for the compute stage. The bloom takes ownership
+    /// of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the downsampling of the HDR image into the chain.
+    ///
+    /// It must be recorded after the exposure passes, outside of any render
+    /// pass. The chain is then readable by fragment shaders in the `GENERAL` layout.
+    pub fn cmd_dispatch(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let chain = self.chain.as_ref().unwrap();
+        let level_count = self.level_views.len() as u32;
+        // The tonemapping of the previous frame might still read the chain
+        let mut chain_image = TrackedImage::new(
+            chain.image,
+            vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ImageState {
+                layout: vk::ImageLayout::UNDEFINED,
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::SHADER_READ,
+            },
+        );
+        chain_image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        // The last group of the previous frame resets the counter
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::buffer(
+                self.counter_buffer.buffer,
+                Access::compute_write(),
+                Access::compute_read().and(Access::compute_write()),
+            )],
+        );
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &level_count.to_ne_bytes(),
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                self.hdr_extent.width.div_ceil(TILE_SIZE),
+                self.hdr_extent.height.div_ceil(TILE_SIZE),
+                1,
+            );
+        }
+
+        chain_image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+    }
+
+    /// Destroy the chain and its views.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_chain(&mut self, device: &Device) {
+        unsafe {
+            self.level_views
+                .drain(..)
+                .for_each(|view| device.destroy_image_view(view, None));
+        }
+        if let Some(mut chain) = self.chain.take() {
+            chain.destroy(device);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_chain(device);
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_sampler(self.sampler, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.counter_buffer.destroy(device);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{gpu_device::Device, lifetime, reflect, render_target::RenderTarget, texture::Texture};
+use ash::{version::DeviceV1_0, vk};
+
+/// Shaders of the pipeline of both passes, from which its layouts are reflected.
+pub const BLUR_SHADERS: [(vk::ShaderStageFlags, &str); 2] = [
+    (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+    (
+        vk::ShaderStageFlags::FRAGMENT,
+        "shaders/gaussian_blur.frag.spv",
+    ),
+];
+
+const SOURCE_BINDING: u32 = 0;
+
+/// Separable Gaussian blur of a texture, to blur the outputs of other passes.
+///
+/// The horizontal pass samples the source into the intermediate target, then
+/// the vertical pass samples the intermediate target into the output target.
+/// Both passes draw a full screen triangle with the same pipeline, the
+/// direction of the blur being given as push constants. Each pass samples 9
+/// texels with 5 linear fetches, so the source must have a linear sampler.
+pub struct GaussianBlur {
+    intermediate: RenderTarget,
+    output: RenderTarget,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    /// Sets sampling the source then the intermediate target.
+    sets: Vec<vk::DescriptorSet>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl GaussianBlur {
+    /// Create the descriptor sets of both passes.
+    ///
+    /// `intermediate` and `output` must have a single color attachment of the
+    /// same format and size and no depth. `source` must be readable by fragment
+    /// shaders in the `SHADER_READ_ONLY_OPTIMAL` layout when the blur is recorded.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording the blur.
+    pub fn new(
+        device: &Device,
+        source: Texture,
+        (intermediate, output): (RenderTarget, RenderTarget),
+    ) -> Self {
+        let shaders = BLUR_SHADERS
+            .iter()
+            .map(|(_, path)| reflect::reflect_shader(path))
+            .collect::<Vec<_>>();
+        let layout = reflect::create_set_layout(device, &shaders, 0);
+        let pool = Self::create_descriptor_pool(device);
+        let sets = {
+            let layouts = [layout; 2];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+        for (set, texture) in sets.iter().zip(&[source, intermediate.color(0)]) {
+            let image_infos = [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(texture.view)
+                .sampler(texture.sampler.unwrap())
+                .build()];
+            let descriptor_writes = [vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(SOURCE_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos)
+                .build()];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        let pipeline_layout = {
+            let layouts = [layout];
+            let push_constant_ranges = reflect::push_constant_ranges(&shaders);
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .push_constant_ranges(&push_constant_ranges)
+                .build();
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        };
+
+        GaussianBlur {
+            intermediate,
+            output,
+            layout,
+            pool,
+            sets,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout,
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 2,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(2)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl GaussianBlur {
+    /// Get the render pass of the targets, compatible with the passes of both.
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.intermediate.render_pass()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.output.extent()
+    }
+
+    pub fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    /// Get the blurred texture with its sampler.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the blur.
+    pub fn output(&self) -> Texture {
+        self.output.color(0)
+    }
+
+    /// Set the pipeline of both passes, created with `pipeline_layout`.
+    ///
+    /// The blur takes ownership of the pipeline.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline) {
+        self.pipeline = pipeline;
+    }
+
+    /// Record both passes of the blur in `command_buffer`.
+    ///
+    /// `spread` scales the distance between the samples, in texels. It must be
+    /// recorded outside of any render pass, after the pass writing the source.
+    pub fn cmd_blur(&self, device: &Device, command_buffer: vk::CommandBuffer, spread: f32) {
+        let extent = self.extent();
+        let directions = [
+            [spread / extent.width as f32, 0.0],
+            [0.0, spread / extent.height as f32],
+        ];
+        let targets = [&self.intermediate, &self.output];
+        for ((target, set), direction) in targets.iter().zip(&self.sets).zip(&directions) {
+            target.cmd_begin(device, command_buffer);
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline,
+                );
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    0,
+                    &[*set],
+                    &[],
+                );
+                let bytes = direction
+                    .iter()
+                    .flat_map(|value| value.to_ne_bytes())
+                    .collect::<Vec<_>>();
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    &bytes,
+                );
+                device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            }
+            target.cmd_end(device, command_buffer);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.intermediate.destroy(device);
+        self.output.destroy(device);
+    }
+}
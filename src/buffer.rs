@@ -0,0 +1,94 @@
+use crate::{
+    gpu_device::GpuDevice,
+    lifetime::{self, ObjectKind},
+    memory::{self, AllocationError},
+};
+use ash::vk;
+use std::mem::{align_of, size_of_val};
+
+#[derive(Clone, Copy)]
+pub struct Buffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    /// Size in bytes of the allocated memory.
+    ///
+    /// It may differ from the requested size.
+    pub size: vk::DeviceSize,
+    device_address: Option<vk::DeviceAddress>,
+}
+
+impl Buffer {
+    pub fn new(
+        buffer: vk::Buffer,
+        memory: vk::DeviceMemory,
+        size: vk::DeviceSize,
+        device_address: Option<vk::DeviceAddress>,
+    ) -> Self {
+        Buffer {
+            buffer,
+            memory,
+            size,
+            device_address,
+        }
+    }
+
+    /// Create a buffer from `buffer_info` and bind it to the memory `allocate`
+    /// returns for its requirements.
+    ///
+    /// The buffer has no device address, see `new`.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the memory cannot be allocated, nothing is created then.
+    pub fn create(
+        device: &impl GpuDevice,
+        buffer_info: &vk::BufferCreateInfo,
+        allocate: impl FnOnce(vk::MemoryRequirements) -> Result<vk::DeviceMemory, AllocationError>,
+    ) -> Result<Self, AllocationError> {
+        let buffer = unsafe { device.create_buffer(buffer_info).unwrap() };
+        let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let memory = match allocate(mem_requirements) {
+            Ok(memory) => memory,
+            Err(error) => {
+                unsafe { device.destroy_buffer(buffer) };
+                return Err(error);
+            }
+        };
+
+        unsafe { device.bind_buffer_memory(buffer, memory, 0).unwrap() };
+        lifetime::track(
+            device,
+            ObjectKind::Buffer,
+            buffer,
+            format_args!("{} bytes, {:?}", buffer_info.size, buffer_info.usage),
+        );
+        Ok(Buffer::new(buffer, memory, mem_requirements.size, None))
+    }
+
+    /// Get the address of the buffer in gpu memory.
+    ///
+    /// Only buffers created with the `SHADER_DEVICE_ADDRESS_EXT` usage
+    /// have an address.
+    pub fn device_address(&self) -> Option<vk::DeviceAddress> {
+        self.device_address
+    }
+
+    /// Copy `data` at `offset` bytes in the buffer, whose memory must be host visible.
+    pub fn write<T: Copy>(&self, device: &impl GpuDevice, offset: vk::DeviceSize, data: &[T]) {
+        let size = size_of_val(data) as vk::DeviceSize;
+        if size == 0 {
+            return;
+        }
+        unsafe {
+            let data_ptr = device.map_memory(self.memory, offset, size).unwrap();
+            let mut align = ash::util::Align::new(data_ptr, align_of::<T>() as _, size);
+            align.copy_from_slice(data);
+            device.unmap_memory(self.memory);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &impl GpuDevice) {
+        lifetime::destroy_buffer(device, self.buffer);
+        memory::free(device, self.memory);
+    }
+}
@@ -1,21 +1,42 @@
-use crate::math::clamp;
-use cgmath::Point3;
+use crate::math::{clamp, Aabb};
+use cgmath::{EuclideanSpace, Point3, Rad};
+
+/// Smallest radius of the boxes framed by the camera, so it does not end up
+/// inside a point or a flat box.
+const MIN_FRAMED_RADIUS: f32 = 0.1;
 
 #[derive(Clone, Copy)]
 pub struct Camera {
     theta: f32,
     phi: f32,
     r: f32,
+    /// Point the camera orbits around and looks at.
+    target: Point3<f32>,
 }
 
 impl Camera {
+    /// Create a camera at the distance `r` of the origin, with the angle
+    /// `phi` from the up axis and `theta` around it.
+    pub fn new(theta: f32, phi: f32, r: f32) -> Self {
+        Camera {
+            theta,
+            phi: clamp(phi, 10.0_f32.to_radians(), 170.0_f32.to_radians()),
+            r,
+            target: Point3::origin(),
+        }
+    }
+
     pub fn position(&self) -> Point3<f32> {
         Point3::new(
-            self.r * self.phi.sin() * self.theta.sin(),
-            self.r * self.phi.cos(),
-            self.r * self.phi.sin() * self.theta.cos(),
+            self.target.x + self.r * self.phi.sin() * self.theta.sin(),
+            self.target.y + self.r * self.phi.cos(),
+            self.target.z + self.r * self.phi.sin() * self.theta.cos(),
         )
     }
+
+    pub fn target(&self) -> Point3<f32> {
+        self.target
+    }
 }
 
 impl Camera {
@@ -28,6 +49,18 @@ impl Camera {
     pub fn forward(&mut self, r: f32) {
         self.r -= r;
     }
+
+    /// Orbit around the center of `aabb`, at the distance where its bounding
+    /// sphere fits in a view of vertical field of view `fov` and `aspect` ratio.
+    ///
+    /// The angles of the camera are kept.
+    pub fn frame(&mut self, aabb: &Aabb, fov: Rad<f32>, aspect: f32) {
+        let half_fov = fov.0 * 0.5;
+        let half_horizontal_fov = (half_fov.tan() * aspect).atan();
+        let radius = aabb.radius().max(MIN_FRAMED_RADIUS);
+        self.target = aabb.center();
+        self.r = radius / half_fov.min(half_horizontal_fov).sin();
+    }
 }
 
 impl Default for Camera {
@@ -36,6 +69,7 @@ impl Default for Camera {
             theta: 0.0_f32.to_radians(),
             phi: 45.0_f32.to_radians(),
             r: 3.0,
+            target: Point3::origin(),
         }
     }
 }
@@ -0,0 +1,148 @@
+use ash::vk;
+
+/// Format of the color lookup table texture.
+///
+/// 10 bits per channel are enough for a LUT sampled with trilinear filtering,
+/// and linear filtering of this format is supported by all devices.
+pub const COLOR_LUT_FORMAT: vk::Format = vk::Format::A2B10G10R10_UNORM_PACK32;
+
+/// Size of the neutral LUT used when no color grading is requested.
+///
+/// Two texels per axis are enough for the identity since it is linear.
+pub const NEUTRAL_LUT_SIZE: u32 = 2;
+
+#[derive(Clone, Debug)]
+pub enum CubeError {
+    /// A line could not be parsed.
+    InvalidLine(usize),
+    /// `LUT_3D_SIZE` is missing.
+    MissingSize,
+    /// The file contains a 1D LUT, which is not supported.
+    Unsupported1D,
+    /// The domain is not [0, 1], which is not supported.
+    UnsupportedDomain,
+    /// The number of entries does not match the size of the LUT.
+    WrongEntryCount { expected: usize, found: usize },
+}
+
+impl std::error::Error for CubeError {}
+
+impl std::fmt::Display for CubeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CubeError::InvalidLine(line) => write!(f, "Invalid line {}", line),
+            CubeError::MissingSize => write!(f, "LUT_3D_SIZE is missing"),
+            CubeError::Unsupported1D => write!(f, "1D LUTs are not supported"),
+            CubeError::UnsupportedDomain => write!(f, "Only the [0, 1] domain is supported"),
+            CubeError::WrongEntryCount { expected, found } => {
+                write!(f, "Expected {} entries, found {}", expected, found)
+            }
+        }
+    }
+}
+
+/// 3D color lookup table mapping a color to its graded color.
+///
+/// Entries are stored with red varying the fastest, then green, then blue,
+/// which is the order of both `.cube` files and the texels of a 3D texture.
+#[derive(Clone, Debug)]
+pub struct ColorLut {
+    size: u32,
+    entries: Vec<[f32; 3]>,
+}
+
+impl ColorLut {
+    /// Create the LUT mapping each color to itself with `size` entries per axis.
+    pub fn neutral(size: u32) -> Self {
+        let max = (size - 1) as f32;
+        let entries = (0..size)
+            .flat_map(|b| (0..size).flat_map(move |g| (0..size).map(move |r| (r, g, b))))
+            .map(|(r, g, b)| [r as f32 / max, g as f32 / max, b as f32 / max])
+            .collect();
+        ColorLut { size, entries }
+    }
+
+    /// Parse the content of a `.cube` file.
+    ///
+    /// Only 3D LUTs with the default [0, 1] domain are supported.
+    pub fn from_cube(content: &str) -> Result<Self, CubeError> {
+        let mut size = None;
+        let mut entries = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+            let parse_floats = |tokens: std::str::SplitWhitespace| {
+                let values = tokens
+                    .map(str::parse)
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|_| CubeError::InvalidLine(line_number))?;
+                match values.as_slice() {
+                    [r, g, b] => Ok([*r, *g, *b]),
+                    _ => Err(CubeError::InvalidLine(line_number)),
+                }
+            };
+
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => return Err(CubeError::Unsupported1D),
+                "LUT_3D_SIZE" => {
+                    let value = tokens
+                        .next()
+                        .and_then(|value| value.parse::<u32>().ok())
+                        .filter(|value| *value >= 2)
+                        .ok_or(CubeError::InvalidLine(line_number))?;
+                    size = Some(value);
+                }
+                "DOMAIN_MIN" => {
+                    if parse_floats(tokens)? != [0.0; 3] {
+                        return Err(CubeError::UnsupportedDomain);
+                    }
+                }
+                "DOMAIN_MAX" => {
+                    if parse_floats(tokens)? != [1.0; 3] {
+                        return Err(CubeError::UnsupportedDomain);
+                    }
+                }
+                _ => {
+                    let all_tokens = line.split_whitespace();
+                    entries.push(parse_floats(all_tokens)?);
+                }
+            }
+        }
+
+        let size = size.ok_or(CubeError::MissingSize)?;
+        let expected = size.pow(3) as usize;
+        if entries.len() != expected {
+            return Err(CubeError::WrongEntryCount {
+                expected,
+                found: entries.len(),
+            });
+        }
+        Ok(ColorLut { size, entries })
+    }
+
+    /// Get the extent of the 3D texture storing the LUT.
+    pub fn extent(&self) -> vk::Extent3D {
+        vk::Extent3D {
+            width: self.size,
+            height: self.size,
+            depth: self.size,
+        }
+    }
+
+    /// Get the texels of the LUT in `COLOR_LUT_FORMAT`.
+    pub fn to_pixels(&self) -> Vec<u8> {
+        let to_unorm10 = |value: f32| (value.clamp(0.0, 1.0) * 1023.0).round() as u32;
+        self.entries
+            .iter()
+            .map(|[r, g, b]| {
+                (3 << 30) | (to_unorm10(*b) << 20) | (to_unorm10(*g) << 10) | to_unorm10(*r)
+            })
+            .flat_map(u32::to_ne_bytes)
+            .collect()
+    }
+}
@@ -0,0 +1,191 @@
+use crate::gpu_device::Device;
+use ash::{version::DeviceV1_0, vk};
+
+/// Maximum number of descriptor sets whose binding is tracked.
+const MAX_TRACKED_SETS: usize = 4;
+
+/// Number of bind calls recorded and skipped by a `CommandEncoder`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BindStats {
+    pub recorded: u32,
+    pub skipped: u32,
+}
+
+/// Recording of the graphics commands of a command buffer skipping redundant binds.
+///
+/// The encoder tracks the pipeline, the vertex and index buffers and the
+/// descriptor sets currently bound and only records a bind when it changes
+/// something. The state is unknown when the encoder is created, so the first
+/// bind of each kind is always recorded.
+///
+/// Commands without state tracking are recorded directly with `device` and
+/// `command_buffer`. Binds recorded this way must be followed by a call to
+/// `invalidate` so the encoder does not skip the next ones.
+pub struct CommandEncoder<'a> {
+    device: &'a Device,
+    command_buffer: vk::CommandBuffer,
+    pipeline: Option<vk::Pipeline>,
+    vertex_buffers: Vec<Option<(vk::Buffer, vk::DeviceSize)>>,
+    index_buffer: Option<(vk::Buffer, vk::DeviceSize, vk::IndexType)>,
+    descriptor_sets: [Option<(vk::DescriptorSet, Vec<u32>)>; MAX_TRACKED_SETS],
+    stats: BindStats,
+}
+
+impl<'a> CommandEncoder<'a> {
+    /// Create an encoder recording into `command_buffer`, which must be recording.
+    pub fn new(device: &'a Device, command_buffer: vk::CommandBuffer) -> Self {
+        CommandEncoder {
+            device,
+            command_buffer,
+            pipeline: None,
+            vertex_buffers: Vec::new(),
+            index_buffer: None,
+            descriptor_sets: Default::default(),
+            stats: BindStats::default(),
+        }
+    }
+}
+
+impl<'a> CommandEncoder<'a> {
+    pub fn device(&self) -> &'a Device {
+        self.device
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    /// Get the number of binds recorded and skipped so far.
+    pub fn stats(&self) -> BindStats {
+        self.stats
+    }
+
+    /// Forget the bound state after binds recorded without the encoder.
+    pub fn invalidate(&mut self) {
+        self.pipeline = None;
+        self.vertex_buffers.clear();
+        self.index_buffer = None;
+        self.descriptor_sets = Default::default();
+    }
+
+    /// Count a bind and return whether it must be recorded.
+    fn track(&mut self, redundant: bool) -> bool {
+        if redundant {
+            self.stats.skipped += 1;
+        } else {
+            self.stats.recorded += 1;
+        }
+        !redundant
+    }
+
+    pub fn bind_pipeline(&mut self, pipeline: vk::Pipeline) {
+        if self.track(self.pipeline == Some(pipeline)) {
+            self.pipeline = Some(pipeline);
+            unsafe {
+                self.device.cmd_bind_pipeline(
+                    self.command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline,
+                )
+            };
+        }
+    }
+
+    pub fn bind_vertex_buffers(
+        &mut self,
+        first_binding: u32,
+        buffers: &[vk::Buffer],
+        offsets: &[vk::DeviceSize],
+    ) {
+        let first = first_binding as usize;
+        let bound = buffers
+            .iter()
+            .zip(offsets)
+            .enumerate()
+            .all(|(i, (&buffer, &offset))| {
+                self.vertex_buffers.get(first + i) == Some(&Some((buffer, offset)))
+            });
+        if self.track(bound) {
+            let len = self.vertex_buffers.len().max(first + buffers.len());
+            self.vertex_buffers.resize(len, None);
+            for (i, (&buffer, &offset)) in buffers.iter().zip(offsets).enumerate() {
+                self.vertex_buffers[first + i] = Some((buffer, offset));
+            }
+            unsafe {
+                self.device.cmd_bind_vertex_buffers(
+                    self.command_buffer,
+                    first_binding,
+                    buffers,
+                    offsets,
+                )
+            };
+        }
+    }
+
+    pub fn bind_index_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        index_type: vk::IndexType,
+    ) {
+        let index_buffer = (buffer, offset, index_type);
+        if self.track(self.index_buffer == Some(index_buffer)) {
+            self.index_buffer = Some(index_buffer);
+            unsafe {
+                self.device
+                    .cmd_bind_index_buffer(self.command_buffer, buffer, offset, index_type)
+            };
+        }
+    }
+
+    /// Bind graphics `sets` starting at `first_set`.
+    ///
+    /// A set is considered bound only if it was bound at the same index with
+    /// the same dynamic offsets, using a compatible pipeline layout. Binding
+    /// a set forgets the ones after it since the layout may not be compatible
+    /// for them. The dynamic offsets of several sets bound at once cannot be
+    /// split between them, so such binds are always recorded.
+    pub fn bind_descriptor_sets(
+        &mut self,
+        layout: vk::PipelineLayout,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
+        let first = first_set as usize;
+        let tracked = first + sets.len() <= MAX_TRACKED_SETS
+            && (sets.len() == 1 || dynamic_offsets.is_empty());
+        let bound = tracked
+            && sets.iter().enumerate().all(|(i, &set)| {
+                let offsets = if sets.len() == 1 {
+                    dynamic_offsets
+                } else {
+                    &[]
+                };
+                match &self.descriptor_sets[first + i] {
+                    Some((bound_set, bound_offsets)) => {
+                        *bound_set == set && bound_offsets.as_slice() == offsets
+                    }
+                    None => false,
+                }
+            });
+        if self.track(bound) {
+            for (i, slot) in self.descriptor_sets.iter_mut().enumerate().skip(first) {
+                *slot = match sets.get(i - first) {
+                    Some(&set) if tracked => Some((set, dynamic_offsets.to_vec())),
+                    _ => None,
+                };
+            }
+            unsafe {
+                self.device.cmd_bind_descriptor_sets(
+                    self.command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    layout,
+                    first_set,
+                    sets,
+                    dynamic_offsets,
+                )
+            };
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use crate::text::Text;
+use ash::vk;
+use std::{collections::VecDeque, fmt};
+
+/// Number of output lines kept and shown.
+const MAX_OUTPUT_LINES: usize = 12;
+
+/// Height in pixels of a line of the console.
+const LINE_SIZE: f32 = 18.0;
+
+/// Value of a console variable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CVarValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+}
+
+impl CVarValue {
+    /// Parse `text` as a value of the same type as `self`.
+    ///
+    /// Booleans accept `0`, `1`, `true`, `false`, `on` and `off`.
+    fn parse_as(self, text: &str) -> Option<Self> {
+        match self {
+            CVarValue::Bool(_) => match text {
+                "1" | "true" | "on" => Some(CVarValue::Bool(true)),
+                "0" | "false" | "off" => Some(CVarValue::Bool(false)),
+                _ => None,
+            },
+            CVarValue::Int(_) => text.parse().ok().map(CVarValue::Int),
+            CVarValue::Float(_) => text.parse().ok().map(CVarValue::Float),
+        }
+    }
+
+    /// Clamp `self` between `min` and `max`, booleans being left as they are.
+    fn clamp(self, min: Self, max: Self) -> Self {
+        match self {
+            CVarValue::Bool(_) => self,
+            CVarValue::Int(value) => CVarValue::Int(value.clamp(min.as_int(), max.as_int())),
+            CVarValue::Float(value) => {
+                CVarValue::Float(value.clamp(min.as_float(), max.as_float()))
+            }
+        }
+    }
+
+    pub fn as_bool(self) -> bool {
+        match self {
+            CVarValue::Bool(value) => value,
+            CVarValue::Int(value) => value != 0,
+            CVarValue::Float(value) => value != 0.0,
+        }
+    }
+
+    pub fn as_int(self) -> i64 {
+        match self {
+            CVarValue::Bool(value) => value as _,
+            CVarValue::Int(value) => value,
+            CVarValue::Float(value) => value as _,
+        }
+    }
+
+    pub fn as_float(self) -> f32 {
+        match self {
+            CVarValue::Bool(value) => value as u8 as _,
+            CVarValue::Int(value) => value as _,
+            CVarValue::Float(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for CVarValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CVarValue::Bool(value) => write!(f, "{}", *value as u8),
+            CVarValue::Int(value) => write!(f, "{}", value),
+            CVarValue::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Variable of the app that can be read and set from the console.
+#[derive(Clone, Copy, Debug)]
+struct CVar {
+    name: &'static str,
+    description: &'static str,
+    value: CVarValue,
+    /// Smallest and largest values the variable can be set to, if bounded.
+    range: Option<(CVarValue, CVarValue)>,
+}
+
+/// What the app must do after a line was executed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConsoleAction {
+    /// The variable with this name was set, its new value must be applied.
+    CVarChanged(&'static str),
+    /// The command with this name was entered with these arguments.
+    Command(&'static str, Vec<String>),
+}
+
+/// Console typing commands and setting variables of the app at runtime.
+///
+/// A line is either the name of a variable to print its value, the name of
+/// a variable and its new value, or the name of a command and its
+/// arguments. `help`, `cvars` and `clear` are handled by the console, the
+/// other commands and the changes of variables are returned to the app.
+#[derive(Debug, Default)]
+pub struct Console {
+    open: bool,
+    input: String,
+    output: VecDeque<String>,
+    /// Lines entered, the most recent last.
+    history: Vec<String>,
+    /// Line of the history recalled in the input, if any.
+    history_index: Option<usize>,
+    cvars: Vec<CVar>,
+    commands: Vec<(&'static str, &'static str)>,
+}
+
+impl Console {
+    /// Add the variable `name` with its initial `value`.
+    pub fn register_cvar(
+        &mut self,
+        name: &'static str,
+        description: &'static str,
+        value: CVarValue,
+    ) {
+        self.cvars.push(CVar {
+            name,
+            description,
+            value,
+            range: None,
+        });
+    }
+
+    /// Add the variable `name` with its initial `value`, the values entered
+    /// being clamped between `min` and `max`.
+    pub fn register_clamped_cvar(
+        &mut self,
+        name: &'static str,
+        description: &'static str,
+        value: CVarValue,
+        (min, max): (CVarValue, CVarValue),
+    ) {
+        self.cvars.push(CVar {
+            name,
+            description,
+            value,
+            range: Some((min, max)),
+        });
+    }
+
+    /// Add the command `name`, executed by the app.
+    pub fn register_command(&mut self, name: &'static str, description: &'static str) {
+        self.commands.push((name, description));
+    }
+
+    /// Get the value of the variable `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the variable was not registered.
+    pub fn get(&self, name: &str) -> CVarValue {
+        self.cvars
+            .iter()
+            .find(|cvar| cvar.name == name)
+            .unwrap_or_else(|| panic!("Unknown console variable {}", name))
+            .value
+    }
+
+    /// Set the value of the variable `name` from the app, without returning
+    /// an action.
+    pub fn set(&mut self, name: &str, value: CVarValue) {
+        if let Some(cvar) = self.cvars.iter_mut().find(|cvar| cvar.name == name) {
+            cvar.value = value;
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    /// Add `line` to the output.
+    pub fn print(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        log::info!("{}", line);
+        self.output.push_back(line);
+        while self.output.len() > MAX_OUTPUT_LINES {
+            self.output.pop_front();
+        }
+    }
+
+    /// Type `c` in the input.
+    ///
+    /// Return the action of the line if `c` is a new line.
+    pub fn handle_char(&mut self, c: char) -> Option<ConsoleAction> {
+        match c {
+            '\r' | '\n' => {
+                let line = std::mem::take(&mut self.input);
+                self.history_index = None;
+                if line.trim().is_empty() {
+                    return None;
+                }
+                self.history.push(line.clone());
+                self.execute(&line)
+            }
+            '\u{8}' => {
+                self.input.pop();
+                None
+            }
+            // The key opening the console
+            '`' | '~' => None,
+            c if !c.is_control() => {
+                self.input.push(c);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace the input by the previous line of the history.
+    pub fn recall_previous(&mut self) {
+        let index = match self.history_index {
+            Some(index) => index.saturating_sub(1),
+            None if !self.history.is_empty() => self.history.len() - 1,
+            None => return,
+        };
+        self.history_index = Some(index);
+        self.input = self.history[index].clone();
+    }
+
+    /// Replace the input by the next line of the history, or clear it after the last one.
+    pub fn recall_next(&mut self) {
+        match self.history_index {
+            Some(index) if index + 1 < self.history.len() => {
+                self.history_index = Some(index + 1);
+                self.input = self.history[index + 1].clone();
+            }
+            Some(_) => {
+                self.history_index = None;
+                self.input.clear();
+            }
+            None => {}
+        }
+    }
+
+    /// Execute `line` and return what the app must do.
+    pub fn execute(&mut self, line: &str) -> Option<ConsoleAction> {
+        self.print(format!("> {}", line));
+        let mut words = line.split_whitespace();
+        let name = words.next()?;
+        let args = words.map(str::to_owned).collect::<Vec<_>>();
+
+        match name {
+            "help" => {
+                self.print("Commands: help, cvars, clear");
+                let commands = self.commands.clone();
+                for (name, description) in commands {
+                    self.print(format!("  {}: {}", name, description));
+                }
+                self.print(
+                    "Type the name of a variable to print it, followed by a value to set it.",
+                );
+                return None;
+            }
+            "cvars" => {
+                for cvar in self.cvars.clone() {
+                    self.print(format!(
+                        "  {} = {}: {}",
+                        cvar.name, cvar.value, cvar.description
+                    ));
+                }
+                return None;
+            }
+            "clear" => {
+                self.output.clear();
+                return None;
+            }
+            _ => {}
+        }
+
+        if let Some(&(name, _)) = self.commands.iter().find(|(command, _)| *command == name) {
+            return Some(ConsoleAction::Command(name, args));
+        }
+
+        let cvar = match self.cvars.iter_mut().find(|cvar| cvar.name == name) {
+            Some(cvar) => cvar,
+            None => {
+                self.print(format!("Unknown command or variable {}.", name));
+                return None;
+            }
+        };
+        let text = match args.as_slice() {
+            [] => {
+                let line = format!("{} = {}", cvar.name, cvar.value);
+                self.print(line);
+                return None;
+            }
+            [text] => text,
+            _ => {
+                self.print(format!("{} takes a single value.", name));
+                return None;
+            }
+        };
+        match cvar.value.parse_as(text) {
+            Some(value) => {
+                let name = cvar.name;
+                let clamped = match cvar.range {
+                    Some((min, max)) => value.clamp(min, max),
+                    None => value,
+                };
+                cvar.value = clamped;
+                if clamped != value {
+                    let (min, max) = cvar.range.unwrap();
+                    self.print(format!("{} ranges from {} to {}.", name, min, max));
+                }
+                self.print(format!("{} = {}", name, clamped));
+                Some(ConsoleAction::CVarChanged(name))
+            }
+            None => {
+                self.print(format!("Invalid value {} for {}.", text, name));
+                None
+            }
+        }
+    }
+
+    /// Get the text of the output and the input, at the bottom left of a
+    /// window of `extent`.
+    pub fn text(&self, extent: vk::Extent2D) -> Text {
+        let mut text = self.output.iter().fold(String::new(), |mut text, line| {
+            text.push_str(line);
+            text.push('\n');
+            text
+        });
+        text.push_str(&format!("> {}_", self.input));
+        let line_count = self.output.len() + 1;
+        Text {
+            text,
+            position: [
+                10.0,
+                extent.height as f32 - 10.0 - line_count as f32 * LINE_SIZE,
+            ],
+            size: LINE_SIZE,
+            color: [1.0, 0.9, 0.5, 1.0],
+        }
+    }
+}
@@ -1,30 +1,174 @@
+use crate::{
+    device_address::BufferDeviceAddress, gpu_device::Device, ray_tracing::RayTracing, sync2,
+};
 use ash::{
     extensions::{ext::DebugReport, khr::Surface},
-    version::{DeviceV1_0, InstanceV1_0},
-    vk, Device, Entry, Instance,
+    version::{DeviceV1_0, InstanceV1_0, InstanceV1_1},
+    vk, Entry, Instance,
+};
+use std::{
+    ffi::{c_void, CStr},
+    rc::Rc,
 };
 
-pub struct VkContext {
-    _entry: Entry,
+/// Instance and the objects created from it that do not depend on the device.
+///
+/// They are kept when the device is lost so only the logical device and its
+/// resources are created again, see `VkContext::instance_context`.
+pub struct InstanceContext {
+    entry: Entry,
     instance: Instance,
+    /// Version of the api the instance was created with.
+    api_version: u32,
     debug_report_callback: Option<(DebugReport, vk::DebugReportCallbackEXT)>,
-    surface: Surface,
-    surface_khr: vk::SurfaceKHR,
+    surface: Option<(Surface, vk::SurfaceKHR)>,
+}
+
+impl InstanceContext {
+    pub fn new(
+        entry: Entry,
+        instance: Instance,
+        api_version: u32,
+        debug_report_callback: Option<(DebugReport, vk::DebugReportCallbackEXT)>,
+        surface: Option<(Surface, vk::SurfaceKHR)>,
+    ) -> Self {
+        InstanceContext {
+            entry,
+            instance,
+            api_version,
+            debug_report_callback,
+            surface,
+        }
+    }
+
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    pub fn instance(&self) -> &Instance {
+        &self.instance
+    }
+
+    pub fn api_version(&self) -> u32 {
+        self.api_version
+    }
+
+    /// Get the surface loader and the surface, or `None` when running headless.
+    pub fn surface(&self) -> Option<(&Surface, vk::SurfaceKHR)> {
+        self.surface
+            .as_ref()
+            .map(|(surface, surface_khr)| (surface, *surface_khr))
+    }
+}
+
+impl Drop for InstanceContext {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some((surface, surface_khr)) = self.surface.take() {
+                surface.destroy_surface(surface_khr, None);
+            }
+            if let Some((report, callback)) = self.debug_report_callback.take() {
+                report.destroy_debug_report_callback(callback, None);
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+pub struct VkContext {
+    /// Shared with the next context when the device is lost.
+    instance: Rc<InstanceContext>,
     physical_device: vk::PhysicalDevice,
     device: Device,
+    buffer_device_address: Option<BufferDeviceAddress>,
+    ray_tracing: Option<RayTracing>,
+    memory_budget: bool,
+    subgroup_properties: Option<SubgroupProperties>,
+}
+
+/// Size and supported operations of the subgroups of a device.
+#[derive(Clone, Copy, Debug)]
+pub struct SubgroupProperties {
+    /// Number of invocations in each subgroup.
+    pub size: u32,
+    /// Stages where subgroup operations are supported.
+    pub stages: vk::ShaderStageFlags,
+    /// Subgroup operations supported in `stages`.
+    pub operations: vk::SubgroupFeatureFlags,
+}
+
+impl SubgroupProperties {
+    /// Query the subgroup properties of `device`.
+    ///
+    /// # Safety
+    ///
+    /// Vulkan 1.1 must be supported by the instance and the device.
+    pub unsafe fn query(instance: &Instance, device: vk::PhysicalDevice) -> Self {
+        let mut subgroup_properties = vk::PhysicalDeviceSubgroupProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2 {
+            p_next: &mut subgroup_properties as *mut _ as *mut c_void,
+            ..Default::default()
+        };
+        get_physical_device_properties2(instance, device, &mut properties);
+
+        SubgroupProperties {
+            size: subgroup_properties.subgroup_size,
+            stages: subgroup_properties.supported_stages,
+            operations: subgroup_properties.supported_operations,
+        }
+    }
+
+    /// Check if compute shaders support all of `operations`.
+    ///
+    /// Subgroups of less than 4 invocations are not considered, since quads
+    /// and the reductions through shared memory need at least 4.
+    pub fn supports_compute(&self, operations: vk::SubgroupFeatureFlags) -> bool {
+        self.size >= 4
+            && self.stages.contains(vk::ShaderStageFlags::COMPUTE)
+            && self.operations.contains(operations)
+    }
+
+    /// Get the specialization constants of compute shaders using subgroups.
+    ///
+    /// The subgroup size is the constant 0, `SUBGROUP_SIZE` in `subgroup.glsl`.
+    pub fn specialization_constants(&self) -> [u32; 1] {
+        [self.size]
+    }
 }
 
 impl VkContext {
-    pub fn instance(&self) -> &Instance {
+    /// Get the instance the device was created from.
+    ///
+    /// The instance stays alive as long as a clone is kept, so a new device
+    /// can be created from it after this context is dropped.
+    pub fn instance_context(&self) -> &Rc<InstanceContext> {
         &self.instance
     }
 
-    pub fn surface(&self) -> &Surface {
-        &self.surface
+    pub fn entry(&self) -> &Entry {
+        self.instance.entry()
     }
 
-    pub fn surface_khr(&self) -> vk::SurfaceKHR {
-        self.surface_khr
+    pub fn instance(&self) -> &Instance {
+        self.instance.instance()
+    }
+
+    /// Get the surface loader and the surface, or `None` when running headless.
+    pub fn surface(&self) -> Option<(&Surface, vk::SurfaceKHR)> {
+        self.instance.surface()
+    }
+
+    /// Replace the surface by `surface_khr` and destroy the previous one.
+    ///
+    /// The swapchain created from the previous surface must have been destroyed.
+    /// It is used on Android where the window the surface is created from is
+    /// destroyed when the app goes to the background.
+    pub fn replace_surface(&mut self, surface_khr: vk::SurfaceKHR) {
+        let instance = Rc::get_mut(&mut self.instance).expect("The instance is shared.");
+        if let Some((surface, previous_surface_khr)) = instance.surface.as_mut() {
+            unsafe { surface.destroy_surface(*previous_surface_khr, None) };
+            *previous_surface_khr = surface_khr;
+        }
     }
 
     pub fn physical_device(&self) -> vk::PhysicalDevice {
@@ -34,12 +178,33 @@ impl VkContext {
     pub fn device(&self) -> &Device {
         &self.device
     }
+
+    /// Get the buffer device address loader if the feature is enabled.
+    pub fn buffer_device_address(&self) -> Option<&BufferDeviceAddress> {
+        self.buffer_device_address.as_ref()
+    }
+
+    /// Get the ray tracing loader if the acceleration structures and the ray
+    /// tracing pipelines are enabled.
+    pub fn ray_tracing(&self) -> Option<&RayTracing> {
+        self.ray_tracing.as_ref()
+    }
+
+    /// Check if `VK_EXT_memory_budget` is enabled.
+    pub fn is_memory_budget_enabled(&self) -> bool {
+        self.memory_budget
+    }
+
+    /// Get the subgroup properties of the device, or `None` without Vulkan 1.1.
+    pub fn subgroup_properties(&self) -> Option<SubgroupProperties> {
+        self.subgroup_properties
+    }
 }
 
 impl VkContext {
     pub fn get_mem_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
         unsafe {
-            self.instance
+            self.instance()
                 .get_physical_device_memory_properties(self.physical_device)
         }
     }
@@ -53,7 +218,7 @@ impl VkContext {
     ) -> Option<vk::Format> {
         candidates.iter().cloned().find(|candidate| {
             let props = unsafe {
-                self.instance
+                self.instance()
                     .get_physical_device_format_properties(self.physical_device, *candidate)
             };
             (tiling == vk::ImageTiling::LINEAR && props.linear_tiling_features.contains(features))
@@ -65,12 +230,13 @@ impl VkContext {
     /// Return the maximim sample count supported.
     pub fn get_max_usable_sample_count(&self) -> vk::SampleCountFlags {
         let props = unsafe {
-            self.instance
+            self.instance()
                 .get_physical_device_properties(self.physical_device)
         };
         let color_sample_counts = props.limits.framebuffer_color_sample_counts;
         let depth_sample_counts = props.limits.framebuffer_depth_sample_counts;
-        let sample_counts = color_sample_counts.min(depth_sample_counts);
+        // Some devices, like MoltenVK, support different counts for color and depth
+        let sample_counts = color_sample_counts & depth_sample_counts;
 
         if sample_counts.contains(vk::SampleCountFlags::TYPE_64) {
             vk::SampleCountFlags::TYPE_64
@@ -92,35 +258,89 @@ impl VkContext {
 
 impl VkContext {
     pub fn new(
-        entry: Entry,
-        instance: Instance,
-        debug_report_callback: Option<(DebugReport, vk::DebugReportCallbackEXT)>,
-        surface: Surface,
-        surface_khr: vk::SurfaceKHR,
+        instance: Rc<InstanceContext>,
         physical_device: vk::PhysicalDevice,
         device: Device,
+        (buffer_device_address, ray_tracing): (Option<BufferDeviceAddress>, Option<RayTracing>),
+        memory_budget: bool,
+        subgroup_properties: Option<SubgroupProperties>,
     ) -> Self {
         VkContext {
-            _entry: entry,
             instance,
-            debug_report_callback,
-            surface,
-            surface_khr,
             physical_device,
             device,
+            buffer_device_address,
+            ray_tracing,
+            memory_budget,
+            subgroup_properties,
         }
     }
 }
 
+/// Check if `device` supports the device extension `extension`.
+pub fn is_device_extension_supported(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    extension: &CStr,
+) -> bool {
+    let extension_props = unsafe {
+        instance
+            .enumerate_device_extension_properties(device)
+            .unwrap()
+    };
+    extension_props.iter().any(|ext| {
+        let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+        extension == name
+    })
+}
+
+/// Check if `device` exposes the Vulkan 1.1 api.
+///
+/// The instance must also have been created with at least the 1.1 api version
+/// for the 1.1 entry points to be callable.
+pub fn is_vulkan_1_1_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let props = unsafe { instance.get_physical_device_properties(device) };
+    props.api_version >= ash::vk_make_version!(1, 1, 0)
+}
+
+/// Query the features of `device` and fill the extension feature structures
+/// chained to `features`.
+///
+/// # Safety
+///
+/// Vulkan 1.1 must be supported by the instance and the device.
+pub unsafe fn get_physical_device_features2(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    features: &mut vk::PhysicalDeviceFeatures2,
+) {
+    instance
+        .fp_v1_1()
+        .get_physical_device_features2(device, features);
+}
+
+/// Query the properties of `device` and fill the extension property
+/// structures chained to `properties`.
+///
+/// # Safety
+///
+/// Vulkan 1.1 must be supported by the instance and the device.
+pub unsafe fn get_physical_device_properties2(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    properties: &mut vk::PhysicalDeviceProperties2,
+) {
+    instance
+        .fp_v1_1()
+        .get_physical_device_properties2(device, properties);
+}
+
 impl Drop for VkContext {
     fn drop(&mut self) {
-        unsafe {
-            self.device.destroy_device(None);
-            self.surface.destroy_surface(self.surface_khr, None);
-            if let Some((report, callback)) = self.debug_report_callback.take() {
-                report.destroy_debug_report_callback(callback, None);
-            }
-            self.instance.destroy_instance(None);
-        }
+        sync2::disable();
+        #[cfg(feature = "debug-markers")]
+        crate::debug_markers::disable();
+        // The instance, if not shared, is destroyed after the device
+        unsafe { self.device.destroy_device(None) };
     }
 }
@@ -1,17 +1,58 @@
 use ash::{extensions::ext::DebugReport, version::EntryV1_0};
 use ash::{vk, Entry, Instance};
+#[cfg(feature = "validation")]
+use std::os::raw::c_void;
 use std::{
     ffi::{CStr, CString},
-    os::raw::{c_char, c_void},
+    os::raw::c_char,
+    sync::OnceLock,
 };
 
-#[cfg(debug_assertions)]
-pub const ENABLE_VALIDATION_LAYERS: bool = true;
-#[cfg(not(debug_assertions))]
-pub const ENABLE_VALIDATION_LAYERS: bool = false;
-
 const REQUIRED_LAYERS: [&str; 1] = ["VK_LAYER_LUNARG_standard_validation"];
 
+/// Environment variable set to `1` or `0` to enable or disable the validation
+/// layers, which are enabled by default in debug builds.
+const VALIDATION_VAR: &str = "VULKAN_VALIDATION";
+
+/// Environment variable set to `0` to disable the GPU-assisted validation.
+const GPU_ASSISTED_VAR: &str = "VULKAN_GPU_ASSISTED";
+
+/// Read the environment variable `name` set to `1` or `0`.
+///
+/// A warning is logged if it has another value.
+pub fn env_flag(name: &str) -> Option<bool> {
+    match std::env::var(name).ok()?.as_str() {
+        "1" => Some(true),
+        "0" => Some(false),
+        value => {
+            log::warn!("Ignoring {}={}, it must be 1 or 0.", name, value);
+            None
+        }
+    }
+}
+
+/// Check if the validation layers and their debug callback are enabled.
+///
+/// They are only built with the `validation` feature, and are enabled in
+/// debug builds unless `VULKAN_VALIDATION` overrides it.
+pub fn is_validation_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    cfg!(feature = "validation")
+        && *ENABLED.get_or_init(|| env_flag(VALIDATION_VAR).unwrap_or(cfg!(debug_assertions)))
+}
+
+/// Check if the validation layers also instrument the shaders to validate
+/// their accesses on the GPU.
+///
+/// It is built with the `gpu-assisted` feature and enabled with the
+/// validation layers unless `VULKAN_GPU_ASSISTED` is `0`.
+pub fn is_gpu_assisted_enabled() -> bool {
+    cfg!(feature = "gpu-assisted")
+        && is_validation_enabled()
+        && env_flag(GPU_ASSISTED_VAR).unwrap_or(true)
+}
+
+#[cfg(feature = "validation")]
 unsafe extern "system" fn vulkan_debug_callback(
     flag: vk::DebugReportFlagsEXT,
     typ: vk::DebugReportObjectTypeEXT,
@@ -75,11 +116,12 @@ pub fn check_validation_layer_support(entry: &Entry) {
 }
 
 /// Setup the debug message if validation layers are enabled.
+#[cfg(feature = "validation")]
 pub fn setup_debug_messenger(
     entry: &Entry,
     instance: &Instance,
 ) -> Option<(DebugReport, vk::DebugReportCallbackEXT)> {
-    if !ENABLE_VALIDATION_LAYERS {
+    if !is_validation_enabled() {
         return None;
     }
     let create_info = vk::DebugReportCallbackCreateInfoEXT::builder()
@@ -94,3 +136,12 @@ pub fn setup_debug_messenger(
     };
     Some((debug_report, debug_report_callback))
 }
+
+/// Without the `validation` feature there is no debug callback.
+#[cfg(not(feature = "validation"))]
+pub fn setup_debug_messenger(
+    _entry: &Entry,
+    _instance: &Instance,
+) -> Option<(DebugReport, vk::DebugReportCallbackEXT)> {
+    None
+}
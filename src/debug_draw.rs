@@ -0,0 +1,235 @@
+use crate::{
+    buffer::Buffer, command_encoder::CommandEncoder, gpu_device::Device, lifetime, math::Aabb,
+    transient::TransientBufferAllocator,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Point3, Vector3};
+use std::mem::size_of;
+
+/// Maximum number of lines drawn in a frame.
+pub const MAX_DEBUG_LINES: usize = 8192;
+
+/// Number of segments of the circles.
+const CIRCLE_SEGMENTS: usize = 48;
+
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub struct DebugVertex {
+    pub pos: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl DebugVertex {
+    pub fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<DebugVertex>() as _)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        let position_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let color_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(12)
+            .build();
+        [position_desc, color_desc]
+    }
+}
+
+/// Immediate mode drawing of world space lines on top of the scene.
+///
+/// Lines are accumulated during the frame then written by `update` in the
+/// transient buffer of the swapchain image, along with the indirect draw reading
+/// them. They are drawn in the second subpass of the main render pass without
+/// depth test.
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+    indirect_buffers: Vec<Buffer>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl DebugDraw {
+    /// Create the debug draw.
+    ///
+    /// `indirect_buffers` must be host visible and coherent buffers of at least
+    /// `DebugDraw::indirect_buffer_size()` bytes, one per swapchain image.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(device: &Device, indirect_buffers: Vec<Buffer>) -> Self {
+        // Nothing is drawn until the first update
+        indirect_buffers
+            .iter()
+            .for_each(|buffer| buffer.write(device, 0, &[draw_command(0, 0)]));
+
+        DebugDraw {
+            vertices: Vec::new(),
+            indirect_buffers,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Size in bytes of each indirect buffer.
+    pub fn indirect_buffer_size() -> vk::DeviceSize {
+        size_of::<vk::DrawIndirectCommand>() as _
+    }
+}
+
+impl DebugDraw {
+    /// Set the pipeline used to draw the lines.
+    ///
+    /// The debug draw takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    pub fn line(&mut self, start: Point3<f32>, end: Point3<f32>, color: [f32; 4]) {
+        self.vertices.push(DebugVertex {
+            pos: start.into(),
+            color,
+        });
+        self.vertices.push(DebugVertex {
+            pos: end.into(),
+            color,
+        });
+    }
+
+    /// Draw the edges of `aabb`.
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            )
+        };
+        // Each edge links two corners differing by one bit
+        for i in 0..8 {
+            for bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    /// Draw a circle of `radius` around `center` in the plane of `normal`.
+    pub fn circle(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        color: [f32; 4],
+    ) {
+        let normal = normal.normalize();
+        let reference = if normal.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let tangent = normal.cross(reference).normalize() * radius;
+        let bitangent = normal.cross(tangent);
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * std::f32::consts::PI;
+            center + tangent * angle.cos() + bitangent * angle.sin()
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    /// Write the lines drawn since the last update in `transient_allocator`,
+    /// and the draw reading them in the indirect buffer of the swapchain image
+    /// `image_index`, then clear them.
+    ///
+    /// The frame of `transient_allocator` must have begun for `image_index`.
+    /// At most `MAX_DEBUG_LINES` lines are drawn.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        image_index: usize,
+        transient_allocator: &mut TransientBufferAllocator,
+    ) {
+        self.vertices.truncate(MAX_DEBUG_LINES * 2);
+        let stride = size_of::<DebugVertex>() as vk::DeviceSize;
+        let command = if self.vertices.is_empty() {
+            draw_command(0, 0)
+        } else {
+            // Vertices are aligned on their stride so they can be reached with the first vertex
+            match transient_allocator.push(&self.vertices, stride) {
+                Some(allocation) => {
+                    draw_command(self.vertices.len() as _, (allocation.offset / stride) as _)
+                }
+                None => {
+                    log::warn!("Transient buffer full, debug lines are not drawn.");
+                    draw_command(0, 0)
+                }
+            }
+        };
+        self.indirect_buffers[image_index].write(device, 0, &[command]);
+        self.vertices.clear();
+    }
+
+    /// Record the draw of the lines of the swapchain image `image_index` with `encoder`.
+    ///
+    /// It must be recorded in the second subpass of the main render pass.
+    /// `vertex_buffer` is the transient buffer of the image. The frame set must
+    /// be bound with `FrameLayout::cmd_bind`.
+    pub fn cmd_draw(
+        &self,
+        encoder: &mut CommandEncoder,
+        image_index: usize,
+        vertex_buffer: vk::Buffer,
+    ) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[vertex_buffer], &[0]);
+        unsafe {
+            encoder.device().cmd_draw_indirect(
+                encoder.command_buffer(),
+                self.indirect_buffers[image_index].buffer,
+                0,
+                1,
+                size_of::<vk::DrawIndirectCommand>() as _,
+            )
+        };
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        self.indirect_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
+
+fn draw_command(vertex_count: u32, first_vertex: u32) -> vk::DrawIndirectCommand {
+    vk::DrawIndirectCommand {
+        vertex_count,
+        instance_count: 1,
+        first_vertex,
+        first_instance: 0,
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{debug::env_flag, gpu_crash::FramePass, gpu_device::Device};
+use ash::{
+    extensions::ext::DebugUtils,
+    version::EntryV1_0,
+    vk::{self, Handle},
+    Entry, Instance,
+};
+use std::{
+    cell::Cell,
+    ffi::{CStr, CString},
+    sync::Mutex,
+};
+
+/// Environment variable set to `1` or `0` to enable or disable the names of
+/// the objects, which are enabled by default in debug builds.
+const DEBUG_MARKERS_VAR: &str = "VULKAN_DEBUG_MARKERS";
+
+/// Functions of `VK_EXT_debug_utils` and the device whose objects are named, if enabled.
+///
+/// It is global because the objects are named where they are tracked, with
+/// only their handle at hand, and the labels are recorded where the commands are.
+static DEBUG_UTILS: Mutex<Option<(DebugUtils, vk::Device)>> = Mutex::new(None);
+
+/// Get the instance extensions required to name the objects.
+pub fn required_instance_extensions() -> [&'static CStr; 1] {
+    [DebugUtils::name()]
+}
+
+/// Check if the objects should be named, unless `VULKAN_DEBUG_MARKERS` overrides it.
+pub fn is_requested() -> bool {
+    env_flag(DEBUG_MARKERS_VAR).unwrap_or(cfg!(debug_assertions))
+}
+
+/// Check if the instance supports `VK_EXT_debug_utils`.
+pub fn is_supported(entry: &Entry) -> bool {
+    let extensions = entry.enumerate_instance_extension_properties().unwrap();
+    required_instance_extensions().iter().all(|required| {
+        extensions
+            .iter()
+            .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == *required)
+    })
+}
+
+/// Load the functions of `VK_EXT_debug_utils`, which must be enabled on
+/// `instance`, and name the objects of `device` until `disable` is called.
+pub fn enable(entry: &Entry, instance: &Instance, device: &Device) {
+    let debug_utils = DebugUtils::new(entry, instance);
+    *DEBUG_UTILS.lock().unwrap() = Some((debug_utils, device.handle()));
+}
+
+/// Stop naming the objects, before the device is destroyed.
+pub fn disable() {
+    DEBUG_UTILS.lock().unwrap().take();
+}
+
+/// Give `name` to the object `handle` if the names are enabled.
+///
+/// It is shown by the validation messages and the graphics debuggers.
+pub fn set_object_name<H: Handle>(handle: H, name: &str) {
+    if let Some((debug_utils, device)) = DEBUG_UTILS.lock().unwrap().as_ref() {
+        let name = CString::new(name.replace('\0', "")).unwrap();
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(H::TYPE)
+            .object_handle(handle.as_raw())
+            .object_name(&name)
+            .build();
+        if let Err(error) = unsafe { debug_utils.debug_utils_set_object_name(*device, &name_info) }
+        {
+            log::warn!("Failed to name {:?}: {}.", name, error);
+        }
+    }
+}
+
+/// Color of the labels of the passes rendering shadows and reflections.
+const OFFSCREEN_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+/// Color of the labels of the compute passes culling and simulating the scene.
+const COMPUTE_COLOR: [f32; 4] = [0.2, 0.4, 0.9, 1.0];
+
+/// Color of the labels of the main pass and of the frame.
+const MAIN_COLOR: [f32; 4] = [0.2, 0.8, 0.3, 1.0];
+
+/// Color of the labels of the post-processing passes.
+const POST_COLOR: [f32; 4] = [0.9, 0.6, 0.1, 1.0];
+
+/// Color of the labels of the uploads submitted before the frame.
+pub const UPLOAD_COLOR: [f32; 4] = [0.7, 0.3, 0.8, 1.0];
+
+/// Color of the labels inserted between the commands.
+pub const INSERTED_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+fn pass_color(pass: FramePass) -> [f32; 4] {
+    match pass {
+        FramePass::Shadows | FramePass::Reflection | FramePass::StereoEyes => OFFSCREEN_COLOR,
+        FramePass::Velocity
+        | FramePass::Culling
+        | FramePass::Particles
+        | FramePass::RayTracedShadows => COMPUTE_COLOR,
+        FramePass::Begin | FramePass::MainPass | FramePass::Readback | FramePass::End => MAIN_COLOR,
+        FramePass::Exposure
+        | FramePass::Bloom
+        | FramePass::Ssr
+        | FramePass::LightShafts
+        | FramePass::MotionBlur
+        | FramePass::Fsr
+        | FramePass::Tonemap => POST_COLOR,
+    }
+}
+
+fn with_label(name: &str, color: [f32; 4], f: impl FnOnce(&DebugUtils, &vk::DebugUtilsLabelEXT)) {
+    if let Some((debug_utils, _)) = DEBUG_UTILS.lock().unwrap().as_ref() {
+        let name = CString::new(name.replace('\0', "")).unwrap();
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&name)
+            .color(color)
+            .build();
+        f(debug_utils, &label);
+    }
+}
+
+/// Open a label named `name` around the next commands of `command_buffer`,
+/// until `cmd_end_label` is recorded. Labels can be nested.
+pub fn cmd_begin_label(command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+    with_label(name, color, |debug_utils, label| unsafe {
+        debug_utils.cmd_begin_debug_utils_label(command_buffer, label)
+    });
+}
+
+/// Close the last label opened with `cmd_begin_label` in `command_buffer`.
+pub fn cmd_end_label(command_buffer: vk::CommandBuffer) {
+    if let Some((debug_utils, _)) = DEBUG_UTILS.lock().unwrap().as_ref() {
+        unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+    }
+}
+
+/// Insert a label named `name` between two commands of `command_buffer`.
+pub fn cmd_insert_label(command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+    with_label(name, color, |debug_utils, label| unsafe {
+        debug_utils.cmd_insert_debug_utils_label(command_buffer, label)
+    });
+}
+
+/// Labels of the passes of the command buffer of a swapchain image, nested in
+/// a label of the frame.
+///
+/// They follow the markers of the passes: the label of a pass lasts until the
+/// next pass begins, and the label of the frame from `FramePass::Begin` to
+/// `FramePass::End`.
+pub struct PassLabels {
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    /// Whether the label of a pass is open.
+    is_pass_open: Cell<bool>,
+}
+
+impl PassLabels {
+    pub fn new(command_buffer: vk::CommandBuffer, image_index: usize) -> Self {
+        PassLabels {
+            command_buffer,
+            image_index,
+            is_pass_open: Cell::new(false),
+        }
+    }
+
+    /// Close the label of the previous pass and open the one of `pass`.
+    pub fn cmd_begin_pass(&self, pass: FramePass) {
+        if self.is_pass_open.replace(false) {
+            cmd_end_label(self.command_buffer);
+        }
+        match pass {
+            FramePass::Begin => cmd_begin_label(
+                self.command_buffer,
+                &format!("Frame of swapchain image {}", self.image_index),
+                pass_color(pass),
+            ),
+            FramePass::End => cmd_end_label(self.command_buffer),
+            _ => {
+                cmd_begin_label(self.command_buffer, pass.name(), pass_color(pass));
+                self.is_pass_open.set(true);
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use winit::VirtualKeyCode;
+
+/// Debug visualizations that can be toggled at runtime.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DebugViews {
+    /// Draw the vertex normals of the model as lines.
+    ///
+    /// Toggled with the N key. Requires geometry shader support.
+    pub normals: bool,
+}
+
+impl DebugViews {
+    /// Toggle the view bound to `key`.
+    ///
+    /// # Returns
+    ///
+    /// Whether a view was toggled, in which case the command buffers must be recorded again.
+    pub fn handle_key(&mut self, key: VirtualKeyCode) -> bool {
+        match key {
+            VirtualKeyCode::N => {
+                self.normals = !self.normals;
+                log::debug!("Normals view: {}", self.normals);
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    buffer::Buffer, command_encoder::CommandEncoder, frame_layout::PASS_SET, gpu_device::Device,
+    lifetime, std140::std140_struct, texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Deg, Matrix4, Point3, Vector3};
+use std::mem::size_of;
+
+/// Maximum number of decals.
+///
+/// Must match `MAX_DECALS` in `decal_uniforms.glsl`.
+pub const MAX_DECALS: usize = 16;
+
+/// Width and height in pixels of the generated decal texture.
+pub const DECAL_TEXTURE_SIZE: u32 = 128;
+
+const DEPTH_BINDING: u32 = 0;
+const TEXTURE_BINDING: u32 = 1;
+const DECALS_BINDING: u32 = 2;
+
+/// Box projecting a texture on the surfaces it contains.
+///
+/// The texture is projected from the top of the box along its local y-axis.
+#[derive(Clone, Copy, Debug)]
+pub struct Decal {
+    pub position: Point3<f32>,
+    pub size: Vector3<f32>,
+    /// Rotation around the vertical axis.
+    pub angle: Deg<f32>,
+    /// Color multiplied with the decal texture.
+    pub color: [f32; 4],
+}
+
+impl Decal {
+    /// Get the transform from the unit box centered on the origin to the decal box.
+    pub fn transform(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position.to_vec())
+            * Matrix4::from_angle_y(self.angle)
+            * Matrix4::from_nonuniform_scale(self.size.x, self.size.y, self.size.z)
+    }
+}
+
+std140_struct! {
+    /// Decal as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct DecalUniform {
+        decal_to_world: Matrix4<f32>,
+        world_to_decal: Matrix4<f32>,
+        color: [f32; 4],
+    }
+}
+
+impl From<Decal> for DecalUniform {
+    fn from(decal: Decal) -> Self {
+        let transform = decal.transform();
+        DecalUniform {
+            decal_to_world: transform,
+            world_to_decal: transform.invert().unwrap(),
+            color: decal.color,
+        }
+    }
+}
+
+/// Get the size of the uniform buffer holding the decals.
+pub fn uniform_buffer_size() -> vk::DeviceSize {
+    (size_of::<DecalUniform>() * MAX_DECALS) as _
+}
+
+/// Generate the RGBA8 pixels of a white splat whose alpha fades on its edges.
+pub fn generate_decal_texture() -> Vec<u8> {
+    let size = DECAL_TEXTURE_SIZE as usize;
+    let mut pixels = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let radius = (u * u + v * v).sqrt();
+            let angle = v.atan2(u);
+
+            let edge = 0.7 + 0.1 * (7.0 * angle).sin() + 0.05 * (13.0 * angle + 1.0).sin();
+            let alpha = ((edge - radius) / 0.1).clamp(0.0, 1.0);
+            pixels.extend_from_slice(&[255, 255, 255, (alpha * 255.0) as u8]);
+        }
+    }
+    pixels
+}
+
+/// Box projected decals drawn in the second subpass of the main render pass.
+///
+/// Each decal is drawn as a box. The fragment shader reads the depth of the
+/// scene from an input attachment, reconstructs the world position of the
+/// surface behind the fragment and projects the decal texture on it if it
+/// is inside the box.
+///
+/// Only the color is blended. The normals of the surfaces are not affected
+/// since the scene is not rendered in a G-buffer.
+pub struct Decals {
+    count: u32,
+    uniform_buffer: Buffer,
+    texture: Texture,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Decals {
+    /// Create the descriptor set of the decals and write them in `uniform_buffer`.
+    ///
+    /// `uniform_buffer` must be a host visible and coherent buffer of at least
+    /// `uniform_buffer_size()` bytes. At most `MAX_DECALS` decals are drawn.
+    ///
+    /// The depth attachment must be set using `set_depth_attachment` and the
+    /// pipeline using `set_pipeline` before recording draws.
+    pub fn new(
+        device: &Device,
+        decals: &[Decal],
+        uniform_buffer: Buffer,
+        texture: Texture,
+    ) -> Self {
+        if decals.len() > MAX_DECALS {
+            log::warn!(
+                "{} decals requested but only {} are supported.",
+                decals.len(),
+                MAX_DECALS
+            );
+        }
+        let uniforms = decals
+            .iter()
+            .take(MAX_DECALS)
+            .map(|decal| DecalUniform::from(*decal))
+            .collect::<Vec<_>>();
+        uniform_buffer.write(device, 0, &uniforms);
+
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build();
+        let image_infos = [image_info];
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer)
+            .offset(0)
+            .range(uniform_buffer_size())
+            .build();
+        let buffer_infos = [buffer_info];
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(TEXTURE_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(DECALS_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_infos)
+                .build(),
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Decals {
+            count: uniforms.len() as _,
+            uniform_buffer,
+            texture,
+            layout,
+            pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type, stage_flags| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(stage_flags)
+                .build()
+        };
+        let bindings = [
+            binding(
+                DEPTH_BINDING,
+                vk::DescriptorType::INPUT_ATTACHMENT,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                TEXTURE_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                DECALS_BINDING,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            ),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::INPUT_ATTACHMENT,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Decals {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Set the view of the depth attachment read by the decals.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn set_depth_attachment(&self, device: &Device, depth_view: vk::ImageView) {
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(depth_view)
+            .build();
+        let image_infos = [image_info];
+        let depth_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(DEPTH_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+            .image_info(&image_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[depth_write], &[]) };
+    }
+
+    /// Set the pipeline used to draw the decals.
+    ///
+    /// The decals take ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the draw of the decals with `encoder`.
+    ///
+    /// It must be recorded in the second subpass of the main render pass.
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_descriptor_sets(self.pipeline_layout, PASS_SET, &[self.set], &[]);
+        // One box of 36 vertices per decal
+        unsafe {
+            encoder
+                .device()
+                .cmd_draw(encoder.command_buffer(), 36, self.count, 0, 0)
+        };
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.uniform_buffer.destroy(device);
+        self.texture.destroy(device);
+    }
+}
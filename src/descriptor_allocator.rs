@@ -0,0 +1,94 @@
+use crate::{gpu_device::GpuDevice, lifetime};
+use ash::vk;
+
+/// Maximum number of sets of the pools created by a `DescriptorAllocator`,
+/// unless a single allocation needs more.
+const MAX_SETS_PER_POOL: u32 = 1024;
+
+/// Allocator of descriptor sets from pools created as the previous ones fill up.
+///
+/// All the sets allocated have the same descriptors, given per set by
+/// `set_sizes`. The first pool has room for `sets_per_pool` sets and each
+/// new pool for twice as many as the previous one, so the number of pools
+/// stays low whatever the number of sets. The sets are freed all at once
+/// when the pools are destroyed.
+pub struct DescriptorAllocator {
+    set_sizes: Vec<vk::DescriptorPoolSize>,
+    sets_per_pool: u32,
+    /// Pools created so far, the last one being the one allocated from.
+    pools: Vec<vk::DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(set_sizes: &[vk::DescriptorPoolSize], sets_per_pool: u32) -> Self {
+        DescriptorAllocator {
+            set_sizes: set_sizes.to_vec(),
+            sets_per_pool: sets_per_pool.max(1),
+            pools: Vec::new(),
+        }
+    }
+}
+
+impl DescriptorAllocator {
+    /// Allocate a set for each of `layouts`, creating a new pool if the current one is full.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the allocation fails for another reason than a full pool.
+    #[track_caller]
+    pub fn allocate(
+        &mut self,
+        device: &impl GpuDevice,
+        layouts: &[vk::DescriptorSetLayout],
+    ) -> Vec<vk::DescriptorSet> {
+        if let Some(&pool) = self.pools.last() {
+            match Self::allocate_from(device, pool, layouts) {
+                Ok(sets) => return sets,
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {}
+                Err(error) => panic!("Failed to allocate descriptor sets. Cause: {}", error),
+            }
+            self.sets_per_pool = (self.sets_per_pool * 2).min(MAX_SETS_PER_POOL);
+        }
+
+        let pool = self.create_pool(device, self.sets_per_pool.max(layouts.len() as _));
+        self.pools.push(pool);
+        Self::allocate_from(device, pool, layouts).expect("Failed to allocate descriptor sets")
+    }
+
+    fn allocate_from(
+        device: &impl GpuDevice,
+        pool: vk::DescriptorPool,
+        layouts: &[vk::DescriptorSetLayout],
+    ) -> ash::prelude::VkResult<Vec<vk::DescriptorSet>> {
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(layouts)
+            .build();
+        unsafe { device.allocate_descriptor_sets(&alloc_info) }
+    }
+
+    #[track_caller]
+    fn create_pool(&self, device: &impl GpuDevice, max_sets: u32) -> vk::DescriptorPool {
+        let pool_sizes = self
+            .set_sizes
+            .iter()
+            .map(|size| vk::DescriptorPoolSize {
+                ty: size.ty,
+                descriptor_count: size.descriptor_count * max_sets,
+            })
+            .collect::<Vec<_>>();
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(max_sets)
+            .build();
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    /// Destroy the pools, and so the sets allocated from them.
+    pub fn destroy(&mut self, device: &impl GpuDevice) {
+        self.pools
+            .drain(..)
+            .for_each(|pool| lifetime::destroy_descriptor_pool(device, pool));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{context::*, gpu_device::Device, ray_tracing};
+use ash::{version::InstanceV1_0, vk, Instance};
+use std::{
+    ffi::{c_void, CStr},
+    mem,
+};
+
+/// Get the device extensions required to query buffer device addresses.
+pub fn required_device_extensions() -> [&'static CStr; 1] {
+    [vk::ExtBufferDeviceAddressFn::name()]
+}
+
+/// Check if `device` supports buffer device addresses.
+///
+/// Vulkan 1.1 must be supported by the instance and the device since the
+/// features are queried using `vkGetPhysicalDeviceFeatures2`.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let extensions_supported = required_device_extensions()
+        .iter()
+        .all(|ext| is_device_extension_supported(instance, device, ext));
+    if !extensions_supported {
+        return false;
+    }
+
+    let mut address_features = vk::PhysicalDeviceBufferAddressFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut address_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { get_physical_device_features2(instance, device, &mut features) };
+
+    address_features.buffer_device_address == vk::TRUE
+}
+
+/// Build the buffer device address features to enable at device creation.
+pub fn required_features() -> vk::PhysicalDeviceBufferAddressFeaturesEXT {
+    vk::PhysicalDeviceBufferAddressFeaturesEXT::builder()
+        .buffer_device_address(true)
+        .build()
+}
+
+/// Loader for the `VK_EXT_buffer_device_address` functions.
+///
+/// The KHR version of the extension is not exposed by our version of Ash,
+/// but the EXT version provides the same functionality. The ray tracing
+/// needs the KHR version though, see `new_khr`.
+pub struct BufferDeviceAddress {
+    handle: vk::Device,
+    buffer_device_address_fn: vk::ExtBufferDeviceAddressFn,
+    memory_allocate_flags: vk::MemoryAllocateFlags,
+}
+
+impl BufferDeviceAddress {
+    pub fn new(instance: &Instance, device: &Device) -> Self {
+        let buffer_device_address_fn = vk::ExtBufferDeviceAddressFn::load(|name| unsafe {
+            mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        });
+        BufferDeviceAddress {
+            handle: device.handle(),
+            buffer_device_address_fn,
+            memory_allocate_flags: vk::MemoryAllocateFlags::empty(),
+        }
+    }
+
+    /// Load `vkGetBufferDeviceAddressKHR` for `device`, created with the
+    /// extensions and the features of the ray tracing enabled.
+    ///
+    /// The KHR function has the signature of the EXT one and takes the same
+    /// structure, so it is loaded in its place.
+    pub fn new_khr(instance: &Instance, device: &Device) -> Self {
+        let name = CStr::from_bytes_with_nul(b"vkGetBufferDeviceAddressKHR\0").unwrap();
+        let buffer_device_address_fn = vk::ExtBufferDeviceAddressFn::load(|_| unsafe {
+            mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        });
+        BufferDeviceAddress {
+            handle: device.handle(),
+            buffer_device_address_fn,
+            memory_allocate_flags: ray_tracing::memory_allocate_flags(),
+        }
+    }
+
+    /// Get the flags of the allocations, which the KHR version needs to get
+    /// the address of the buffers bound to them.
+    pub fn memory_allocate_flags(&self) -> vk::MemoryAllocateFlags {
+        self.memory_allocate_flags
+    }
+
+    /// Get the address of `buffer` in gpu memory.
+    ///
+    /// `buffer` must have been created with the `SHADER_DEVICE_ADDRESS_EXT` usage.
+    pub fn get_buffer_device_address(&self, buffer: vk::Buffer) -> vk::DeviceAddress {
+        let info = vk::BufferDeviceAddressInfoEXT::builder()
+            .buffer(buffer)
+            .build();
+        unsafe {
+            self.buffer_device_address_fn
+                .get_buffer_device_address_ext(self.handle, &info)
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{
+    buffer::Buffer, command_encoder::CommandEncoder, frame_layout::PASS_SET, gpu_device::Device,
+    lifetime, texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::mem::size_of;
+
+/// Number of control points of the patches. Each patch is a quad.
+pub const PATCH_CONTROL_POINTS: u32 = 4;
+
+/// Width and depth of the plane in world units.
+///
+/// Must match `PLANE_SIZE` in `displacement.frag`.
+pub const PLANE_SIZE: f32 = 6.0;
+
+/// Number of patches along each side of the plane.
+pub const PLANE_RESOLUTION: u32 = 16;
+
+/// Width and height in pixels of the generated height map.
+pub const HEIGHT_MAP_SIZE: u32 = 256;
+
+const HEIGHT_MAP_BINDING: u32 = 0;
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub struct PlaneVertex {
+    pub pos: [f32; 3],
+    pub coords: [f32; 2],
+}
+
+impl PlaneVertex {
+    pub fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<PlaneVertex>() as _)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        let position_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let coords_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(12)
+            .build();
+        [position_desc, coords_desc]
+    }
+}
+
+/// Build a flat grid of `PLANE_RESOLUTION` x `PLANE_RESOLUTION` quad patches
+/// centered on the origin.
+///
+/// # Returns
+///
+/// The vertices and the indices of the 4 control points of each patch.
+pub fn build_plane_patches() -> (Vec<PlaneVertex>, Vec<u32>) {
+    let vertices_per_side = PLANE_RESOLUTION + 1;
+    let mut vertices = Vec::with_capacity((vertices_per_side * vertices_per_side) as _);
+    for z in 0..vertices_per_side {
+        for x in 0..vertices_per_side {
+            let u = x as f32 / PLANE_RESOLUTION as f32;
+            let v = z as f32 / PLANE_RESOLUTION as f32;
+            vertices.push(PlaneVertex {
+                pos: [(u - 0.5) * PLANE_SIZE, 0.0, (v - 0.5) * PLANE_SIZE],
+                coords: [u, v],
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity((PLANE_RESOLUTION * PLANE_RESOLUTION * 4) as _);
+    for z in 0..PLANE_RESOLUTION {
+        for x in 0..PLANE_RESOLUTION {
+            let first = z * vertices_per_side + x;
+            indices.push(first);
+            indices.push(first + 1);
+            indices.push(first + vertices_per_side + 1);
+            indices.push(first + vertices_per_side);
+        }
+    }
+
+    (vertices, indices)
+}
+
+/// Generate a procedural RGBA8 height map of `HEIGHT_MAP_SIZE` x `HEIGHT_MAP_SIZE`
+/// pixels made of a few octaves of sine waves.
+///
+/// The height is stored in all the color channels.
+pub fn generate_height_map() -> Vec<u8> {
+    let size = HEIGHT_MAP_SIZE as usize;
+    let mut pixels = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let u = x as f32 / size as f32 * std::f32::consts::PI * 2.0;
+            let v = y as f32 / size as f32 * std::f32::consts::PI * 2.0;
+            let height = 0.5
+                + 0.25 * (u * 2.0).sin() * (v * 3.0).cos()
+                + 0.15 * (u * 5.0 + v * 4.0).sin()
+                + 0.1 * (u * 11.0).cos() * (v * 13.0).sin();
+            let value = (height.clamp(0.0, 1.0) * 255.0) as u8;
+            pixels.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+    pixels
+}
+
+/// Plane tessellated on the gpu and displaced using a height map.
+///
+/// The tessellation factors of each patch edge depend on the distance
+/// between the camera and the middle of the edge. The height map is bound
+/// in set 1.
+pub struct DisplacedPlane {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    index_count: u32,
+    height_map: Texture,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl DisplacedPlane {
+    /// Create the descriptor set containing the height map.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(
+        device: &Device,
+        vertex_buffer: Buffer,
+        index_buffer: Buffer,
+        index_count: u32,
+        height_map: Texture,
+    ) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(height_map.view)
+            .sampler(height_map.sampler.unwrap())
+            .build();
+        let image_infos = [image_info];
+        let height_map_write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(HEIGHT_MAP_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[height_map_write], &[]) };
+
+        DisplacedPlane {
+            vertex_buffer,
+            index_buffer,
+            index_count,
+            height_map,
+            layout,
+            pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let height_map_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(HEIGHT_MAP_BINDING)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(
+                vk::ShaderStageFlags::TESSELLATION_EVALUATION | vk::ShaderStageFlags::FRAGMENT,
+            )
+            .build();
+        let bindings = [height_map_binding];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl DisplacedPlane {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Set the pipeline used to draw the plane.
+    ///
+    /// The plane takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the draw of the plane with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[self.vertex_buffer.buffer], &[0]);
+        encoder.bind_index_buffer(self.index_buffer.buffer, 0, vk::IndexType::UINT32);
+        encoder.bind_descriptor_sets(self.pipeline_layout, PASS_SET, &[self.set], &[]);
+        unsafe {
+            encoder.device().cmd_draw_indexed(
+                encoder.command_buffer(),
+                self.index_count,
+                1,
+                0,
+                0,
+                0,
+            )
+        };
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.height_map.destroy(device);
+        self.index_buffer.destroy(device);
+        self.vertex_buffer.destroy(device);
+    }
+}
@@ -0,0 +1,98 @@
+use ash::vk;
+
+/// Smallest fraction of the swapchain size the scene is rendered at.
+pub const MIN_RENDER_SCALE: f32 = 0.5;
+
+/// Largest fraction of the swapchain size the scene is rendered at.
+pub const MAX_RENDER_SCALE: f32 = 1.0;
+
+/// Frame rate held by the dynamic resolution when none is given.
+pub const DEFAULT_TARGET_FPS: f32 = 60.0;
+
+/// Change of the render scale when the frame is over or under its budget.
+const RENDER_SCALE_STEP: f32 = 0.1;
+
+/// Weight of the last frame in the average GPU frame time.
+const SMOOTHING: f32 = 0.1;
+
+/// Fraction of the budget under which the frames are fast enough to raise the scale.
+///
+/// It is low enough that the scale does not oscillate between two steps, the
+/// time of a frame growing with the square of the scale.
+const HEADROOM: f32 = 0.75;
+
+/// Frames to wait after a change of the render scale before changing it again,
+/// so the average frame time reflects the new scale.
+const COOLDOWN_FRAMES: u32 = 30;
+
+/// Get the extent of the images rendered at `scale` for a swapchain of `extent`.
+pub fn scale_extent(extent: vk::Extent2D, scale: f32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: ((extent.width as f32 * scale).round() as u32).max(1),
+        height: ((extent.height as f32 * scale).round() as u32).max(1),
+    }
+}
+
+/// Render scale following the GPU frame time to hold a target frame rate.
+///
+/// The GPU time of the frames is averaged, and the scale is lowered by a step
+/// while the average is over the budget of a frame, or raised by a step while
+/// it leaves enough headroom. The scale only changes the size of the images
+/// the scene is rendered to, so it is applied by recreating them.
+#[derive(Clone, Copy, Debug)]
+pub struct DynamicResolution {
+    /// Budget of a frame in milliseconds.
+    target_milliseconds: f32,
+    scale: f32,
+    average_milliseconds: Option<f32>,
+    frames_since_change: u32,
+}
+
+impl DynamicResolution {
+    /// Create a render scale starting at `scale`, targeting `target_fps` frames per second.
+    pub fn new(target_fps: f32, scale: f32) -> Self {
+        DynamicResolution {
+            target_milliseconds: 1000.0 / target_fps,
+            scale: scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE),
+            average_milliseconds: None,
+            frames_since_change: 0,
+        }
+    }
+
+    /// Get the scale the scene should be rendered at.
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Add the GPU time of a frame in milliseconds and update the scale.
+    pub fn update(&mut self, milliseconds: f32) {
+        let average = match self.average_milliseconds {
+            Some(average) => average + (milliseconds - average) * SMOOTHING,
+            None => milliseconds,
+        };
+        self.average_milliseconds = Some(average);
+
+        self.frames_since_change += 1;
+        if self.frames_since_change < COOLDOWN_FRAMES {
+            return;
+        }
+        let scale = if average > self.target_milliseconds {
+            self.scale - RENDER_SCALE_STEP
+        } else if average < self.target_milliseconds * HEADROOM {
+            self.scale + RENDER_SCALE_STEP
+        } else {
+            return;
+        };
+        let scale = scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+        if (scale - self.scale).abs() > f32::EPSILON {
+            log::debug!(
+                "GPU frame time of {:.2} ms, render scale changed from {:.0}% to {:.0}%.",
+                average,
+                self.scale * 100.0,
+                scale * 100.0
+            );
+            self.scale = scale;
+            self.frames_since_change = 0;
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use crate::{
+    buffer::Buffer,
+    gpu_device::Device,
+    lifetime,
+    std140::std140_struct,
+    texture::Texture,
+    tracked_image::{ImageState, TrackedImage},
+};
+use ash::{version::DeviceV1_0, vk};
+use std::mem::size_of;
+
+/// Format of the image the scene is rendered into before tonemapping.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Number of bins of the luminance histogram.
+///
+/// Must match `HISTOGRAM_BINS` in `exposure.glsl`.
+pub const HISTOGRAM_BINS: usize = 256;
+
+/// Width and height of the work groups of the histogram shader.
+///
+/// Must match the local size of `histogram.comp`.
+const HISTOGRAM_GROUP_SIZE: u32 = 16;
+
+const HDR_IMAGE_BINDING: u32 = 0;
+const HISTOGRAM_BINDING: u32 = 1;
+const EXPOSURE_BINDING: u32 = 2;
+const UNIFORMS_BINDING: u32 = 3;
+
+/// Parameters of the automatic exposure.
+#[derive(Clone, Copy, Debug)]
+pub struct ExposureParameters {
+    /// Log2 of the lowest luminance of the histogram.
+    pub min_log_luminance: f32,
+    /// Log2 of the highest luminance of the histogram.
+    pub max_log_luminance: f32,
+    /// Speed at which the exposure adapts to the luminance, higher is faster.
+    pub adaptation_speed: f32,
+    /// Exposure compensation in stops.
+    pub compensation: f32,
+}
+
+impl Default for ExposureParameters {
+    fn default() -> Self {
+        ExposureParameters {
+            min_log_luminance: -10.0,
+            max_log_luminance: 4.0,
+            adaptation_speed: 1.5,
+            compensation: 0.0,
+        }
+    }
+}
+
+std140_struct! {
+    /// Uniforms of the exposure shaders as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct ExposureUniforms {
+        pub min_log_luminance: f32,
+        pub log_luminance_range: f32,
+        /// Time in seconds since the last frame.
+        pub time_delta: f32,
+        pub adaptation_speed: f32,
+        pub compensation: f32,
+    }
+}
+
+/// Exposure computed on the gpu as laid out in the exposure buffer.
+///
+/// Must match `ExposureData` in `exposure.glsl`.
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub struct ExposureData {
+    /// Factor applied to the HDR colors before tonemapping.
+    pub exposure: f32,
+    /// Average luminance of the scene, adapted over time.
+    pub average_luminance: f32,
+}
+
+impl Default for ExposureData {
+    fn default() -> Self {
+        // Middle gray keeps the scene as is until the first adaptation
+        ExposureData {
+            exposure: 1.0,
+            average_luminance: 0.18,
+        }
+    }
+}
+
+/// Automatic exposure computed from the luminance of the HDR image.
+///
+/// After the main render pass a first compute pass builds the histogram of
+/// the log luminance of the HDR image with atomics, first in shared memory
+/// then in the histogram buffer. A second pass of a single work group
+/// averages the histogram, adapts the average luminance over time and writes
+/// the resulting exposure in the exposure buffer, which the tonemapper reads.
+/// It also clears the histogram for the next frame.
+pub struct AutoExposure {
+    parameters: ExposureParameters,
+    hdr_image: vk::Image,
+    extent: vk::Extent2D,
+    histogram_buffer: Buffer,
+    exposure_buffer: Buffer,
+    uniform_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    histogram_pipeline: vk::Pipeline,
+    average_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl AutoExposure {
+    /// Create the descriptor sets of the exposure passes.
+    ///
+    /// `histogram_buffer` must be a zeroed storage buffer of `HISTOGRAM_BINS`
+    /// integers and `exposure_buffer` a storage buffer holding an initial
+    /// `ExposureData`. Uniform buffers must be host visible and coherent, one
+    /// per swapchain image.
+    ///
+    /// The HDR image must be set using `set_hdr_texture` and the pipelines
+    /// using `set_pipelines` before recording dispatches.
+    pub fn new(
+        device: &Device,
+        parameters: ExposureParameters,
+        histogram_buffer: Buffer,
+        exposure_buffer: Buffer,
+        uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        let image_count = uniform_buffers.len();
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let buffer_info = |buffer: &Buffer, size| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size as _)
+                .build()]
+        };
+        let histogram_infos = buffer_info(&histogram_buffer, size_of::<u32>() * HISTOGRAM_BINS);
+        let exposure_infos = buffer_info(&exposure_buffer, size_of::<ExposureData>());
+        for (set, uniform_buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let uniforms_infos = buffer_info(uniform_buffer, size_of::<ExposureUniforms>());
+            let write = |binding, descriptor_type| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(descriptor_type)
+            };
+            let descriptor_writes = [
+                write(HISTOGRAM_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&histogram_infos)
+                    .build(),
+                write(EXPOSURE_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&exposure_infos)
+                    .build(),
+                write(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&uniforms_infos)
+                    .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        AutoExposure {
+            parameters,
+            hdr_image: vk::Image::null(),
+            extent: vk::Extent2D::default(),
+            histogram_buffer,
+            exposure_buffer,
+            uniform_buffers,
+            layout,
+            pool,
+            sets,
+            histogram_pipeline: vk::Pipeline::null(),
+            average_pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        };
+        let bindings = [
+            binding(HDR_IMAGE_BINDING, vk::DescriptorType::STORAGE_IMAGE),
+            binding(HISTOGRAM_BINDING, vk::DescriptorType::STORAGE_BUFFER),
+            binding(EXPOSURE_BINDING, vk::DescriptorType::STORAGE_BUFFER),
+            binding(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: image_count * 2,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl AutoExposure {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the buffer containing the `ExposureData` of the last frame.
+    pub fn exposure_buffer(&self) -> Buffer {
+        self.exposure_buffer
+    }
+
+    /// Set the HDR image the luminance is computed from.
+    ///
+    /// It must have been created with the `STORAGE` usage and must be
+    /// in the `GENERAL` layout at the end of the main render pass.
+    pub fn set_hdr_texture(&mut self, device: &Device, texture: &Texture, extent: vk::Extent2D) {
+        self.hdr_image = texture.image;
+        self.extent = extent;
+
+        let image_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(texture.view)
+            .build()];
+        let descriptor_writes = self
+            .sets
+            .iter()
+            .map(|set| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(HDR_IMAGE_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&image_infos)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    /// Set the compute pipelines building the histogram and averaging it.
+    ///
+    /// Both use `pipeline_layout`. The exposure takes ownership of the
+    /// pipelines and their layout.
+    pub fn parameters(&self) -> ExposureParameters {
+        self.parameters
+    }
+
+    /// Set the parameters, uploaded by the next `update`.
+    pub fn set_parameters(&mut self, parameters: ExposureParameters) {
+        self.parameters = parameters;
+    }
+
+    pub fn set_pipelines(
+        &mut self,
+        histogram_pipeline: vk::Pipeline,
+        average_pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        self.histogram_pipeline = histogram_pipeline;
+        self.average_pipeline = average_pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index`.
+    ///
+    /// `time_delta` is the time in seconds since the last frame.
+    pub fn update(&self, device: &Device, image_index: usize, time_delta: f32) {
+        let parameters = self.parameters;
+        let uniforms = ExposureUniforms {
+            min_log_luminance: parameters.min_log_luminance,
+            log_luminance_range: parameters.max_log_luminance - parameters.min_log_luminance,
+            time_delta,
+            adaptation_speed: parameters.adaptation_speed,
+            compensation: parameters.compensation,
+        };
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+    }
+
+    /// Record the exposure passes of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// It must be recorded after the main render pass, outside of any render
+    /// pass. The HDR image is then readable by fragment shaders too.
+    pub fn cmd_dispatch(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let mut hdr_image = TrackedImage::new(
+            self.hdr_image,
+            vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ImageState {
+                layout: vk::ImageLayout::GENERAL,
+                stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            },
+        );
+        hdr_image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        let buffer_barrier = |buffer: &Buffer| {
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(buffer.buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()
+        };
+        let compute_barrier = |buffers: &[vk::BufferMemoryBarrier]| unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                buffers,
+                &[],
+            )
+        };
+
+        unsafe {
+            // The previous frame might still be averaging the histogram
+            compute_barrier(&[
+                buffer_barrier(&self.histogram_buffer),
+                buffer_barrier(&self.exposure_buffer),
+            ]);
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &self.sets[image_index..=image_index],
+                &[],
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.histogram_pipeline,
+            );
+            let group_count = |size: u32| match size % HISTOGRAM_GROUP_SIZE {
+                0 => size / HISTOGRAM_GROUP_SIZE,
+                _ => size / HISTOGRAM_GROUP_SIZE + 1,
+            };
+            device.cmd_dispatch(
+                command_buffer,
+                group_count(self.extent.width),
+                group_count(self.extent.height),
+                1,
+            );
+
+            compute_barrier(&[buffer_barrier(&self.histogram_buffer)]);
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.average_pipeline,
+            );
+            device.cmd_dispatch(command_buffer, 1, 1, 1);
+
+            // The tonemapper reads the exposure
+            let exposure_barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(self.exposure_buffer.buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[exposure_barrier],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.histogram_pipeline);
+            lifetime::destroy_pipeline(device, self.average_pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        self.histogram_buffer.destroy(device);
+        self.exposure_buffer.destroy(device);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::std140::std140_struct;
+
+/// Distance fog modes.
+///
+/// Must match the `FOG_MODE_*` constants in `frame.glsl`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FogMode {
+    None = 0,
+    /// Fog increasing linearly between `start` and `end`.
+    Linear = 1,
+    /// Fog increasing exponentially with the distance, depending on `density`.
+    Exponential = 2,
+}
+
+impl FogMode {
+    /// Get the mode from its name, as used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(FogMode::None),
+            "linear" => Some(FogMode::Linear),
+            "exponential" | "exp" => Some(FogMode::Exponential),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters of the fog applied after lighting.
+///
+/// The distance fog is combined with a height fog whose density decreases
+/// exponentially with the altitude. The height fog is disabled when
+/// `height_density` is 0.
+#[derive(Clone, Copy, Debug)]
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: [f32; 3],
+    pub start: f32,
+    pub end: f32,
+    pub density: f32,
+    pub height_density: f32,
+    pub height_falloff: f32,
+    /// Altitude at which the density of the height fog is `height_density`.
+    pub height_base: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            mode: FogMode::None,
+            color: [0.6, 0.65, 0.7],
+            start: 2.0,
+            end: 10.0,
+            density: 0.15,
+            height_density: 0.0,
+            height_falloff: 2.0,
+            height_base: -0.5,
+        }
+    }
+}
+
+std140_struct! {
+    /// Fog as laid out in the frame uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct FogUniform {
+        color: [f32; 4],
+        mode: u32,
+        start: f32,
+        end: f32,
+        density: f32,
+        height_density: f32,
+        height_falloff: f32,
+        height_base: f32,
+        _padding: f32,
+    }
+}
+
+impl From<Fog> for FogUniform {
+    fn from(fog: Fog) -> Self {
+        FogUniform {
+            color: [fog.color[0], fog.color[1], fog.color[2], 1.0],
+            mode: fog.mode as _,
+            start: fog.start,
+            end: fog.end,
+            density: fog.density,
+            height_density: fog.height_density,
+            height_falloff: fog.height_falloff,
+            height_base: fog.height_base,
+            _padding: 0.0,
+        }
+    }
+}
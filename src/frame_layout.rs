@@ -0,0 +1,135 @@
+use crate::{command_encoder::CommandEncoder, gpu_device::Device, texture::Texture};
+use ash::{version::DeviceV1_0, vk};
+
+/// Index of the descriptor set of the frame uniforms.
+pub const FRAME_SET: u32 = 0;
+
+/// Index of the descriptor set of a pass without material or object resources.
+pub const PASS_SET: u32 = 1;
+
+/// Index of the descriptor set of the resources of the object drawn, like its
+/// meshlets or the bindless textures.
+pub const OBJECT_SET: u32 = 2;
+
+/// Binding of the texture of the model in the frame set.
+pub const MODEL_TEXTURE_BINDING: u32 = 1;
+
+/// Layout of the descriptor sets and push constants shared by the pipelines.
+///
+/// The pipelines lay out their sets the same way: the frame at `FRAME_SET`,
+/// the material at `MATERIAL_SET` and the object at `OBJECT_SET`. Passes
+/// without material or object resources put their own set at `PASS_SET`.
+///
+/// The pipeline layouts created from the frame layout share the layout of the
+/// frame set and the same push constant ranges, so they are all compatible for
+/// the frame set. It is bound once at the start of each pass with `cmd_bind`
+/// and stays bound when the pipeline changes, so the draws only bind their own
+/// sets.
+pub struct FrameLayout {
+    set_layout: vk::DescriptorSetLayout,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl FrameLayout {
+    /// Create the frame layout from the layout of the frame set.
+    ///
+    /// The frame layout takes ownership of `set_layout`. `push_constant_ranges`
+    /// must contain the ranges of all the pipelines since they are shared.
+    pub fn new(
+        device: &Device,
+        set_layout: vk::DescriptorSetLayout,
+        push_constant_ranges: Vec<vk::PushConstantRange>,
+    ) -> Self {
+        let pipeline_layout = create_pipeline_layout(device, &[set_layout], &push_constant_ranges);
+        FrameLayout {
+            set_layout,
+            push_constant_ranges,
+            pipeline_layout,
+        }
+    }
+}
+
+impl FrameLayout {
+    /// Get the layout of the frame set.
+    pub fn set_layout(&self) -> vk::DescriptorSetLayout {
+        self.set_layout
+    }
+
+    /// Create the layout of a pipeline using `set_layouts` after the frame set.
+    ///
+    /// The first one is bound at `MATERIAL_SET` or `PASS_SET` and the second
+    /// one at `OBJECT_SET`. The caller owns the returned layout.
+    ///
+    /// # Panics
+    ///
+    /// Panic if there are more sets than the convention allows.
+    pub fn create_pipeline_layout(
+        &self,
+        device: &Device,
+        set_layouts: &[vk::DescriptorSetLayout],
+    ) -> vk::PipelineLayout {
+        assert!(
+            set_layouts.len() <= OBJECT_SET as usize,
+            "Pipelines have at most a material and an object set after the frame set."
+        );
+        let layouts = [self.set_layout]
+            .iter()
+            .chain(set_layouts)
+            .copied()
+            .collect::<Vec<_>>();
+        create_pipeline_layout(device, &layouts, &self.push_constant_ranges)
+    }
+
+    /// Bind `frame_set` with `encoder` for the draws of the pass recorded next.
+    ///
+    /// It stays bound until a pipeline layout not created by the frame layout is used.
+    pub fn cmd_bind(&self, encoder: &mut CommandEncoder, frame_set: vk::DescriptorSet) {
+        encoder.bind_descriptor_sets(self.pipeline_layout, FRAME_SET, &[frame_set], &[]);
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+/// Write `texture` as the texture of the model in the frame `sets`.
+///
+/// It is used to swap the texture of the model when it is reloaded, so the
+/// sets must not be in use.
+pub fn write_model_texture(device: &Device, sets: &[vk::DescriptorSet], texture: Texture) {
+    let image_infos = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(texture.view)
+        .sampler(texture.sampler.unwrap())
+        .build()];
+    let descriptor_writes = sets
+        .iter()
+        .map(|set| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(MODEL_TEXTURE_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+}
+
+fn create_pipeline_layout(
+    device: &Device,
+    set_layouts: &[vk::DescriptorSetLayout],
+    push_constant_ranges: &[vk::PushConstantRange],
+) -> vk::PipelineLayout {
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges)
+        .build();
+
+    unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+}
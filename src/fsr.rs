@@ -0,0 +1,391 @@
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    exposure::HDR_FORMAT,
+    gpu_device::Device,
+    lifetime,
+    texture::Texture,
+    tracked_image::{ImageState, TrackedImage},
+};
+use ash::{version::DeviceV1_0, vk};
+
+/// Format of the upscaled images.
+pub const FSR_FORMAT: vk::Format = HDR_FORMAT;
+
+/// Width and height of the tile of pixels written by each work group.
+///
+/// Must match the local size in `fsr.glsl`.
+const FSR_GROUP_SIZE: u32 = 8;
+
+/// Sharpness of RCAS, in stops below the maximum.
+const SHARPNESS_STOPS: f32 = 0.2;
+
+const SOURCE_BINDING: u32 = 0;
+const DESTINATION_BINDING: u32 = 1;
+
+/// Quality modes of FSR 1, each rendering the scene at a fraction of the output size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsrQuality {
+    UltraQuality,
+    Quality,
+    Balanced,
+    Performance,
+}
+
+impl FsrQuality {
+    /// Get the mode from its name, as used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ultra-quality" | "ultra" => Some(FsrQuality::UltraQuality),
+            "quality" => Some(FsrQuality::Quality),
+            "balanced" => Some(FsrQuality::Balanced),
+            "performance" => Some(FsrQuality::Performance),
+            _ => None,
+        }
+    }
+
+    /// Get the fraction of the output size the scene is rendered at.
+    pub fn render_scale(self) -> f32 {
+        match self {
+            FsrQuality::UltraQuality => 1.0 / 1.3,
+            FsrQuality::Quality => 1.0 / 1.5,
+            FsrQuality::Balanced => 1.0 / 1.7,
+            FsrQuality::Performance => 1.0 / 2.0,
+        }
+    }
+}
+
+/// AMD FidelityFX Super Resolution 1.0 upscaling of the HDR image.
+///
+/// Two compute passes run between the post processing and the tonemapper.
+/// EASU upscales the image rendered at the render scale to the size of the
+/// swapchain with a kernel following its edges, then RCAS sharpens the
+/// result. The passes work on colors mapped between 0 and 1 with a reversible
+/// tonemap, and the output is an HDR image again, which the tonemapper reads
+/// in place of the scaled image.
+pub struct Fsr {
+    sharpness: f32,
+    output_extent: vk::Extent2D,
+    /// Output of EASU, read by RCAS.
+    upscaled: Option<Texture>,
+    output: Option<Texture>,
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    easu_set: vk::DescriptorSet,
+    rcas_set: vk::DescriptorSet,
+    easu_pipeline: vk::Pipeline,
+    rcas_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Fsr {
+    /// Create the descriptor sets of the passes.
+    ///
+    /// The targets must be set using `set_targets` and the pipelines using
+    /// `set_pipelines` before recording the passes.
+    pub fn new(device: &Device) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let sets = {
+            let layouts = [layout, layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        // The texels are fetched, the tonemapper samples the output bilinearly
+        let sampler = {
+            let sampler_info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build();
+            unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+        };
+
+        Fsr {
+            sharpness: (-SHARPNESS_STOPS).exp2(),
+            output_extent: vk::Extent2D::default(),
+            upscaled: None,
+            output: None,
+            sampler,
+            layout,
+            pool,
+            easu_set: sets[0],
+            rcas_set: sets[1],
+            easu_pipeline: vk::Pipeline::null(),
+            rcas_pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        };
+        let bindings = [
+            binding(SOURCE_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            binding(DESTINATION_BINDING, vk::DescriptorType::STORAGE_IMAGE),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 2,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 2,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(2)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Fsr {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the upscaled and sharpened image with the sampler the tonemapper
+    /// reads it with.
+    ///
+    /// It is in the `GENERAL` layout after the passes. It is only valid after
+    /// `set_targets` and is owned by the upscaler.
+    pub fn output(&self) -> Texture {
+        Texture {
+            sampler: Some(self.sampler),
+            ..self.output.unwrap()
+        }
+    }
+
+    /// Set the image upscaled into `output`, through `upscaled`.
+    ///
+    /// `input` must be readable by compute shaders in `input_layout` when the
+    /// passes are recorded. `upscaled` and `output` must be `FSR_FORMAT`
+    /// textures of `output_extent` with the `STORAGE` and `SAMPLED` usages.
+    /// The upscaler takes ownership of them.
+    pub fn set_targets(
+        &mut self,
+        device: &Device,
+        (input, input_layout): (Texture, vk::ImageLayout),
+        (upscaled, output): (Texture, Texture),
+        output_extent: vk::Extent2D,
+    ) {
+        self.output_extent = output_extent;
+
+        let write_set =
+            |set, (source, source_layout): (Texture, vk::ImageLayout), destination: Texture| {
+                let source_infos = [vk::DescriptorImageInfo::builder()
+                    .image_layout(source_layout)
+                    .image_view(source.view)
+                    .sampler(self.sampler)
+                    .build()];
+                let destination_infos = [vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::GENERAL)
+                    .image_view(destination.view)
+                    .build()];
+                let descriptor_writes = [
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(SOURCE_BINDING)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&source_infos)
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(DESTINATION_BINDING)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&destination_infos)
+                        .build(),
+                ];
+                unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+            };
+        write_set(self.easu_set, (input, input_layout), upscaled);
+        write_set(self.rcas_set, (upscaled, vk::ImageLayout::GENERAL), output);
+
+        self.upscaled = Some(upscaled);
+        self.output = Some(output);
+    }
+
+    /// Set the compute pipelines of EASU and RCAS.
+    ///
+    /// `pipeline_layout` must have the layout of the upscaler and a push
+    /// constant range of one float for the compute stage. The upscaler takes
+    /// ownership of the pipelines and their layout.
+    pub fn set_pipelines(
+        &mut self,
+        (easu_pipeline, rcas_pipeline): (vk::Pipeline, vk::Pipeline),
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        self.easu_pipeline = easu_pipeline;
+        self.rcas_pipeline = rcas_pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the upscaling and the sharpening of the input.
+    ///
+    /// It must be recorded after the post processing, outside of any render
+    /// pass. The output is then readable by fragment shaders in the `GENERAL` layout.
+    pub fn cmd_dispatch(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        // The previous frame might still read the images
+        let track = |texture: &Option<Texture>, stage, access| {
+            TrackedImage::new(
+                texture.unwrap().image,
+                range,
+                ImageState {
+                    layout: vk::ImageLayout::UNDEFINED,
+                    stage,
+                    access,
+                },
+            )
+        };
+        let mut upscaled = track(
+            &self.upscaled,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+        let mut output = track(
+            &self.output,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        // The input is the last attachment written before the upscaling
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::memory(
+                Access::color_attachment(),
+                Access::compute_read(),
+            )],
+        );
+        upscaled.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+        self.cmd_pass(device, command_buffer, self.easu_pipeline, self.easu_set);
+
+        upscaled.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+        output.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+        self.cmd_pass(device, command_buffer, self.rcas_pipeline, self.rcas_set);
+
+        output.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+    }
+
+    /// Record a dispatch of `pipeline` over the pixels of the output.
+    fn cmd_pass(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        set: vk::DescriptorSet,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &self.sharpness.to_ne_bytes(),
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                self.output_extent.width.div_ceil(FSR_GROUP_SIZE),
+                self.output_extent.height.div_ceil(FSR_GROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    /// Destroy the upscaled images.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_targets(&mut self, device: &Device) {
+        if let Some(mut upscaled) = self.upscaled.take() {
+            upscaled.destroy(device);
+        }
+        if let Some(mut output) = self.output.take() {
+            output.destroy(device);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_targets(device);
+        unsafe {
+            lifetime::destroy_pipeline(device, self.easu_pipeline);
+            lifetime::destroy_pipeline(device, self.rcas_pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_sampler(self.sampler, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
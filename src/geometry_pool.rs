@@ -0,0 +1,152 @@
+use crate::{buffer::Buffer, gpu_device::Device};
+use ash::vk;
+use std::mem::size_of;
+
+/// Block of a `GeometryPool` holding the vertices and indices of one mesh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GeometryBlock {
+    pub index: u32,
+    /// Index of the first vertex of the block, the vertex offset of its draws.
+    pub vertex_offset: i32,
+    /// Index of the first index of the block.
+    pub first_index: u32,
+}
+
+/// Vertex and index buffers sub-allocated in blocks of the same size.
+///
+/// Each block holds up to `block_vertices` vertices and `block_indices`
+/// indices, relative to the first vertex of the block. Since all the blocks
+/// have the same size, any freed block fits the next mesh and the buffers
+/// never fragment, at the cost of the space unused by smaller meshes.
+///
+/// The buffers are host visible so meshes are written without staging copies
+/// nor waiting for the queue. A freed block might still be drawn by the frames
+/// in flight, so it is only reused after `latency` calls to `begin_frame`.
+pub struct GeometryPool {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    vertex_size: u32,
+    block_vertices: u32,
+    block_indices: u32,
+    free_blocks: Vec<u32>,
+    /// Freed blocks and the number of frames before they can be reused.
+    retired_blocks: Vec<(u32, usize)>,
+    latency: usize,
+}
+
+impl GeometryPool {
+    /// Create a pool of `block_count` blocks.
+    ///
+    /// `vertex_buffer` and `index_buffer` must be host visible and coherent
+    /// buffers of `GeometryPool::vertex_buffer_size` and
+    /// `GeometryPool::index_buffer_size` bytes, with the `VERTEX_BUFFER` and
+    /// `INDEX_BUFFER` usages. `latency` is the number of frames a freed block
+    /// can still be drawn, usually the number of swapchain images.
+    pub fn new(
+        (vertex_buffer, index_buffer): (Buffer, Buffer),
+        vertex_size: u32,
+        (block_count, block_vertices, block_indices): (u32, u32, u32),
+        latency: usize,
+    ) -> Self {
+        GeometryPool {
+            vertex_buffer,
+            index_buffer,
+            vertex_size,
+            block_vertices,
+            block_indices,
+            // Blocks are allocated from the start of the buffers
+            free_blocks: (0..block_count).rev().collect(),
+            retired_blocks: Vec::new(),
+            latency,
+        }
+    }
+
+    pub fn vertex_buffer_size(
+        vertex_size: u32,
+        (block_count, block_vertices, _): (u32, u32, u32),
+    ) -> vk::DeviceSize {
+        (vertex_size * block_vertices * block_count) as _
+    }
+
+    pub fn index_buffer_size((block_count, _, block_indices): (u32, u32, u32)) -> vk::DeviceSize {
+        (size_of::<u32>() as u32 * block_indices * block_count) as _
+    }
+}
+
+impl GeometryPool {
+    pub fn vertex_buffer(&self) -> Buffer {
+        self.vertex_buffer
+    }
+
+    pub fn index_buffer(&self) -> Buffer {
+        self.index_buffer
+    }
+
+    /// Get the number of blocks available to `allocate`.
+    pub fn free_block_count(&self) -> usize {
+        self.free_blocks.len()
+    }
+
+    /// Take a free block, or get `None` if the pool is full.
+    pub fn allocate(&mut self) -> Option<GeometryBlock> {
+        self.free_blocks.pop().map(|index| GeometryBlock {
+            index,
+            vertex_offset: (index * self.block_vertices) as _,
+            first_index: index * self.block_indices,
+        })
+    }
+
+    /// Write `vertices` and `indices` in `block`.
+    ///
+    /// The indices are relative to the first vertex of the block. The block
+    /// must not be drawn by a frame in flight.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the mesh is larger than a block.
+    pub fn write<V: Copy>(
+        &self,
+        device: &Device,
+        block: GeometryBlock,
+        vertices: &[V],
+        indices: &[u32],
+    ) {
+        assert_eq!(size_of::<V>() as u32, self.vertex_size);
+        assert!(
+            vertices.len() as u32 <= self.block_vertices
+                && indices.len() as u32 <= self.block_indices,
+            "Mesh of {} vertices and {} indices larger than a block",
+            vertices.len(),
+            indices.len()
+        );
+        let vertex_offset = block.vertex_offset as vk::DeviceSize * self.vertex_size as u64;
+        let index_offset = block.first_index as vk::DeviceSize * size_of::<u32>() as u64;
+        self.vertex_buffer.write(device, vertex_offset, vertices);
+        self.index_buffer.write(device, index_offset, indices);
+    }
+
+    /// Give `block` back to the pool once the frames in flight are done with it.
+    pub fn free(&mut self, block: GeometryBlock) {
+        self.retired_blocks.push((block.index, self.latency));
+    }
+
+    /// Make the blocks freed `latency` frames ago available again.
+    ///
+    /// It must be called once per frame, after waiting for its fence.
+    pub fn begin_frame(&mut self) {
+        let free_blocks = &mut self.free_blocks;
+        self.retired_blocks.retain_mut(|(index, frames)| {
+            if *frames <= 1 {
+                free_blocks.push(*index);
+                return false;
+            }
+            *frames -= 1;
+            true
+        });
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.vertex_buffer.destroy(device);
+        self.index_buffer.destroy(device);
+    }
+}
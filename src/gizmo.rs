@@ -0,0 +1,379 @@
+use crate::{
+    debug_draw::DebugDraw,
+    math::{Aabb, Plane, Ray, Transform},
+};
+use cgmath::{prelude::*, Point3, Quaternion, Rad, Vector3};
+use winit::VirtualKeyCode;
+
+/// Size of the gizmo relative to its distance to the camera, so it keeps the
+/// same size on screen.
+const SCREEN_SIZE: f32 = 0.2;
+
+/// Distance from a handle under which it is grabbed, relative to the size of the gizmo.
+const GRAB_DISTANCE: f32 = 0.08;
+
+/// Extent of the plane handles along their two axes, relative to the size of the gizmo.
+const PLANE_HANDLE_START: f32 = 0.25;
+const PLANE_HANDLE_END: f32 = 0.45;
+
+const TRANSLATION_SNAP: f32 = 0.1;
+const ROTATION_SNAP: Rad<f32> = Rad(std::f32::consts::PI / 12.0);
+const SCALE_SNAP: f32 = 0.1;
+const MIN_SCALE: f32 = 0.01;
+
+const AXIS_COLORS: [[f32; 4]; 3] = [
+    [0.9, 0.2, 0.2, 1.0],
+    [0.2, 0.9, 0.2, 1.0],
+    [0.2, 0.3, 0.9, 1.0],
+];
+const ACTIVE_COLOR: [f32; 4] = [1.0, 0.9, 0.1, 1.0];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// Part of the gizmo that can be grabbed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Handle {
+    /// Arrow along an axis, or ring around it when rotating.
+    Axis(usize),
+    /// Square in the plane orthogonal to an axis.
+    Plane(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Drag {
+    handle: Handle,
+    start_transform: Transform,
+    /// Point grabbed on the handle.
+    start_point: Point3<f32>,
+    /// Size of the gizmo when grabbed, kept while dragging.
+    size: f32,
+}
+
+/// 3D manipulator editing the transform of the selected object with the mouse.
+///
+/// Translation and rotation happen along the world axes, scaling along the
+/// axes of the object. Handles are drawn with the debug draw, the one under
+/// the cursor being highlighted.
+///
+/// The mode is selected with the T (translate), R (rotate) and E (scale) keys.
+/// Snapping is toggled with the X key.
+#[derive(Clone, Copy, Debug)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub snapping: bool,
+    hovered: Option<Handle>,
+    drag: Option<Drag>,
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Gizmo {
+            mode: GizmoMode::Translate,
+            snapping: false,
+            hovered: None,
+            drag: None,
+        }
+    }
+}
+
+impl Gizmo {
+    /// Change the mode or the snapping with `key`.
+    ///
+    /// # Returns
+    ///
+    /// Whether the key was handled.
+    pub fn handle_key(&mut self, key: VirtualKeyCode) -> bool {
+        match key {
+            VirtualKeyCode::T => self.mode = GizmoMode::Translate,
+            VirtualKeyCode::R => self.mode = GizmoMode::Rotate,
+            VirtualKeyCode::E => self.mode = GizmoMode::Scale,
+            VirtualKeyCode::X => {
+                self.snapping = !self.snapping;
+                log::debug!("Gizmo snapping: {}", self.snapping);
+            }
+            _ => return false,
+        }
+        self.drag = None;
+        true
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Highlight the handle under `ray`.
+    pub fn hover(&mut self, ray: &Ray, transform: &Transform, camera_position: Point3<f32>) {
+        let size = gizmo_size(transform, camera_position);
+        self.hovered = self
+            .pick_handle(ray, transform, size)
+            .map(|(handle, _)| handle);
+    }
+
+    /// Grab the handle under `ray` if any.
+    ///
+    /// # Returns
+    ///
+    /// Whether a handle was grabbed.
+    pub fn begin_drag(
+        &mut self,
+        ray: &Ray,
+        transform: &Transform,
+        camera_position: Point3<f32>,
+    ) -> bool {
+        let size = gizmo_size(transform, camera_position);
+        self.drag = self
+            .pick_handle(ray, transform, size)
+            .map(|(handle, start_point)| Drag {
+                handle,
+                start_transform: *transform,
+                start_point,
+                size,
+            });
+        self.drag.is_some()
+    }
+
+    pub fn end_drag(&mut self) {
+        self.drag = None;
+    }
+
+    /// Move the grabbed handle to follow `ray` and update `transform`.
+    pub fn drag(&self, ray: &Ray, transform: &mut Transform) {
+        let drag = match self.drag {
+            Some(drag) => drag,
+            None => return,
+        };
+        let start = drag.start_transform;
+        let center = Point3::from_vec(start.translation);
+        let axes = self.axes(&start);
+
+        match (self.mode, drag.handle) {
+            (GizmoMode::Translate, Handle::Axis(i)) => {
+                let start_t = (drag.start_point - center).dot(axes[i]);
+                let (_, t) = closest_to_line(ray, center, axes[i]);
+                let delta = self.snap(t - start_t, TRANSLATION_SNAP);
+                transform.translation = start.translation + axes[i] * delta;
+            }
+            (GizmoMode::Translate, Handle::Plane(i)) => {
+                if let Some(point) = intersect_plane(ray, center, axes[i]) {
+                    let delta = point - drag.start_point;
+                    transform.translation = start.translation
+                        + Vector3::new(
+                            self.snap(delta.x, TRANSLATION_SNAP),
+                            self.snap(delta.y, TRANSLATION_SNAP),
+                            self.snap(delta.z, TRANSLATION_SNAP),
+                        );
+                }
+            }
+            (GizmoMode::Rotate, Handle::Axis(i)) | (GizmoMode::Rotate, Handle::Plane(i)) => {
+                if let Some(point) = intersect_plane(ray, center, axes[i]) {
+                    let from = drag.start_point - center;
+                    let to = point - center;
+                    let angle = Rad(axes[i].dot(from.cross(to)).atan2(from.dot(to)));
+                    let angle = Rad(self.snap(angle.0, ROTATION_SNAP.0));
+                    transform.rotation =
+                        Quaternion::from_axis_angle(axes[i], angle) * start.rotation;
+                }
+            }
+            (GizmoMode::Scale, Handle::Axis(i)) => {
+                let start_t = (drag.start_point - center).dot(axes[i]);
+                let (_, t) = closest_to_line(ray, center, axes[i]);
+                let factor = t / start_t;
+                transform.scale[i] = self
+                    .snap(start.scale[i] * factor, SCALE_SNAP)
+                    .max(MIN_SCALE);
+            }
+            (GizmoMode::Scale, Handle::Plane(i)) => {
+                if let Some(point) = intersect_plane(ray, center, axes[i]) {
+                    let factor =
+                        (point - center).magnitude() / (drag.start_point - center).magnitude();
+                    for j in other_axes(i).iter() {
+                        transform.scale[*j] = self
+                            .snap(start.scale[*j] * factor, SCALE_SNAP)
+                            .max(MIN_SCALE);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw the handles of the gizmo around `transform`.
+    pub fn draw(
+        &self,
+        debug_draw: &mut DebugDraw,
+        transform: &Transform,
+        camera_position: Point3<f32>,
+    ) {
+        let size = match self.drag {
+            Some(drag) => drag.size,
+            None => gizmo_size(transform, camera_position),
+        };
+        let center = Point3::from_vec(transform.translation);
+        let axes = self.axes(transform);
+        let active = self.drag.map(|drag| drag.handle).or(self.hovered);
+        let color = |handle: Handle, axis: usize| {
+            if active == Some(handle) {
+                ACTIVE_COLOR
+            } else {
+                AXIS_COLORS[axis]
+            }
+        };
+
+        if self.mode == GizmoMode::Rotate {
+            for (i, axis) in axes.iter().enumerate() {
+                debug_draw.circle(center, *axis, size, color(Handle::Axis(i), i));
+            }
+            return;
+        }
+
+        for (i, axis) in axes.iter().enumerate() {
+            let handle_color = color(Handle::Axis(i), i);
+            let tip = center + axis * size;
+            debug_draw.line(center, tip, handle_color);
+            if self.mode == GizmoMode::Translate {
+                let [j, k] = other_axes(i);
+                for side in &[axes[j], -axes[j], axes[k], -axes[k]] {
+                    let base = tip - axis * (size * 0.15) + side * (size * 0.05);
+                    debug_draw.line(tip, base, handle_color);
+                }
+            } else {
+                let half = size * 0.04;
+                let bounds = Aabb {
+                    min: tip - Vector3::new(half, half, half),
+                    max: tip + Vector3::new(half, half, half),
+                };
+                debug_draw.aabb(&bounds, handle_color);
+            }
+        }
+
+        for i in 0..3 {
+            let [j, k] = other_axes(i);
+            let corner = |a: f32, b: f32| center + axes[j] * (a * size) + axes[k] * (b * size);
+            let (start, end) = (PLANE_HANDLE_START, PLANE_HANDLE_END);
+            let corners = [
+                corner(start, start),
+                corner(end, start),
+                corner(end, end),
+                corner(start, end),
+            ];
+            let plane_color = color(Handle::Plane(i), i);
+            for c in 0..4 {
+                debug_draw.line(corners[c], corners[(c + 1) % 4], plane_color);
+            }
+        }
+    }
+
+    /// Get the axes along which the gizmo operates.
+    fn axes(&self, transform: &Transform) -> [Vector3<f32>; 3] {
+        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        if self.mode == GizmoMode::Scale {
+            let rotation = transform.rotation;
+            [
+                rotation.rotate_vector(axes[0]),
+                rotation.rotate_vector(axes[1]),
+                rotation.rotate_vector(axes[2]),
+            ]
+        } else {
+            axes
+        }
+    }
+
+    /// Find the handle under `ray` the closest to its origin.
+    ///
+    /// # Returns
+    ///
+    /// The handle and the point grabbed on it.
+    fn pick_handle(
+        &self,
+        ray: &Ray,
+        transform: &Transform,
+        size: f32,
+    ) -> Option<(Handle, Point3<f32>)> {
+        let center = Point3::from_vec(transform.translation);
+        let axes = self.axes(transform);
+        let grab_distance = GRAB_DISTANCE * size;
+
+        let mut hits = Vec::new();
+        for (i, axis) in axes.iter().enumerate() {
+            if self.mode == GizmoMode::Rotate {
+                if let Some(point) = intersect_plane(ray, center, *axis) {
+                    if ((point - center).magnitude() - size).abs() < grab_distance {
+                        hits.push((Handle::Axis(i), point));
+                    }
+                }
+                continue;
+            }
+
+            let (ray_point, t) = closest_to_line(ray, center, *axis);
+            let axis_point = center + axis * t;
+            if t >= 0.0 && t <= size && ray_point.distance(axis_point) < grab_distance {
+                hits.push((Handle::Axis(i), axis_point));
+            }
+
+            if let Some(point) = intersect_plane(ray, center, *axis) {
+                let [j, k] = other_axes(i);
+                let a = (point - center).dot(axes[j]) / size;
+                let b = (point - center).dot(axes[k]) / size;
+                let range = PLANE_HANDLE_START..=PLANE_HANDLE_END;
+                if range.contains(&a) && range.contains(&b) {
+                    hits.push((Handle::Plane(i), point));
+                }
+            }
+        }
+
+        hits.into_iter().min_by(|(_, a), (_, b)| {
+            let distance_a = a.distance2(ray.origin);
+            let distance_b = b.distance2(ray.origin);
+            distance_a.partial_cmp(&distance_b).unwrap()
+        })
+    }
+
+    fn snap(&self, value: f32, step: f32) -> f32 {
+        if self.snapping {
+            (value / step).round() * step
+        } else {
+            value
+        }
+    }
+}
+
+fn gizmo_size(transform: &Transform, camera_position: Point3<f32>) -> f32 {
+    Point3::from_vec(transform.translation).distance(camera_position) * SCREEN_SIZE
+}
+
+fn other_axes(axis: usize) -> [usize; 2] {
+    [(axis + 1) % 3, (axis + 2) % 3]
+}
+
+/// Find the points of `ray` and of the line going through `origin` along
+/// `direction` that are the closest to each other.
+///
+/// # Returns
+///
+/// The point on the ray and the position of the other one along the line.
+fn closest_to_line(ray: &Ray, origin: Point3<f32>, direction: Vector3<f32>) -> (Point3<f32>, f32) {
+    let w = ray.origin - origin;
+    let a = ray.direction.dot(ray.direction);
+    let b = ray.direction.dot(direction);
+    let c = direction.dot(direction);
+    let d = ray.direction.dot(w);
+    let e = direction.dot(w);
+    let denominator = a * c - b * b;
+    if denominator.abs() < f32::EPSILON {
+        // Parallel lines
+        return (ray.origin, e / c);
+    }
+    let s = ((b * e - c * d) / denominator).max(0.0);
+    let t = (a * e - b * d) / denominator;
+    (ray.at(s), t)
+}
+
+/// Intersect `ray` with the plane going through `point` with `normal`.
+fn intersect_plane(ray: &Ray, point: Point3<f32>, normal: Vector3<f32>) -> Option<Point3<f32>> {
+    let distance = Plane::from_point_normal(point, normal).intersect_ray(ray)?;
+    Some(ray.at(distance))
+}
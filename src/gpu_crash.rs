@@ -0,0 +1,370 @@
+use crate::{buffer::Buffer, context::*, gpu_device::Device, submit_batch::SubmitStats};
+use ash::{
+    prelude::VkResult,
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Instance,
+};
+use std::{
+    collections::VecDeque,
+    ffi::CStr,
+    fmt::Write as _,
+    fs,
+    mem::{self, size_of},
+    path::PathBuf,
+    ptr,
+};
+
+/// Number of submits kept in the breadcrumbs.
+const BREADCRUMB_COUNT: usize = 16;
+
+/// Bits of the checkpoint markers holding the pass, the swapchain image being above.
+const PASS_BITS: u32 = 16;
+
+/// Extension writing the markers of the passes as the GPU executes them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarkerExtension {
+    /// `VK_NV_device_diagnostic_checkpoints`, whose last markers reached by
+    /// each pipeline stage are queried from the queue.
+    Checkpoints,
+    /// `VK_AMD_buffer_marker`, writing the markers to a host visible buffer.
+    BufferMarkers,
+}
+
+impl MarkerExtension {
+    /// Get the device extensions required to write the markers.
+    pub fn required_device_extensions(self) -> [&'static CStr; 1] {
+        match self {
+            MarkerExtension::Checkpoints => [vk::NvDeviceDiagnosticCheckpointsFn::name()],
+            MarkerExtension::BufferMarkers => [vk::AmdBufferMarkerFn::name()],
+        }
+    }
+
+    /// Get the extension `device` supports to write markers, preferring the checkpoints.
+    pub fn find_supported(instance: &Instance, device: vk::PhysicalDevice) -> Option<Self> {
+        [MarkerExtension::Checkpoints, MarkerExtension::BufferMarkers]
+            .iter()
+            .copied()
+            .find(|extension| {
+                extension
+                    .required_device_extensions()
+                    .iter()
+                    .all(|ext| is_device_extension_supported(instance, device, ext))
+            })
+    }
+}
+
+/// Passes of a frame, marked in its command buffer before they start for the
+/// crash dump and the debug labels.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramePass {
+    /// Start of the command buffer, so no marker is left from a previous frame.
+    Begin = 1,
+    Shadows,
+    Reflection,
+    StereoEyes,
+    Velocity,
+    Culling,
+    Particles,
+    MainPass,
+    /// Shadows traced from the depth buffer into the HDR image.
+    RayTracedShadows,
+    /// Copy of the occlusion queries, reduction of the depth and read back
+    /// of the virtual texture feedback.
+    Readback,
+    Exposure,
+    Bloom,
+    Ssr,
+    LightShafts,
+    MotionBlur,
+    Fsr,
+    Tonemap,
+    /// End of the command buffer, once all the passes were executed.
+    End,
+}
+
+const FRAME_PASSES: [FramePass; 18] = [
+    FramePass::Begin,
+    FramePass::Shadows,
+    FramePass::Reflection,
+    FramePass::StereoEyes,
+    FramePass::Velocity,
+    FramePass::Culling,
+    FramePass::Particles,
+    FramePass::MainPass,
+    FramePass::RayTracedShadows,
+    FramePass::Readback,
+    FramePass::Exposure,
+    FramePass::Bloom,
+    FramePass::Ssr,
+    FramePass::LightShafts,
+    FramePass::MotionBlur,
+    FramePass::Fsr,
+    FramePass::Tonemap,
+    FramePass::End,
+];
+
+impl FramePass {
+    pub fn name(self) -> &'static str {
+        match self {
+            FramePass::Begin => "Begin",
+            FramePass::Shadows => "Shadows",
+            FramePass::Reflection => "Reflection",
+            FramePass::StereoEyes => "Stereo eyes",
+            FramePass::Velocity => "Velocity",
+            FramePass::Culling => "Culling",
+            FramePass::Particles => "Particles",
+            FramePass::MainPass => "Main pass",
+            FramePass::RayTracedShadows => "Ray-traced shadows",
+            FramePass::Readback => "Readback",
+            FramePass::Exposure => "Exposure",
+            FramePass::Bloom => "Bloom",
+            FramePass::Ssr => "Screen-space reflections",
+            FramePass::LightShafts => "Light shafts",
+            FramePass::MotionBlur => "Motion blur",
+            FramePass::Fsr => "FSR",
+            FramePass::Tonemap => "Tonemap",
+            FramePass::End => "End",
+        }
+    }
+
+    fn from_marker(marker: u32) -> Option<Self> {
+        FRAME_PASSES
+            .iter()
+            .copied()
+            .find(|pass| *pass as u32 == marker)
+    }
+}
+
+/// Submit of a frame, kept to tell what the GPU was working on when the device was lost.
+#[derive(Clone, Copy, Debug)]
+struct SubmitBreadcrumb {
+    /// Number of submits before this one.
+    submit: u64,
+    image_index: u32,
+    stats: SubmitStats,
+}
+
+enum MarkerWriter {
+    Checkpoints(vk::NvDeviceDiagnosticCheckpointsFn),
+    /// One buffer per swapchain image, holding the last marker reached at the
+    /// top of the pipe followed by the last one reached at the bottom.
+    BufferMarkers(vk::AmdBufferMarkerFn, Vec<Buffer>),
+    /// No extension is supported, only the breadcrumbs are written.
+    None,
+}
+
+/// Diagnostic written to a file when the device is lost.
+///
+/// Each pass of the command buffers is preceded by a marker, which the GPU
+/// records as it reaches it. When a Vulkan call returns `VK_ERROR_DEVICE_LOST`
+/// the last markers are read back and written with the last submits of the
+/// frames, so the pass the GPU crashed in is known. A pass which started but
+/// whose marker did not reach the bottom of the pipe was still executing.
+pub struct GpuCrashDump {
+    path: PathBuf,
+    markers: MarkerWriter,
+    breadcrumbs: VecDeque<SubmitBreadcrumb>,
+    submit_count: u64,
+}
+
+impl GpuCrashDump {
+    /// Create the markers written with `extension` if any, the report being
+    /// written to `path`.
+    ///
+    /// With `BufferMarkers`, `marker_buffers` must hold a host visible and
+    /// coherent buffer per swapchain image of `2 * size_of::<u32>()` bytes,
+    /// with the `TRANSFER_DST` usage. It is ignored otherwise. The extension
+    /// must be enabled on `device`.
+    pub fn new(
+        (instance, device): (&Instance, &Device),
+        extension: Option<MarkerExtension>,
+        marker_buffers: Vec<Buffer>,
+        path: PathBuf,
+    ) -> Self {
+        let load = |name: &CStr| unsafe {
+            mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        };
+        let markers = match extension {
+            Some(MarkerExtension::Checkpoints) => {
+                MarkerWriter::Checkpoints(vk::NvDeviceDiagnosticCheckpointsFn::load(load))
+            }
+            Some(MarkerExtension::BufferMarkers) => {
+                MarkerWriter::BufferMarkers(vk::AmdBufferMarkerFn::load(load), marker_buffers)
+            }
+            None => MarkerWriter::None,
+        };
+        log::info!(
+            "GPU crash dump written to {} if the device is lost.",
+            path.display()
+        );
+
+        GpuCrashDump {
+            path,
+            markers,
+            breadcrumbs: VecDeque::with_capacity(BREADCRUMB_COUNT),
+            submit_count: 0,
+        }
+    }
+
+    /// Get the size of the buffer of a swapchain image if the markers are written to buffers.
+    pub fn marker_buffer_size() -> vk::DeviceSize {
+        2 * size_of::<u32>() as vk::DeviceSize
+    }
+
+    /// Record the marker of `pass` in the command buffer of the swapchain image `image_index`.
+    pub fn cmd_mark(&self, command_buffer: vk::CommandBuffer, image_index: usize, pass: FramePass) {
+        match &self.markers {
+            MarkerWriter::Checkpoints(checkpoints_fn) => {
+                // The marker is an opaque pointer which is never dereferenced
+                let marker = (image_index as u32) << PASS_BITS | pass as u32;
+                unsafe {
+                    checkpoints_fn.cmd_set_checkpoint_nv(command_buffer, marker as usize as _);
+                }
+            }
+            MarkerWriter::BufferMarkers(buffer_marker_fn, buffers) => {
+                let buffer = buffers[image_index].buffer;
+                let stages = [
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                ];
+                for (slot, stage) in stages.iter().enumerate() {
+                    unsafe {
+                        buffer_marker_fn.cmd_write_buffer_marker_amd(
+                            command_buffer,
+                            *stage,
+                            buffer,
+                            (slot * size_of::<u32>()) as _,
+                            pass as u32,
+                        );
+                    }
+                }
+            }
+            MarkerWriter::None => {}
+        }
+    }
+
+    /// Keep the submit of the command buffer of `image_index` in the breadcrumbs.
+    pub fn add_submit(&mut self, image_index: u32, stats: SubmitStats) {
+        if self.breadcrumbs.len() == BREADCRUMB_COUNT {
+            self.breadcrumbs.pop_front();
+        }
+        self.breadcrumbs.push_back(SubmitBreadcrumb {
+            submit: self.submit_count,
+            image_index,
+            stats,
+        });
+        self.submit_count += 1;
+    }
+
+    /// Write the last markers reached on `queue` and the last submits to the file.
+    ///
+    /// It must be called once the device is lost, errors are logged.
+    pub fn write(&self, device: &Device, queue: vk::Queue) {
+        let report = self.report(device, queue);
+        match fs::write(&self.path, report) {
+            Ok(()) => log::error!("GPU crash dump written to {}.", self.path.display()),
+            Err(error) => log::error!(
+                "Failed to write the GPU crash dump to {}: {}.",
+                self.path.display(),
+                error
+            ),
+        }
+    }
+
+    fn report(&self, device: &Device, queue: vk::Queue) -> String {
+        let mut report = String::from("Device lost\n\n");
+        match &self.markers {
+            MarkerWriter::Checkpoints(checkpoints_fn) => {
+                report.push_str("Last checkpoints of the graphics queue:\n");
+                for checkpoint in query_checkpoints(checkpoints_fn, queue) {
+                    let marker = checkpoint.p_checkpoint_marker as usize as u32;
+                    let _ = writeln!(
+                        report,
+                        "  {:?}: {} of swapchain image {}",
+                        checkpoint.stage,
+                        pass_name(marker & ((1 << PASS_BITS) - 1)),
+                        marker >> PASS_BITS
+                    );
+                }
+            }
+            MarkerWriter::BufferMarkers(_, buffers) => {
+                report.push_str(
+                    "Last markers of each swapchain image (top of pipe, bottom of pipe):\n",
+                );
+                for (index, buffer) in buffers.iter().enumerate() {
+                    let _ = match read_markers(device, *buffer) {
+                        Ok([top, bottom]) => writeln!(
+                            report,
+                            "  Image {}: {}, {}",
+                            index,
+                            pass_name(top),
+                            pass_name(bottom)
+                        ),
+                        Err(error) => {
+                            writeln!(
+                                report,
+                                "  Image {}: failed to map the markers: {}",
+                                index, error
+                            )
+                        }
+                    };
+                }
+            }
+            MarkerWriter::None => report.push_str("Markers not supported by the device.\n"),
+        }
+
+        report.push_str("\nLast submits, oldest first:\n");
+        for breadcrumb in &self.breadcrumbs {
+            let _ = writeln!(
+                report,
+                "  Submit {}: swapchain image {}, {} command buffers, {} wait and {} signal semaphores",
+                breadcrumb.submit,
+                breadcrumb.image_index,
+                breadcrumb.stats.command_buffers,
+                breadcrumb.stats.wait_semaphores,
+                breadcrumb.stats.signal_semaphores
+            );
+        }
+        report
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        if let MarkerWriter::BufferMarkers(_, buffers) = &mut self.markers {
+            buffers.iter_mut().for_each(|buffer| buffer.destroy(device));
+        }
+    }
+}
+
+fn pass_name(marker: u32) -> String {
+    match FramePass::from_marker(marker) {
+        Some(pass) => pass.name().to_owned(),
+        None if marker == 0 => "none".to_owned(),
+        None => format!("unknown marker {}", marker),
+    }
+}
+
+fn query_checkpoints(
+    checkpoints_fn: &vk::NvDeviceDiagnosticCheckpointsFn,
+    queue: vk::Queue,
+) -> Vec<vk::CheckpointDataNV> {
+    let mut count = 0;
+    unsafe { checkpoints_fn.get_queue_checkpoint_data_nv(queue, &mut count, ptr::null_mut()) };
+    let mut checkpoints = vec![vk::CheckpointDataNV::default(); count as usize];
+    unsafe {
+        checkpoints_fn.get_queue_checkpoint_data_nv(queue, &mut count, checkpoints.as_mut_ptr())
+    };
+    checkpoints.truncate(count as usize);
+    checkpoints
+}
+
+/// Read the markers reached at the top and at the bottom of the pipe.
+fn read_markers(device: &Device, buffer: Buffer) -> VkResult<[u32; 2]> {
+    let size = GpuCrashDump::marker_buffer_size();
+    unsafe {
+        let data_ptr = device.map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())?;
+        let markers = ptr::read_unaligned(data_ptr as *const [u32; 2]);
+        device.unmap_memory(buffer.memory);
+        Ok(markers)
+    }
+}
@@ -0,0 +1,211 @@
+//! The device calls used to create, write and destroy buffers, images, their
+//! memory and descriptor pools.
+//!
+//! `Buffer`, `Texture`, `memory`, `lifetime`, `TransientBufferAllocator` and
+//! `DescriptorAllocator` use them through `GpuDevice`
+//! instead of `DeviceV1_0`, so their logic can run against a mock device in
+//! the tests, without a Vulkan driver.
+
+use crate::{lifetime::ObjectRegistry, memory::MemoryUsage};
+use ash::{prelude::VkResult, version::DeviceV1_0, vk};
+use std::{ffi::c_void, ops::Deref, sync::Arc};
+
+/// Logical device, with the memory allocated from it and the objects created with it.
+///
+/// It dereferences to the `ash` device for the other commands. Its clones
+/// share the same memory usage and objects.
+#[derive(Clone)]
+pub struct Device {
+    raw: ash::Device,
+    memory_usage: Arc<MemoryUsage>,
+    objects: Arc<ObjectRegistry>,
+}
+
+impl Device {
+    pub fn new(raw: ash::Device) -> Self {
+        Device {
+            raw,
+            memory_usage: Arc::new(MemoryUsage::default()),
+            objects: Arc::new(ObjectRegistry::default()),
+        }
+    }
+
+    /// Get the `ash` device, for the functions generic over `DeviceV1_0`.
+    pub fn raw(&self) -> &ash::Device {
+        &self.raw
+    }
+}
+
+impl Deref for Device {
+    type Target = ash::Device;
+
+    fn deref(&self) -> &ash::Device {
+        &self.raw
+    }
+}
+
+/// Device creating, binding, mapping and destroying buffers, images and memory.
+///
+/// The functions are unsafe for the same reasons as the Vulkan commands they
+/// wrap: the handles must be valid and belong to the device.
+pub trait GpuDevice {
+    /// Get the memory allocated from the device with `memory::allocate`.
+    fn memory_usage(&self) -> &MemoryUsage;
+
+    /// Get the objects created with the device and tracked by `lifetime`.
+    fn objects(&self) -> &ObjectRegistry;
+
+    unsafe fn create_buffer(&self, info: &vk::BufferCreateInfo) -> VkResult<vk::Buffer>;
+
+    unsafe fn get_buffer_memory_requirements(&self, buffer: vk::Buffer) -> vk::MemoryRequirements;
+
+    unsafe fn bind_buffer_memory(
+        &self,
+        buffer: vk::Buffer,
+        memory: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+    ) -> VkResult<()>;
+
+    unsafe fn destroy_buffer(&self, buffer: vk::Buffer);
+
+    unsafe fn create_image(&self, info: &vk::ImageCreateInfo) -> VkResult<vk::Image>;
+
+    unsafe fn get_image_memory_requirements(&self, image: vk::Image) -> vk::MemoryRequirements;
+
+    unsafe fn bind_image_memory(
+        &self,
+        image: vk::Image,
+        memory: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+    ) -> VkResult<()>;
+
+    unsafe fn destroy_image(&self, image: vk::Image);
+
+    unsafe fn destroy_image_view(&self, view: vk::ImageView);
+
+    unsafe fn destroy_sampler(&self, sampler: vk::Sampler);
+
+    unsafe fn allocate_memory(&self, info: &vk::MemoryAllocateInfo) -> VkResult<vk::DeviceMemory>;
+
+    unsafe fn free_memory(&self, memory: vk::DeviceMemory);
+
+    unsafe fn map_memory(
+        &self,
+        memory: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> VkResult<*mut c_void>;
+
+    unsafe fn unmap_memory(&self, memory: vk::DeviceMemory);
+
+    unsafe fn create_descriptor_pool(
+        &self,
+        info: &vk::DescriptorPoolCreateInfo,
+    ) -> VkResult<vk::DescriptorPool>;
+
+    unsafe fn allocate_descriptor_sets(
+        &self,
+        info: &vk::DescriptorSetAllocateInfo,
+    ) -> VkResult<Vec<vk::DescriptorSet>>;
+
+    unsafe fn destroy_descriptor_pool(&self, pool: vk::DescriptorPool);
+}
+
+impl GpuDevice for Device {
+    fn memory_usage(&self) -> &MemoryUsage {
+        &self.memory_usage
+    }
+
+    fn objects(&self) -> &ObjectRegistry {
+        &self.objects
+    }
+
+    unsafe fn create_buffer(&self, info: &vk::BufferCreateInfo) -> VkResult<vk::Buffer> {
+        DeviceV1_0::create_buffer(&self.raw, info, None)
+    }
+
+    unsafe fn get_buffer_memory_requirements(&self, buffer: vk::Buffer) -> vk::MemoryRequirements {
+        DeviceV1_0::get_buffer_memory_requirements(&self.raw, buffer)
+    }
+
+    unsafe fn bind_buffer_memory(
+        &self,
+        buffer: vk::Buffer,
+        memory: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+    ) -> VkResult<()> {
+        DeviceV1_0::bind_buffer_memory(&self.raw, buffer, memory, offset)
+    }
+
+    unsafe fn destroy_buffer(&self, buffer: vk::Buffer) {
+        DeviceV1_0::destroy_buffer(&self.raw, buffer, None)
+    }
+
+    unsafe fn create_image(&self, info: &vk::ImageCreateInfo) -> VkResult<vk::Image> {
+        DeviceV1_0::create_image(&self.raw, info, None)
+    }
+
+    unsafe fn get_image_memory_requirements(&self, image: vk::Image) -> vk::MemoryRequirements {
+        DeviceV1_0::get_image_memory_requirements(&self.raw, image)
+    }
+
+    unsafe fn bind_image_memory(
+        &self,
+        image: vk::Image,
+        memory: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+    ) -> VkResult<()> {
+        DeviceV1_0::bind_image_memory(&self.raw, image, memory, offset)
+    }
+
+    unsafe fn destroy_image(&self, image: vk::Image) {
+        DeviceV1_0::destroy_image(&self.raw, image, None)
+    }
+
+    unsafe fn destroy_image_view(&self, view: vk::ImageView) {
+        DeviceV1_0::destroy_image_view(&self.raw, view, None)
+    }
+
+    unsafe fn destroy_sampler(&self, sampler: vk::Sampler) {
+        DeviceV1_0::destroy_sampler(&self.raw, sampler, None)
+    }
+
+    unsafe fn allocate_memory(&self, info: &vk::MemoryAllocateInfo) -> VkResult<vk::DeviceMemory> {
+        DeviceV1_0::allocate_memory(&self.raw, info, None)
+    }
+
+    unsafe fn free_memory(&self, memory: vk::DeviceMemory) {
+        DeviceV1_0::free_memory(&self.raw, memory, None)
+    }
+
+    unsafe fn map_memory(
+        &self,
+        memory: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> VkResult<*mut c_void> {
+        DeviceV1_0::map_memory(&self.raw, memory, offset, size, vk::MemoryMapFlags::empty())
+    }
+
+    unsafe fn unmap_memory(&self, memory: vk::DeviceMemory) {
+        DeviceV1_0::unmap_memory(&self.raw, memory)
+    }
+
+    unsafe fn create_descriptor_pool(
+        &self,
+        info: &vk::DescriptorPoolCreateInfo,
+    ) -> VkResult<vk::DescriptorPool> {
+        DeviceV1_0::create_descriptor_pool(&self.raw, info, None)
+    }
+
+    unsafe fn allocate_descriptor_sets(
+        &self,
+        info: &vk::DescriptorSetAllocateInfo,
+    ) -> VkResult<Vec<vk::DescriptorSet>> {
+        DeviceV1_0::allocate_descriptor_sets(&self.raw, info)
+    }
+
+    unsafe fn destroy_descriptor_pool(&self, pool: vk::DescriptorPool) {
+        DeviceV1_0::destroy_descriptor_pool(&self.raw, pool, None)
+    }
+}
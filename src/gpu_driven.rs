@@ -0,0 +1,507 @@
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    context::*,
+    frame_layout::{OBJECT_SET, PASS_SET},
+    gpu_device::Device,
+    lifetime,
+    render_list::RenderList,
+    std140::std140_struct,
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Instance,
+};
+use std::{
+    ffi::CStr,
+    mem::{self, size_of},
+};
+
+/// Number of levels of detail of the mesh of the instances.
+///
+/// Must match `LOD_COUNT` in `gpu_driven.glsl`.
+pub const GPU_DRIVEN_LOD_COUNT: usize = 3;
+
+/// Maximum number of instances, and of visible instances of each level of detail.
+///
+/// Must match `MAX_INSTANCES` in `gpu_driven.glsl`.
+pub const GPU_DRIVEN_MAX_INSTANCES: u32 = 4096;
+
+/// Number of instances culled by each work group of the culling shader.
+///
+/// Must match the local size of `gpu_driven_cull.comp`.
+const GPU_DRIVEN_GROUP_SIZE: u32 = 64;
+
+/// Offset of the draws in the draw buffers, after the draw count and the
+/// instance count of each level of detail.
+const DRAWS_OFFSET: vk::DeviceSize = 16;
+
+const UNIFORMS_BINDING: u32 = 0;
+const INSTANCES_BINDING: u32 = 1;
+const DRAWS_BINDING: u32 = 2;
+
+/// Get the device extensions required for the GPU-driven path.
+pub fn required_device_extensions() -> [&'static CStr; 1] {
+    [vk::KhrDrawIndirectCountFn::name()]
+}
+
+/// Check if `device` supports indirect draws whose count is read from a buffer,
+/// with several draws starting at any instance.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let extensions_supported = required_device_extensions()
+        .iter()
+        .all(|ext| is_device_extension_supported(instance, device, ext));
+    if !extensions_supported {
+        return false;
+    }
+
+    let features = unsafe { instance.get_physical_device_features(device) };
+    features.multi_draw_indirect == vk::TRUE && features.draw_indirect_first_instance == vk::TRUE
+}
+
+/// Range of the index buffer holding a level of detail of the mesh.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GpuLod {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+}
+
+/// Instance as laid out in the instance storage buffer.
+///
+/// Must match `Instance` in `gpu_driven.glsl`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct GpuInstance {
+    /// Center and radius of the bounding sphere, which are also the position
+    /// and the scale of the mesh of radius 1.
+    pub sphere: [f32; 4],
+    pub color: [f32; 4],
+    /// Index of the material in the material buffer of `BindlessTextures`.
+    pub material: u32,
+    pub padding: [u32; 3],
+}
+
+std140_struct! {
+    /// Uniforms of the GPU-driven pass as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct GpuDrivenUniforms {
+        pub frustum_planes: [[f32; 4]; 6],
+        pub camera_position: [f32; 4],
+        /// Distances from which the second and the third levels of detail are used.
+        pub lod_distances: [f32; 4],
+        /// First index, index count and vertex offset of each level of detail.
+        pub lods: [[u32; 4]; GPU_DRIVEN_LOD_COUNT],
+        pub instance_count: u32,
+    }
+}
+
+/// Instances whose visibility, level of detail and draws are decided by the GPU.
+///
+/// Before the main render pass, a compute shader culls each instance against
+/// the frustum, picks its level of detail from its distance to the camera and
+/// appends its index to the visible instances of that level. A second compute
+/// shader then writes one indirect draw per level with visible instances,
+/// drawing its range of the visible instances, and counts the draws. The draws
+/// are recorded with `vkCmdDrawIndexedIndirectCountKHR`, which reads the draw
+/// count from the same buffer, so the CPU never knows what is drawn: it only
+/// writes the camera in the uniform buffer each frame.
+///
+/// The vertex shader fetches its instance from the visible instances with the
+/// instance index, which starts at the first instance of the draw, and the
+/// fragment shader samples its texture from the bindless texture array with
+/// the material index of the instance.
+///
+/// The draw buffers hold the draw count, the instance count of each level, the
+/// draws and the visible instances. There is one per swapchain image, like the
+/// uniform buffers.
+pub struct GpuDriven {
+    draw_indirect_count_fn: vk::KhrDrawIndirectCountFn,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    lods: [GpuLod; GPU_DRIVEN_LOD_COUNT],
+    lod_distances: [f32; GPU_DRIVEN_LOD_COUNT - 1],
+    instance_buffer: Buffer,
+    instance_count: u32,
+    uniform_buffers: Vec<Buffer>,
+    draw_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    cull_pipeline: vk::Pipeline,
+    draws_pipeline: vk::Pipeline,
+    compute_pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl GpuDriven {
+    /// Create the descriptor sets of the pass and write the instances.
+    ///
+    /// `vertex_buffer` and `index_buffer` hold the levels of detail of the
+    /// mesh, from the finest to the coarsest, each used from the distance of
+    /// `lod_distances` preceding it. `instance_buffer` must be a host visible
+    /// and coherent storage buffer of `GpuDriven::instance_buffer_size` bytes.
+    /// There must be one uniform buffer, host visible and coherent, and one
+    /// draw buffer of `GpuDriven::draw_buffer_size` bytes per swapchain image.
+    /// The draw buffers must have the `STORAGE_BUFFER`, `INDIRECT_BUFFER` and
+    /// `TRANSFER_DST` usages.
+    ///
+    /// The pipelines must be set using `set_compute_pipelines` and
+    /// `set_pipeline` before recording commands.
+    pub fn new(
+        (instance, device): (&Instance, &Device),
+        (vertex_buffer, index_buffer): (Buffer, Buffer),
+        (lods, lod_distances): (
+            [GpuLod; GPU_DRIVEN_LOD_COUNT],
+            [f32; GPU_DRIVEN_LOD_COUNT - 1],
+        ),
+        (instance_buffer, instances): (Buffer, &[GpuInstance]),
+        (uniform_buffers, draw_buffers): (Vec<Buffer>, Vec<Buffer>),
+    ) -> Self {
+        assert!(
+            instances.len() <= GPU_DRIVEN_MAX_INSTANCES as usize,
+            "Too many instances"
+        );
+        instance_buffer.write(device, 0, instances);
+
+        let draw_indirect_count_fn = vk::KhrDrawIndirectCountFn::load(|name| unsafe {
+            mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        });
+
+        let image_count = uniform_buffers.len();
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let buffer_info = |buffer: &Buffer, size| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size)
+                .build()]
+        };
+        let instances_infos = buffer_info(&instance_buffer, Self::instance_buffer_size());
+        for (i, set) in sets.iter().enumerate() {
+            let uniforms_infos =
+                buffer_info(&uniform_buffers[i], size_of::<GpuDrivenUniforms>() as _);
+            let draws_infos = buffer_info(&draw_buffers[i], Self::draw_buffer_size());
+            let write = |binding, descriptor_type| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(descriptor_type)
+            };
+            let descriptor_writes = [
+                write(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&uniforms_infos)
+                    .build(),
+                write(INSTANCES_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&instances_infos)
+                    .build(),
+                write(DRAWS_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&draws_infos)
+                    .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        GpuDriven {
+            draw_indirect_count_fn,
+            vertex_buffer,
+            index_buffer,
+            lods,
+            lod_distances,
+            instance_buffer,
+            instance_count: instances.len() as _,
+            uniform_buffers,
+            draw_buffers,
+            layout,
+            pool,
+            sets,
+            cull_pipeline: vk::Pipeline::null(),
+            draws_pipeline: vk::Pipeline::null(),
+            compute_pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type, stage_flags| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(stage_flags)
+                .build()
+        };
+        let bindings = [
+            binding(
+                UNIFORMS_BINDING,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::ShaderStageFlags::COMPUTE,
+            ),
+            binding(
+                INSTANCES_BINDING,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::VERTEX,
+            ),
+            binding(
+                DRAWS_BINDING,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::VERTEX,
+            ),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 2 * image_count,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    pub fn instance_buffer_size() -> vk::DeviceSize {
+        (size_of::<GpuInstance>() * GPU_DRIVEN_MAX_INSTANCES as usize) as _
+    }
+
+    /// Get the size of a draw buffer: the counts, the draws and the visible
+    /// instances of each level of detail.
+    pub fn draw_buffer_size() -> vk::DeviceSize {
+        let draws = size_of::<vk::DrawIndexedIndirectCommand>() * GPU_DRIVEN_LOD_COUNT;
+        let visible_instances =
+            size_of::<u32>() * GPU_DRIVEN_LOD_COUNT * GPU_DRIVEN_MAX_INSTANCES as usize;
+        DRAWS_OFFSET + (draws + visible_instances) as vk::DeviceSize
+    }
+}
+
+impl GpuDriven {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+
+    /// Set the compute pipelines culling the instances and writing the draws,
+    /// which share `compute_pipeline_layout`.
+    ///
+    /// The GPU-driven pass takes ownership of the pipelines and their layout.
+    pub fn set_compute_pipelines(
+        &mut self,
+        (cull_pipeline, draws_pipeline): (vk::Pipeline, vk::Pipeline),
+        compute_pipeline_layout: vk::PipelineLayout,
+    ) {
+        self.cull_pipeline = cull_pipeline;
+        self.draws_pipeline = draws_pipeline;
+        self.compute_pipeline_layout = compute_pipeline_layout;
+    }
+
+    /// Set the pipeline drawing the instances.
+    ///
+    /// The GPU-driven pass takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the frustum and the camera of `list` in the uniform buffer of the
+    /// swapchain image `image_index`.
+    pub fn update(&self, device: &Device, image_index: usize, list: &RenderList) {
+        let mut frustum_planes = [[0.0; 4]; 6];
+        for (coefficients, plane) in frustum_planes.iter_mut().zip(&list.frustum.planes) {
+            *coefficients = plane.coefficients().into();
+        }
+        let mut lods = [[0; 4]; GPU_DRIVEN_LOD_COUNT];
+        for (range, lod) in lods.iter_mut().zip(&self.lods) {
+            *range = [
+                lod.first_index,
+                lod.index_count,
+                lod.vertex_offset as u32,
+                0,
+            ];
+        }
+        let mut lod_distances = [f32::MAX; 4];
+        lod_distances[..self.lod_distances.len()].copy_from_slice(&self.lod_distances);
+
+        let uniforms = GpuDrivenUniforms {
+            frustum_planes,
+            camera_position: list.camera_position.to_homogeneous().into(),
+            lod_distances,
+            lods,
+            instance_count: self.instance_count,
+        };
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+    }
+
+    /// Record the culling of the instances and the writing of the draws of
+    /// the swapchain image `image_index`.
+    ///
+    /// It must be recorded before the draws, outside of any render pass.
+    pub fn cmd_cull(&self, device: &Device, command_buffer: vk::CommandBuffer, image_index: usize) {
+        let draw_buffer = self.draw_buffers[image_index].buffer;
+        let bind_and_dispatch = |pipeline, group_count| unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.compute_pipeline_layout,
+                0,
+                &self.sets[image_index..=image_index],
+                &[],
+            );
+            device.cmd_dispatch(command_buffer, group_count, 1, 1);
+        };
+        // The draws of the previous frame might still read the buffer
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::memory(
+                Access::indirect_read().and(Access::new(
+                    vk::PipelineStageFlags::VERTEX_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                )),
+                Access::transfer_write(),
+            )],
+        );
+        unsafe { device.cmd_fill_buffer(command_buffer, draw_buffer, 0, DRAWS_OFFSET, 0) };
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::memory(
+                Access::transfer_write(),
+                Access::new(
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                ),
+            )],
+        );
+        bind_and_dispatch(
+            self.cull_pipeline,
+            self.instance_count.div_ceil(GPU_DRIVEN_GROUP_SIZE),
+        );
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::memory(
+                Access::compute_write(),
+                Access::new(
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                ),
+            )],
+        );
+        bind_and_dispatch(self.draws_pipeline, 1);
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::memory(
+                Access::compute_write(),
+                Access::indirect_read().and(Access::new(
+                    vk::PipelineStageFlags::VERTEX_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                )),
+            )],
+        );
+    }
+
+    /// Record the draws written by the GPU for the swapchain image `image_index`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`, and
+    /// `bindless_set` is the set of the bindless texture array.
+    pub fn cmd_draw(
+        &self,
+        encoder: &mut CommandEncoder,
+        image_index: usize,
+        bindless_set: vk::DescriptorSet,
+    ) {
+        let draw_buffer = self.draw_buffers[image_index].buffer;
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[self.vertex_buffer.buffer], &[0]);
+        encoder.bind_index_buffer(self.index_buffer.buffer, 0, vk::IndexType::UINT32);
+        encoder.bind_descriptor_sets(
+            self.pipeline_layout,
+            PASS_SET,
+            &[self.sets[image_index]],
+            &[],
+        );
+        encoder.bind_descriptor_sets(self.pipeline_layout, OBJECT_SET, &[bindless_set], &[]);
+        unsafe {
+            self.draw_indirect_count_fn
+                .cmd_draw_indexed_indirect_count_khr(
+                    encoder.command_buffer(),
+                    draw_buffer,
+                    DRAWS_OFFSET,
+                    draw_buffer,
+                    0,
+                    GPU_DRIVEN_LOD_COUNT as _,
+                    size_of::<vk::DrawIndexedIndirectCommand>() as _,
+                )
+        };
+    }
+
+    /// Destroy the pipeline drawing the instances and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_pipeline(device, self.cull_pipeline);
+            lifetime::destroy_pipeline(device, self.draws_pipeline);
+            device.destroy_pipeline_layout(self.compute_pipeline_layout, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.vertex_buffer.destroy(device);
+        self.index_buffer.destroy(device);
+        self.instance_buffer.destroy(device);
+        self.uniform_buffers
+            .iter_mut()
+            .chain(self.draw_buffers.iter_mut())
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
@@ -0,0 +1,340 @@
+use crate::{buffer::Buffer, gpu_device::Device, lifetime};
+use ash::{version::DeviceV1_0, vk};
+use std::mem::size_of;
+
+/// Number of bits of the keys sorted by each pass.
+///
+/// Must match `RADIX_BITS` in `radix_sort.glsl`.
+const RADIX_BITS: u32 = 4;
+
+const RADIX: u32 = 1 << RADIX_BITS;
+
+/// Number of elements sorted by each work group.
+///
+/// Must match `WORKGROUP_SIZE` in `radix_sort.glsl`.
+const BLOCK_SIZE: u32 = 256;
+
+/// Number of passes sorting 32 bit keys. It is even so the sorted elements
+/// end up in the key and value buffers.
+const PASS_COUNT: u32 = 32 / RADIX_BITS;
+
+const KEYS_IN_BINDING: u32 = 0;
+const VALUES_IN_BINDING: u32 = 1;
+const KEYS_OUT_BINDING: u32 = 2;
+const VALUES_OUT_BINDING: u32 = 3;
+const HISTOGRAM_BINDING: u32 = 4;
+
+/// Stage of a pass of the sort, the specialization constant 1 of `radix_sort.glsl`.
+///
+/// Must match the `STAGE_*` constants of `radix_sort.glsl`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortStage {
+    /// Count the digits of each block.
+    Count = 0,
+    /// Scan the counts into the offset of each digit of each block.
+    Scan = 1,
+    /// Write each element at the offset of its digit plus its rank in its block.
+    Scatter = 2,
+}
+
+impl SortStage {
+    /// All the stages, in the order they are recorded.
+    pub const ALL: [SortStage; 3] = [SortStage::Count, SortStage::Scan, SortStage::Scatter];
+}
+
+/// Get the number of blocks sorted by a work group for `count` elements.
+fn block_count(count: u32) -> u32 {
+    count.div_ceil(BLOCK_SIZE)
+}
+
+/// Stable radix sort of 32 bit keys with 32 bit values on the GPU.
+///
+/// Each of the 8 passes sorts the elements by 4 bits of the keys, from the
+/// lowest to the highest, in three compute dispatches: the digits of each
+/// block of 256 elements are counted, a single work group scans the counts
+/// into the offset of each digit of each block, then the elements are
+/// scattered from a pair of buffers to the other. The ranks and the offsets
+/// are prefix sums using subgroup operations where they are supported.
+///
+/// Elements with equal keys keep their order, so values ordered by a
+/// secondary key before the sort stay ordered by it.
+pub struct GpuSort {
+    capacity: u32,
+    keys: Buffer,
+    values: Buffer,
+    scratch_keys: Buffer,
+    scratch_values: Buffer,
+    histogram: Buffer,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    /// Sets sorting from the keys and values to the scratch buffers, then back.
+    sets: Vec<vk::DescriptorSet>,
+    /// Pipelines of each `SortStage`.
+    pipelines: Option<[vk::Pipeline; 3]>,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl GpuSort {
+    /// Create the descriptor sets of the passes.
+    ///
+    /// `keys`, `values`, `scratch_keys` and `scratch_values` must be storage
+    /// buffers of at least `GpuSort::buffer_size(capacity)` bytes, and
+    /// `histogram` a storage buffer of `GpuSort::histogram_size(capacity)` bytes.
+    ///
+    /// The pipelines must be set using `set_pipelines` before recording sorts.
+    pub fn new(
+        device: &Device,
+        capacity: u32,
+        (keys, values): (Buffer, Buffer),
+        (scratch_keys, scratch_values, histogram): (Buffer, Buffer, Buffer),
+    ) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let sets = {
+            let layouts = [layout; 2];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let buffer_info = |buffer: &Buffer| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()]
+        };
+        let histogram_infos = buffer_info(&histogram);
+        let pairs = [
+            ((&keys, &values), (&scratch_keys, &scratch_values)),
+            ((&scratch_keys, &scratch_values), (&keys, &values)),
+        ];
+        for (set, ((keys_in, values_in), (keys_out, values_out))) in sets.iter().zip(&pairs) {
+            let keys_in_infos = buffer_info(keys_in);
+            let values_in_infos = buffer_info(values_in);
+            let keys_out_infos = buffer_info(keys_out);
+            let values_out_infos = buffer_info(values_out);
+            let write = |binding, buffer_infos: &[vk::DescriptorBufferInfo]| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_infos)
+                    .build()
+            };
+            let descriptor_writes = [
+                write(KEYS_IN_BINDING, &keys_in_infos),
+                write(VALUES_IN_BINDING, &values_in_infos),
+                write(KEYS_OUT_BINDING, &keys_out_infos),
+                write(VALUES_OUT_BINDING, &values_out_infos),
+                write(HISTOGRAM_BINDING, &histogram_infos),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        GpuSort {
+            capacity,
+            keys,
+            values,
+            scratch_keys,
+            scratch_values,
+            histogram,
+            layout,
+            pool,
+            sets,
+            pipelines: None,
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Size in bytes of the key, value and scratch buffers sorting `capacity` elements.
+    pub fn buffer_size(capacity: u32) -> vk::DeviceSize {
+        (size_of::<u32>() as u32 * capacity.max(1)) as _
+    }
+
+    /// Size in bytes of the histogram buffer sorting `capacity` elements.
+    pub fn histogram_size(capacity: u32) -> vk::DeviceSize {
+        (size_of::<u32>() as u32 * RADIX * block_count(capacity.max(1))) as _
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [
+            KEYS_IN_BINDING,
+            VALUES_IN_BINDING,
+            KEYS_OUT_BINDING,
+            VALUES_OUT_BINDING,
+            HISTOGRAM_BINDING,
+        ]
+        .iter()
+        .map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(*binding)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 10,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(2)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl GpuSort {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the buffer of the keys, sorted in place.
+    pub fn keys(&self) -> Buffer {
+        self.keys
+    }
+
+    /// Get the buffer of the values, reordered with their keys.
+    pub fn values(&self) -> Buffer {
+        self.values
+    }
+
+    /// Set the pipelines of each `SortStage`, in the order of `SortStage::ALL`.
+    ///
+    /// All use `pipeline_layout`, with the element count and the shift of the
+    /// digit as push constants. The sort takes ownership of the pipelines and
+    /// their layout.
+    pub fn set_pipelines(&mut self, pipelines: [vk::Pipeline; 3], layout: vk::PipelineLayout) {
+        self.pipelines = Some(pipelines);
+        self.pipeline_layout = layout;
+    }
+
+    /// Record the sort of the first `count` keys and values in `command_buffer`.
+    ///
+    /// It must be recorded outside of any render pass, after a barrier making
+    /// the keys and values visible to compute shaders. The sorted elements are
+    /// written by compute shaders.
+    ///
+    /// # Panics
+    ///
+    /// Panic if `count` is larger than the capacity.
+    pub fn cmd_sort(&self, device: &Device, command_buffer: vk::CommandBuffer, count: u32) {
+        assert!(
+            count <= self.capacity,
+            "Cannot sort {} elements with a capacity of {}",
+            count,
+            self.capacity
+        );
+        if count == 0 {
+            return;
+        }
+        let pipelines = self.pipelines.expect("Sort pipelines not set");
+
+        let barrier = || unsafe {
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            )
+        };
+
+        let block_count = block_count(count);
+        for pass in 0..PASS_COUNT {
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &self.sets[(pass % 2) as usize..=(pass % 2) as usize],
+                    &[],
+                );
+                let bytes = [count, pass * RADIX_BITS]
+                    .iter()
+                    .flat_map(|value| value.to_ne_bytes())
+                    .collect::<Vec<_>>();
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    &bytes,
+                );
+            }
+
+            for (stage, pipeline) in SortStage::ALL.iter().zip(&pipelines) {
+                let group_count = match stage {
+                    SortStage::Scan => 1,
+                    _ => block_count,
+                };
+                unsafe {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        *pipeline,
+                    );
+                    device.cmd_dispatch(command_buffer, group_count, 1, 1);
+                }
+                // The last scatter is synchronized by the reader of the elements
+                if pass + 1 < PASS_COUNT || *stage != SortStage::Scatter {
+                    barrier();
+                }
+            }
+        }
+    }
+
+    /// Destroy the pipelines and their layout.
+    pub fn destroy_pipelines(&mut self, device: &Device) {
+        unsafe {
+            if let Some(pipelines) = self.pipelines.take() {
+                pipelines
+                    .iter()
+                    .for_each(|pipeline| lifetime::destroy_pipeline(device, *pipeline));
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipelines(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.keys.destroy(device);
+        self.values.destroy(device);
+        self.scratch_keys.destroy(device);
+        self.scratch_values.destroy(device);
+        self.histogram.destroy(device);
+    }
+}
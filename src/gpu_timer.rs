@@ -0,0 +1,216 @@
+use crate::gpu_device::Device;
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Instance,
+};
+use std::mem;
+
+/// Check if the queues of `queue_family_index` on `device` support timestamps.
+pub fn is_supported(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    queue_family_index: u32,
+) -> bool {
+    let families = unsafe { instance.get_physical_device_queue_family_properties(device) };
+    families
+        .get(queue_family_index as usize)
+        .is_some_and(|family| family.timestamp_valid_bits > 0)
+}
+
+/// Check if `device` supports pipeline statistics queries.
+pub fn is_pipeline_statistics_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let features = unsafe { instance.get_physical_device_features(device) };
+    features.pipeline_statistics_query == vk::TRUE
+}
+
+/// Time taken by the GPU to execute the commands of each swapchain image,
+/// and the number of primitives they drew.
+///
+/// A timestamp is written at the start and at the end of the commands, and
+/// the results are read back by `read` once the commands of the image
+/// executed, so they are a few frames late.
+pub struct GpuTimer {
+    /// Two timestamps per swapchain image.
+    timestamp_pool: vk::QueryPool,
+    /// One pipeline statistics query per swapchain image, counting the
+    /// primitives assembled, if supported.
+    statistics_pool: Option<vk::QueryPool>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    /// Mask of the valid bits of the timestamps.
+    timestamp_mask: u64,
+    /// Whether the queries of each swapchain image were submitted since they
+    /// were last read.
+    submitted: Vec<bool>,
+}
+
+/// Results of the queries of a swapchain image.
+#[derive(Clone, Copy, Debug)]
+pub struct GpuFrameStats {
+    /// Time between the start and the end of the commands, in milliseconds.
+    pub milliseconds: f32,
+    /// Number of primitives assembled, if pipeline statistics are enabled.
+    pub primitives: Option<u64>,
+}
+
+impl GpuTimer {
+    /// Create the queries of `image_count` swapchain images recorded for a
+    /// queue of `queue_family_index`, which must support timestamps.
+    ///
+    /// The primitives are only counted if `pipeline_statistics` is true, in
+    /// which case the `pipelineStatisticsQuery` feature must be enabled.
+    pub fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue_family_index: u32,
+        image_count: usize,
+        pipeline_statistics: bool,
+    ) -> Self {
+        let timestamp_period = unsafe { instance.get_physical_device_properties(physical_device) }
+            .limits
+            .timestamp_period;
+        let valid_bits =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) }
+                [queue_family_index as usize]
+                .timestamp_valid_bits;
+        let timestamp_mask = if valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1 << valid_bits) - 1
+        };
+
+        let pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2 * image_count as u32)
+            .build();
+        let timestamp_pool = unsafe { device.create_query_pool(&pool_info, None).unwrap() };
+
+        let statistics_pool = if pipeline_statistics {
+            let pool_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                .query_count(image_count as _)
+                .pipeline_statistics(vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES)
+                .build();
+            Some(unsafe { device.create_query_pool(&pool_info, None).unwrap() })
+        } else {
+            None
+        };
+
+        GpuTimer {
+            timestamp_pool,
+            statistics_pool,
+            timestamp_period,
+            timestamp_mask,
+            submitted: vec![false; image_count],
+        }
+    }
+
+    /// Reset the queries of the swapchain image `image_index` and start
+    /// timing the commands recorded after it.
+    ///
+    /// Must be recorded outside of a render pass, before any other command.
+    pub fn cmd_begin(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let first_query = 2 * image_index as u32;
+        unsafe {
+            device.cmd_reset_query_pool(command_buffer, self.timestamp_pool, first_query, 2);
+            if let Some(statistics_pool) = self.statistics_pool {
+                device.cmd_reset_query_pool(command_buffer, statistics_pool, image_index as _, 1);
+            }
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.timestamp_pool,
+                first_query,
+            );
+            if let Some(statistics_pool) = self.statistics_pool {
+                device.cmd_begin_query(
+                    command_buffer,
+                    statistics_pool,
+                    image_index as _,
+                    vk::QueryControlFlags::empty(),
+                );
+            }
+        }
+    }
+
+    /// Stop timing the commands of the swapchain image `image_index`.
+    ///
+    /// Must be recorded outside of a render pass, after every other command.
+    pub fn cmd_end(&self, device: &Device, command_buffer: vk::CommandBuffer, image_index: usize) {
+        unsafe {
+            if let Some(statistics_pool) = self.statistics_pool {
+                device.cmd_end_query(command_buffer, statistics_pool, image_index as _);
+            }
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.timestamp_pool,
+                2 * image_index as u32 + 1,
+            );
+        }
+    }
+
+    /// Read the results of the queries of the swapchain image `image_index`.
+    ///
+    /// The commands of the image must have completed. Return `None` the
+    /// first time the image is used or if the results are not available.
+    pub fn read(&mut self, device: &Device, image_index: usize) -> Option<GpuFrameStats> {
+        if !mem::replace(&mut self.submitted[image_index], true) {
+            return None;
+        }
+
+        let mut timestamps = [0u64; 2];
+        unsafe {
+            device.get_query_pool_results(
+                self.timestamp_pool,
+                2 * image_index as u32,
+                2,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        }
+        .ok()?;
+        let ticks = (timestamps[1] & self.timestamp_mask)
+            .wrapping_sub(timestamps[0] & self.timestamp_mask)
+            & self.timestamp_mask;
+        let milliseconds = (ticks as f64 * self.timestamp_period as f64 / 1_000_000.0) as f32;
+
+        let primitives = match self.statistics_pool {
+            Some(statistics_pool) => {
+                let mut primitives = [0u64; 1];
+                unsafe {
+                    device.get_query_pool_results(
+                        statistics_pool,
+                        image_index as _,
+                        1,
+                        &mut primitives,
+                        vk::QueryResultFlags::TYPE_64,
+                    )
+                }
+                .ok()?;
+                Some(primitives[0])
+            }
+            None => None,
+        };
+
+        Some(GpuFrameStats {
+            milliseconds,
+            primitives,
+        })
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_query_pool(self.timestamp_pool, None);
+            if let Some(statistics_pool) = self.statistics_pool {
+                device.destroy_query_pool(statistics_pool, None);
+            }
+        }
+    }
+}
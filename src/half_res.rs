@@ -0,0 +1,299 @@
+use crate::{
+    frame_layout::PASS_SET,
+    gpu_device::Device,
+    lifetime,
+    render_target::RenderTarget,
+    texture::Texture,
+    tracked_image::{ImageState, TrackedImage},
+};
+use ash::{version::DeviceV1_0, vk};
+
+const HDR_COLOR_BINDING: u32 = 0;
+const EFFECT_BINDING: u32 = 1;
+const DEPTH_BINDING: u32 = 2;
+
+/// Get the extent of the half resolution images of an effect rendered at `extent`.
+pub fn half_extent(extent: vk::Extent2D) -> vk::Extent2D {
+    vk::Extent2D {
+        width: extent.width.div_ceil(2).max(1),
+        height: extent.height.div_ceil(2).max(1),
+    }
+}
+
+/// Effects rendered at half resolution then upsampled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HalfResEffects {
+    pub light_shafts: bool,
+}
+
+impl HalfResEffects {
+    /// Check whether an effect is rendered at half resolution, so the depth
+    /// buffer must be kept for the upsample.
+    pub fn any(self) -> bool {
+        self.light_shafts
+    }
+}
+
+/// Depth-aware upsample of an effect rendered at half resolution.
+///
+/// The effect is rendered alone into a half resolution target, then this pass
+/// adds it to the HDR image at full resolution. Each pixel blends the four
+/// closest texels of the effect with bilinear weights, lowered for the texels
+/// whose surface is at another distance from the camera than the one of the
+/// pixel. The distance of a texel is read from the depth buffer at the pixel
+/// it covers, so the effect does not bleed across the edges of the objects.
+pub struct BilateralUpsample {
+    target: RenderTarget,
+    /// Depth buffer of the main render pass and the state it is in when the pass begins.
+    depth: Option<(vk::Image, vk::ImageAspectFlags, ImageState)>,
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl BilateralUpsample {
+    /// Create the descriptor set of the upsample.
+    ///
+    /// `target` must have a single HDR color attachment at full resolution.
+    ///
+    /// The inputs must be set using `set_inputs` and `set_depth` and the
+    /// pipeline using `set_pipeline` before recording the pass.
+    pub fn new(device: &Device, target: RenderTarget) -> Self {
+        let sampler = Self::create_sampler(device);
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        BilateralUpsample {
+            target,
+            depth: None,
+            sampler,
+            layout,
+            pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [HDR_COLOR_BINDING, EFFECT_BINDING, DEPTH_BINDING]
+            .iter()
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(*binding)
+                    .descriptor_count(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 3,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl BilateralUpsample {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.target.render_pass()
+    }
+
+    /// Get the HDR texture with the upsampled effect.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the pass.
+    pub fn output(&self) -> Texture {
+        self.target.color(0)
+    }
+
+    /// Get the target so it can be resized.
+    ///
+    /// `set_inputs` and `set_depth` must be called after resizing it.
+    pub fn target_mut(&mut self) -> &mut RenderTarget {
+        &mut self.target
+    }
+
+    /// Set the HDR image the effect is added to and the half resolution effect.
+    ///
+    /// `hdr` must be in `hdr_layout` and `effect` in `SHADER_READ_ONLY_OPTIMAL`,
+    /// both readable by fragment shaders when the pass begins.
+    pub fn set_inputs(
+        &self,
+        device: &Device,
+        (hdr, hdr_layout): (Texture, vk::ImageLayout),
+        effect: Texture,
+    ) {
+        self.write_image(device, HDR_COLOR_BINDING, hdr.view, hdr_layout);
+        self.write_image(
+            device,
+            EFFECT_BINDING,
+            effect.view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    }
+
+    /// Set the depth buffer of the main render pass.
+    ///
+    /// `depth` must have the `SAMPLED` usage and its content must be stored
+    /// by the main render pass. `depth_aspect` is the aspect of its format and
+    /// `depth_state` the state it is in when the pass is recorded.
+    pub fn set_depth(
+        &mut self,
+        device: &Device,
+        (depth, depth_aspect): (&Texture, vk::ImageAspectFlags),
+        depth_state: ImageState,
+    ) {
+        self.write_image(
+            device,
+            DEPTH_BINDING,
+            depth.view,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        );
+        self.depth = Some((depth.image, depth_aspect, depth_state));
+    }
+
+    fn write_image(
+        &self,
+        device: &Device,
+        binding: u32,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) {
+        let image_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(layout)
+            .image_view(view)
+            .sampler(self.sampler)
+            .build()];
+        let descriptor_writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build()];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the upsample in `command_buffer`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`. It must be
+    /// recorded outside of any render pass, after the pass rendering the
+    /// effect. The depth buffer is then in the `DEPTH_STENCIL_READ_ONLY_OPTIMAL` layout.
+    pub fn cmd_draw(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let (depth_image, depth_aspect, depth_state) =
+            self.depth.expect("Depth buffer of the upsample not set");
+        let mut depth = TrackedImage::new(
+            depth_image,
+            vk::ImageSubresourceRange {
+                aspect_mask: depth_aspect,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            depth_state,
+        );
+        depth.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        self.target.cmd_begin(device, command_buffer);
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                PASS_SET,
+                &[self.set],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+        self.target.cmd_end(device, command_buffer);
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.target.destroy(device);
+    }
+}
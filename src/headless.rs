@@ -0,0 +1,175 @@
+use crate::{
+    buffer::Buffer,
+    gpu_device::Device,
+    lifetime, memory,
+    swapchain::SwapchainProperties,
+    tracked_image::{ImageState, TrackedImage},
+};
+use ash::{version::DeviceV1_0, vk};
+use image::RgbaImage;
+
+/// Format of the offscreen color target.
+///
+/// It matches the layout of `RgbaImage` and stores sRGB encoded colors like the
+/// written PNG so pixels can be read back without conversion.
+pub const OFFSCREEN_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Color image rendered in place of the swapchain images when running without a window.
+///
+/// The render pass leaves the image in `TRANSFER_SRC_OPTIMAL` so it can be
+/// copied to a host visible buffer and read back once the frame is done.
+pub struct OffscreenTarget {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    extent: vk::Extent2D,
+    readback_buffer: Buffer,
+}
+
+impl OffscreenTarget {
+    /// Create the offscreen target.
+    ///
+    /// `image` must be a `OFFSCREEN_FORMAT` image of `extent` with `COLOR_ATTACHMENT`
+    /// and `TRANSFER_SRC` usages. `readback_buffer` must be a host visible and
+    /// coherent buffer of at least `OffscreenTarget::readback_buffer_size(extent)` bytes.
+    pub fn new(
+        image: vk::Image,
+        memory: vk::DeviceMemory,
+        extent: vk::Extent2D,
+        readback_buffer: Buffer,
+    ) -> Self {
+        OffscreenTarget {
+            image,
+            memory,
+            extent,
+            readback_buffer,
+        }
+    }
+
+    /// Size in bytes of the readback buffer.
+    pub fn readback_buffer_size(extent: vk::Extent2D) -> vk::DeviceSize {
+        (extent.width * extent.height * 4) as _
+    }
+}
+
+impl OffscreenTarget {
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    /// Get the properties of the target as if it was a swapchain.
+    pub fn properties(&self) -> SwapchainProperties {
+        SwapchainProperties {
+            format: vk::SurfaceFormatKHR {
+                format: OFFSCREEN_FORMAT,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+            present_mode: vk::PresentModeKHR::FIFO,
+            extent: self.extent,
+        }
+    }
+
+    /// Record the copy of the rendered image into the readback buffer.
+    ///
+    /// It must be recorded after the main render pass.
+    pub fn cmd_copy_to_readback_buffer(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        // The layout is already set by the render pass, only the writes need to be visible
+        let mut image = TrackedImage::new(
+            self.image,
+            subresource_range,
+            ImageState {
+                layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            },
+        );
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .build();
+        let buffer_barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.readback_buffer.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+        // The copy below reads the image in this layout
+        image.assert_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.readback_buffer.buffer,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[buffer_barrier],
+                &[],
+            );
+        }
+    }
+
+    /// Read the content of the readback buffer.
+    ///
+    /// The copy recorded with `cmd_copy_to_readback_buffer` must be complete.
+    pub fn read_pixels(&self, device: &Device) -> RgbaImage {
+        let size = Self::readback_buffer_size(self.extent);
+        let pixels = unsafe {
+            let data_ptr = device
+                .map_memory(
+                    self.readback_buffer.memory,
+                    0,
+                    size,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .unwrap();
+            let pixels = std::slice::from_raw_parts(data_ptr as *const u8, size as _).to_vec();
+            device.unmap_memory(self.readback_buffer.memory);
+            pixels
+        };
+        RgbaImage::from_raw(self.extent.width, self.extent.height, pixels).unwrap()
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        lifetime::destroy_image(device, self.image);
+        memory::free(device, self.memory);
+        self.readback_buffer.destroy(device);
+    }
+}
@@ -0,0 +1,787 @@
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    gpu_device::Device,
+    lifetime,
+    math::Aabb,
+    readback::ReadbackBuffer,
+    std140::std140_struct,
+    texture::Texture,
+    tracked_image::{ImageState, TrackedImage},
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::Matrix4;
+use std::mem::{size_of, size_of_val};
+
+/// Format of the levels of the hierarchical depth buffer.
+///
+/// The first channel holds the furthest depth and the second the closest one.
+pub const HIZ_FORMAT: vk::Format = vk::Format::R32G32_SFLOAT;
+
+/// Width and height of the work groups of the reduction shaders.
+///
+/// Must match the local size in `hiz.glsl`.
+const HIZ_GROUP_SIZE: u32 = 8;
+
+/// Number of objects tested by each work group of the culling shader.
+///
+/// Must match the local size of `hiz_cull.comp`.
+const HIZ_CULL_GROUP_SIZE: u32 = 64;
+
+/// Maximum number of levels of the pyramid, enough for a 64k depth buffer.
+const MAX_LEVELS: u32 = 16;
+
+const SOURCE_BINDING: u32 = 0;
+const DESTINATION_BINDING: u32 = 1;
+
+const UNIFORMS_BINDING: u32 = 0;
+const PYRAMID_BINDING: u32 = 1;
+const BOUNDS_BINDING: u32 = 2;
+const DRAWS_BINDING: u32 = 3;
+const STATS_BINDING: u32 = 4;
+
+std140_struct! {
+    /// Uniforms of the culling shader as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct HiZUniforms {
+        /// View projection of the frame the pyramid was built from.
+        pub view_proj: Matrix4<f32>,
+        pub depth_size: [f32; 2],
+        pub object_count: u32,
+        pub level_count: u32,
+    }
+}
+
+/// Get the size of the first level of the pyramid of a depth buffer of `extent`.
+pub fn pyramid_extent(extent: vk::Extent2D) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width / 2).max(1),
+        height: (extent.height / 2).max(1),
+    }
+}
+
+/// Get the number of levels of the pyramid of a depth buffer of `extent`,
+/// down to a single texel.
+pub fn pyramid_level_count(extent: vk::Extent2D) -> u32 {
+    let extent = pyramid_extent(extent);
+    let size = extent.width.max(extent.height);
+    (32 - size.leading_zeros()).min(MAX_LEVELS)
+}
+
+/// Hierarchical depth buffer reduced from the depth buffer of the main render pass.
+///
+/// After the main render pass, compute shaders reduce the depth buffer into a
+/// pyramid of `HIZ_FORMAT` levels. Each texel keeps the furthest depth of the
+/// texels it covers in its first channel, so a box behind it is hidden by all
+/// of them, and the closest depth in its second channel, so a ray in front of
+/// it crosses none of them.
+pub struct DepthPyramid {
+    depth_image: vk::Image,
+    depth_aspect: vk::ImageAspectFlags,
+    depth_extent: vk::Extent2D,
+    pyramid: Option<Texture>,
+    level_views: Vec<vk::ImageView>,
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    depth_pipeline: vk::Pipeline,
+    reduce_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl DepthPyramid {
+    /// Create the descriptor sets of the reduction.
+    ///
+    /// The depth buffer must be set using `set_pyramid` and the pipelines
+    /// using `set_pipelines` before recording commands.
+    pub fn new(device: &Device) -> Self {
+        let layout = create_set_layout(
+            device,
+            &[
+                binding(SOURCE_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                binding(DESTINATION_BINDING, vk::DescriptorType::STORAGE_IMAGE),
+            ],
+        );
+        let pool = {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: MAX_LEVELS,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: MAX_LEVELS,
+                },
+            ];
+            let pool_info = vk::DescriptorPoolCreateInfo::builder()
+                .pool_sizes(&pool_sizes)
+                .max_sets(MAX_LEVELS)
+                .build();
+            lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+        };
+        let sets = {
+            let layouts = vec![layout; MAX_LEVELS as usize];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let sampler = {
+            let sampler_info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(MAX_LEVELS as _)
+                .build();
+            unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+        };
+
+        DepthPyramid {
+            depth_image: vk::Image::null(),
+            depth_aspect: vk::ImageAspectFlags::DEPTH,
+            depth_extent: vk::Extent2D::default(),
+            pyramid: None,
+            level_views: Vec::new(),
+            sampler,
+            layout,
+            pool,
+            sets,
+            depth_pipeline: vk::Pipeline::null(),
+            reduce_pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the size of the depth buffer the pyramid is reduced from.
+    pub fn depth_extent(&self) -> vk::Extent2D {
+        self.depth_extent
+    }
+
+    /// Get the pyramid with its sampler, filtering the nearest texel.
+    ///
+    /// It stays in the `GENERAL` layout and is readable by compute and
+    /// fragment shaders after `cmd_build`.
+    pub fn texture(&self) -> Texture {
+        Texture {
+            sampler: Some(self.sampler),
+            ..*self.pyramid.as_ref().unwrap()
+        }
+    }
+
+    /// Set the depth buffer reduced into `pyramid`.
+    ///
+    /// `depth` must have the `SAMPLED` usage and its content must be stored
+    /// by the main render pass. `depth_aspect` is the aspect of its format.
+    /// `pyramid` must be a `HIZ_FORMAT` texture of `pyramid_extent(extent)`
+    /// with `pyramid_level_count(extent)` levels and the `STORAGE`, `SAMPLED`
+    /// and `TRANSFER_DST` usages. The depth pyramid takes ownership of it, and
+    /// it must be cleared with `cmd_clear` before the first frame.
+    pub fn set_pyramid(
+        &mut self,
+        device: &Device,
+        (depth, depth_aspect): (&Texture, vk::ImageAspectFlags),
+        extent: vk::Extent2D,
+        pyramid: Texture,
+    ) {
+        self.depth_image = depth.image;
+        self.depth_aspect = depth_aspect;
+        self.depth_extent = extent;
+
+        let level_count = pyramid_level_count(extent);
+        self.level_views = (0..level_count)
+            .map(|level| {
+                let create_info = vk::ImageViewCreateInfo::builder()
+                    .image(pyramid.image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(HIZ_FORMAT)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: level,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .build();
+                unsafe { device.create_image_view(&create_info, None).unwrap() }
+            })
+            .collect();
+
+        let image_info = |view, layout| {
+            [vk::DescriptorImageInfo::builder()
+                .image_layout(layout)
+                .image_view(view)
+                .sampler(self.sampler)
+                .build()]
+        };
+        let mut image_infos = Vec::new();
+        for (level, view) in self.level_views.iter().enumerate() {
+            let source = match level {
+                0 => image_info(depth.view, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+                _ => image_info(self.level_views[level - 1], vk::ImageLayout::GENERAL),
+            };
+            image_infos.push((
+                self.sets[level],
+                source,
+                image_info(*view, vk::ImageLayout::GENERAL),
+            ));
+        }
+        let mut descriptor_writes = Vec::new();
+        for (set, source_infos, destination_infos) in &image_infos {
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(SOURCE_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(source_infos)
+                    .build(),
+            );
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(DESTINATION_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(destination_infos)
+                    .build(),
+            );
+        }
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        self.pyramid = Some(pyramid);
+    }
+
+    /// Set the compute pipelines building the pyramid.
+    ///
+    /// `depth_pipeline` reduces the depth buffer into the first level, and
+    /// `reduce_pipeline` each level into the next one. Both use
+    /// `pipeline_layout`. The depth pyramid takes ownership of the pipelines
+    /// and their layout.
+    pub fn set_pipelines(
+        &mut self,
+        depth_pipeline: vk::Pipeline,
+        reduce_pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        self.depth_pipeline = depth_pipeline;
+        self.reduce_pipeline = reduce_pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the clear of the pyramid to the far plane and its transition
+    /// to the `GENERAL` layout it stays in.
+    pub fn cmd_clear(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let pyramid = self.pyramid.as_ref().unwrap();
+        let range = self.pyramid_range();
+        let mut image = TrackedImage::new(pyramid.image, range, ImageState::undefined());
+        image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+        unsafe {
+            device.cmd_clear_color_image(
+                command_buffer,
+                pyramid.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue {
+                    float32: [1.0, 1.0, 0.0, 0.0],
+                },
+                &[range],
+            )
+        };
+        image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+    }
+
+    /// Record the reduction of the depth buffer into the pyramid.
+    ///
+    /// It must be recorded after the main render pass, outside of any render
+    /// pass. The depth buffer is then in the `DEPTH_STENCIL_READ_ONLY_OPTIMAL`
+    /// layout.
+    pub fn cmd_build(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let mut depth_image = TrackedImage::new(
+            self.depth_image,
+            vk::ImageSubresourceRange {
+                aspect_mask: self.depth_aspect,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ImageState {
+                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                stage: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            },
+        );
+        depth_image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        let group_count = |size: u32| size.div_ceil(HIZ_GROUP_SIZE);
+        let mut extent = pyramid_extent(self.depth_extent);
+        unsafe {
+            // The culling of this frame and the reflections of the previous
+            // one read the previous pyramid
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(
+                    Access::compute_read().and(Access::fragment_read()),
+                    Access::compute_write(),
+                )],
+            );
+            for (level, set) in self.sets[..self.level_views.len()].iter().enumerate() {
+                if level == 0 {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        self.depth_pipeline,
+                    );
+                } else {
+                    cmd_barriers(
+                        device,
+                        command_buffer,
+                        &[Barrier::memory(
+                            Access::compute_write(),
+                            Access::compute_read(),
+                        )],
+                    );
+                    if level == 1 {
+                        device.cmd_bind_pipeline(
+                            command_buffer,
+                            vk::PipelineBindPoint::COMPUTE,
+                            self.reduce_pipeline,
+                        );
+                    }
+                }
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &[*set],
+                    &[],
+                );
+                device.cmd_dispatch(
+                    command_buffer,
+                    group_count(extent.width),
+                    group_count(extent.height),
+                    1,
+                );
+                extent = pyramid_extent(extent);
+            }
+            // The reflections of this frame trace rays through the pyramid
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(
+                    Access::compute_write(),
+                    Access::fragment_read(),
+                )],
+            );
+        }
+    }
+
+    fn pyramid_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: self.level_views.len() as _,
+            base_array_layer: 0,
+            layer_count: 1,
+        }
+    }
+
+    /// Destroy the pyramid and its views.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pyramid(&mut self, device: &Device) {
+        unsafe {
+            self.level_views
+                .drain(..)
+                .for_each(|view| device.destroy_image_view(view, None));
+        }
+        if let Some(mut pyramid) = self.pyramid.take() {
+            pyramid.destroy(device);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pyramid(device);
+        unsafe {
+            lifetime::destroy_pipeline(device, self.depth_pipeline);
+            lifetime::destroy_pipeline(device, self.reduce_pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_sampler(self.sampler, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+/// Occlusion culling of indirect draws against the `DepthPyramid`.
+///
+/// Before the main render pass, a compute shader projects the bounds of each
+/// object drawn by an indirect draw with the view projection of the previous
+/// frame, whose depth buffer the pyramid was reduced from. It reads the level
+/// of the pyramid where the bounds cover at most two by two texels, and clears
+/// the instance count of the draw if the box is behind the furthest depth of
+/// all of them. So an object appearing from behind an occluder is only drawn
+/// one frame late.
+///
+/// The number of objects occluded is counted in a buffer per swapchain image,
+/// copied to a `ReadbackBuffer` and read back by `update` a few frames later.
+pub struct HiZ {
+    depth_extent: vk::Extent2D,
+    bounds_buffer: Buffer,
+    object_count: u32,
+    uniform_buffers: Vec<Buffer>,
+    stats_buffers: Vec<Buffer>,
+    stats: ReadbackBuffer<u32>,
+    view_proj: Option<Matrix4<f32>>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl HiZ {
+    /// Create the descriptor sets culling the draws of `draw_buffers`.
+    ///
+    /// Each draw buffer holds one `vk::DrawIndexedIndirectCommand` per box of
+    /// `bounds`, and must have the `STORAGE_BUFFER` usage. There is one per
+    /// swapchain image, like the uniform buffers which must be host visible
+    /// and coherent and the stats buffers which must be storage buffers of a
+    /// `u32` with the `TRANSFER_SRC` and `TRANSFER_DST` usages. `stats` reads
+    /// back a single `u32` per swapchain image. `bounds_buffer` must be a host
+    /// visible and coherent storage buffer of `HiZ::bounds_buffer_size(bounds)`
+    /// bytes.
+    ///
+    /// The pyramid must be set using `set_pyramid` and the pipeline using
+    /// `set_pipeline` before recording commands.
+    pub fn new(
+        device: &Device,
+        bounds: &[Aabb],
+        bounds_buffer: Buffer,
+        draw_buffers: &[Buffer],
+        uniform_buffers: Vec<Buffer>,
+        (stats_buffers, stats): (Vec<Buffer>, ReadbackBuffer<u32>),
+    ) -> Self {
+        let corners = bounds
+            .iter()
+            .flat_map(|aabb| {
+                vec![
+                    [aabb.min.x, aabb.min.y, aabb.min.z, 0.0],
+                    [aabb.max.x, aabb.max.y, aabb.max.z, 0.0],
+                ]
+            })
+            .collect::<Vec<_>>();
+        bounds_buffer.write(device, 0, &corners);
+
+        let image_count = uniform_buffers.len();
+        let layout = Self::create_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let buffer_info = |buffer: &Buffer, size| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size as _)
+                .build()]
+        };
+        let bounds_infos = buffer_info(&bounds_buffer, size_of_val(corners.as_slice()));
+        let draws_size = size_of::<vk::DrawIndexedIndirectCommand>() * bounds.len();
+        for (i, set) in sets.iter().enumerate() {
+            let uniforms_infos = buffer_info(&uniform_buffers[i], size_of::<HiZUniforms>());
+            let draws_infos = buffer_info(&draw_buffers[i], draws_size);
+            let stats_infos = buffer_info(&stats_buffers[i], size_of::<u32>());
+            let write = |binding, descriptor_type| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(descriptor_type)
+            };
+            let descriptor_writes = [
+                write(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&uniforms_infos)
+                    .build(),
+                write(BOUNDS_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&bounds_infos)
+                    .build(),
+                write(DRAWS_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&draws_infos)
+                    .build(),
+                write(STATS_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&stats_infos)
+                    .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        HiZ {
+            depth_extent: vk::Extent2D::default(),
+            bounds_buffer,
+            object_count: bounds.len() as _,
+            uniform_buffers,
+            stats_buffers,
+            stats,
+            view_proj: None,
+            layout,
+            pool,
+            sets,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Size in bytes of the buffer holding `bounds`.
+    pub fn bounds_buffer_size(bounds: &[Aabb]) -> vk::DeviceSize {
+        (size_of::<[f32; 4]>() * 2 * bounds.len()) as _
+    }
+
+    fn create_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [
+            binding(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER),
+            binding(PYRAMID_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            binding(BOUNDS_BINDING, vk::DescriptorType::STORAGE_BUFFER),
+            binding(DRAWS_BINDING, vk::DescriptorType::STORAGE_BUFFER),
+            binding(STATS_BINDING, vk::DescriptorType::STORAGE_BUFFER),
+        ];
+        create_set_layout(device, &bindings)
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: image_count * 3,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl HiZ {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Set the pyramid the draws are culled against.
+    ///
+    /// It must be called again when the pyramid is recreated with the swapchain.
+    pub fn set_pyramid(&mut self, device: &Device, pyramid: &DepthPyramid) {
+        self.depth_extent = pyramid.depth_extent();
+        let texture = pyramid.texture();
+        let pyramid_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build()];
+        let descriptor_writes = self
+            .sets
+            .iter()
+            .map(|set| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(PYRAMID_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&pyramid_infos)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        // The new depth buffer does not match the last view projection
+        self.view_proj = None;
+    }
+
+    /// Set the compute pipeline culling the draws.
+    ///
+    /// The hierarchical depth buffer takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index` and get
+    /// the number of objects occluded the last time its commands were executed,
+    /// or 0 before they were.
+    ///
+    /// `view_proj` is the view projection of the frame, used to cull the
+    /// draws of the next one since the pyramid is built at its end.
+    pub fn update(&mut self, device: &Device, image_index: usize, view_proj: Matrix4<f32>) -> u32 {
+        let occluded_count = self
+            .stats
+            .read(device, image_index)
+            .map_or(0, |stats| stats[0]);
+
+        // Nothing is culled until a pyramid has been built
+        let uniforms = HiZUniforms {
+            view_proj: self.view_proj.unwrap_or(view_proj),
+            depth_size: [self.depth_extent.width as _, self.depth_extent.height as _],
+            object_count: if self.view_proj.is_some() {
+                self.object_count
+            } else {
+                0
+            },
+            level_count: pyramid_level_count(self.depth_extent),
+        };
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+        self.view_proj = Some(view_proj);
+
+        occluded_count
+    }
+
+    /// Record the culling of the draws of the swapchain image `image_index`.
+    ///
+    /// It must be recorded before the draws, outside of any render pass.
+    pub fn cmd_cull(&self, device: &Device, command_buffer: vk::CommandBuffer, image_index: usize) {
+        let stats_buffer = self.stats_buffers[image_index].buffer;
+        unsafe {
+            // The pyramid is built by the previous frame, which also read
+            // back the stats
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(
+                    Access::new(
+                        vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+                        vk::AccessFlags::SHADER_WRITE,
+                    ),
+                    Access::new(
+                        vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+                        vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                )],
+            );
+            device.cmd_fill_buffer(command_buffer, stats_buffer, 0, vk::WHOLE_SIZE, 0);
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(
+                    Access::transfer_write(),
+                    Access::new(
+                        vk::PipelineStageFlags::COMPUTE_SHADER,
+                        vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                    ),
+                )],
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &self.sets[image_index..=image_index],
+                &[],
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                self.object_count.div_ceil(HIZ_CULL_GROUP_SIZE),
+                1,
+                1,
+            );
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(
+                    Access::compute_write(),
+                    Access::indirect_read().and(Access::transfer_read()),
+                )],
+            );
+        }
+        self.stats
+            .cmd_copy(device, command_buffer, image_index, (stats_buffer, 0));
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .chain(self.stats_buffers.iter_mut())
+            .for_each(|buffer| buffer.destroy(device));
+        self.stats.destroy(device);
+        self.bounds_buffer.destroy(device);
+    }
+}
+
+fn binding(binding: u32, descriptor_type: vk::DescriptorType) -> vk::DescriptorSetLayoutBinding {
+    vk::DescriptorSetLayoutBinding::builder()
+        .binding(binding)
+        .descriptor_count(1)
+        .descriptor_type(descriptor_type)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .build()
+}
+
+fn create_set_layout(
+    device: &Device,
+    bindings: &[vk::DescriptorSetLayoutBinding],
+) -> vk::DescriptorSetLayout {
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(bindings)
+        .build();
+
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+use winit::VirtualKeyCode;
+
+/// Keys handled by the app, the only ones recorded.
+///
+/// Keys bound to new actions must be added so they are played back.
+const RECORDED_KEYS: [VirtualKeyCode; 13] = [
+    VirtualKeyCode::Grave,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::N,
+    VirtualKeyCode::T,
+    VirtualKeyCode::R,
+    VirtualKeyCode::E,
+    VirtualKeyCode::X,
+    VirtualKeyCode::F,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Period,
+    VirtualKeyCode::LBracket,
+    VirtualKeyCode::RBracket,
+];
+
+/// Input of the main window gathered during a frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameInput {
+    /// Time since the previous frame in seconds.
+    pub delta: f32,
+    pub close_requested: bool,
+    pub resize: Option<[u32; 2]>,
+    /// Whether the left button was pressed or released.
+    pub left_click: Option<bool>,
+    /// The right button was pressed to pick an object.
+    pub pick: bool,
+    pub cursor_position: Option<[i32; 2]>,
+    /// A touch started at the cursor position, so the cursor did not move.
+    pub touch_started: bool,
+    pub wheel_delta: Option<f32>,
+    pub pressed_keys: Vec<VirtualKeyCode>,
+    pub received_chars: Vec<char>,
+}
+
+impl FrameInput {
+    /// Write the input as a line of space separated fields, starting with the delta.
+    ///
+    /// The keys which are not handled by the app are left out.
+    fn to_line(&self) -> String {
+        let mut line = format!("{}", self.delta);
+        if self.close_requested {
+            line.push_str(" close");
+        }
+        if let Some([width, height]) = self.resize {
+            let _ = write!(line, " resize={},{}", width, height);
+        }
+        if let Some(pressed) = self.left_click {
+            let _ = write!(line, " click={}", pressed as u8);
+        }
+        if self.pick {
+            line.push_str(" pick");
+        }
+        if let Some([x, y]) = self.cursor_position {
+            let _ = write!(line, " cursor={},{}", x, y);
+        }
+        if self.touch_started {
+            line.push_str(" touch");
+        }
+        if let Some(delta) = self.wheel_delta {
+            let _ = write!(line, " wheel={}", delta);
+        }
+        for key in self
+            .pressed_keys
+            .iter()
+            .filter(|key| RECORDED_KEYS.contains(key))
+        {
+            let _ = write!(line, " key={:?}", key);
+        }
+        for c in &self.received_chars {
+            let _ = write!(line, " char={}", *c as u32);
+        }
+        line
+    }
+
+    /// Parse a line written by `to_line`.
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut fields = line.split_whitespace();
+        let delta = fields.next().ok_or("Missing frame delta")?;
+        let mut input = FrameInput {
+            delta: delta
+                .parse()
+                .map_err(|_| format!("Invalid frame delta {}", delta))?,
+            ..Default::default()
+        };
+        for field in fields {
+            let (name, value) = match field.find('=') {
+                Some(index) => (&field[..index], &field[index + 1..]),
+                None => (field, ""),
+            };
+            let invalid = || format!("Invalid field {}", field);
+            match name {
+                "close" => input.close_requested = true,
+                "resize" => input.resize = Some(parse_pair(value).ok_or_else(invalid)?),
+                "click" => input.left_click = Some(value == "1"),
+                "pick" => input.pick = true,
+                "cursor" => input.cursor_position = Some(parse_pair(value).ok_or_else(invalid)?),
+                "touch" => input.touch_started = true,
+                "wheel" => input.wheel_delta = Some(value.parse().map_err(|_| invalid())?),
+                "key" => {
+                    let key = RECORDED_KEYS
+                        .iter()
+                        .find(|key| format!("{:?}", key) == value)
+                        .ok_or_else(invalid)?;
+                    input.pressed_keys.push(*key);
+                }
+                "char" => {
+                    let c = value
+                        .parse()
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .ok_or_else(invalid)?;
+                    input.received_chars.push(c);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(input)
+    }
+}
+
+/// Parse two comma separated values.
+fn parse_pair<T: std::str::FromStr>(text: &str) -> Option<[T; 2]> {
+    let mut values = text.split(',').map(str::parse);
+    match (values.next(), values.next(), values.next()) {
+        (Some(Ok(x)), Some(Ok(y)), None) => Some([x, y]),
+        _ => None,
+    }
+}
+
+/// Input of each frame written to a file so the session can be played back.
+pub struct InputRecorder {
+    file: BufWriter<File>,
+}
+
+impl InputRecorder {
+    /// Create the recording at `path`, replacing any existing file.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        log::info!("Recording the input to {}.", path.display());
+        Ok(InputRecorder { file })
+    }
+
+    /// Write the input of a frame.
+    ///
+    /// The frames are flushed as they are recorded so a crash keeps them.
+    pub fn record(&mut self, input: &FrameInput) {
+        let result = writeln!(self.file, "{}", input.to_line()).and_then(|_| self.file.flush());
+        if let Err(error) = result {
+            log::error!("Failed to record the input: {}.", error);
+        }
+    }
+}
+
+/// Input of the frames of a recording, played back in place of the window events.
+pub struct InputPlayback {
+    frames: VecDeque<FrameInput>,
+}
+
+impl InputPlayback {
+    /// Load the recording at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let frames = fs::read_to_string(path)?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                FrameInput::parse(line).map_err(|error| {
+                    let message = format!("{} at line {}", error, index + 1);
+                    io::Error::new(io::ErrorKind::InvalidData, message)
+                })
+            })
+            .collect::<io::Result<VecDeque<_>>>()?;
+        log::info!(
+            "Playing back {} frames of input from {}.",
+            frames.len(),
+            path.display()
+        );
+        Ok(InputPlayback { frames })
+    }
+
+    /// Take the input of the next frame, or `None` at the end of the recording.
+    pub fn next_frame(&mut self) -> Option<FrameInput> {
+        self.frames.pop_front()
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{gpu_device::Device, gpu_device::GpuDevice};
+use ash::{version::DeviceV1_0, vk, vk::Handle};
+use std::{fmt, panic::Location, sync::Mutex};
+
+/// Objects created with `track` or the functions of this module and not destroyed yet.
+///
+/// Each device has its own, given by `GpuDevice::objects`.
+#[derive(Default)]
+pub struct ObjectRegistry {
+    objects: Mutex<Vec<TrackedObject>>,
+}
+
+/// Type of the Vulkan objects whose lifetime is tracked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectKind {
+    Buffer,
+    Image,
+    Pipeline,
+    DescriptorPool,
+}
+
+struct TrackedObject {
+    kind: ObjectKind,
+    handle: u64,
+    /// Name given when the object was created, the shaders of a pipeline or
+    /// the code creating a descriptor pool for instance.
+    name: String,
+}
+
+impl fmt::Display for TrackedObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {:#x} ({})", self.kind, self.handle, self.name)
+    }
+}
+
+/// Register `handle` as created, named `name` in the logs.
+///
+/// The name is also given to the object with the `debug-markers` feature.
+pub fn track<H: Handle + Copy>(
+    device: &impl GpuDevice,
+    kind: ObjectKind,
+    handle: H,
+    name: impl fmt::Display,
+) {
+    let object = TrackedObject {
+        kind,
+        handle: handle.as_raw(),
+        name: name.to_string(),
+    };
+    log::trace!("Created {}.", object);
+    #[cfg(feature = "debug-markers")]
+    crate::debug_markers::set_object_name(handle, &object.name);
+    device.objects().objects.lock().unwrap().push(object);
+}
+
+/// Register `handle` as destroyed.
+///
+/// A warning is logged if it was not tracked or was already destroyed.
+fn untrack<H: Handle>(device: &impl GpuDevice, kind: ObjectKind, handle: H) {
+    let handle = handle.as_raw();
+    let mut objects = device.objects().objects.lock().unwrap();
+    match objects
+        .iter()
+        .position(|object| object.kind == kind && object.handle == handle)
+    {
+        // Not removed in the arguments of the log, which are not evaluated when it is disabled
+        Some(index) => {
+            let object = objects.swap_remove(index);
+            log::trace!("Destroyed {}.", object);
+        }
+        // Null handles can be destroyed, pipelines are before they are set
+        None if handle != 0 => log::warn!("Destroyed untracked {:?} {:#x}.", kind, handle),
+        None => {}
+    }
+}
+
+/// Create a descriptor pool named after the code calling the function.
+#[track_caller]
+pub fn create_descriptor_pool(
+    device: &impl GpuDevice,
+    pool_info: &vk::DescriptorPoolCreateInfo,
+) -> ash::prelude::VkResult<vk::DescriptorPool> {
+    let pool = unsafe { device.create_descriptor_pool(pool_info)? };
+    track(device, ObjectKind::DescriptorPool, pool, Location::caller());
+    Ok(pool)
+}
+
+/// Destroy `buffer` created with `track`.
+pub fn destroy_buffer(device: &impl GpuDevice, buffer: vk::Buffer) {
+    untrack(device, ObjectKind::Buffer, buffer);
+    unsafe { device.destroy_buffer(buffer) };
+}
+
+/// Destroy `image` created with `track`.
+pub fn destroy_image(device: &impl GpuDevice, image: vk::Image) {
+    untrack(device, ObjectKind::Image, image);
+    unsafe { device.destroy_image(image) };
+}
+
+/// Destroy `pipeline` created with `track`.
+pub fn destroy_pipeline(device: &Device, pipeline: vk::Pipeline) {
+    untrack(device, ObjectKind::Pipeline, pipeline);
+    unsafe { device.destroy_pipeline(pipeline, None) };
+}
+
+/// Destroy `pool` created with `create_descriptor_pool`.
+pub fn destroy_descriptor_pool(device: &impl GpuDevice, pool: vk::DescriptorPool) {
+    untrack(device, ObjectKind::DescriptorPool, pool);
+    unsafe { device.destroy_descriptor_pool(pool) };
+}
+
+/// Log the objects of `device` which were not destroyed and forget them.
+///
+/// It must be called once everything created with the device was destroyed,
+/// before the device itself is.
+///
+/// # Returns
+///
+/// The number of objects leaked.
+pub fn report_leaks(device: &impl GpuDevice) -> usize {
+    let leaks = std::mem::take(&mut *device.objects().objects.lock().unwrap());
+    for object in &leaks {
+        log::warn!("Leaked {}.", object);
+    }
+    if leaks.is_empty() {
+        log::debug!("No Vulkan object leaked.");
+    } else {
+        log::warn!("{} Vulkan objects leaked.", leaks.len());
+    }
+    leaks.len()
+}
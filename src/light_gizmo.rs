@@ -0,0 +1,133 @@
+use crate::{
+    debug_draw::DebugDraw,
+    math::{Aabb, Ray, Transform},
+    scene::{Entity, Light, LightKind, Scene},
+    sprite::{Sprite, LIGHT_TILE},
+};
+use cgmath::{prelude::*, Point3, Vector3};
+
+/// Width and height of the icons of the lights in world units, also the size
+/// of the box picking them.
+const ICON_SIZE: f32 = 0.3;
+
+/// Distance from the origin of the arrows of directional lights without transform.
+const DIRECTIONAL_DISTANCE: f32 = 3.0;
+
+/// Length of the arrows of directional lights.
+const ARROW_LENGTH: f32 = 1.0;
+
+/// Number of lines from the apex of a spot light to the base of its cone.
+const CONE_LINES: usize = 8;
+
+const LIGHT_COLOR: [f32; 4] = [1.0, 0.85, 0.3, 1.0];
+const SELECTED_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Get the position of the icon of `light`.
+///
+/// Lights are placed by the transform of their entity. Directional lights
+/// without one are shown in their direction from the origin.
+fn icon_position(transform: Option<&Transform>, light: &Light) -> Point3<f32> {
+    match transform {
+        Some(transform) => Point3::from_vec(transform.translation),
+        None => Point3::origin() + light.direction.normalize() * DIRECTIONAL_DISTANCE,
+    }
+}
+
+/// Iterate over the lights of `scene` with the position of their icon and their transform.
+fn lights(
+    scene: &Scene,
+) -> impl Iterator<Item = (Entity, &Light, Point3<f32>, Option<&Transform>)> {
+    scene.iter::<Light>().map(move |(entity, light)| {
+        let transform = scene.get::<Transform>(entity);
+        (entity, light, icon_position(transform, light), transform)
+    })
+}
+
+/// Draw the shape of each light of `scene` with `debug_draw`.
+///
+/// Point lights are drawn as a sphere of their radius, spot lights as their
+/// cone and directional lights as an arrow in the direction of their rays.
+/// The `selected` light is highlighted.
+pub fn draw_light_gizmos(debug_draw: &mut DebugDraw, scene: &Scene, selected: Option<Entity>) {
+    for (entity, light, position, transform) in lights(scene) {
+        let color = if selected == Some(entity) {
+            SELECTED_COLOR
+        } else {
+            LIGHT_COLOR
+        };
+        match light.kind {
+            LightKind::Directional => {
+                let direction = -light.direction.normalize();
+                let tip = position + direction * ARROW_LENGTH;
+                debug_draw.line(position, tip, color);
+                debug_draw.circle(position, direction, ICON_SIZE * 0.5, color);
+                let side = perpendicular(direction) * (ARROW_LENGTH * 0.1);
+                let base = tip - direction * (ARROW_LENGTH * 0.2);
+                debug_draw.line(tip, base + side, color);
+                debug_draw.line(tip, base - side, color);
+            }
+            LightKind::Point { radius } => {
+                for axis in &[Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+                    debug_draw.circle(position, *axis, radius, color);
+                }
+            }
+            LightKind::Spot { range, angle } => {
+                let rotation = transform.map(|transform| transform.rotation);
+                let axis = rotation.map_or(-Vector3::unit_y(), |rotation| {
+                    rotation.rotate_vector(-Vector3::unit_y())
+                });
+                let base = position + axis * range;
+                let base_radius = range * angle.tan();
+                debug_draw.circle(base, axis, base_radius, color);
+                let tangent = perpendicular(axis);
+                let bitangent = axis.cross(tangent);
+                for i in 0..CONE_LINES {
+                    let theta = i as f32 / CONE_LINES as f32 * 2.0 * std::f32::consts::PI;
+                    let edge = (tangent * theta.cos() + bitangent * theta.sin()) * base_radius;
+                    debug_draw.line(position, base + edge, color);
+                }
+            }
+        }
+    }
+}
+
+/// Get an icon sprite for each light of `scene`.
+pub fn light_sprites(scene: &Scene) -> Vec<Sprite> {
+    lights(scene)
+        .map(|(_, _, position, _)| Sprite {
+            position,
+            size: [ICON_SIZE, ICON_SIZE],
+            tile: LIGHT_TILE,
+            color: LIGHT_COLOR,
+            upright: false,
+        })
+        .collect()
+}
+
+/// Intersect the world space `ray` with the icons of the lights of `scene`.
+///
+/// # Returns
+///
+/// The closest light hit and the distance along the ray.
+pub fn pick_light(scene: &Scene, ray: &Ray) -> Option<(Entity, f32)> {
+    lights(scene)
+        .filter_map(|(entity, _, position, _)| {
+            let half_size = Vector3::new(1.0, 1.0, 1.0) * (ICON_SIZE * 0.5);
+            let bounds = Aabb {
+                min: position - half_size,
+                max: position + half_size,
+            };
+            Some((entity, bounds.intersect_ray(ray)?))
+        })
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+}
+
+/// Get a unit vector perpendicular to the unit vector `direction`.
+fn perpendicular(direction: Vector3<f32>) -> Vector3<f32> {
+    let reference = if direction.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    direction.cross(reference).normalize()
+}
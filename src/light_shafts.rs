@@ -0,0 +1,370 @@
+use crate::{
+    buffer::Buffer, frame_layout::PASS_SET, gpu_device::Device, half_res::BilateralUpsample,
+    lifetime, render_target::RenderTarget, std140::std140_struct, texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{Matrix4, Vector3};
+use std::mem::size_of;
+
+const UNIFORMS_BINDING: u32 = 0;
+const HDR_COLOR_BINDING: u32 = 1;
+
+/// Parameters of the light shafts.
+#[derive(Clone, Copy, Debug)]
+pub struct LightShaftParameters {
+    /// Fraction of the distance to the sun covered by the samples.
+    pub density: f32,
+    /// Attenuation of each sample relative to the previous one, closer to the pixel.
+    pub decay: f32,
+    /// Weight of each sample.
+    pub weight: f32,
+    /// Factor applied to the shafts before adding them to the image.
+    pub intensity: f32,
+    /// Luminance below which pixels do not emit shafts.
+    pub threshold: f32,
+    /// Henyey-Greenstein asymmetry of the scattering, from -1 (backward) to
+    /// 1 (forward). 0 scatters the same in all directions.
+    pub anisotropy: f32,
+}
+
+impl Default for LightShaftParameters {
+    fn default() -> Self {
+        LightShaftParameters {
+            density: 0.9,
+            decay: 0.97,
+            weight: 0.04,
+            intensity: 1.0,
+            threshold: 0.8,
+            anisotropy: 0.6,
+        }
+    }
+}
+
+std140_struct! {
+    /// Uniforms of the light shafts shader as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct LightShaftUniforms {
+        pub sun_direction: [f32; 4],
+        /// Position of the sun in texture coordinates.
+        pub sun_coords: [f32; 2],
+        /// 0 when the sun is behind the camera, 1 otherwise.
+        pub sun_visibility: f32,
+        pub density: f32,
+        pub decay: f32,
+        pub weight: f32,
+        pub intensity: f32,
+        pub threshold: f32,
+        pub anisotropy: f32,
+    }
+}
+
+/// Light shafts scattered from the sun, computed in screen space.
+///
+/// After the main render pass, each pixel of the HDR image gathers samples on
+/// the segment going toward the sun on screen. Samples brighter than a
+/// threshold, like the sky, add light which decays along the segment, so
+/// objects in front of a bright sky cast shafts. The result is weighted by a
+/// Henyey-Greenstein phase function of the angle between the view ray and the
+/// sun, added to the image and written to its own HDR target, before
+/// tonemapping.
+///
+/// There is no shadow map so occlusion only comes from what is visible on screen.
+///
+/// At half resolution, the shafts are rendered alone and added to the image by
+/// a bilateral upsample.
+pub struct LightShafts {
+    parameters: LightShaftParameters,
+    target: RenderTarget,
+    upsample: Option<BilateralUpsample>,
+    sampler: vk::Sampler,
+    uniform_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl LightShafts {
+    /// Create the descriptor sets of the light shafts pass.
+    ///
+    /// `target` must have a single HDR color attachment. Uniform buffers must
+    /// be host visible and coherent, one per swapchain image. With `upsample`,
+    /// `target` is at half resolution and only receives the shafts.
+    ///
+    /// The input image must be set using `set_hdr_texture` and the pipeline
+    /// using `set_pipeline` before recording the pass, as well as the depth
+    /// buffer and the pipeline of the upsample.
+    pub fn new(
+        device: &Device,
+        parameters: LightShaftParameters,
+        target: RenderTarget,
+        uniform_buffers: Vec<Buffer>,
+        upsample: Option<BilateralUpsample>,
+    ) -> Self {
+        let image_count = uniform_buffers.len();
+        let sampler = Self::create_sampler(device);
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        for (set, buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let buffer_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size_of::<LightShaftUniforms>() as _)
+                .build()];
+            let descriptor_writes = [vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(UNIFORMS_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_infos)
+                .build()];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        LightShafts {
+            parameters,
+            target,
+            upsample,
+            sampler,
+            uniform_buffers,
+            layout,
+            pool,
+            sets,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(UNIFORMS_BINDING)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(HDR_COLOR_BINDING)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl LightShafts {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.target.render_pass()
+    }
+
+    /// Get the HDR texture with the light shafts.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the pass.
+    pub fn output(&self) -> Texture {
+        match self.upsample.as_ref() {
+            Some(upsample) => upsample.output(),
+            None => self.target.color(0),
+        }
+    }
+
+    /// Get the target so it can be resized.
+    ///
+    /// `set_hdr_texture` must be called after resizing it.
+    pub fn target_mut(&mut self) -> &mut RenderTarget {
+        &mut self.target
+    }
+
+    /// Get the upsample of the shafts if they are rendered at half resolution.
+    pub fn upsample_mut(&mut self) -> Option<&mut BilateralUpsample> {
+        self.upsample.as_mut()
+    }
+
+    /// Set the HDR image the shafts are added to.
+    ///
+    /// It must be in `hdr_layout` and readable by fragment shaders when the pass begins.
+    pub fn set_hdr_texture(&self, device: &Device, (hdr, hdr_layout): (Texture, vk::ImageLayout)) {
+        if let Some(upsample) = self.upsample.as_ref() {
+            upsample.set_inputs(device, (hdr, hdr_layout), self.target.color(0));
+        }
+        let image_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(hdr_layout)
+            .image_view(hdr.view)
+            .sampler(self.sampler)
+            .build()];
+        let descriptor_writes = self
+            .sets
+            .iter()
+            .map(|set| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(HDR_COLOR_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&image_infos)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index`.
+    ///
+    /// `sun_direction` points toward the sun.
+    pub fn update(
+        &self,
+        device: &Device,
+        image_index: usize,
+        view_proj: Matrix4<f32>,
+        sun_direction: Vector3<f32>,
+    ) {
+        // The sun is infinitely far so only the rotation of the camera moves it on screen
+        let sun_clip = view_proj * sun_direction.extend(0.0);
+        let (sun_coords, sun_visibility) = if sun_clip.w > 0.0 {
+            let coords = [
+                sun_clip.x / sun_clip.w * 0.5 + 0.5,
+                sun_clip.y / sun_clip.w * 0.5 + 0.5,
+            ];
+            (coords, 1.0)
+        } else {
+            ([0.5, 0.5], 0.0)
+        };
+
+        let parameters = self.parameters;
+        let uniforms = LightShaftUniforms {
+            sun_direction: sun_direction.extend(0.0).into(),
+            sun_coords,
+            sun_visibility,
+            density: parameters.density,
+            decay: parameters.decay,
+            weight: parameters.weight,
+            intensity: parameters.intensity,
+            threshold: parameters.threshold,
+            anisotropy: parameters.anisotropy,
+        };
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+    }
+
+    /// Record the light shafts pass of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    /// It must be recorded outside of any render pass, after the main render pass.
+    pub fn cmd_draw(&self, device: &Device, command_buffer: vk::CommandBuffer, image_index: usize) {
+        self.target.cmd_begin(device, command_buffer);
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                PASS_SET,
+                &[self.sets[image_index]],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+        self.target.cmd_end(device, command_buffer);
+        if let Some(upsample) = self.upsample.as_ref() {
+            upsample.cmd_draw(device, command_buffer);
+        }
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+        if let Some(upsample) = self.upsample.as_mut() {
+            upsample.destroy_pipeline(device);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        self.target.destroy(device);
+        if let Some(upsample) = self.upsample.as_mut() {
+            upsample.destroy(device);
+        }
+    }
+}
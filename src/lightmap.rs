@@ -0,0 +1,707 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    gpu_device::Device,
+    lifetime,
+    math::Aabb,
+    reflect,
+    render_target::RenderTarget,
+    std140::std140_struct,
+    texture::{Texture, TextureDesc},
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Matrix4, Vector3};
+use std::mem::size_of;
+
+/// Width and height in texels of the lightmap.
+pub const LIGHTMAP_SIZE: u32 = 1024;
+
+/// Format of the lightmap, storing the irradiance divided by pi.
+pub const LIGHTMAP_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Width and height in texels of the visibility map of each direction.
+pub const VISIBILITY_SIZE: u32 = 512;
+
+/// Format of the visibility maps, storing the depth of the closest surface.
+pub const VISIBILITY_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+/// Number of directions the sky light is sampled from.
+pub const SKY_SAMPLE_COUNT: usize = 48;
+
+/// Number of directions the model is lit from while baking, the sky samples and the sun.
+///
+/// Must match `MAX_DIRECTIONS` in `lightmap_bake.glsl`.
+pub const BAKE_DIRECTION_COUNT: usize = SKY_SAMPLE_COUNT + 1;
+
+/// Binding of the lightmap coordinates, the second UV set of the model.
+pub const LIGHTMAP_COORDS_BINDING: u32 = 1;
+
+/// Location of the lightmap coordinates in the vertex shaders.
+pub const LIGHTMAP_COORDS_LOCATION: u32 = 4;
+
+/// Radiance of the sky, uniform over the upper hemisphere.
+const SKY_RADIANCE: [f32; 3] = [0.35, 0.4, 0.45];
+
+/// Irradiance of the sun divided by pi, on a surface facing it.
+const SUN_IRRADIANCE: [f32; 3] = [0.9, 0.85, 0.75];
+
+/// Shaders rendering the visibility maps.
+pub const VISIBILITY_SHADERS: [(vk::ShaderStageFlags, &str); 2] = [
+    (
+        vk::ShaderStageFlags::VERTEX,
+        "shaders/lightmap_visibility.vert.spv",
+    ),
+    (
+        vk::ShaderStageFlags::FRAGMENT,
+        "shaders/lightmap_visibility.frag.spv",
+    ),
+];
+
+/// Shaders baking the lightmap.
+pub const BAKE_SHADERS: [(vk::ShaderStageFlags, &str); 2] = [
+    (
+        vk::ShaderStageFlags::VERTEX,
+        "shaders/lightmap_bake.vert.spv",
+    ),
+    (
+        vk::ShaderStageFlags::FRAGMENT,
+        "shaders/lightmap_bake.frag.spv",
+    ),
+];
+
+/// Shaders dilating the lightmap.
+pub const DILATE_SHADERS: [(vk::ShaderStageFlags, &str); 2] = [
+    (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+    (
+        vk::ShaderStageFlags::FRAGMENT,
+        "shaders/lightmap_dilate.frag.spv",
+    ),
+];
+
+const UNIFORMS_BINDING: u32 = 0;
+const VISIBILITY_BINDING: u32 = 1;
+const BAKED_BINDING: u32 = 2;
+
+std140_struct! {
+    /// Uniforms of the bake as laid out in the uniform buffer.
+    ///
+    /// Must match `lightmap_bake.glsl`.
+    #[derive(Clone, Copy)]
+    pub struct BakeUniforms {
+        pub model: Matrix4<f32>,
+        /// Orthographic view projection of the visibility map of each direction.
+        pub view_projs: [Matrix4<f32>; BAKE_DIRECTION_COUNT],
+        /// Directions pointing toward the light, in the xyz components.
+        pub directions: [[f32; 4]; BAKE_DIRECTION_COUNT],
+        /// Light received from each direction, in the rgb components.
+        pub radiances: [[f32; 4]; BAKE_DIRECTION_COUNT],
+    }
+}
+
+/// Get the description of the binding of the lightmap coordinates.
+pub fn lightmap_coords_binding_description() -> vk::VertexInputBindingDescription {
+    vk::VertexInputBindingDescription::builder()
+        .binding(LIGHTMAP_COORDS_BINDING)
+        .stride(size_of::<[f32; 2]>() as _)
+        .input_rate(vk::VertexInputRate::VERTEX)
+        .build()
+}
+
+/// Get the description of the lightmap coordinates attribute.
+pub fn lightmap_coords_attribute_description() -> vk::VertexInputAttributeDescription {
+    vk::VertexInputAttributeDescription::builder()
+        .binding(LIGHTMAP_COORDS_BINDING)
+        .location(LIGHTMAP_COORDS_LOCATION)
+        .format(vk::Format::R32G32_SFLOAT)
+        .offset(0)
+        .build()
+}
+
+/// Get `SKY_SAMPLE_COUNT` directions evenly spread over the upper hemisphere.
+///
+/// The directions follow a Fibonacci spiral whose height is uniform, so each
+/// covers the same solid angle.
+pub fn sky_directions() -> Vec<Vector3<f32>> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+    (0..SKY_SAMPLE_COUNT)
+        .map(|i| {
+            let height = (i as f32 + 0.5) / SKY_SAMPLE_COUNT as f32;
+            let radius = (1.0 - height * height).sqrt();
+            let angle = golden_angle * i as f32;
+            Vector3::new(radius * angle.cos(), height, radius * angle.sin())
+        })
+        .collect()
+}
+
+/// Get the uniforms baking the light of the sky and of the sun toward `sun_direction`
+/// received by the model placed by `model` and whose world space bounds are `bounds`.
+///
+/// Each sky sample receives an equal share of the irradiance of the sky, so
+/// their sum is the sky irradiance over pi on an unoccluded surface facing up.
+pub fn bake_uniforms(
+    model: Matrix4<f32>,
+    bounds: Aabb,
+    sun_direction: Vector3<f32>,
+) -> BakeUniforms {
+    let sky_weight = 2.0 / SKY_SAMPLE_COUNT as f32;
+    let sky_radiance = [
+        SKY_RADIANCE[0] * sky_weight,
+        SKY_RADIANCE[1] * sky_weight,
+        SKY_RADIANCE[2] * sky_weight,
+    ];
+    let lights = sky_directions()
+        .into_iter()
+        .map(|direction| (direction, sky_radiance))
+        .chain(std::iter::once((sun_direction.normalize(), SUN_IRRADIANCE)));
+
+    let mut uniforms = BakeUniforms {
+        model,
+        view_projs: [Matrix4::identity(); BAKE_DIRECTION_COUNT],
+        directions: [[0.0; 4]; BAKE_DIRECTION_COUNT],
+        radiances: [[0.0; 4]; BAKE_DIRECTION_COUNT],
+    };
+    for (i, (direction, [r, g, b])) in lights.enumerate() {
+        uniforms.view_projs[i] = visibility_view_proj(&bounds, direction);
+        uniforms.directions[i] = direction.extend(0.0).into();
+        uniforms.radiances[i] = [r, g, b, 0.0];
+    }
+    uniforms
+}
+
+/// Get the orthographic view projection looking at `bounds` from `direction`.
+///
+/// The projection covers the bounding sphere of the box, with the y axis
+/// flipped and the depth mapped to 0..1 like `math::perspective`.
+fn visibility_view_proj(bounds: &Aabb, direction: Vector3<f32>) -> Matrix4<f32> {
+    let center = bounds.center();
+    let radius = bounds.min.distance(bounds.max) * 0.5;
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let view = Matrix4::look_at_dir(center + direction * radius, -direction, up);
+    #[rustfmt::skip]
+    let proj = Matrix4::new(
+        1.0 / radius, 0.0, 0.0, 0.0,
+        0.0, -1.0 / radius, 0.0, 0.0,
+        0.0, 0.0, -0.5 / radius, 0.0,
+        0.0, 0.0, 0.0, 1.0,
+    );
+    proj * view
+}
+
+/// Bake of the static diffuse lighting of the model into a lightmap.
+///
+/// The model is lit by the sky, sampled from `SKY_SAMPLE_COUNT` directions,
+/// and by the sun. For each direction, the depth of the model seen from an
+/// orthographic camera looking along it is rendered in a layer of the
+/// visibility maps. The model is then rasterized in the space of its lightmap
+/// coordinates, and each texel sums the light of the directions in which its
+/// surface is not hidden behind the visibility maps.
+///
+/// The texels not covered by any triangle are then filled with their covered
+/// neighbours so the bilinear filtering does not bleed black along the seams
+/// of the charts. The bake writes an alpha of 0 in the texels it covers, the
+/// target being cleared to an alpha of 1.
+///
+/// Everything is recorded at once with `cmd_bake`, then `finish` keeps the
+/// lightmap and destroys the rest.
+pub struct LightmapBaker {
+    visibility: Texture,
+    visibility_depth: Texture,
+    visibility_render_pass: vk::RenderPass,
+    layer_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+    baked: RenderTarget,
+    lightmap: RenderTarget,
+    uniform_buffer: Buffer,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: [vk::Pipeline; 3],
+}
+
+impl LightmapBaker {
+    /// Create the targets and the descriptor set of the bake.
+    ///
+    /// `visibility` must be created from `visibility_desc` and
+    /// `visibility_depth` from `visibility_depth_desc`. `baked` and `lightmap`
+    /// are render targets of `LIGHTMAP_SIZE` with a single `LIGHTMAP_FORMAT`
+    /// color attachment. The uniform buffer must be host visible and coherent.
+    pub fn new(
+        device: &Device,
+        mut visibility: Texture,
+        (visibility_depth, depth_format): (Texture, vk::Format),
+        (baked, lightmap): (RenderTarget, RenderTarget),
+        uniform_buffer: Buffer,
+    ) -> Self {
+        visibility.sampler = Some(Self::create_sampler(device));
+        let visibility_render_pass = Self::create_visibility_render_pass(device, depth_format);
+        let layer_views = (0..BAKE_DIRECTION_COUNT as u32)
+            .map(|layer| Self::create_layer_view(device, visibility.image, layer))
+            .collect::<Vec<_>>();
+        let framebuffers = layer_views
+            .iter()
+            .map(|view| {
+                let attachments = [*view, visibility_depth.view];
+                let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(visibility_render_pass)
+                    .attachments(&attachments)
+                    .width(VISIBILITY_SIZE)
+                    .height(VISIBILITY_SIZE)
+                    .layers(1)
+                    .build();
+                unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+            })
+            .collect();
+
+        // The layouts shared by the pipelines are reflected from all their shaders
+        let shaders = [VISIBILITY_SHADERS, BAKE_SHADERS, DILATE_SHADERS]
+            .iter()
+            .flatten()
+            .map(|(_, path)| reflect::reflect_shader(path))
+            .collect::<Vec<_>>();
+        let layout = reflect::create_set_layout(device, &shaders, 0);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let uniforms_infos = [vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer)
+            .offset(0)
+            .range(size_of::<BakeUniforms>() as _)
+            .build()];
+        let image_info = |texture: &Texture| {
+            [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(texture.view)
+                .sampler(texture.sampler.unwrap())
+                .build()]
+        };
+        let visibility_infos = image_info(&visibility);
+        let baked_infos = image_info(&baked.color(0));
+        let write = |binding, descriptor_type| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(descriptor_type)
+        };
+        let descriptor_writes = [
+            write(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&uniforms_infos)
+                .build(),
+            write(
+                VISIBILITY_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            )
+            .image_info(&visibility_infos)
+            .build(),
+            write(BAKED_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&baked_infos)
+                .build(),
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        let pipeline_layout = {
+            let layouts = [layout];
+            let push_constant_ranges = reflect::push_constant_ranges(&shaders);
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .push_constant_ranges(&push_constant_ranges)
+                .build();
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        };
+
+        LightmapBaker {
+            visibility,
+            visibility_depth,
+            visibility_render_pass,
+            layer_views,
+            framebuffers,
+            baked,
+            lightmap,
+            uniform_buffer,
+            layout,
+            pool,
+            set,
+            pipeline_layout,
+            pipelines: [vk::Pipeline::null(); 3],
+        }
+    }
+
+    /// Describe the texture array holding the visibility map of each direction.
+    pub fn visibility_desc() -> TextureDesc {
+        TextureDesc::new_2d_array(
+            Self::visibility_extent(),
+            BAKE_DIRECTION_COUNT as _,
+            VISIBILITY_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    /// Describe the depth texture shared by the renders of the visibility maps.
+    pub fn visibility_depth_desc(format: vk::Format) -> TextureDesc {
+        TextureDesc::new_2d(
+            Self::visibility_extent(),
+            format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+    }
+
+    pub fn visibility_extent() -> vk::Extent2D {
+        vk::Extent2D {
+            width: VISIBILITY_SIZE,
+            height: VISIBILITY_SIZE,
+        }
+    }
+
+    pub fn lightmap_extent() -> vk::Extent2D {
+        vk::Extent2D {
+            width: LIGHTMAP_SIZE,
+            height: LIGHTMAP_SIZE,
+        }
+    }
+
+    /// Create the sampler of the visibility maps, which are only fetched.
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    /// Create the render pass of a visibility map.
+    ///
+    /// The map is left in the `SHADER_READ_ONLY_OPTIMAL` layout for the bake.
+    fn create_visibility_render_pass(device: &Device, depth_format: vk::Format) -> vk::RenderPass {
+        let attachment_descs = [
+            vk::AttachmentDescription::builder()
+                .format(VISIBILITY_FORMAT)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+            vk::AttachmentDescription::builder()
+                .format(depth_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .build(),
+        ];
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+        let subpass_descs = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build()];
+
+        // The render of the previous direction might still be writing the depth.
+        let begin_dep = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build();
+        // The bake then samples the map.
+        let end_dep = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+        let subpass_deps = [begin_dep, end_dep];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descs)
+            .subpasses(&subpass_descs)
+            .dependencies(&subpass_deps)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+
+    /// Create a view of `layer` to render its visibility map into it.
+    fn create_layer_view(device: &Device, image: vk::Image, layer: u32) -> vk::ImageView {
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(VISIBILITY_FORMAT)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: layer,
+                layer_count: 1,
+            })
+            .build();
+
+        unsafe { device.create_image_view(&view_info, None).unwrap() }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 2,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl LightmapBaker {
+    /// Get the layout shared by the pipelines of the bake.
+    ///
+    /// The index of the direction is pushed to the vertex shader as a `u32`.
+    pub fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    pub fn visibility_render_pass(&self) -> vk::RenderPass {
+        self.visibility_render_pass
+    }
+
+    pub fn bake_render_pass(&self) -> vk::RenderPass {
+        self.baked.render_pass()
+    }
+
+    pub fn dilate_render_pass(&self) -> vk::RenderPass {
+        self.lightmap.render_pass()
+    }
+
+    /// Set the pipelines rendering the visibility maps, baking the lightmap and
+    /// dilating it. The baker takes ownership of them.
+    pub fn set_pipelines(
+        &mut self,
+        visibility: vk::Pipeline,
+        bake: vk::Pipeline,
+        dilate: vk::Pipeline,
+    ) {
+        self.pipelines = [visibility, bake, dilate];
+    }
+
+    /// Write `uniforms` in the uniform buffer.
+    pub fn update(&self, device: &Device, uniforms: BakeUniforms) {
+        self.uniform_buffer.write(device, 0, &[uniforms]);
+    }
+
+    /// Record the whole bake in `command_buffer`.
+    ///
+    /// `lightmap_coords` is the vertex buffer of the lightmap coordinates of
+    /// the model drawn from `vertex_buffer` and `index_buffer`.
+    pub fn cmd_bake(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (vertex_buffer, lightmap_coords): (Buffer, Buffer),
+        index_buffer: Buffer,
+        index_count: u32,
+    ) {
+        let [visibility_pipeline, bake_pipeline, dilate_pipeline] = self.pipelines;
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [1.0, 0.0, 0.0, 0.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[vertex_buffer.buffer, lightmap_coords.buffer],
+                &[0, 0],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+        }
+
+        // Render the depth of the model seen from each direction
+        for (direction, framebuffer) in self.framebuffers.iter().enumerate() {
+            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(self.visibility_render_pass)
+                .framebuffer(*framebuffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: Self::visibility_extent(),
+                })
+                .clear_values(&clear_values)
+                .build();
+            unsafe {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    visibility_pipeline,
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    &(direction as u32).to_ne_bytes(),
+                );
+                device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+                device.cmd_end_render_pass(command_buffer);
+            }
+        }
+
+        // Sum the light of each direction in the lightmap texels
+        self.baked.cmd_begin(device, command_buffer);
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                bake_pipeline,
+            );
+            device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+        }
+        self.baked.cmd_end(device, command_buffer);
+
+        // Fill the texels around the charts
+        self.lightmap.cmd_begin(device, command_buffer);
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                dilate_pipeline,
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+        self.lightmap.cmd_end(device, command_buffer);
+    }
+
+    /// Destroy the resources of the bake and return the lightmap.
+    ///
+    /// The bake must be complete. `lightmap_coords` is the vertex buffer of the
+    /// lightmap coordinates, the lightmap takes ownership of it.
+    pub fn finish(mut self, device: &Device, lightmap_coords: Buffer) -> Lightmap {
+        unsafe {
+            self.pipelines
+                .iter()
+                .for_each(|pipeline| lifetime::destroy_pipeline(device, *pipeline));
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            self.framebuffers
+                .iter()
+                .for_each(|framebuffer| device.destroy_framebuffer(*framebuffer, None));
+            self.layer_views
+                .iter()
+                .for_each(|view| device.destroy_image_view(*view, None));
+            device.destroy_render_pass(self.visibility_render_pass, None);
+        }
+        self.uniform_buffer.destroy(device);
+        self.visibility.destroy(device);
+        self.visibility_depth.destroy(device);
+        self.baked.destroy(device);
+
+        Lightmap {
+            target: self.lightmap,
+            lightmap_coords,
+        }
+    }
+}
+
+/// Lightmap baked by a `LightmapBaker` and the lightmap coordinates of the model.
+///
+/// The lightmap is sampled by the base material when it has the `LIGHTMAP`
+/// keyword, with the coordinates bound at `LIGHTMAP_COORDS_BINDING`.
+pub struct Lightmap {
+    target: RenderTarget,
+    lightmap_coords: Buffer,
+}
+
+impl Lightmap {
+    /// Get the lightmap texture with its sampler.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout.
+    pub fn texture(&self) -> Texture {
+        self.target.color(0)
+    }
+
+    /// Bind the lightmap coordinates with `encoder` for the draws of the model.
+    pub fn cmd_bind_coords(&self, encoder: &mut CommandEncoder) {
+        encoder.bind_vertex_buffers(
+            LIGHTMAP_COORDS_BINDING,
+            &[self.lightmap_coords.buffer],
+            &[0],
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.lightmap_coords.destroy(device);
+        self.target.destroy(device);
+    }
+}
@@ -1,57 +1,252 @@
+mod ambient;
+mod background;
+mod barrier;
+mod batching;
+mod benchmark;
+mod bindless;
+mod bloom;
+mod blur;
+mod buffer;
 mod camera;
+mod color_grading;
+mod command_encoder;
+mod console;
 mod context;
 mod debug;
+mod debug_draw;
+#[cfg(feature = "debug-markers")]
+mod debug_markers;
+mod debug_view;
+mod decal;
+mod descriptor_allocator;
+mod device_address;
+mod displacement;
+mod dynamic_resolution;
+mod exposure;
+mod fog;
+mod frame_layout;
 mod fs;
+mod fsr;
+mod geometry_pool;
+mod gizmo;
+mod gpu_crash;
+mod gpu_device;
+mod gpu_driven;
+mod gpu_sort;
+mod gpu_timer;
+mod half_res;
+mod headless;
+mod hiz;
+mod input_recording;
+mod lifetime;
+mod light_gizmo;
+mod light_shafts;
+mod lightmap;
+mod material;
 mod math;
+mod memory;
+mod mesh_shader;
+mod meshlet;
+mod mirror;
+mod motion_blur;
+mod objects;
+mod occlusion;
+mod outline;
+mod particles;
+mod portability;
+mod present_transfer;
+mod probe;
+mod profiler;
+mod ray_traced_shadows;
+mod ray_tracing;
+mod readback;
+mod reflect;
+mod render_list;
+mod render_target;
+mod render_thread;
+mod scene;
+mod shader_variant;
+mod shadow;
+mod shadow_atlas;
+mod sky;
+mod sprite;
+mod ssr;
+mod std140;
+mod stereo;
+mod streaming;
+mod submit_batch;
 mod surface;
 mod swapchain;
+mod sync2;
+mod terrain;
+#[cfg(test)]
+mod tests;
+mod text;
 mod texture;
-
-use crate::{camera::*, context::*, debug::*, swapchain::*, texture::*};
+mod texture_streaming;
+mod time_controller;
+mod tonemap;
+mod tracked_image;
+mod transient;
+mod viewport;
+mod virtual_texture;
+mod watcher;
+mod water;
+mod window;
+#[cfg(feature = "openxr")]
+mod xr;
+
+use crate::profiler::profile_scope;
+#[cfg(feature = "openxr")]
+use crate::xr::*;
+use crate::{
+    ambient::*, background::*, barrier::*, batching::*, benchmark::*, bindless::*, bloom::*,
+    blur::*, buffer::*, camera::*, color_grading::*, command_encoder::*, console::*, context::*,
+    debug::*, debug_draw::*, debug_view::*, decal::*, descriptor_allocator::*, device_address::*,
+    displacement::*, dynamic_resolution::*, exposure::*, fog::*, frame_layout::*, fsr::*,
+    geometry_pool::*, gizmo::*, gpu_crash::*, gpu_device::Device, gpu_driven::*, gpu_sort::*,
+    gpu_timer::*, half_res::*, headless::*, hiz::*, input_recording::*, lifetime::ObjectKind,
+    light_gizmo::*, light_shafts::*, lightmap::*, material::*, memory::*, mesh_shader::*,
+    meshlet::*, mirror::*, motion_blur::*, objects::*, occlusion::*, outline::*, particles::*,
+    present_transfer::*, probe::*, ray_traced_shadows::*, ray_tracing::*, readback::*, reflect::*,
+    render_list::*, render_target::*, render_thread::*, scene::*, shader_variant::*, shadow::*,
+    shadow_atlas::*, sky::*, sprite::*, ssr::*, std140::*, stereo::*, streaming::*,
+    submit_batch::*, swapchain::*, terrain::*, text::*, texture::*, texture_streaming::*,
+    time_controller::*, tonemap::*, tracked_image::*, transient::*, viewport::*,
+    virtual_texture::*, watcher::*, water::*, window::*,
+};
 use ash::{
     extensions::{
         ext::DebugReport,
         khr::{Surface, Swapchain},
     },
+    prelude::VkResult,
     version::{DeviceV1_0, EntryV1_0, InstanceV1_0},
 };
-use ash::{vk, Device, Entry, Instance};
-use cgmath::{Deg, Matrix4, Point3, Vector3};
+use ash::{vk, Entry, Instance};
+use cgmath::{
+    Deg, EuclideanSpace, InnerSpace, Matrix3, Matrix4, MetricSpace, Point3, Quaternion, Rotation3,
+    SquareMatrix, Vector3,
+};
 use std::{
     ffi::{CStr, CString},
-    mem::{align_of, size_of},
+    mem::{align_of, size_of, size_of_val},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use winit::{
-    dpi::LogicalSize, ElementState, Event, EventsLoop, MouseButton, MouseScrollDelta, Touch,
-    TouchPhase, Window, WindowBuilder, WindowEvent,
+    dpi::LogicalSize, ElementState, Event, EventsLoop, KeyboardInput, MouseButton,
+    MouseScrollDelta, Touch, TouchPhase, VirtualKeyCode, Window, WindowBuilder, WindowEvent,
 };
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 600;
 const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+/// Offset of the vertex buffer address in the push constants.
+///
+/// The first bytes are used by the material index of the fragment shader.
+const VERTEX_ADDRESS_PUSH_CONSTANT_OFFSET: u32 = 8;
+/// Time between two logs of the CPU frame breakdown.
+const PROFILE_LOG_INTERVAL_SECS: u64 = 5;
+/// Simulated time between two frames in seconds when the frames are deterministic.
+const DETERMINISTIC_TIME_STEP: f32 = 1.0 / 60.0;
+/// Vertical field of view of the main camera.
+const FIELD_OF_VIEW: Deg<f32> = Deg(45.0);
+/// Time between two polls of the events while the app is in the background.
+const SUSPENDED_POLL_INTERVAL_MS: u64 = 50;
+/// Model loaded at startup and reloaded when it changes with `--hot-reload`.
+const MODEL_PATH: &str = "models/chalet.obj";
+/// Texture of the model, reloaded like the model.
+const TEXTURE_PATH: &str = "images/chalet.jpg";
+/// Directory of the compiled shaders, whose pipelines are recreated when they change.
+const SHADERS_DIRECTORY: &str = "shaders";
+/// Number of clip planes in the frame uniforms.
+///
+/// Must match `MAX_CLIP_PLANES` in `frame.glsl`.
+const MAX_CLIP_PLANES: usize = 4;
+/// Center of the particle emitter of the demo, next to the model.
+const PARTICLE_EMITTER: Point3<f32> = Point3 {
+    x: 1.0,
+    y: 0.0,
+    z: -1.0,
+};
+/// Particles emitted per second by the emitter of the demo.
+const PARTICLE_EMISSION_RATE: f32 = 400.0;
+/// Approximate size of the model, over which its texture is stretched.
+const MODEL_SIZE: f32 = 2.0;
 
 struct VulkanApp {
-    events_loop: EventsLoop,
-    _window: Window,
+    /// The events loop and the window are `None` when running headless.
+    events_loop: Option<EventsLoop>,
+    window: Option<Window>,
     resize_dimensions: Option<[u32; 2]>,
-
-    camera: Camera,
+    /// Time animating the scene. It starts at a fixed time when headless or
+    /// with `--deterministic` so the frames are reproducible.
+    time_controller: TimeController,
+    /// Time in seconds added to the time after each frame and used instead
+    /// of the duration of the frames with `--deterministic`.
+    time_step: Option<f32>,
+    last_frame_instant: Instant,
+    /// Input of the frames written with `--record-input=<path>`.
+    input_recorder: Option<InputRecorder>,
+    /// Input of the frames played back with `--play-input=<path>`.
+    input_playback: Option<InputPlayback>,
+    /// Duration of the frame recorded or played back, used instead of the
+    /// measured one so the playback animates the scene like the recording.
+    input_delta: Option<f32>,
+    /// Smoothed duration of a frame in seconds.
+    frame_time: f32,
+    /// Last time the CPU frame breakdown was logged, if it is enabled with `--profile`.
+    last_profile_log: Option<Instant>,
+    /// Scripted frames measured with `--benchmark`.
+    benchmark: Option<Benchmark>,
+    /// Loader of the assets changed on disk, with `--hot-reload`.
+    asset_loader: Option<AssetLoader<ReloadedAsset>>,
+
+    scene: Scene,
+    /// Entity of the camera of the main view.
+    main_camera: Entity,
+    /// Entity of the loaded model.
+    model: Entity,
+    /// Entity of the directional light following the sun of the sky.
+    sun: Entity,
+    /// Scene state extracted for the frame being prepared.
+    render_list: RenderList,
+    /// Layers drawn by each pass.
+    pass_layers: PassLayers,
     is_left_clicked: bool,
     cursor_position: [i32; 2],
     cursor_delta: Option<[i32; 2]>,
     wheel_delta: Option<f32>,
+    debug_views: DebugViews,
+    fog: Fog,
+    shadow_filter: ShadowFilter,
+    /// Planes clipping the model in the main view, keeping the side of their normal.
+    clip_planes: Vec<math::Plane>,
+    /// Entity selected by picking, whose transform is edited by the gizmo.
+    selected: Option<Entity>,
+    /// Draw the lights and allow picking them.
+    light_gizmos: bool,
+    gizmo: Gizmo,
 
     vk_context: VkContext,
     queue_families_indices: QueueFamiliesIndices,
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
-    swapchain: Swapchain,
-    swapchain_khr: vk::SwapchainKHR,
+    /// Swapchain of the window, or `None` when running headless.
+    swapchain: Option<(Swapchain, vk::SwapchainKHR)>,
+    /// Image rendered instead of the swapchain images when running headless.
+    offscreen_target: Option<OffscreenTarget>,
     swapchain_properties: SwapchainProperties,
     images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
+    /// Transfer of the swapchain images to the present queue, if it is of
+    /// another queue family.
+    present_transfer: Option<PresentTransfer>,
     render_pass: vk::RenderPass,
-    descriptor_set_layout: vk::DescriptorSetLayout,
+    frame_layout: FrameLayout,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
     swapchain_framebuffers: Vec<vk::Framebuffer>,
@@ -62,81 +257,368 @@ struct VulkanApp {
     depth_format: vk::Format,
     depth_texture: Texture,
     texture: Texture,
-    model_index_count: usize,
-    vertex_buffer: vk::Buffer,
-    vertex_buffer_memory: vk::DeviceMemory,
-    index_buffer: vk::Buffer,
-    index_buffer_memory: vk::DeviceMemory,
-    uniform_buffers: Vec<vk::Buffer>,
-    uniform_buffer_memories: Vec<vk::DeviceMemory>,
-    descriptor_pool: vk::DescriptorPool,
+    texture_streamer: Option<TextureStreamer>,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    uniform_buffers: Vec<Buffer>,
+    descriptor_allocator: DescriptorAllocator,
     descriptor_sets: Vec<vk::DescriptorSet>,
+    bindless_textures: Option<BindlessTextures>,
+    /// Index of the texture of the model in the bindless textures.
+    model_texture_index: u32,
+    model_material_index: u32,
+    material_instances: MaterialInstances,
+    model_material: MaterialInstance,
+    shader_variants: ShaderVariants,
+    mesh_shading: Option<MeshShading>,
+    displaced_plane: Option<DisplacedPlane>,
+    terrain: Option<Terrain>,
+    mesh_streamer: Option<MeshStreamer>,
+    objects: Option<ObjectRenderer>,
+    /// Static objects of `--static-objects`, merged with `--static-batching`.
+    static_batches: Option<StaticBatches>,
+    /// Spheres culled and drawn by the GPU with `--gpu-driven`.
+    gpu_driven: Option<GpuDriven>,
+    /// Demo spawning and despawning objects every frame with `--objects`.
+    object_demo: Option<ObjectDemo>,
+    virtual_texture: Option<VirtualTexture>,
+    /// Pyramid of the depth of the frame, for the HiZ and the screen-space reflections.
+    depth_pyramid: Option<DepthPyramid>,
+    hiz: Option<HiZ>,
+    /// Number of terrain chunks culled by the HiZ in the last frame.
+    occluded_count: u32,
+    water: Option<Water>,
+    secondary_views: Option<SecondaryViews>,
+    mirror: Option<Mirror>,
+    /// Additional window with its own swapchain, drawn after the main window.
+    secondary_window: Option<SecondaryWindow>,
+    stereo: Option<Stereo>,
+    /// OpenXR runtime and session when rendering to a headset.
+    #[cfg(feature = "openxr")]
+    xr: Option<(XrRuntime, XrSession)>,
+    sky: Option<Sky>,
+    background: Option<Background>,
+    /// Linear color the main image is cleared with.
+    clear_color: [f32; 3],
+    decals: Option<Decals>,
+    sprites: Option<SpriteRenderer>,
+    particles: Option<Particles>,
+    /// Number of living particles a few frames ago.
+    particle_count: u32,
+    /// Text drawn over the frame, with `--hud` or `--console`.
+    hud: Option<TextRenderer>,
+    /// Whether the statistics are drawn on the HUD, with `--hud`.
+    hud_stats: bool,
+    /// Console opened with the grave key, with `--console`.
+    console: Option<Console>,
+    /// Set by the `reload` console command to reload all the assets.
+    is_reload_requested: bool,
+    /// Image the scene is rendered to when the exposure is automatic.
+    hdr_texture: Option<Texture>,
+    /// Fraction of the swapchain size the scene is rendered at.
+    render_scale: f32,
+    /// Render scale following the GPU frame time with `--dynamic-resolution`.
+    dynamic_resolution: Option<DynamicResolution>,
+    auto_exposure: Option<AutoExposure>,
+    bloom: Option<Bloom>,
+    tonemapper: Option<Tonemapper>,
+    /// Screen-space reflections with `--ssr`.
+    ssr: Option<ScreenSpaceReflections>,
+    /// Acceleration structures of the model, built when the device supports ray tracing.
+    acceleration_structures: Option<AccelerationStructures>,
+    /// Shadows of the sun traced against `acceleration_structures` with `--ray-traced-shadows`.
+    ray_traced_shadows: Option<RayTracedShadows>,
+    light_shafts: Option<LightShafts>,
+    motion_blur: Option<MotionBlur>,
+    fsr: Option<Fsr>,
+    /// Effects rendered at half resolution with `--half-res`, which keep the depth buffer.
+    half_res: HalfResEffects,
+    transient_allocator: TransientBufferAllocator,
+    debug_draw: DebugDraw,
+    occlusion_queries: Option<OcclusionQueries>,
+    /// Number of objects whose occlusion query had no visible sample.
+    occluded_objects: u32,
+    /// Timestamps of the frames, with `--benchmark` or `--profile`.
+    gpu_timer: Option<GpuTimer>,
+    /// Last results of the GPU timer.
+    gpu_frame: Option<GpuFrameStats>,
+    /// Markers of the passes and last submits written when the device is
+    /// lost, with `--gpu-crash-dump`.
+    gpu_crash_dump: Option<GpuCrashDump>,
+    reflection_probes: Option<ReflectionProbes>,
+    lightmap: Option<Lightmap>,
+    sh_ambient: Option<ShAmbient>,
+    shadow_map: Option<ShadowMap>,
+    shadow_atlas: Option<ShadowAtlas>,
+    normals_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    /// Pipeline drawing the outline of the model when it is selected.
+    outline_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
     command_buffers: Vec<vk::CommandBuffer>,
+    /// Binds recorded and skipped in each command buffer.
+    bind_stats: Vec<BindStats>,
     in_flight_frames: InFlightFrames,
+    /// Commands of the frame being prepared, submitted together.
+    submit_batch: SubmitBatch,
+    /// What was submitted for the last frame.
+    submit_stats: SubmitStats,
+    /// Set when a Vulkan call returns `VK_ERROR_DEVICE_LOST`.
+    ///
+    /// The logical device and its resources must then be recreated, see `main`.
+    is_device_lost: bool,
+    /// Set when the resources depending on the swapchain could not be created again.
+    ///
+    /// They are half created so the app can only stop, see `main`.
+    allocation_error: Option<AllocationError>,
+    /// Thread submitting and presenting the frames, enabled with `--render-thread`.
+    render_thread: Option<RenderThread>,
+    /// Set while the app is in the background on Android.
+    ///
+    /// The native window is destroyed when the app is paused so the swapchain
+    /// and the surface are destroyed until the app is resumed.
+    is_suspended: bool,
 }
 
-impl VulkanApp {
-    fn new() -> Self {
-        log::debug!("Creating application.");
+/// Reason why the main loop stopped.
+enum RunResult {
+    Exit,
+    DeviceLost,
+    /// The resources could not be created again, for example after a resize.
+    OutOfMemory(AllocationError),
+}
 
-        let events_loop = EventsLoop::new();
-        let window = WindowBuilder::new()
-            .with_title("Vulkan tutorial with Ash")
-            .with_dimensions(LogicalSize::new(f64::from(WIDTH), f64::from(HEIGHT)))
-            .build(&events_loop)
-            .unwrap();
+/// Asset loaded again by the asset loader thread after it changed on disk.
+enum ReloadedAsset {
+    /// Extent and RGBA8 pixels of the texture of the model.
+    Texture(vk::Extent2D, Vec<u8>),
+    /// Vertices and indices of the model.
+    Model(Vec<Vertex>, Vec<u32>),
+    /// A compiled shader changed so the pipelines must be created again.
+    Shaders,
+}
 
-        let entry = Entry::new().expect("Failed to create entry.");
-        let instance = Self::create_instance(&entry);
+/// Window and instance kept when the app is recreated after the device was lost.
+struct AppBase {
+    window: Option<(EventsLoop, Window)>,
+    instance: Rc<InstanceContext>,
+}
 
-        let surface = Surface::new(&entry, &instance);
-        let surface_khr = unsafe { surface::create_surface(&entry, &instance, &window).unwrap() };
+/// View and settings kept when the app is recreated.
+struct AppState {
+    camera: Camera,
+    model_transform: math::Transform,
+    model_layers: Layers,
+    pass_layers: PassLayers,
+    debug_views: DebugViews,
+    fog: Fog,
+    gizmo: Gizmo,
+    light_gizmos: bool,
+    time_controller: TimeController,
+    hud_stats: bool,
+    last_profile_log: Option<Instant>,
+    console: Option<Console>,
+    input_recorder: Option<InputRecorder>,
+    input_playback: Option<InputPlayback>,
+}
 
-        let debug_report_callback = setup_debug_messenger(&entry, &instance);
+impl VulkanApp {
+    /// Create the application.
+    ///
+    /// The window and the instance of `base` are used instead of creating new
+    /// ones when the app is recreated after the device was lost.
+    fn new(base: Option<AppBase>) -> Result<Self, AllocationError> {
+        log::debug!("Creating application.");
+
+        let headless = Self::get_headless_output().is_some();
+        let deterministic = Self::is_deterministic();
+        let (window, instance_context) = match base {
+            Some(base) => (base.window, Some(base.instance)),
+            None => (None, None),
+        };
+        let (events_loop, window) = if let Some((events_loop, window)) = window {
+            (Some(events_loop), Some(window))
+        } else if headless {
+            (None, None)
+        } else {
+            let events_loop = EventsLoop::new();
+            let window = WindowBuilder::new()
+                .with_title("Vulkan tutorial with Ash")
+                .with_dimensions(LogicalSize::new(f64::from(WIDTH), f64::from(HEIGHT)))
+                .build(&events_loop)
+                .unwrap();
+            (Some(events_loop), Some(window))
+        };
+
+        // The OpenXR runtime is not created again with a kept instance, since
+        // the instance extensions it needs cannot be enabled anymore
+        #[cfg(feature = "openxr")]
+        let (xr_runtime, xr_instance_extensions, xr_device_extensions) = match instance_context
+            .is_none()
+            .then(Self::create_xr_runtime)
+            .flatten()
+        {
+            Some((runtime, instance_extensions, device_extensions)) => {
+                (Some(runtime), instance_extensions, device_extensions)
+            }
+            None => (None, Vec::new(), Vec::new()),
+        };
+        #[cfg(not(feature = "openxr"))]
+        let (xr_instance_extensions, xr_device_extensions) = (Vec::new(), Vec::new());
+        let instance_context = match instance_context {
+            Some(instance_context) => instance_context,
+            None => Rc::new(Self::create_instance_context(
+                window.as_ref(),
+                xr_instance_extensions,
+            )),
+        };
+        let entry = instance_context.entry();
+        let instance = instance_context.instance();
+        let api_version = instance_context.api_version();
+        #[cfg(feature = "debug-markers")]
+        let debug_markers = Self::are_debug_markers_supported(entry);
 
         let (physical_device, queue_families_indices) =
-            Self::pick_physical_device(&instance, &surface, surface_khr);
+            Self::pick_physical_device(instance, instance_context.surface());
+        #[cfg(feature = "openxr")]
+        let (xr_runtime, xr_device_extensions) = match xr_runtime {
+            Some(runtime) if Self::is_xr_device(&runtime, instance, physical_device) => {
+                (Some(runtime), xr_device_extensions)
+            }
+            _ => (None, Vec::new()),
+        };
+
+        let device_features = Self::get_device_features(
+            instance,
+            physical_device,
+            queue_families_indices.graphics_index,
+            api_version,
+        );
 
         let (device, graphics_queue, present_queue) =
             Self::create_logical_device_with_graphics_queue(
-                &instance,
+                instance,
                 physical_device,
                 queue_families_indices,
+                device_features,
+                !headless,
+                &xr_device_extensions,
             );
+        if device_features.synchronization2 {
+            sync2::enable(instance, &device);
+        }
+        #[cfg(feature = "debug-markers")]
+        if debug_markers {
+            debug_markers::enable(entry, instance, &device);
+        }
+        let (buffer_device_address, ray_tracing) = if device_features.ray_tracing {
+            (
+                Some(BufferDeviceAddress::new_khr(instance, &device)),
+                Some(RayTracing::new(instance, physical_device, &device)),
+            )
+        } else if device_features.device_address {
+            (Some(BufferDeviceAddress::new(instance, &device)), None)
+        } else {
+            (None, None)
+        };
 
         let vk_context = VkContext::new(
-            entry,
-            instance,
-            debug_report_callback,
-            surface,
-            surface_khr,
+            instance_context,
             physical_device,
             device,
+            (buffer_device_address, ray_tracing),
+            device_features.memory_budget,
+            device_features.subgroup,
         );
+        log::debug!("Memory heaps: {:?}", memory::memory_stats(&vk_context));
 
-        let (swapchain, swapchain_khr, properties, images) =
-            Self::create_swapchain_and_images(&vk_context, queue_families_indices, [WIDTH, HEIGHT]);
+        let (swapchain, offscreen_target, properties, images) = if headless {
+            let target = Self::create_offscreen_target(&vk_context, [WIDTH, HEIGHT])?;
+            let properties = target.properties();
+            let images = vec![target.image()];
+            (None, Some(target), properties, images)
+        } else {
+            let (_, surface_khr) = vk_context.surface().unwrap();
+            let (swapchain, swapchain_khr, properties, images) = Self::create_swapchain_and_images(
+                &vk_context,
+                queue_families_indices,
+                (surface_khr, true),
+                [WIDTH, HEIGHT],
+            );
+            (Some((swapchain, swapchain_khr)), None, properties, images)
+        };
         let swapchain_image_views =
             Self::create_swapchain_image_views(vk_context.device(), &images, properties);
+        let present_transfer = if swapchain.is_some() {
+            Self::create_present_transfer(vk_context.device(), queue_families_indices, &images)
+        } else {
+            None
+        };
 
         let msaa_samples = vk_context.get_max_usable_sample_count();
-        let depth_format = Self::find_depth_format(&vk_context);
+        let outline_enabled = std::env::args().any(|arg| arg == "--outline");
+        let mirror_enabled = std::env::args().any(|arg| arg == "--mirror");
+        let depth_format = if outline_enabled || mirror_enabled {
+            Self::find_depth_stencil_format(&vk_context)
+        } else {
+            Self::find_depth_format(&vk_context)
+        };
+        let auto_exposure_enabled = std::env::args().any(|arg| arg == "--auto-exposure");
+        let (render_scale, dynamic_resolution) = Self::get_render_scale(auto_exposure_enabled);
+        // The scene is rendered at the render scale and upscaled by the tonemapper
+        let swapchain_properties = properties;
+        let properties = Self::get_render_properties(properties, render_scale);
+        let scene_properties = Self::get_scene_properties(properties, auto_exposure_enabled);
+        let terrain_enabled = std::env::args().any(|arg| arg == "--terrain");
+        let hiz_enabled = std::env::args().any(|arg| arg == "--hiz");
+        if hiz_enabled && !terrain_enabled {
+            log::warn!("Occlusion culling requested but it needs --terrain.");
+        }
+        let hiz_enabled = hiz_enabled && terrain_enabled;
+        let ssr_enabled = std::env::args().any(|arg| arg == "--ssr");
+        let motion_blur_enabled = std::env::args().any(|arg| arg == "--motion-blur");
+        if ssr_enabled && !(auto_exposure_enabled && motion_blur_enabled) {
+            log::warn!(
+                "Screen-space reflections requested but they need --auto-exposure and --motion-blur."
+            );
+        }
+        let ssr_enabled = ssr_enabled && auto_exposure_enabled && motion_blur_enabled;
+        let depth_pyramid_enabled = hiz_enabled || ssr_enabled;
+        if depth_pyramid_enabled && !device_features.storage_image_extended_formats {
+            log::warn!("Depth pyramid requested but R32G32 storage images are not supported.");
+        }
+        let depth_pyramid_enabled =
+            depth_pyramid_enabled && device_features.storage_image_extended_formats;
+        let hiz_enabled = hiz_enabled && depth_pyramid_enabled;
+        let ssr_enabled = ssr_enabled && depth_pyramid_enabled;
+        let half_res = Self::get_half_res_effects();
+        // The ray tracing is also enabled for the ray queries alone
+        let ray_traced_shadows_enabled = device_features.ray_tracing
+            && std::env::args().any(|arg| arg == "--ray-traced-shadows");
+        if ray_traced_shadows_enabled && !auto_exposure_enabled {
+            log::warn!("Ray-traced shadows requested but they need --auto-exposure.");
+        }
+        let ray_traced_shadows_enabled = ray_traced_shadows_enabled && auto_exposure_enabled;
+        let sample_depth = depth_pyramid_enabled || half_res.any() || ray_traced_shadows_enabled;
 
-        let render_pass =
-            Self::create_render_pass(vk_context.device(), properties, msaa_samples, depth_format);
-        let descriptor_set_layout = Self::create_descriptor_set_layout(vk_context.device());
-        let (pipeline, layout) = Self::create_pipeline(
+        let render_pass = Self::create_render_pass(
             vk_context.device(),
-            properties,
+            scene_properties,
             msaa_samples,
-            render_pass,
-            descriptor_set_layout,
+            (depth_format, sample_depth),
+            Self::get_scene_final_layout(headless, auto_exposure_enabled),
         );
+        let frame_layout = Self::create_frame_layout(vk_context.device(), device_features);
+        let descriptor_set_layout = frame_layout.set_layout();
+        let mut bindless_textures = if device_features.bindless {
+            Some(Self::create_bindless_textures(&vk_context)?)
+        } else {
+            None
+        };
 
+        // The command buffers are reset and recorded again when what is drawn changes
         let command_pool = Self::create_command_pool(
             vk_context.device(),
             queue_families_indices,
-            vk::CommandPoolCreateFlags::empty(),
+            vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
         );
         let transient_command_pool = Self::create_command_pool(
             vk_context.device(),
@@ -148,273 +630,2000 @@ impl VulkanApp {
             &vk_context,
             command_pool,
             graphics_queue,
-            properties,
+            scene_properties,
             msaa_samples,
-        );
+        )?;
 
         let depth_texture = Self::create_depth_texture(
             &vk_context,
             command_pool,
             graphics_queue,
-            depth_format,
+            (depth_format, sample_depth),
             properties.extent,
             msaa_samples,
-        );
+        )?;
+
+        let hdr_texture = if auto_exposure_enabled {
+            Some(Self::create_hdr_texture(&vk_context, properties.extent)?)
+        } else {
+            None
+        };
 
         let swapchain_framebuffers = Self::create_framebuffers(
             vk_context.device(),
-            &swapchain_image_views,
+            &Self::get_scene_views(&swapchain_image_views, hdr_texture.as_ref()),
             color_texture,
             depth_texture,
             render_pass,
             properties,
         );
 
-        let texture = Self::create_texture_image(&vk_context, command_pool, graphics_queue);
+        let (texture, texture_streamer) =
+            if std::env::args().any(|arg| arg == "--texture-streaming") {
+                Self::create_streamed_texture(
+                    &vk_context,
+                    queue_families_indices.graphics_index,
+                    command_pool,
+                    graphics_queue,
+                )?
+            } else {
+                (
+                    Self::create_texture_image(&vk_context, command_pool, graphics_queue)?,
+                    None,
+                )
+            };
+        let (model_texture_index, model_material_index) =
+            bindless_textures.as_mut().map_or((0, 0), |bindless| {
+                let texture_index = bindless.add_texture(vk_context.device(), texture);
+                let material_index =
+                    bindless.add_material(vk_context.device(), MaterialData { texture_index });
+                (texture_index, material_index)
+            });
+        let lightmaps_enabled = if std::env::args().any(|arg| arg == "--lightmaps") {
+            if device_features.mesh_shader {
+                log::warn!(
+                    "Lightmaps requested but the mesh shaders do not output their coordinates."
+                );
+            }
+            !device_features.mesh_shader
+        } else {
+            false
+        };
+        let sh_ambient_enabled = if std::env::args().any(|arg| arg == "--sh-ambient") {
+            if device_features.mesh_shader {
+                log::warn!("SH ambient requested but the mesh shaders do not output normals.");
+            }
+            !device_features.mesh_shader
+        } else {
+            false
+        };
+        let shadows_enabled = if std::env::args().any(|arg| arg == "--shadows") {
+            if device_features.mesh_shader {
+                log::warn!("Shadows requested but the mesh shaders do not output normals.");
+            }
+            !device_features.mesh_shader
+        } else {
+            false
+        };
+        let ray_query_shadows_enabled = if device_features.ray_query {
+            if device_features.mesh_shader {
+                log::warn!(
+                    "Ray query shadows requested but the mesh shaders do not output normals."
+                );
+            }
+            !device_features.mesh_shader
+        } else {
+            false
+        };
+        if shadows_enabled && ray_query_shadows_enabled {
+            log::warn!("Shadows requested but the ray query shadows replace the shadow map.");
+        }
+        let shadows_enabled = shadows_enabled && !ray_query_shadows_enabled;
+        let spot_shadows_enabled = if std::env::args().any(|arg| arg == "--spot-shadows") {
+            if device_features.mesh_shader {
+                log::warn!("Spot shadows requested but the mesh shaders do not output normals.");
+            }
+            !device_features.mesh_shader
+        } else {
+            false
+        };
+        let cross_section_enabled = if std::env::args().any(|arg| arg == "--cross-section") {
+            let supported = device_features.clip_distance && !device_features.mesh_shader;
+            if !supported {
+                log::warn!("Cross section requested but clip distances are not supported.");
+            }
+            supported
+        } else {
+            false
+        };
+        let feature_keywords = [
+            (lightmaps_enabled, LIGHTMAP),
+            (sh_ambient_enabled, SH_AMBIENT),
+            (shadows_enabled, SHADOWS),
+            (spot_shadows_enabled, SPOT_SHADOWS),
+            (ray_query_shadows_enabled, RAY_QUERY_SHADOWS),
+            (cross_section_enabled, CLIP_PLANES),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, keyword)| *keyword)
+        .collect::<Vec<_>>();
+        let (mut material_instances, model_material) =
+            Self::create_material_instances(&vk_context, &feature_keywords)?;
+        let mut shader_variants = ShaderVariants::default();
 
         let (vertices, indices) = Self::load_model();
-        let (vertex_buffer, vertex_buffer_memory) = Self::create_vertex_buffer(
+        let vertex_buffer = Self::create_vertex_buffer(
             &vk_context,
             transient_command_pool,
             graphics_queue,
             &vertices,
-        );
-        let (index_buffer, index_buffer_memory) = Self::create_index_buffer(
+        )?;
+        let index_buffer = Self::create_index_buffer(
             &vk_context,
             transient_command_pool,
             graphics_queue,
             &indices,
+        )?;
+        let acceleration_structures = if device_features.ray_tracing {
+            Some(Self::create_acceleration_structures(
+                &vk_context,
+                (transient_command_pool, graphics_queue),
+                (vertex_buffer, vertices.len()),
+                (index_buffer, indices.len()),
+            )?)
+        } else {
+            None
+        };
+        if let Some(acceleration_structures) = acceleration_structures
+            .as_ref()
+            .filter(|_| ray_query_shadows_enabled)
+        {
+            material_instances
+                .set_top_level(vk_context.device(), acceleration_structures.top_level());
+        }
+        let mesh_shading = if device_features.mesh_shader {
+            Some(Self::create_mesh_shading(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                vertex_buffer,
+                &vertices,
+                &indices,
+            )?)
+        } else {
+            None
+        };
+        let mut displaced_plane = if device_features.tessellation {
+            Some(Self::create_displaced_plane(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+            )?)
+        } else {
+            None
+        };
+        let mut terrain = if terrain_enabled {
+            Some(Self::create_terrain(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+        let mut mesh_streamer = if std::env::args().any(|arg| arg == "--streaming") {
+            Some(Self::create_mesh_streamer(&vk_context, images.len())?)
+        } else {
+            None
+        };
+        let mut static_batches = {
+            let batching = std::env::args().any(|arg| arg == "--static-batching");
+            if batching || std::env::args().any(|arg| arg == "--static-objects") {
+                Some(Self::create_static_batches(
+                    &vk_context,
+                    transient_command_pool,
+                    graphics_queue,
+                    &mut material_instances,
+                    batching,
+                )?)
+            } else {
+                None
+            }
+        };
+        let mut gpu_driven = if device_features.draw_indirect_count {
+            Some(Self::create_gpu_driven(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                images.len(),
+                model_material_index,
+            )?)
+        } else {
+            None
+        };
+        let (mut objects, object_demo) = if std::env::args().any(|arg| arg == "--objects") {
+            let (objects, demo) =
+                Self::create_objects(&vk_context, images.len(), &mut material_instances)?;
+            (Some(objects), Some(demo))
+        } else {
+            (None, None)
+        };
+        let mut virtual_texture = if device_features.sparse_residency {
+            Self::create_virtual_texture(
+                &vk_context,
+                queue_families_indices.graphics_index,
+                transient_command_pool,
+                graphics_queue,
+                images.len(),
+            )?
+        } else {
+            None
+        };
+        let depth_pyramid = if depth_pyramid_enabled {
+            let mut depth_pyramid = DepthPyramid::new(vk_context.device());
+            Self::set_depth_pyramid(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                &mut depth_pyramid,
+                (&depth_texture, depth_format),
+                properties.extent,
+            )?;
+            Self::create_depth_pyramid_pipelines(
+                vk_context.device(),
+                msaa_samples,
+                &mut depth_pyramid,
+            );
+            Some(depth_pyramid)
+        } else {
+            None
+        };
+        let mut hiz = terrain
+            .as_ref()
+            .filter(|_| hiz_enabled)
+            .map(|terrain| Self::create_hiz(&vk_context, terrain, images.len()))
+            .transpose()?;
+        if let (Some(hiz), Some(depth_pyramid)) = (hiz.as_mut(), depth_pyramid.as_ref()) {
+            hiz.set_pyramid(vk_context.device(), depth_pyramid);
+            Self::create_hiz_pipeline(vk_context.device(), vk_context.subgroup_properties(), hiz);
+        }
+        let uniform_buffers = Self::create_uniform_buffers(&vk_context, images.len())?;
+        let time_of_day = std::env::args().any(|arg| arg == "--time-of-day");
+        if time_of_day && deterministic {
+            log::warn!("The time of day is not animated when the frames are deterministic.");
+        }
+        let background_parameters = Self::get_background();
+        let mut sky = if time_of_day
+            || background_parameters.mode == BackgroundMode::Sky
+            || std::env::args().any(|arg| arg == "--sky")
+        {
+            let parameters = SkyParameters {
+                animate_time_of_day: time_of_day && !deterministic,
+                ..Default::default()
+            };
+            Some(Self::create_sky(&vk_context, parameters, images.len())?)
+        } else {
+            None
+        };
+        let mut background = match background_parameters.mode {
+            BackgroundMode::Gradient | BackgroundMode::Grid => {
+                Some(Self::create_background(&vk_context, background_parameters)?)
+            }
+            BackgroundMode::Color | BackgroundMode::Sky => None,
+        };
+        // The ambient light follows the sky, or the default one if it is not drawn
+        let sh_ambient = if sh_ambient_enabled {
+            Some(ShAmbient::new(
+                sky.as_ref()
+                    .map_or_else(SkyParameters::default, Sky::parameters),
+            ))
+        } else {
+            None
+        };
+        let mut decals = if std::env::args().any(|arg| arg == "--decals") {
+            match Self::create_decals(&vk_context, transient_command_pool, graphics_queue) {
+                Ok(decals) => {
+                    decals.set_depth_attachment(vk_context.device(), depth_texture.view);
+                    Some(decals)
+                }
+                Err(error) => {
+                    log::warn!("Decals disabled. Failed to allocate them: {}", error);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let mut sprites = if std::env::args().any(|arg| arg == "--sprites") {
+            Some(Self::create_sprite_renderer(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+        let mut particles = if std::env::args().any(|arg| arg == "--particles") {
+            let mut particles = Self::create_particles(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                images.len(),
+            )?;
+            Self::create_particle_pipelines(
+                vk_context.device(),
+                vk_context.subgroup_properties(),
+                &mut particles,
+            );
+            Some(particles)
+        } else {
+            None
+        };
+        let hud_stats = std::env::args().any(|arg| arg == "--hud");
+        let mut hud = if hud_stats || std::env::args().any(|arg| arg == "--console") {
+            Some(Self::create_text_renderer(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+        let mut auto_exposure = if auto_exposure_enabled {
+            Some(Self::create_auto_exposure(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+        let mut bloom = if std::env::args().any(|arg| arg == "--bloom") {
+            if !auto_exposure_enabled {
+                log::warn!("Bloom requested but it needs --auto-exposure.");
+                None
+            } else if !device_features.storage_image_array_indexing {
+                log::warn!("Bloom requested but storage image arrays cannot be indexed.");
+                None
+            } else {
+                Some(Self::create_bloom(
+                    &vk_context,
+                    transient_command_pool,
+                    graphics_queue,
+                )?)
+            }
+        } else {
+            None
+        };
+        let mut tonemapper = auto_exposure
+            .as_ref()
+            .map(|exposure| {
+                let color_lut = Self::create_color_lut(
+                    &vk_context,
+                    transient_command_pool,
+                    graphics_queue,
+                    &Self::get_color_lut(),
+                )?;
+                Ok(Tonemapper::new(
+                    vk_context.device(),
+                    exposure.exposure_buffer(),
+                    color_lut,
+                ))
+            })
+            .transpose()?;
+        let mut ray_traced_shadows = match acceleration_structures.as_ref() {
+            Some(acceleration_structures) if ray_traced_shadows_enabled => {
+                Some(Self::create_ray_traced_shadows(
+                    &vk_context,
+                    acceleration_structures,
+                    images.len(),
+                )?)
+            }
+            _ => None,
+        };
+        let mut ssr = if ssr_enabled {
+            Some(Self::create_ssr(
+                &vk_context,
+                properties.extent,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+        let mut light_shafts = if std::env::args().any(|arg| arg == "--light-shafts") {
+            if auto_exposure_enabled {
+                Some(Self::create_light_shafts(
+                    &vk_context,
+                    properties.extent,
+                    images.len(),
+                    half_res.light_shafts,
+                )?)
+            } else {
+                log::warn!("Light shafts requested but they need --auto-exposure.");
+                None
+            }
+        } else {
+            None
+        };
+        let mut motion_blur = if motion_blur_enabled {
+            if auto_exposure_enabled {
+                Some(Self::create_motion_blur(
+                    &vk_context,
+                    depth_format,
+                    properties.extent,
+                    images.len(),
+                )?)
+            } else {
+                log::warn!("Motion blur requested but it needs --auto-exposure.");
+                None
+            }
+        } else {
+            None
+        };
+        let mut fsr = if std::env::args().any(|arg| arg == "--fsr" || arg.starts_with("--fsr=")) {
+            if auto_exposure_enabled {
+                let mut fsr = Fsr::new(vk_context.device());
+                Self::create_fsr_pipelines(vk_context.device(), &mut fsr);
+                Some(fsr)
+            } else {
+                log::warn!("FSR requested but it needs --auto-exposure.");
+                None
+            }
+        } else {
+            None
+        };
+        let transient_allocator = Self::create_transient_allocator(&vk_context, images.len())?;
+        let gpu_timer = Self::create_gpu_timer(
+            &vk_context,
+            queue_families_indices.graphics_index,
+            images.len(),
+            device_features.pipeline_statistics,
+        );
+        let gpu_crash_dump =
+            Self::create_gpu_crash_dump(&vk_context, device_features.crash_markers, images.len())?;
+        let mut debug_draw = Self::create_debug_draw(&vk_context, images.len())?;
+        let mut occlusion_queries = if std::env::args().any(|arg| arg == "--occlusion-queries") {
+            if mesh_shading.is_none() {
+                Some(Self::create_occlusion_queries(
+                    &vk_context,
+                    device_features.conditional_rendering,
+                    (&vertices, indices.len()),
+                    images.len(),
+                )?)
+            } else {
+                log::warn!("Occlusion queries requested but they cannot skip mesh shader draws.");
+                None
+            }
+        } else {
+            None
+        };
+        let mut water = if std::env::args().any(|arg| arg == "--water") {
+            Some(Self::create_water(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                descriptor_set_layout,
+                texture,
+                properties.format.format,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+        let mut secondary_views = if std::env::args().any(|arg| arg == "--minimap") {
+            Some(Self::create_secondary_views(
+                &vk_context,
+                vec![SecondaryView::minimap()],
+                descriptor_set_layout,
+                texture,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+        let mut mirror = if mirror_enabled {
+            Some(Self::create_mirror(
+                &vk_context,
+                (transient_command_pool, graphics_queue),
+                MirrorQuad::behind_model(),
+                descriptor_set_layout,
+                texture,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+
+        let (mut scene, main_camera, model, sun) =
+            Self::create_scene(&vertices, &indices, model_material);
+        // The lights are spawned before the shadow atlas allocates their tiles
+        let pass_layers = PassLayers::default();
+        let light_gizmos = std::env::args().any(|arg| arg == "--light-gizmos");
+        if light_gizmos {
+            Self::spawn_demo_lights(&mut scene);
+        }
+        if spot_shadows_enabled {
+            Self::spawn_spot_lights(&mut scene);
+        }
+        let fog = Self::get_fog();
+        let shadow_filter = Self::get_shadow_filter();
+        let clip_planes = if cross_section_enabled {
+            vec![math::Plane::from_point_normal(
+                Point3::new(0.0, 0.0, 0.0),
+                -Vector3::unit_x(),
+            )]
+        } else {
+            Vec::new()
+        };
+        let reflection_probes = if std::env::args().any(|arg| arg == "--reflection-probes") {
+            if mesh_shading.is_none() {
+                Some(Self::create_reflection_probes(
+                    &vk_context,
+                    (transient_command_pool, graphics_queue),
+                    (&frame_layout, properties),
+                    (descriptor_set_layout, texture),
+                    (vertex_buffer, index_buffer, indices.len() as _),
+                    (scene.get::<math::Transform>(model).unwrap().matrix(), fog),
+                    images.len(),
+                )?)
+            } else {
+                log::warn!(
+                    "Reflection probes requested but the mesh shaders do not output normals."
+                );
+                None
+            }
+        } else {
+            None
+        };
+        let lightmap = if lightmaps_enabled {
+            let lightmap = Self::bake_lightmap(
+                &vk_context,
+                transient_command_pool,
+                graphics_queue,
+                properties,
+                &vertices,
+                (vertex_buffer, index_buffer, indices.len() as _),
+                (
+                    scene.get::<math::Transform>(model).unwrap().matrix(),
+                    scene.get::<Light>(sun).unwrap().direction,
+                ),
+            )?;
+            material_instances.set_lightmap(vk_context.device(), lightmap.texture());
+            Some(lightmap)
+        } else {
+            None
+        };
+        let shadow_map = if shadows_enabled {
+            let mut shadow_map = Self::create_shadow_map(&vk_context, &vertices, shadow_filter)?;
+            Self::create_shadow_map_pipeline(
+                vk_context.device(),
+                properties,
+                &frame_layout,
+                &mut shadow_map,
+            );
+            material_instances.set_shadow_map(vk_context.device(), shadow_map.texture());
+            Some(shadow_map)
+        } else {
+            None
+        };
+        let shadow_atlas = if spot_shadows_enabled {
+            let mut shadow_atlas =
+                Self::create_shadow_atlas(&vk_context, &extract_spot_lights(&scene))?;
+            Self::create_shadow_atlas_pipeline(
+                vk_context.device(),
+                properties,
+                &frame_layout,
+                &mut shadow_atlas,
+            );
+            material_instances.set_shadow_atlas(vk_context.device(), shadow_atlas.texture());
+            Some(shadow_atlas)
+        } else {
+            None
+        };
+
+        let secondary_window = match events_loop.as_ref() {
+            Some(events_loop) if std::env::args().any(|arg| arg == "--second-window") => {
+                Self::create_secondary_window(
+                    &vk_context,
+                    events_loop,
+                    queue_families_indices,
+                    descriptor_set_layout,
+                    texture,
+                )?
+            }
+            _ => None,
+        };
+
+        let mut stereo = if device_features.multiview {
+            Some(Self::create_stereo(
+                &vk_context,
+                descriptor_set_layout,
+                texture,
+                (scene_properties.format.format, depth_format),
+                properties.extent,
+                images.len(),
+            )?)
+        } else {
+            None
+        };
+        #[cfg(feature = "openxr")]
+        let xr = Self::create_xr_session(
+            xr_runtime,
+            &vk_context,
+            stereo.as_ref(),
+            queue_families_indices.graphics_index,
+            command_pool,
         );
-        let (uniform_buffers, uniform_buffer_memories) =
-            Self::create_uniform_buffers(&vk_context, images.len());
 
-        let descriptor_pool = Self::create_descriptor_pool(vk_context.device(), images.len() as _);
+        let mut descriptor_allocator = Self::create_descriptor_allocator(images.len() as _);
         let descriptor_sets = Self::create_descriptor_sets(
             vk_context.device(),
-            descriptor_pool,
+            &mut descriptor_allocator,
             descriptor_set_layout,
             &uniform_buffers,
             texture,
         );
 
-        let command_buffers = Self::create_and_register_command_buffers(
+        let (pipeline, layout) = Self::create_pipeline(
             vk_context.device(),
-            command_pool,
-            &swapchain_framebuffers,
-            render_pass,
-            properties,
-            vertex_buffer,
-            index_buffer,
-            indices.len(),
-            layout,
-            &descriptor_sets,
-            pipeline,
+            (properties, msaa_samples, render_pass),
+            (&frame_layout, &material_instances, &mut shader_variants),
+            (
+                bindless_textures.as_ref(),
+                mesh_shading.as_ref(),
+                reflection_probes.as_ref(),
+            ),
+            vertex_buffer.device_address().is_some() && mesh_shading.is_none(),
+            Some(outlined_stencil()).filter(|_| outline_enabled),
         );
-
-        let in_flight_frames = Self::create_sync_objects(vk_context.device());
-
-        Self {
-            events_loop,
-            _window: window,
-            resize_dimensions: None,
-            camera: Default::default(),
-            is_left_clicked: false,
-            cursor_position: [0, 0],
-            cursor_delta: None,
-            wheel_delta: None,
-            vk_context,
-            queue_families_indices,
-            graphics_queue,
-            present_queue,
-            swapchain,
-            swapchain_khr,
-            swapchain_properties: properties,
-            images,
-            swapchain_image_views,
-            render_pass,
-            descriptor_set_layout,
-            pipeline_layout: layout,
-            pipeline,
-            swapchain_framebuffers,
-            command_pool,
-            transient_command_pool,
-            msaa_samples,
-            color_texture,
-            depth_format,
-            depth_texture,
-            texture,
-            model_index_count: indices.len(),
-            vertex_buffer,
-            vertex_buffer_memory,
-            index_buffer,
-            index_buffer_memory,
-            uniform_buffers,
-            uniform_buffer_memories,
-            descriptor_pool,
-            descriptor_sets,
-            command_buffers,
-            in_flight_frames,
+        if let Some(plane) = displaced_plane.as_mut() {
+            Self::create_displacement_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                plane,
+            );
         }
-    }
-
-    fn create_instance(entry: &Entry) -> Instance {
-        let app_name = CString::new("Vulkan Application").unwrap();
-        let engine_name = CString::new("No Engine").unwrap();
-        let app_info = vk::ApplicationInfo::builder()
-            .application_name(app_name.as_c_str())
-            .application_version(ash::vk_make_version!(0, 1, 0))
-            .engine_name(engine_name.as_c_str())
-            .engine_version(ash::vk_make_version!(0, 1, 0))
-            .api_version(ash::vk_make_version!(1, 0, 0))
-            .build();
-
-        let mut extension_names = surface::required_extension_names();
-        if ENABLE_VALIDATION_LAYERS {
-            extension_names.push(DebugReport::name().as_ptr());
+        if let Some(terrain) = terrain.as_mut() {
+            Self::create_terrain_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                terrain,
+            );
+        }
+        if let Some(mesh_streamer) = mesh_streamer.as_mut() {
+            Self::create_mesh_streamer_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                mesh_streamer,
+            );
+        }
+        if let Some(objects) = objects.as_mut() {
+            let (pipeline, layout) = Self::create_object_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                objects.layout(),
+            );
+            objects.set_pipeline(pipeline, layout);
+        }
+        if let Some(static_batches) = static_batches.as_mut() {
+            let (pipeline, layout) = Self::create_object_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                static_batches.layout(),
+            );
+            static_batches.set_pipeline(pipeline, layout);
+        }
+        if let (Some(gpu_driven), Some(bindless_textures)) =
+            (gpu_driven.as_mut(), bindless_textures.as_ref())
+        {
+            Self::create_gpu_driven_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                (&frame_layout, bindless_textures),
+                gpu_driven,
+            );
+        }
+        if let Some(virtual_texture) = virtual_texture.as_mut() {
+            Self::create_virtual_texture_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                virtual_texture,
+            );
+        }
+        if let Some(water) = water.as_mut() {
+            Self::create_water_pipelines(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                water,
+            );
+        }
+        if let Some(secondary_views) = secondary_views.as_mut() {
+            Self::create_secondary_views_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                secondary_views,
+            );
+        }
+        if let Some(mirror) = mirror.as_mut() {
+            Self::create_mirror_pipelines(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                mirror,
+            );
+        }
+        if let Some(stereo) = stereo.as_mut() {
+            Self::create_stereo_pipelines(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                stereo,
+            );
+        }
+        if let Some(sky) = sky.as_mut() {
+            Self::create_sky_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                sky,
+            );
+        }
+        if let Some(background) = background.as_mut() {
+            Self::create_background_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                background,
+            );
+        }
+        if let Some(sprites) = sprites.as_mut() {
+            Self::create_sprite_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                sprites,
+            );
+        }
+        if let Some(particles) = particles.as_mut() {
+            Self::create_particle_draw_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                particles,
+            );
+        }
+        if let Some(decals) = decals.as_mut() {
+            Self::create_decals_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                decals,
+            );
+        }
+        if let (Some(exposure), Some(hdr)) = (auto_exposure.as_mut(), hdr_texture.as_ref()) {
+            exposure.set_hdr_texture(vk_context.device(), hdr, properties.extent);
+            Self::create_auto_exposure_pipelines(
+                vk_context.device(),
+                vk_context.subgroup_properties(),
+                exposure,
+            );
+        }
+        if let (Some(bloom), Some(hdr)) = (bloom.as_mut(), hdr_texture.as_ref()) {
+            Self::set_bloom_chain(&vk_context, bloom, hdr, properties.extent)?;
+            Self::create_bloom_pipeline(
+                vk_context.device(),
+                vk_context.subgroup_properties(),
+                bloom,
+            );
+        }
+        if let (Some(shadows), Some(hdr)) = (ray_traced_shadows.as_mut(), hdr_texture.as_ref()) {
+            shadows.set_images(
+                vk_context.device(),
+                hdr,
+                (&depth_texture, Self::get_depth_aspect(depth_format)),
+                properties.extent,
+            );
+            Self::create_ray_traced_shadows_pipeline(&vk_context, msaa_samples, shadows)?;
+        }
+        if let (Some(ssr), Some(hdr)) = (ssr.as_mut(), hdr_texture.as_ref()) {
+            Self::set_ssr_inputs(
+                &vk_context,
+                (transient_command_pool, graphics_queue),
+                ssr,
+                hdr,
+                &depth_texture,
+                depth_pyramid.as_ref().unwrap(),
+                motion_blur.as_ref().unwrap(),
+            )?;
+            Self::create_ssr_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                &frame_layout,
+                &mut shader_variants,
+                reflection_probes.as_ref(),
+                ssr,
+            );
+        }
+        if let (Some(light_shafts), Some(hdr)) = (light_shafts.as_mut(), hdr_texture.as_ref()) {
+            light_shafts.set_hdr_texture(
+                vk_context.device(),
+                Self::get_post_output(hdr, ssr.as_ref(), None, None, None),
+            );
+            if let Some(upsample) = light_shafts.upsample_mut() {
+                Self::set_upsample_depth(
+                    vk_context.device(),
+                    upsample,
+                    (&depth_texture, depth_format),
+                    depth_pyramid.is_some(),
+                );
+            }
+            Self::create_light_shafts_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                &frame_layout,
+                light_shafts,
+            );
+        }
+        if let (Some(motion_blur), Some(hdr)) = (motion_blur.as_mut(), hdr_texture.as_ref()) {
+            motion_blur.set_hdr_texture(
+                vk_context.device(),
+                Self::get_post_output(hdr, ssr.as_ref(), light_shafts.as_ref(), None, None),
+            );
+            Self::create_motion_blur_pipelines(vk_context.device(), properties, motion_blur);
+        }
+        if let (Some(fsr), Some(hdr)) = (fsr.as_mut(), hdr_texture.as_ref()) {
+            Self::set_fsr_targets(
+                &vk_context,
+                fsr,
+                Self::get_post_output(
+                    hdr,
+                    ssr.as_ref(),
+                    light_shafts.as_ref(),
+                    motion_blur.as_ref(),
+                    None,
+                ),
+                swapchain_properties.extent,
+            )?;
         }
+        if let (Some(tonemapper), Some(hdr)) = (tonemapper.as_mut(), hdr_texture.as_ref()) {
+            tonemapper.set_targets(
+                vk_context.device(),
+                Self::get_post_output(
+                    hdr,
+                    ssr.as_ref(),
+                    light_shafts.as_ref(),
+                    motion_blur.as_ref(),
+                    fsr.as_ref(),
+                ),
+                swapchain_properties,
+                Self::get_final_layout(headless),
+                &swapchain_image_views,
+            );
+            if let Some(bloom) = bloom.as_ref() {
+                tonemapper.set_bloom(vk_context.device(), bloom.output());
+            }
+            Self::create_tonemap_pipeline(
+                vk_context.device(),
+                swapchain_properties,
+                &mut shader_variants,
+                bloom.is_some(),
+                tonemapper,
+            );
+        }
+        if let Some(hud) = hud.as_mut() {
+            Self::create_text_pipeline(
+                vk_context.device(),
+                swapchain_properties,
+                msaa_samples,
+                render_pass,
+                tonemapper.as_ref(),
+                hud,
+            );
+        }
+        Self::create_debug_draw_pipeline(
+            vk_context.device(),
+            properties,
+            msaa_samples,
+            render_pass,
+            &frame_layout,
+            &mut debug_draw,
+        );
+        if let Some(occlusion_queries) = occlusion_queries.as_mut() {
+            Self::create_occlusion_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+                occlusion_queries,
+            );
+        }
+        let normals_pipeline = if device_features.geometry_shader {
+            Some(Self::create_normals_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+            ))
+        } else {
+            None
+        };
+        let outline_pipeline = if outline_enabled {
+            Some(Self::create_outline_pipeline(
+                vk_context.device(),
+                properties,
+                msaa_samples,
+                render_pass,
+                &frame_layout,
+            ))
+        } else {
+            None
+        };
 
-        let (_layer_names, layer_names_ptrs) = get_layer_names_and_pointers();
+        let command_buffers = Self::allocate_command_buffers(
+            vk_context.device(),
+            command_pool,
+            swapchain_framebuffers.len(),
+        );
+        let bind_stats = Self::record_command_buffers(
+            vk_context.device(),
+            &command_buffers,
+            FrameResources {
+                framebuffers: &swapchain_framebuffers,
+                render_pass,
+                swapchain_properties: properties,
+                vertex_buffer,
+                index_buffer,
+                index_count: indices.len(),
+                pass_layers,
+                model_layers: scene.get::<MeshRenderer>(model).unwrap().layers,
+                pipeline_layout: layout,
+                frame_layout: &frame_layout,
+                descriptor_sets: &descriptor_sets,
+                bindless_textures: bindless_textures.as_ref(),
+                material_index: model_material_index,
+                material_instances: &material_instances,
+                model_material,
+                mesh_shading: mesh_shading.as_ref(),
+                displaced_plane: displaced_plane.as_ref(),
+                terrain: terrain.as_ref(),
+                mesh_streamer: mesh_streamer.as_ref(),
+                objects: objects.as_ref(),
+                static_batches: static_batches.as_ref(),
+                gpu_driven: gpu_driven.as_ref(),
+                virtual_texture: virtual_texture.as_ref(),
+                depth_pyramid: depth_pyramid.as_ref(),
+                hiz: hiz.as_ref(),
+                water: water.as_ref(),
+                secondary_views: secondary_views.as_ref(),
+                mirror: mirror.as_ref(),
+                stereo: stereo.as_ref(),
+                sky: sky.as_ref(),
+                background: background.as_ref(),
+                clear_color: background_parameters.clear_color,
+                sprites: sprites.as_ref(),
+                particles: particles.as_ref(),
+                decals: decals.as_ref(),
+                debug_draw: &debug_draw,
+                occlusion_queries: occlusion_queries.as_ref(),
+                reflection_probes: reflection_probes.as_ref(),
+                lightmap: lightmap.as_ref(),
+                shadow_map: shadow_map.as_ref(),
+                shadow_atlas: shadow_atlas.as_ref(),
+                transient_allocator: &transient_allocator,
+                hud: hud.as_ref(),
+                auto_exposure: auto_exposure.as_ref(),
+                bloom: bloom.as_ref(),
+                tonemapper: tonemapper.as_ref(),
+                ssr: ssr.as_ref(),
+                ray_traced_shadows: ray_traced_shadows.as_ref(),
+                light_shafts: light_shafts.as_ref(),
+                motion_blur: motion_blur.as_ref(),
+                fsr: fsr.as_ref(),
+                gpu_timer: gpu_timer.as_ref(),
+                gpu_crash_dump: gpu_crash_dump.as_ref(),
+                present_transfer: present_transfer.as_ref(),
+                normals_pipeline: None,
+                outline_pipeline: None,
+                graphics_pipeline: pipeline,
+            },
+        );
 
-        let mut instance_create_info = vk::InstanceCreateInfo::builder()
-            .application_info(&app_info)
-            .enabled_extension_names(&extension_names);
-        if ENABLE_VALIDATION_LAYERS {
-            check_validation_layer_support(&entry);
-            instance_create_info = instance_create_info.enabled_layer_names(&layer_names_ptrs);
-        }
+        let in_flight_frames = Self::create_sync_objects(vk_context.device());
 
-        unsafe { entry.create_instance(&instance_create_info, None).unwrap() }
+        let render_thread = std::env::args().any(|arg| arg == "--render-thread");
+        // The headset frames are submitted from the main thread between the
+        // frames of the window, which the render thread would overlap with
+        #[cfg(feature = "openxr")]
+        let render_thread = if render_thread && xr.is_some() {
+            log::warn!("The render thread is not supported with OpenXR.");
+            false
+        } else {
+            render_thread
+        };
+        let render_thread = if !headless && render_thread {
+            Some(RenderThread::new(
+                vk_context.instance(),
+                vk_context.device(),
+                (graphics_queue, present_queue),
+            ))
+        } else {
+            None
+        };
+
+        let mut app = Self {
+            events_loop,
+            window,
+            resize_dimensions: None,
+            time_controller: TimeController::new(if headless || deterministic {
+                Self::get_fixed_time()
+            } else {
+                0.0
+            }),
+            time_step: if deterministic {
+                Some(DETERMINISTIC_TIME_STEP)
+            } else {
+                None
+            },
+            last_frame_instant: Instant::now(),
+            input_recorder: None,
+            input_playback: None,
+            input_delta: None,
+            frame_time: 0.0,
+            last_profile_log: if std::env::args().any(|arg| arg == "--profile") {
+                Some(Instant::now())
+            } else {
+                None
+            },
+            benchmark: Self::get_benchmark()
+                .map(|(frame_count, output)| Benchmark::new(frame_count, output, images.len())),
+            asset_loader: if std::env::args().any(|arg| arg == "--hot-reload") {
+                Some(Self::create_asset_loader())
+            } else {
+                None
+            },
+            scene,
+            main_camera,
+            model,
+            sun,
+            render_list: RenderList::default(),
+            pass_layers,
+            is_left_clicked: false,
+            cursor_position: [0, 0],
+            cursor_delta: None,
+            wheel_delta: None,
+            debug_views: Default::default(),
+            fog,
+            clip_planes,
+            shadow_filter,
+            selected: None,
+            light_gizmos,
+            gizmo: Default::default(),
+            vk_context,
+            queue_families_indices,
+            graphics_queue,
+            present_queue,
+            swapchain,
+            offscreen_target,
+            swapchain_properties,
+            images,
+            swapchain_image_views,
+            present_transfer,
+            render_pass,
+            frame_layout,
+            pipeline_layout: layout,
+            pipeline,
+            swapchain_framebuffers,
+            command_pool,
+            transient_command_pool,
+            msaa_samples,
+            color_texture,
+            depth_format,
+            depth_texture,
+            texture,
+            texture_streamer,
+            vertex_buffer,
+            index_buffer,
+            uniform_buffers,
+            descriptor_allocator,
+            descriptor_sets,
+            bindless_textures,
+            model_texture_index,
+            model_material_index,
+            material_instances,
+            model_material,
+            shader_variants,
+            mesh_shading,
+            displaced_plane,
+            terrain,
+            mesh_streamer,
+            objects,
+            static_batches,
+            gpu_driven,
+            object_demo,
+            virtual_texture,
+            depth_pyramid,
+            hiz,
+            occluded_count: 0,
+            particle_count: 0,
+            water,
+            secondary_views,
+            mirror,
+            secondary_window,
+            stereo,
+            #[cfg(feature = "openxr")]
+            xr,
+            sky,
+            background,
+            clear_color: background_parameters.clear_color,
+            decals,
+            sprites,
+            particles,
+            hud,
+            hud_stats,
+            console: None,
+            is_reload_requested: false,
+            hdr_texture,
+            render_scale,
+            dynamic_resolution,
+            auto_exposure,
+            bloom,
+            tonemapper,
+            ssr,
+            acceleration_structures,
+            ray_traced_shadows,
+            light_shafts,
+            motion_blur,
+            fsr,
+            half_res,
+            transient_allocator,
+            debug_draw,
+            occlusion_queries,
+            occluded_objects: 0,
+            gpu_timer,
+            gpu_frame: None,
+            gpu_crash_dump,
+            reflection_probes,
+            lightmap,
+            sh_ambient,
+            shadow_map,
+            shadow_atlas,
+            normals_pipeline,
+            outline_pipeline,
+            command_buffers,
+            bind_stats,
+            submit_batch: SubmitBatch::default(),
+            submit_stats: SubmitStats::default(),
+            in_flight_frames,
+            is_device_lost: false,
+            allocation_error: None,
+            is_suspended: false,
+            render_thread,
+        };
+        app.recreate_secondary_window()?;
+        if std::env::args().any(|arg| arg == "--console") {
+            app.console = Some(app.create_console());
+        }
+        Ok(app)
     }
 
-    /// Pick the first suitable physical device.
+    /// Get the optional features to enable on `physical_device`.
     ///
-    /// # Requirements
-    /// - At least one queue family with one queue supportting graphics.
-    /// - At least one queue family with one queue supporting presentation to `surface_khr`.
-    /// - Swapchain extension support.
+    /// Optional features are queried using Vulkan 1.1 so they are all
+    /// disabled if it is not supported.
     ///
-    /// # Returns
+    /// The mesh shader path is experimental so it is only enabled if the
+    /// `--mesh-shaders` argument is passed. Since it uses the same descriptor
+    /// set index as the bindless textures, it disables them. So do the
+    /// reflection probes of the `--reflection-probes` argument.
     ///
-    /// A tuple containing the physical device and the queue families indices.
-    fn pick_physical_device(
+    /// The displaced plane demo is only enabled if the `--tessellation`
+    /// argument is passed.
+    ///
+    /// Conditional rendering is only enabled for the occlusion queries of the
+    /// `--occlusion-queries` argument, which read their results back without it.
+    ///
+    /// The GPU-driven instances of the `--gpu-driven` argument need the
+    /// bindless textures and the count of indirect draws.
+    ///
+    /// Sparse residency is only enabled for the virtual texture of the
+    /// `--virtual-texture` argument, whose pages are bound on the graphics
+    /// queue of the family `graphics_family_index`.
+    ///
+    /// Clip distances are enabled when supported, for the clip planes of the
+    /// frame uniforms, like the dynamic indexing of storage image arrays for
+    /// the bloom.
+    fn get_device_features(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
-    ) -> (vk::PhysicalDevice, QueueFamiliesIndices) {
-        let devices = unsafe { instance.enumerate_physical_devices().unwrap() };
-        let device = devices
-            .into_iter()
-            .find(|device| Self::is_device_suitable(instance, surface, surface_khr, *device))
-            .expect("No suitable physical device.");
+        physical_device: vk::PhysicalDevice,
+        graphics_family_index: u32,
+        api_version: u32,
+    ) -> DeviceFeatures {
+        let vulkan_1_1_supported = api_version >= ash::vk_make_version!(1, 1, 0)
+            && is_vulkan_1_1_supported(instance, physical_device);
+
+        let mesh_shader_requested = std::env::args().any(|arg| arg == "--mesh-shaders");
+        let mesh_shader = mesh_shader_requested
+            && vulkan_1_1_supported
+            && mesh_shader::is_supported(instance, physical_device);
+        if mesh_shader_requested && !mesh_shader {
+            log::warn!("Mesh shaders requested but not supported.");
+        }
 
-        let props = unsafe { instance.get_physical_device_properties(device) };
-        log::debug!("Selected physical device: {:?}", unsafe {
-            CStr::from_ptr(props.device_name.as_ptr())
-        });
+        let tessellation_requested = std::env::args().any(|arg| arg == "--tessellation");
+        let tessellation = tessellation_requested
+            && unsafe { instance.get_physical_device_features(physical_device) }
+                .tessellation_shader
+                == vk::TRUE;
+        if tessellation_requested && !tessellation {
+            log::warn!("Tessellation requested but not supported.");
+        }
 
-        let (graphics, present) = Self::find_queue_families(instance, surface, surface_khr, device);
-        let queue_families_indices = QueueFamiliesIndices {
-            graphics_index: graphics.unwrap(),
-            present_index: present.unwrap(),
-        };
+        // Headsets are rendered to using the stereo pass
+        let stereo_requested = std::env::args().any(|arg| arg == "--stereo" || arg == "--xr");
+        let multiview = stereo_requested
+            && vulkan_1_1_supported
+            && stereo::is_supported(instance, physical_device);
+        if stereo_requested && !multiview {
+            log::warn!("Stereo rendering requested but multiview is not supported.");
+        }
 
-        (device, queue_families_indices)
-    }
+        let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+        let geometry_shader = supported_features.geometry_shader == vk::TRUE;
+        let clip_distance = supported_features.shader_clip_distance == vk::TRUE;
+        let storage_image_array_indexing =
+            supported_features.shader_storage_image_array_dynamic_indexing == vk::TRUE;
+        let storage_image_extended_formats =
+            supported_features.shader_storage_image_extended_formats == vk::TRUE;
+
+        let conditional_rendering = std::env::args().any(|arg| arg == "--occlusion-queries")
+            && vulkan_1_1_supported
+            && occlusion::is_supported(instance, physical_device);
+
+        let virtual_texture_requested = std::env::args().any(|arg| arg == "--virtual-texture");
+        let sparse_residency = virtual_texture_requested
+            && virtual_texture::is_supported(instance, physical_device, graphics_family_index);
+        if virtual_texture_requested && !sparse_residency {
+            log::warn!("Virtual texturing requested but sparse residency is not supported.");
+        }
 
-    fn is_device_suitable(
-        instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
-        device: vk::PhysicalDevice,
-    ) -> bool {
-        let (graphics, present) = Self::find_queue_families(instance, surface, surface_khr, device);
-        let extention_support = Self::check_device_extension_support(instance, device);
-        let is_swapchain_adequate = {
-            let details = SwapchainSupportDetails::new(device, surface, surface_khr);
-            !details.formats.is_empty() && !details.present_modes.is_empty()
-        };
-        let features = unsafe { instance.get_physical_device_features(device) };
-        graphics.is_some()
-            && present.is_some()
-            && extention_support
-            && is_swapchain_adequate
-            && features.sampler_anisotropy == vk::TRUE
-    }
+        let synchronization2 = vulkan_1_1_supported
+            && !std::env::args().any(|arg| arg == "--legacy-barriers")
+            && sync2::is_supported(instance, physical_device);
 
-    fn check_device_extension_support(instance: &Instance, device: vk::PhysicalDevice) -> bool {
-        let required_extentions = Self::get_required_device_extensions();
+        let pipeline_statistics = Self::get_benchmark().is_some()
+            && gpu_timer::is_pipeline_statistics_supported(instance, physical_device);
 
-        let extension_props = unsafe {
-            instance
-                .enumerate_device_extension_properties(device)
-                .unwrap()
-        };
+        let bindless = vulkan_1_1_supported
+            && !mesh_shader
+            && !std::env::args().any(|arg| arg == "--reflection-probes")
+            && bindless::is_supported(instance, physical_device);
 
-        for required in required_extentions.iter() {
-            let found = extension_props.iter().any(|ext| {
-                let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
-                required == &name
-            });
+        let gpu_driven_requested = std::env::args().any(|arg| arg == "--gpu-driven");
+        let draw_indirect_count =
+            gpu_driven_requested && bindless && gpu_driven::is_supported(instance, physical_device);
+        if gpu_driven_requested && !draw_indirect_count {
+            log::warn!("GPU-driven rendering requested but not supported.");
+        }
 
-            if !found {
-                return false;
+        let crash_markers = if Self::get_gpu_crash_dump_path().is_some() {
+            let extension = MarkerExtension::find_supported(instance, physical_device);
+            if extension.is_none() {
+                log::warn!("GPU crash markers not supported, only the submits are dumped.");
             }
+            extension
+        } else {
+            None
+        };
+
+        let ray_traced_shadows_requested =
+            std::env::args().any(|arg| arg == "--ray-traced-shadows");
+        let ray_query_requested = std::env::args().any(|arg| arg == "--ray-query-shadows");
+        let ray_tracing = (ray_traced_shadows_requested || ray_query_requested)
+            && vulkan_1_1_supported
+            && ray_tracing::is_supported(instance, physical_device);
+        if ray_traced_shadows_requested && !ray_tracing {
+            log::warn!("Ray-traced shadows requested but ray tracing is not supported.");
+        }
+        let ray_query = ray_query_requested
+            && ray_tracing
+            && ray_tracing::is_ray_query_supported(instance, physical_device);
+        if ray_query_requested && !ray_query {
+            log::warn!("Ray query shadows requested but ray queries are not supported.");
         }
 
-        true
+        let features = DeviceFeatures {
+            bindless,
+            // The KHR buffer device address of the ray tracing replaces the EXT one
+            device_address: vulkan_1_1_supported
+                && !ray_tracing
+                && device_address::is_supported(instance, physical_device),
+            ray_tracing,
+            ray_query,
+            mesh_shader,
+            tessellation,
+            geometry_shader,
+            clip_distance,
+            storage_image_array_indexing,
+            storage_image_extended_formats,
+            subgroup: if vulkan_1_1_supported {
+                Some(unsafe { SubgroupProperties::query(instance, physical_device) })
+            } else {
+                None
+            },
+            memory_budget: vulkan_1_1_supported && memory::is_supported(instance, physical_device),
+            multiview,
+            conditional_rendering,
+            sparse_residency,
+            synchronization2,
+            pipeline_statistics,
+            draw_indirect_count,
+            crash_markers,
+        };
+        log::debug!("Optional device features: {:?}", features);
+        features
     }
 
-    fn get_required_device_extensions() -> [&'static CStr; 1] {
-        [Swapchain::name()]
+    /// Get the fog parameters from the command line.
+    ///
+    /// The distance fog mode is selected with `--fog=<none|linear|exponential>`
+    /// and the height fog is enabled with `--height-fog`.
+    fn get_fog() -> Fog {
+        let mut fog = Fog::default();
+        for arg in std::env::args() {
+            if let Some(name) = arg.strip_prefix("--fog=") {
+                match FogMode::from_name(name) {
+                    Some(mode) => fog.mode = mode,
+                    None => log::warn!("Unknown fog mode {}.", name),
+                }
+            } else if arg == "--height-fog" {
+                fog.height_density = 0.5;
+            }
+        }
+        log::debug!("Fog: {:?}", fog);
+        fog
     }
 
-    /// Find a queue family with at least one graphics queue and one with
-    /// at least one presentation queue from `device`.
-    ///
-    /// #Returns
+    /// Get the background parameters from the command line.
     ///
-    /// Return a tuple (Option<graphics_family_index>, Option<present_family_index>).
-    fn find_queue_families(
-        instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
-        device: vk::PhysicalDevice,
-    ) -> (Option<u32>, Option<u32>) {
-        let mut graphics = None;
-        let mut present = None;
+    /// The mode is selected with `--background=<color|gradient|sky|grid>`, the
+    /// clear color with `--clear-color=<r>,<g>,<b>` in linear values from 0 to 1
+    /// and the distance between the lines of the grid with `--grid-spacing=<distance>`.
+    fn get_background() -> BackgroundParameters {
+        let mut background = BackgroundParameters::default();
+        for arg in std::env::args() {
+            if let Some(name) = arg.strip_prefix("--background=") {
+                match BackgroundMode::from_name(name) {
+                    Some(mode) => background.mode = mode,
+                    None => log::warn!("Unknown background mode {}.", name),
+                }
+            } else if let Some(color) = arg.strip_prefix("--clear-color=") {
+                let components = color
+                    .split(',')
+                    .map(|component| component.trim().parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>();
+                match components.as_deref() {
+                    Ok(&[r, g, b]) => background.clear_color = [r, g, b],
+                    _ => log::warn!("Invalid clear color {}.", color),
+                }
+            } else if let Some(spacing) = arg.strip_prefix("--grid-spacing=") {
+                match spacing.parse::<f32>() {
+                    Ok(spacing) if spacing > 0.0 => background.grid_spacing = spacing,
+                    _ => log::warn!("Invalid grid spacing {}.", spacing),
+                }
+            }
+        }
+        log::debug!("Background: {:?}", background);
+        background
+    }
 
-        let props = unsafe { instance.get_physical_device_queue_family_properties(device) };
-        for (index, family) in props.iter().filter(|f| f.queue_count > 0).enumerate() {
-            let index = index as u32;
+    /// Get the render scale and the dynamic resolution from the command line.
+    ///
+    /// The scene is rendered at a fraction of the swapchain size with
+    /// `--render-scale=<scale>`, from 0.5 to 1. With `--dynamic-resolution`
+    /// the fraction starts there and follows the GPU frame time to hold 60
+    /// frames per second, or `<fps>` with `--dynamic-resolution=<fps>`. Both
+    /// need the HDR image of `--auto-exposure`, which the tonemapper upscales
+    /// to the swapchain images, or FSR with `--fsr`. The FSR quality modes of
+    /// `--fsr=<ultra-quality|quality|balanced|performance>` set the scale.
+    fn get_render_scale(hdr: bool) -> (f32, Option<DynamicResolution>) {
+        let mut render_scale = MAX_RENDER_SCALE;
+        let mut target_fps = None;
+        for arg in std::env::args() {
+            if let Some(scale) = arg.strip_prefix("--render-scale=") {
+                match scale.parse::<f32>() {
+                    Ok(scale) if scale > 0.0 => {
+                        render_scale = scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE)
+                    }
+                    _ => log::warn!("Invalid render scale {}.", scale),
+                }
+            } else if let Some(name) = arg.strip_prefix("--fsr=") {
+                match FsrQuality::from_name(name) {
+                    Some(quality) => render_scale = quality.render_scale(),
+                    None => log::warn!("Unknown FSR mode {}.", name),
+                }
+            } else if arg == "--dynamic-resolution" {
+                target_fps = Some(DEFAULT_TARGET_FPS);
+            } else if let Some(fps) = arg.strip_prefix("--dynamic-resolution=") {
+                match fps.parse::<f32>() {
+                    Ok(fps) if fps > 0.0 => target_fps = Some(fps),
+                    _ => log::warn!("Invalid target frame rate {}.", fps),
+                }
+            }
+        }
+        if (render_scale < MAX_RENDER_SCALE || target_fps.is_some()) && !hdr {
+            log::warn!("Render scale requested but it needs --auto-exposure.");
+            return (MAX_RENDER_SCALE, None);
+        }
+        let dynamic_resolution = target_fps.map(|fps| DynamicResolution::new(fps, render_scale));
+        log::debug!(
+            "Render scale: {}, dynamic resolution: {:?}",
+            render_scale,
+            dynamic_resolution
+        );
+        (
+            dynamic_resolution.map_or(render_scale, |dynamic| dynamic.scale()),
+            dynamic_resolution,
+        )
+    }
 
-            if family.queue_flags.contains(vk::QueueFlags::GRAPHICS) && graphics.is_none() {
-                graphics = Some(index);
+    /// Get the effects rendered at half resolution from the command line.
+    ///
+    /// They are listed with `--half-res=<effect>,...`. Only the light shafts,
+    /// also named `volumetrics`, can be rendered at half resolution.
+    fn get_half_res_effects() -> HalfResEffects {
+        let mut effects = HalfResEffects::default();
+        for arg in std::env::args() {
+            if let Some(names) = arg.strip_prefix("--half-res=") {
+                for name in names.split(',') {
+                    match name {
+                        "light-shafts" | "volumetrics" => effects.light_shafts = true,
+                        "ssao" => log::warn!("There is no SSAO pass to render at half resolution."),
+                        "ssr" => log::warn!(
+                            "SSR cannot be rendered at half resolution, its rays are traced in the full resolution depth."
+                        ),
+                        _ => log::warn!("Unknown half resolution effect {}.", name),
+                    }
+                }
             }
+        }
+        log::debug!("Half resolution effects: {:?}", effects);
+        effects
+    }
 
-            let present_support =
-                unsafe { surface.get_physical_device_surface_support(device, index, surface_khr) };
-            if present_support && present.is_none() {
-                present = Some(index);
+    /// Get the face culling of the base material.
+    ///
+    /// The culled faces are selected with `--cull=<back|front|none>`, or none
+    /// with `--double-sided`, and the front faces are clockwise with `--clockwise`.
+    fn get_face_culling() -> FaceCulling {
+        let mut culling = FaceCulling::default();
+        for arg in std::env::args() {
+            if let Some(name) = arg.strip_prefix("--cull=") {
+                match CullMode::from_name(name) {
+                    Some(mode) => culling.cull_mode = mode,
+                    None => log::warn!("Unknown cull mode {}.", name),
+                }
+            } else if arg == "--double-sided" {
+                culling.cull_mode = CullMode::None;
+            } else if arg == "--clockwise" {
+                culling.front_face = vk::FrontFace::CLOCKWISE;
             }
+        }
+        log::debug!("Face culling: {:?}", culling);
+        culling
+    }
 
-            if graphics.is_some() && present.is_some() {
-                break;
+    /// Get the filter of the shadow maps given with `--shadow-filter=<pcf|pcss|vsm>`.
+    fn get_shadow_filter() -> ShadowFilter {
+        std::env::args()
+            .find_map(|arg| {
+                let name = arg.strip_prefix("--shadow-filter=")?;
+                let filter = ShadowFilter::from_name(name);
+                if filter.is_none() {
+                    log::warn!("Unknown shadow filter {}.", name);
+                }
+                filter
+            })
+            .unwrap_or(ShadowFilter::Pcf)
+    }
+
+    /// Get the color lookup table given with `--color-grading=<path>`.
+    ///
+    /// The path is a `.cube` file. The neutral LUT is returned if the argument
+    /// is not passed or if the file cannot be loaded.
+    fn get_color_lut() -> ColorLut {
+        let path = match std::env::args()
+            .find_map(|arg| arg.strip_prefix("--color-grading=").map(PathBuf::from))
+        {
+            Some(path) => path,
+            None => return ColorLut::neutral(NEUTRAL_LUT_SIZE),
+        };
+        let lut = std::fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|content| ColorLut::from_cube(&content).map_err(|error| error.to_string()));
+        match lut {
+            Ok(lut) => lut,
+            Err(error) => {
+                log::warn!("Failed to load LUT {}: {}.", path.display(), error);
+                ColorLut::neutral(NEUTRAL_LUT_SIZE)
             }
         }
+    }
 
-        (graphics, present)
+    /// Get the path of the image to write if the `--headless` argument is passed.
+    ///
+    /// The path can be given with `--headless=<path>`, `frame.png` is used otherwise.
+    fn get_headless_output() -> Option<PathBuf> {
+        std::env::args().find_map(|arg| {
+            if arg == "--headless" {
+                Some(PathBuf::from("frame.png"))
+            } else {
+                arg.strip_prefix("--headless=").map(PathBuf::from)
+            }
+        })
     }
 
-    /// Create the logical device to interact with `device`, a graphics queue
-    /// and a presentation queue.
+    /// Get the number of frames to measure and the path of the reports if the
+    /// `--benchmark` argument is passed.
     ///
-    /// # Returns
+    /// The number of frames can be given with `--benchmark=<frames>`, 600 are
+    /// measured otherwise. The path of the reports, without extension, can be
+    /// given with `--benchmark-output=<path>`, `benchmark` is used otherwise.
+    fn get_benchmark() -> Option<(u32, PathBuf)> {
+        let frame_count = std::env::args().find_map(|arg| {
+            if arg == "--benchmark" {
+                Some(600)
+            } else {
+                arg.strip_prefix("--benchmark=")
+                    .map(|frames| frames.parse().expect("Invalid benchmark frame count"))
+            }
+        })?;
+        let output = std::env::args()
+            .find_map(|arg| arg.strip_prefix("--benchmark-output=").map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("benchmark"));
+        Some((frame_count, output))
+    }
+
+    /// Check if the frames must be reproducible, with `--deterministic` or `--benchmark`.
     ///
-    /// Return a tuple containing the logical device, the graphics queue and the presentation queue.
-    fn create_logical_device_with_graphics_queue(
+    /// The time then advances by a fixed step per frame from the time given
+    /// with `--time=<seconds>`, so the animations and the simulations do not
+    /// depend on the frame rate, and the time of day is not animated. The
+    /// pseudo random numbers, like those of the particles, are hashes of
+    /// indices and of the time so they are reproducible too.
+    fn is_deterministic() -> bool {
+        std::env::args().any(|arg| arg == "--deterministic") || Self::get_benchmark().is_some()
+    }
+
+    /// Get the number of keys to sort given with `--sort-check=<count>`.
+    fn get_sort_check_count() -> Option<u32> {
+        std::env::args().find_map(|arg| {
+            arg.strip_prefix("--sort-check=")
+                .map(|count| count.parse().expect("Invalid sort check count"))
+        })
+    }
+
+    /// Create the input recording of `--record-input=<path>` and load the
+    /// playback of `--play-input=<path>`.
+    ///
+    /// They are kept when the app is recreated, so they are created once by `main`.
+    fn get_input_recording() -> (Option<InputRecorder>, Option<InputPlayback>) {
+        let mut recorder = None;
+        let mut playback = None;
+        for arg in std::env::args() {
+            if let Some(path) = arg.strip_prefix("--record-input=") {
+                recorder = InputRecorder::create(Path::new(path))
+                    .map_err(|error| log::error!("Failed to create {}: {}.", path, error))
+                    .ok();
+            } else if let Some(path) = arg.strip_prefix("--play-input=") {
+                playback = InputPlayback::load(Path::new(path))
+                    .map_err(|error| log::error!("Failed to load {}: {}.", path, error))
+                    .ok();
+            }
+        }
+        (recorder, playback)
+    }
+
+    /// Get the time in seconds given with `--time=<seconds>`, or 0.
+    fn get_fixed_time() -> f32 {
+        std::env::args()
+            .find_map(|arg| {
+                arg.strip_prefix("--time=")
+                    .map(|time| time.parse().expect("Invalid time"))
+            })
+            .unwrap_or(0.0)
+    }
+
+    /// Get the layout of the rendered images at the end of the render pass.
+    ///
+    /// Swapchain images are presented while the offscreen target is copied to the CPU.
+    fn get_final_layout(headless: bool) -> vk::ImageLayout {
+        if headless {
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+        } else {
+            vk::ImageLayout::PRESENT_SRC_KHR
+        }
+    }
+
+    /// Get the properties of the images rendered at `render_scale` for a
+    /// swapchain of `properties`.
+    fn get_render_properties(
+        properties: SwapchainProperties,
+        render_scale: f32,
+    ) -> SwapchainProperties {
+        SwapchainProperties {
+            extent: dynamic_resolution::scale_extent(properties.extent, render_scale),
+            ..properties
+        }
+    }
+
+    /// Get the properties of the images the scene is rendered to.
+    ///
+    /// When `hdr` is set the scene is rendered to an HDR image which is then
+    /// tonemapped to the swapchain images.
+    fn get_scene_properties(properties: SwapchainProperties, hdr: bool) -> SwapchainProperties {
+        if hdr {
+            SwapchainProperties {
+                format: vk::SurfaceFormatKHR {
+                    format: HDR_FORMAT,
+                    ..properties.format
+                },
+                ..properties
+            }
+        } else {
+            properties
+        }
+    }
+
+    /// Get the layout of the images the scene is rendered to at the end of the main render pass.
+    ///
+    /// The HDR image is read by the exposure and tonemapping passes in the `GENERAL` layout.
+    fn get_scene_final_layout(headless: bool, hdr: bool) -> vk::ImageLayout {
+        if hdr {
+            vk::ImageLayout::GENERAL
+        } else {
+            Self::get_final_layout(headless)
+        }
+    }
+
+    /// Get the output of the last enabled post processing pass and its layout.
+    ///
+    /// Passes run in order: screen-space reflections, light shafts, motion
+    /// blur, FSR, then the tonemapper which samples this output. It is the HDR
+    /// image if no pass is enabled.
+    fn get_post_output(
+        hdr_texture: &Texture,
+        ssr: Option<&ScreenSpaceReflections>,
+        light_shafts: Option<&LightShafts>,
+        motion_blur: Option<&MotionBlur>,
+        fsr: Option<&Fsr>,
+    ) -> (Texture, vk::ImageLayout) {
+        if let Some(fsr) = fsr {
+            return (fsr.output(), vk::ImageLayout::GENERAL);
+        }
+        motion_blur
+            .map(MotionBlur::output)
+            .or_else(|| light_shafts.map(LightShafts::output))
+            .or_else(|| ssr.map(ScreenSpaceReflections::output))
+            .map(|output| (output, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))
+            .unwrap_or((*hdr_texture, vk::ImageLayout::GENERAL))
+    }
+
+    /// Get the views the main render pass resolves to for each swapchain image.
+    fn get_scene_views(
+        swapchain_image_views: &[vk::ImageView],
+        hdr_texture: Option<&Texture>,
+    ) -> Vec<vk::ImageView> {
+        match hdr_texture {
+            Some(hdr) => vec![hdr.view; swapchain_image_views.len()],
+            None => swapchain_image_views.to_vec(),
+        }
+    }
+
+    /// Create the instance, the surface of `window` if any and the debug messenger.
+    ///
+    /// `extensions` are enabled with the extensions needed by the surface and
+    /// the debug markers.
+    fn create_instance_context(
+        window: Option<&Window>,
+        extensions: Vec<CString>,
+    ) -> InstanceContext {
+        let entry = Entry::new().expect("Failed to create entry.");
+        let api_version = Self::get_api_version(&entry);
+        #[cfg(feature = "debug-markers")]
+        let extensions = if Self::are_debug_markers_supported(&entry) {
+            let names = debug_markers::required_instance_extensions();
+            let mut extensions = extensions;
+            extensions.extend(names.iter().map(|name| CString::from(*name)));
+            extensions
+        } else {
+            extensions
+        };
+        let instance = Self::create_instance(&entry, api_version, window, &extensions);
+
+        let surface = window.map(|window| {
+            let surface = Surface::new(&entry, &instance);
+            let surface_khr =
+                unsafe { surface::create_surface(&entry, &instance, window).unwrap() };
+            (surface, surface_khr)
+        });
+
+        let debug_report_callback = setup_debug_messenger(&entry, &instance);
+        InstanceContext::new(entry, instance, api_version, debug_report_callback, surface)
+    }
+
+    /// Get the api version to create the instance with.
+    ///
+    /// Use Vulkan 1.1 if the loader supports it so optional features can be
+    /// queried. Fallback to Vulkan 1.0 otherwise.
+    fn get_api_version(entry: &Entry) -> u32 {
+        match entry.try_enumerate_instance_version() {
+            Ok(Some(version)) if version >= ash::vk_make_version!(1, 1, 0) => {
+                ash::vk_make_version!(1, 1, 0)
+            }
+            _ => ash::vk_make_version!(1, 0, 0),
+        }
+    }
+
+    /// Create the instance.
+    ///
+    /// The surface extensions for `window` are not enabled when there is no window.
+    /// `extra_extensions` are also enabled, like the ones required by OpenXR.
+    fn create_instance(
+        entry: &Entry,
+        api_version: u32,
+        window: Option<&Window>,
+        extra_extensions: &[CString],
+    ) -> Instance {
+        let app_name = CString::new("Vulkan Application").unwrap();
+        let engine_name = CString::new("No Engine").unwrap();
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(app_name.as_c_str())
+            .application_version(ash::vk_make_version!(0, 1, 0))
+            .engine_name(engine_name.as_c_str())
+            .engine_version(ash::vk_make_version!(0, 1, 0))
+            .api_version(api_version)
+            .build();
+
+        let mut extension_names = window
+            .map(surface::required_extension_names)
+            .unwrap_or_default();
+        if is_validation_enabled() {
+            extension_names.push(DebugReport::name().as_ptr());
+        }
+        if is_gpu_assisted_enabled() {
+            extension_names.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+        }
+        extension_names.extend(extra_extensions.iter().map(|name| name.as_ptr()));
+        // List the devices implementing a subset of Vulkan, like MoltenVK
+        let portability = portability::is_enumeration_supported(entry);
+        if portability {
+            extension_names.extend(
+                portability::required_instance_extensions(api_version)
+                    .iter()
+                    .map(|name| name.as_ptr()),
+            );
+        }
+
+        let (_layer_names, layer_names_ptrs) = get_layer_names_and_pointers();
+
+        let mut instance_create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .enabled_extension_names(&extension_names);
+        if portability {
+            instance_create_info = instance_create_info.flags(portability::instance_create_flags());
+        }
+        if is_validation_enabled() {
+            check_validation_layer_support(&entry);
+            instance_create_info = instance_create_info.enabled_layer_names(&layer_names_ptrs);
+        }
+        // Instrument the shaders to validate their accesses on the GPU
+        let enabled_validation_features = [
+            vk::ValidationFeatureEnableEXT::GPU_ASSISTED,
+            vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT,
+        ];
+        let mut validation_features = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(&enabled_validation_features)
+            .build();
+        if is_gpu_assisted_enabled() {
+            instance_create_info = instance_create_info.push_next(&mut validation_features);
+        }
+
+        unsafe { entry.create_instance(&instance_create_info, None).unwrap() }
+    }
+
+    /// Check if the objects should be named and the instance supports it.
+    #[cfg(feature = "debug-markers")]
+    fn are_debug_markers_supported(entry: &Entry) -> bool {
+        if !debug_markers::is_requested() {
+            return false;
+        }
+        let supported = debug_markers::is_supported(entry);
+        if !supported {
+            log::warn!("Debug markers requested but VK_EXT_debug_utils is not supported.");
+        }
+        supported
+    }
+
+    /// Pick the first suitable physical device.
+    ///
+    /// # Requirements
+    /// - At least one queue family with one queue supportting graphics.
+    /// - At least one queue family with one queue supporting presentation to `surface_khr`.
+    /// - Swapchain extension support.
+    ///
+    /// The presentation requirements are ignored when there is no surface.
+    ///
+    /// # Returns
+    ///
+    /// A tuple containing the physical device and the queue families indices.
+    fn pick_physical_device(
+        instance: &Instance,
+        surface: Option<(&Surface, vk::SurfaceKHR)>,
+    ) -> (vk::PhysicalDevice, QueueFamiliesIndices) {
+        let devices = unsafe { instance.enumerate_physical_devices().unwrap() };
+        let device = devices
+            .into_iter()
+            .find(|device| Self::is_device_suitable(instance, surface, *device))
+            .expect("No suitable physical device.");
+
+        let props = unsafe { instance.get_physical_device_properties(device) };
+        log::debug!("Selected physical device: {:?}", unsafe {
+            CStr::from_ptr(props.device_name.as_ptr())
+        });
+
+        let (graphics, present) = Self::find_queue_families(instance, surface, device);
+        let queue_families_indices = QueueFamiliesIndices {
+            graphics_index: graphics.unwrap(),
+            present_index: present.unwrap(),
+        };
+
+        (device, queue_families_indices)
+    }
+
+    fn is_device_suitable(
+        instance: &Instance,
+        surface: Option<(&Surface, vk::SurfaceKHR)>,
+        device: vk::PhysicalDevice,
+    ) -> bool {
+        let (graphics, present) = Self::find_queue_families(instance, surface, device);
+        let extention_support =
+            surface.is_none() || Self::check_device_extension_support(instance, device);
+        let is_swapchain_adequate = match surface {
+            Some((surface, surface_khr)) => {
+                let details = SwapchainSupportDetails::new(device, surface, surface_khr);
+                !details.formats.is_empty() && !details.present_modes.is_empty()
+            }
+            None => true,
+        };
+        let features = unsafe { instance.get_physical_device_features(device) };
+        graphics.is_some()
+            && present.is_some()
+            && extention_support
+            && is_swapchain_adequate
+            && features.sampler_anisotropy == vk::TRUE
+    }
+
+    fn check_device_extension_support(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+        Self::get_required_device_extensions()
+            .iter()
+            .all(|required| is_device_extension_supported(instance, device, required))
+    }
+
+    fn get_required_device_extensions() -> [&'static CStr; 1] {
+        [Swapchain::name()]
+    }
+
+    /// Find a queue family with at least one graphics queue and one with
+    /// at least one presentation queue from `device`.
+    ///
+    /// Without surface, the graphics family is used as the presentation family.
+    ///
+    /// #Returns
+    ///
+    /// Return a tuple (Option<graphics_family_index>, Option<present_family_index>).
+    fn find_queue_families(
+        instance: &Instance,
+        surface: Option<(&Surface, vk::SurfaceKHR)>,
+        device: vk::PhysicalDevice,
+    ) -> (Option<u32>, Option<u32>) {
+        let mut graphics = None;
+        let mut present = None;
+
+        let props = unsafe { instance.get_physical_device_queue_family_properties(device) };
+        for (index, family) in props.iter().filter(|f| f.queue_count > 0).enumerate() {
+            let index = index as u32;
+
+            if family.queue_flags.contains(vk::QueueFlags::GRAPHICS) && graphics.is_none() {
+                graphics = Some(index);
+            }
+
+            let present_support = match surface {
+                Some((surface, surface_khr)) => unsafe {
+                    surface.get_physical_device_surface_support(device, index, surface_khr)
+                },
+                None => false,
+            };
+            if present_support && present.is_none() {
+                present = Some(index);
+            }
+
+            if graphics.is_some() && present.is_some() {
+                break;
+            }
+        }
+
+        if surface.is_none() {
+            present = graphics;
+        }
+
+        (graphics, present)
+    }
+
+    /// Create the logical device to interact with `device`, a graphics queue
+    /// and a presentation queue.
+    ///
+    /// The swapchain extension is only enabled if `with_swapchain` is true.
+    /// `extra_extensions` are also enabled, like the ones required by OpenXR.
+    ///
+    /// # Returns
+    ///
+    /// Return a tuple containing the logical device, the graphics queue and the presentation queue.
+    fn create_logical_device_with_graphics_queue(
         instance: &Instance,
         device: vk::PhysicalDevice,
         queue_families_indices: QueueFamiliesIndices,
+        features: DeviceFeatures,
+        with_swapchain: bool,
+        extra_extensions: &[CString],
     ) -> (Device, vk::Queue, vk::Queue) {
         let graphics_family_index = queue_families_indices.graphics_index;
         let present_family_index = queue_families_indices.present_index;
@@ -440,7 +2649,49 @@ impl VulkanApp {
                 .collect::<Vec<_>>()
         };
 
-        let device_extensions = Self::get_required_device_extensions();
+        let mut device_extensions = if with_swapchain {
+            Self::get_required_device_extensions().to_vec()
+        } else {
+            Vec::new()
+        };
+        if features.bindless {
+            device_extensions.extend_from_slice(&bindless::required_device_extensions());
+        }
+        if features.device_address {
+            device_extensions.extend_from_slice(&device_address::required_device_extensions());
+        }
+        if features.ray_tracing {
+            device_extensions.extend_from_slice(&ray_tracing::required_device_extensions());
+        }
+        if features.ray_query {
+            device_extensions.extend_from_slice(&ray_tracing::ray_query_device_extensions());
+        }
+        if features.mesh_shader {
+            device_extensions.extend_from_slice(&mesh_shader::required_device_extensions());
+        }
+        if features.memory_budget {
+            device_extensions.extend_from_slice(&memory::required_device_extensions());
+        }
+        if features.conditional_rendering {
+            device_extensions.extend_from_slice(&occlusion::required_device_extensions());
+        }
+        if features.synchronization2 {
+            device_extensions.extend_from_slice(&sync2::required_device_extensions());
+        }
+        if features.draw_indirect_count {
+            device_extensions.extend_from_slice(&gpu_driven::required_device_extensions());
+        }
+        if let Some(crash_markers) = features.crash_markers {
+            device_extensions.extend_from_slice(&crash_markers.required_device_extensions());
+        }
+        device_extensions.extend(extra_extensions.iter().map(CString::as_c_str));
+        if portability::is_subset_device(instance, device) {
+            log::debug!("The device only implements a subset of Vulkan.");
+            device_extensions.extend_from_slice(&portability::required_device_extensions());
+        }
+        // The ray tracing needs the descriptor indexing of the bindless textures too
+        device_extensions.sort();
+        device_extensions.dedup();
         let device_extensions_ptrs = device_extensions
             .iter()
             .map(|ext| ext.as_ptr())
@@ -448,7 +2699,26 @@ impl VulkanApp {
 
         let device_features = vk::PhysicalDeviceFeatures::builder()
             .sampler_anisotropy(true)
+            .tessellation_shader(features.tessellation)
+            .geometry_shader(features.geometry_shader)
+            .shader_clip_distance(features.clip_distance)
+            .shader_storage_image_array_dynamic_indexing(features.storage_image_array_indexing)
+            .shader_storage_image_extended_formats(features.storage_image_extended_formats)
+            .sparse_binding(features.sparse_residency)
+            .sparse_residency_image2_d(features.sparse_residency)
+            .fragment_stores_and_atomics(features.sparse_residency)
+            .pipeline_statistics_query(features.pipeline_statistics)
+            .multi_draw_indirect(features.draw_indirect_count)
+            .draw_indirect_first_instance(features.draw_indirect_count)
             .build();
+        let mut indexing_features = bindless::required_features();
+        let mut address_features = device_address::required_features();
+        let mut ray_tracing_features = ray_tracing::required_features();
+        let mut ray_query_features = ray_tracing::ray_query_features();
+        let mut mesh_shader_features = mesh_shader::required_features();
+        let mut multiview_features = stereo::required_features();
+        let mut conditional_rendering_features = occlusion::required_features();
+        let mut synchronization2_features = sync2::required_features();
 
         let (_layer_names, layer_names_ptrs) = get_layer_names_and_pointers();
 
@@ -456,33 +2726,72 @@ impl VulkanApp {
             .queue_create_infos(&queue_create_infos)
             .enabled_extension_names(&device_extensions_ptrs)
             .enabled_features(&device_features);
-        if ENABLE_VALIDATION_LAYERS {
+        if features.bindless {
+            device_create_info_builder =
+                device_create_info_builder.push_next(&mut indexing_features);
+        }
+        if features.device_address {
+            device_create_info_builder =
+                device_create_info_builder.push_next(&mut address_features);
+        }
+        if features.ray_tracing {
+            device_create_info_builder = device_create_info_builder
+                .push_next(&mut ray_tracing_features.acceleration_structure)
+                .push_next(&mut ray_tracing_features.ray_tracing_pipeline)
+                .push_next(&mut ray_tracing_features.buffer_device_address);
+        }
+        if features.ray_query {
+            device_create_info_builder =
+                device_create_info_builder.push_next(&mut ray_query_features);
+        }
+        if features.mesh_shader {
+            device_create_info_builder =
+                device_create_info_builder.push_next(&mut mesh_shader_features);
+        }
+        if features.multiview {
+            device_create_info_builder =
+                device_create_info_builder.push_next(&mut multiview_features);
+        }
+        if features.conditional_rendering {
+            device_create_info_builder =
+                device_create_info_builder.push_next(&mut conditional_rendering_features);
+        }
+        if features.synchronization2 {
+            device_create_info_builder =
+                device_create_info_builder.push_next(&mut synchronization2_features);
+        }
+        if is_validation_enabled() {
             device_create_info_builder =
                 device_create_info_builder.enabled_layer_names(&layer_names_ptrs)
         }
         let device_create_info = device_create_info_builder.build();
 
         // Build device and queues
-        let device = unsafe {
+        let device = Device::new(unsafe {
             instance
                 .create_device(device, &device_create_info, None)
                 .expect("Failed to create logical device.")
-        };
+        });
         let graphics_queue = unsafe { device.get_device_queue(graphics_family_index, 0) };
         let present_queue = unsafe { device.get_device_queue(present_family_index, 0) };
 
         (device, graphics_queue, present_queue)
     }
 
-    /// Create the swapchain with optimal settings possible with
+    /// Create the swapchain of `surface_khr` with optimal settings possible with
     /// `device`.
     ///
+    /// When the graphics and present queue families differ, the images are
+    /// shared by both unless `transfer_ownership` is set, in which case they
+    /// are exclusive and must be transferred with a `PresentTransfer`.
+    ///
     /// # Returns
     ///
     /// A tuple containing the swapchain loader and the actual swapchain.
     fn create_swapchain_and_images(
         vk_context: &VkContext,
         queue_families_indices: QueueFamiliesIndices,
+        (surface_khr, transfer_ownership): (vk::SurfaceKHR, bool),
         dimensions: [u32; 2],
     ) -> (
         Swapchain,
@@ -490,11 +2799,11 @@ impl VulkanApp {
         SwapchainProperties,
         Vec<vk::Image>,
     ) {
-        let details = SwapchainSupportDetails::new(
-            vk_context.physical_device(),
-            vk_context.surface(),
-            vk_context.surface_khr(),
-        );
+        let (surface, _) = vk_context
+            .surface()
+            .expect("A surface is required to create a swapchain");
+        let details =
+            SwapchainSupportDetails::new(vk_context.physical_device(), surface, surface_khr);
         let properties = details.get_ideal_swapchain_properties(dimensions);
 
         let format = properties.format;
@@ -524,7 +2833,7 @@ impl VulkanApp {
 
         let create_info = {
             let mut builder = vk::SwapchainCreateInfoKHR::builder()
-                .surface(vk_context.surface_khr())
+                .surface(surface_khr)
                 .min_image_count(image_count)
                 .image_format(format.format)
                 .image_color_space(format.color_space)
@@ -532,7 +2841,7 @@ impl VulkanApp {
                 .image_array_layers(1)
                 .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
 
-            builder = if graphics != present {
+            builder = if graphics != present && !transfer_ownership {
                 builder
                     .image_sharing_mode(vk::SharingMode::CONCURRENT)
                     .queue_family_indices(&families_indices)
@@ -549,12 +2858,60 @@ impl VulkanApp {
             // .old_swapchain() We don't have an old swapchain but can't pass null
         };
 
-        let swapchain = Swapchain::new(vk_context.instance(), vk_context.device());
+        let swapchain = Swapchain::new(vk_context.instance(), vk_context.device().raw());
         let swapchain_khr = unsafe { swapchain.create_swapchain(&create_info, None).unwrap() };
         let images = unsafe { swapchain.get_swapchain_images(swapchain_khr).unwrap() };
         (swapchain, swapchain_khr, properties, images)
     }
 
+    /// Create the image rendered instead of the swapchain images when running headless.
+    fn create_offscreen_target(
+        vk_context: &VkContext,
+        dimensions: [u32; 2],
+    ) -> Result<OffscreenTarget, AllocationError> {
+        let extent = vk::Extent2D {
+            width: dimensions[0],
+            height: dimensions[1],
+        };
+        log::debug!("Creating offscreen target.\n\tExtent: {:?}", extent);
+
+        let (image, memory) = Self::create_image(
+            vk_context,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            extent,
+            1,
+            vk::SampleCountFlags::TYPE_1,
+            (
+                OFFSCREEN_FORMAT,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            ),
+        )?;
+        let readback_buffer = Self::create_buffer(
+            vk_context,
+            OffscreenTarget::readback_buffer_size(extent),
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        Ok(OffscreenTarget::new(image, memory, extent, readback_buffer))
+    }
+
+    /// Create the transfer of the swapchain `images` to the present queue, if
+    /// it is not of the graphics queue family.
+    fn create_present_transfer(
+        device: &Device,
+        queue_families_indices: QueueFamiliesIndices,
+        images: &[vk::Image],
+    ) -> Option<PresentTransfer> {
+        let graphics = queue_families_indices.graphics_index;
+        let present = queue_families_indices.present_index;
+        if graphics == present {
+            return None;
+        }
+        Some(PresentTransfer::new(device, (graphics, present), images))
+    }
+
     /// Create one image view for each image of the swapchain.
     fn create_swapchain_image_views(
         device: &Device,
@@ -602,7 +2959,8 @@ impl VulkanApp {
         device: &Device,
         swapchain_properties: SwapchainProperties,
         msaa_samples: vk::SampleCountFlags,
-        depth_format: vk::Format,
+        (depth_format, store_depth): (vk::Format, bool),
+        final_layout: vk::ImageLayout,
     ) -> vk::RenderPass {
         let color_attachment_desc = vk::AttachmentDescription::builder()
             .format(swapchain_properties.format.format)
@@ -616,8 +2974,16 @@ impl VulkanApp {
             .format(depth_format)
             .samples(msaa_samples)
             .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(if store_depth {
+                vk::AttachmentStoreOp::STORE
+            } else {
+                vk::AttachmentStoreOp::DONT_CARE
+            })
+            .stencil_load_op(if Self::has_stencil_component(depth_format) {
+                vk::AttachmentLoadOp::CLEAR
+            } else {
+                vk::AttachmentLoadOp::DONT_CARE
+            })
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
             .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
@@ -630,7 +2996,7 @@ impl VulkanApp {
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .final_layout(final_layout)
             .build();
         let attachment_descs = [
             color_attachment_desc,
@@ -655,25 +3021,71 @@ impl VulkanApp {
             .build();
         let resolve_attachment_refs = [resolve_attachment_ref];
 
-        let subpass_desc = vk::SubpassDescription::builder()
+        let depth_input_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .build();
+        let depth_input_attachment_refs = [depth_input_attachment_ref];
+
+        // The scene is drawn in the first subpass. The second one reads its depth
+        // to draw the decals and resolves the color attachment.
+        let scene_subpass_desc = vk::SubpassDescription::builder()
             .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
             .color_attachments(&color_attachment_refs)
-            .resolve_attachments(&resolve_attachment_refs)
             .depth_stencil_attachment(&depth_attachment_ref)
             .build();
-        let subpass_descs = [subpass_desc];
+        let decal_subpass_desc = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .input_attachments(&depth_input_attachment_refs)
+            .resolve_attachments(&resolve_attachment_refs)
+            .build();
+        let subpass_descs = [scene_subpass_desc, decal_subpass_desc];
 
+        // The previous frame might still be reading the HDR image, and the
+        // depth buffer when the occlusion culling reduces it
         let subpass_dep = vk::SubpassDependency::builder()
             .src_subpass(vk::SUBPASS_EXTERNAL)
             .dst_subpass(0)
-            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
             .src_access_mask(vk::AccessFlags::empty())
-            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build();
+        let decal_subpass_dep = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(1)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dst_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
             .dst_access_mask(
-                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::INPUT_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
             )
+            .dependency_flags(vk::DependencyFlags::BY_REGION)
             .build();
-        let subpass_deps = [subpass_dep];
+        let subpass_deps = [subpass_dep, decal_subpass_dep];
 
         let render_pass_info = vk::RenderPassCreateInfo::builder()
             .attachments(&attachment_descs)
@@ -684,71 +3096,79 @@ impl VulkanApp {
         unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
     }
 
-    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
-        let ubo_binding = UniformBufferObject::get_descriptor_set_layout_binding();
-        let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
-            .binding(1)
-            .descriptor_count(1)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-            .build();
-        let bindings = [ubo_binding, sampler_binding];
-
-        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&bindings)
-            .build();
-
-        unsafe {
-            device
-                .create_descriptor_set_layout(&layout_info, None)
-                .unwrap()
+    /// Create the layout of the per-frame descriptor set and the push constant
+    /// ranges shared by the pipelines.
+    ///
+    /// The uniform buffer is visible to the vertex and fragment shaders, the
+    /// latter using it to apply the fog. It is also visible to the task and mesh shaders, the
+    /// tessellation shaders and the geometry shader when the corresponding
+    /// features are enabled. The push constants are the ones of the shaders
+    /// the model can be drawn with.
+    fn create_frame_layout(device: &Device, features: DeviceFeatures) -> FrameLayout {
+        let mut shaders = vec![
+            "shaders/shader.vert.spv",
+            "shaders/material.frag.spv",
+            "shaders/sky.frag.spv",
+            "shaders/gradient.frag.spv",
+            "shaders/grid.frag.spv",
+            "shaders/light_shafts.frag.spv",
+        ];
+        if features.mesh_shader {
+            shaders.extend_from_slice(&["shaders/shader.task.spv", "shaders/shader.mesh.spv"]);
+        }
+        if features.tessellation {
+            shaders.extend_from_slice(&[
+                "shaders/displacement.tesc.spv",
+                "shaders/displacement.tese.spv",
+            ]);
         }
+        if features.geometry_shader {
+            shaders.push("shaders/normals.geom.spv");
+        }
+        // The vertices are pulled with the KHR address of the ray tracing too
+        if features.device_address || features.ray_tracing {
+            shaders.push("shaders/device_address.vert.spv");
+        }
+        if features.bindless {
+            shaders.push("shaders/bindless.frag.spv");
+        }
+        let reflections = shaders.into_iter().map(reflect_shader).collect::<Vec<_>>();
+        let set_layout = create_set_layout(device, &reflections, FRAME_SET);
+        FrameLayout::new(device, set_layout, push_constant_ranges(&reflections))
     }
 
-    /// Create a descriptor pool to allocate the descriptor sets.
-    fn create_descriptor_pool(device: &Device, size: u32) -> vk::DescriptorPool {
+    /// Create the allocator of the descriptor sets, whose first pool holds `size` sets.
+    fn create_descriptor_allocator(size: u32) -> DescriptorAllocator {
         let ubo_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: size,
+            descriptor_count: 1,
         };
         let sampler_pool_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: size,
+            descriptor_count: 1,
         };
-
-        let pool_sizes = [ubo_pool_size, sampler_pool_size];
-
-        let pool_info = vk::DescriptorPoolCreateInfo::builder()
-            .pool_sizes(&pool_sizes)
-            .max_sets(size)
-            .build();
-
-        unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() }
+        DescriptorAllocator::new(&[ubo_pool_size, sampler_pool_size], size)
     }
 
     /// Create one descriptor set for each uniform buffer.
     fn create_descriptor_sets(
         device: &Device,
-        pool: vk::DescriptorPool,
+        allocator: &mut DescriptorAllocator,
         layout: vk::DescriptorSetLayout,
-        uniform_buffers: &[vk::Buffer],
+        uniform_buffers: &[Buffer],
         texture: Texture,
     ) -> Vec<vk::DescriptorSet> {
         let layouts = (0..uniform_buffers.len())
             .map(|_| layout)
             .collect::<Vec<_>>();
-        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
-            .descriptor_pool(pool)
-            .set_layouts(&layouts)
-            .build();
-        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() };
+        let descriptor_sets = allocator.allocate(device, &layouts);
 
         descriptor_sets
             .iter()
             .zip(uniform_buffers.iter())
             .for_each(|(set, buffer)| {
                 let buffer_info = vk::DescriptorBufferInfo::builder()
-                    .buffer(*buffer)
+                    .buffer(buffer.buffer)
                     .offset(0)
                     .range(size_of::<UniformBufferObject>() as vk::DeviceSize)
                     .build();
@@ -784,649 +3204,5455 @@ impl VulkanApp {
         descriptor_sets
     }
 
-    fn create_pipeline(
-        device: &Device,
-        swapchain_properties: SwapchainProperties,
-        msaa_samples: vk::SampleCountFlags,
-        render_pass: vk::RenderPass,
-        descriptor_set_layout: vk::DescriptorSetLayout,
-    ) -> (vk::Pipeline, vk::PipelineLayout) {
-        let vertex_source = Self::read_shader_from_file("shaders/shader.vert.spv");
-        let fragment_source = Self::read_shader_from_file("shaders/shader.frag.spv");
-
-        let vertex_shader_module = Self::create_shader_module(device, &vertex_source);
-        let fragment_shader_module = Self::create_shader_module(device, &fragment_source);
-
-        let entry_point_name = CString::new("main").unwrap();
-        let vertex_shader_state_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .module(vertex_shader_module)
-            .name(&entry_point_name)
-            .build();
-        let fragment_shader_state_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(fragment_shader_module)
-            .name(&entry_point_name)
-            .build();
-        let shader_states_infos = [vertex_shader_state_info, fragment_shader_state_info];
-
-        let vertex_binding_descs = [Vertex::get_binding_description()];
-        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
-        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
-            .vertex_binding_descriptions(&vertex_binding_descs)
-            .vertex_attribute_descriptions(&vertex_attribute_descs)
-            .build();
-
-        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-            .primitive_restart_enable(false)
-            .build();
+    /// Create the global texture array and the material buffer it indexes.
+    fn create_bindless_textures(
+        vk_context: &VkContext,
+    ) -> Result<BindlessTextures, AllocationError> {
+        let material_buffer = Self::create_buffer(
+            vk_context,
+            MaterialData::buffer_size(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        Ok(BindlessTextures::new(vk_context.device(), material_buffer))
+    }
 
-        let viewport = vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: swapchain_properties.extent.width as _,
-            height: swapchain_properties.extent.height as _,
-            min_depth: 0.0,
-            max_depth: 1.0,
+    /// Create the material instances and the one of the model.
+    ///
+    /// The model uses the preset given with `--material=<default|tinted|glowing|glossy>`.
+    /// The base material enables the `ALPHA_TEST` keyword with `--alpha-test`
+    /// and the `feature_keywords` of the optional features, like `LIGHTMAP`. Its
+    /// faces are culled as given with `--cull=<back|front|none>`, or drawn
+    /// both with `--double-sided`, and its front faces are wound clockwise
+    /// with `--clockwise`.
+    fn create_material_instances(
+        vk_context: &VkContext,
+        feature_keywords: &[&'static str],
+    ) -> Result<(MaterialInstances, MaterialInstance), AllocationError> {
+        let min_offset_alignment = unsafe {
+            vk_context
+                .instance()
+                .get_physical_device_properties(vk_context.physical_device())
+                .limits
+                .min_uniform_buffer_offset_alignment
         };
-        let viewports = [viewport];
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain_properties.extent,
+        let uniform_buffer = Self::create_buffer(
+            vk_context,
+            material::uniform_buffer_size(min_offset_alignment),
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mut keywords = if std::env::args().any(|arg| arg == "--alpha-test") {
+            vec![ALPHA_TEST]
+        } else {
+            Vec::new()
         };
-        let scissors = [scissor];
-        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(&viewports)
-            .scissors(&scissors)
-            .build();
-
-        let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
-            .depth_clamp_enable(false)
-            .rasterizer_discard_enable(false)
-            .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0)
-            .cull_mode(vk::CullModeFlags::BACK)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-            .depth_bias_enable(false)
-            .depth_bias_constant_factor(0.0)
-            .depth_bias_clamp(0.0)
-            .depth_bias_slope_factor(0.0)
-            .build();
-
-        let multisampling_info = vk::PipelineMultisampleStateCreateInfo::builder()
-            .sample_shading_enable(false)
-            .rasterization_samples(msaa_samples)
-            .min_sample_shading(1.0)
-            // .sample_mask() // null
-            .alpha_to_coverage_enable(false)
-            .alpha_to_one_enable(false)
-            .build();
-
-        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_compare_op(vk::CompareOp::LESS)
-            .depth_bounds_test_enable(false)
-            .min_depth_bounds(0.0)
-            .max_depth_bounds(1.0)
-            .stencil_test_enable(false)
-            .front(Default::default())
-            .back(Default::default())
-            .build();
-
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(vk::ColorComponentFlags::all())
-            .blend_enable(false)
-            .src_color_blend_factor(vk::BlendFactor::ONE)
-            .dst_color_blend_factor(vk::BlendFactor::ZERO)
-            .color_blend_op(vk::BlendOp::ADD)
-            .src_alpha_blend_factor(vk::BlendFactor::ONE)
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-            .alpha_blend_op(vk::BlendOp::ADD)
-            .build();
-        let color_blend_attachments = [color_blend_attachment];
-
-        let color_blending_info = vk::PipelineColorBlendStateCreateInfo::builder()
-            .logic_op_enable(false)
-            .logic_op(vk::LogicOp::COPY)
-            .attachments(&color_blend_attachments)
-            .blend_constants([0.0, 0.0, 0.0, 0.0])
-            .build();
-
-        let layout = {
-            let layouts = [descriptor_set_layout];
-            let layout_info = vk::PipelineLayoutCreateInfo::builder()
-                .set_layouts(&layouts)
-                // .push_constant_ranges
-                .build();
+        keywords.extend_from_slice(feature_keywords);
+        let mut material_instances = MaterialInstances::new(
+            vk_context.device(),
+            keywords,
+            Self::get_face_culling(),
+            uniform_buffer,
+            min_offset_alignment,
+        );
 
-            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
-        };
+        let parameters = std::env::args()
+            .find_map(|arg| {
+                arg.strip_prefix("--material=").and_then(|name| {
+                    let parameters = MaterialParameters::from_name(name);
+                    if parameters.is_none() {
+                        log::warn!("Unknown material preset {}.", name);
+                    }
+                    parameters
+                })
+            })
+            .unwrap_or_default();
+        let model_material = material_instances.add_instance(vk_context.device(), parameters);
 
-        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
-            .stages(&shader_states_infos)
-            .vertex_input_state(&vertex_input_info)
-            .input_assembly_state(&input_assembly_info)
-            .viewport_state(&viewport_info)
-            .rasterization_state(&rasterizer_info)
-            .multisample_state(&multisampling_info)
-            .depth_stencil_state(&depth_stencil_info)
-            .color_blend_state(&color_blending_info)
-            // .dynamic_state() null since don't have any dynamic states
-            .layout(layout)
-            .render_pass(render_pass)
-            .subpass(0)
-            // .base_pipeline_handle() null since it is not derived from another
-            // .base_pipeline_index(-1) same
-            .build();
-        let pipeline_infos = [pipeline_info];
+        Ok((material_instances, model_material))
+    }
 
-        let pipeline = unsafe {
-            device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_infos, None)
-                .unwrap()[0]
-        };
+    /// Split the model into meshlets and upload them for the mesh shaders.
+    ///
+    /// `vertex_buffer` must have been created with the `STORAGE_BUFFER` usage.
+    fn create_mesh_shading(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        vertex_buffer: Buffer,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Result<MeshShading, AllocationError> {
+        let positions = vertices.iter().map(|v| v.pos).collect::<Vec<_>>();
+        let meshlets = build_meshlets(&positions, indices);
 
-        unsafe {
-            device.destroy_shader_module(vertex_shader_module, None);
-            device.destroy_shader_module(fragment_shader_module, None);
+        let meshlet_buffers = MeshletBuffers {
+            meshlets: Self::create_device_local_buffer_with_data::<f32, _>(
+                vk_context,
+                command_pool,
+                transfer_queue,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                &meshlets.meshlets,
+            )?,
+            vertex_indices: Self::create_device_local_buffer_with_data::<u32, _>(
+                vk_context,
+                command_pool,
+                transfer_queue,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                &meshlets.vertex_indices,
+            )?,
+            primitive_indices: Self::create_device_local_buffer_with_data::<u8, _>(
+                vk_context,
+                command_pool,
+                transfer_queue,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                &meshlets.primitive_indices,
+            )?,
+            meshlet_count: meshlets.meshlets.len() as _,
         };
 
-        (pipeline, layout)
+        Ok(MeshShading::new(
+            vk_context.instance(),
+            vk_context.physical_device(),
+            vk_context.device(),
+            vertex_buffer,
+            meshlet_buffers,
+        ))
     }
 
-    fn read_shader_from_file<P: AsRef<std::path::Path>>(path: P) -> Vec<u32> {
-        log::debug!("Loading shader file {}", path.as_ref().to_str().unwrap());
-        let mut cursor = fs::load(path);
-        ash::util::read_spv(&mut cursor).unwrap()
-    }
+    /// Create the tessellated plane and its height map.
+    fn create_displaced_plane(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+    ) -> Result<DisplacedPlane, AllocationError> {
+        let (vertices, indices) = build_plane_patches();
+        let vertex_buffer = Self::create_device_local_buffer_with_data::<u32, _>(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &vertices,
+        )?;
+        let index_buffer = Self::create_device_local_buffer_with_data::<u16, _>(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            &indices,
+        )?;
 
-    fn create_shader_module(device: &Device, code: &[u32]) -> vk::ShaderModule {
-        let create_info = vk::ShaderModuleCreateInfo::builder().code(code).build();
-        unsafe { device.create_shader_module(&create_info, None).unwrap() }
+        let height_map = Self::create_texture_from_pixels(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::Extent2D {
+                width: HEIGHT_MAP_SIZE,
+                height: HEIGHT_MAP_SIZE,
+            },
+            &generate_height_map(),
+            ColorSpace::Linear,
+        )?;
+
+        Ok(DisplacedPlane::new(
+            vk_context.device(),
+            vertex_buffer,
+            index_buffer,
+            indices.len() as _,
+            height_map,
+        ))
     }
 
-    fn create_framebuffers(
+    /// Create the pipeline drawing `plane` and give it to the plane.
+    fn create_displacement_pipeline(
         device: &Device,
-        image_views: &[vk::ImageView],
-        color_texture: Texture,
-        depth_texture: Texture,
-        render_pass: vk::RenderPass,
         swapchain_properties: SwapchainProperties,
-    ) -> Vec<vk::Framebuffer> {
-        image_views
-            .iter()
-            .map(|view| [color_texture.view, depth_texture.view, *view])
-            .map(|attachments| {
-                let framebuffer_info = vk::FramebufferCreateInfo::builder()
-                    .render_pass(render_pass)
-                    .attachments(&attachments)
-                    .width(swapchain_properties.extent.width)
-                    .height(swapchain_properties.extent.height)
-                    .layers(1)
-                    .build();
-                unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
-            })
-            .collect::<Vec<_>>()
-    }
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        plane: &mut DisplacedPlane,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[plane.layout()]);
 
-    fn create_command_pool(
-        device: &Device,
-        queue_families_indices: QueueFamiliesIndices,
-        create_flags: vk::CommandPoolCreateFlags,
-    ) -> vk::CommandPool {
-        let command_pool_info = vk::CommandPoolCreateInfo::builder()
-            .queue_family_index(queue_families_indices.graphics_index)
-            .flags(create_flags)
-            .build();
+        let shaders = [
+            (
+                vk::ShaderStageFlags::VERTEX,
+                "shaders/displacement.vert.spv",
+            ),
+            (
+                vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                "shaders/displacement.tesc.spv",
+            ),
+            (
+                vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                "shaders/displacement.tese.spv",
+            ),
+            (
+                vk::ShaderStageFlags::FRAGMENT,
+                "shaders/displacement.frag.spv",
+            ),
+        ];
+        let vertex_binding_descs = [PlaneVertex::get_binding_description()];
+        let vertex_attribute_descs = PlaneVertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::PATCH_LIST,
+                patch_control_points: Some(PATCH_CONTROL_POINTS),
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
 
-        unsafe {
-            device
-                .create_command_pool(&command_pool_info, None)
-                .unwrap()
-        }
+        plane.set_pipeline(pipeline, layout);
     }
 
-    fn create_color_texture(
+    /// Create the terrain, its textures and its indirect draw buffers.
+    ///
+    /// The height map is procedurally generated but any grayscale image can be used.
+    fn create_terrain(
         vk_context: &VkContext,
         command_pool: vk::CommandPool,
-        transition_queue: vk::Queue,
-        swapchain_properties: SwapchainProperties,
-        msaa_samples: vk::SampleCountFlags,
-    ) -> Texture {
-        let format = swapchain_properties.format.format;
-        let (image, memory) = Self::create_image(
+        transfer_queue: vk::Queue,
+        image_count: usize,
+    ) -> Result<Terrain, AllocationError> {
+        let height_map_pixels = generate_height_map();
+        let height_map = image::GrayImage::from_fn(HEIGHT_MAP_SIZE, HEIGHT_MAP_SIZE, |x, y| {
+            image::Luma([height_map_pixels[((y * HEIGHT_MAP_SIZE + x) * 4) as usize]])
+        });
+        let mesh = TerrainMesh::from_height_map(&height_map);
+
+        let vertex_buffer = Self::create_device_local_buffer_with_data::<u32, _>(
             vk_context,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            swapchain_properties.extent,
-            1,
-            msaa_samples,
-            format,
-            vk::ImageTiling::OPTIMAL,
-            vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-        );
-
-        Self::transition_image_layout(
-            vk_context.device(),
             command_pool,
-            transition_queue,
-            image,
-            1,
-            format,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        );
+            transfer_queue,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &mesh.vertices,
+        )?;
+        let index_buffer = Self::create_device_local_buffer_with_data::<u32, _>(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            &mesh.indices,
+        )?;
 
-        let view = Self::create_image_view(
+        let create_texture = |size, pixels: &[u8], color_space| {
+            Self::create_texture_from_pixels(
+                vk_context,
+                command_pool,
+                transfer_queue,
+                vk::Extent2D {
+                    width: size,
+                    height: size,
+                },
+                pixels,
+                color_space,
+            )
+        };
+        let splat_map = create_texture(
+            mesh.splat_map_size(),
+            &mesh.generate_splat_map(),
+            ColorSpace::Linear,
+        )?;
+        let mut layers = [Texture::new(
+            vk::Image::null(),
+            vk::DeviceMemory::null(),
+            vk::ImageView::null(),
+            None,
+        ); LAYER_COUNT];
+        for (i, layer) in layers.iter_mut().enumerate() {
+            *layer = create_texture(
+                LAYER_TEXTURE_SIZE,
+                &generate_layer_texture(i),
+                ColorSpace::Srgb,
+            )?;
+        }
+
+        let indirect_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    Terrain::indirect_buffer_size(&mesh),
+                    // The occlusion culling writes the draws of the occluded chunks
+                    vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Terrain::new(
             vk_context.device(),
-            image,
-            1,
-            format,
-            vk::ImageAspectFlags::COLOR,
+            &mesh,
+            vertex_buffer,
+            index_buffer,
+            TerrainTextures { splat_map, layers },
+            indirect_buffers,
+        ))
+    }
+
+    /// Create the streamer of the ground chunks, its geometry pool and its
+    /// indirect draw buffers.
+    ///
+    /// The chunks are stored in the temporary directory, where they are
+    /// generated the first time they are loaded.
+    fn create_mesh_streamer(
+        vk_context: &VkContext,
+        image_count: usize,
+    ) -> Result<MeshStreamer, AllocationError> {
+        let host_buffer = |size, usage| {
+            Self::create_buffer(
+                vk_context,
+                size,
+                usage,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+        };
+        let blocks = MeshStreamer::pool_blocks();
+        let vertex_size = size_of::<StreamedVertex>() as u32;
+        let vertex_buffer = host_buffer(
+            GeometryPool::vertex_buffer_size(vertex_size, blocks),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let index_buffer = host_buffer(
+            GeometryPool::index_buffer_size(blocks),
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        )?;
+        let pool = GeometryPool::new(
+            (vertex_buffer, index_buffer),
+            vertex_size,
+            blocks,
+            image_count,
         );
+        let indirect_buffers = (0..image_count)
+            .map(|_| {
+                host_buffer(
+                    MeshStreamer::indirect_buffer_size(),
+                    vk::BufferUsageFlags::INDIRECT_BUFFER,
+                )
+            })
+            .collect::<Result<_, _>>()?;
 
-        Texture::new(image, memory, view, None)
+        let directory = std::env::temp_dir().join("vulkan-tutorial-ash-chunks");
+        Ok(MeshStreamer::new(directory, pool, indirect_buffers))
     }
 
-    /// Create the depth buffer texture (image, memory and view).
-    ///
-    /// This function also transitions the image to be ready to be used
-    /// as a depth/stencil attachement.
-    fn create_depth_texture(
+    /// Create the renderer of the objects spawned at runtime, its geometry pool
+    /// and its buffers, and the demo spawning cubes with the material presets
+    /// added to `material_instances`.
+    fn create_objects(
         vk_context: &VkContext,
-        command_pool: vk::CommandPool,
-        transition_queue: vk::Queue,
-        format: vk::Format,
-        extent: vk::Extent2D,
-        msaa_samples: vk::SampleCountFlags,
-    ) -> Texture {
-        let (image, mem) = Self::create_image(
-            vk_context,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            extent,
-            1,
-            msaa_samples,
-            format,
-            vk::ImageTiling::OPTIMAL,
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        image_count: usize,
+        material_instances: &mut MaterialInstances,
+    ) -> Result<(ObjectRenderer, ObjectDemo), AllocationError> {
+        let host_buffer = |size, usage| {
+            Self::create_buffer(
+                vk_context,
+                size,
+                usage,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+        };
+        let min_offset_alignment = unsafe {
+            vk_context
+                .instance()
+                .get_physical_device_properties(vk_context.physical_device())
+                .limits
+                .min_uniform_buffer_offset_alignment
+        };
+        let blocks = ObjectRenderer::pool_blocks();
+        let vertex_size = size_of::<Vertex>() as u32;
+        let vertex_buffer = host_buffer(
+            GeometryPool::vertex_buffer_size(vertex_size, blocks),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let index_buffer = host_buffer(
+            GeometryPool::index_buffer_size(blocks),
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        )?;
+        let pool = GeometryPool::new(
+            (vertex_buffer, index_buffer),
+            vertex_size,
+            blocks,
+            image_count,
         );
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                host_buffer(
+                    ObjectRenderer::uniform_buffer_size(min_offset_alignment),
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        let indirect_buffers = (0..image_count)
+            .map(|_| {
+                host_buffer(
+                    ObjectRenderer::indirect_buffer_size(),
+                    vk::BufferUsageFlags::INDIRECT_BUFFER,
+                )
+            })
+            .collect::<Result<_, _>>()?;
 
         let device = vk_context.device();
-        Self::transition_image_layout(
+        let mut objects = ObjectRenderer::new(
             device,
-            command_pool,
-            transition_queue,
-            image,
-            1,
-            format,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            pool,
+            (uniform_buffers, indirect_buffers),
+            min_offset_alignment,
         );
 
-        let view = Self::create_image_view(device, image, 1, format, vk::ImageAspectFlags::DEPTH);
+        let (vertices, indices) = create_cube();
+        let collision_mesh = math::CollisionMesh::new(
+            vertices
+                .iter()
+                .map(|vertex| Point3::from(vertex.pos))
+                .collect(),
+            indices.clone(),
+        );
+        let cube = objects
+            .add_mesh(device, &vertices, &indices, collision_mesh)
+            .unwrap();
+        let materials = ["default", "tinted", "glowing", "glossy"]
+            .iter()
+            .map(|name| {
+                let parameters = MaterialParameters::from_name(name).unwrap();
+                material_instances.add_instance(device, parameters)
+            })
+            .collect();
 
-        Texture::new(image, mem, view, None)
+        Ok((objects, ObjectDemo::new(cube, materials)))
     }
 
-    fn find_depth_format(vk_context: &VkContext) -> vk::Format {
-        let candidates = vec![
-            vk::Format::D32_SFLOAT,
-            vk::Format::D32_SFLOAT_S8_UINT,
-            vk::Format::D24_UNORM_S8_UINT,
+    /// Create a pipeline drawing meshes with the object shaders, with the
+    /// uniforms of the objects in a set of layout `set_layout`.
+    fn create_object_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let layout = frame_layout.create_pipeline_layout(device, &[set_layout]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/object.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/object.frag.spv"),
         ];
-        vk_context
-            .find_supported_format(
-                &candidates,
-                vk::ImageTiling::OPTIMAL,
-                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
-            )
-            .expect("Failed to find a supported depth format")
-    }
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::BACK,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
 
-    fn has_stencil_component(format: vk::Format) -> bool {
-        format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
+        (pipeline, layout)
     }
 
-    fn create_texture_image(
+    /// Create the static objects scattered around the model, merged by
+    /// material in a few draws if `batching` is set, or drawn one by one.
+    ///
+    /// Their materials are added to `material_instances`.
+    fn create_static_batches(
         vk_context: &VkContext,
         command_pool: vk::CommandPool,
-        copy_queue: vk::Queue,
-    ) -> Texture {
-        let cursor = fs::load("images/chalet.jpg");
-        let image = image::load(cursor, image::ImageFormat::JPEG)
-            .unwrap()
-            .flipv();
-        let image_as_rgb = image.to_rgba();
-        let width = (&image_as_rgb).width();
-        let height = (&image_as_rgb).height();
-        let max_mip_levels = ((width.min(height) as f32).log2().floor() + 1.0) as u32;
-        let extent = vk::Extent2D { width, height };
-        let pixels = image_as_rgb.into_raw();
-        let image_size = (pixels.len() * size_of::<u8>()) as vk::DeviceSize;
+        transfer_queue: vk::Queue,
+        material_instances: &mut MaterialInstances,
+        batching: bool,
+    ) -> Result<StaticBatches, AllocationError> {
         let device = vk_context.device();
+        let materials = ["default", "tinted", "glowing", "glossy"]
+            .iter()
+            .map(|name| {
+                let parameters = MaterialParameters::from_name(name).unwrap();
+                material_instances.add_instance(device, parameters)
+            })
+            .collect::<Vec<_>>();
+        let (vertices, indices) = create_cube();
+        let meshes = [StaticMesh { vertices, indices }];
+        let objects = get_static_objects(&materials);
+
+        let geometry = if batching {
+            batching::batch(&meshes, &objects)
+        } else {
+            batching::unbatched(&meshes, &objects)
+        };
+        log::debug!(
+            "{} static objects drawn with {} draws of {} vertices and {} indices.",
+            objects.len(),
+            geometry.draws.len(),
+            geometry.vertices.len(),
+            geometry.indices.len()
+        );
 
-        let (buffer, memory, mem_size) = Self::create_buffer(
+        let vertex_buffer = Self::create_device_local_buffer_with_data::<u32, _>(
             vk_context,
-            image_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
+            command_pool,
+            transfer_queue,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &geometry.vertices,
+        )?;
+        let index_buffer =
+            Self::create_index_buffer(vk_context, command_pool, transfer_queue, &geometry.indices)?;
+        let min_offset_alignment = unsafe {
+            vk_context
+                .instance()
+                .get_physical_device_properties(vk_context.physical_device())
+                .limits
+                .min_uniform_buffer_offset_alignment
+        };
+        let uniform_buffer = Self::create_buffer(
+            vk_context,
+            StaticBatches::uniform_buffer_size(geometry.draws.len(), min_offset_alignment),
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
+        )?;
 
-        unsafe {
-            let ptr = device
-                .map_memory(memory, 0, image_size, vk::MemoryMapFlags::empty())
-                .unwrap();
-            let mut align = ash::util::Align::new(ptr, align_of::<u8>() as _, mem_size);
-            align.copy_from_slice(&pixels);
-            device.unmap_memory(memory);
+        Ok(StaticBatches::new(
+            device,
+            (vertex_buffer, index_buffer),
+            uniform_buffer,
+            geometry.draws,
+            (material_instances, min_offset_alignment),
+        ))
+    }
+
+    /// Create the spheres of the GPU-driven demo, on a large grid around the
+    /// model, textured with the bindless material `material_index`.
+    ///
+    /// The sphere has three levels of detail, of fewer rings and segments.
+    fn create_gpu_driven(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        image_count: usize,
+        material_index: u32,
+    ) -> Result<GpuDriven, AllocationError> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut lods = [GpuLod::default(); GPU_DRIVEN_LOD_COUNT];
+        for (lod, &(rings, segments)) in lods.iter_mut().zip(&[(24, 48), (12, 24), (6, 12)]) {
+            let (lod_vertices, lod_indices) = create_sphere(rings, segments);
+            *lod = GpuLod {
+                first_index: indices.len() as _,
+                index_count: lod_indices.len() as _,
+                vertex_offset: vertices.len() as _,
+            };
+            vertices.extend(lod_vertices);
+            indices.extend(lod_indices);
         }
+        let instances = get_gpu_driven_instances(material_index);
+        log::debug!(
+            "{} GPU-driven instances with {} levels of detail.",
+            instances.len(),
+            lods.len()
+        );
 
-        let (image, image_memory) = Self::create_image(
+        let vertex_buffer = Self::create_device_local_buffer_with_data::<u32, _>(
             vk_context,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            extent,
-            max_mip_levels,
-            vk::SampleCountFlags::TYPE_1,
-            vk::Format::R8G8B8A8_UNORM,
-            vk::ImageTiling::OPTIMAL,
-            vk::ImageUsageFlags::TRANSFER_SRC
-                | vk::ImageUsageFlags::TRANSFER_DST
-                | vk::ImageUsageFlags::SAMPLED,
+            command_pool,
+            transfer_queue,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &vertices,
+        )?;
+        let index_buffer =
+            Self::create_index_buffer(vk_context, command_pool, transfer_queue, &indices)?;
+        let instance_buffer = Self::create_buffer(
+            vk_context,
+            GpuDriven::instance_buffer_size(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<GpuDrivenUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        let draw_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    GpuDriven::draw_buffer_size(),
+                    vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::INDIRECT_BUFFER
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut gpu_driven = GpuDriven::new(
+            (vk_context.instance(), vk_context.device()),
+            (vertex_buffer, index_buffer),
+            (lods, GPU_DRIVEN_LOD_DISTANCES),
+            (instance_buffer, &instances),
+            (uniform_buffers, draw_buffers),
         );
+        Self::create_gpu_driven_compute_pipelines(vk_context.device(), &mut gpu_driven);
+        Ok(gpu_driven)
+    }
 
-        // Transition the image layout and copy the buffer into the image
-        // and transition the layout again to be readable from fragment shader.
-        {
-            Self::transition_image_layout(
-                device,
-                command_pool,
-                copy_queue,
-                image,
-                max_mip_levels,
-                vk::Format::R8G8B8A8_UNORM,
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
+    /// Create the compute pipelines culling the instances of `gpu_driven` and
+    /// writing its draws, and give them to it.
+    ///
+    /// They do not depend on the swapchain so they are only created once.
+    fn create_gpu_driven_compute_pipelines(device: &Device, gpu_driven: &mut GpuDriven) {
+        let layout = {
+            let layouts = [gpu_driven.layout()];
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .build();
+
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        };
+        let cull_pipeline =
+            Self::create_compute_pipeline(device, "shaders/gpu_driven_cull.comp.spv", layout, &[]);
+        let draws_pipeline =
+            Self::create_compute_pipeline(device, "shaders/gpu_driven_draws.comp.spv", layout, &[]);
+        gpu_driven.set_compute_pipelines((cull_pipeline, draws_pipeline), layout);
+    }
 
-            Self::copy_buffer_to_image(device, command_pool, copy_queue, buffer, image, extent);
+    /// Create the pipeline drawing the instances of `gpu_driven` and give it to it.
+    ///
+    /// The textures are read from the set of `bindless_textures`.
+    fn create_gpu_driven_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        (frame_layout, bindless_textures): (&FrameLayout, &BindlessTextures),
+        gpu_driven: &mut GpuDriven,
+    ) {
+        let layout = frame_layout
+            .create_pipeline_layout(device, &[gpu_driven.layout(), bindless_textures.layout()]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/gpu_driven.vert.spv"),
+            (
+                vk::ShaderStageFlags::FRAGMENT,
+                "shaders/gpu_driven.frag.spv",
+            ),
+        ];
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::BACK,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
 
-            Self::generate_mipmaps(
-                vk_context,
-                command_pool,
-                copy_queue,
-                image,
-                extent,
-                vk::Format::R8G8B8A8_UNORM,
-                max_mip_levels,
-            );
-        }
+        gpu_driven.set_pipeline(pipeline, layout);
+    }
 
-        unsafe {
-            device.destroy_buffer(buffer, None);
-            device.free_memory(memory, None);
-        }
+    /// Create the sparse image of the virtual texture, its page cache and its
+    /// buffers, then bind and upload its mip tail.
+    ///
+    /// Get `None` if the sparse layout of the image is not supported.
+    fn create_virtual_texture(
+        vk_context: &VkContext,
+        queue_family_index: u32,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        image_count: usize,
+    ) -> Result<Option<VirtualTexture>, AllocationError> {
+        let device = vk_context.device();
+        let mip_levels = virtual_texture::mip_levels();
+        let image = {
+            let image_info = vk::ImageCreateInfo::builder()
+                .flags(
+                    vk::ImageCreateFlags::SPARSE_BINDING | vk::ImageCreateFlags::SPARSE_RESIDENCY,
+                )
+                .image_type(vk::ImageType::TYPE_2D)
+                .extent(vk::Extent3D {
+                    width: VIRTUAL_TEXTURE_SIZE,
+                    height: VIRTUAL_TEXTURE_SIZE,
+                    depth: 1,
+                })
+                .mip_levels(mip_levels)
+                .array_layers(1)
+                .format(VIRTUAL_TEXTURE_FORMAT)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .usage(virtual_texture::usage())
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .build();
+            unsafe { device.create_image(&image_info, None).unwrap() }
+        };
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+        let sparse = match SparseLayout::query(device, image, requirements) {
+            Some(sparse) => sparse,
+            None => {
+                log::warn!("Virtual texturing requested but the sparse layout of the image is not supported.");
+                unsafe { device.destroy_image(image, None) };
+                return Ok(None);
+            }
+        };
+        log::debug!("Virtual texture layout: {:?}", sparse);
+        lifetime::track(device, ObjectKind::Image, image, "virtual texture");
 
-        let image_view = Self::create_image_view(
+        // The pages and the mip tail are bound to memory allocated separately from the image
+        let memory_type = Self::find_memory_type(
+            requirements,
+            vk_context.get_mem_properties(),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        let allocate = |size| {
+            let requirements = vk::MemoryRequirements {
+                size,
+                ..requirements
+            };
+            memory::allocate(vk_context, requirements, memory_type)
+        };
+        let page_memory = allocate(sparse.page_bytes * PAGE_CACHE_SIZE as vk::DeviceSize)?;
+        let tail_memory = allocate(sparse.tail_size)?;
+
+        let view = Self::create_image_view(
             device,
             image,
-            max_mip_levels,
-            vk::Format::R8G8B8A8_UNORM,
+            mip_levels,
+            VIRTUAL_TEXTURE_FORMAT,
             vk::ImageAspectFlags::COLOR,
         );
-
+        // The level is chosen by the shader among the resident ones
         let sampler = {
             let sampler_info = vk::SamplerCreateInfo::builder()
                 .mag_filter(vk::Filter::LINEAR)
                 .min_filter(vk::Filter::LINEAR)
-                .address_mode_u(vk::SamplerAddressMode::REPEAT)
-                .address_mode_v(vk::SamplerAddressMode::REPEAT)
-                .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                .anisotropy_enable(true)
-                .max_anisotropy(16.0)
-                .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-                .unnormalized_coordinates(false)
-                .compare_enable(false)
-                .compare_op(vk::CompareOp::ALWAYS)
-                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-                .mip_lod_bias(0.0)
-                .min_lod(0.0)
-                .max_lod(max_mip_levels as _)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(mip_levels as _)
                 .build();
-
             unsafe { device.create_sampler(&sampler_info, None).unwrap() }
         };
 
-        Texture::new(image, image_memory, image_view, Some(sampler))
-    }
+        let host_buffers = |size, usage| {
+            (0..image_count)
+                .map(|_| {
+                    Self::create_buffer(
+                        vk_context,
+                        size,
+                        usage,
+                        vk::MemoryPropertyFlags::HOST_VISIBLE
+                            | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let page_tables = host_buffers(
+            sparse.page_table_size(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        )?;
+        let staging_buffers =
+            host_buffers(sparse.staging_size(), vk::BufferUsageFlags::TRANSFER_SRC)?;
+        let feedback_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    sparse.feedback_size(),
+                    vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::TRANSFER_SRC
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        let feedback =
+            Self::create_readback_buffer(vk_context, sparse.page_count() as _, image_count)?;
 
-    fn create_image(
-        vk_context: &VkContext,
-        mem_properties: vk::MemoryPropertyFlags,
-        extent: vk::Extent2D,
-        mip_levels: u32,
-        sample_count: vk::SampleCountFlags,
-        format: vk::Format,
-        tiling: vk::ImageTiling,
-        usage: vk::ImageUsageFlags,
-    ) -> (vk::Image, vk::DeviceMemory) {
-        let image_info = vk::ImageCreateInfo::builder()
-            .image_type(vk::ImageType::TYPE_2D)
-            .extent(vk::Extent3D {
-                width: extent.width,
-                height: extent.height,
-                depth: 1,
+        let virtual_texture = VirtualTexture::new(
+            device,
+            queue_family_index,
+            (image, view, sampler),
+            (page_memory, tail_memory),
+            sparse,
+            (page_tables, feedback_buffers, feedback),
+            staging_buffers,
+        );
+
+        virtual_texture.bind_mip_tail(device, queue);
+        let texels = VirtualTexture::mip_tail_texels(&sparse);
+        let mut staging = Self::create_buffer(
+            vk_context,
+            texels.len() as _,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        unsafe {
+            let data_ptr = device
+                .map_memory(staging.memory, 0, staging.size, vk::MemoryMapFlags::empty())
+                .unwrap();
+            let mut align = ash::util::Align::new(data_ptr, align_of::<u8>() as _, staging.size);
+            align.copy_from_slice(&texels);
+            device.unmap_memory(staging.memory);
+        }
+        Self::execute_one_time_commands(device, command_pool, queue, |buffer| {
+            virtual_texture.cmd_upload_mip_tail(device, buffer, staging)
+        });
+        staging.destroy(device);
+
+        Ok(Some(virtual_texture))
+    }
+
+    /// Create the occlusion culling of the chunks of `terrain` and its buffers.
+    fn create_hiz(
+        vk_context: &VkContext,
+        terrain: &Terrain,
+        image_count: usize,
+    ) -> Result<HiZ, AllocationError> {
+        let host_buffer = |size, usage| {
+            Self::create_buffer(
+                vk_context,
+                size,
+                usage,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+        };
+        let bounds = terrain.chunk_bounds();
+        let bounds_buffer = host_buffer(
+            HiZ::bounds_buffer_size(&bounds),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        )?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                host_buffer(
+                    size_of::<HiZUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                )
             })
-            .mip_levels(mip_levels)
-            .array_layers(1)
-            .format(format)
-            .tiling(tiling)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(usage)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .samples(sample_count)
-            .flags(vk::ImageCreateFlags::empty())
-            .build();
+            .collect::<Result<_, _>>()?;
+        let stats_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<u32>() as _,
+                    vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::TRANSFER_SRC
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        let stats = Self::create_readback_buffer(vk_context, 1, image_count)?;
+
+        Ok(HiZ::new(
+            vk_context.device(),
+            &bounds,
+            bounds_buffer,
+            terrain.indirect_buffers(),
+            uniform_buffers,
+            (stats_buffers, stats),
+        ))
+    }
+
+    /// Create a ring reading back `len` values per swapchain image.
+    fn create_readback_buffer<T: Copy>(
+        vk_context: &VkContext,
+        len: usize,
+        image_count: usize,
+    ) -> Result<ReadbackBuffer<T>, AllocationError> {
+        let buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    ReadbackBuffer::<T>::buffer_size(len),
+                    vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ReadbackBuffer::new(buffers, len))
+    }
 
+    /// Create the pyramid of `depth_texture` and give it to `depth_pyramid`.
+    fn set_depth_pyramid(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        depth_pyramid: &mut DepthPyramid,
+        (depth_texture, depth_format): (&Texture, vk::Format),
+        extent: vk::Extent2D,
+    ) -> Result<(), AllocationError> {
+        let desc = TextureDesc {
+            mip_levels: pyramid_level_count(extent),
+            ..TextureDesc::new_2d(
+                pyramid_extent(extent),
+                HIZ_FORMAT,
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+        };
+        let pyramid =
+            Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        let depth_aspect = Self::get_depth_aspect(depth_format);
         let device = vk_context.device();
-        let image = unsafe { device.create_image(&image_info, None).unwrap() };
-        let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
-        let mem_type_index = Self::find_memory_type(
-            mem_requirements,
-            vk_context.get_mem_properties(),
-            mem_properties,
+        depth_pyramid.set_pyramid(device, (depth_texture, depth_aspect), extent, pyramid);
+        Self::execute_one_time_commands(device, command_pool, queue, |buffer| {
+            depth_pyramid.cmd_clear(device, buffer)
+        });
+        Ok(())
+    }
+
+    /// Create the compute pipelines of `depth_pyramid` and give them to it.
+    ///
+    /// They do not depend on the swapchain so they are only created once. The
+    /// first level is reduced from each sample of the depth buffer if it is
+    /// multisampled.
+    fn create_depth_pyramid_pipelines(
+        device: &Device,
+        msaa_samples: vk::SampleCountFlags,
+        depth_pyramid: &mut DepthPyramid,
+    ) {
+        let depth_shader = if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            "shaders/hiz_depth.comp.spv"
+        } else {
+            "shaders/hiz_depth_ms.comp.spv"
+        };
+        let reduce_shader = "shaders/hiz_reduce.comp.spv";
+        let layout = Self::create_compute_pipeline_layout(
+            device,
+            depth_pyramid.layout(),
+            &[depth_shader, reduce_shader],
         );
+        let depth_pipeline = Self::create_compute_pipeline(device, depth_shader, layout, &[]);
+        let reduce_pipeline = Self::create_compute_pipeline(device, reduce_shader, layout, &[]);
+        depth_pyramid.set_pipelines(depth_pipeline, reduce_pipeline, layout);
+    }
 
-        let alloc_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(mem_requirements.size)
-            .memory_type_index(mem_type_index)
-            .build();
-        let memory = unsafe {
-            let mem = device.allocate_memory(&alloc_info, None).unwrap();
-            device.bind_image_memory(image, mem, 0).unwrap();
-            mem
+    /// Create the culling pipeline of `hiz` and give it to it.
+    ///
+    /// It does not depend on the swapchain so it is only created once. The
+    /// occluded objects are counted with subgroup operations if `subgroup`
+    /// supports arithmetic operations in compute shaders.
+    fn create_hiz_pipeline(device: &Device, subgroup: Option<SubgroupProperties>, hiz: &mut HiZ) {
+        let (shader, specialization_constants) = match Self::subgroup_arithmetic(subgroup) {
+            Some(subgroup) => (
+                "shaders/hiz_cull_subgroup.comp.spv",
+                subgroup.specialization_constants().to_vec(),
+            ),
+            None => ("shaders/hiz_cull.comp.spv", Vec::new()),
         };
+        let cull_layout = Self::create_compute_pipeline_layout(device, hiz.layout(), &[shader]);
+        let cull_pipeline =
+            Self::create_compute_pipeline(device, shader, cull_layout, &specialization_constants);
 
-        (image, memory)
+        hiz.set_pipeline(cull_pipeline, cull_layout);
     }
 
-    fn transition_image_layout(
+    /// Create the pipeline drawing the chunks of `mesh_streamer` and give it to the streamer.
+    fn create_mesh_streamer_pipeline(
         device: &Device,
-        command_pool: vk::CommandPool,
-        transition_queue: vk::Queue,
-        image: vk::Image,
-        mip_levels: u32,
-        format: vk::Format,
-        old_layout: vk::ImageLayout,
-        new_layout: vk::ImageLayout,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        mesh_streamer: &mut MeshStreamer,
     ) {
-        Self::execute_one_time_commands(device, command_pool, transition_queue, |buffer| {
-            let (src_access_mask, dst_access_mask, src_stage, dst_stage) =
-                match (old_layout, new_layout) {
-                    (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
-                        vk::AccessFlags::empty(),
-                        vk::AccessFlags::TRANSFER_WRITE,
-                        vk::PipelineStageFlags::TOP_OF_PIPE,
-                        vk::PipelineStageFlags::TRANSFER,
-                    ),
-                    (
-                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                    ) => (
-                        vk::AccessFlags::TRANSFER_WRITE,
-                        vk::AccessFlags::SHADER_READ,
-                        vk::PipelineStageFlags::TRANSFER,
-                        vk::PipelineStageFlags::FRAGMENT_SHADER,
-                    ),
-                    (
-                        vk::ImageLayout::UNDEFINED,
-                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                    ) => (
-                        vk::AccessFlags::empty(),
-                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                        vk::PipelineStageFlags::TOP_OF_PIPE,
-                        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-                    ),
-                    (vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL) => (
-                        vk::AccessFlags::empty(),
-                        vk::AccessFlags::COLOR_ATTACHMENT_READ
-                            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-                        vk::PipelineStageFlags::TOP_OF_PIPE,
-                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                    ),
-                    _ => panic!(
-                        "Unsupported layout transition({:?} => {:?}).",
-                        old_layout, new_layout
-                    ),
-                };
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
 
-            let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL {
-                let mut mask = vk::ImageAspectFlags::DEPTH;
-                if Self::has_stencil_component(format) {
-                    mask |= vk::ImageAspectFlags::STENCIL;
-                }
-                mask
-            } else {
-                vk::ImageAspectFlags::COLOR
-            };
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/streamed.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/streamed.frag.spv"),
+        ];
+        let vertex_binding_descs = [StreamedVertex::get_binding_description()];
+        let vertex_attribute_descs = StreamedVertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
 
-            let barrier = vk::ImageMemoryBarrier::builder()
-                .old_layout(old_layout)
-                .new_layout(new_layout)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .image(image)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask,
-                    base_mip_level: 0,
-                    level_count: mip_levels,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                })
-                .src_access_mask(src_access_mask)
-                .dst_access_mask(dst_access_mask)
-                .build();
-            let barriers = [barrier];
+        mesh_streamer.set_pipeline(pipeline, layout);
+    }
 
-            unsafe {
-                device.cmd_pipeline_barrier(
-                    buffer,
-                    src_stage,
-                    dst_stage,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &barriers,
-                )
-            };
-        });
+    /// Create the pipeline drawing the plane of `virtual_texture` and give it to the texture.
+    fn create_virtual_texture_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        virtual_texture: &mut VirtualTexture,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[virtual_texture.layout()]);
+
+        let shaders = [
+            (
+                vk::ShaderStageFlags::VERTEX,
+                "shaders/virtual_texture.vert.spv",
+            ),
+            (
+                vk::ShaderStageFlags::FRAGMENT,
+                "shaders/virtual_texture.frag.spv",
+            ),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        virtual_texture.set_pipeline(pipeline, layout);
     }
 
-    fn copy_buffer_to_image(
+    /// Create the pipeline drawing `terrain` and give it to the terrain.
+    fn create_terrain_pipeline(
         device: &Device,
-        command_pool: vk::CommandPool,
-        transition_queue: vk::Queue,
-        buffer: vk::Buffer,
-        image: vk::Image,
-        extent: vk::Extent2D,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        terrain: &mut Terrain,
     ) {
-        Self::execute_one_time_commands(device, command_pool, transition_queue, |command_buffer| {
-            let region = vk::BufferImageCopy::builder()
-                .buffer_offset(0)
-                .buffer_row_length(0)
-                .buffer_image_height(0)
-                .image_subresource(vk::ImageSubresourceLayers {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    mip_level: 0,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                })
-                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
-                .image_extent(vk::Extent3D {
-                    width: extent.width,
-                    height: extent.height,
-                    depth: 1,
-                })
-                .build();
-            let regions = [region];
-            unsafe {
-                device.cmd_copy_buffer_to_image(
-                    command_buffer,
-                    buffer,
-                    image,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    &regions,
+        let layout = frame_layout.create_pipeline_layout(device, &[terrain.layout()]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/terrain.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/terrain.frag.spv"),
+        ];
+        let vertex_binding_descs = [TerrainVertex::get_binding_description()];
+        let vertex_attribute_descs = TerrainVertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        terrain.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the sky and its uniform buffers.
+    fn create_sky(
+        vk_context: &VkContext,
+        parameters: SkyParameters,
+        image_count: usize,
+    ) -> Result<Sky, AllocationError> {
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<SkyUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
                 )
-            }
-        })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Sky::new(vk_context.device(), parameters, uniform_buffers))
     }
 
-    fn generate_mipmaps(
+    /// Create the pipeline drawing `sky` and give it to the sky.
+    fn create_sky_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        sky: &mut Sky,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[sky.layout()]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/sky.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/sky.frag.spv"),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                // The sky is drawn first, behind everything else
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        sky.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the gradient or the grid drawn in the background and its uniform buffer.
+    fn create_background(
+        vk_context: &VkContext,
+        parameters: BackgroundParameters,
+    ) -> Result<Background, AllocationError> {
+        let uniform_buffer = Self::create_buffer(
+            vk_context,
+            size_of::<BackgroundUniforms>() as _,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        Ok(Background::new(
+            vk_context.device(),
+            parameters,
+            uniform_buffer,
+        ))
+    }
+
+    /// Create the pipeline drawing `background` and give it to the background.
+    fn create_background_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        background: &mut Background,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[background.layout()]);
+
+        // The grid is tested against the opaque geometry and blended over it
+        let grid = background.mode() == BackgroundMode::Grid;
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/sky.vert.spv"),
+            (
+                vk::ShaderStageFlags::FRAGMENT,
+                if grid {
+                    "shaders/grid.frag.spv"
+                } else {
+                    "shaders/gradient.frag.spv"
+                },
+            ),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: grid,
+                writes: true,
+                stencil: None,
+                alpha_blending: grid,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        background.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the sprite renderer, its atlas and its buffers.
+    fn create_sprite_renderer(
         vk_context: &VkContext,
         command_pool: vk::CommandPool,
         transfer_queue: vk::Queue,
-        image: vk::Image,
-        extent: vk::Extent2D,
-        format: vk::Format,
-        mip_levels: u32,
-    ) {
-        let format_properties = unsafe {
-            vk_context
-                .instance()
-                .get_physical_device_format_properties(vk_context.physical_device(), format)
+        image_count: usize,
+    ) -> Result<SpriteRenderer, AllocationError> {
+        let texture = Self::create_texture_from_pixels(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::Extent2D {
+                width: ATLAS_TILE_SIZE * SPRITE_ATLAS.columns,
+                height: ATLAS_TILE_SIZE * SPRITE_ATLAS.rows,
+            },
+            &generate_sprite_atlas(),
+            ColorSpace::Srgb,
+        )?;
+        let create_buffers = |size, usage| {
+            (0..image_count)
+                .map(|_| {
+                    Self::create_buffer(
+                        vk_context,
+                        size,
+                        usage,
+                        vk::MemoryPropertyFlags::HOST_VISIBLE
+                            | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()
         };
-        if !format_properties
-            .optimal_tiling_features
-            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
-        {
-            panic!("Linear blitting is not supported for format {:?}.", format)
-        }
+        let vertex_buffers = create_buffers(
+            SpriteRenderer::vertex_buffer_size(),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let indirect_buffers = create_buffers(
+            SpriteRenderer::indirect_buffer_size(),
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+        )?;
 
-        Self::execute_one_time_commands(
+        Ok(SpriteRenderer::new(
             vk_context.device(),
-            command_pool,
-            transfer_queue,
-            |buffer| {
-                let mut barrier = vk::ImageMemoryBarrier::builder()
-                    .image(image)
-                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                        level_count: 1,
-                        ..Default::default()
-                    })
-                    .build();
+            SPRITE_ATLAS,
+            texture,
+            vertex_buffers,
+            indirect_buffers,
+        ))
+    }
 
-                let mut mip_width = extent.width as i32;
-                let mut mip_height = extent.height as i32;
-                for level in 1..mip_levels {
-                    let next_mip_width = if mip_width > 1 {
-                        mip_width / 2
-                    } else {
+    /// Create the pipeline drawing the sprites and give it to `sprites`.
+    fn create_sprite_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        sprites: &mut SpriteRenderer,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[sprites.layout()]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/sprite.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/sprite.frag.spv"),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[SpriteVertex::get_binding_description()],
+                vertex_attribute_descs: &SpriteVertex::get_attribute_descriptions(),
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: true,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        sprites.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the particles, their buffers and their sort, and clear their counters.
+    fn create_particles(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        image_count: usize,
+    ) -> Result<Particles, AllocationError> {
+        let device_local = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        let storage = vk::BufferUsageFlags::STORAGE_BUFFER;
+        let particle_buffer = |usage| {
+            Self::create_buffer(
+                vk_context,
+                Particles::particle_buffer_size(),
+                storage | usage,
+                device_local,
+            )
+        };
+        let particles = particle_buffer(vk::BufferUsageFlags::TRANSFER_DST)?;
+        let output = particle_buffer(vk::BufferUsageFlags::TRANSFER_SRC)?;
+        let counters = Self::create_buffer(
+            vk_context,
+            size_of::<ParticleCounters>() as _,
+            storage
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            device_local,
+        )?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<ParticleUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let alive_counts = Self::create_readback_buffer(vk_context, 1, image_count)?;
+        let sort = Self::create_gpu_sort(vk_context, PARTICLE_CAPACITY, device_local)?;
+
+        let device = vk_context.device();
+        let particles = Particles::new(
+            device,
+            (particles, output, counters),
+            sort,
+            (uniform_buffers, alive_counts),
+        );
+        Self::execute_one_time_commands(device, command_pool, queue, |buffer| {
+            particles.cmd_reset(device, buffer)
+        });
+        Ok(particles)
+    }
+
+    /// Create the compute pipelines of `particles` and of their sort and give them to them.
+    ///
+    /// They do not depend on the swapchain so they are only created once.
+    fn create_particle_pipelines(
+        device: &Device,
+        subgroup: Option<SubgroupProperties>,
+        particles: &mut Particles,
+    ) {
+        let layout = {
+            let layouts = [particles.layout()];
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .build();
+
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        };
+        let pipelines = ParticleStage::ALL.map(|stage| {
+            Self::create_compute_pipeline(
+                device,
+                "shaders/particles.comp.spv",
+                layout,
+                &[stage as u32],
+            )
+        });
+        particles.set_pipelines(pipelines, layout);
+        Self::create_gpu_sort_pipelines(device, subgroup, particles.sort_mut());
+    }
+
+    /// Create the pipeline drawing `particles` and give it to them.
+    fn create_particle_draw_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        particles: &mut Particles,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[particles.draw_layout()]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/particle.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/particle.frag.spv"),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: true,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        particles.set_draw_pipeline(pipeline, layout);
+    }
+
+    /// Build the sprites of the demo at `time` seconds.
+    ///
+    /// A light gizmo floats above the model, a ring of grass and flowers
+    /// surrounds it and particles rise from its center.
+    fn create_demo_sprites(time: f32) -> Vec<Sprite> {
+        let mut sprites = vec![Sprite {
+            position: Point3::new(1.0, 1.2, 1.0),
+            size: [0.3, 0.3],
+            tile: LIGHT_TILE,
+            color: [1.0, 1.0, 1.0, 1.0],
+            upright: false,
+        }];
+
+        let vegetation_count = 32;
+        sprites.extend((0..vegetation_count).map(|i| {
+            let angle = i as f32 / vegetation_count as f32 * 2.0 * std::f32::consts::PI;
+            let radius = 1.3 + 0.15 * (i as f32 * 2.7).sin();
+            let tile = if i % 5 == 0 { FLOWER_TILE } else { GRASS_TILE };
+            Sprite {
+                position: Point3::new(angle.cos() * radius, 0.1, angle.sin() * radius),
+                size: [0.2, 0.2],
+                tile,
+                color: [1.0, 1.0, 1.0, 1.0],
+                upright: true,
+            }
+        }));
+
+        let particle_count = 24;
+        sprites.extend((0..particle_count).map(|i| {
+            let phase = (time * 0.3 + i as f32 / particle_count as f32).fract();
+            let angle = i as f32 * 2.4;
+            let spread = 0.3 * phase;
+            Sprite {
+                position: Point3::new(angle.cos() * spread, phase * 1.5, angle.sin() * spread),
+                size: [0.08, 0.08],
+                tile: PARTICLE_TILE,
+                color: [1.0, 0.6, 0.2, 1.0 - phase],
+                upright: false,
+            }
+        }));
+
+        sprites
+    }
+
+    /// Create the text renderer, bake its font atlas and create its buffers.
+    fn create_text_renderer(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        image_count: usize,
+    ) -> Result<TextRenderer, AllocationError> {
+        let (font, pixels) = FontAtlas::bake(fs::load("fonts/DejaVuSansMono.ttf").into_inner());
+        let texture = Self::create_texture_from_pixels(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            font.extent(),
+            &pixels,
+            ColorSpace::Linear,
+        )?;
+        let create_buffers = |size, usage| {
+            (0..image_count)
+                .map(|_| {
+                    Self::create_buffer(
+                        vk_context,
+                        size,
+                        usage,
+                        vk::MemoryPropertyFlags::HOST_VISIBLE
+                            | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let vertex_buffers = create_buffers(
+            TextRenderer::vertex_buffer_size(),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let indirect_buffers = create_buffers(
+            TextRenderer::indirect_buffer_size(),
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+        )?;
+
+        Ok(TextRenderer::new(
+            vk_context.device(),
+            font,
+            texture,
+            vertex_buffers,
+            indirect_buffers,
+        ))
+    }
+
+    /// Create the pipeline drawing the text and give it to `text`.
+    ///
+    /// The text is drawn in the second subpass, or after the tonemapping when
+    /// there is a `tonemapper` so it is not exposed like the scene.
+    fn create_text_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        tonemapper: Option<&Tonemapper>,
+        text: &mut TextRenderer,
+    ) {
+        let (msaa_samples, render_pass, subpass) = match tonemapper {
+            Some(tonemapper) => (vk::SampleCountFlags::TYPE_1, tonemapper.render_pass(), 0),
+            None => (msaa_samples, render_pass, 1),
+        };
+
+        let layout = {
+            let layouts = [text.layout()];
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .build();
+
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        };
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/text.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/text.frag.spv"),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[TextVertex::get_binding_description()],
+                vertex_attribute_descs: &TextVertex::get_attribute_descriptions(),
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                // Text is drawn on top of everything
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: true,
+                dynamic_viewport: false,
+                subpass,
+                layout,
+            },
+        );
+
+        text.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the HDR image the scene is resolved to when the exposure is automatic.
+    fn create_hdr_texture(
+        vk_context: &VkContext,
+        extent: vk::Extent2D,
+    ) -> Result<Texture, AllocationError> {
+        let desc = TextureDesc::new_2d(
+            extent,
+            HDR_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::SAMPLED,
+        );
+        Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+    }
+
+    /// Create the automatic exposure and its buffers.
+    fn create_auto_exposure(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        image_count: usize,
+    ) -> Result<AutoExposure, AllocationError> {
+        let histogram_buffer = Self::create_device_local_buffer_with_data::<u32, _>(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &[0u32; HISTOGRAM_BINS],
+        )?;
+        let exposure_buffer = Self::create_device_local_buffer_with_data::<f32, _>(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &[ExposureData::default()],
+        )?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<ExposureUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(AutoExposure::new(
+            vk_context.device(),
+            Default::default(),
+            histogram_buffer,
+            exposure_buffer,
+            uniform_buffers,
+        ))
+    }
+
+    /// Create the bloom and its counter buffer.
+    fn create_bloom(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+    ) -> Result<Bloom, AllocationError> {
+        let counter_buffer = Self::create_device_local_buffer_with_data::<u32, _>(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &[0u32],
+        )?;
+        Ok(Bloom::new(vk_context.device(), counter_buffer))
+    }
+
+    /// Create the chain of `hdr` and give it to `bloom`.
+    fn set_bloom_chain(
+        vk_context: &VkContext,
+        bloom: &mut Bloom,
+        hdr: &Texture,
+        extent: vk::Extent2D,
+    ) -> Result<(), AllocationError> {
+        let desc = TextureDesc {
+            mip_levels: chain_level_count(extent),
+            ..TextureDesc::new_2d(
+                chain_extent(extent),
+                BLOOM_FORMAT,
+                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            )
+        };
+        let chain = Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        bloom.set_chain(vk_context.device(), hdr, extent, chain);
+        Ok(())
+    }
+
+    /// Create the compute pipeline of `bloom` and give it to it.
+    ///
+    /// It does not depend on the swapchain so it is only created once. The
+    /// blocks are averaged with subgroup operations if `subgroup` supports quad
+    /// operations in compute shaders.
+    fn create_bloom_pipeline(
+        device: &Device,
+        subgroup: Option<SubgroupProperties>,
+        bloom: &mut Bloom,
+    ) {
+        let subgroup_quad = subgroup
+            .filter(|subgroup| subgroup.supports_compute(vk::SubgroupFeatureFlags::QUAD))
+            .is_some();
+        let shader = if subgroup_quad {
+            "shaders/downsample_subgroup.comp.spv"
+        } else {
+            "shaders/downsample.comp.spv"
+        };
+        let layout = Self::create_compute_pipeline_layout(device, bloom.layout(), &[shader]);
+        let pipeline = Self::create_compute_pipeline(device, shader, layout, &[]);
+        bloom.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the images `fsr` upscales `input` into, at `extent`, and give them to it.
+    fn set_fsr_targets(
+        vk_context: &VkContext,
+        fsr: &mut Fsr,
+        input: (Texture, vk::ImageLayout),
+        extent: vk::Extent2D,
+    ) -> Result<(), AllocationError> {
+        let desc = TextureDesc::new_2d(
+            extent,
+            FSR_FORMAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        );
+        let create_target =
+            || Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+        let targets = (create_target()?, create_target()?);
+        fsr.set_targets(vk_context.device(), input, targets, extent);
+        Ok(())
+    }
+
+    /// Create the compute pipelines of EASU and RCAS and give them to `fsr`.
+    ///
+    /// They do not depend on the swapchain so they are only created once.
+    fn create_fsr_pipelines(device: &Device, fsr: &mut Fsr) {
+        let shaders = ["shaders/fsr_easu.comp.spv", "shaders/fsr_rcas.comp.spv"];
+        let layout = Self::create_compute_pipeline_layout(device, fsr.layout(), &shaders);
+        let easu = Self::create_compute_pipeline(device, shaders[0], layout, &[]);
+        let rcas = Self::create_compute_pipeline(device, shaders[1], layout, &[]);
+        fsr.set_pipelines((easu, rcas), layout);
+    }
+
+    /// Create a sort of up to `capacity` keys and values.
+    ///
+    /// The keys and values have the memory properties `mem_properties` and can
+    /// be filled with transfers, the scratch buffers and the histogram are
+    /// device local.
+    fn create_gpu_sort(
+        vk_context: &VkContext,
+        capacity: u32,
+        mem_properties: vk::MemoryPropertyFlags,
+    ) -> Result<GpuSort, AllocationError> {
+        let create_buffer = |size, mem_properties| {
+            Self::create_buffer(
+                vk_context,
+                size,
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                mem_properties,
+            )
+        };
+        let size = GpuSort::buffer_size(capacity);
+        let device_local = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        Ok(GpuSort::new(
+            vk_context.device(),
+            capacity,
+            (
+                create_buffer(size, mem_properties)?,
+                create_buffer(size, mem_properties)?,
+            ),
+            (
+                create_buffer(size, device_local)?,
+                create_buffer(size, device_local)?,
+                create_buffer(GpuSort::histogram_size(capacity), device_local)?,
+            ),
+        ))
+    }
+
+    /// Create the compute pipelines of `sort` and give them to it.
+    ///
+    /// The pipeline of each stage is specialized from the same shader, which
+    /// uses subgroup operations if `subgroup` supports arithmetic operations in
+    /// compute shaders.
+    fn create_gpu_sort_pipelines(
+        device: &Device,
+        subgroup: Option<SubgroupProperties>,
+        sort: &mut GpuSort,
+    ) {
+        // The subgroup size is ignored by the shader without subgroups
+        let (shader, [subgroup_size]) = match Self::subgroup_arithmetic(subgroup) {
+            Some(subgroup) => (
+                "shaders/radix_sort_subgroup.comp.spv",
+                subgroup.specialization_constants(),
+            ),
+            None => ("shaders/radix_sort.comp.spv", [0]),
+        };
+        let layout = Self::create_compute_pipeline_layout(device, sort.layout(), &[shader]);
+        let pipelines = SortStage::ALL.map(|stage| {
+            Self::create_compute_pipeline(device, shader, layout, &[subgroup_size, stage as u32])
+        });
+        sort.set_pipelines(pipelines, layout);
+    }
+
+    /// Create the compute pipelines of `auto_exposure` and give them to it.
+    ///
+    /// They do not depend on the swapchain so they are only created once. The
+    /// histogram is averaged with subgroup operations if `subgroup` supports
+    /// arithmetic operations in compute shaders.
+    fn create_auto_exposure_pipelines(
+        device: &Device,
+        subgroup: Option<SubgroupProperties>,
+        auto_exposure: &mut AutoExposure,
+    ) {
+        let layout = {
+            let layouts = [auto_exposure.layout()];
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .build();
+
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        };
+
+        let histogram_pipeline =
+            Self::create_compute_pipeline(device, "shaders/histogram.comp.spv", layout, &[]);
+        let average_pipeline = match Self::subgroup_arithmetic(subgroup) {
+            Some(subgroup) => Self::create_compute_pipeline(
+                device,
+                "shaders/exposure_subgroup.comp.spv",
+                layout,
+                &subgroup.specialization_constants(),
+            ),
+            None => Self::create_compute_pipeline(device, "shaders/exposure.comp.spv", layout, &[]),
+        };
+
+        auto_exposure.set_pipelines(histogram_pipeline, average_pipeline, layout);
+    }
+
+    /// Create the motion blur, its render targets and its buffers.
+    fn create_motion_blur(
+        vk_context: &VkContext,
+        depth_format: vk::Format,
+        extent: vk::Extent2D,
+        image_count: usize,
+    ) -> Result<MotionBlur, AllocationError> {
+        let velocity_target = Self::create_render_target(
+            vk_context,
+            RenderTargetDesc {
+                color_formats: vec![VELOCITY_FORMAT],
+                depth_format: Some(depth_format),
+                sample_depth: false,
+            },
+            extent,
+        )?;
+        let blur_target = Self::create_render_target(
+            vk_context,
+            RenderTargetDesc {
+                color_formats: vec![HDR_FORMAT],
+                depth_format: None,
+                sample_depth: false,
+            },
+            extent,
+        )?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<MotionBlurUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        // Like a camera shutter open during half of the frame
+        let intensity = 0.5;
+        Ok(MotionBlur::new(
+            vk_context.device(),
+            intensity,
+            velocity_target,
+            blur_target,
+            uniform_buffers,
+        ))
+    }
+
+    /// Create the light shafts, their render target and their buffers.
+    fn create_light_shafts(
+        vk_context: &VkContext,
+        extent: vk::Extent2D,
+        image_count: usize,
+        half_resolution: bool,
+    ) -> Result<LightShafts, AllocationError> {
+        let desc = RenderTargetDesc {
+            color_formats: vec![HDR_FORMAT],
+            depth_format: None,
+            sample_depth: false,
+        };
+        let (target, upsample) = if half_resolution {
+            let target = Self::create_render_target(vk_context, desc.clone(), half_extent(extent))?;
+            let upsample_target = Self::create_render_target(vk_context, desc, extent)?;
+            let upsample = BilateralUpsample::new(vk_context.device(), upsample_target);
+            (target, Some(upsample))
+        } else {
+            (Self::create_render_target(vk_context, desc, extent)?, None)
+        };
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<LightShaftUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(LightShafts::new(
+            vk_context.device(),
+            LightShaftParameters::default(),
+            target,
+            uniform_buffers,
+            upsample,
+        ))
+    }
+
+    /// Create the screen-space reflections, their render target and their buffers.
+    fn create_ssr(
+        vk_context: &VkContext,
+        extent: vk::Extent2D,
+        image_count: usize,
+    ) -> Result<ScreenSpaceReflections, AllocationError> {
+        let desc = RenderTargetDesc {
+            color_formats: vec![HDR_FORMAT],
+            depth_format: None,
+            sample_depth: false,
+        };
+        let target = Self::create_render_target(vk_context, desc, extent)?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<SsrUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ScreenSpaceReflections::new(
+            vk_context.device(),
+            SsrParameters::default(),
+            target,
+            uniform_buffers,
+        ))
+    }
+
+    /// Give `ssr` the images it traces the reflections in and a new history,
+    /// cleared with `queue`.
+    ///
+    /// The depth buffer is readable once `depth_pyramid` is built from it.
+    fn set_ssr_inputs(
+        vk_context: &VkContext,
+        (command_pool, queue): (vk::CommandPool, vk::Queue),
+        ssr: &mut ScreenSpaceReflections,
+        hdr_texture: &Texture,
+        depth_texture: &Texture,
+        depth_pyramid: &DepthPyramid,
+        motion_blur: &MotionBlur,
+    ) -> Result<(), AllocationError> {
+        let desc = TextureDesc::new_2d(
+            depth_pyramid.depth_extent(),
+            HDR_FORMAT,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        );
+        let history =
+            Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        let device = vk_context.device();
+        ssr.set_inputs(
+            device,
+            (*hdr_texture, vk::ImageLayout::GENERAL),
+            depth_texture,
+            depth_pyramid.texture(),
+            motion_blur.velocity(),
+            history,
+        );
+        Self::execute_one_time_commands(device, command_pool, queue, |buffer| {
+            ssr.cmd_clear_history(device, buffer)
+        });
+        Ok(())
+    }
+
+    /// Create the pipeline of the screen-space reflections and give it to `ssr`.
+    ///
+    /// It reads the depth buffer multisampled `msaa_samples` times. With
+    /// `reflection_probes`, the fragment shader is compiled with the
+    /// `REFLECTION_PROBES` keyword and falls back to the probes bound in set 2.
+    fn create_ssr_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        frame_layout: &FrameLayout,
+        shader_variants: &mut ShaderVariants,
+        reflection_probes: Option<&ReflectionProbes>,
+        ssr: &mut ScreenSpaceReflections,
+    ) {
+        let fragment_shader = if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            "ssr.frag"
+        } else {
+            "ssr_ms.frag"
+        };
+        let keywords = if reflection_probes.is_some() {
+            vec!["REFLECTION_PROBES"]
+        } else {
+            Vec::new()
+        };
+        let fragment_shader = shader_variants
+            .path(fragment_shader, &keywords)
+            .unwrap_or_else(|error| {
+                log::warn!(
+                    "Failed to compile {} with {:?}: {}.",
+                    fragment_shader,
+                    keywords,
+                    error
+                );
+                format!("shaders/{}.spv", fragment_shader)
+            });
+
+        let layout = match reflection_probes {
+            Some(reflection_probes) => frame_layout
+                .create_pipeline_layout(device, &[ssr.layout(), reflection_probes.layout()]),
+            None => frame_layout.create_pipeline_layout(device, &[ssr.layout()]),
+        };
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            vk::SampleCountFlags::TYPE_1,
+            ssr.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &[
+                    (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+                    (vk::ShaderStageFlags::FRAGMENT, &fragment_shader),
+                ],
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        ssr.set_pipeline(pipeline, layout);
+    }
+
+    /// Create and build the acceleration structures of the model, with `queue`.
+    ///
+    /// The model is placed with an identity transform, the rays are moved to
+    /// its space instead.
+    fn create_acceleration_structures(
+        vk_context: &VkContext,
+        (command_pool, queue): (vk::CommandPool, vk::Queue),
+        (vertex_buffer, vertex_count): (Buffer, usize),
+        (index_buffer, index_count): (Buffer, usize),
+    ) -> Result<AccelerationStructures, AllocationError> {
+        let device = vk_context.device();
+        let ray_tracing = vk_context
+            .ray_tracing()
+            .expect("The acceleration structures need ray tracing");
+        let mesh = TriangleMesh {
+            vertex_buffer,
+            vertex_stride: size_of::<Vertex>() as _,
+            vertex_count: vertex_count as _,
+            index_buffer,
+            index_count: index_count as _,
+        };
+        let (structures, mut scratch) = AccelerationStructures::new(
+            device,
+            ray_tracing,
+            &[(mesh, Matrix4::identity())],
+            |size, usage, mem_properties| {
+                Self::create_buffer(vk_context, size, usage, mem_properties)
+            },
+        )?;
+        Self::execute_one_time_commands(device, command_pool, queue, |buffer| {
+            structures.cmd_build(device, ray_tracing, buffer, &scratch)
+        });
+        scratch.destroy(device);
+        Ok(structures)
+    }
+
+    fn create_ray_traced_shadows(
+        vk_context: &VkContext,
+        acceleration_structures: &AccelerationStructures,
+        image_count: usize,
+    ) -> Result<RayTracedShadows, AllocationError> {
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<RayTracedShadowUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(RayTracedShadows::new(
+            vk_context.device(),
+            vk_context.ray_tracing().unwrap(),
+            RayTracedShadowParameters::default(),
+            acceleration_structures.top_level(),
+            uniform_buffers,
+        ))
+    }
+
+    /// Create the ray tracing pipeline of the ray-traced shadows and its shader
+    /// binding table, and give them to `shadows`.
+    ///
+    /// It reads the depth buffer multisampled `msaa_samples` times.
+    fn create_ray_traced_shadows_pipeline(
+        vk_context: &VkContext,
+        msaa_samples: vk::SampleCountFlags,
+        shadows: &mut RayTracedShadows,
+    ) -> Result<(), AllocationError> {
+        let device = vk_context.device();
+        let ray_tracing = vk_context.ray_tracing().unwrap();
+        let raygen_path = if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            "shaders/ray_traced_shadows.rgen.spv"
+        } else {
+            "shaders/ray_traced_shadows_ms.rgen.spv"
+        };
+        let raygen_module =
+            Self::create_shader_module(device, &Self::read_shader_from_file(raygen_path));
+        let miss_module = Self::create_shader_module(
+            device,
+            &Self::read_shader_from_file("shaders/ray_traced_shadows.rmiss.spv"),
+        );
+
+        let entry_point_name = CString::new("main").unwrap();
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::RAYGEN_NV)
+                .module(raygen_module)
+                .name(&entry_point_name)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::MISS_NV)
+                .module(miss_module)
+                .name(&entry_point_name)
+                .build(),
+        ];
+        let layouts = [shadows.layout()];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&layouts)
+            .build();
+        let layout = unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() };
+        let pipeline = ray_tracing.create_pipeline(
+            device,
+            &stages,
+            &[ShaderGroup::General(0), ShaderGroup::General(1)],
+            layout,
+        );
+        unsafe {
+            device.destroy_shader_module(raygen_module, None);
+            device.destroy_shader_module(miss_module, None);
+        }
+
+        let shader_binding_table =
+            ShaderBindingTable::new(device, ray_tracing, pipeline, (1, 0), |size, usage| {
+                Self::create_buffer(
+                    vk_context,
+                    size,
+                    usage,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            });
+        let shader_binding_table = match shader_binding_table {
+            Ok(shader_binding_table) => shader_binding_table,
+            Err(error) => {
+                unsafe {
+                    lifetime::destroy_pipeline(device, pipeline);
+                    device.destroy_pipeline_layout(layout, None);
+                }
+                return Err(error);
+            }
+        };
+        shadows.set_pipeline(pipeline, layout, shader_binding_table);
+        Ok(())
+    }
+
+    /// Create the pipeline of the light shafts pass and give it to `light_shafts`.
+    ///
+    /// At half resolution, the pipeline of the upsample is also created and
+    /// reads the depth buffer multisampled `msaa_samples` times.
+    fn create_light_shafts_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        frame_layout: &FrameLayout,
+        light_shafts: &mut LightShafts,
+    ) {
+        let (shafts_properties, fragment_shader) =
+            if let Some(upsample) = light_shafts.upsample_mut() {
+                Self::create_upsample_pipeline(
+                    device,
+                    swapchain_properties,
+                    msaa_samples,
+                    frame_layout,
+                    upsample,
+                );
+                let properties = SwapchainProperties {
+                    extent: half_extent(swapchain_properties.extent),
+                    ..swapchain_properties
+                };
+                (properties, "shaders/light_shafts_half.frag.spv")
+            } else {
+                (swapchain_properties, "shaders/light_shafts.frag.spv")
+            };
+
+        let layout = frame_layout.create_pipeline_layout(device, &[light_shafts.layout()]);
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            shafts_properties,
+            vk::SampleCountFlags::TYPE_1,
+            light_shafts.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &[
+                    (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+                    (vk::ShaderStageFlags::FRAGMENT, fragment_shader),
+                ],
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        light_shafts.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the pipeline of the bilateral upsample and give it to `upsample`.
+    fn create_upsample_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        frame_layout: &FrameLayout,
+        upsample: &mut BilateralUpsample,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[upsample.layout()]);
+
+        // The depth buffer is multisampled along with the color attachment
+        let fragment_shader = if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            "shaders/bilateral_upsample.frag.spv"
+        } else {
+            "shaders/bilateral_upsample_ms.frag.spv"
+        };
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            vk::SampleCountFlags::TYPE_1,
+            upsample.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &[
+                    (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+                    (vk::ShaderStageFlags::FRAGMENT, fragment_shader),
+                ],
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        upsample.set_pipeline(pipeline, layout);
+    }
+
+    /// Give the depth buffer of the main render pass to `upsample`.
+    ///
+    /// With `depth_pyramid`, the reduction of the depth pyramid has already
+    /// made it readable when the upsample is recorded.
+    fn set_upsample_depth(
+        device: &Device,
+        upsample: &mut BilateralUpsample,
+        (depth_texture, depth_format): (&Texture, vk::Format),
+        depth_pyramid: bool,
+    ) {
+        let depth_state = if depth_pyramid {
+            ImageState {
+                layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::SHADER_READ,
+            }
+        } else {
+            ImageState {
+                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                stage: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            }
+        };
+        let depth_aspect = Self::get_depth_aspect(depth_format);
+        upsample.set_depth(device, (depth_texture, depth_aspect), depth_state);
+    }
+
+    /// Create the pipelines of the velocity and blur passes and give them to `motion_blur`.
+    fn create_motion_blur_pipelines(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        motion_blur: &mut MotionBlur,
+    ) {
+        let layout = {
+            let layouts = [motion_blur.layout()];
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .build();
+
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        };
+        let create_pipeline = |render_pass,
+                               shaders: &[_],
+                               vertex_binding_descs,
+                               vertex_attribute_descs,
+                               depth_test| {
+            Self::create_graphics_pipeline(
+                device,
+                swapchain_properties,
+                vk::SampleCountFlags::TYPE_1,
+                render_pass,
+                &GraphicsPipelineDesc {
+                    shaders,
+                    vertex_binding_descs,
+                    vertex_attribute_descs,
+                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    patch_control_points: None,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                    depth_bias: DepthBiasState::Disabled,
+                    depth_test,
+                    writes: true,
+                    stencil: None,
+                    alpha_blending: false,
+                    dynamic_viewport: false,
+                    subpass: 0,
+                    layout,
+                },
+            )
+        };
+
+        let sky_velocity_pipeline = create_pipeline(
+            motion_blur.velocity_render_pass(),
+            &[
+                (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+                (
+                    vk::ShaderStageFlags::FRAGMENT,
+                    "shaders/sky_velocity.frag.spv",
+                ),
+            ],
+            &[],
+            &[],
+            false,
+        );
+        // Only the positions of the vertices are needed
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let velocity_pipeline = create_pipeline(
+            motion_blur.velocity_render_pass(),
+            &[
+                (vk::ShaderStageFlags::VERTEX, "shaders/velocity.vert.spv"),
+                (vk::ShaderStageFlags::FRAGMENT, "shaders/velocity.frag.spv"),
+            ],
+            &vertex_binding_descs,
+            &vertex_attribute_descs[..1],
+            true,
+        );
+        let blur_pipeline = create_pipeline(
+            motion_blur.blur_render_pass(),
+            &[
+                (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+                (
+                    vk::ShaderStageFlags::FRAGMENT,
+                    "shaders/motion_blur.frag.spv",
+                ),
+            ],
+            &[],
+            &[],
+            false,
+        );
+
+        motion_blur.set_pipelines(
+            sky_velocity_pipeline,
+            velocity_pipeline,
+            blur_pipeline,
+            layout,
+        );
+    }
+
+    /// Create the 3D texture of `lut` sampled by the tonemapper.
+    fn create_color_lut(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        copy_queue: vk::Queue,
+        lut: &ColorLut,
+    ) -> Result<Texture, AllocationError> {
+        Self::create_texture_3d_from_data(
+            vk_context,
+            command_pool,
+            copy_queue,
+            lut.extent(),
+            COLOR_LUT_FORMAT,
+            &lut.to_pixels(),
+        )
+    }
+
+    /// Create the pipeline tonemapping the HDR image and give it to `tonemapper`.
+    ///
+    /// The targets of the tonemapper must be set. If `bloom` is set, the
+    /// fragment shader is compiled with the `BLOOM` keyword and the bloom chain
+    /// must be set too.
+    fn create_tonemap_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        shader_variants: &mut ShaderVariants,
+        bloom: bool,
+        tonemapper: &mut Tonemapper,
+    ) {
+        let layout = {
+            let layouts = [tonemapper.layout()];
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .build();
+
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        };
+
+        let keywords: &[&str] = if bloom { &[BLOOM] } else { &[] };
+        let fragment_shader = shader_variants
+            .path("tonemap.frag", keywords)
+            .unwrap_or_else(|error| {
+                log::warn!(
+                    "Failed to compile tonemap.frag with {:?}: {}.",
+                    keywords,
+                    error
+                );
+                "shaders/tonemap.frag.spv".to_owned()
+            });
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, fragment_shader.as_str()),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            vk::SampleCountFlags::TYPE_1,
+            tonemapper.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        tonemapper.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the allocator of per-frame data and its buffers.
+    fn create_transient_allocator(
+        vk_context: &VkContext,
+        image_count: usize,
+    ) -> Result<TransientBufferAllocator, AllocationError> {
+        let buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    TRANSIENT_BUFFER_SIZE,
+                    TransientBufferAllocator::usage(),
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(TransientBufferAllocator::new(vk_context.device(), buffers))
+    }
+
+    /// Create the timestamps of the frames if `--benchmark` or `--profile` is
+    /// passed, or `--dynamic-resolution` which follows the GPU frame time.
+    ///
+    /// The primitives drawn are also counted if `pipeline_statistics` is enabled.
+    fn create_gpu_timer(
+        vk_context: &VkContext,
+        graphics_family_index: u32,
+        image_count: usize,
+        pipeline_statistics: bool,
+    ) -> Option<GpuTimer> {
+        if Self::get_benchmark().is_none()
+            && !std::env::args()
+                .any(|arg| arg == "--profile" || arg.starts_with("--dynamic-resolution"))
+        {
+            return None;
+        }
+        if !gpu_timer::is_supported(
+            vk_context.instance(),
+            vk_context.physical_device(),
+            graphics_family_index,
+        ) {
+            log::warn!("GPU timings requested but timestamps are not supported.");
+            return None;
+        }
+        Some(GpuTimer::new(
+            vk_context.instance(),
+            vk_context.device(),
+            vk_context.physical_device(),
+            graphics_family_index,
+            image_count,
+            pipeline_statistics,
+        ))
+    }
+
+    /// Get the file the GPU crash dump is written to if `--gpu-crash-dump` is passed.
+    ///
+    /// It is `gpu_crash.txt` unless given with `--gpu-crash-dump=<path>`.
+    fn get_gpu_crash_dump_path() -> Option<PathBuf> {
+        std::env::args().find_map(|arg| {
+            if arg == "--gpu-crash-dump" {
+                Some(PathBuf::from("gpu_crash.txt"))
+            } else {
+                arg.strip_prefix("--gpu-crash-dump=").map(PathBuf::from)
+            }
+        })
+    }
+
+    /// Create the GPU crash dump if `--gpu-crash-dump` is passed, with the
+    /// buffers of the markers if they are written with `crash_markers`.
+    fn create_gpu_crash_dump(
+        vk_context: &VkContext,
+        crash_markers: Option<MarkerExtension>,
+        image_count: usize,
+    ) -> Result<Option<GpuCrashDump>, AllocationError> {
+        let path = match Self::get_gpu_crash_dump_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let marker_buffers = if crash_markers == Some(MarkerExtension::BufferMarkers) {
+            (0..image_count)
+                .map(|_| {
+                    Self::create_buffer(
+                        vk_context,
+                        GpuCrashDump::marker_buffer_size(),
+                        vk::BufferUsageFlags::TRANSFER_DST,
+                        vk::MemoryPropertyFlags::HOST_VISIBLE
+                            | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    )
+                })
+                .collect::<Result<_, _>>()?
+        } else {
+            Vec::new()
+        };
+        Ok(Some(GpuCrashDump::new(
+            (vk_context.instance(), vk_context.device()),
+            crash_markers,
+            marker_buffers,
+            path,
+        )))
+    }
+
+    /// Create the debug draw and its buffers.
+    fn create_debug_draw(
+        vk_context: &VkContext,
+        image_count: usize,
+    ) -> Result<DebugDraw, AllocationError> {
+        let indirect_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    DebugDraw::indirect_buffer_size(),
+                    vk::BufferUsageFlags::INDIRECT_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(DebugDraw::new(vk_context.device(), indirect_buffers))
+    }
+
+    /// Create the pipeline drawing the debug lines in the second subpass and give it to `debug_draw`.
+    fn create_debug_draw_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        debug_draw: &mut DebugDraw,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/debug_draw.vert.spv"),
+            (
+                vk::ShaderStageFlags::FRAGMENT,
+                "shaders/debug_draw.frag.spv",
+            ),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[DebugVertex::get_binding_description()],
+                vertex_attribute_descs: &DebugVertex::get_attribute_descriptions(),
+                topology: vk::PrimitiveTopology::LINE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                // Debug lines stay visible through the scene
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 1,
+                layout,
+            },
+        );
+
+        debug_draw.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the occlusion queries of the model and their buffers.
+    ///
+    /// The model is drawn with `index_count` indices and bounded by the
+    /// positions of `vertices`. Its draw is predicated with conditional
+    /// rendering if `conditional_rendering` is enabled on the device, and is
+    /// indirect otherwise.
+    fn create_occlusion_queries(
+        vk_context: &VkContext,
+        conditional_rendering: bool,
+        (vertices, index_count): (&[Vertex], usize),
+        image_count: usize,
+    ) -> Result<OcclusionQueries, AllocationError> {
+        let host_buffer = |size, usage| {
+            Self::create_buffer(
+                vk_context,
+                size,
+                usage,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+        };
+        let mut bounds = math::Aabb::empty();
+        vertices
+            .iter()
+            .for_each(|vertex| bounds.expand(Point3::from(vertex.pos)));
+        let bounds = [bounds];
+        let draws = [vk::DrawIndexedIndirectCommand {
+            index_count: index_count as _,
+            instance_count: 1,
+            first_index: 0,
+            vertex_offset: 0,
+            first_instance: 0,
+        }];
+
+        let proxy_buffer = host_buffer(
+            OcclusionQueries::proxy_buffer_size(&bounds),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let predicate = if conditional_rendering {
+            OcclusionPredicate::ConditionalRendering {
+                buffer: host_buffer(
+                    OcclusionQueries::predicate_buffer_size(&bounds),
+                    vk::BufferUsageFlags::CONDITIONAL_RENDERING_EXT
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                )?,
+            }
+        } else {
+            OcclusionPredicate::Readback {
+                draw_buffers: (0..image_count)
+                    .map(|_| {
+                        host_buffer(
+                            OcclusionQueries::draw_buffer_size(&bounds),
+                            vk::BufferUsageFlags::INDIRECT_BUFFER,
+                        )
+                    })
+                    .collect::<Result<_, _>>()?,
+            }
+        };
+        log::debug!(
+            "Occluded draws skipped using {}.",
+            if conditional_rendering {
+                "conditional rendering"
+            } else {
+                "query readbacks"
+            }
+        );
+
+        Ok(OcclusionQueries::new(
+            vk_context.instance(),
+            vk_context.device(),
+            (&bounds, &draws),
+            proxy_buffer,
+            image_count,
+            predicate,
+        ))
+    }
+
+    /// Create the pipeline drawing the occlusion proxies and give it to `occlusion_queries`.
+    fn create_occlusion_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        occlusion_queries: &mut OcclusionQueries,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+
+        // Only the depth test matters so there is no fragment shader
+        let shaders = [(
+            vk::ShaderStageFlags::VERTEX,
+            "shaders/occlusion_proxy.vert.spv",
+        )];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[proxy_binding_description()],
+                vertex_attribute_descs: &proxy_attribute_descriptions(),
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: false,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        occlusion_queries.set_pipeline(pipeline, layout);
+    }
+
+    /// Create a few decals, their texture and their uniform buffer.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the memory of the texture or of the buffer cannot be allocated.
+    fn create_decals(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+    ) -> Result<Decals, AllocationError> {
+        let mut texture = Self::create_texture_from_pixels(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::Extent2D {
+                width: DECAL_TEXTURE_SIZE,
+                height: DECAL_TEXTURE_SIZE,
+            },
+            &generate_decal_texture(),
+            ColorSpace::Srgb,
+        )?;
+        let uniform_buffer = match Self::create_buffer(
+            vk_context,
+            decal::uniform_buffer_size(),
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        ) {
+            Ok(buffer) => buffer,
+            Err(error) => {
+                texture.destroy(vk_context.device());
+                return Err(error);
+            }
+        };
+
+        let decals = [
+            Decal {
+                position: Point3::new(0.0, 0.0, 0.0),
+                size: Vector3::new(1.2, 2.0, 1.2),
+                angle: Deg(0.0),
+                color: [0.8, 0.1, 0.1, 0.9],
+            },
+            Decal {
+                position: Point3::new(0.6, 0.0, 0.4),
+                size: Vector3::new(0.5, 2.0, 0.5),
+                angle: Deg(30.0),
+                color: [0.1, 0.7, 0.2, 0.9],
+            },
+            Decal {
+                position: Point3::new(-0.5, 0.0, -0.3),
+                size: Vector3::new(0.8, 2.0, 0.8),
+                angle: Deg(75.0),
+                color: [0.1, 0.3, 0.9, 0.9],
+            },
+        ];
+
+        Ok(Decals::new(
+            vk_context.device(),
+            &decals,
+            uniform_buffer,
+            texture,
+        ))
+    }
+
+    /// Create the pipeline drawing `decals` in the second subpass and give it to the decals.
+    fn create_decals_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        decals: &mut Decals,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[decals.layout()]);
+
+        // The depth input attachment is multisampled along with the color attachment
+        let fragment_shader = if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            "shaders/decal.frag.spv"
+        } else {
+            "shaders/decal_ms.frag.spv"
+        };
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/decal.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, fragment_shader),
+        ];
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                // Draw the inner faces so the decals are still drawn when the camera is inside
+                cull_mode: vk::CullModeFlags::FRONT,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                // The depth is read from the input attachment instead
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: true,
+                dynamic_viewport: false,
+                subpass: 1,
+                layout,
+            },
+        );
+
+        decals.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the water plane, its reflection render target and its uniform buffers.
+    ///
+    /// `color_format` is the format of the reflection, it should match the
+    /// format of the swapchain images.
+    fn create_water(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        color_format: vk::Format,
+        image_count: usize,
+    ) -> Result<Water, AllocationError> {
+        let extent = vk::Extent2D {
+            width: REFLECTION_SIZE,
+            height: REFLECTION_SIZE,
+        };
+        let reflection = Self::create_render_target(
+            vk_context,
+            RenderTargetDesc {
+                color_formats: vec![color_format],
+                depth_format: Some(Self::find_depth_format(vk_context)),
+                sample_depth: false,
+            },
+            extent,
+        )?;
+
+        let normal_map = Self::create_texture_from_pixels(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            vk::Extent2D {
+                width: NORMAL_MAP_SIZE,
+                height: NORMAL_MAP_SIZE,
+            },
+            &generate_normal_map(),
+            ColorSpace::Linear,
+        )?;
+
+        Ok(Water::new(
+            vk_context.device(),
+            descriptor_set_layout,
+            texture,
+            reflection,
+            normal_map,
+            Self::create_uniform_buffers(vk_context, image_count)?,
+        ))
+    }
+
+    /// Create the reflection probes placed around the model and capture them.
+    ///
+    /// The model is drawn in each face of the probes with `texture`, like in
+    /// the reflection of the water. The faces have their own frame uniforms,
+    /// written from `model` and `fog`, and their own pipeline. They are all
+    /// destroyed once the capture is complete.
+    fn create_reflection_probes(
+        vk_context: &VkContext,
+        (command_pool, queue): (vk::CommandPool, vk::Queue),
+        (frame_layout, swapchain_properties): (&FrameLayout, SwapchainProperties),
+        (descriptor_set_layout, texture): (vk::DescriptorSetLayout, Texture),
+        (vertex_buffer, index_buffer, index_count): (Buffer, Buffer, u32),
+        (model, fog): (Matrix4<f32>, Fog),
+        image_count: usize,
+    ) -> Result<ReflectionProbes, AllocationError> {
+        let device = vk_context.device();
+        let probes = Self::get_reflection_probes();
+        let depth_format = Self::find_depth_format(vk_context);
+        let faces = Self::create_texture(
+            vk_context,
+            &ReflectionProbes::faces_desc(probes.len()),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let depth = Self::create_texture(
+            vk_context,
+            &ReflectionProbes::depth_desc(depth_format),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<ProbeUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        let mut reflection_probes = ReflectionProbes::new(
+            device,
+            probes,
+            faces,
+            (depth, depth_format),
+            uniform_buffers,
+        );
+
+        // Write the frame uniforms of each face
+        let extent = ReflectionProbes::extent();
+        let frame_ubo = UniformBufferObject {
+            model,
+            view: Matrix4::identity(),
+            proj: Matrix4::identity(),
+            camera_position: [0.0; 4],
+            fog: fog.into(),
+            inverse_view_proj: Matrix4::identity(),
+            inverse_view: Matrix4::identity(),
+            inverse_proj: Matrix4::identity(),
+            screen_size: [extent.width as _, extent.height as _],
+            time: 0.0,
+            light_count: 1,
+            ambient: [[0.0; 4]; SH_COEFFICIENT_COUNT],
+            shadow_view_proj: Matrix4::identity(),
+            inverse_model: model.invert().unwrap_or_else(Matrix4::identity),
+            sun_direction: [0.0, 1.0, 0.0, 0.0],
+            spot_lights: SpotLightsUniform::default(),
+            shadow_filter: ShadowFilter::Pcf as _,
+            sun_penumbra_scale: 0.0,
+            sun_shadow_samples: 1,
+            sun_softness: 0.0,
+            clip_planes: [[0.0; 4]; MAX_CLIP_PLANES],
+            clip_plane_count: 0,
+        };
+        let face_ubos = reflection_probes
+            .probes()
+            .iter()
+            .flat_map(|probe| {
+                (0..6).map(move |face| {
+                    let (view, proj) = probe.face_camera(face);
+                    frame_ubo.with_camera(view, proj, probe.position)
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut face_uniform_buffers = Self::create_uniform_buffers(vk_context, face_ubos.len())?;
+        for (buffer, ubo) in face_uniform_buffers.iter().zip(face_ubos.iter()) {
+            buffer.write(device, 0, &[*ubo]);
+        }
+        let mut descriptor_allocator = Self::create_descriptor_allocator(face_ubos.len() as _);
+        let face_sets = Self::create_descriptor_sets(
+            device,
+            &mut descriptor_allocator,
+            descriptor_set_layout,
+            &face_uniform_buffers,
+            texture,
+        );
+
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/shader.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/shader.frag.spv"),
+        ];
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            SwapchainProperties {
+                extent,
+                ..swapchain_properties
+            },
+            vk::SampleCountFlags::TYPE_1,
+            reflection_probes.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::BACK,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        Self::execute_one_time_commands(device, command_pool, queue, |command_buffer| {
+            reflection_probes.cmd_capture(device, command_buffer, |command_buffer, face| unsafe {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    index_buffer.buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    layout,
+                    FRAME_SET,
+                    &face_sets[face..=face],
+                    &[],
+                );
+                device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+            });
+        });
+
+        unsafe {
+            lifetime::destroy_pipeline(device, pipeline);
+            device.destroy_pipeline_layout(layout, None);
+        }
+        descriptor_allocator.destroy(device);
+        face_uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        reflection_probes.destroy_capture_targets(device);
+        Ok(reflection_probes)
+    }
+
+    /// Get the reflection probes placed on each side of the model.
+    fn get_reflection_probes() -> Vec<ReflectionProbe> {
+        vec![
+            ReflectionProbe {
+                position: Point3::new(2.0, 0.5, 0.0),
+                radius: 4.0,
+            },
+            ReflectionProbe {
+                position: Point3::new(-2.0, 0.5, 0.0),
+                radius: 4.0,
+            },
+        ]
+    }
+
+    /// Bake the lightmap of the model placed by `model` and lit by the sun
+    /// coming from `sun_direction`.
+    ///
+    /// The bake is recorded and waited for at once, then everything but the
+    /// lightmap and its coordinates is destroyed.
+    fn bake_lightmap(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        swapchain_properties: SwapchainProperties,
+        vertices: &[Vertex],
+        (vertex_buffer, index_buffer, index_count): (Buffer, Buffer, u32),
+        (model, sun_direction): (Matrix4<f32>, Vector3<f32>),
+    ) -> Result<Lightmap, AllocationError> {
+        let device = vk_context.device();
+        let lightmap_coords = Self::create_device_local_buffer_with_data::<u32, _>(
+            vk_context,
+            command_pool,
+            queue,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &Self::generate_lightmap_coords(vertices),
+        )?;
+
+        let depth_format = Self::find_depth_format(vk_context);
+        let visibility = Self::create_texture(
+            vk_context,
+            &LightmapBaker::visibility_desc(),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let visibility_depth = Self::create_texture(
+            vk_context,
+            &LightmapBaker::visibility_depth_desc(depth_format),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let lightmap_target = || {
+            Self::create_render_target(
+                vk_context,
+                RenderTargetDesc {
+                    color_formats: vec![LIGHTMAP_FORMAT],
+                    depth_format: None,
+                    sample_depth: false,
+                },
+                LightmapBaker::lightmap_extent(),
+            )
+        };
+        let uniform_buffer = Self::create_buffer(
+            vk_context,
+            size_of::<BakeUniforms>() as _,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mut baker = LightmapBaker::new(
+            device,
+            visibility,
+            (visibility_depth, depth_format),
+            (lightmap_target()?, lightmap_target()?),
+            uniform_buffer,
+        );
+        Self::create_lightmap_pipelines(device, swapchain_properties, &mut baker);
+
+        let mut bounds = math::Aabb::empty();
+        vertices.iter().for_each(|vertex| {
+            let position = model * Point3::from(vertex.pos).to_homogeneous();
+            bounds.expand(Point3::from_homogeneous(position));
+        });
+        baker.update(
+            device,
+            lightmap::bake_uniforms(model, bounds, sun_direction),
+        );
+
+        Self::execute_one_time_commands(device, command_pool, queue, |command_buffer| {
+            baker.cmd_bake(
+                device,
+                command_buffer,
+                (vertex_buffer, lightmap_coords),
+                index_buffer,
+                index_count,
+            );
+        });
+
+        Ok(baker.finish(device, lightmap_coords))
+    }
+
+    /// Get the lightmap coordinates of `vertices`, the second UV set of the model.
+    ///
+    /// The texture atlas of the model does not overlap itself, so its
+    /// coordinates are reused. A model whose texture is tiled or mirrored would
+    /// need them to be generated by unwrapping its charts instead.
+    fn generate_lightmap_coords(vertices: &[Vertex]) -> Vec<[f32; 2]> {
+        vertices.iter().map(|vertex| vertex.coords).collect()
+    }
+
+    /// Create the pipelines of the lightmap bake and give them to `baker`.
+    fn create_lightmap_pipelines(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        baker: &mut LightmapBaker,
+    ) {
+        let layout = baker.pipeline_layout();
+        let vertex_binding_descs = [
+            Vertex::get_binding_description(),
+            lightmap_coords_binding_description(),
+        ];
+        let mut vertex_attribute_descs = Vertex::get_attribute_descriptions().to_vec();
+        vertex_attribute_descs.push(lightmap_coords_attribute_description());
+        let create_pipeline = |extent, render_pass, shaders: &[_], model_input, depth_test| {
+            let (vertex_binding_descs, vertex_attribute_descs): (&[_], &[_]) = if model_input {
+                (&vertex_binding_descs, &vertex_attribute_descs)
+            } else {
+                (&[], &[])
+            };
+            Self::create_graphics_pipeline(
+                device,
+                SwapchainProperties {
+                    extent,
+                    ..swapchain_properties
+                },
+                vk::SampleCountFlags::TYPE_1,
+                render_pass,
+                &GraphicsPipelineDesc {
+                    shaders,
+                    vertex_binding_descs,
+                    vertex_attribute_descs,
+                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    patch_control_points: None,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                    depth_bias: DepthBiasState::Disabled,
+                    depth_test,
+                    writes: true,
+                    stencil: None,
+                    alpha_blending: false,
+                    dynamic_viewport: false,
+                    subpass: 0,
+                    layout,
+                },
+            )
+        };
+
+        let visibility = create_pipeline(
+            LightmapBaker::visibility_extent(),
+            baker.visibility_render_pass(),
+            &VISIBILITY_SHADERS,
+            true,
+            true,
+        );
+        let bake = create_pipeline(
+            LightmapBaker::lightmap_extent(),
+            baker.bake_render_pass(),
+            &BAKE_SHADERS,
+            true,
+            false,
+        );
+        let dilate = create_pipeline(
+            LightmapBaker::lightmap_extent(),
+            baker.dilate_render_pass(),
+            &DILATE_SHADERS,
+            false,
+            false,
+        );
+        baker.set_pipelines(visibility, bake, dilate);
+    }
+
+    /// Create the shadow map of the sun, fitted around the bounds of `vertices`.
+    ///
+    /// With the variance `filter`, the map stores the moments of the depth and
+    /// gets the targets of their blur.
+    fn create_shadow_map(
+        vk_context: &VkContext,
+        vertices: &[Vertex],
+        filter: ShadowFilter,
+    ) -> Result<ShadowMap, AllocationError> {
+        let mut caster_bounds = math::Aabb::empty();
+        vertices
+            .iter()
+            .for_each(|vertex| caster_bounds.expand(Point3::from(vertex.pos)));
+
+        let depth_format = Self::find_depth_format(vk_context);
+        let color = Self::create_texture(
+            vk_context,
+            &ShadowMap::color_desc(filter),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let depth = Self::create_texture(
+            vk_context,
+            &ShadowMap::depth_desc(depth_format),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let blur_target = || {
+            Self::create_render_target(
+                vk_context,
+                RenderTargetDesc {
+                    color_formats: vec![SHADOW_MOMENTS_FORMAT],
+                    depth_format: None,
+                    sample_depth: false,
+                },
+                ShadowMap::extent(),
+            )
+        };
+        let blur_targets = if filter == ShadowFilter::Variance {
+            Some((blur_target()?, blur_target()?))
+        } else {
+            None
+        };
+        Ok(ShadowMap::new(
+            vk_context.device(),
+            caster_bounds,
+            color,
+            (depth, depth_format),
+            blur_targets,
+        ))
+    }
+
+    /// Create the shadow atlas of `spot_lights`.
+    fn create_shadow_atlas(
+        vk_context: &VkContext,
+        spot_lights: &[SpotLightItem],
+    ) -> Result<ShadowAtlas, AllocationError> {
+        let depth_format = Self::find_depth_format(vk_context);
+        let color = Self::create_texture(
+            vk_context,
+            &ShadowAtlas::color_desc(),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let depth = Self::create_texture(
+            vk_context,
+            &ShadowAtlas::depth_desc(depth_format),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        Ok(ShadowAtlas::new(
+            vk_context.device(),
+            spot_lights,
+            color,
+            (depth, depth_format),
+        ))
+    }
+
+    /// Create the pipeline drawing the model in the tiles of `shadow_atlas` and give it to it.
+    fn create_shadow_atlas_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        frame_layout: &FrameLayout,
+        shadow_atlas: &mut ShadowAtlas,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            SwapchainProperties {
+                extent: ShadowAtlas::extent(),
+                ..swapchain_properties
+            },
+            vk::SampleCountFlags::TYPE_1,
+            shadow_atlas.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &[
+                    (
+                        vk::ShaderStageFlags::VERTEX,
+                        "shaders/shadow_atlas_caster.vert.spv",
+                    ),
+                    (
+                        vk::ShaderStageFlags::FRAGMENT,
+                        "shaders/shadow_caster.frag.spv",
+                    ),
+                ],
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Dynamic,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                // Each light draws in its own tile
+                dynamic_viewport: true,
+                subpass: 0,
+                layout,
+            },
+        );
+        shadow_atlas.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the pipeline drawing the model in `shadow_map` and give it to it,
+    /// with the pipeline of the blur of its moments if it stores them.
+    fn create_shadow_map_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        frame_layout: &FrameLayout,
+        shadow_map: &mut ShadowMap,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let fragment_shader = if shadow_map.stores_moments() {
+            "shaders/shadow_moments.frag.spv"
+        } else {
+            "shaders/shadow_caster.frag.spv"
+        };
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            SwapchainProperties {
+                extent: ShadowMap::extent(),
+                ..swapchain_properties
+            },
+            vk::SampleCountFlags::TYPE_1,
+            shadow_map.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &[
+                    (
+                        vk::ShaderStageFlags::VERTEX,
+                        "shaders/shadow_caster.vert.spv",
+                    ),
+                    (vk::ShaderStageFlags::FRAGMENT, fragment_shader),
+                ],
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                // The model is not closed so both faces cast shadows
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: shadow_map
+                    .caster_depth_bias()
+                    .map_or(DepthBiasState::Disabled, DepthBiasState::Static),
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+        shadow_map.set_pipeline(pipeline, layout);
+
+        if let Some(blur) = shadow_map.blur_mut() {
+            Self::create_gaussian_blur_pipeline(device, swapchain_properties, blur);
+        }
+    }
+
+    /// Create the pipeline of both passes of `blur` and give it to it.
+    fn create_gaussian_blur_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        blur: &mut GaussianBlur,
+    ) {
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            SwapchainProperties {
+                extent: blur.extent(),
+                ..swapchain_properties
+            },
+            vk::SampleCountFlags::TYPE_1,
+            blur.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &BLUR_SHADERS,
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout: blur.pipeline_layout(),
+            },
+        );
+        blur.set_pipeline(pipeline);
+    }
+
+    /// Create the secondary views of the scene and their uniform buffers.
+    fn create_secondary_views(
+        vk_context: &VkContext,
+        views: Vec<SecondaryView>,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        image_count: usize,
+    ) -> Result<SecondaryViews, AllocationError> {
+        let uniform_buffers = (0..views.len() * image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<UniformBufferObject>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(SecondaryViews::new(
+            vk_context.device(),
+            views,
+            descriptor_set_layout,
+            texture,
+            uniform_buffers,
+        ))
+    }
+
+    /// Create the mirror, the vertex buffer of its quad and its uniform buffers.
+    fn create_mirror(
+        vk_context: &VkContext,
+        (command_pool, transfer_queue): (vk::CommandPool, vk::Queue),
+        quad: MirrorQuad,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        image_count: usize,
+    ) -> Result<Mirror, AllocationError> {
+        let vertices = quad
+            .triangles()
+            .iter()
+            .map(|position| Vertex {
+                pos: (*position).into(),
+                color: [1.0; 3],
+                coords: [0.0; 2],
+                normal: quad.normal.into(),
+            })
+            .collect::<Vec<_>>();
+        let vertex_buffer =
+            Self::create_vertex_buffer(vk_context, command_pool, transfer_queue, &vertices)?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<UniformBufferObject>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Mirror::new(
+            vk_context.device(),
+            (quad, vertex_buffer),
+            descriptor_set_layout,
+            texture,
+            uniform_buffers,
+        ))
+    }
+
+    /// Create the pipelines drawing the mask, the reflection and the surface of
+    /// `mirror` and give them to it.
+    fn create_mirror_pipelines(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        mirror: &mut Mirror,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let mirror_shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/mirror.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/mirror.frag.spv"),
+        ];
+        let model_shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/shader.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/shader.frag.spv"),
+        ];
+        let stencil = |compare_op, pass_op, write_mask| vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op,
+            compare_mask: 0xff,
+            write_mask,
+            reference: MIRROR_STENCIL_REFERENCE,
+        };
+        let create_pipeline = |shaders, front_face, depth_test, stencil, alpha_blending| {
+            Self::create_graphics_pipeline(
+                device,
+                swapchain_properties,
+                msaa_samples,
+                render_pass,
+                &GraphicsPipelineDesc {
+                    shaders,
+                    vertex_binding_descs: &vertex_binding_descs,
+                    vertex_attribute_descs: &vertex_attribute_descs,
+                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    patch_control_points: None,
+                    cull_mode: vk::CullModeFlags::BACK,
+                    front_face,
+                    depth_bias: DepthBiasState::Disabled,
+                    depth_test,
+                    // Only the mask writes neither the color nor the depth
+                    writes: depth_test,
+                    stencil,
+                    alpha_blending,
+                    dynamic_viewport: false,
+                    subpass: 0,
+                    layout,
+                },
+            )
+        };
+
+        let mask = create_pipeline(
+            &mirror_shaders,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            false,
+            Some(stencil(vk::CompareOp::ALWAYS, vk::StencilOp::REPLACE, 0xff)),
+            false,
+        );
+        // The reflection mirrors the winding of the triangles
+        let reflection = create_pipeline(
+            &model_shaders,
+            vk::FrontFace::CLOCKWISE,
+            true,
+            Some(stencil(vk::CompareOp::EQUAL, vk::StencilOp::KEEP, 0)),
+            false,
+        );
+        let surface = create_pipeline(
+            &mirror_shaders,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            true,
+            None,
+            true,
+        );
+
+        mirror.set_pipelines(
+            MirrorPipelines {
+                mask,
+                reflection,
+                surface,
+            },
+            layout,
+            swapchain_properties.extent,
+        );
+    }
+
+    /// Create the pipeline drawing the model in the secondary views and give it to `secondary_views`.
+    fn create_secondary_views_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        secondary_views: &mut SecondaryViews,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/shader.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/shader.frag.spv"),
+        ];
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::BACK,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: true,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        secondary_views.set_pipeline(pipeline, layout, swapchain_properties.extent);
+    }
+
+    /// Create the stereo rendering of the model, its attachments and its uniform buffers.
+    ///
+    /// The eyes are rendered in `color_format`, the format of the scene, and
+    /// `depth_format`. Each eye covers half of `extent`.
+    fn create_stereo(
+        vk_context: &VkContext,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        (color_format, depth_format): (vk::Format, vk::Format),
+        extent: vk::Extent2D,
+        image_count: usize,
+    ) -> Result<Stereo, AllocationError> {
+        let eye_extent = eye_extent(extent);
+        let (color, depth) =
+            Self::create_stereo_textures(vk_context, (color_format, depth_format), eye_extent)?;
+        let uniform_buffers = (0..image_count)
+            .map(|_| {
+                Self::create_buffer(
+                    vk_context,
+                    size_of::<StereoUniforms>() as _,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Stereo::new(
+            vk_context.device(),
+            descriptor_set_layout,
+            texture,
+            (color, color_format),
+            (depth, depth_format),
+            eye_extent,
+            uniform_buffers,
+        ))
+    }
+
+    /// Create the layered color and depth attachments of the eyes.
+    fn create_stereo_textures(
+        vk_context: &VkContext,
+        (color_format, depth_format): (vk::Format, vk::Format),
+        eye_extent: vk::Extent2D,
+    ) -> Result<(Texture, Texture), AllocationError> {
+        let color_desc = TextureDesc::new_2d_array(
+            eye_extent,
+            EYE_COUNT,
+            color_format,
+            // Transfer source to be copied to the headset
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+        );
+        let depth_desc = TextureDesc::new_2d_array(
+            eye_extent,
+            EYE_COUNT,
+            depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        );
+        Ok((
+            Self::create_texture(
+                vk_context,
+                &color_desc,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?,
+            Self::create_texture(
+                vk_context,
+                &depth_desc,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?,
+        ))
+    }
+
+    /// Create the pipelines drawing the eyes and drawing them on screen and give them to `stereo`.
+    fn create_stereo_pipelines(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        stereo: &mut Stereo,
+    ) {
+        let eye_layout = frame_layout.create_pipeline_layout(device, &[]);
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let eye_pipeline = Self::create_graphics_pipeline(
+            device,
+            SwapchainProperties {
+                extent: stereo.eye_extent(),
+                ..swapchain_properties
+            },
+            vk::SampleCountFlags::TYPE_1,
+            stereo.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &[
+                    (vk::ShaderStageFlags::VERTEX, "shaders/stereo.vert.spv"),
+                    (vk::ShaderStageFlags::FRAGMENT, "shaders/stereo.frag.spv"),
+                ],
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::BACK,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout: eye_layout,
+            },
+        );
+
+        let composite_layout =
+            frame_layout.create_pipeline_layout(device, &[stereo.composite_layout()]);
+        let composite_pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &[
+                    (vk::ShaderStageFlags::VERTEX, "shaders/fullscreen.vert.spv"),
+                    (
+                        vk::ShaderStageFlags::FRAGMENT,
+                        "shaders/stereo_composite.frag.spv",
+                    ),
+                ],
+                vertex_binding_descs: &[],
+                vertex_attribute_descs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: false,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout: composite_layout,
+            },
+        );
+
+        stereo.set_pipelines(
+            (eye_pipeline, eye_layout),
+            (composite_pipeline, composite_layout),
+        );
+    }
+
+    /// Load the OpenXR runtime if `--xr` is passed.
+    ///
+    /// Return the runtime and the Vulkan instance and device extensions it requires,
+    /// or `None` if no runtime or headset is available.
+    #[cfg(feature = "openxr")]
+    fn create_xr_runtime() -> Option<(XrRuntime, Vec<CString>, Vec<CString>)> {
+        if !std::env::args().any(|arg| arg == "--xr") {
+            return None;
+        }
+        let result = XrRuntime::new().and_then(|runtime| {
+            let instance_extensions = runtime.instance_extensions()?;
+            let device_extensions = runtime.device_extensions()?;
+            Ok((runtime, instance_extensions, device_extensions))
+        });
+        match result {
+            Ok(runtime) => Some(runtime),
+            Err(error) => {
+                log::warn!("OpenXR is not available, ignoring it. Cause: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Check that `physical_device` is the device driving the headset of `runtime`.
+    #[cfg(feature = "openxr")]
+    fn is_xr_device(
+        runtime: &XrRuntime,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> bool {
+        match runtime.physical_device(instance) {
+            Ok(device) if device == physical_device => true,
+            Ok(_) => {
+                log::warn!("The headset is driven by another device, ignoring OpenXR.");
+                false
+            }
+            Err(error) => {
+                log::warn!(
+                    "Failed to get the OpenXR device, ignoring OpenXR. Cause: {}",
+                    error
+                );
+                false
+            }
+        }
+    }
+
+    /// Create the session rendering `stereo` to the headset of `runtime`.
+    ///
+    /// Return `None` if there is no runtime, if stereo rendering is not
+    /// supported or if the session cannot be created.
+    #[cfg(feature = "openxr")]
+    fn create_xr_session(
+        runtime: Option<XrRuntime>,
+        vk_context: &VkContext,
+        stereo: Option<&Stereo>,
+        graphics_index: u32,
+        command_pool: vk::CommandPool,
+    ) -> Option<(XrRuntime, XrSession)> {
+        let runtime = runtime?;
+        if stereo.is_none() {
+            log::warn!("Stereo rendering is not supported, ignoring OpenXR.");
+            return None;
+        }
+        match XrSession::new(&runtime, vk_context, graphics_index, command_pool) {
+            Ok(session) => Some((runtime, session)),
+            Err(error) => {
+                log::warn!(
+                    "Failed to create the OpenXR session, ignoring it. Cause: {}",
+                    error
+                );
+                None
+            }
+        }
+    }
+
+    /// Create the secondary window, its surface and its uniform buffer.
+    ///
+    /// Return `None` if the present queue cannot present to the window.
+    fn create_secondary_window(
+        vk_context: &VkContext,
+        events_loop: &EventsLoop,
+        queue_families_indices: QueueFamiliesIndices,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+    ) -> Result<Option<SecondaryWindow>, AllocationError> {
+        let window = WindowBuilder::new()
+            .with_title("Vulkan tutorial with Ash - Top view")
+            .with_dimensions(LogicalSize::new(
+                f64::from(WIDTH / 2),
+                f64::from(HEIGHT / 2),
+            ))
+            .build(events_loop)
+            .unwrap();
+        let (surface, _) = vk_context.surface().unwrap();
+        let surface_khr = unsafe {
+            surface::create_surface(vk_context.entry(), vk_context.instance(), &window).unwrap()
+        };
+
+        let present_support = unsafe {
+            surface.get_physical_device_surface_support(
+                vk_context.physical_device(),
+                queue_families_indices.present_index,
+                surface_khr,
+            )
+        };
+        if !present_support {
+            log::warn!("The present queue cannot present to the secondary window, ignoring it.");
+            unsafe { surface.destroy_surface(surface_khr, None) };
+            return Ok(None);
+        }
+
+        let uniform_buffer = Self::create_buffer(
+            vk_context,
+            size_of::<UniformBufferObject>() as _,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        Ok(Some(SecondaryWindow::new(
+            vk_context.device(),
+            (window, surface_khr),
+            ViewCamera::top_down(4.0),
+            descriptor_set_layout,
+            texture,
+            uniform_buffer,
+        )))
+    }
+
+    /// Create the pipeline drawing the model in the secondary window and give it to `secondary_window`.
+    fn create_secondary_window_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        frame_layout: &FrameLayout,
+        secondary_window: &mut SecondaryWindow,
+    ) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/shader.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/shader.frag.spv"),
+        ];
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            vk::SampleCountFlags::TYPE_1,
+            secondary_window.render_pass(),
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::BACK,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        secondary_window.set_pipeline(pipeline, layout);
+    }
+
+    /// Create the pipelines drawing the reflection of the model and the water
+    /// plane and give them to `water`.
+    fn create_water_pipelines(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+        water: &mut Water,
+    ) {
+        let reflection = {
+            let layout = frame_layout.create_pipeline_layout(device, &[]);
+            let shaders = [
+                (vk::ShaderStageFlags::VERTEX, "shaders/shader.vert.spv"),
+                (vk::ShaderStageFlags::FRAGMENT, "shaders/shader.frag.spv"),
+            ];
+            let vertex_binding_descs = [Vertex::get_binding_description()];
+            let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+            let reflection_properties = SwapchainProperties {
+                extent: water.reflection_extent(),
+                ..swapchain_properties
+            };
+            let pipeline = Self::create_graphics_pipeline(
+                device,
+                reflection_properties,
+                vk::SampleCountFlags::TYPE_1,
+                water.render_pass(),
+                &GraphicsPipelineDesc {
+                    shaders: &shaders,
+                    vertex_binding_descs: &vertex_binding_descs,
+                    vertex_attribute_descs: &vertex_attribute_descs,
+                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    patch_control_points: None,
+                    // Mirroring the scene flips the winding of the triangles
+                    cull_mode: vk::CullModeFlags::FRONT,
+                    front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                    depth_bias: DepthBiasState::Disabled,
+                    depth_test: true,
+                    writes: true,
+                    stencil: None,
+                    alpha_blending: false,
+                    dynamic_viewport: false,
+                    subpass: 0,
+                    layout,
+                },
+            );
+            (pipeline, layout)
+        };
+
+        let plane = {
+            let layout = frame_layout.create_pipeline_layout(device, &[water.layout()]);
+            let shaders = [
+                (vk::ShaderStageFlags::VERTEX, "shaders/water.vert.spv"),
+                (vk::ShaderStageFlags::FRAGMENT, "shaders/water.frag.spv"),
+            ];
+            let pipeline = Self::create_graphics_pipeline(
+                device,
+                swapchain_properties,
+                msaa_samples,
+                render_pass,
+                &GraphicsPipelineDesc {
+                    shaders: &shaders,
+                    vertex_binding_descs: &[],
+                    vertex_attribute_descs: &[],
+                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    patch_control_points: None,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                    depth_bias: DepthBiasState::Disabled,
+                    depth_test: true,
+                    writes: true,
+                    stencil: None,
+                    alpha_blending: false,
+                    dynamic_viewport: false,
+                    subpass: 0,
+                    layout,
+                },
+            );
+            (pipeline, layout)
+        };
+
+        water.set_pipelines(reflection, plane);
+    }
+
+    /// Create the debug pipeline drawing the vertex normals of the model as lines.
+    ///
+    /// The lines are generated by the geometry shader from the triangles of the model.
+    fn create_normals_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/normals.vert.spv"),
+            (vk::ShaderStageFlags::GEOMETRY, "shaders/normals.geom.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/normals.frag.spv"),
+        ];
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil: None,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        (pipeline, layout)
+    }
+
+    /// Create the pipeline drawing the outline of the selected model.
+    ///
+    /// It draws the model inflated along its normals, without depth test so the
+    /// outline stays visible behind other objects, and only where the stencil
+    /// was not written by the model.
+    fn create_outline_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        frame_layout: &FrameLayout,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let layout = frame_layout.create_pipeline_layout(device, &[]);
+
+        let shaders = [
+            (vk::ShaderStageFlags::VERTEX, "shaders/outline.vert.spv"),
+            (vk::ShaderStageFlags::FRAGMENT, "shaders/outline.frag.spv"),
+        ];
+        let vertex_binding_descs = [Vertex::get_binding_description()];
+        let vertex_attribute_descs = Vertex::get_attribute_descriptions();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: vk::CullModeFlags::NONE,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: false,
+                writes: true,
+                stencil: Some(outline_stencil()),
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        (pipeline, layout)
+    }
+
+    /// Create the graphics pipeline.
+    ///
+    /// The parameters of the material instance are read from the set of
+    /// `material_instances` (set 1). The fragment shader is compiled with the
+    /// keywords of their base material.
+    ///
+    /// If `bindless_textures` is set, the pipeline reads its texture from the
+    /// global texture array (set 2) using the material index passed as a push
+    /// constant instead of the sampler from set 0.
+    ///
+    /// If `vertex_pulling` is set, the vertex shader reads the vertices from
+    /// the address of the vertex buffer passed as a push constant instead of
+    /// using vertex attributes.
+    ///
+    /// If `mesh_shading` is set, the vertex shader is replaced by the task and
+    /// mesh shaders which read the meshlets from set 2.
+    ///
+    /// If `reflection_probes` is set, the fragment shader is also compiled with
+    /// the `REFLECTION_PROBES` keyword and blends the probes bound in set 2.
+    ///
+    /// If the base material has the `LIGHTMAP` keyword, the vertex shader is
+    /// compiled with it too and reads the lightmap coordinates from a second
+    /// vertex buffer. The `CLIP_PLANES` keyword only goes to the vertex shader.
+    ///
+    /// The faces are culled as set by the base material.
+    ///
+    /// If `stencil` is set, the pipeline tests and writes the stencil with it.
+    fn create_pipeline(
+        device: &Device,
+        (swapchain_properties, msaa_samples, render_pass): (
+            SwapchainProperties,
+            vk::SampleCountFlags,
+            vk::RenderPass,
+        ),
+        (frame_layout, material_instances, shader_variants): (
+            &FrameLayout,
+            &MaterialInstances,
+            &mut ShaderVariants,
+        ),
+        (bindless_textures, mesh_shading, reflection_probes): (
+            Option<&BindlessTextures>,
+            Option<&MeshShading>,
+            Option<&ReflectionProbes>,
+        ),
+        vertex_pulling: bool,
+        stencil: Option<vk::StencilOpState>,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let fragment_shader = if bindless_textures.is_some() {
+            "bindless.frag"
+        } else {
+            "material.frag"
+        };
+        let mut keywords = material_instances.keywords().to_vec();
+        keywords.retain(|keyword| *keyword != CLIP_PLANES);
+        if reflection_probes.is_some() {
+            keywords.push("REFLECTION_PROBES");
+        }
+        let fragment_shader = shader_variants
+            .path(fragment_shader, &keywords)
+            .unwrap_or_else(|error| {
+                log::warn!(
+                    "Failed to compile {} with {:?}: {}.",
+                    fragment_shader,
+                    keywords,
+                    error
+                );
+                format!("shaders/{}.spv", fragment_shader)
+            });
+
+        let lightmapped = material_instances.keywords().contains(&LIGHTMAP);
+        let vertex_keywords = material_instances
+            .keywords()
+            .iter()
+            .copied()
+            .filter(|keyword| *keyword == LIGHTMAP || *keyword == CLIP_PLANES)
+            .collect::<Vec<_>>();
+        let vertex_shader = if vertex_pulling {
+            "device_address.vert"
+        } else {
+            "shader.vert"
+        };
+        let vertex_shader = if !vertex_keywords.is_empty() {
+            shader_variants
+                .path(vertex_shader, &vertex_keywords)
+                .unwrap_or_else(|error| {
+                    log::warn!(
+                        "Failed to compile {} with {:?}: {}.",
+                        vertex_shader,
+                        vertex_keywords,
+                        error
+                    );
+                    format!("shaders/{}.spv", vertex_shader)
+                })
+        } else {
+            format!("shaders/{}.spv", vertex_shader)
+        };
+
+        let mut shaders = if mesh_shading.is_some() {
+            vec![
+                (vk::ShaderStageFlags::TASK_NV, "shaders/shader.task.spv"),
+                (vk::ShaderStageFlags::MESH_NV, "shaders/shader.mesh.spv"),
+            ]
+        } else {
+            vec![(vk::ShaderStageFlags::VERTEX, vertex_shader.as_str())]
+        };
+        shaders.push((vk::ShaderStageFlags::FRAGMENT, &fragment_shader));
+
+        let mut vertex_binding_descs = Vec::new();
+        let mut vertex_attribute_descs = Vec::new();
+        if !vertex_pulling {
+            vertex_binding_descs.push(Vertex::get_binding_description());
+            vertex_attribute_descs.extend_from_slice(&Vertex::get_attribute_descriptions());
+        }
+        if lightmapped {
+            vertex_binding_descs.push(lightmap_coords_binding_description());
+            vertex_attribute_descs.push(lightmap_coords_attribute_description());
+        }
+
+        let layout = {
+            let mut layouts = vec![material_instances.layout()];
+            if let Some(bindless_textures) = bindless_textures {
+                layouts.push(bindless_textures.layout());
+            }
+            if let Some(mesh_shading) = mesh_shading {
+                layouts.push(mesh_shading.layout());
+            }
+            if let Some(reflection_probes) = reflection_probes {
+                layouts.push(reflection_probes.layout());
+            }
+            frame_layout.create_pipeline_layout(device, &layouts)
+        };
+
+        let culling = material_instances.culling();
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            swapchain_properties,
+            msaa_samples,
+            render_pass,
+            &GraphicsPipelineDesc {
+                shaders: &shaders,
+                vertex_binding_descs: &vertex_binding_descs,
+                vertex_attribute_descs: &vertex_attribute_descs,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                patch_control_points: None,
+                cull_mode: culling.cull_mode.flags(),
+                front_face: culling.front_face,
+                depth_bias: DepthBiasState::Disabled,
+                depth_test: true,
+                writes: true,
+                stencil,
+                alpha_blending: false,
+                dynamic_viewport: false,
+                subpass: 0,
+                layout,
+            },
+        );
+
+        (pipeline, layout)
+    }
+
+    /// Create a graphics pipeline rendering in a subpass of `render_pass`.
+    ///
+    /// Most fixed function states are the same for all the pipelines of the app.
+    /// Only the programmable stages, the vertex input, the culling and winding,
+    /// the depth bias, the depth and stencil tests, the blending, the subpass
+    /// and the layout change.
+    fn create_graphics_pipeline(
+        device: &Device,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+        render_pass: vk::RenderPass,
+        desc: &GraphicsPipelineDesc,
+    ) -> vk::Pipeline {
+        let shader_modules = desc
+            .shaders
+            .iter()
+            .map(|(stage, path)| {
+                let source = Self::read_shader_from_file(path);
+                if *stage == vk::ShaderStageFlags::VERTEX {
+                    Self::validate_vertex_input(path, &source, desc.vertex_attribute_descs);
+                }
+                Self::create_shader_module(device, &source)
+            })
+            .collect::<Vec<_>>();
+
+        let entry_point_name = CString::new("main").unwrap();
+        let shader_states_infos = desc
+            .shaders
+            .iter()
+            .zip(shader_modules.iter())
+            .map(|((stage, _), module)| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(*stage)
+                    .module(*module)
+                    .name(&entry_point_name)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(desc.vertex_binding_descs)
+            .vertex_attribute_descriptions(desc.vertex_attribute_descs)
+            .build();
+
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(desc.topology)
+            .primitive_restart_enable(false)
+            .build();
+
+        let tessellation_info = vk::PipelineTessellationStateCreateInfo::builder()
+            .patch_control_points(desc.patch_control_points.unwrap_or(0))
+            .build();
+
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: swapchain_properties.extent.width as _,
+            height: swapchain_properties.extent.height as _,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let viewports = [viewport];
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: swapchain_properties.extent,
+        };
+        let scissors = [scissor];
+        let viewport_info = if desc.dynamic_viewport {
+            vk::PipelineViewportStateCreateInfo::builder()
+                .viewport_count(1)
+                .scissor_count(1)
+                .build()
+        } else {
+            vk::PipelineViewportStateCreateInfo::builder()
+                .viewports(&viewports)
+                .scissors(&scissors)
+                .build()
+        };
+        let mut dynamic_states = Vec::new();
+        if desc.dynamic_viewport {
+            dynamic_states
+                .extend_from_slice(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+        }
+        if let DepthBiasState::Dynamic = desc.depth_bias {
+            dynamic_states.push(vk::DynamicState::DEPTH_BIAS);
+        }
+        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states)
+            .build();
+
+        let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(desc.cull_mode)
+            .front_face(desc.front_face);
+        let rasterizer_info = match desc.depth_bias {
+            DepthBiasState::Disabled => rasterizer_info.depth_bias_enable(false),
+            DepthBiasState::Static(bias) => rasterizer_info
+                .depth_bias_enable(true)
+                .depth_bias_constant_factor(bias.constant_factor)
+                .depth_bias_slope_factor(bias.slope_factor),
+            DepthBiasState::Dynamic => rasterizer_info.depth_bias_enable(true),
+        }
+        .depth_bias_clamp(0.0)
+        .build();
+
+        let multisampling_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(msaa_samples)
+            .min_sample_shading(1.0)
+            // .sample_mask() // null
+            .alpha_to_coverage_enable(false)
+            .alpha_to_one_enable(false)
+            .build();
+
+        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(desc.depth_test)
+            .depth_write_enable(desc.depth_test && desc.writes)
+            .depth_compare_op(vk::CompareOp::LESS)
+            .depth_bounds_test_enable(false)
+            .min_depth_bounds(0.0)
+            .max_depth_bounds(1.0)
+            .stencil_test_enable(desc.stencil.is_some())
+            .front(desc.stencil.unwrap_or_default())
+            .back(desc.stencil.unwrap_or_default())
+            .build();
+
+        let (src_color_blend_factor, dst_color_blend_factor) = if desc.alpha_blending {
+            (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            )
+        } else {
+            (vk::BlendFactor::ONE, vk::BlendFactor::ZERO)
+        };
+        let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(if desc.writes {
+                vk::ColorComponentFlags::all()
+            } else {
+                vk::ColorComponentFlags::empty()
+            })
+            .blend_enable(desc.alpha_blending)
+            .src_color_blend_factor(src_color_blend_factor)
+            .dst_color_blend_factor(dst_color_blend_factor)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build();
+        let color_blend_attachments = [color_blend_attachment];
+
+        let color_blending_info = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .logic_op(vk::LogicOp::COPY)
+            .attachments(&color_blend_attachments)
+            .blend_constants([0.0, 0.0, 0.0, 0.0])
+            .build();
+
+        let mut pipeline_info_builder = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_states_infos)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_info)
+            .rasterization_state(&rasterizer_info)
+            .multisample_state(&multisampling_info)
+            .depth_stencil_state(&depth_stencil_info)
+            .color_blend_state(&color_blending_info)
+            .layout(desc.layout)
+            .render_pass(render_pass)
+            .subpass(desc.subpass);
+        // .base_pipeline_handle() null since it is not derived from another
+        // .base_pipeline_index(-1) same
+        if desc.patch_control_points.is_some() {
+            pipeline_info_builder = pipeline_info_builder.tessellation_state(&tessellation_info);
+        }
+        if !dynamic_states.is_empty() {
+            pipeline_info_builder = pipeline_info_builder.dynamic_state(&dynamic_state_info);
+        }
+        let pipeline_info = pipeline_info_builder.build();
+        let pipeline_infos = [pipeline_info];
+
+        let pipeline = unsafe {
+            device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_infos, None)
+                .unwrap()[0]
+        };
+        let shaders = desc
+            .shaders
+            .iter()
+            .map(|(_, path)| *path)
+            .collect::<Vec<_>>();
+        lifetime::track(device, ObjectKind::Pipeline, pipeline, shaders.join(", "));
+
+        unsafe {
+            shader_modules
+                .iter()
+                .for_each(|module| device.destroy_shader_module(*module, None));
+        };
+
+        pipeline
+    }
+
+    /// Create the layout of the compute pipelines running the compiled shaders
+    /// at `paths`, with the push constants they read.
+    fn create_compute_pipeline_layout(
+        device: &Device,
+        set_layout: vk::DescriptorSetLayout,
+        paths: &[&str],
+    ) -> vk::PipelineLayout {
+        let shaders = paths
+            .iter()
+            .map(|path| reflect_shader(path))
+            .collect::<Vec<_>>();
+        let layouts = [set_layout];
+        let push_constant_ranges = push_constant_ranges(&shaders);
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&layouts)
+            .push_constant_ranges(&push_constant_ranges)
+            .build();
+        unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+    }
+
+    /// Create a compute pipeline running the compiled shader at `path`.
+    ///
+    /// `constants` are the values of the specialization constants of the
+    /// shader, the constant `i` being `constants[i]`.
+    fn create_compute_pipeline(
+        device: &Device,
+        path: &str,
+        layout: vk::PipelineLayout,
+        constants: &[u32],
+    ) -> vk::Pipeline {
+        let source = Self::read_shader_from_file(path);
+        let module = Self::create_shader_module(device, &source);
+
+        let map_entries = (0..constants.len())
+            .map(|index| vk::SpecializationMapEntry {
+                constant_id: index as _,
+                offset: (index * size_of::<u32>()) as _,
+                size: size_of::<u32>(),
+            })
+            .collect::<Vec<_>>();
+        let data = constants
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect::<Vec<_>>();
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&map_entries)
+            .data(&data)
+            .build();
+
+        let entry_point_name = CString::new("main").unwrap();
+        let shader_state_info = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(&entry_point_name)
+            .specialization_info(&specialization_info)
+            .build();
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(shader_state_info)
+            .layout(layout)
+            .build();
+        let pipeline_infos = [pipeline_info];
+
+        let pipeline = unsafe {
+            device
+                .create_compute_pipelines(vk::PipelineCache::null(), &pipeline_infos, None)
+                .unwrap()[0]
+        };
+        lifetime::track(device, ObjectKind::Pipeline, pipeline, path);
+
+        unsafe { device.destroy_shader_module(module, None) };
+
+        pipeline
+    }
+
+    /// Get `subgroup` if it supports the arithmetic operations in compute shaders.
+    ///
+    /// The shaders of `subgroup.glsl` need the basic and arithmetic operations.
+    fn subgroup_arithmetic(subgroup: Option<SubgroupProperties>) -> Option<SubgroupProperties> {
+        subgroup.filter(|subgroup| {
+            subgroup.supports_compute(
+                vk::SubgroupFeatureFlags::BASIC | vk::SubgroupFeatureFlags::ARITHMETIC,
+            )
+        })
+    }
+
+    fn read_shader_from_file<P: AsRef<std::path::Path>>(path: P) -> Vec<u32> {
+        log::debug!("Loading shader file {}", path.as_ref().to_str().unwrap());
+        let mut cursor = fs::load(path);
+        ash::util::read_spv(&mut cursor).unwrap()
+    }
+
+    /// Check that `attributes` provide the inputs of the vertex shader `code`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if an input is missing or does not match its attribute.
+    fn validate_vertex_input(
+        path: &str,
+        code: &[u32],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) {
+        if let Err(error) =
+            ShaderReflection::new(code).and_then(|shader| shader.validate_vertex_input(attributes))
+        {
+            panic!("Invalid vertex input for shader {}: {}", path, error);
+        }
+    }
+
+    fn create_shader_module(device: &Device, code: &[u32]) -> vk::ShaderModule {
+        let create_info = vk::ShaderModuleCreateInfo::builder().code(code).build();
+        unsafe { device.create_shader_module(&create_info, None).unwrap() }
+    }
+
+    fn create_framebuffers(
+        device: &Device,
+        image_views: &[vk::ImageView],
+        color_texture: Texture,
+        depth_texture: Texture,
+        render_pass: vk::RenderPass,
+        swapchain_properties: SwapchainProperties,
+    ) -> Vec<vk::Framebuffer> {
+        image_views
+            .iter()
+            .map(|view| [color_texture.view, depth_texture.view, *view])
+            .map(|attachments| {
+                let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass)
+                    .attachments(&attachments)
+                    .width(swapchain_properties.extent.width)
+                    .height(swapchain_properties.extent.height)
+                    .layers(1)
+                    .build();
+                unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+            })
+            .collect::<Vec<_>>()
+    }
+
+    fn create_command_pool(
+        device: &Device,
+        queue_families_indices: QueueFamiliesIndices,
+        create_flags: vk::CommandPoolCreateFlags,
+    ) -> vk::CommandPool {
+        let command_pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_families_indices.graphics_index)
+            .flags(create_flags)
+            .build();
+
+        unsafe {
+            device
+                .create_command_pool(&command_pool_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_color_texture(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transition_queue: vk::Queue,
+        swapchain_properties: SwapchainProperties,
+        msaa_samples: vk::SampleCountFlags,
+    ) -> Result<Texture, AllocationError> {
+        let format = swapchain_properties.format.format;
+        let desc = TextureDesc {
+            samples: msaa_samples,
+            ..TextureDesc::new_2d(
+                swapchain_properties.extent,
+                format,
+                vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            )
+        };
+        let texture =
+            Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+        Self::transition_image_layout(
+            vk_context.device(),
+            (command_pool, transition_queue),
+            texture.image,
+            1,
+            format,
+            (
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+        );
+
+        Ok(texture)
+    }
+
+    /// Create the depth buffer texture (image, memory and view).
+    ///
+    /// This function also transitions the image to be ready to be used
+    /// as a depth/stencil attachement.
+    fn create_depth_texture(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transition_queue: vk::Queue,
+        (format, sampled): (vk::Format, bool),
+        extent: vk::Extent2D,
+        msaa_samples: vk::SampleCountFlags,
+    ) -> Result<Texture, AllocationError> {
+        let mut usage =
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT;
+        if sampled {
+            usage |= vk::ImageUsageFlags::SAMPLED;
+        }
+        let desc = TextureDesc {
+            samples: msaa_samples,
+            ..TextureDesc::new_2d(extent, format, usage)
+        };
+        let texture =
+            Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+        Self::transition_image_layout(
+            vk_context.device(),
+            (command_pool, transition_queue),
+            texture.image,
+            1,
+            format,
+            (
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+        );
+
+        Ok(texture)
+    }
+
+    fn find_depth_format(vk_context: &VkContext) -> vk::Format {
+        let candidates = vec![
+            vk::Format::D32_SFLOAT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+        ];
+        vk_context
+            .find_supported_format(
+                &candidates,
+                vk::ImageTiling::OPTIMAL,
+                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+            )
+            .expect("Failed to find a supported depth format")
+    }
+
+    /// Find a depth format with a stencil component.
+    fn find_depth_stencil_format(vk_context: &VkContext) -> vk::Format {
+        let candidates = vec![
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+        ];
+        vk_context
+            .find_supported_format(
+                &candidates,
+                vk::ImageTiling::OPTIMAL,
+                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+            )
+            .expect("Failed to find a supported depth stencil format")
+    }
+
+    fn has_stencil_component(format: vk::Format) -> bool {
+        format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
+    }
+
+    /// Get the aspects of an image of the depth format `format`.
+    fn get_depth_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+        if Self::has_stencil_component(format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        }
+    }
+
+    fn create_texture_image(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        copy_queue: vk::Queue,
+    ) -> Result<Texture, AllocationError> {
+        let (extent, pixels) = Self::load_texture_pixels();
+        Self::create_texture_from_pixels(
+            vk_context,
+            command_pool,
+            copy_queue,
+            extent,
+            &pixels,
+            ColorSpace::Srgb,
+        )
+    }
+
+    /// Load the extent and the RGBA8 pixels of the texture of the model.
+    fn load_texture_pixels() -> (vk::Extent2D, Vec<u8>) {
+        let cursor = fs::load(TEXTURE_PATH);
+        let image = image::load(cursor, image::ImageFormat::JPEG)
+            .unwrap()
+            .flipv();
+        let image_as_rgb = image.to_rgba();
+        let width = (&image_as_rgb).width();
+        let height = (&image_as_rgb).height();
+        let extent = vk::Extent2D { width, height };
+        (extent, image_as_rgb.into_raw())
+    }
+
+    /// Create the texture of the model with only its coarser levels, and the
+    /// streamer uploading the finer ones when the model gets closer.
+    ///
+    /// The levels are stored in the temporary directory, where they are
+    /// written from the texture the first time. If they cannot be, the whole
+    /// texture is loaded and no streamer is created.
+    fn create_streamed_texture(
+        vk_context: &VkContext,
+        queue_family_index: u32,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) -> Result<(Texture, Option<TextureStreamer>), AllocationError> {
+        let directory = std::env::temp_dir().join("vulkan-tutorial-ash-mips");
+        let extent = match texture_streaming::prepare_mip_files(
+            &directory,
+            "chalet",
+            Self::load_texture_pixels,
+        ) {
+            Ok(extent) => extent,
+            Err(error) => {
+                log::warn!("Failed to prepare the streamed texture. Cause: {}", error);
+                let texture = Self::create_texture_image(vk_context, command_pool, queue)?;
+                return Ok((texture, None));
+            }
+        };
+
+        let device = vk_context.device();
+        let format = ColorSpace::Srgb.rgba8_format();
+        let mip_levels = texture_streaming::mip_level_count(extent);
+        let (image, memory) = Self::create_image(
+            vk_context,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            extent,
+            mip_levels,
+            vk::SampleCountFlags::TYPE_1,
+            (
+                format,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::SAMPLED,
+            ),
+        )?;
+        let view = Self::create_image_view(
+            device,
+            image,
+            mip_levels,
+            format,
+            vk::ImageAspectFlags::COLOR,
+        );
+        let sampler = Self::create_texture_sampler(device, mip_levels);
+        let texture = Texture::new(image, memory, view, Some(sampler));
+
+        let host_buffer = |size| {
+            Self::create_buffer(
+                vk_context,
+                size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+        };
+        let streamer = TextureStreamer::new(
+            device,
+            queue_family_index,
+            (directory, "chalet"),
+            (image, extent),
+            host_buffer(TEXTURE_STREAMING_STAGING_SIZE)?,
+        );
+        let pixels = match streamer.read_initial_levels() {
+            Ok(pixels) => pixels,
+            Err(error) => panic!("Failed to read the streamed texture. Cause: {}", error),
+        };
+        let mut staging = host_buffer(pixels.len() as _)?;
+        staging.write(device, 0, &pixels);
+        Self::execute_one_time_commands(device, command_pool, queue, |buffer| {
+            streamer.cmd_upload_initial_levels(device, buffer, staging)
+        });
+        staging.destroy(device);
+
+        Ok((texture, Some(streamer)))
+    }
+
+    /// Create the sampler of the textures with `mip_levels` levels, repeated
+    /// and filtered anisotropically.
+    fn create_texture_sampler(device: &Device, mip_levels: u32) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::REPEAT)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .anisotropy_enable(true)
+            .max_anisotropy(16.0)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .mip_lod_bias(0.0)
+            .min_lod(0.0)
+            .max_lod(mip_levels as _)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    /// Create a sampled texture from RGBA8 `pixels` and generate its mipmaps.
+    ///
+    /// `color_space` tells if the pixels are colors to decode to linear when
+    /// sampled or data to sample as is.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the memory cannot be allocated, nothing is created then.
+    fn create_texture_from_pixels(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        copy_queue: vk::Queue,
+        extent: vk::Extent2D,
+        pixels: &[u8],
+        color_space: ColorSpace,
+    ) -> Result<Texture, AllocationError> {
+        let format = color_space.rgba8_format();
+        let max_mip_levels = ((extent.width.min(extent.height) as f32).log2().floor() + 1.0) as u32;
+        let image_size = size_of_val(pixels) as vk::DeviceSize;
+        let device = vk_context.device();
+
+        let mut buffer = Self::create_buffer(
+            vk_context,
+            image_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        unsafe {
+            let ptr = device
+                .map_memory(buffer.memory, 0, image_size, vk::MemoryMapFlags::empty())
+                .unwrap();
+            let mut align = ash::util::Align::new(ptr, align_of::<u8>() as _, buffer.size);
+            align.copy_from_slice(pixels);
+            device.unmap_memory(buffer.memory);
+        }
+
+        let (image, image_memory) = match Self::create_image(
+            vk_context,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            extent,
+            max_mip_levels,
+            vk::SampleCountFlags::TYPE_1,
+            (
+                format,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::SAMPLED,
+            ),
+        ) {
+            Ok(image) => image,
+            Err(error) => {
+                buffer.destroy(device);
+                return Err(error);
+            }
+        };
+
+        // Transition the image layout and copy the buffer into the image
+        // and transition the layout again to be readable from fragment shader.
+        {
+            Self::transition_image_layout(
+                device,
+                (command_pool, copy_queue),
+                image,
+                max_mip_levels,
+                format,
+                (
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                ),
+            );
+
+            Self::copy_buffer_to_image(
+                device,
+                command_pool,
+                copy_queue,
+                buffer.buffer,
+                image,
+                vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                },
+            );
+
+            Self::generate_mipmaps(
+                vk_context,
+                command_pool,
+                copy_queue,
+                image,
+                extent,
+                format,
+                max_mip_levels,
+            );
+        }
+
+        buffer.destroy(device);
+
+        let image_view = Self::create_image_view(
+            device,
+            image,
+            max_mip_levels,
+            format,
+            vk::ImageAspectFlags::COLOR,
+        );
+
+        let sampler = Self::create_texture_sampler(device, max_mip_levels);
+
+        Ok(Texture::new(image, image_memory, image_view, Some(sampler)))
+    }
+
+    /// Create a 3D texture of `format` from `data` sampled with trilinear filtering.
+    ///
+    /// Texels outside of the texture are clamped to the edges. The texture is
+    /// left in the `SHADER_READ_ONLY_OPTIMAL` layout.
+    fn create_texture_3d_from_data(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        copy_queue: vk::Queue,
+        extent: vk::Extent3D,
+        format: vk::Format,
+        data: &[u8],
+    ) -> Result<Texture, AllocationError> {
+        let device = vk_context.device();
+        let size = data.len() as vk::DeviceSize;
+        let mut buffer = Self::create_buffer(
+            vk_context,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        unsafe {
+            let ptr = device
+                .map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap();
+            let mut align = ash::util::Align::new(ptr, align_of::<u8>() as _, buffer.size);
+            align.copy_from_slice(data);
+            device.unmap_memory(buffer.memory);
+        }
+
+        let desc = TextureDesc::new_3d(
+            extent,
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        );
+        let mut texture =
+            Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+        Self::transition_image_layout(
+            device,
+            (command_pool, copy_queue),
+            texture.image,
+            1,
+            format,
+            (
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+        );
+        Self::copy_buffer_to_image(
+            device,
+            command_pool,
+            copy_queue,
+            buffer.buffer,
+            texture.image,
+            extent,
+        );
+        Self::transition_image_layout(
+            device,
+            (command_pool, copy_queue),
+            texture.image,
+            1,
+            format,
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+        );
+        buffer.destroy(device);
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+        texture.sampler = Some(unsafe { device.create_sampler(&sampler_info, None).unwrap() });
+
+        Ok(texture)
+    }
+
+    /// Create an image and allocate its memory.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the memory cannot be allocated, nothing is created then.
+    fn create_image(
+        vk_context: &VkContext,
+        mem_properties: vk::MemoryPropertyFlags,
+        extent: vk::Extent2D,
+        mip_levels: u32,
+        sample_count: vk::SampleCountFlags,
+        (format, tiling, usage): (vk::Format, vk::ImageTiling, vk::ImageUsageFlags),
+    ) -> Result<(vk::Image, vk::DeviceMemory), AllocationError> {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .format(format)
+            .tiling(tiling)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(sample_count)
+            .flags(vk::ImageCreateFlags::empty())
+            .build();
+
+        Self::create_image_from_info(vk_context, &image_info, mem_properties)
+    }
+
+    /// Create an image from `image_info` and allocate its memory.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the memory cannot be allocated, nothing is created then.
+    fn create_image_from_info(
+        vk_context: &VkContext,
+        image_info: &vk::ImageCreateInfo,
+        mem_properties: vk::MemoryPropertyFlags,
+    ) -> Result<(vk::Image, vk::DeviceMemory), AllocationError> {
+        texture::create_image(vk_context.device(), image_info, |mem_requirements| {
+            let mem_type_index = Self::find_memory_type(
+                mem_requirements,
+                vk_context.get_mem_properties(),
+                mem_properties,
+            );
+            memory::allocate(vk_context, mem_requirements, mem_type_index)
+        })
+    }
+
+    /// Create the texture described by `desc` (image, memory and view).
+    ///
+    /// The image is left in the `UNDEFINED` layout and no sampler is created.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the memory cannot be allocated, nothing is created then.
+    fn create_texture(
+        vk_context: &VkContext,
+        desc: &TextureDesc,
+        mem_properties: vk::MemoryPropertyFlags,
+    ) -> Result<Texture, AllocationError> {
+        let (image, memory) =
+            Self::create_image_from_info(vk_context, &desc.image_create_info(), mem_properties)?;
+        let view = unsafe {
+            vk_context
+                .device()
+                .create_image_view(&desc.view_create_info(image), None)
+                .unwrap()
+        };
+        Ok(Texture::new(image, memory, view, None))
+    }
+
+    /// Create the textures of a render target described by `desc` of `extent`.
+    fn create_render_target_textures(
+        vk_context: &VkContext,
+        desc: &RenderTargetDesc,
+        extent: vk::Extent2D,
+    ) -> Result<(Vec<Texture>, Option<Texture>), AllocationError> {
+        let create =
+            |desc| Self::create_texture(vk_context, &desc, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+        let colors = desc
+            .color_texture_descs(extent)
+            .into_iter()
+            .map(create)
+            .collect::<Result<_, _>>()?;
+        let depth = desc.depth_texture_desc(extent).map(create).transpose()?;
+        Ok((colors, depth))
+    }
+
+    /// Create a render target described by `desc` of `extent`.
+    fn create_render_target(
+        vk_context: &VkContext,
+        desc: RenderTargetDesc,
+        extent: vk::Extent2D,
+    ) -> Result<RenderTarget, AllocationError> {
+        let (colors, depth) = Self::create_render_target_textures(vk_context, &desc, extent)?;
+        Ok(RenderTarget::new(
+            vk_context.device(),
+            desc,
+            extent,
+            colors,
+            depth,
+        ))
+    }
+
+    /// Recreate the textures of `target` with `extent`.
+    ///
+    /// The target must not be in use.
+    fn resize_render_target(
+        vk_context: &VkContext,
+        target: &mut RenderTarget,
+        extent: vk::Extent2D,
+    ) -> Result<(), AllocationError> {
+        let (colors, depth) =
+            Self::create_render_target_textures(vk_context, target.desc(), extent)?;
+        target.resize(vk_context.device(), extent, colors, depth);
+        Ok(())
+    }
+
+    fn transition_image_layout(
+        device: &Device,
+        (command_pool, transition_queue): (vk::CommandPool, vk::Queue),
+        image: vk::Image,
+        mip_levels: u32,
+        format: vk::Format,
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+    ) {
+        Self::execute_one_time_commands(device, command_pool, transition_queue, |buffer| {
+            let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL {
+                let mut mask = vk::ImageAspectFlags::DEPTH;
+                if Self::has_stencil_component(format) {
+                    mask |= vk::ImageAspectFlags::STENCIL;
+                }
+                mask
+            } else {
+                vk::ImageAspectFlags::COLOR
+            };
+
+            let subresource_range = vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: mip_levels,
+                base_array_layer: 0,
+                layer_count: 1,
+            };
+            let barrier = match (old_layout, new_layout) {
+                (
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ) => Barrier::image_sampled_to_attachment(image, subresource_range),
+                (
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ) => Barrier::image_attachment_to_sampled(image, subresource_range),
+                _ => {
+                    let old = ImageState::for_layout(old_layout);
+                    let new = ImageState::for_layout(new_layout);
+                    Barrier::image(
+                        image,
+                        subresource_range,
+                        (old_layout, new_layout),
+                        Access::new(old.stage, old.access),
+                        Access::new(new.stage, new.access),
+                    )
+                }
+            };
+            cmd_barriers(device, buffer, &[barrier]);
+        });
+    }
+
+    fn copy_buffer_to_image(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        transition_queue: vk::Queue,
+        buffer: vk::Buffer,
+        image: vk::Image,
+        extent: vk::Extent3D,
+    ) {
+        Self::execute_one_time_commands(device, command_pool, transition_queue, |command_buffer| {
+            let region = vk::BufferImageCopy::builder()
+                .buffer_offset(0)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(extent)
+                .build();
+            let regions = [region];
+            unsafe {
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                )
+            }
+        })
+    }
+
+    fn generate_mipmaps(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        image: vk::Image,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        mip_levels: u32,
+    ) {
+        let format_properties = unsafe {
+            vk_context
+                .instance()
+                .get_physical_device_format_properties(vk_context.physical_device(), format)
+        };
+        if !format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+        {
+            panic!("Linear blitting is not supported for format {:?}.", format)
+        }
+
+        Self::execute_one_time_commands(
+            vk_context.device(),
+            command_pool,
+            transfer_queue,
+            |buffer| {
+                let device = vk_context.device();
+                let mip = |level| vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: level,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                };
+
+                let mut mip_width = extent.width as i32;
+                let mut mip_height = extent.height as i32;
+                for level in 1..mip_levels {
+                    let next_mip_width = if mip_width > 1 {
+                        mip_width / 2
+                    } else {
                         mip_width
                     };
                     let next_mip_height = if mip_height > 1 {
@@ -1435,806 +8661,3286 @@ impl VulkanApp {
                         mip_height
                     };
 
-                    barrier.subresource_range.base_mip_level = level - 1;
-                    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-                    barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-                    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-                    barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
-                    let barriers = [barrier];
+                    cmd_barriers(
+                        device,
+                        buffer,
+                        &[Barrier::image(
+                            image,
+                            mip(level - 1),
+                            (
+                                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            ),
+                            Access::transfer_write(),
+                            Access::transfer_read(),
+                        )],
+                    );
+
+                    let blit = vk::ImageBlit::builder()
+                        .src_offsets([
+                            vk::Offset3D { x: 0, y: 0, z: 0 },
+                            vk::Offset3D {
+                                x: mip_width,
+                                y: mip_height,
+                                z: 1,
+                            },
+                        ])
+                        .src_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: level - 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .dst_offsets([
+                            vk::Offset3D { x: 0, y: 0, z: 0 },
+                            vk::Offset3D {
+                                x: next_mip_width,
+                                y: next_mip_height,
+                                z: 1,
+                            },
+                        ])
+                        .dst_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: level,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .build();
+                    let blits = [blit];
+
+                    unsafe {
+                        device.cmd_blit_image(
+                            buffer,
+                            image,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            image,
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &blits,
+                            vk::Filter::LINEAR,
+                        )
+                    };
+
+                    cmd_barriers(
+                        device,
+                        buffer,
+                        &[Barrier::image(
+                            image,
+                            mip(level - 1),
+                            (
+                                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                            ),
+                            Access::transfer_read(),
+                            Access::fragment_read(),
+                        )],
+                    );
+
+                    mip_width = next_mip_width;
+                    mip_height = next_mip_height;
+                }
+
+                cmd_barriers(
+                    device,
+                    buffer,
+                    &[Barrier::image(
+                        image,
+                        mip(mip_levels - 1),
+                        (
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        ),
+                        Access::transfer_write(),
+                        Access::fragment_read(),
+                    )],
+                );
+            },
+        );
+    }
+
+    /// Create the scene with the main camera, the model and the sun.
+    ///
+    /// # Returns
+    ///
+    /// Return a tuple containing the scene and the entities of the camera, the model and the sun.
+    fn create_scene(
+        vertices: &[Vertex],
+        indices: &[u32],
+        material: MaterialInstance,
+    ) -> (Scene, Entity, Entity, Entity) {
+        let mut scene = Scene::default();
+
+        let main_camera = scene.spawn_empty();
+        scene.insert(main_camera, Camera::default());
+
+        let model = scene.spawn_empty();
+        scene.insert(
+            model,
+            math::Transform {
+                rotation: Quaternion::from_angle_x(Deg(270.0)),
+                ..Default::default()
+            },
+        );
+        scene.insert(
+            model,
+            MeshRenderer {
+                index_count: indices.len() as _,
+                collision_mesh: Arc::new(math::CollisionMesh::new(
+                    vertices
+                        .iter()
+                        .map(|vertex| Point3::from(vertex.pos))
+                        .collect(),
+                    indices.to_vec(),
+                )),
+                pipeline: 0,
+                material,
+                mesh: 0,
+                transparent: false,
+                layers: Layers::DEFAULT,
+            },
+        );
+
+        let sun = scene.spawn_empty();
+        scene.insert(
+            sun,
+            Light {
+                kind: LightKind::Directional,
+                direction: SkyParameters::default().sun_direction(0.0),
+                softness: 0.02,
+                shadow_samples: 8,
+            },
+        );
+
+        log::debug!("Scene bounds: {:?}", scene.bounds());
+
+        (scene, main_camera, model, sun)
+    }
+
+    /// Add a point light and a spot light around the model to show their gizmos.
+    ///
+    /// The shaders only light the scene with the sun, and with the spot lights
+    /// of `--spot-shadows`.
+    fn spawn_demo_lights(scene: &mut Scene) {
+        let lights = [
+            (
+                Vector3::new(1.5, 1.0, 0.5),
+                Deg(0.0),
+                LightKind::Point { radius: 1.0 },
+            ),
+            (
+                Vector3::new(-1.0, 2.0, -1.0),
+                Deg(20.0),
+                LightKind::Spot {
+                    range: 2.5,
+                    angle: Deg(25.0).into(),
+                },
+            ),
+        ];
+        for (translation, angle, kind) in lights.iter().copied() {
+            let light = scene.spawn_empty();
+            scene.insert(
+                light,
+                math::Transform {
+                    translation,
+                    rotation: Quaternion::from_angle_x(angle),
+                    ..Default::default()
+                },
+            );
+            scene.insert(
+                light,
+                Light {
+                    kind,
+                    direction: Vector3::unit_y(),
+                    softness: 0.1,
+                    shadow_samples: 4,
+                },
+            );
+        }
+    }
+
+    /// Spawn a ring of spot lights of various sizes aimed at the model, to cast
+    /// shadows in the shadow atlas.
+    fn spawn_spot_lights(scene: &mut Scene) {
+        const LIGHT_COUNT: usize = 12;
+        for i in 0..LIGHT_COUNT {
+            let theta = i as f32 / LIGHT_COUNT as f32 * 2.0 * std::f32::consts::PI;
+            let translation = Vector3::new(2.5 * theta.cos(), 2.0, 2.5 * theta.sin());
+            let rotation = Quaternion::from_arc(-Vector3::unit_y(), -translation.normalize(), None);
+            let light = scene.spawn_empty();
+            scene.insert(
+                light,
+                math::Transform {
+                    translation,
+                    rotation,
+                    ..Default::default()
+                },
+            );
+            scene.insert(
+                light,
+                Light {
+                    kind: LightKind::Spot {
+                        range: 3.5 + (i % 3) as f32,
+                        angle: Deg(15.0 + 5.0 * (i % 4) as f32).into(),
+                    },
+                    direction: Vector3::unit_y(),
+                    softness: 0.05 * (1 + i % 3) as f32,
+                    // The larger lights trace more rays to keep their penumbrae smooth
+                    shadow_samples: 4 << (i % 3),
+                },
+            );
+        }
+    }
+
+    fn load_model() -> (Vec<Vertex>, Vec<u32>) {
+        log::debug!("Loading model.");
+        let mut cursor = fs::load(MODEL_PATH);
+        let (models, _) = tobj::load_obj_buf(&mut cursor, |_| {
+            Ok((vec![], std::collections::HashMap::new()))
+        })
+        .unwrap();
+
+        let mesh = &models[0].mesh;
+        let positions = mesh.positions.as_slice();
+        let coords = mesh.texcoords.as_slice();
+        let vertex_count = mesh.positions.len() / 3;
+        let normals = if mesh.normals.is_empty() {
+            log::debug!("Model has no normals, computing them.");
+            compute_normals(positions, &mesh.indices)
+        } else {
+            mesh.normals.clone()
+        };
+
+        let mut vertices = Vec::with_capacity(vertex_count);
+        for i in 0..vertex_count {
+            let x = positions[i * 3];
+            let y = positions[i * 3 + 1];
+            let z = positions[i * 3 + 2];
+            let u = coords[i * 2];
+            let v = coords[i * 2 + 1];
+
+            let vertex = Vertex {
+                pos: [x, y, z],
+                color: [1.0, 1.0, 1.0],
+                coords: [u, v],
+                normal: [normals[i * 3], normals[i * 3 + 1], normals[i * 3 + 2]],
+            };
+            vertices.push(vertex);
+        }
+
+        (vertices, mesh.indices.clone())
+    }
+
+    /// Create the vertex buffer.
+    ///
+    /// The buffer can also be bound as a storage buffer so the mesh shaders
+    /// can read the vertices. If buffer device address is enabled, it can be
+    /// read from the vertex shader using its address.
+    fn create_vertex_buffer(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        vertices: &[Vertex],
+    ) -> Result<Buffer, AllocationError> {
+        let mut usage = if vk_context.buffer_device_address().is_some() {
+            vk::BufferUsageFlags::VERTEX_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS_EXT
+        } else {
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER
+        };
+        // The acceleration structures are built from the model
+        if vk_context.ray_tracing().is_some() {
+            usage |= ray_tracing::build_input_usage();
+        }
+        Self::create_device_local_buffer_with_data::<u32, _>(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            usage,
+            vertices,
+        )
+    }
+
+    fn create_index_buffer(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        indices: &[u32],
+    ) -> Result<Buffer, AllocationError> {
+        let usage = if vk_context.ray_tracing().is_some() {
+            vk::BufferUsageFlags::INDEX_BUFFER | ray_tracing::build_input_usage()
+        } else {
+            vk::BufferUsageFlags::INDEX_BUFFER
+        };
+        Self::create_device_local_buffer_with_data::<u16, _>(
+            vk_context,
+            command_pool,
+            transfer_queue,
+            usage,
+            indices,
+        )
+    }
+
+    /// Create a buffer and it's gpu  memory and fill it.
+    ///
+    /// This function internally creates an host visible staging buffer and
+    /// a device local buffer. The data is first copied from the cpu to the
+    /// staging buffer. Then we copy the data from the staging buffer to the
+    /// final buffer using a one-time command buffer.
+    fn create_device_local_buffer_with_data<A, T: Copy>(
+        vk_context: &VkContext,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        usage: vk::BufferUsageFlags,
+        data: &[T],
+    ) -> Result<Buffer, AllocationError> {
+        let device = vk_context.device();
+        let size = size_of_val(data) as vk::DeviceSize;
+        let mut staging_buffer = Self::create_buffer(
+            vk_context,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        unsafe {
+            let data_ptr = device
+                .map_memory(staging_buffer.memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap();
+            let mut align =
+                ash::util::Align::new(data_ptr, align_of::<A>() as _, staging_buffer.size);
+            align.copy_from_slice(data);
+            device.unmap_memory(staging_buffer.memory);
+        };
+
+        let buffer = Self::create_buffer(
+            vk_context,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST | usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        Self::copy_buffer(
+            device,
+            command_pool,
+            transfer_queue,
+            staging_buffer.buffer,
+            buffer.buffer,
+            staging_buffer.size,
+        );
+
+        staging_buffer.destroy(device);
+
+        Ok(buffer)
+    }
+
+    fn create_uniform_buffers(
+        vk_context: &VkContext,
+        count: usize,
+    ) -> Result<Vec<Buffer>, AllocationError> {
+        let size = size_of::<UniformBufferObject>() as vk::DeviceSize;
+        let mut buffers = Vec::new();
+
+        for _ in 0..count {
+            let buffer = Self::create_buffer(
+                vk_context,
+                size,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            buffers.push(buffer);
+        }
+
+        Ok(buffers)
+    }
+
+    /// Create a buffer and allocate its memory.
+    ///
+    /// If `usage` contains `SHADER_DEVICE_ADDRESS_EXT` the address of the
+    /// buffer is queried once the memory is bound.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the memory cannot be allocated, nothing is created then.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the address is requested but buffer device address is not enabled.
+    fn create_buffer(
+        vk_context: &VkContext,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        mem_properties: vk::MemoryPropertyFlags,
+    ) -> Result<Buffer, AllocationError> {
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+        let buffer = Buffer::create(vk_context.device(), &buffer_info, |mem_requirements| {
+            let mem_type = Self::find_memory_type(
+                mem_requirements,
+                vk_context.get_mem_properties(),
+                mem_properties,
+            );
+            memory::allocate(vk_context, mem_requirements, mem_type)
+        })?;
+
+        let device_address = if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS_EXT) {
+            let buffer_device_address = vk_context
+                .buffer_device_address()
+                .expect("Buffer device address is not enabled");
+            Some(buffer_device_address.get_buffer_device_address(buffer.buffer))
+        } else {
+            None
+        };
+
+        Ok(Buffer::new(
+            buffer.buffer,
+            buffer.memory,
+            buffer.size,
+            device_address,
+        ))
+    }
+
+    /// Copy the `size` first bytes of `src` into `dst`.
+    ///
+    /// It's done using a command buffer allocated from
+    /// `command_pool`. The command buffer is cubmitted tp
+    /// `transfer_queue`.
+    fn copy_buffer(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        src: vk::Buffer,
+        dst: vk::Buffer,
+        size: vk::DeviceSize,
+    ) {
+        Self::execute_one_time_commands(&device, command_pool, transfer_queue, |buffer| {
+            let region = vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size,
+            };
+            let regions = [region];
+
+            unsafe { device.cmd_copy_buffer(buffer, src, dst, &regions) };
+        });
+    }
+
+    /// Create a one time use command buffer and pass it to `executor`.
+    fn execute_one_time_commands<F: FnOnce(vk::CommandBuffer)>(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        executor: F,
+    ) {
+        let command_buffer = {
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_pool(command_pool)
+                .command_buffer_count(1)
+                .build();
+
+            unsafe { device.allocate_command_buffers(&alloc_info).unwrap()[0] }
+        };
+        let command_buffers = [command_buffer];
+
+        // Begin recording
+        {
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build();
+            unsafe {
+                device
+                    .begin_command_buffer(command_buffer, &begin_info)
+                    .unwrap()
+            };
+        }
+
+        // Execute user function
+        executor(command_buffer);
+
+        // End recording
+        unsafe { device.end_command_buffer(command_buffer).unwrap() };
+
+        // Submit and wait
+        {
+            let submit_info = vk::SubmitInfo::builder()
+                .command_buffers(&command_buffers)
+                .build();
+            let submit_infos = [submit_info];
+            unsafe {
+                device
+                    .queue_submit(queue, &submit_infos, vk::Fence::null())
+                    .unwrap();
+                device.queue_wait_idle(queue).unwrap();
+            };
+        }
+
+        // Free
+        unsafe { device.free_command_buffers(command_pool, &command_buffers) };
+    }
+
+    /// Find a memory type in `mem_properties` that is suitable
+    /// for `requirements` and supports `required_properties`.
+    ///
+    /// # Returns
+    ///
+    /// The index of the memory type from `mem_properties`.
+    fn find_memory_type(
+        requirements: vk::MemoryRequirements,
+        mem_properties: vk::PhysicalDeviceMemoryProperties,
+        required_properties: vk::MemoryPropertyFlags,
+    ) -> u32 {
+        for i in 0..mem_properties.memory_type_count {
+            if requirements.memory_type_bits & (1 << i) != 0
+                && mem_properties.memory_types[i as usize]
+                    .property_flags
+                    .contains(required_properties)
+            {
+                return i;
+            }
+        }
+        panic!("Failed to find suitable memory type.")
+    }
+
+    /// Allocate `count` primary command buffers from `pool`.
+    fn allocate_command_buffers(
+        device: &Device,
+        pool: vk::CommandPool,
+        count: usize,
+    ) -> Vec<vk::CommandBuffer> {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(count as _)
+            .build();
+        unsafe { device.allocate_command_buffers(&allocate_info).unwrap() }
+    }
+
+    /// Record the commands drawing into each framebuffer in the command
+    /// buffer of the same index.
+    ///
+    /// The command buffers are reset when their recording begins, so they can
+    /// be recorded again once they are not in use anymore, without being
+    /// reallocated.
+    fn record_command_buffers(
+        device: &Device,
+        buffers: &[vk::CommandBuffer],
+        resources: FrameResources,
+    ) -> Vec<BindStats> {
+        let FrameResources {
+            framebuffers,
+            render_pass,
+            swapchain_properties,
+            vertex_buffer,
+            index_buffer,
+            index_count,
+            pass_layers,
+            model_layers,
+            pipeline_layout,
+            frame_layout,
+            descriptor_sets,
+            bindless_textures,
+            material_index,
+            material_instances,
+            model_material,
+            mesh_shading,
+            displaced_plane,
+            terrain,
+            mesh_streamer,
+            objects,
+            static_batches,
+            gpu_driven,
+            virtual_texture,
+            depth_pyramid,
+            hiz,
+            water,
+            secondary_views,
+            mirror,
+            stereo,
+            sky,
+            background,
+            clear_color,
+            sprites,
+            particles,
+            decals,
+            debug_draw,
+            occlusion_queries,
+            reflection_probes,
+            lightmap,
+            shadow_map,
+            shadow_atlas,
+            transient_allocator,
+            hud,
+            auto_exposure,
+            bloom,
+            tonemapper,
+            ssr,
+            ray_traced_shadows,
+            light_shafts,
+            motion_blur,
+            fsr,
+            gpu_timer,
+            gpu_crash_dump,
+            present_transfer,
+            normals_pipeline,
+            outline_pipeline,
+            graphics_pipeline,
+        } = resources;
+        profile_scope!("record_commands");
+        let mut bind_stats = Vec::with_capacity(buffers.len());
+
+        // The passes which do not draw a layer of the model skip its triangles
+        let pass_index_count = |pass| {
+            if pass_layers.draws(pass, model_layers) {
+                index_count as u32
+            } else {
+                0
+            }
+        };
+        let main_index_count = pass_index_count(Pass::Main);
+        let shadow_index_count = pass_index_count(Pass::Shadow);
+        let reflection_index_count = pass_index_count(Pass::Reflection);
+        let draws_background = pass_layers.draws(Pass::Main, Layers::BACKGROUND);
+        let draws_gizmos = pass_layers.draws(Pass::Main, Layers::GIZMOS);
+
+        buffers.iter().enumerate().for_each(|(i, buffer)| {
+            let buffer = *buffer;
+            let framebuffer = framebuffers[i];
+
+            // begin command buffer
+            {
+                let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
+                    // .inheritance_info() null since it's a primary command buffer
+                    .build();
+                unsafe {
+                    device
+                        .begin_command_buffer(buffer, &command_buffer_begin_info)
+                        .unwrap()
+                };
+            }
+
+            if let Some(gpu_timer) = gpu_timer {
+                gpu_timer.cmd_begin(device, buffer, i);
+            }
+
+            // Mark the start of each pass for the crash dump and the debuggers
+            #[cfg(feature = "debug-markers")]
+            let pass_labels = debug_markers::PassLabels::new(buffer, i);
+            let mark = |pass| {
+                if let Some(gpu_crash_dump) = gpu_crash_dump {
+                    gpu_crash_dump.cmd_mark(buffer, i, pass);
+                }
+                #[cfg(feature = "debug-markers")]
+                pass_labels.cmd_begin_pass(pass);
+            };
+            mark(FramePass::Begin);
+
+            // Render the shadow map of the sun before the main pass samples it
+            if shadow_map.is_some() || shadow_atlas.is_some() {
+                mark(FramePass::Shadows);
+            }
+            if let Some(shadow_map) = shadow_map {
+                shadow_map.cmd_render(
+                    device,
+                    buffer,
+                    descriptor_sets[i],
+                    (vertex_buffer, index_buffer, shadow_index_count),
+                );
+            }
+
+            // Render the shadows of the spot lights in their tiles of the atlas
+            if let Some(shadow_atlas) = shadow_atlas {
+                shadow_atlas.cmd_render(
+                    device,
+                    buffer,
+                    descriptor_sets[i],
+                    (vertex_buffer, index_buffer, shadow_index_count),
+                );
+            }
+
+            // Render the reflection of the model before the main pass samples it
+            if let Some(water) = water {
+                mark(FramePass::Reflection);
+                water.cmd_render_reflection(
+                    device,
+                    buffer,
+                    i,
+                    vertex_buffer,
+                    index_buffer,
+                    reflection_index_count,
+                );
+            }
+
+            // Render both eyes before the main pass draws them
+            if let Some(stereo) = stereo {
+                mark(FramePass::StereoEyes);
+                stereo.cmd_render_eyes(
+                    device,
+                    buffer,
+                    i,
+                    vertex_buffer,
+                    index_buffer,
+                    main_index_count,
+                );
+            }
+
+            // Write the velocity of each pixel for the motion blur
+            if let Some(motion_blur) = motion_blur {
+                mark(FramePass::Velocity);
+                motion_blur.cmd_render_velocity(
+                    device,
+                    buffer,
+                    i,
+                    vertex_buffer,
+                    index_buffer,
+                    main_index_count,
+                );
+            }
+
+            // Cull the terrain chunks hidden behind the depth of the previous frame
+            if hiz.is_some() || gpu_driven.is_some() {
+                mark(FramePass::Culling);
+            }
+            if let Some(hiz) = hiz {
+                hiz.cmd_cull(device, buffer, i);
+            }
+
+            // Cull the GPU-driven instances and write their draws
+            if let Some(gpu_driven) =
+                gpu_driven.filter(|_| pass_layers.draws(Pass::Main, Layers::DEFAULT))
+            {
+                gpu_driven.cmd_cull(device, buffer, i);
+            }
+
+            if let Some(occlusion_queries) = occlusion_queries {
+                occlusion_queries.cmd_reset(device, buffer, i);
+            }
+
+            // Emit, move and sort the particles, sized from the counts of the GPU
+            if let Some(particles) = particles {
+                mark(FramePass::Particles);
+                particles.cmd_simulate(device, buffer, i);
+            }
+
+            // Clear the pages requested by the virtual texture
+            if let Some(virtual_texture) = virtual_texture {
+                virtual_texture.cmd_clear_feedback(device, buffer, i);
+            }
+
+            // begin render pass
+            mark(FramePass::MainPass);
+            {
+                let clear_values = [
+                    vk::ClearValue {
+                        color: vk::ClearColorValue {
+                            float32: [clear_color[0], clear_color[1], clear_color[2], 1.0],
+                        },
+                    },
+                    vk::ClearValue {
+                        depth_stencil: vk::ClearDepthStencilValue {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
+                    },
+                ];
+                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(render_pass)
+                    .framebuffer(framebuffer)
+                    .render_area(vk::Rect2D {
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                        extent: swapchain_properties.extent,
+                    })
+                    .clear_values(&clear_values)
+                    .build();
+
+                unsafe {
+                    device.cmd_begin_render_pass(
+                        buffer,
+                        &render_pass_begin_info,
+                        vk::SubpassContents::INLINE,
+                    )
+                };
+            }
+
+            // Skip the binds of the main pass that do not change anything
+            let mut encoder = CommandEncoder::new(device, buffer);
 
-                    unsafe {
-                        vk_context.device().cmd_pipeline_barrier(
-                            buffer,
-                            vk::PipelineStageFlags::TRANSFER,
-                            vk::PipelineStageFlags::TRANSFER,
-                            vk::DependencyFlags::empty(),
-                            &[],
-                            &[],
-                            &barriers,
-                        )
-                    };
+            // Bind the frame uniforms for all the draws of the pass
+            frame_layout.cmd_bind(&mut encoder, descriptor_sets[i]);
 
-                    let blit = vk::ImageBlit::builder()
-                        .src_offsets([
-                            vk::Offset3D { x: 0, y: 0, z: 0 },
-                            vk::Offset3D {
-                                x: mip_width,
-                                y: mip_height,
-                                z: 1,
-                            },
-                        ])
-                        .src_subresource(vk::ImageSubresourceLayers {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            mip_level: level - 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        })
-                        .dst_offsets([
-                            vk::Offset3D { x: 0, y: 0, z: 0 },
-                            vk::Offset3D {
-                                x: next_mip_width,
-                                y: next_mip_height,
-                                z: 1,
-                            },
-                        ])
-                        .dst_subresource(vk::ImageSubresourceLayers {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            mip_level: level,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        })
-                        .build();
-                    let blits = [blit];
+            // Draw the sky in the background
+            if let Some(sky) = sky.filter(|_| draws_background) {
+                sky.cmd_draw(&mut encoder, i);
+            }
+            if let Some(background) = background.filter(|background| {
+                draws_background && background.mode() == BackgroundMode::Gradient
+            }) {
+                background.cmd_draw(&mut encoder);
+            }
 
-                    unsafe {
-                        vk_context.device().cmd_blit_image(
-                            buffer,
-                            image,
-                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                            image,
-                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                            &blits,
-                            vk::Filter::LINEAR,
-                        )
-                    };
+            // Draw the reflection in the mirror before the geometry in front of it
+            if let Some(mirror) = mirror {
+                mirror.cmd_draw(
+                    &mut encoder,
+                    i,
+                    (vertex_buffer, index_buffer, reflection_index_count),
+                );
+                frame_layout.cmd_bind(&mut encoder, descriptor_sets[i]);
+            }
 
-                    barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-                    barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-                    barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
-                    barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-                    let barriers = [barrier];
+            // Bind pipeline
+            encoder.bind_pipeline(graphics_pipeline);
 
+            // Mesh shaders read both vertices and indices from their own descriptor set
+            if mesh_shading.is_none() {
+                // Bind vertex buffer or push its address if the vertices are pulled from the shader
+                if let Some(vertex_address) = vertex_buffer.device_address() {
                     unsafe {
-                        vk_context.device().cmd_pipeline_barrier(
+                        device.cmd_push_constants(
                             buffer,
-                            vk::PipelineStageFlags::TRANSFER,
-                            vk::PipelineStageFlags::FRAGMENT_SHADER,
-                            vk::DependencyFlags::empty(),
-                            &[],
-                            &[],
-                            &barriers,
+                            pipeline_layout,
+                            vk::ShaderStageFlags::VERTEX,
+                            VERTEX_ADDRESS_PUSH_CONSTANT_OFFSET,
+                            &vertex_address.to_ne_bytes(),
                         )
                     };
+                } else {
+                    encoder.bind_vertex_buffers(0, &[vertex_buffer.buffer], &[0]);
+                }
 
-                    mip_width = next_mip_width;
-                    mip_height = next_mip_height;
+                // Bind index buffer
+                encoder.bind_index_buffer(index_buffer.buffer, 0, vk::IndexType::UINT32);
+
+                // Bind the second UV set sampling the lightmap
+                if let Some(lightmap) = lightmap {
+                    lightmap.cmd_bind_coords(&mut encoder);
                 }
+            }
 
-                barrier.subresource_range.base_mip_level = mip_levels - 1;
-                barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-                barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-                barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-                barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-                let barriers = [barrier];
+            // Bind the parameters of the material instance of the model
+            material_instances.cmd_bind(&mut encoder, pipeline_layout, model_material);
 
+            // Bind the reflection probes blended for the model
+            if let Some(reflection_probes) = reflection_probes {
+                reflection_probes.cmd_bind(&mut encoder, pipeline_layout, i);
+            }
+
+            // Bind the global texture array and push the material index
+            if let Some(bindless_textures) = bindless_textures {
+                encoder.bind_descriptor_sets(
+                    pipeline_layout,
+                    OBJECT_SET,
+                    &[bindless_textures.set()],
+                    &[],
+                );
                 unsafe {
-                    vk_context.device().cmd_pipeline_barrier(
+                    device.cmd_push_constants(
                         buffer,
-                        vk::PipelineStageFlags::TRANSFER,
-                        vk::PipelineStageFlags::FRAGMENT_SHADER,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[],
-                        &barriers,
-                    )
+                        pipeline_layout,
+                        vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        &material_index.to_ne_bytes(),
+                    );
                 };
-            },
-        );
+            }
+
+            // Draw the model if the main pass draws one of its layers
+            if let Some(mesh_shading) = mesh_shading.filter(|_| main_index_count > 0) {
+                encoder.bind_descriptor_sets(
+                    pipeline_layout,
+                    OBJECT_SET,
+                    &[mesh_shading.set()],
+                    &[],
+                );
+                mesh_shading.cmd_draw_meshlets(buffer);
+            } else if let Some(occlusion_queries) =
+                occlusion_queries.filter(|_| main_index_count > 0)
+            {
+                // Skip the model if its proxy was occluded
+                occlusion_queries.cmd_draw(&mut encoder, i, 0);
+            } else if main_index_count > 0 {
+                unsafe { device.cmd_draw_indexed(buffer, main_index_count, 1, 0, 0, 0) };
+            }
+
+            // Draw the tessellated plane
+            if let Some(plane) = displaced_plane {
+                plane.cmd_draw(&mut encoder);
+            }
+
+            // Draw the visible terrain chunks
+            if let Some(terrain) = terrain {
+                terrain.cmd_draw(&mut encoder, i);
+            }
+
+            // Draw the streamed chunks in the geometry pool
+            if let Some(mesh_streamer) = mesh_streamer {
+                mesh_streamer.cmd_draw(&mut encoder, i);
+            }
+
+            // Draw the objects spawned at runtime
+            if let Some(objects) = objects {
+                objects.cmd_draw(&mut encoder, i);
+            }
+
+            // Draw the static objects, merged by material if they are batched
+            if let Some(static_batches) =
+                static_batches.filter(|_| pass_layers.draws(Pass::Main, Layers::DEFAULT))
+            {
+                static_batches.cmd_draw(&mut encoder);
+            }
+
+            // Draw the instances left by the culling, with the draws counted by the GPU
+            if let (Some(gpu_driven), Some(bindless_textures)) = (
+                gpu_driven.filter(|_| pass_layers.draws(Pass::Main, Layers::DEFAULT)),
+                bindless_textures,
+            ) {
+                gpu_driven.cmd_draw(&mut encoder, i, bindless_textures.set());
+            }
+
+            // Draw the plane sampling the resident pages of the virtual texture
+            if let Some(virtual_texture) = virtual_texture {
+                virtual_texture.cmd_draw(&mut encoder, i);
+            }
+
+            // Draw the water plane
+            if let Some(water) = water {
+                water.cmd_draw(&mut encoder, i);
+            }
+
+            // Draw the grid after the opaque geometry since it is blended
+            if let Some(background) = background
+                .filter(|background| draws_background && background.mode() == BackgroundMode::Grid)
+            {
+                background.cmd_draw(&mut encoder);
+            }
+
+            // Test the proxies of the model against the opaque geometry
+            if let Some(occlusion_queries) = occlusion_queries {
+                occlusion_queries.cmd_draw_proxies(&mut encoder, i);
+            }
+
+            // Draw the sprites after the opaque geometry since they are blended
+            if let Some(sprites) = sprites {
+                sprites.cmd_draw(&mut encoder, i);
+            }
+            if let Some(particles) = particles {
+                particles.cmd_draw(&mut encoder);
+            }
+
+            // Draw the normals of the model on top of it
+            if let Some((normals_pipeline, _)) = normals_pipeline.filter(|_| draws_gizmos) {
+                encoder.bind_pipeline(normals_pipeline);
+                encoder.bind_vertex_buffers(0, &[vertex_buffer.buffer], &[0]);
+                encoder.bind_index_buffer(index_buffer.buffer, 0, vk::IndexType::UINT32);
+                unsafe { device.cmd_draw_indexed(buffer, index_count as _, 1, 0, 0, 0) };
+            }
+
+            // Draw the outline of the selected model around it, over the scene
+            if let Some((outline_pipeline, _)) = outline_pipeline.filter(|_| draws_gizmos) {
+                encoder.bind_pipeline(outline_pipeline);
+                encoder.bind_vertex_buffers(0, &[vertex_buffer.buffer], &[0]);
+                encoder.bind_index_buffer(index_buffer.buffer, 0, vk::IndexType::UINT32);
+                unsafe { device.cmd_draw_indexed(buffer, index_count as _, 1, 0, 0, 0) };
+            }
+
+            // Draw the eyes side by side over the main view
+            if let Some(stereo) = stereo {
+                stereo.cmd_draw_composite(device, buffer);
+                encoder.invalidate();
+            }
+
+            // Draw the secondary views in their regions, over the main view
+            if let Some(secondary_views) = secondary_views {
+                secondary_views.cmd_draw(
+                    device,
+                    buffer,
+                    i,
+                    vertex_buffer,
+                    index_buffer,
+                    main_index_count,
+                );
+                encoder.invalidate();
+            }
+
+            // Draw the decals over the scene in the second subpass
+            unsafe { device.cmd_next_subpass(buffer, vk::SubpassContents::INLINE) };
+            #[cfg(feature = "debug-markers")]
+            debug_markers::cmd_insert_label(
+                buffer,
+                "Overlay subpass",
+                debug_markers::INSERTED_COLOR,
+            );
+            // The stereo and the secondary views bind their own sets
+            frame_layout.cmd_bind(&mut encoder, descriptor_sets[i]);
+            if let Some(decals) = decals {
+                decals.cmd_draw(&mut encoder);
+            }
+            if draws_gizmos {
+                debug_draw.cmd_draw(&mut encoder, i, transient_allocator.buffer(i));
+            }
+
+            // Draw the text over the whole scene, after the tonemapping if there is one
+            if let Some(hud) = hud.filter(|_| tonemapper.is_none()) {
+                hud.cmd_draw(device, buffer, i);
+                encoder.invalidate();
+            }
+
+            // End render pass
+            unsafe { device.cmd_end_render_pass(buffer) };
+
+            // Darken the HDR image where the sun is occluded, before any post-processing
+            if let Some(ray_traced_shadows) = ray_traced_shadows {
+                mark(FramePass::RayTracedShadows);
+                ray_traced_shadows.cmd_trace(device, buffer, i);
+            }
+            mark(FramePass::Readback);
+
+            // Predicate the draws of the next frame on the occlusion queries
+            if let Some(occlusion_queries) = occlusion_queries {
+                occlusion_queries.cmd_copy_results(device, buffer, i);
+            }
+
+            // Reduce the depth of the frame for the culling of the next one
+            // and the screen-space reflections
+            if let Some(depth_pyramid) = depth_pyramid {
+                depth_pyramid.cmd_build(device, buffer);
+            }
+
+            // Read the requested pages back to bind them a few frames later
+            if let Some(virtual_texture) = virtual_texture {
+                virtual_texture.cmd_read_feedback(device, buffer, i);
+            }
+
+            // Expose the HDR image and tonemap it to the swapchain image
+            if let (Some(auto_exposure), Some(tonemapper)) = (auto_exposure, tonemapper) {
+                mark(FramePass::Exposure);
+                auto_exposure.cmd_dispatch(device, buffer, i);
+                if let Some(bloom) = bloom {
+                    mark(FramePass::Bloom);
+                    bloom.cmd_dispatch(device, buffer);
+                }
+                if let Some(ssr) = ssr {
+                    mark(FramePass::Ssr);
+                    frame_layout.cmd_bind(&mut encoder, descriptor_sets[i]);
+                    if let Some(reflection_probes) = reflection_probes {
+                        reflection_probes.cmd_bind(&mut encoder, ssr.pipeline_layout(), i);
+                    }
+                    ssr.cmd_draw(device, buffer, i);
+                    encoder.invalidate();
+                }
+                if let Some(light_shafts) = light_shafts {
+                    mark(FramePass::LightShafts);
+                    frame_layout.cmd_bind(&mut encoder, descriptor_sets[i]);
+                    light_shafts.cmd_draw(device, buffer, i);
+                    encoder.invalidate();
+                }
+                if let Some(motion_blur) = motion_blur {
+                    mark(FramePass::MotionBlur);
+                    motion_blur.cmd_blur(device, buffer, i);
+                }
+                if let Some(fsr) = fsr {
+                    mark(FramePass::Fsr);
+                    fsr.cmd_dispatch(device, buffer);
+                }
+                mark(FramePass::Tonemap);
+                tonemapper.cmd_begin(device, buffer, i);
+                if let Some(hud) = hud {
+                    hud.cmd_draw(device, buffer, i);
+                }
+                tonemapper.cmd_end(device, buffer);
+            }
+
+            // Hand the swapchain image over to the present queue
+            if let Some(present_transfer) = present_transfer {
+                present_transfer.cmd_release(device, buffer, i);
+            }
+
+            mark(FramePass::End);
+            if let Some(gpu_timer) = gpu_timer {
+                gpu_timer.cmd_end(device, buffer, i);
+            }
+
+            // End command buffer
+            unsafe { device.end_command_buffer(buffer).unwrap() };
+
+            let stats = encoder.stats();
+            log::debug!(
+                "Command buffer {}: {} binds recorded, {} redundant binds skipped.",
+                i,
+                stats.recorded,
+                stats.skipped
+            );
+            bind_stats.push(stats);
+        });
+
+        bind_stats
     }
 
-    fn load_model() -> (Vec<Vertex>, Vec<u32>) {
-        log::debug!("Loading model.");
-        let mut cursor = fs::load("models/chalet.obj");
-        let (models, _) = tobj::load_obj_buf(&mut cursor, |_| {
-            Ok((vec![], std::collections::HashMap::new()))
-        })
-        .unwrap();
+    fn create_sync_objects(device: &Device) -> InFlightFrames {
+        let mut sync_objects_vec = Vec::new();
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let image_available_semaphore = {
+                let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
+                unsafe { device.create_semaphore(&semaphore_info, None).unwrap() }
+            };
 
-        let mesh = &models[0].mesh;
-        let positions = mesh.positions.as_slice();
-        let coords = mesh.texcoords.as_slice();
-        let vertex_count = mesh.positions.len() / 3;
+            let render_finished_semaphore = {
+                let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
+                unsafe { device.create_semaphore(&semaphore_info, None).unwrap() }
+            };
 
-        let mut vertices = Vec::with_capacity(vertex_count);
-        for i in 0..vertex_count {
-            let x = positions[i * 3];
-            let y = positions[i * 3 + 1];
-            let z = positions[i * 3 + 2];
-            let u = coords[i * 2];
-            let v = coords[i * 2 + 1];
+            let in_flight_fence = {
+                let fence_info = vk::FenceCreateInfo::builder()
+                    .flags(vk::FenceCreateFlags::SIGNALED)
+                    .build();
+                unsafe { device.create_fence(&fence_info, None).unwrap() }
+            };
 
-            let vertex = Vertex {
-                pos: [x, y, z],
-                color: [1.0, 1.0, 1.0],
-                coords: [u, v],
+            let sync_objects = SyncObjects {
+                image_available_semaphore,
+                render_finished_semaphore,
+                fence: in_flight_fence,
             };
-            vertices.push(vertex);
+            sync_objects_vec.push(sync_objects)
+        }
+
+        InFlightFrames::new(sync_objects_vec)
+    }
+
+    fn run(&mut self) -> RunResult {
+        log::debug!("Running application.");
+        loop {
+            if self.process_event() {
+                break;
+            }
+            if let Some(error) = self.allocation_error.take() {
+                return RunResult::OutOfMemory(error);
+            }
+            if self.is_suspended {
+                // Nothing can be presented until the app is resumed
+                std::thread::sleep(Duration::from_millis(SUSPENDED_POLL_INTERVAL_MS));
+                continue;
+            }
+            self.draw_frame();
+            if let Some(error) = self.allocation_error.take() {
+                return RunResult::OutOfMemory(error);
+            }
+            if self.is_device_lost {
+                return self.device_lost();
+            }
+            self.end_profiled_frame();
+            if let Some(dynamic_resolution) = self.dynamic_resolution {
+                if dynamic_resolution.scale() != self.render_scale {
+                    // The scene images are created again at the new scale
+                    self.render_scale = dynamic_resolution.scale();
+                    self.recreate_swapchain();
+                }
+            }
+            if self.benchmark.as_ref().is_some_and(Benchmark::is_done) {
+                break;
+            }
+            self.reload_assets();
+            if self.is_device_lost {
+                return self.device_lost();
+            }
+        }
+        if !self.wait_idle() {
+            return self.device_lost();
+        }
+        self.finish_benchmark();
+        RunResult::Exit
+    }
+
+    /// Write the GPU crash dump, if enabled, after the device was lost.
+    fn device_lost(&self) -> RunResult {
+        if let Some(gpu_crash_dump) = self.gpu_crash_dump.as_ref() {
+            gpu_crash_dump.write(self.vk_context.device(), self.graphics_queue);
+        }
+        RunResult::DeviceLost
+    }
+
+    /// Create the loader of the model, its texture and the compiled shaders.
+    fn create_asset_loader() -> AssetLoader<ReloadedAsset> {
+        let shaders = std::fs::read_dir(Path::new("assets").join(SHADERS_DIRECTORY))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "spv"))
+                    .filter_map(|path| {
+                        let name = path.file_name()?.to_str()?;
+                        Some(format!("{}/{}", SHADERS_DIRECTORY, name))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let paths = [MODEL_PATH, TEXTURE_PATH]
+            .iter()
+            .copied()
+            .chain(shaders.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+        AssetLoader::new(&paths, |path| {
+            if path == Path::new(MODEL_PATH) {
+                let (vertices, indices) = Self::load_model();
+                ReloadedAsset::Model(vertices, indices)
+            } else if path == Path::new(TEXTURE_PATH) {
+                let (extent, pixels) = Self::load_texture_pixels();
+                ReloadedAsset::Texture(extent, pixels)
+            } else {
+                ReloadedAsset::Shaders
+            }
+        })
+    }
+
+    /// Swap in the assets loaded since the last call, or all of them after
+    /// the `reload` command.
+    ///
+    /// The device is waited for first since the previous resources may be
+    /// used by the frames in flight. Only the resources of the changed assets
+    /// and what depends on them are created again.
+    fn reload_assets(&mut self) {
+        let assets = if std::mem::take(&mut self.is_reload_requested) {
+            let (vertices, indices) = Self::load_model();
+            let (extent, pixels) = Self::load_texture_pixels();
+            vec![
+                ReloadedAsset::Model(vertices, indices),
+                ReloadedAsset::Texture(extent, pixels),
+                ReloadedAsset::Shaders,
+            ]
+        } else {
+            match self.asset_loader.as_ref() {
+                Some(asset_loader) => asset_loader.loaded_assets(),
+                None => return,
+            }
+        };
+        if assets.is_empty() || !self.wait_idle() {
+            return;
+        }
+
+        let mut recreate_pipelines = false;
+        let mut recapture_probes = false;
+        for asset in assets {
+            // Whether the probes must be captured again and the pipelines created again
+            let result = match asset {
+                ReloadedAsset::Texture(extent, pixels) => self
+                    .swap_texture(extent, &pixels)
+                    .map(|swapped| (swapped, false)),
+                ReloadedAsset::Model(vertices, indices) => self
+                    .swap_model(&vertices, &indices)
+                    .map(|must_recreate| (true, must_recreate)),
+                ReloadedAsset::Shaders => Ok((false, true)),
+            };
+            match result {
+                Ok((must_recapture, must_recreate)) => {
+                    recapture_probes |= must_recapture;
+                    recreate_pipelines |= must_recreate;
+                }
+                // The previous resources are kept
+                Err(error) => log::error!("Failed to reload an asset. Cause: {}", error),
+            }
+        }
+        // The probes captured the previous model and texture, once for both
+        if recapture_probes {
+            match self.recapture_reflection_probes() {
+                Ok(must_recreate) => recreate_pipelines |= must_recreate,
+                Err(error) => log::error!("Failed to capture the probes again. Cause: {}", error),
+            }
+        }
+        if recreate_pipelines {
+            // The pipelines are created with the swapchain resources, which
+            // read the shaders from disk again
+            self.recreate_swapchain();
+        } else {
+            self.recreate_command_buffers();
+        }
+        if let Err(error) = self.recreate_secondary_window() {
+            self.allocation_error = Some(error);
+        }
+    }
+
+    /// Replace the texture of the model by a texture of `pixels`.
+    ///
+    /// The descriptors referencing the previous texture are written again
+    /// before it is destroyed, so the device must be idle.
+    ///
+    /// Return whether the texture was replaced.
+    fn swap_texture(
+        &mut self,
+        extent: vk::Extent2D,
+        pixels: &[u8],
+    ) -> Result<bool, AllocationError> {
+        if self.texture_streamer.is_some() {
+            log::warn!("The streamed texture is read from its levels and is not reloaded.");
+            return Ok(false);
+        }
+        let texture = Self::create_texture_from_pixels(
+            &self.vk_context,
+            self.command_pool,
+            self.graphics_queue,
+            extent,
+            pixels,
+            ColorSpace::Srgb,
+        )?;
+
+        let device = self.vk_context.device();
+        write_model_texture(device, &self.descriptor_sets, texture);
+        if let Some(bindless_textures) = self.bindless_textures.as_ref() {
+            bindless_textures.set_texture(device, self.model_texture_index, texture);
+        }
+        if let Some(water) = self.water.as_ref() {
+            water.set_texture(device, texture);
+        }
+        if let Some(secondary_views) = self.secondary_views.as_ref() {
+            secondary_views.set_texture(device, texture);
+        }
+        if let Some(mirror) = self.mirror.as_ref() {
+            mirror.set_texture(device, texture);
         }
-
-        (vertices, mesh.indices.clone())
+        if let Some(secondary_window) = self.secondary_window.as_ref() {
+            secondary_window.set_texture(device, texture);
+        }
+        if let Some(stereo) = self.stereo.as_ref() {
+            stereo.set_texture(device, texture);
+        }
+        let mut previous_texture = std::mem::replace(&mut self.texture, texture);
+        previous_texture.destroy(device);
+        Ok(true)
     }
 
-    fn create_vertex_buffer(
-        vk_context: &VkContext,
-        command_pool: vk::CommandPool,
-        transfer_queue: vk::Queue,
+    /// Replace the geometry of the model by `vertices` and `indices`.
+    ///
+    /// The meshlets, the acceleration structures, the lightmap and the bounds
+    /// of the shadow casters are computed again from the new geometry, so the
+    /// device must be idle. The previous resources are only destroyed once
+    /// all the new ones are created.
+    ///
+    /// Return whether the pipelines must be created again.
+    fn swap_model(
+        &mut self,
         vertices: &[Vertex],
-    ) -> (vk::Buffer, vk::DeviceMemory) {
-        Self::create_device_local_buffer_with_data::<u32, _>(
-            vk_context,
-            command_pool,
-            transfer_queue,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            vertices,
-        )
+        indices: &[u32],
+    ) -> Result<bool, AllocationError> {
+        let mut resources = ModelResources::default();
+        if let Err(error) = self.create_model_resources(vertices, indices, &mut resources) {
+            resources.destroy(&self.vk_context);
+            return Err(error);
+        }
+
+        let device = self.vk_context.device();
+        let vertex_buffer = resources.vertex_buffer.unwrap();
+        let index_buffer = resources.index_buffer.unwrap();
+        std::mem::replace(&mut self.vertex_buffer, vertex_buffer).destroy(device);
+        std::mem::replace(&mut self.index_buffer, index_buffer).destroy(device);
+        let model = self.model;
+        let mesh = self.scene.get_mut::<MeshRenderer>(model).unwrap();
+        mesh.index_count = indices.len() as _;
+        mesh.collision_mesh = Arc::new(math::CollisionMesh::new(
+            vertices
+                .iter()
+                .map(|vertex| Point3::from(vertex.pos))
+                .collect(),
+            indices.to_vec(),
+        ));
+
+        if let Some(shadow_map) = self.shadow_map.as_mut() {
+            let mut caster_bounds = math::Aabb::empty();
+            vertices
+                .iter()
+                .for_each(|vertex| caster_bounds.expand(Point3::from(vertex.pos)));
+            shadow_map.set_caster_bounds(caster_bounds);
+        }
+        // The pipeline layout uses the layout of the meshlets
+        let recreate_pipelines = resources.mesh_shading.is_some();
+        if let Some(mesh_shading) = resources.mesh_shading.take() {
+            let mut previous = self.mesh_shading.replace(mesh_shading).unwrap();
+            previous.destroy(device);
+        }
+        if let Some(acceleration_structures) = resources.acceleration_structures.take() {
+            if let Some(ray_traced_shadows) = self.ray_traced_shadows.as_ref() {
+                ray_traced_shadows.set_top_level(device, acceleration_structures.top_level());
+            }
+            if self
+                .material_instances
+                .keywords()
+                .contains(&RAY_QUERY_SHADOWS)
+            {
+                self.material_instances
+                    .set_top_level(device, acceleration_structures.top_level());
+            }
+            self.acceleration_structures
+                .replace(acceleration_structures)
+                .unwrap()
+                .destroy(device, self.vk_context.ray_tracing().unwrap());
+        }
+        if let Some(lightmap) = resources.lightmap.take() {
+            self.material_instances
+                .set_lightmap(device, lightmap.texture());
+            self.lightmap.replace(lightmap).unwrap().destroy(device);
+        }
+        Ok(recreate_pipelines)
     }
 
-    fn create_index_buffer(
-        vk_context: &VkContext,
-        command_pool: vk::CommandPool,
-        transfer_queue: vk::Queue,
+    /// Create the resources of the model of `vertices` and `indices` which
+    /// the app uses into `resources`.
+    ///
+    /// On error, `resources` holds those created before and must be destroyed.
+    fn create_model_resources(
+        &self,
+        vertices: &[Vertex],
         indices: &[u32],
-    ) -> (vk::Buffer, vk::DeviceMemory) {
-        Self::create_device_local_buffer_with_data::<u16, _>(
-            vk_context,
-            command_pool,
-            transfer_queue,
-            vk::BufferUsageFlags::INDEX_BUFFER,
+        resources: &mut ModelResources,
+    ) -> Result<(), AllocationError> {
+        let vertex_buffer = Self::create_vertex_buffer(
+            &self.vk_context,
+            self.transient_command_pool,
+            self.graphics_queue,
+            vertices,
+        )?;
+        resources.vertex_buffer = Some(vertex_buffer);
+        let index_buffer = Self::create_index_buffer(
+            &self.vk_context,
+            self.transient_command_pool,
+            self.graphics_queue,
             indices,
-        )
+        )?;
+        resources.index_buffer = Some(index_buffer);
+
+        if self.mesh_shading.is_some() {
+            resources.mesh_shading = Some(Self::create_mesh_shading(
+                &self.vk_context,
+                self.transient_command_pool,
+                self.graphics_queue,
+                vertex_buffer,
+                vertices,
+                indices,
+            )?);
+        }
+        if self.acceleration_structures.is_some() {
+            resources.acceleration_structures = Some(Self::create_acceleration_structures(
+                &self.vk_context,
+                (self.transient_command_pool, self.graphics_queue),
+                (vertex_buffer, vertices.len()),
+                (index_buffer, indices.len()),
+            )?);
+        }
+        if self.lightmap.is_some() {
+            resources.lightmap = Some(Self::bake_lightmap(
+                &self.vk_context,
+                self.transient_command_pool,
+                self.graphics_queue,
+                self.swapchain_properties,
+                vertices,
+                (vertex_buffer, index_buffer, indices.len() as _),
+                (
+                    self.model_transform().matrix(),
+                    self.scene.get::<Light>(self.sun).unwrap().direction,
+                ),
+            )?);
+        }
+        Ok(())
     }
 
-    /// Create a buffer and it's gpu  memory and fill it.
+    /// Capture the reflection probes again after the model or its texture changed.
     ///
-    /// This function internally creates an host visible staging buffer and
-    /// a device local buffer. The data is first copied from the cpu to the
-    /// staging buffer. Then we copy the data from the staging buffer to the
-    /// final buffer using a one-time command buffer.
-    fn create_device_local_buffer_with_data<A, T: Copy>(
-        vk_context: &VkContext,
-        command_pool: vk::CommandPool,
-        transfer_queue: vk::Queue,
-        usage: vk::BufferUsageFlags,
-        data: &[T],
-    ) -> (vk::Buffer, vk::DeviceMemory) {
-        let device = vk_context.device();
-        let size = (data.len() * size_of::<T>()) as vk::DeviceSize;
-        let (staging_buffer, staging_memory, staging_mem_size) = Self::create_buffer(
-            vk_context,
-            size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
+    /// Return whether the pipelines must be created again, which is the case
+    /// when there are probes since their layout is created with them.
+    fn recapture_reflection_probes(&mut self) -> Result<bool, AllocationError> {
+        if self.reflection_probes.is_none() {
+            return Ok(false);
+        }
+        let reflection_probes = Self::create_reflection_probes(
+            &self.vk_context,
+            (self.transient_command_pool, self.graphics_queue),
+            (&self.frame_layout, self.swapchain_properties),
+            (self.frame_layout.set_layout(), self.texture),
+            (
+                self.vertex_buffer,
+                self.index_buffer,
+                self.model_index_count() as _,
+            ),
+            (self.model_transform().matrix(), self.fog),
+            self.images.len(),
+        )?;
+        let device = self.vk_context.device();
+        self.reflection_probes
+            .replace(reflection_probes)
+            .unwrap()
+            .destroy(device);
+        Ok(true)
+    }
 
-        unsafe {
-            let data_ptr = device
-                .map_memory(staging_memory, 0, size, vk::MemoryMapFlags::empty())
-                .unwrap();
-            let mut align = ash::util::Align::new(data_ptr, align_of::<A>() as _, staging_mem_size);
-            align.copy_from_slice(data);
-            device.unmap_memory(staging_memory);
+    /// Read the GPU times of the last frames of the benchmark and write its reports.
+    ///
+    /// The device must be idle.
+    fn finish_benchmark(&mut self) {
+        let benchmark = match self.benchmark.as_mut() {
+            Some(benchmark) => benchmark,
+            None => return,
         };
+        if !benchmark.is_done() {
+            log::warn!("Benchmark interrupted, only the frames measured are reported.");
+        }
+        if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+            for image_index in 0..self.images.len() {
+                benchmark.resolve(
+                    image_index,
+                    gpu_timer.read(self.vk_context.device(), image_index),
+                );
+            }
+        }
 
-        let (buffer, memory, _) = Self::create_buffer(
-            vk_context,
-            size,
-            vk::BufferUsageFlags::TRANSFER_DST | usage,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        );
-
-        Self::copy_buffer(
-            device,
-            command_pool,
-            transfer_queue,
-            staging_buffer,
-            buffer,
-            staging_mem_size,
-        );
-
-        unsafe {
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_memory, None);
+        let properties = unsafe {
+            self.vk_context
+                .instance()
+                .get_physical_device_properties(self.vk_context.physical_device())
         };
-
-        (buffer, memory)
+        let device_name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+        if let Err(error) = benchmark.write_reports(&device_name.to_string_lossy()) {
+            log::error!("Failed to write the benchmark reports. Cause: {}", error);
+        }
     }
 
-    fn create_uniform_buffers(
-        vk_context: &VkContext,
-        count: usize,
-    ) -> (Vec<vk::Buffer>, Vec<vk::DeviceMemory>) {
-        let size = size_of::<UniformBufferObject>() as vk::DeviceSize;
-        let mut buffers = Vec::new();
-        let mut memories = Vec::new();
-
-        for _ in 0..count {
-            let (buffer, memory, _) = Self::create_buffer(
-                vk_context,
-                size,
-                vk::BufferUsageFlags::UNIFORM_BUFFER,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            );
-            buffers.push(buffer);
-            memories.push(memory);
+    /// Add the CPU timings of the frame to the breakdown, logging it periodically.
+    fn end_profiled_frame(&mut self) {
+        profiler::end_frame();
+        if let Some(last_log) = self.last_profile_log {
+            if last_log.elapsed() >= Duration::from_secs(PROFILE_LOG_INTERVAL_SECS) {
+                log::info!("CPU frame breakdown:\n{}", profiler::report());
+                self.last_profile_log = Some(Instant::now());
+            }
         }
-
-        (buffers, memories)
     }
 
-    /// Create a buffer and allocate its memory.
-    ///
-    /// # Returns
-    ///
-    /// The buffer, its memory and the actual size in bytes of the
-    /// allocated memory since in may differ from the requested size.
-    fn create_buffer(
-        vk_context: &VkContext,
-        size: vk::DeviceSize,
-        usage: vk::BufferUsageFlags,
-        mem_properties: vk::MemoryPropertyFlags,
-    ) -> (vk::Buffer, vk::DeviceMemory, vk::DeviceSize) {
-        let device = vk_context.device();
-        let buffer = {
-            let buffer_info = vk::BufferCreateInfo::builder()
-                .size(size)
-                .usage(usage)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                .build();
-            unsafe { device.create_buffer(&buffer_info, None).unwrap() }
+    /// Take the window and share the instance so they can be reused by a new app.
+    fn take_base(&mut self) -> AppBase {
+        let window = match (self.events_loop.take(), self.window.take()) {
+            (Some(events_loop), Some(window)) => Some((events_loop, window)),
+            _ => None,
         };
+        AppBase {
+            window,
+            instance: Rc::clone(self.vk_context.instance_context()),
+        }
+    }
 
-        let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
-        let memory = {
-            let mem_type = Self::find_memory_type(
-                mem_requirements,
-                vk_context.get_mem_properties(),
-                mem_properties,
-            );
-
-            let alloc_info = vk::MemoryAllocateInfo::builder()
-                .allocation_size(mem_requirements.size)
-                .memory_type_index(mem_type)
-                .build();
-            unsafe { device.allocate_memory(&alloc_info, None).unwrap() }
-        };
+    /// Take the view and the settings so they can be restored in a new app.
+    fn take_state(&mut self) -> AppState {
+        AppState {
+            camera: *self.camera(),
+            model_transform: *self.model_transform(),
+            model_layers: self.model_layers(),
+            pass_layers: self.pass_layers,
+            debug_views: self.debug_views,
+            fog: self.fog,
+            gizmo: self.gizmo,
+            light_gizmos: self.light_gizmos,
+            time_controller: self.time_controller,
+            hud_stats: self.hud_stats,
+            last_profile_log: self.last_profile_log,
+            console: self.console.take(),
+            input_recorder: self.input_recorder.take(),
+            input_playback: self.input_playback.take(),
+        }
+    }
 
-        unsafe { device.bind_buffer_memory(buffer, memory, 0).unwrap() };
+    /// Restore the view and the settings taken from a previous app.
+    fn restore_state(&mut self, state: AppState) {
+        *self.camera_mut() = state.camera;
+        self.scene.insert(self.model, state.model_transform);
+        let model = self.model;
+        self.scene.get_mut::<MeshRenderer>(model).unwrap().layers = state.model_layers;
+        self.pass_layers = state.pass_layers;
+        self.debug_views = state.debug_views;
+        self.fog = state.fog;
+        self.gizmo = state.gizmo;
+        self.light_gizmos = state.light_gizmos;
+        self.time_controller = state.time_controller;
+        self.hud_stats = state.hud_stats;
+        self.last_profile_log = state.last_profile_log;
+        // Only kept if the new app has a console too
+        if self.console.is_some() {
+            self.console = state.console;
+        }
+        self.input_recorder = state.input_recorder;
+        self.input_playback = state.input_playback;
+        // The layers and the debug views are recorded in the command buffers
+        self.recreate_command_buffers();
+    }
 
-        (buffer, memory, mem_requirements.size)
+    /// Render a single frame in the offscreen target and write it to `path`.
+    fn run_headless(&mut self, path: &Path) -> RunResult {
+        log::debug!("Running application headless.");
+        let frame = match self.draw_offscreen_frame() {
+            Ok(frame) => frame,
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                self.is_device_lost = true;
+                return self.device_lost();
+            }
+            Err(error) => panic!("Failed to draw the offscreen frame. Cause: {}", error),
+        };
+        if !self.wait_idle() {
+            return self.device_lost();
+        }
+        frame.save(path).unwrap();
+        log::info!("Frame written to {}.", path.display());
+        RunResult::Exit
     }
 
-    /// Copy the `size` first bytes of `src` into `dst`.
+    /// Sort `count` pseudo random keys on the GPU and compare them with a CPU sort.
     ///
-    /// It's done using a command buffer allocated from
-    /// `command_pool`. The command buffer is cubmitted tp
-    /// `transfer_queue`.
-    fn copy_buffer(
-        device: &Device,
-        command_pool: vk::CommandPool,
-        transfer_queue: vk::Queue,
-        src: vk::Buffer,
-        dst: vk::Buffer,
-        size: vk::DeviceSize,
-    ) {
-        Self::execute_one_time_commands(&device, command_pool, transfer_queue, |buffer| {
-            let region = vk::BufferCopy {
-                src_offset: 0,
-                dst_offset: 0,
-                size,
-            };
-            let regions = [region];
+    /// The values are the indices of the keys. Every other key only uses its
+    /// 8 lowest bits so many keys are equal, which checks that the sort is
+    /// stable. Return whether both sorts match.
+    fn run_sort_check(&self, count: u32) -> bool {
+        log::debug!("Checking the GPU sort of {} keys.", count);
+        let device = self.vk_context.device();
+        let mut sort = match Self::create_gpu_sort(
+            &self.vk_context,
+            count,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        ) {
+            Ok(sort) => sort,
+            Err(error) => {
+                log::error!("Failed to allocate the GPU sort. Cause: {}", error);
+                return false;
+            }
+        };
+        Self::create_gpu_sort_pipelines(device, self.vk_context.subgroup_properties(), &mut sort);
+
+        let mut state = 0x9e37_79b9_u32;
+        let keys = (0..count)
+            .map(|index| {
+                // Xorshift
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                if index % 2 == 0 {
+                    state & 0xff
+                } else {
+                    state
+                }
+            })
+            .collect::<Vec<_>>();
+        let values = (0..count).collect::<Vec<_>>();
+        let size = (count as usize * size_of::<u32>()) as vk::DeviceSize;
+        let read = |buffer: Buffer| unsafe {
+            let data_ptr = device
+                .map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap();
+            let data = std::slice::from_raw_parts(data_ptr as *const u32, count as _).to_vec();
+            device.unmap_memory(buffer.memory);
+            data
+        };
+        sort.keys().write(device, 0, &keys);
+        sort.values().write(device, 0, &values);
 
-            unsafe { device.cmd_copy_buffer(buffer, src, dst, &regions) };
+        Self::execute_one_time_commands(device, self.command_pool, self.graphics_queue, |buffer| {
+            sort.cmd_sort(device, buffer, count);
+            cmd_barriers(
+                device,
+                buffer,
+                &[Barrier::memory(
+                    Access::compute_write(),
+                    Access::host_read(),
+                )],
+            );
         });
+        let sorted = read(sort.keys())
+            .into_iter()
+            .zip(read(sort.values()))
+            .collect::<Vec<_>>();
+        sort.destroy(device);
+
+        // The sort of the standard library is stable too
+        let mut expected = keys.into_iter().zip(values).collect::<Vec<_>>();
+        expected.sort_by_key(|(key, _)| *key);
+        match sorted.iter().zip(&expected).position(|(a, b)| a != b) {
+            Some(index) => {
+                log::error!(
+                    "GPU sort differs from the CPU sort at {}: {:?} instead of {:?}.",
+                    index,
+                    sorted[index],
+                    expected[index]
+                );
+                false
+            }
+            None => {
+                log::info!("GPU sort of {} keys matches the CPU sort.", count);
+                true
+            }
+        }
     }
 
-    /// Create a one time use command buffer and pass it to `executor`.
-    fn execute_one_time_commands<F: FnOnce(vk::CommandBuffer)>(
-        device: &Device,
-        command_pool: vk::CommandPool,
-        queue: vk::Queue,
-        executor: F,
-    ) {
-        let command_buffer = {
-            let alloc_info = vk::CommandBufferAllocateInfo::builder()
-                .level(vk::CommandBufferLevel::PRIMARY)
-                .command_pool(command_pool)
-                .command_buffer_count(1)
-                .build();
-
-            unsafe { device.allocate_command_buffers(&alloc_info).unwrap()[0] }
+    /// Process the events from the `EventsLoop` and return whether the
+    /// main loop should stop.
+    fn process_event(&mut self) -> bool {
+        profile_scope!("events");
+        let mut should_stop = false;
+        let mut resize_dimensions = None;
+        let mut is_left_clicked = None;
+        let mut cursor_position = None;
+        let mut last_position = self.cursor_position;
+        let mut wheel_delta = None;
+        let mut pressed_keys = Vec::new();
+        let mut received_chars = Vec::new();
+        let mut pick_requested = false;
+        let mut secondary_window_closed = false;
+        let mut secondary_window_resized = false;
+        let mut suspended = None;
+        let secondary_window_id = self.secondary_window.as_ref().map(SecondaryWindow::id);
+
+        let events_loop = match self.events_loop.as_mut() {
+            Some(events_loop) => events_loop,
+            None => return should_stop,
         };
-        let command_buffers = [command_buffer];
+        events_loop.poll_events(|event| match event {
+            Event::WindowEvent { window_id, event } if Some(window_id) == secondary_window_id => {
+                match event {
+                    WindowEvent::CloseRequested => secondary_window_closed = true,
+                    WindowEvent::Resized(_) => secondary_window_resized = true,
+                    _ => {}
+                }
+            }
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => should_stop = true,
+                WindowEvent::Resized(LogicalSize { width, height }) => {
+                    resize_dimensions = Some([width as u32, height as u32]);
+                }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Left,
+                    state,
+                    ..
+                } => {
+                    if state == ElementState::Pressed {
+                        is_left_clicked = Some(true);
+                    } else {
+                        is_left_clicked = Some(false);
+                    }
+                }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Right,
+                    state: ElementState::Pressed,
+                    ..
+                } => pick_requested = true,
+                WindowEvent::CursorMoved { position, .. } => {
+                    let position: (i32, i32) = position.into();
+                    cursor_position = Some([position.0, position.1]);
+                }
+                WindowEvent::Touch(Touch {
+                    location, phase, ..
+                }) => {
+                    let position: (i32, i32) = location.into();
+                    cursor_position = Some([-position.0, -position.1]);
+
+                    if phase == TouchPhase::Started {
+                        last_position = cursor_position.unwrap();
+                        is_left_clicked = Some(true);
+                    } else if phase == TouchPhase::Ended {
+                        is_left_clicked = Some(false);
+                    }
+                }
+                WindowEvent::MouseWheel {
+                    delta: MouseScrollDelta::LineDelta(_, v_lines),
+                    ..
+                } => {
+                    wheel_delta = Some(v_lines);
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } => pressed_keys.push(key),
+                WindowEvent::ReceivedCharacter(c) => received_chars.push(c),
+                _ => {}
+            },
+            Event::Suspended(is_suspended) => suspended = Some(is_suspended),
+            _ => {}
+        });
 
-        // Begin recording
-        {
-            let begin_info = vk::CommandBufferBeginInfo::builder()
-                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
-                .build();
-            unsafe {
-                device
-                    .begin_command_buffer(command_buffer, &begin_info)
-                    .unwrap()
+        if self.input_recorder.is_some() || self.input_playback.is_some() {
+            let mut input = FrameInput {
+                delta: self.last_frame_instant.elapsed().as_secs_f32(),
+                close_requested: should_stop,
+                resize: resize_dimensions,
+                left_click: is_left_clicked,
+                pick: pick_requested,
+                cursor_position,
+                touch_started: cursor_position.is_some() && last_position != self.cursor_position,
+                wheel_delta,
+                pressed_keys,
+                received_chars,
             };
+            if let Some(playback) = self.input_playback.as_mut() {
+                match playback.next_frame() {
+                    Some(frame) => {
+                        // The window is resized like during the recording
+                        if let (Some([width, height]), Some(window)) =
+                            (frame.resize, self.window.as_ref())
+                        {
+                            window.set_inner_size(LogicalSize::new(width as _, height as _));
+                        }
+                        input = FrameInput {
+                            close_requested: input.close_requested || frame.close_requested,
+                            ..frame
+                        };
+                    }
+                    None => {
+                        log::info!("End of the input playback.");
+                        input.close_requested = true;
+                    }
+                }
+            }
+            if let Some(recorder) = self.input_recorder.as_mut() {
+                recorder.record(&input);
+            }
+            self.input_delta = Some(input.delta);
+            should_stop = input.close_requested;
+            resize_dimensions = input.resize;
+            is_left_clicked = input.left_click;
+            pick_requested = input.pick;
+            cursor_position = input.cursor_position;
+            if input.touch_started {
+                last_position = input.cursor_position.unwrap_or(last_position);
+            } else {
+                last_position = self.cursor_position;
+            }
+            wheel_delta = input.wheel_delta;
+            pressed_keys = input.pressed_keys;
+            received_chars = input.received_chars;
         }
 
-        // Execute user function
-        executor(command_buffer);
+        match suspended {
+            Some(true) if !self.is_suspended => self.suspend(),
+            Some(false) if self.is_suspended => self.resume(),
+            _ => {}
+        }
+        self.resize_dimensions = resize_dimensions;
+        if secondary_window_closed {
+            self.destroy_secondary_window();
+        } else if secondary_window_resized {
+            if let Err(error) = self.recreate_secondary_window() {
+                self.allocation_error = Some(error);
+            }
+        }
+        if let Some(position) = cursor_position {
+            self.cursor_position = position;
+            self.cursor_delta = Some([
+                position[0] - last_position[0],
+                position[1] - last_position[1],
+            ]);
+        } else {
+            self.cursor_delta = None;
+        }
+        if let Some(is_left_clicked) = is_left_clicked {
+            // Grabbing a handle of the gizmo does not rotate the camera
+            let ray = self.cursor_ray();
+            let camera_position = self.camera().position();
+            let scene = &self.scene;
+            let gizmo = &mut self.gizmo;
+            let is_handle_grabbed = is_left_clicked
+                && self
+                    .selected
+                    .and_then(|selected| scene.get::<math::Transform>(selected))
+                    .is_some_and(|transform| gizmo.begin_drag(&ray, transform, camera_position));
+            if !is_left_clicked {
+                self.gizmo.end_drag();
+            }
+            self.is_left_clicked = is_left_clicked && !is_handle_grabbed;
+        }
+        if let (Some(selected), Some(_)) = (self.selected, cursor_position) {
+            let ray = self.cursor_ray();
+            let camera_position = self.camera().position();
+            if let Some(transform) = self.scene.get_mut::<math::Transform>(selected) {
+                if self.gizmo.is_dragging() {
+                    self.gizmo.drag(&ray, transform);
+                } else {
+                    self.gizmo.hover(&ray, transform, camera_position);
+                }
+            }
+        }
+        self.wheel_delta = wheel_delta;
+        if pick_requested {
+            self.pick();
+        }
 
-        // End recording
-        unsafe { device.end_command_buffer(command_buffer).unwrap() };
+        // The keys type in the console while it is open
+        if let Some(console) = self.console.as_mut() {
+            if pressed_keys.contains(&VirtualKeyCode::Grave) {
+                console.toggle();
+            }
+            if console.is_open() {
+                for key in pressed_keys.drain(..) {
+                    match key {
+                        VirtualKeyCode::Up => console.recall_previous(),
+                        VirtualKeyCode::Down => console.recall_next(),
+                        _ => {}
+                    }
+                }
+                let actions = received_chars
+                    .into_iter()
+                    .filter_map(|c| console.handle_char(c))
+                    .collect::<Vec<_>>();
+                for action in actions {
+                    should_stop |= self.apply_console_action(action);
+                }
+            }
+        }
 
-        // Submit and wait
-        {
-            let submit_info = vk::SubmitInfo::builder()
-                .command_buffers(&command_buffers)
-                .build();
-            let submit_infos = [submit_info];
-            unsafe {
-                device
-                    .queue_submit(queue, &submit_infos, vk::Fence::null())
-                    .unwrap();
-                device.queue_wait_idle(queue).unwrap();
-            };
+        let mut debug_views_changed = false;
+        for key in pressed_keys {
+            debug_views_changed |= self.debug_views.handle_key(key);
+            self.gizmo.handle_key(key);
+            self.time_controller.handle_key(key);
+            if key == VirtualKeyCode::F {
+                self.frame_selection();
+            }
+        }
+        if debug_views_changed {
+            self.recreate_command_buffers();
+        }
+        // Keep the variables of the console in sync with the keys
+        if let Some(console) = self.console.as_mut() {
+            console.set("r.normals", CVarValue::Bool(self.debug_views.normals));
+            console.set("gizmo.mode", CVarValue::Int(self.gizmo.mode as _));
+            console.set("gizmo.snapping", CVarValue::Bool(self.gizmo.snapping));
+            console.set(
+                "time.paused",
+                CVarValue::Bool(self.time_controller.is_paused()),
+            );
+            console.set("time.scale", CVarValue::Float(self.time_controller.scale()));
         }
 
-        // Free
-        unsafe { device.free_command_buffers(command_pool, &command_buffers) };
+        should_stop
     }
 
-    /// Find a memory type in `mem_properties` that is suitable
-    /// for `requirements` and supports `required_properties`.
-    ///
-    /// # Returns
+    /// Create the console with the variables and the commands of the app.
+    fn create_console(&self) -> Console {
+        // Masks of the 32 layers
+        const LAYERS_RANGE: (CVarValue, CVarValue) =
+            (CVarValue::Int(0), CVarValue::Int(u32::MAX as _));
+        let mut console = Console::default();
+        console.register_cvar(
+            "r.normals",
+            "Draw the vertex normals of the model",
+            CVarValue::Bool(self.debug_views.normals),
+        );
+        console.register_cvar(
+            "r.light_gizmos",
+            "Draw the lights",
+            CVarValue::Bool(self.light_gizmos),
+        );
+        console.register_clamped_cvar(
+            "r.fog.density",
+            "Density of the exponential fog",
+            CVarValue::Float(self.fog.density),
+            (CVarValue::Float(0.0), CVarValue::Float(1.0)),
+        );
+        console.register_clamped_cvar(
+            "r.fog.height_density",
+            "Density of the height fog at its base, 0 to disable it",
+            CVarValue::Float(self.fog.height_density),
+            (CVarValue::Float(0.0), CVarValue::Float(1.0)),
+        );
+        console.register_clamped_cvar(
+            "gizmo.mode",
+            "0 to translate, 1 to rotate and 2 to scale the selection",
+            CVarValue::Int(self.gizmo.mode as _),
+            (CVarValue::Int(0), CVarValue::Int(2)),
+        );
+        console.register_cvar(
+            "gizmo.snapping",
+            "Snap the gizmo to steps",
+            CVarValue::Bool(self.gizmo.snapping),
+        );
+        console.register_cvar(
+            "hud.stats",
+            "Draw the statistics",
+            CVarValue::Bool(self.hud_stats),
+        );
+        console.register_cvar(
+            "hud.profile",
+            "Show and log the CPU frame breakdown",
+            CVarValue::Bool(self.last_profile_log.is_some()),
+        );
+        // The dynamic resolution sets the scale itself
+        if self.hdr_texture.is_some() && self.dynamic_resolution.is_none() {
+            console.register_clamped_cvar(
+                "r.render_scale",
+                "Fraction of the window size the scene is rendered at",
+                CVarValue::Float(self.render_scale),
+                (
+                    CVarValue::Float(MIN_RENDER_SCALE),
+                    CVarValue::Float(MAX_RENDER_SCALE),
+                ),
+            );
+        }
+        if let Some(auto_exposure) = self.auto_exposure.as_ref() {
+            console.register_clamped_cvar(
+                "r.exposure.compensation",
+                "Exposure compensation in stops",
+                CVarValue::Float(auto_exposure.parameters().compensation),
+                (CVarValue::Float(-8.0), CVarValue::Float(8.0)),
+            );
+        }
+        console.register_clamped_cvar(
+            "r.shadow.filter",
+            "0 for PCF, 1 for PCSS and 2 for variance shadow maps, which need --shadow-filter=vsm",
+            CVarValue::Int(self.shadow_filter as _),
+            (CVarValue::Int(0), CVarValue::Int(2)),
+        );
+        console.register_clamped_cvar(
+            "r.background",
+            "0 for the clear color, 1 for a gradient and 2 for a grid",
+            CVarValue::Int(match self.background.as_ref().map(Background::mode) {
+                Some(BackgroundMode::Gradient) => 1,
+                Some(BackgroundMode::Grid) => 2,
+                _ => 0,
+            }),
+            (CVarValue::Int(0), CVarValue::Int(2)),
+        );
+        let channels = [
+            ("r.clear_color.r", "Red of the clear color, in linear"),
+            ("r.clear_color.g", "Green of the clear color, in linear"),
+            ("r.clear_color.b", "Blue of the clear color, in linear"),
+        ];
+        for (&(name, description), &value) in channels.iter().zip(&self.clear_color) {
+            console.register_clamped_cvar(
+                name,
+                description,
+                CVarValue::Float(value),
+                (CVarValue::Float(0.0), CVarValue::Float(1.0)),
+            );
+        }
+        let passes = [
+            (
+                "r.layers.main",
+                "Layers drawn by the views of the cameras",
+                Pass::Main,
+            ),
+            (
+                "r.layers.shadow",
+                "Layers drawn in the shadow maps",
+                Pass::Shadow,
+            ),
+            (
+                "r.layers.reflection",
+                "Layers drawn in the reflections",
+                Pass::Reflection,
+            ),
+        ];
+        for &(name, description, pass) in &passes {
+            let layers = self.pass_layers.get(pass);
+            console.register_clamped_cvar(
+                name,
+                description,
+                CVarValue::Int(layers.0 as _),
+                LAYERS_RANGE,
+            );
+        }
+        console.register_clamped_cvar(
+            "r.layers.model",
+            "Layers of the model",
+            CVarValue::Int(self.model_layers().0 as _),
+            LAYERS_RANGE,
+        );
+        console.register_cvar(
+            "time.paused",
+            "Pause the animations and the particles",
+            CVarValue::Bool(self.time_controller.is_paused()),
+        );
+        console.register_clamped_cvar(
+            "time.scale",
+            "Speed of the animations and the particles",
+            CVarValue::Float(self.time_controller.scale()),
+            (
+                CVarValue::Float(MIN_TIME_SCALE),
+                CVarValue::Float(MAX_TIME_SCALE),
+            ),
+        );
+        console.register_command("step", "Advance the paused time by a frame");
+        console.register_command("frame", "Move the camera to frame the selection");
+        console.register_command("reload", "Reload the assets");
+        console.register_command("quit", "Close the application");
+        console
+    }
+
+    /// Apply a change of a console variable or execute a command.
     ///
-    /// The index of the memory type from `mem_properties`.
-    fn find_memory_type(
-        requirements: vk::MemoryRequirements,
-        mem_properties: vk::PhysicalDeviceMemoryProperties,
-        required_properties: vk::MemoryPropertyFlags,
-    ) -> u32 {
-        for i in 0..mem_properties.memory_type_count {
-            if requirements.memory_type_bits & (1 << i) != 0
-                && mem_properties.memory_types[i as usize]
-                    .property_flags
-                    .contains(required_properties)
-            {
-                return i;
+    /// Return whether the app must stop.
+    fn apply_console_action(&mut self, action: ConsoleAction) -> bool {
+        let console = self.console.as_ref().unwrap();
+        match action {
+            ConsoleAction::CVarChanged(name) => {
+                let value = console.get(name);
+                match name {
+                    "r.normals" => {
+                        self.debug_views.normals = value.as_bool();
+                        self.recreate_command_buffers();
+                    }
+                    "r.light_gizmos" => self.light_gizmos = value.as_bool(),
+                    "r.fog.density" => self.fog.density = value.as_float(),
+                    "r.fog.height_density" => self.fog.height_density = value.as_float(),
+                    "gizmo.mode" => {
+                        self.gizmo.mode = match value.as_int() {
+                            1 => GizmoMode::Rotate,
+                            2 => GizmoMode::Scale,
+                            _ => GizmoMode::Translate,
+                        }
+                    }
+                    "gizmo.snapping" => self.gizmo.snapping = value.as_bool(),
+                    "time.paused" => self.time_controller.set_paused(value.as_bool()),
+                    "time.scale" => self.time_controller.set_scale(value.as_float()),
+                    "r.layers.main" | "r.layers.shadow" | "r.layers.reflection" => {
+                        let pass = match name {
+                            "r.layers.main" => Pass::Main,
+                            "r.layers.shadow" => Pass::Shadow,
+                            _ => Pass::Reflection,
+                        };
+                        *self.pass_layers.get_mut(pass) = Layers(value.as_int() as _);
+                        self.recreate_command_buffers();
+                    }
+                    "r.layers.model" => {
+                        let model = self.model;
+                        let mesh = self.scene.get_mut::<MeshRenderer>(model).unwrap();
+                        mesh.layers = Layers(value.as_int() as _);
+                        self.recreate_command_buffers();
+                    }
+                    "r.render_scale" => {
+                        // The scene images are created again at the new scale
+                        self.render_scale = value.as_float();
+                        self.recreate_swapchain();
+                    }
+                    "r.exposure.compensation" => {
+                        let auto_exposure = self.auto_exposure.as_mut().unwrap();
+                        let mut parameters = auto_exposure.parameters();
+                        parameters.compensation = value.as_float();
+                        auto_exposure.set_parameters(parameters);
+                    }
+                    "r.shadow.filter" => {
+                        let filter = match value.as_int() {
+                            1 => ShadowFilter::Pcss,
+                            2 => ShadowFilter::Variance,
+                            _ => ShadowFilter::Pcf,
+                        };
+                        // Only the variance reads the moments, stored by another shadow map
+                        let stores_moments = self
+                            .shadow_map
+                            .as_ref()
+                            .is_some_and(ShadowMap::stores_moments);
+                        if (filter == ShadowFilter::Variance) == stores_moments {
+                            self.shadow_filter = filter;
+                        } else {
+                            let console = self.console.as_mut().unwrap();
+                            console.print(format!(
+                                "The shadow map cannot be filtered with {:?}.",
+                                filter
+                            ));
+                            console.set(name, CVarValue::Int(self.shadow_filter as _));
+                        }
+                    }
+                    "r.background" => {
+                        let mode = match value.as_int() {
+                            1 => BackgroundMode::Gradient,
+                            2 => BackgroundMode::Grid,
+                            _ => BackgroundMode::Color,
+                        };
+                        self.set_background_mode(mode);
+                    }
+                    "r.clear_color.r" | "r.clear_color.g" | "r.clear_color.b" => {
+                        let channel = match name {
+                            "r.clear_color.r" => 0,
+                            "r.clear_color.g" => 1,
+                            _ => 2,
+                        };
+                        self.clear_color[channel] = value.as_float();
+                        self.recreate_command_buffers();
+                    }
+                    "hud.stats" => self.hud_stats = value.as_bool(),
+                    "hud.profile" => {
+                        self.last_profile_log = if value.as_bool() {
+                            Some(Instant::now())
+                        } else {
+                            None
+                        };
+                    }
+                    _ => unreachable!("Unhandled console variable {}", name),
+                }
+                false
             }
+            ConsoleAction::Command("step", _) => {
+                self.time_controller.step();
+                false
+            }
+            ConsoleAction::Command("frame", _) => {
+                self.frame_selection();
+                false
+            }
+            ConsoleAction::Command("reload", _) => {
+                self.is_reload_requested = true;
+                false
+            }
+            ConsoleAction::Command("quit", _) => true,
+            ConsoleAction::Command(name, _) => unreachable!("Unhandled console command {}", name),
         }
-        panic!("Failed to find suitable memory type.")
     }
 
-    fn create_and_register_command_buffers(
-        device: &Device,
-        pool: vk::CommandPool,
-        framebuffers: &[vk::Framebuffer],
-        render_pass: vk::RenderPass,
-        swapchain_properties: SwapchainProperties,
-        vertex_buffer: vk::Buffer,
-        index_buffer: vk::Buffer,
-        index_count: usize,
-        pipeline_layout: vk::PipelineLayout,
-        descriptor_sets: &[vk::DescriptorSet],
-        graphics_pipeline: vk::Pipeline,
-    ) -> Vec<vk::CommandBuffer> {
-        let allocate_info = vk::CommandBufferAllocateInfo::builder()
-            .command_pool(pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(framebuffers.len() as _)
-            .build();
-
-        let buffers = unsafe { device.allocate_command_buffers(&allocate_info).unwrap() };
+    fn draw_frame(&mut self) {
+        profile_scope!("draw_frame");
+        log::trace!("Drawing frame.");
+        // The benchmark frames only depend on their index
+        if let Some(benchmark) = self.benchmark.as_ref() {
+            *self.camera_mut() = benchmark.camera();
+        }
+        // Runs while the render thread presents the previous frame
+        self.update_scene();
 
-        buffers.iter().enumerate().for_each(|(i, buffer)| {
-            let buffer = *buffer;
-            let framebuffer = framebuffers[i];
+        let sync_objects = self.in_flight_frames.next().unwrap();
+        let image_available_semaphore = sync_objects.image_available_semaphore;
+        let render_finished_semaphore = sync_objects.render_finished_semaphore;
+        let in_flight_fence = sync_objects.fence;
+        let wait_fences = [in_flight_fence];
 
-            // begin command buffer
-            {
-                let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
-                    .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
-                    // .inheritance_info() null since it's a primary command buffer
-                    .build();
-                unsafe {
-                    device
-                        .begin_command_buffer(buffer, &command_buffer_begin_info)
-                        .unwrap()
-                };
+        let result = {
+            profile_scope!("wait_fence");
+            unsafe {
+                self.vk_context
+                    .device()
+                    .wait_for_fences(&wait_fences, true, u64::MAX)
             }
-
-            // begin render pass
-            {
-                let clear_values = [
-                    vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    },
-                    vk::ClearValue {
-                        depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 1.0,
-                            stencil: 0,
-                        },
-                    },
-                ];
-                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-                    .render_pass(render_pass)
-                    .framebuffer(framebuffer)
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: swapchain_properties.extent,
-                    })
-                    .clear_values(&clear_values)
-                    .build();
-
-                unsafe {
-                    device.cmd_begin_render_pass(
-                        buffer,
-                        &render_pass_begin_info,
-                        vk::SubpassContents::INLINE,
-                    )
-                };
+        };
+        match result {
+            Ok(()) => {}
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                self.is_device_lost = true;
+                return;
             }
+            Err(error) => panic!("Error while waiting for fences. Cause: {}", error),
+        }
 
-            // Bind pipeline
-            unsafe {
-                device.cmd_bind_pipeline(buffer, vk::PipelineBindPoint::GRAPHICS, graphics_pipeline)
-            };
+        let image_index = match self.acquire_next_image(image_available_semaphore) {
+            Some(image_index) => image_index,
+            None => return,
+        };
 
-            // Bind vertex buffer
-            let vertex_buffers = [vertex_buffer];
-            let offsets = [0];
-            unsafe { device.cmd_bind_vertex_buffers(buffer, 0, &vertex_buffers, &offsets) };
+        unsafe { self.vk_context.device().reset_fences(&wait_fences).unwrap() };
 
-            // Bind index buffer
-            unsafe { device.cmd_bind_index_buffer(buffer, index_buffer, 0, vk::IndexType::UINT32) };
+        #[cfg(feature = "openxr")]
+        self.begin_xr_frame();
+        self.submit_batch = SubmitBatch::new(in_flight_fence);
+        self.update_uniform_buffers(image_index);
 
-            // Bind descriptor set
-            unsafe {
-                let null = [];
-                device.cmd_bind_descriptor_sets(
-                    buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    pipeline_layout,
-                    0,
-                    &descriptor_sets[i..=i],
-                    &null,
-                )
+        // The uploads added to the batch run before the commands of the frame
+        let mut batch = std::mem::take(&mut self.submit_batch);
+        batch.wait(
+            image_available_semaphore,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        );
+        batch.push(self.command_buffers[image_index as usize]);
+        batch.signal(
+            render_finished_semaphore,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        );
+        self.submit_stats = batch.stats();
+        log::trace!("Submitting frame: {:?}", self.submit_stats);
+        if let Some(gpu_crash_dump) = self.gpu_crash_dump.as_mut() {
+            gpu_crash_dump.add_submit(image_index, self.submit_stats);
+        }
+
+        if self.render_thread.is_some() {
+            // The render thread does not use the queues until the frame is sent
+            self.draw_secondary_window();
+            let (_, swapchain_khr) = self.swapchain.as_ref().unwrap();
+            let frame = RenderFrame {
+                render_list: self.render_list.clone(),
+                batch,
+                render_finished: render_finished_semaphore,
+                present_acquire: self
+                    .present_transfer
+                    .as_ref()
+                    .map(|present_transfer| present_transfer.acquire(image_index as _)),
+                swapchain: *swapchain_khr,
+                image_index,
             };
+            self.render_thread.as_ref().unwrap().present(frame);
+            if self.resize_dimensions.is_some() {
+                self.recreate_swapchain();
+            }
+            return;
+        }
 
-            // Draw
-            unsafe { device.cmd_draw_indexed(buffer, index_count as _, 1, 0, 0, 0) };
+        let device = self.vk_context.device();
+        let mut signal_semaphores = [render_finished_semaphore];
 
-            // End render pass
-            unsafe { device.cmd_end_render_pass(buffer) };
+        // Submit command buffer
+        {
+            profile_scope!("submit");
+            let result = unsafe { batch.submit(device, self.graphics_queue) };
+            match result {
+                Ok(()) => {}
+                Err(vk::Result::ERROR_DEVICE_LOST) => {
+                    self.is_device_lost = true;
+                    return;
+                }
+                Err(error) => panic!("Failed to submit command buffer. Cause: {}", error),
+            }
+        }
 
-            // End command buffer
-            unsafe { device.end_command_buffer(buffer).unwrap() };
-        });
+        // Acquire the image on the present queue once it is rendered
+        if let Some(present_transfer) = self.present_transfer.as_ref() {
+            let acquire = present_transfer.acquire(image_index as _);
+            let result =
+                unsafe { acquire.submit(device, self.present_queue, render_finished_semaphore) };
+            match result {
+                Ok(semaphore) => signal_semaphores = [semaphore],
+                Err(vk::Result::ERROR_DEVICE_LOST) => {
+                    self.is_device_lost = true;
+                    return;
+                }
+                Err(error) => panic!("Failed to submit the image acquisition. Cause: {}", error),
+            }
+        }
 
-        buffers
-    }
+        let (swapchain, swapchain_khr) = self.swapchain.as_ref().unwrap();
+        let swapchains = [*swapchain_khr];
+        let images_indices = [image_index];
 
-    fn create_sync_objects(device: &Device) -> InFlightFrames {
-        let mut sync_objects_vec = Vec::new();
-        for _ in 0..MAX_FRAMES_IN_FLIGHT {
-            let image_available_semaphore = {
-                let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
-                unsafe { device.create_semaphore(&semaphore_info, None).unwrap() }
-            };
+        {
+            let present_info = vk::PresentInfoKHR::builder()
+                .wait_semaphores(&signal_semaphores)
+                .swapchains(&swapchains)
+                .image_indices(&images_indices)
+                // .results() null since we only have one swapchain
+                .build();
+            let result = unsafe { swapchain.queue_present(self.present_queue, &present_info) };
+            match result {
+                Ok(is_suboptimal) if is_suboptimal => {
+                    self.recreate_swapchain();
+                }
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.recreate_swapchain();
+                }
+                Err(vk::Result::ERROR_DEVICE_LOST) => {
+                    self.is_device_lost = true;
+                    return;
+                }
+                Err(error) => panic!("Failed to present queue. Cause: {}", error),
+                _ => {}
+            }
 
-            let render_finished_semaphore = {
-                let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
-                unsafe { device.create_semaphore(&semaphore_info, None).unwrap() }
-            };
+            if self.resize_dimensions.is_some() {
+                self.recreate_swapchain();
+            }
+        }
 
-            let in_flight_fence = {
-                let fence_info = vk::FenceCreateInfo::builder()
-                    .flags(vk::FenceCreateFlags::SIGNALED)
-                    .build();
-                unsafe { device.create_fence(&fence_info, None).unwrap() }
-            };
+        #[cfg(feature = "openxr")]
+        self.submit_xr_frame();
+        self.draw_secondary_window();
+    }
 
-            let sync_objects = SyncObjects {
-                image_available_semaphore,
-                render_finished_semaphore,
-                fence: in_flight_fence,
-            };
-            sync_objects_vec.push(sync_objects)
+    /// Acquire the next swapchain image, on the render thread if there is one.
+    ///
+    /// Return `None` if the swapchain was recreated or the device was lost.
+    fn acquire_next_image(&mut self, semaphore: vk::Semaphore) -> Option<u32> {
+        let (swapchain, swapchain_khr) = self.swapchain.as_ref().unwrap();
+        let result = match self.render_thread.as_ref() {
+            Some(render_thread) => render_thread.acquire(*swapchain_khr, semaphore),
+            None => unsafe {
+                swapchain
+                    .acquire_next_image(*swapchain_khr, u64::MAX, semaphore, vk::Fence::null())
+                    .map(|(image_index, _)| AcquiredImage::Image(image_index))
+            },
+        };
+        match result {
+            Ok(AcquiredImage::Image(image_index)) => Some(image_index),
+            Ok(AcquiredImage::OutOfDate) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.recreate_swapchain();
+                None
+            }
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                self.is_device_lost = true;
+                None
+            }
+            Err(error) => panic!("Error while acquiring next image. Cause: {}", error),
         }
-
-        InFlightFrames::new(sync_objects_vec)
     }
 
-    fn run(&mut self) {
-        log::debug!("Running application.");
-        loop {
-            if self.process_event() {
-                break;
+    /// Wait for the render thread and the device to be idle.
+    ///
+    /// Return false and set `is_device_lost` if the device was lost.
+    fn wait_idle(&mut self) -> bool {
+        if let Some(render_thread) = self.render_thread.as_ref() {
+            render_thread.finish();
+        }
+        match unsafe { self.vk_context.device().device_wait_idle() } {
+            Ok(()) => !self.is_device_lost,
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                self.is_device_lost = true;
+                false
             }
-            self.draw_frame()
+            Err(error) => panic!("Error while waiting for the device. Cause: {}", error),
         }
-        unsafe { self.vk_context.device().device_wait_idle().unwrap() };
     }
 
-    /// Process the events from the `EventsLoop` and return whether the
-    /// main loop should stop.
-    fn process_event(&mut self) -> bool {
-        let mut should_stop = false;
-        let mut resize_dimensions = None;
-        let mut is_left_clicked = None;
-        let mut cursor_position = None;
-        let mut last_position = self.cursor_position;
-        let mut wheel_delta = None;
+    /// Process the OpenXR events and begin a headset frame.
+    ///
+    /// OpenXR is disabled when the session ends or fails.
+    #[cfg(feature = "openxr")]
+    fn begin_xr_frame(&mut self) {
+        let result = match self.xr.as_mut() {
+            Some((runtime, session)) => session
+                .poll_events(runtime)
+                .and_then(|is_alive| session.begin_frame().map(|_| is_alive)),
+            None => return,
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                log::debug!("OpenXR session ended.");
+                self.destroy_xr();
+            }
+            Err(error) => {
+                log::warn!("OpenXR frame failed, disabling OpenXR. Cause: {}", error);
+                self.destroy_xr();
+            }
+        }
+    }
 
-        self.events_loop.poll_events(|event| match event {
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => should_stop = true,
-                WindowEvent::Resized(LogicalSize { width, height }) => {
-                    resize_dimensions = Some([width as u32, height as u32]);
-                }
-                WindowEvent::MouseInput {
-                    button: MouseButton::Left,
-                    state,
-                    ..
-                } => {
-                    if state == ElementState::Pressed {
-                        is_left_clicked = Some(true);
-                    } else {
-                        is_left_clicked = Some(false);
-                    }
-                }
-                WindowEvent::CursorMoved { position, .. } => {
-                    let position: (i32, i32) = position.into();
-                    cursor_position = Some([position.0, position.1]);
-                }
-                WindowEvent::Touch(Touch {
-                    location, phase, ..
-                }) => {
-                    let position: (i32, i32) = location.into();
-                    cursor_position = Some([-position.0, -position.1]);
+    /// Copy the eyes of the stereo pass to the headset and submit the frame.
+    #[cfg(feature = "openxr")]
+    fn submit_xr_frame(&mut self) {
+        let (session, stereo) = match (self.xr.as_mut(), self.stereo.as_ref()) {
+            (Some((_, session)), Some(stereo)) => (session, stereo),
+            _ => return,
+        };
+        let result =
+            session.submit_frame(self.vk_context.device(), self.graphics_queue, stereo.eyes());
+        if let Err(error) = result {
+            log::warn!("OpenXR frame failed, disabling OpenXR. Cause: {}", error);
+            self.destroy_xr();
+        }
+    }
 
-                    if phase == TouchPhase::Started {
-                        last_position = cursor_position.unwrap();
-                        is_left_clicked = Some(true);
-                    } else if phase == TouchPhase::Ended {
-                        is_left_clicked = Some(false);
-                    }
-                }
-                WindowEvent::MouseWheel {
-                    delta: MouseScrollDelta::LineDelta(_, v_lines),
-                    ..
-                } => {
-                    wheel_delta = Some(v_lines);
-                }
-                _ => {}
-            },
-            _ => {}
-        });
+    /// Destroy the OpenXR session and runtime.
+    #[cfg(feature = "openxr")]
+    fn destroy_xr(&mut self) {
+        if let Some((_, mut session)) = self.xr.take() {
+            // The session is destroyed with the device if it was lost
+            self.wait_idle();
+            session.destroy(self.vk_context.device(), self.command_pool);
+        }
+    }
 
-        self.resize_dimensions = resize_dimensions;
-        if let Some(is_left_clicked) = is_left_clicked {
-            self.is_left_clicked = is_left_clicked;
+    /// Draw a frame in the secondary window if it has a swapchain.
+    fn draw_secondary_window(&mut self) {
+        profile_scope!("secondary_window");
+        match self.secondary_window.as_ref() {
+            Some(secondary_window) if secondary_window.has_targets() => {}
+            _ => return,
         }
-        if let Some(position) = cursor_position {
-            self.cursor_position = position;
-            self.cursor_delta = Some([
-                position[0] - last_position[0],
-                position[1] - last_position[1],
-            ]);
-        } else {
-            self.cursor_delta = None;
+        let secondary_window = self.secondary_window.as_ref().unwrap();
+        let result = secondary_window.draw(
+            self.vk_context.device(),
+            (self.graphics_queue, self.present_queue),
+            self.create_frame_ubo(),
+        );
+        match result {
+            Ok(true) => {
+                if let Err(error) = self.recreate_secondary_window() {
+                    self.allocation_error = Some(error);
+                }
+            }
+            Ok(false) => {}
+            Err(vk::Result::ERROR_DEVICE_LOST) => self.is_device_lost = true,
+            Err(error) => panic!("Failed to draw the secondary window. Cause: {}", error),
         }
-        self.wheel_delta = wheel_delta;
-        should_stop
     }
 
-    fn draw_frame(&mut self) {
-        log::trace!("Drawing frame.");
-        let sync_objects = self.in_flight_frames.next().unwrap();
-        let image_available_semaphore = sync_objects.image_available_semaphore;
-        let render_finished_semaphore = sync_objects.render_finished_semaphore;
-        let in_flight_fence = sync_objects.fence;
+    /// Draw a frame in the offscreen target and read it back.
+    ///
+    /// There is a single offscreen image so the frame is waited for before returning.
+    fn draw_offscreen_frame(&mut self) -> VkResult<image::RgbaImage> {
+        log::trace!("Drawing offscreen frame.");
+        let in_flight_fence = self.in_flight_frames.next().unwrap().fence;
         let wait_fences = [in_flight_fence];
 
+        let device = self.vk_context.device();
         unsafe {
-            self.vk_context
-                .device()
-                .wait_for_fences(&wait_fences, true, std::u64::MAX)
-                .unwrap()
+            device.wait_for_fences(&wait_fences, true, u64::MAX)?;
+            device.reset_fences(&wait_fences)?;
         };
 
-        let result = unsafe {
-            self.swapchain.acquire_next_image(
-                self.swapchain_khr,
-                std::u64::MAX,
-                image_available_semaphore,
-                vk::Fence::null(),
-            )
+        self.update_scene();
+        self.submit_batch = SubmitBatch::new(in_flight_fence);
+        self.update_uniform_buffers(0);
+
+        let device = self.vk_context.device();
+        let mut batch = std::mem::take(&mut self.submit_batch);
+        batch.push(self.command_buffers[0]);
+        unsafe {
+            batch.submit(device, self.graphics_queue)?;
+            device.wait_for_fences(&wait_fences, true, u64::MAX)?;
         };
-        let image_index = match result {
-            Ok((image_index, _)) => image_index,
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                self.recreate_swapchain();
-                return;
+
+        let target = self.offscreen_target.as_ref().unwrap();
+        Self::execute_one_time_commands(
+            device,
+            self.transient_command_pool,
+            self.graphics_queue,
+            |buffer| target.cmd_copy_to_readback_buffer(device, buffer),
+        );
+        Ok(target.read_pixels(device))
+    }
+
+    /// Recreates the swapchain.
+    ///
+    /// If the window has been resized, then the new size is used
+    /// otherwise, the size of the current swapchain is used.
+    ///
+    /// If the window has been minimized, then the functions block until
+    /// the window is maximized. This is because a width or height of 0
+    /// is not legal.
+    /// Replace the background by one drawn with `mode`, keeping the other
+    /// parameters of the command line.
+    ///
+    /// Its pipeline is created with the swapchain, which is recreated.
+    fn set_background_mode(&mut self, mode: BackgroundMode) {
+        if !self.wait_idle() {
+            return;
+        }
+        if let Some(mut background) = self.background.take() {
+            background.destroy(self.vk_context.device());
+        }
+        let parameters = BackgroundParameters {
+            mode,
+            clear_color: self.clear_color,
+            ..Self::get_background()
+        };
+        self.background = match mode {
+            BackgroundMode::Gradient | BackgroundMode::Grid => {
+                match Self::create_background(&self.vk_context, parameters) {
+                    Ok(background) => Some(background),
+                    Err(error) => {
+                        self.allocation_error = Some(error);
+                        return;
+                    }
+                }
             }
-            Err(error) => panic!("Error while acquiring next image. Cause: {}", error),
+            BackgroundMode::Color | BackgroundMode::Sky => None,
         };
+        self.recreate_swapchain();
+    }
 
-        unsafe { self.vk_context.device().reset_fences(&wait_fences).unwrap() };
+    fn recreate_swapchain(&mut self) {
+        log::debug!("Recreating swapchain.");
 
-        self.update_uniform_buffers(image_index);
+        if self.has_window_been_minimized() {
+            while !self.has_window_been_maximized() {
+                self.process_event();
+            }
+        }
+
+        // The swapchain must not be presented to while it is destroyed
+        if !self.wait_idle() {
+            return;
+        }
+
+        self.cleanup_swapchain();
+        if let Err(error) = self.create_swapchain_resources() {
+            self.allocation_error = Some(error);
+        }
+    }
+
+    /// Destroy the swapchain and the surface when the app goes to the background.
+    ///
+    /// On Android, the native window the surface was created from is destroyed
+    /// when the app is paused.
+    fn suspend(&mut self) {
+        log::debug!("Suspending application.");
+        if !self.wait_idle() {
+            return;
+        }
+        self.cleanup_swapchain();
+        self.vk_context.replace_surface(vk::SurfaceKHR::null());
+        self.is_suspended = true;
+    }
+
+    /// Create the surface and the swapchain again when the app is resumed.
+    fn resume(&mut self) {
+        log::debug!("Resuming application.");
+        let surface_khr = unsafe {
+            surface::create_surface(
+                self.vk_context.entry(),
+                self.vk_context.instance(),
+                self.window.as_ref().unwrap(),
+            )
+            .unwrap()
+        };
+        self.vk_context.replace_surface(surface_khr);
+        // The window may have been resized or rotated while in the background
+        self.resize_dimensions = self
+            .window
+            .as_ref()
+            .and_then(Window::get_inner_size)
+            .map(|LogicalSize { width, height }| [width as u32, height as u32]);
+        if let Err(error) = self.create_swapchain_resources() {
+            self.allocation_error = Some(error);
+        }
+        self.is_suspended = false;
+    }
 
+    /// Create the swapchain and everything depending on it.
+    ///
+    /// The previous ones must have been destroyed with `cleanup_swapchain`.
+    fn create_swapchain_resources(&mut self) -> Result<(), AllocationError> {
         let device = self.vk_context.device();
-        let wait_semaphores = [image_available_semaphore];
-        let signal_semaphores = [render_finished_semaphore];
 
-        // Submit command buffer
+        let dimensions = self.resize_dimensions.unwrap_or([
+            self.swapchain_properties.extent.width,
+            self.swapchain_properties.extent.height,
+        ]);
+        let (_, surface_khr) = self.vk_context.surface().unwrap();
+        let (swapchain, swapchain_khr, properties, images) = Self::create_swapchain_and_images(
+            &self.vk_context,
+            self.queue_families_indices,
+            (surface_khr, true),
+            dimensions,
+        );
+        let swapchain_image_views = Self::create_swapchain_image_views(device, &images, properties);
+        self.present_transfer =
+            Self::create_present_transfer(device, self.queue_families_indices, &images);
+        let swapchain_properties = properties;
+        let properties = Self::get_render_properties(properties, self.render_scale);
+        let hdr = self.hdr_texture.is_some();
+        let scene_properties = Self::get_scene_properties(properties, hdr);
+
+        let render_pass = Self::create_render_pass(
+            device,
+            scene_properties,
+            self.msaa_samples,
+            (
+                self.depth_format,
+                self.depth_pyramid.is_some()
+                    || self.half_res.any()
+                    || self.ray_traced_shadows.is_some(),
+            ),
+            Self::get_scene_final_layout(false, hdr),
+        );
+        let (pipeline, layout) = Self::create_pipeline(
+            device,
+            (properties, self.msaa_samples, render_pass),
+            (
+                &self.frame_layout,
+                &self.material_instances,
+                &mut self.shader_variants,
+            ),
+            (
+                self.bindless_textures.as_ref(),
+                self.mesh_shading.as_ref(),
+                self.reflection_probes.as_ref(),
+            ),
+            self.vertex_buffer.device_address().is_some() && self.mesh_shading.is_none(),
+            self.outline_pipeline.map(|_| outlined_stencil()),
+        );
+        if let Some(plane) = self.displaced_plane.as_mut() {
+            Self::create_displacement_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                plane,
+            );
+        }
+        if let Some(terrain) = self.terrain.as_mut() {
+            Self::create_terrain_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                terrain,
+            );
+        }
+        if let Some(mesh_streamer) = self.mesh_streamer.as_mut() {
+            Self::create_mesh_streamer_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                mesh_streamer,
+            );
+        }
+        if let Some(objects) = self.objects.as_mut() {
+            let (pipeline, layout) = Self::create_object_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                objects.layout(),
+            );
+            objects.set_pipeline(pipeline, layout);
+        }
+        if let Some(static_batches) = self.static_batches.as_mut() {
+            let (pipeline, layout) = Self::create_object_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                static_batches.layout(),
+            );
+            static_batches.set_pipeline(pipeline, layout);
+        }
+        if let (Some(gpu_driven), Some(bindless_textures)) =
+            (self.gpu_driven.as_mut(), self.bindless_textures.as_ref())
         {
-            let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-            let command_buffers = [self.command_buffers[image_index as usize]];
-            let submit_info = vk::SubmitInfo::builder()
-                .wait_semaphores(&wait_semaphores)
-                .wait_dst_stage_mask(&wait_stages)
-                .command_buffers(&command_buffers)
-                .signal_semaphores(&signal_semaphores)
-                .build();
-            let submit_infos = [submit_info];
-            unsafe {
-                device
-                    .queue_submit(self.graphics_queue, &submit_infos, in_flight_fence)
-                    .unwrap()
-            };
+            Self::create_gpu_driven_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                (&self.frame_layout, bindless_textures),
+                gpu_driven,
+            );
+        }
+        if let Some(virtual_texture) = self.virtual_texture.as_mut() {
+            Self::create_virtual_texture_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                virtual_texture,
+            );
+        }
+        if let Some(water) = self.water.as_mut() {
+            Self::create_water_pipelines(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                water,
+            );
+        }
+        if let Some(secondary_views) = self.secondary_views.as_mut() {
+            Self::create_secondary_views_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                secondary_views,
+            );
+        }
+        if let Some(mirror) = self.mirror.as_mut() {
+            Self::create_mirror_pipelines(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                mirror,
+            );
+        }
+        if let Some(stereo) = self.stereo.as_mut() {
+            let (color, depth) = Self::create_stereo_textures(
+                &self.vk_context,
+                (scene_properties.format.format, self.depth_format),
+                eye_extent(properties.extent),
+            )?;
+            stereo.resize(device, eye_extent(properties.extent), color, depth);
+            Self::create_stereo_pipelines(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                stereo,
+            );
+        }
+        if let Some(sky) = self.sky.as_mut() {
+            Self::create_sky_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                sky,
+            );
+        }
+        if let Some(background) = self.background.as_mut() {
+            Self::create_background_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                background,
+            );
+        }
+        if let Some(sprites) = self.sprites.as_mut() {
+            Self::create_sprite_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                sprites,
+            );
+        }
+        if let Some(particles) = self.particles.as_mut() {
+            Self::create_particle_draw_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                particles,
+            );
         }
-
-        let swapchains = [self.swapchain_khr];
-        let images_indices = [image_index];
-
+        if let Some(decals) = self.decals.as_mut() {
+            Self::create_decals_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                decals,
+            );
+        }
+        let hdr_texture = self
+            .hdr_texture
+            .map(|_| Self::create_hdr_texture(&self.vk_context, properties.extent))
+            .transpose()?;
+        if let (Some(exposure), Some(hdr)) = (self.auto_exposure.as_mut(), hdr_texture.as_ref()) {
+            exposure.set_hdr_texture(device, hdr, properties.extent);
+        }
+        if let (Some(bloom), Some(hdr)) = (self.bloom.as_mut(), hdr_texture.as_ref()) {
+            Self::set_bloom_chain(&self.vk_context, bloom, hdr, properties.extent)?;
+        }
+        if let Some(ssr) = self.ssr.as_mut() {
+            Self::resize_render_target(&self.vk_context, ssr.target_mut(), properties.extent)?;
+        }
+        if let (Some(light_shafts), Some(hdr)) = (self.light_shafts.as_mut(), hdr_texture.as_ref())
         {
-            let present_info = vk::PresentInfoKHR::builder()
-                .wait_semaphores(&signal_semaphores)
-                .swapchains(&swapchains)
-                .image_indices(&images_indices)
-                // .results() null since we only have one swapchain
-                .build();
-            let result = unsafe {
-                self.swapchain
-                    .queue_present(self.present_queue, &present_info)
+            let shafts_extent = if let Some(upsample) = light_shafts.upsample_mut() {
+                Self::resize_render_target(
+                    &self.vk_context,
+                    upsample.target_mut(),
+                    properties.extent,
+                )?;
+                half_extent(properties.extent)
+            } else {
+                properties.extent
             };
-            match result {
-                Ok(is_suboptimal) if is_suboptimal => {
-                    self.recreate_swapchain();
-                }
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                    self.recreate_swapchain();
-                }
-                Err(error) => panic!("Failed to present queue. Cause: {}", error),
-                _ => {}
-            }
-
-            if self.resize_dimensions.is_some() {
-                self.recreate_swapchain();
-            }
+            Self::resize_render_target(&self.vk_context, light_shafts.target_mut(), shafts_extent)?;
+            light_shafts.set_hdr_texture(
+                device,
+                Self::get_post_output(hdr, self.ssr.as_ref(), None, None, None),
+            );
+            Self::create_light_shafts_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                &self.frame_layout,
+                light_shafts,
+            );
         }
-    }
-
-    /// Recreates the swapchain.
-    ///
-    /// If the window has been resized, then the new size is used
-    /// otherwise, the size of the current swapchain is used.
-    ///
-    /// If the window has been minimized, then the functions block until
-    /// the window is maximized. This is because a width or height of 0
-    /// is not legal.
-    fn recreate_swapchain(&mut self) {
-        log::debug!("Recreating swapchain.");
-
-        if self.has_window_been_minimized() {
-            while !self.has_window_been_maximized() {
-                self.process_event();
+        if let (Some(motion_blur), Some(hdr)) = (self.motion_blur.as_mut(), hdr_texture.as_ref()) {
+            let (velocity_target, blur_target) = motion_blur.targets_mut();
+            Self::resize_render_target(&self.vk_context, velocity_target, properties.extent)?;
+            Self::resize_render_target(&self.vk_context, blur_target, properties.extent)?;
+            motion_blur.set_hdr_texture(
+                device,
+                Self::get_post_output(
+                    hdr,
+                    self.ssr.as_ref(),
+                    self.light_shafts.as_ref(),
+                    None,
+                    None,
+                ),
+            );
+            Self::create_motion_blur_pipelines(device, properties, motion_blur);
+        }
+        if let (Some(fsr), Some(hdr)) = (self.fsr.as_mut(), hdr_texture.as_ref()) {
+            Self::set_fsr_targets(
+                &self.vk_context,
+                fsr,
+                Self::get_post_output(
+                    hdr,
+                    self.ssr.as_ref(),
+                    self.light_shafts.as_ref(),
+                    self.motion_blur.as_ref(),
+                    None,
+                ),
+                swapchain_properties.extent,
+            )?;
+        }
+        if let (Some(tonemapper), Some(hdr)) = (self.tonemapper.as_mut(), hdr_texture.as_ref()) {
+            tonemapper.set_targets(
+                device,
+                Self::get_post_output(
+                    hdr,
+                    self.ssr.as_ref(),
+                    self.light_shafts.as_ref(),
+                    self.motion_blur.as_ref(),
+                    self.fsr.as_ref(),
+                ),
+                swapchain_properties,
+                Self::get_final_layout(false),
+                &swapchain_image_views,
+            );
+            if let Some(bloom) = self.bloom.as_ref() {
+                tonemapper.set_bloom(device, bloom.output());
             }
+            Self::create_tonemap_pipeline(
+                device,
+                swapchain_properties,
+                &mut self.shader_variants,
+                self.bloom.is_some(),
+                tonemapper,
+            );
         }
-
-        unsafe { self.vk_context.device().device_wait_idle().unwrap() };
-
-        self.cleanup_swapchain();
-
-        let device = self.vk_context.device();
-
-        let dimensions = self.resize_dimensions.unwrap_or([
-            self.swapchain_properties.extent.width,
-            self.swapchain_properties.extent.height,
-        ]);
-        let (swapchain, swapchain_khr, properties, images) = Self::create_swapchain_and_images(
-            &self.vk_context,
-            self.queue_families_indices,
-            dimensions,
-        );
-        let swapchain_image_views = Self::create_swapchain_image_views(device, &images, properties);
-
-        let render_pass =
-            Self::create_render_pass(device, properties, self.msaa_samples, self.depth_format);
-        let (pipeline, layout) = Self::create_pipeline(
+        if let Some(hud) = self.hud.as_mut() {
+            Self::create_text_pipeline(
+                device,
+                swapchain_properties,
+                self.msaa_samples,
+                render_pass,
+                self.tonemapper.as_ref(),
+                hud,
+            );
+        }
+        Self::create_debug_draw_pipeline(
             device,
             properties,
             self.msaa_samples,
             render_pass,
-            self.descriptor_set_layout,
+            &self.frame_layout,
+            &mut self.debug_draw,
         );
+        if let Some(occlusion_queries) = self.occlusion_queries.as_mut() {
+            Self::create_occlusion_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+                occlusion_queries,
+            );
+        }
+        let normals_pipeline = self.normals_pipeline.map(|_| {
+            Self::create_normals_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+            )
+        });
+        let outline_pipeline = self.outline_pipeline.map(|_| {
+            Self::create_outline_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                render_pass,
+                &self.frame_layout,
+            )
+        });
 
         let color_texture = Self::create_color_texture(
             &self.vk_context,
             self.command_pool,
             self.graphics_queue,
-            properties,
+            scene_properties,
             self.msaa_samples,
-        );
+        )?;
 
         let depth_texture = Self::create_depth_texture(
             &self.vk_context,
             self.command_pool,
             self.graphics_queue,
-            self.depth_format,
+            (
+                self.depth_format,
+                self.depth_pyramid.is_some()
+                    || self.half_res.any()
+                    || self.ray_traced_shadows.is_some(),
+            ),
             properties.extent,
             self.msaa_samples,
-        );
+        )?;
+        if let Some(decals) = self.decals.as_ref() {
+            decals.set_depth_attachment(device, depth_texture.view);
+        }
+        if let Some(depth_pyramid) = self.depth_pyramid.as_mut() {
+            Self::set_depth_pyramid(
+                &self.vk_context,
+                self.command_pool,
+                self.graphics_queue,
+                depth_pyramid,
+                (&depth_texture, self.depth_format),
+                properties.extent,
+            )?;
+            if let Some(hiz) = self.hiz.as_mut() {
+                hiz.set_pyramid(device, depth_pyramid);
+            }
+        }
+        let upsample = self
+            .light_shafts
+            .as_mut()
+            .and_then(LightShafts::upsample_mut);
+        if let Some(upsample) = upsample {
+            Self::set_upsample_depth(
+                device,
+                upsample,
+                (&depth_texture, self.depth_format),
+                self.depth_pyramid.is_some(),
+            );
+        }
+        if let (Some(shadows), Some(hdr)) = (self.ray_traced_shadows.as_mut(), hdr_texture.as_ref())
+        {
+            shadows.set_images(
+                device,
+                hdr,
+                (&depth_texture, Self::get_depth_aspect(self.depth_format)),
+                properties.extent,
+            );
+            Self::create_ray_traced_shadows_pipeline(&self.vk_context, self.msaa_samples, shadows)?;
+        }
+        if let (Some(ssr), Some(hdr)) = (self.ssr.as_mut(), hdr_texture.as_ref()) {
+            Self::set_ssr_inputs(
+                &self.vk_context,
+                (self.command_pool, self.graphics_queue),
+                ssr,
+                hdr,
+                &depth_texture,
+                self.depth_pyramid.as_ref().unwrap(),
+                self.motion_blur.as_ref().unwrap(),
+            )?;
+            Self::create_ssr_pipeline(
+                device,
+                properties,
+                self.msaa_samples,
+                &self.frame_layout,
+                &mut self.shader_variants,
+                self.reflection_probes.as_ref(),
+                ssr,
+            );
+        }
 
         let swapchain_framebuffers = Self::create_framebuffers(
             device,
-            &swapchain_image_views,
+            &Self::get_scene_views(&swapchain_image_views, hdr_texture.as_ref()),
             color_texture,
             depth_texture,
             render_pass,
             properties,
         );
 
-        let command_buffers = Self::create_and_register_command_buffers(
+        // The command buffers are recorded again, unless the number of images changed
+        let command_buffers = if self.command_buffers.len() == swapchain_framebuffers.len() {
+            std::mem::take(&mut self.command_buffers)
+        } else {
+            if !self.command_buffers.is_empty() {
+                unsafe { device.free_command_buffers(self.command_pool, &self.command_buffers) };
+            }
+            Self::allocate_command_buffers(device, self.command_pool, swapchain_framebuffers.len())
+        };
+        let bind_stats = Self::record_command_buffers(
             device,
-            self.command_pool,
-            &swapchain_framebuffers,
-            render_pass,
-            properties,
-            self.vertex_buffer,
-            self.index_buffer,
-            self.model_index_count,
-            layout,
-            &self.descriptor_sets,
-            pipeline,
+            &command_buffers,
+            FrameResources {
+                framebuffers: &swapchain_framebuffers,
+                render_pass,
+                swapchain_properties: properties,
+                pipeline_layout: layout,
+                normals_pipeline: normals_pipeline.filter(|_| self.debug_views.normals),
+                outline_pipeline: outline_pipeline.filter(|_| self.selected == Some(self.model)),
+                graphics_pipeline: pipeline,
+                ..self.frame_resources()
+            },
         );
 
-        self.swapchain = swapchain;
-        self.swapchain_khr = swapchain_khr;
-        self.swapchain_properties = properties;
+        self.swapchain = Some((swapchain, swapchain_khr));
+        self.swapchain_properties = swapchain_properties;
         self.images = images;
         self.swapchain_image_views = swapchain_image_views;
         self.render_pass = render_pass;
         self.pipeline = pipeline;
         self.pipeline_layout = layout;
+        self.normals_pipeline = normals_pipeline;
+        self.outline_pipeline = outline_pipeline;
         self.color_texture = color_texture;
         self.depth_texture = depth_texture;
+        self.hdr_texture = hdr_texture;
         self.swapchain_framebuffers = swapchain_framebuffers;
         self.command_buffers = command_buffers;
+        self.bind_stats = bind_stats;
+        Ok(())
+    }
+
+    /// Recreate the swapchain of the secondary window and everything depending on it.
+    ///
+    /// Nothing is created while the window is minimized, it is then not drawn
+    /// until it is resized again.
+    fn recreate_secondary_window(&mut self) -> Result<(), AllocationError> {
+        if !self.wait_idle() {
+            return Ok(());
+        }
+        let model_index_count = self.model_index_count();
+        let secondary_window = match self.secondary_window.as_mut() {
+            Some(secondary_window) => secondary_window,
+            None => return Ok(()),
+        };
+        log::debug!("Recreating secondary window swapchain.");
+
+        let device = self.vk_context.device();
+        secondary_window.destroy_targets(device, self.command_pool);
+
+        let dimensions = secondary_window.dimensions();
+        if dimensions.contains(&0) {
+            return Ok(());
+        }
+        let (swapchain, swapchain_khr, properties, images) = Self::create_swapchain_and_images(
+            &self.vk_context,
+            self.queue_families_indices,
+            (secondary_window.surface_khr(), false),
+            dimensions,
+        );
+        let image_views = Self::create_swapchain_image_views(device, &images, properties);
+        let depth_texture = Self::create_depth_texture(
+            &self.vk_context,
+            self.command_pool,
+            self.graphics_queue,
+            (self.depth_format, false),
+            properties.extent,
+            vk::SampleCountFlags::TYPE_1,
+        )?;
+        secondary_window.set_targets(
+            device,
+            (swapchain, swapchain_khr),
+            properties,
+            image_views,
+            (depth_texture, self.depth_format),
+        );
+        Self::create_secondary_window_pipeline(
+            device,
+            properties,
+            &self.frame_layout,
+            secondary_window,
+        );
+        secondary_window.record_command_buffers(
+            device,
+            self.command_pool,
+            self.vertex_buffer,
+            self.index_buffer,
+            model_index_count as _,
+        );
+        Ok(())
+    }
+
+    /// Destroy the secondary window after it was closed.
+    fn destroy_secondary_window(&mut self) {
+        if let Some(mut secondary_window) = self.secondary_window.take() {
+            log::debug!("Destroying secondary window.");
+            // The window is destroyed even if the device was lost
+            self.wait_idle();
+            let device = self.vk_context.device();
+            let (surface, _) = self.vk_context.surface().unwrap();
+            secondary_window.destroy(device, surface, self.command_pool);
+        }
+    }
+
+    /// Get the passes and resources the frame command buffers are recorded with.
+    ///
+    /// The normals and the outline are only drawn when their debug view is on
+    /// and the model is selected.
+    fn frame_resources(&self) -> FrameResources<'_> {
+        FrameResources {
+            framebuffers: &self.swapchain_framebuffers,
+            render_pass: self.render_pass,
+            swapchain_properties: self.render_properties(),
+            vertex_buffer: self.vertex_buffer,
+            index_buffer: self.index_buffer,
+            index_count: self.model_index_count(),
+            pass_layers: self.pass_layers,
+            model_layers: self.model_layers(),
+            pipeline_layout: self.pipeline_layout,
+            frame_layout: &self.frame_layout,
+            descriptor_sets: &self.descriptor_sets,
+            bindless_textures: self.bindless_textures.as_ref(),
+            material_index: self.model_material_index,
+            material_instances: &self.material_instances,
+            model_material: self.model_material,
+            mesh_shading: self.mesh_shading.as_ref(),
+            displaced_plane: self.displaced_plane.as_ref(),
+            terrain: self.terrain.as_ref(),
+            mesh_streamer: self.mesh_streamer.as_ref(),
+            objects: self.objects.as_ref(),
+            static_batches: self.static_batches.as_ref(),
+            gpu_driven: self.gpu_driven.as_ref(),
+            virtual_texture: self.virtual_texture.as_ref(),
+            depth_pyramid: self.depth_pyramid.as_ref(),
+            hiz: self.hiz.as_ref(),
+            water: self.water.as_ref(),
+            secondary_views: self.secondary_views.as_ref(),
+            mirror: self.mirror.as_ref(),
+            stereo: self.stereo.as_ref(),
+            sky: self.sky.as_ref(),
+            background: self.background.as_ref(),
+            clear_color: self.clear_color,
+            sprites: self.sprites.as_ref(),
+            particles: self.particles.as_ref(),
+            decals: self.decals.as_ref(),
+            debug_draw: &self.debug_draw,
+            occlusion_queries: self.occlusion_queries.as_ref(),
+            reflection_probes: self.reflection_probes.as_ref(),
+            lightmap: self.lightmap.as_ref(),
+            shadow_map: self.shadow_map.as_ref(),
+            shadow_atlas: self.shadow_atlas.as_ref(),
+            transient_allocator: &self.transient_allocator,
+            hud: self.hud.as_ref(),
+            auto_exposure: self.auto_exposure.as_ref(),
+            bloom: self.bloom.as_ref(),
+            tonemapper: self.tonemapper.as_ref(),
+            ssr: self.ssr.as_ref(),
+            ray_traced_shadows: self.ray_traced_shadows.as_ref(),
+            light_shafts: self.light_shafts.as_ref(),
+            motion_blur: self.motion_blur.as_ref(),
+            fsr: self.fsr.as_ref(),
+            gpu_timer: self.gpu_timer.as_ref(),
+            gpu_crash_dump: self.gpu_crash_dump.as_ref(),
+            present_transfer: self.present_transfer.as_ref(),
+            normals_pipeline: self.normals_pipeline.filter(|_| self.debug_views.normals),
+            outline_pipeline: self
+                .outline_pipeline
+                .filter(|_| self.selected == Some(self.model)),
+            graphics_pipeline: self.pipeline,
+        }
+    }
+
+    /// Record the command buffers again.
+    ///
+    /// It must be called when something changes what is drawn, like toggling a debug view.
+    fn recreate_command_buffers(&mut self) {
+        if !self.wait_idle() {
+            return;
+        }
+        let device = self.vk_context.device();
+
+        let bind_stats =
+            Self::record_command_buffers(device, &self.command_buffers, self.frame_resources());
+        self.bind_stats = bind_stats;
     }
 
     fn has_window_been_minimized(&self) -> bool {
@@ -2251,53 +11957,496 @@ impl VulkanApp {
         }
     }
 
-    /// Clean up the swapchain and all resources that depends on it.
-    fn cleanup_swapchain(&mut self) {
-        let device = self.vk_context.device();
-        unsafe {
-            self.depth_texture.destroy(device);
-            self.color_texture.destroy(device);
-            self.swapchain_framebuffers
-                .iter()
-                .for_each(|f| device.destroy_framebuffer(*f, None));
-            device.free_command_buffers(self.command_pool, &self.command_buffers);
-            device.destroy_pipeline(self.pipeline, None);
-            device.destroy_pipeline_layout(self.pipeline_layout, None);
-            device.destroy_render_pass(self.render_pass, None);
-            self.swapchain_image_views
-                .iter()
-                .for_each(|v| device.destroy_image_view(*v, None));
-            self.swapchain.destroy_swapchain(self.swapchain_khr, None);
+    /// Clean up the swapchain and all resources that depends on it.
+    fn cleanup_swapchain(&mut self) {
+        let device = self.vk_context.device();
+        unsafe {
+            self.depth_texture.destroy(device);
+            self.color_texture.destroy(device);
+            if let Some(depth_pyramid) = self.depth_pyramid.as_mut() {
+                depth_pyramid.destroy_pyramid(device);
+            }
+            if let Some(bloom) = self.bloom.as_mut() {
+                bloom.destroy_chain(device);
+            }
+            if let Some(fsr) = self.fsr.as_mut() {
+                fsr.destroy_targets(device);
+            }
+            if let Some(hdr_texture) = self.hdr_texture.as_mut() {
+                hdr_texture.destroy(device);
+            }
+            self.swapchain_framebuffers
+                .iter()
+                .for_each(|f| device.destroy_framebuffer(*f, None));
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            if let Some(plane) = self.displaced_plane.as_mut() {
+                plane.destroy_pipeline(device);
+            }
+            if let Some(terrain) = self.terrain.as_mut() {
+                terrain.destroy_pipeline(device);
+            }
+            if let Some(mesh_streamer) = self.mesh_streamer.as_mut() {
+                mesh_streamer.destroy_pipeline(device);
+            }
+            if let Some(objects) = self.objects.as_mut() {
+                objects.destroy_pipeline(device);
+            }
+            if let Some(static_batches) = self.static_batches.as_mut() {
+                static_batches.destroy_pipeline(device);
+            }
+            if let Some(gpu_driven) = self.gpu_driven.as_mut() {
+                gpu_driven.destroy_pipeline(device);
+            }
+            if let Some(virtual_texture) = self.virtual_texture.as_mut() {
+                virtual_texture.destroy_pipeline(device);
+            }
+            if let Some(water) = self.water.as_mut() {
+                water.destroy_pipelines(device);
+            }
+            if let Some(secondary_views) = self.secondary_views.as_mut() {
+                secondary_views.destroy_pipeline(device);
+            }
+            if let Some(mirror) = self.mirror.as_mut() {
+                mirror.destroy_pipelines(device);
+            }
+            if let Some(stereo) = self.stereo.as_mut() {
+                stereo.destroy_pipelines(device);
+            }
+            if let Some(sky) = self.sky.as_mut() {
+                sky.destroy_pipeline(device);
+            }
+            if let Some(background) = self.background.as_mut() {
+                background.destroy_pipeline(device);
+            }
+            if let Some(sprites) = self.sprites.as_mut() {
+                sprites.destroy_pipeline(device);
+            }
+            if let Some(particles) = self.particles.as_mut() {
+                particles.destroy_draw_pipeline(device);
+            }
+            if let Some(decals) = self.decals.as_mut() {
+                decals.destroy_pipeline(device);
+            }
+            if let Some(hud) = self.hud.as_mut() {
+                hud.destroy_pipeline(device);
+            }
+            if let Some(tonemapper) = self.tonemapper.as_mut() {
+                tonemapper.destroy_pipeline(device);
+            }
+            if let Some(ssr) = self.ssr.as_mut() {
+                ssr.destroy_pipeline(device);
+            }
+            if let Some(ray_traced_shadows) = self.ray_traced_shadows.as_mut() {
+                ray_traced_shadows.destroy_pipeline(device);
+            }
+            if let Some(light_shafts) = self.light_shafts.as_mut() {
+                light_shafts.destroy_pipeline(device);
+            }
+            if let Some(motion_blur) = self.motion_blur.as_mut() {
+                motion_blur.destroy_pipelines(device);
+            }
+            self.debug_draw.destroy_pipeline(device);
+            if let Some(occlusion_queries) = self.occlusion_queries.as_mut() {
+                occlusion_queries.destroy_pipeline(device);
+            }
+            if let Some((pipeline, layout)) = self.normals_pipeline {
+                lifetime::destroy_pipeline(device, pipeline);
+                device.destroy_pipeline_layout(layout, None);
+            }
+            if let Some((pipeline, layout)) = self.outline_pipeline {
+                lifetime::destroy_pipeline(device, pipeline);
+                device.destroy_pipeline_layout(layout, None);
+            }
+            device.destroy_render_pass(self.render_pass, None);
+            self.swapchain_image_views
+                .iter()
+                .for_each(|v| device.destroy_image_view(*v, None));
+            if let Some(mut present_transfer) = self.present_transfer.take() {
+                present_transfer.destroy(device);
+            }
+            if let Some((swapchain, swapchain_khr)) = self.swapchain.take() {
+                swapchain.destroy_swapchain(swapchain_khr, None);
+            }
+        }
+    }
+
+    /// Get the view and projection matrices of the camera.
+    fn view_and_projection(&self) -> (Matrix4<f32>, Matrix4<f32>) {
+        let aspect = self.swapchain_properties.extent.width as f32
+            / self.swapchain_properties.extent.height as f32;
+        let view = Matrix4::look_at(
+            self.camera().position(),
+            self.camera().target(),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+        let proj = math::perspective(FIELD_OF_VIEW, aspect, 0.1, 10.0);
+        (view, proj)
+    }
+
+    /// Move the camera so it frames the selected entity, or the whole scene
+    /// if nothing is selected.
+    ///
+    /// An entity without mesh, like a light, is framed around its position.
+    fn frame_selection(&mut self) {
+        let bounds = match self.selected {
+            Some(selected) => self.scene.mesh_bounds(selected).or_else(|| {
+                let position =
+                    Point3::from_vec(self.scene.get::<math::Transform>(selected)?.translation);
+                Some(math::Aabb {
+                    min: position,
+                    max: position,
+                })
+            }),
+            None => self.scene.bounds(),
+        };
+        if let Some(bounds) = bounds {
+            log::debug!("Framing {:?}.", bounds);
+            let extent = self.swapchain_properties.extent;
+            let aspect = extent.width as f32 / extent.height as f32;
+            self.camera_mut()
+                .frame(&bounds, FIELD_OF_VIEW.into(), aspect);
+        }
+    }
+
+    /// Get the ray going from the camera through the cursor.
+    fn cursor_ray(&self) -> math::Ray {
+        let (view, proj) = self.view_and_projection();
+        let extent = self.swapchain_properties.extent;
+        math::Ray::from_cursor(
+            [
+                self.cursor_position[0] as f32,
+                self.cursor_position[1] as f32,
+            ],
+            [extent.width as f32, extent.height as f32],
+            (proj * view).invert().unwrap(),
+        )
+    }
+
+    /// Cast a ray from the camera through the cursor and select the model or
+    /// the light it hits first.
+    ///
+    /// The test runs on the CPU against the bounds then the triangles of the model,
+    /// and against the icons of the lights, so no GPU readback is needed.
+    ///
+    /// The command buffers are recorded again when the outline of the model
+    /// appears or disappears.
+    fn pick(&mut self) {
+        let was_model_selected = self.selected == Some(self.model);
+        let ray = self.cursor_ray();
+        let model_hit = self
+            .scene
+            .pick(&ray)
+            .filter(|(entity, _)| *entity == self.model)
+            .map(|(_, hit)| hit);
+        let light_hit = Some(&self.scene)
+            .filter(|_| self.light_gizmos)
+            .and_then(|scene| pick_light(scene, &ray))
+            .filter(|(_, distance)| model_hit.is_none_or(|hit| *distance < hit.distance));
+        self.selected = match (light_hit, model_hit) {
+            (Some((light, distance)), _) => {
+                log::info!("Light {:?} picked at a distance of {}.", light, distance);
+                Some(light)
+            }
+            (None, Some(hit)) => {
+                log::info!(
+                    "Model picked at {:?} at a distance of {}, normal {:?}.",
+                    hit.point,
+                    hit.distance,
+                    hit.normal
+                );
+                Some(self.model)
+            }
+            (None, None) => {
+                log::info!("Nothing picked.");
+                None
+            }
+        };
+        let is_model_selected = self.selected == Some(self.model);
+        if self.outline_pipeline.is_some() && was_model_selected != is_model_selected {
+            self.recreate_command_buffers();
+        }
+    }
+
+    /// Get the camera of the main view.
+    fn camera(&self) -> &Camera {
+        self.scene.get(self.main_camera).unwrap()
+    }
+
+    fn camera_mut(&mut self) -> &mut Camera {
+        self.scene.get_mut(self.main_camera).unwrap()
+    }
+
+    fn model_transform(&self) -> &math::Transform {
+        self.scene.get(self.model).unwrap()
+    }
+
+    /// Get the properties of the images the scene is rendered to at the render scale.
+    fn render_properties(&self) -> SwapchainProperties {
+        Self::get_render_properties(self.swapchain_properties, self.render_scale)
+    }
+
+    /// Get the approximate size in pixels of the model on screen.
+    fn model_screen_size(&self) -> f32 {
+        let transform = self.model_transform();
+        let distance =
+            Point3::from_vec(transform.translation).distance(self.render_list.camera_position);
+        // Must match the field of view of `view_and_projection`
+        let focal_length = self.swapchain_properties.extent.height as f32
+            / (2.0 * (45.0f32.to_radians() * 0.5).tan());
+        MODEL_SIZE * transform.scale.x * focal_length / distance.max(0.1)
+    }
+
+    fn model_index_count(&self) -> usize {
+        self.scene
+            .get::<MeshRenderer>(self.model)
+            .unwrap()
+            .index_count as _
+    }
+
+    fn model_layers(&self) -> Layers {
+        self.scene.get::<MeshRenderer>(self.model).unwrap().layers
+    }
+
+    /// Point the sun light in the direction of the sun of the sky.
+    fn update_sun(&mut self) {
+        let sun_direction = self
+            .sky
+            .as_ref()
+            .map(|sky| sky.parameters().sun_direction(self.time()))
+            .unwrap_or_else(|| SkyParameters::default().sun_direction(0.0));
+        self.scene.get_mut::<Light>(self.sun).unwrap().direction = sun_direction;
+    }
+
+    /// Get the time in seconds animating the scene.
+    fn time(&self) -> f32 {
+        self.time_controller.time()
+    }
+
+    /// Get the uniforms of the main view from the render list.
+    fn create_frame_ubo(&self) -> UniformBufferObject {
+        let list = &self.render_list;
+        let extent = self.render_properties().extent;
+        let model = list
+            .draw(self.model)
+            .map_or_else(Matrix4::identity, |draw| draw.model);
+        let sun_projection = self.shadow_map.as_ref().map(|shadow_map| {
+            shadow_map.light_projection(list.sun_direction, list.proj * list.view, model)
+        });
+        UniformBufferObject {
+            model,
+            view: Matrix4::identity(),
+            proj: Matrix4::identity(),
+            camera_position: [0.0; 4],
+            fog: self.fog.into(),
+            inverse_view_proj: Matrix4::identity(),
+            inverse_view: Matrix4::identity(),
+            inverse_proj: Matrix4::identity(),
+            screen_size: [extent.width as _, extent.height as _],
+            time: self.time(),
+            light_count: list.light_count,
+            ambient: self
+                .sh_ambient
+                .as_ref()
+                .map_or([[0.0; 4]; SH_COEFFICIENT_COUNT], ShAmbient::irradiance),
+            shadow_view_proj: sun_projection
+                .map_or_else(Matrix4::identity, |projection| projection.view_proj),
+            spot_lights: self
+                .shadow_atlas
+                .as_ref()
+                .map_or_else(SpotLightsUniform::default, |shadow_atlas| {
+                    shadow_atlas.uniform(&list.spot_lights)
+                }),
+            shadow_filter: self.shadow_filter as _,
+            inverse_model: model.invert().unwrap_or_else(Matrix4::identity),
+            sun_direction: list.sun_direction.extend(0.0).into(),
+            sun_penumbra_scale: sun_projection.map_or(0.0, |projection| {
+                projection.penumbra_scale(list.sun_softness)
+            }),
+            sun_shadow_samples: list.sun_shadow_samples,
+            sun_softness: list.sun_softness,
+            clip_planes: [[0.0; 4]; MAX_CLIP_PLANES],
+            clip_plane_count: 0,
+        }
+        .with_camera(list.view, list.proj, list.camera_position)
+        .with_clip_planes(&self.clip_planes)
+    }
+
+    /// Get the uniforms of the eyes, from the headset if one is used.
+    fn stereo_uniforms(&self, ubo: UniformBufferObject) -> StereoUniforms {
+        #[cfg(feature = "openxr")]
+        {
+            let headset_uniforms = self
+                .xr
+                .as_ref()
+                .and_then(|(_, session)| session.eye_uniforms(ubo.model));
+            if let Some(uniforms) = headset_uniforms {
+                return uniforms;
+            }
         }
+        StereoUniforms::new(ubo.model, ubo.view, ubo.proj)
     }
 
-    fn update_uniform_buffers(&mut self, current_image: u32) {
+    /// Apply the camera input, update the scene and extract it into the render list.
+    ///
+    /// It does not depend on the swapchain image so it can run while the previous
+    /// frame is still being submitted.
+    fn update_scene(&mut self) {
+        profile_scope!("update_scene");
         if self.is_left_clicked && self.cursor_delta.is_some() {
             let delta = self.cursor_delta.take().unwrap();
             let x_ratio = delta[0] as f32 / self.swapchain_properties.extent.width as f32;
             let y_ratio = delta[1] as f32 / self.swapchain_properties.extent.height as f32;
             let theta = x_ratio * 180.0_f32.to_radians();
             let phi = y_ratio * 90.0_f32.to_radians();
-            self.camera.rotate(theta, phi);
+            self.camera_mut().rotate(theta, phi);
         }
         if let Some(wheel_delta) = self.wheel_delta {
-            self.camera.forward(wheel_delta * 0.3);
+            self.camera_mut().forward(wheel_delta * 0.3);
+        }
+        self.update_sun();
+        if let Some(object_demo) = self.object_demo.as_mut() {
+            for entity in object_demo.update(&mut self.scene) {
+                if self.selected == Some(entity) {
+                    self.selected = None;
+                }
+            }
         }
+        // Only the render list is read when writing the uniforms
+        self.render_list = RenderList::extract(
+            &self.scene,
+            self.main_camera,
+            self.sun,
+            self.view_and_projection(),
+        );
+    }
 
-        let aspect = self.swapchain_properties.extent.width as f32
-            / self.swapchain_properties.extent.height as f32;
-        let ubo = UniformBufferObject {
-            model: Matrix4::from_angle_x(Deg(270.0)),
-            view: Matrix4::look_at(
-                self.camera.position(),
-                Point3::new(0.0, 0.0, 0.0),
-                Vector3::new(0.0, 1.0, 0.0),
-            ),
-            proj: math::perspective(Deg(45.0), aspect, 0.1, 10.0),
+    /// Get the statistics shown on the HUD with `--hud`.
+    fn hud_stats_text(&self, current_image: u32) -> String {
+        let mut text = format!(
+            "{:.0} FPS\n{:.2} ms",
+            1.0 / self.frame_time,
+            self.frame_time * 1000.0
+        );
+        const MIB: f32 = 1024.0 * 1024.0;
+        for (index, heap) in memory::memory_stats(&self.vk_context).iter().enumerate() {
+            text.push_str(&format!(
+                "\nHeap {}: {:.0} MiB ({:.0} / {:.0} MiB)",
+                index,
+                heap.allocated as f32 / MIB,
+                heap.usage as f32 / MIB,
+                heap.budget as f32 / MIB
+            ));
+        }
+        let binds = self.bind_stats[current_image as usize];
+        text.push_str(&format!(
+            "\nBinds: {} ({} skipped)",
+            binds.recorded, binds.skipped
+        ));
+        text.push_str(&format!(
+            "\nSubmit: {} command buffers, {} waits, {} signals",
+            self.submit_stats.command_buffers,
+            self.submit_stats.wait_semaphores,
+            self.submit_stats.signal_semaphores
+        ));
+        if self.hiz.is_some() {
+            text.push_str(&format!("\nOccluded chunks: {}", self.occluded_count));
+        }
+        if self.occlusion_queries.is_some() {
+            text.push_str(&format!("\nOccluded objects: {}", self.occluded_objects));
+        }
+        if let Some(mesh_streamer) = self.mesh_streamer.as_ref() {
+            let (resident, loading) = mesh_streamer.chunk_counts();
+            text.push_str(&format!(
+                "\nStreamed chunks: {} ({} loading)",
+                resident, loading
+            ));
+        }
+        if let Some(objects) = self.objects.as_ref() {
+            text.push_str(&format!(
+                "\nObjects: {} ({} entities)",
+                objects.object_count(),
+                self.scene.entity_count()
+            ));
+        }
+        if let Some(static_batches) = self.static_batches.as_ref() {
+            text.push_str(&format!("\nStatic draws: {}", static_batches.draw_count()));
+        }
+        if let Some(gpu_driven) = self.gpu_driven.as_ref() {
+            text.push_str(&format!(
+                "\nGPU-driven instances: {}",
+                gpu_driven.instance_count()
+            ));
+        }
+        if let Some(virtual_texture) = self.virtual_texture.as_ref() {
+            text.push_str(&format!(
+                "\nResident pages: {}/{}",
+                virtual_texture.resident_count(),
+                PAGE_CACHE_SIZE
+            ));
+        }
+        if self.particles.is_some() {
+            text.push_str(&format!("\nParticles: {}", self.particle_count));
+        }
+        if let Some(texture_streamer) = self.texture_streamer.as_ref() {
+            let (resident_level, mip_levels) = texture_streamer.resident_levels();
+            text.push_str(&format!(
+                "\nTexture levels: {}/{}",
+                mip_levels - resident_level,
+                mip_levels
+            ));
+        }
+        if let Some(gpu_frame) = self.gpu_frame {
+            text.push_str(&format!("\nGPU: {:.2} ms", gpu_frame.milliseconds));
+        }
+        if self.render_scale < MAX_RENDER_SCALE || self.dynamic_resolution.is_some() {
+            let extent = self.render_properties().extent;
+            text.push_str(&format!(
+                "\nRender scale: {:.0}% ({}x{}){}",
+                self.render_scale * 100.0,
+                extent.width,
+                extent.height,
+                if self.fsr.is_some() { ", FSR" } else { "" }
+            ));
+        }
+        let time = &self.time_controller;
+        if time.is_paused() || (time.scale() - 1.0).abs() > f32::EPSILON {
+            text.push_str(&format!(
+                "\nTime: x{}{}",
+                time.scale(),
+                if time.is_paused() { ", paused" } else { "" }
+            ));
+        }
+        if self.last_profile_log.is_some() {
+            text.push_str(&format!("\n{}", profiler::report()));
+        }
+        text
+    }
+
+    fn update_uniform_buffers(&mut self, current_image: u32) {
+        profile_scope!("update_frame");
+        let now = Instant::now();
+        let last_frame_time = now.duration_since(self.last_frame_instant).as_secs_f32();
+        self.frame_time = self.frame_time * 0.95 + last_frame_time * 0.05;
+        self.last_frame_instant = now;
+        self.transient_allocator.begin_frame(current_image as _);
+        // Headless frames are rendered at the fixed time
+        let frame_delta = match (self.time_step, self.input_delta) {
+            (Some(step), _) => step,
+            (None, _) if self.swapchain.is_none() => 0.0,
+            (None, Some(delta)) => delta,
+            (None, None) => last_frame_time,
         };
+
+        let time = self.time();
+        if let Some(sh_ambient) = self.sh_ambient.as_mut() {
+            sh_ambient.update(time);
+        }
+
+        let ubo = self.create_frame_ubo();
         let ubos = [ubo];
 
-        let buffer_mem = self.uniform_buffer_memories[current_image as usize];
+        let buffer_mem = self.uniform_buffers[current_image as usize].memory;
         let size = size_of::<UniformBufferObject>() as vk::DeviceSize;
         unsafe {
             let device = self.vk_context.device();
@@ -2308,36 +12457,576 @@ impl VulkanApp {
             align.copy_from_slice(&ubos);
             device.unmap_memory(buffer_mem);
         }
+
+        if let Some(terrain) = self.terrain.as_ref() {
+            terrain.update_draws(
+                self.vk_context.device(),
+                current_image as _,
+                &self.render_list.frustum,
+                self.render_list.camera_position,
+            );
+        }
+
+        if let Some(mesh_streamer) = self.mesh_streamer.as_mut() {
+            mesh_streamer.update(
+                self.vk_context.device(),
+                current_image as _,
+                self.render_list.camera_position,
+                &self.render_list.frustum,
+            );
+        }
+
+        if let Some(objects) = self.objects.as_mut() {
+            objects.update(
+                self.vk_context.device(),
+                current_image as _,
+                (&self.render_list, self.pass_layers.main),
+                &self.material_instances,
+            );
+        }
+        if let Some(gpu_driven) = self.gpu_driven.as_ref() {
+            gpu_driven.update(
+                self.vk_context.device(),
+                current_image as _,
+                &self.render_list,
+            );
+        }
+
+        let model_screen_size = self.model_screen_size();
+        if let Some(texture_streamer) = self.texture_streamer.as_mut() {
+            texture_streamer.update(
+                self.vk_context.device(),
+                &mut self.submit_batch,
+                model_screen_size,
+            );
+        }
+
+        if let Some(virtual_texture) = self.virtual_texture.as_mut() {
+            virtual_texture.update(
+                self.vk_context.device(),
+                self.graphics_queue,
+                &mut self.submit_batch,
+                current_image as _,
+            );
+        }
+
+        if let Some(hiz) = self.hiz.as_mut() {
+            self.occluded_count = hiz.update(
+                self.vk_context.device(),
+                current_image as _,
+                self.render_list.proj * self.render_list.view,
+            );
+        }
+
+        if let Some(occlusion_queries) = self.occlusion_queries.as_mut() {
+            self.occluded_objects =
+                occlusion_queries.update(self.vk_context.device(), current_image as _);
+        }
+
+        let device = self.vk_context.device();
+        let gpu_frame = self
+            .gpu_timer
+            .as_mut()
+            .and_then(|gpu_timer| gpu_timer.read(device, current_image as _));
+        if gpu_frame.is_some() {
+            self.gpu_frame = gpu_frame;
+        }
+        if let (Some(dynamic_resolution), Some(gpu_frame)) =
+            (self.dynamic_resolution.as_mut(), gpu_frame)
+        {
+            dynamic_resolution.update(gpu_frame.milliseconds);
+        }
+        if let Some(benchmark) = self.benchmark.as_mut() {
+            benchmark.record(
+                current_image as _,
+                last_frame_time * 1000.0,
+                self.render_list.draws.len(),
+                gpu_frame,
+            );
+        }
+
+        if let Some(reflection_probes) = self.reflection_probes.as_ref() {
+            reflection_probes.update(
+                self.vk_context.device(),
+                current_image as _,
+                Point3::from_homogeneous(ubo.model.w),
+            );
+        }
+
+        if let Some(water) = self.water.as_ref() {
+            water.update(self.vk_context.device(), current_image as _, ubo);
+        }
+
+        if let Some(secondary_views) = self.secondary_views.as_ref() {
+            secondary_views.update(self.vk_context.device(), current_image as _, ubo);
+        }
+
+        if let Some(mirror) = self.mirror.as_ref() {
+            mirror.update(self.vk_context.device(), current_image as _, ubo);
+        }
+
+        if let Some(stereo) = self.stereo.as_ref() {
+            stereo.update(
+                self.vk_context.device(),
+                current_image as _,
+                self.stereo_uniforms(ubo),
+            );
+        }
+
+        if let Some(sky) = self.sky.as_ref() {
+            sky.update(self.vk_context.device(), current_image as _, self.time());
+        }
+
+        if let Some(sprites) = self.sprites.as_ref() {
+            let mut demo_sprites = Self::create_demo_sprites(self.time());
+            if self.light_gizmos {
+                demo_sprites.extend(light_sprites(&self.scene));
+            }
+            sprites.update(
+                self.vk_context.device(),
+                current_image as _,
+                ubo.view,
+                self.render_list.camera_position,
+                &demo_sprites,
+            );
+        }
+
+        let time = self.time();
+        if let Some(particles) = self.particles.as_mut() {
+            self.particle_count = particles.update(
+                self.vk_context.device(),
+                current_image as _,
+                (PARTICLE_EMITTER, PARTICLE_EMISSION_RATE),
+                self.render_list.camera_position,
+                (time, self.time_controller.scaled_delta(frame_delta)),
+            );
+        }
+
+        if let Some(hud) = self.hud.as_ref() {
+            profile_scope!("hud");
+            let mut texts = Vec::new();
+            if self.hud_stats {
+                texts.push(Text {
+                    text: self.hud_stats_text(current_image),
+                    position: [10.0, 10.0],
+                    size: 20.0,
+                    color: [1.0, 1.0, 1.0, 1.0],
+                });
+            }
+            if let Some(console) = self.console.as_ref().filter(|console| console.is_open()) {
+                texts.push(console.text(self.swapchain_properties.extent));
+            }
+            hud.update(
+                self.vk_context.device(),
+                current_image as _,
+                self.swapchain_properties.extent,
+                &texts,
+            );
+        }
+
+        if let Some(auto_exposure) = self.auto_exposure.as_ref() {
+            // Headless frames are rendered once so the exposure adapts immediately
+            let time_delta = if self.swapchain.is_none() {
+                f32::INFINITY
+            } else {
+                self.time_step.unwrap_or(last_frame_time)
+            };
+            auto_exposure.update(self.vk_context.device(), current_image as _, time_delta);
+        }
+
+        if let Some(ssr) = self.ssr.as_mut() {
+            ssr.update(self.vk_context.device(), current_image as _);
+        }
+
+        if let Some(ray_traced_shadows) = self.ray_traced_shadows.as_ref() {
+            ray_traced_shadows.update(
+                self.vk_context.device(),
+                current_image as _,
+                ubo.inverse_view_proj,
+                ubo.model,
+                self.render_list.sun_direction,
+            );
+        }
+
+        if let Some(light_shafts) = self.light_shafts.as_ref() {
+            let sun_direction = self.render_list.sun_direction;
+            light_shafts.update(
+                self.vk_context.device(),
+                current_image as _,
+                ubo.proj * ubo.view,
+                sun_direction,
+            );
+        }
+
+        if let Some(motion_blur) = self.motion_blur.as_mut() {
+            motion_blur.update(
+                self.vk_context.device(),
+                current_image as _,
+                ubo.view,
+                ubo.proj,
+                ubo.model,
+            );
+        }
+
+        let camera_position = self.camera().position();
+        let scene = &self.scene;
+        let selected_transform = self
+            .selected
+            .and_then(|selected| scene.get::<math::Transform>(selected));
+        if let Some(transform) = selected_transform {
+            self.gizmo
+                .draw(&mut self.debug_draw, transform, camera_position);
+        }
+        if self.light_gizmos {
+            draw_light_gizmos(&mut self.debug_draw, &self.scene, self.selected);
+        }
+        self.debug_draw.update(
+            self.vk_context.device(),
+            current_image as _,
+            &mut self.transient_allocator,
+        );
+
+        self.time_controller.advance(frame_delta);
     }
 }
 
 impl Drop for VulkanApp {
     fn drop(&mut self) {
         log::debug!("Dropping application.");
-        self.cleanup_swapchain();
+        // Stop the render thread once it presented the frame it was sent
+        self.render_thread.take();
+        // The swapchain was already destroyed when the app was suspended
+        if !self.is_suspended {
+            self.cleanup_swapchain();
+        }
 
         let device = self.vk_context.device();
+        // The session must be dropped before the device is destroyed
+        #[cfg(feature = "openxr")]
+        if let Some((_, mut session)) = self.xr.take() {
+            session.destroy(device, self.command_pool);
+        }
+        if let Some(mut secondary_window) = self.secondary_window.take() {
+            let (surface, _) = self.vk_context.surface().unwrap();
+            secondary_window.destroy(device, surface, self.command_pool);
+        }
         self.in_flight_frames.destroy(device);
         unsafe {
-            device.destroy_descriptor_pool(self.descriptor_pool, None);
-            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
-            self.uniform_buffer_memories
-                .iter()
-                .for_each(|m| device.free_memory(*m, None));
+            self.descriptor_allocator.destroy(device);
+            self.frame_layout.destroy(device);
+            self.material_instances.destroy(device);
+            if let Some(bindless_textures) = self.bindless_textures.as_mut() {
+                bindless_textures.destroy(device);
+            }
+            if let Some(mesh_shading) = self.mesh_shading.as_mut() {
+                mesh_shading.destroy(device);
+            }
+            if let Some(plane) = self.displaced_plane.as_mut() {
+                plane.destroy(device);
+            }
+            if let Some(depth_pyramid) = self.depth_pyramid.as_mut() {
+                depth_pyramid.destroy(device);
+            }
+            if let Some(hiz) = self.hiz.as_mut() {
+                hiz.destroy(device);
+            }
+            if let Some(terrain) = self.terrain.as_mut() {
+                terrain.destroy(device);
+            }
+            if let Some(mesh_streamer) = self.mesh_streamer.as_mut() {
+                mesh_streamer.destroy(device);
+            }
+            if let Some(objects) = self.objects.as_mut() {
+                if let Some(object_demo) = self.object_demo.as_ref() {
+                    objects.remove_mesh(object_demo.mesh());
+                }
+                objects.destroy(device);
+            }
+            if let Some(static_batches) = self.static_batches.as_mut() {
+                static_batches.destroy(device);
+            }
+            if let Some(gpu_driven) = self.gpu_driven.as_mut() {
+                gpu_driven.destroy(device);
+            }
+            if let Some(virtual_texture) = self.virtual_texture.as_mut() {
+                virtual_texture.destroy(device);
+            }
+            if let Some(texture_streamer) = self.texture_streamer.as_mut() {
+                texture_streamer.destroy(device);
+            }
+            if let Some(water) = self.water.as_mut() {
+                water.destroy(device);
+            }
+            if let Some(secondary_views) = self.secondary_views.as_mut() {
+                secondary_views.destroy(device);
+            }
+            if let Some(mirror) = self.mirror.as_mut() {
+                mirror.destroy(device);
+            }
+            if let Some(stereo) = self.stereo.as_mut() {
+                stereo.destroy(device);
+            }
+            if let Some(sky) = self.sky.as_mut() {
+                sky.destroy(device);
+            }
+            if let Some(background) = self.background.as_mut() {
+                background.destroy(device);
+            }
+            if let Some(sprites) = self.sprites.as_mut() {
+                sprites.destroy(device);
+            }
+            if let Some(particles) = self.particles.as_mut() {
+                particles.destroy(device);
+            }
+            if let Some(decals) = self.decals.as_mut() {
+                decals.destroy(device);
+            }
+            if let Some(hud) = self.hud.as_mut() {
+                hud.destroy(device);
+            }
+            if let Some(tonemapper) = self.tonemapper.as_mut() {
+                tonemapper.destroy(device);
+            }
+            if let Some(auto_exposure) = self.auto_exposure.as_mut() {
+                auto_exposure.destroy(device);
+            }
+            if let Some(bloom) = self.bloom.as_mut() {
+                bloom.destroy(device);
+            }
+            if let Some(ssr) = self.ssr.as_mut() {
+                ssr.destroy(device);
+            }
+            if let Some(ray_traced_shadows) = self.ray_traced_shadows.as_mut() {
+                ray_traced_shadows.destroy(device);
+            }
+            if let Some(acceleration_structures) = self.acceleration_structures.as_mut() {
+                acceleration_structures.destroy(device, self.vk_context.ray_tracing().unwrap());
+            }
+            if let Some(light_shafts) = self.light_shafts.as_mut() {
+                light_shafts.destroy(device);
+            }
+            if let Some(motion_blur) = self.motion_blur.as_mut() {
+                motion_blur.destroy(device);
+            }
+            if let Some(fsr) = self.fsr.as_mut() {
+                fsr.destroy(device);
+            }
+            self.debug_draw.destroy(device);
+            if let Some(occlusion_queries) = self.occlusion_queries.as_mut() {
+                occlusion_queries.destroy(device);
+            }
+            if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+                gpu_timer.destroy(device);
+            }
+            if let Some(gpu_crash_dump) = self.gpu_crash_dump.as_mut() {
+                gpu_crash_dump.destroy(device);
+            }
+            if let Some(reflection_probes) = self.reflection_probes.as_mut() {
+                reflection_probes.destroy(device);
+            }
+            if let Some(lightmap) = self.lightmap.as_mut() {
+                lightmap.destroy(device);
+            }
+            if let Some(shadow_map) = self.shadow_map.as_mut() {
+                shadow_map.destroy(device);
+            }
+            if let Some(shadow_atlas) = self.shadow_atlas.as_mut() {
+                shadow_atlas.destroy(device);
+            }
+            self.transient_allocator.destroy(device);
+            if let Some(target) = self.offscreen_target.as_mut() {
+                target.destroy(device);
+            }
             self.uniform_buffers
-                .iter()
-                .for_each(|b| device.destroy_buffer(*b, None));
-            device.free_memory(self.index_buffer_memory, None);
-            device.destroy_buffer(self.index_buffer, None);
-            device.destroy_buffer(self.vertex_buffer, None);
-            device.free_memory(self.vertex_buffer_memory, None);
+                .iter_mut()
+                .for_each(|b| b.destroy(device));
+            self.index_buffer.destroy(device);
+            self.vertex_buffer.destroy(device);
             self.texture.destroy(device);
             device.destroy_command_pool(self.transient_command_pool, None);
             device.destroy_command_pool(self.command_pool, None);
         }
+        // Everything created with the device must be destroyed before it is
+        lifetime::report_leaks(device);
+    }
+}
+
+/// Programmable stages, vertex input and layout of a graphics pipeline.
+struct GraphicsPipelineDesc<'a> {
+    /// Stages and paths of the compiled shaders.
+    shaders: &'a [(vk::ShaderStageFlags, &'a str)],
+    vertex_binding_descs: &'a [vk::VertexInputBindingDescription],
+    vertex_attribute_descs: &'a [vk::VertexInputAttributeDescription],
+    topology: vk::PrimitiveTopology,
+    /// Number of control points per patch if the pipeline has tessellation shaders.
+    patch_control_points: Option<u32>,
+    cull_mode: vk::CullModeFlags,
+    /// Winding of the front faces, which also decides `gl_FrontFacing`.
+    front_face: vk::FrontFace,
+    depth_bias: DepthBiasState,
+    /// Enable the depth test and the depth writes.
+    depth_test: bool,
+    /// Write the color and the depth if it is tested. Occlusion proxies are
+    /// only tested.
+    writes: bool,
+    /// Stencil test and writes of both faces, if the stencil test is enabled.
+    stencil: Option<vk::StencilOpState>,
+    /// Blend the output with the color attachment using its alpha.
+    alpha_blending: bool,
+    /// Set the viewport and the scissor when recording instead of covering the whole target.
+    dynamic_viewport: bool,
+    /// Index of the subpass of the render pass the pipeline is used in.
+    subpass: u32,
+    layout: vk::PipelineLayout,
+}
+
+/// Passes and resources the frame command buffers are recorded with.
+///
+/// Each optional pass is recorded only if it is set.
+struct FrameResources<'a> {
+    /// Framebuffer of each command buffer, at the same index.
+    framebuffers: &'a [vk::Framebuffer],
+    render_pass: vk::RenderPass,
+    swapchain_properties: SwapchainProperties,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    /// Number of indices of the model.
+    index_count: usize,
+    pass_layers: PassLayers,
+    /// Layers of the model, drawn by the passes of `pass_layers` whose mask has them.
+    model_layers: Layers,
+    pipeline_layout: vk::PipelineLayout,
+    frame_layout: &'a FrameLayout,
+    descriptor_sets: &'a [vk::DescriptorSet],
+    bindless_textures: Option<&'a BindlessTextures>,
+    /// Index of the bindless material of the model.
+    material_index: u32,
+    material_instances: &'a MaterialInstances,
+    model_material: MaterialInstance,
+    mesh_shading: Option<&'a MeshShading>,
+    displaced_plane: Option<&'a DisplacedPlane>,
+    terrain: Option<&'a Terrain>,
+    mesh_streamer: Option<&'a MeshStreamer>,
+    objects: Option<&'a ObjectRenderer>,
+    static_batches: Option<&'a StaticBatches>,
+    gpu_driven: Option<&'a GpuDriven>,
+    virtual_texture: Option<&'a VirtualTexture>,
+    depth_pyramid: Option<&'a DepthPyramid>,
+    hiz: Option<&'a HiZ>,
+    water: Option<&'a Water>,
+    secondary_views: Option<&'a SecondaryViews>,
+    mirror: Option<&'a Mirror>,
+    stereo: Option<&'a Stereo>,
+    sky: Option<&'a Sky>,
+    background: Option<&'a Background>,
+    /// Color the main render pass is cleared with.
+    clear_color: [f32; 3],
+    sprites: Option<&'a SpriteRenderer>,
+    particles: Option<&'a Particles>,
+    decals: Option<&'a Decals>,
+    debug_draw: &'a DebugDraw,
+    occlusion_queries: Option<&'a OcclusionQueries>,
+    reflection_probes: Option<&'a ReflectionProbes>,
+    lightmap: Option<&'a Lightmap>,
+    shadow_map: Option<&'a ShadowMap>,
+    shadow_atlas: Option<&'a ShadowAtlas>,
+    transient_allocator: &'a TransientBufferAllocator,
+    hud: Option<&'a TextRenderer>,
+    auto_exposure: Option<&'a AutoExposure>,
+    bloom: Option<&'a Bloom>,
+    tonemapper: Option<&'a Tonemapper>,
+    ssr: Option<&'a ScreenSpaceReflections>,
+    ray_traced_shadows: Option<&'a RayTracedShadows>,
+    light_shafts: Option<&'a LightShafts>,
+    motion_blur: Option<&'a MotionBlur>,
+    fsr: Option<&'a Fsr>,
+    gpu_timer: Option<&'a GpuTimer>,
+    gpu_crash_dump: Option<&'a GpuCrashDump>,
+    /// Transfer of the swapchain images to the present queue family, if it
+    /// is not the graphics queue family.
+    present_transfer: Option<&'a PresentTransfer>,
+    /// Pipeline drawing the normals of the model, if the debug view is on.
+    normals_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    /// Pipeline drawing the outline of the model, if it is selected.
+    outline_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    /// Pipeline drawing the model.
+    graphics_pipeline: vk::Pipeline,
+}
+
+/// Resources created from the geometry of the model by `swap_model`.
+///
+/// They are all created before any of them replaces the previous ones, so a
+/// failed reload keeps the previous model whole.
+#[derive(Default)]
+struct ModelResources {
+    vertex_buffer: Option<Buffer>,
+    index_buffer: Option<Buffer>,
+    mesh_shading: Option<MeshShading>,
+    acceleration_structures: Option<AccelerationStructures>,
+    lightmap: Option<Lightmap>,
+}
+
+impl ModelResources {
+    fn destroy(&mut self, vk_context: &VkContext) {
+        let device = vk_context.device();
+        if let Some(mut lightmap) = self.lightmap.take() {
+            lightmap.destroy(device);
+        }
+        if let Some(mut acceleration_structures) = self.acceleration_structures.take() {
+            acceleration_structures.destroy(device, vk_context.ray_tracing().unwrap());
+        }
+        if let Some(mut mesh_shading) = self.mesh_shading.take() {
+            mesh_shading.destroy(device);
+        }
+        if let Some(mut index_buffer) = self.index_buffer.take() {
+            index_buffer.destroy(device);
+        }
+        if let Some(mut vertex_buffer) = self.vertex_buffer.take() {
+            vertex_buffer.destroy(device);
+        }
     }
 }
 
+/// Depth bias state of a graphics pipeline.
+#[derive(Clone, Copy, Debug)]
+enum DepthBiasState {
+    Disabled,
+    /// Bias fixed when the pipeline is created.
+    Static(DepthBias),
+    /// Bias set with `vkCmdSetDepthBias` when recording, so the draws using the
+    /// pipeline can each have their own.
+    Dynamic,
+}
+
+/// Optional features enabled on the logical device.
+#[derive(Clone, Copy, Debug)]
+struct DeviceFeatures {
+    bindless: bool,
+    device_address: bool,
+    /// Acceleration structures and ray tracing pipelines, with the KHR buffer device address.
+    ray_tracing: bool,
+    /// Ray queries from any shader, on top of the ray tracing.
+    ray_query: bool,
+    mesh_shader: bool,
+    tessellation: bool,
+    geometry_shader: bool,
+    clip_distance: bool,
+    storage_image_array_indexing: bool,
+    /// Storage images of formats like `R32G32_SFLOAT`, used by the depth pyramid.
+    storage_image_extended_formats: bool,
+    /// Subgroup properties, whose operations need nothing to be enabled.
+    subgroup: Option<SubgroupProperties>,
+    memory_budget: bool,
+    multiview: bool,
+    conditional_rendering: bool,
+    sparse_residency: bool,
+    synchronization2: bool,
+    pipeline_statistics: bool,
+    draw_indirect_count: bool,
+    /// Extension writing the markers of the GPU crash dump.
+    crash_markers: Option<MarkerExtension>,
+}
+
 #[derive(Clone, Copy)]
 struct QueueFamiliesIndices {
     graphics_index: u32,
@@ -2397,6 +13086,19 @@ struct Vertex {
     pos: [f32; 3],
     color: [f32; 3],
     coords: [f32; 2],
+    normal: [f32; 3],
+}
+
+impl BatchVertex for Vertex {
+    fn transformed(&self, model: &Matrix4<f32>, normal_matrix: &Matrix3<f32>) -> Self {
+        let pos = model * Point3::from(self.pos).to_homogeneous();
+        let normal = (normal_matrix * Vector3::from(self.normal)).normalize();
+        Vertex {
+            pos: pos.truncate().into(),
+            normal: normal.into(),
+            ..*self
+        }
+    }
 }
 
 impl Vertex {
@@ -2408,7 +13110,7 @@ impl Vertex {
             .build()
     }
 
-    fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+    fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 4] {
         let position_desc = vk::VertexInputAttributeDescription::builder()
             .binding(0)
             .location(0)
@@ -2427,31 +13129,343 @@ impl Vertex {
             .format(vk::Format::R32G32_SFLOAT)
             .offset(24)
             .build();
-        [position_desc, color_desc, coords_desc]
+        let normal_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(3)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(32)
+            .build();
+        [position_desc, color_desc, coords_desc, normal_desc]
     }
 }
 
-#[derive(Clone, Copy)]
-#[allow(dead_code)]
-struct UniformBufferObject {
-    model: Matrix4<f32>,
-    view: Matrix4<f32>,
-    proj: Matrix4<f32>,
+std140_struct! {
+    /// Uniforms of the frame, shared by all the pipelines at `FRAME_SET`.
+    ///
+    /// Must match `frame.glsl`.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    struct UniformBufferObject {
+        model: Matrix4<f32>,
+        view: Matrix4<f32>,
+        proj: Matrix4<f32>,
+        camera_position: [f32; 4],
+        fog: FogUniform,
+        inverse_view_proj: Matrix4<f32>,
+        inverse_view: Matrix4<f32>,
+        inverse_proj: Matrix4<f32>,
+        /// Size in pixels of the rendered image.
+        screen_size: [f32; 2],
+        /// Time in seconds animating the scene.
+        time: f32,
+        /// Number of lights in the scene.
+        light_count: u32,
+        /// Irradiance of the ambient light divided by pi, in spherical harmonics.
+        ///
+        /// It is only read by the `SH_AMBIENT` variants.
+        ambient: [[f32; 4]; SH_COEFFICIENT_COUNT],
+        /// View projection of the sun rendering the shadow map.
+        ///
+        /// It is only read by the shadow casters and the `SHADOWS` variants.
+        shadow_view_proj: Matrix4<f32>,
+        /// Transform from world space to the space of the model, in which its
+        /// acceleration structures are built.
+        ///
+        /// It and the direction of the sun are only read by the `RAY_QUERY_SHADOWS` variants.
+        inverse_model: Matrix4<f32>,
+        /// Direction pointing towards the sun.
+        sun_direction: [f32; 4],
+        /// Spot lights of the shadow atlas, without any when it is not used.
+        spot_lights: SpotLightsUniform,
+        /// Filter of the shadow maps.
+        shadow_filter: u32,
+        /// Width in shadow map coordinates of the penumbra of the sun per unit
+        /// of depth between a receiver and its blocker.
+        sun_penumbra_scale: f32,
+        /// Number of rays traced towards the sun per fragment by the ray query shadows.
+        sun_shadow_samples: u32,
+        /// Tangent of the angular radius of the sun, spreading the rays of the
+        /// ray query shadows.
+        sun_softness: f32,
+        /// World space planes clipping the geometry, as the coefficients of their equation.
+        ///
+        /// They are only read by the `CLIP_PLANES` variants.
+        clip_planes: [[f32; 4]; MAX_CLIP_PLANES],
+        /// Number of clip planes used.
+        clip_plane_count: u32,
+    }
 }
 
 impl UniformBufferObject {
-    fn get_descriptor_set_layout_binding() -> vk::DescriptorSetLayoutBinding {
-        vk::DescriptorSetLayoutBinding::builder()
-            .binding(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
-            // .immutable_samplers() null since we're not creating a sampler descriptor
-            .build()
+    /// Replace the camera of the uniforms, and the inverse matrices derived from it.
+    fn with_camera(self, view: Matrix4<f32>, proj: Matrix4<f32>, position: Point3<f32>) -> Self {
+        UniformBufferObject {
+            view,
+            proj,
+            camera_position: position.to_homogeneous().into(),
+            inverse_view_proj: (proj * view).invert().unwrap(),
+            inverse_view: view.invert().unwrap(),
+            inverse_proj: proj.invert().unwrap(),
+            ..self
+        }
+    }
+
+    /// Replace the clip planes of the uniforms, keeping the side of the normal of `planes`.
+    ///
+    /// Panics if there are more than `MAX_CLIP_PLANES` planes.
+    fn with_clip_planes(self, planes: &[math::Plane]) -> Self {
+        assert!(planes.len() <= MAX_CLIP_PLANES, "Too many clip planes");
+        let mut clip_planes = [[0.0; 4]; MAX_CLIP_PLANES];
+        for (clip_plane, plane) in clip_planes.iter_mut().zip(planes) {
+            *clip_plane = plane.coefficients().into();
+        }
+        UniformBufferObject {
+            clip_planes,
+            clip_plane_count: planes.len() as _,
+            ..self
+        }
+    }
+}
+
+/// Number of static objects along each side of the grid around the model.
+const STATIC_GRID_SIZE: u32 = 32;
+
+/// Distance between two static objects of the grid.
+const STATIC_GRID_SPACING: f32 = 0.3;
+
+/// Scatter small cubes on a grid around the model, leaving the model free,
+/// with pseudo random sizes, rotations and `materials`.
+fn get_static_objects(materials: &[MaterialInstance]) -> Vec<StaticObject> {
+    // Hash of an integer mapped to [0, 1)
+    let random = |seed: u32| (seed.wrapping_mul(2_654_435_761) >> 8) as f32 / (1 << 24) as f32;
+    let half_extent = (STATIC_GRID_SIZE - 1) as f32 * STATIC_GRID_SPACING * 0.5;
+    (0..STATIC_GRID_SIZE * STATIC_GRID_SIZE)
+        .filter_map(|index| {
+            let x = (index % STATIC_GRID_SIZE) as f32 * STATIC_GRID_SPACING - half_extent;
+            let z = (index / STATIC_GRID_SIZE) as f32 * STATIC_GRID_SPACING - half_extent;
+            if x.abs() < 1.2 && z.abs() < 1.2 {
+                return None;
+            }
+            let scale = 0.05 + 0.1 * random(index * 3);
+            let transform = math::Transform {
+                translation: Vector3::new(x, -0.6 + scale * 0.5, z),
+                rotation: Quaternion::from_angle_y(Deg(360.0 * random(index * 3 + 1))),
+                scale: Vector3::new(scale, scale, scale),
+            };
+            let material = (random(index * 3 + 2) * materials.len() as f32) as usize;
+            Some(StaticObject {
+                mesh: 0,
+                material: materials[material],
+                model: transform.matrix(),
+            })
+        })
+        .collect()
+}
+
+/// Number of GPU-driven instances along each side of the grid around the model.
+const GPU_DRIVEN_GRID_SIZE: u32 = 64;
+
+/// Distance between two GPU-driven instances of the grid.
+const GPU_DRIVEN_GRID_SPACING: f32 = 0.5;
+
+/// Distances from the camera from which the second and the third levels of
+/// detail of the GPU-driven spheres are drawn.
+const GPU_DRIVEN_LOD_DISTANCES: [f32; GPU_DRIVEN_LOD_COUNT - 1] = [6.0, 14.0];
+
+/// Place spheres of pseudo random sizes and colors on a grid around the
+/// model, leaving the model free, all of material `material_index`.
+fn get_gpu_driven_instances(material_index: u32) -> Vec<GpuInstance> {
+    // Hash of an integer mapped to [0, 1)
+    let random = |seed: u32| (seed.wrapping_mul(2_654_435_761) >> 8) as f32 / (1 << 24) as f32;
+    let half_extent = (GPU_DRIVEN_GRID_SIZE - 1) as f32 * GPU_DRIVEN_GRID_SPACING * 0.5;
+    (0..GPU_DRIVEN_GRID_SIZE * GPU_DRIVEN_GRID_SIZE)
+        .filter_map(|index| {
+            let x = (index % GPU_DRIVEN_GRID_SIZE) as f32 * GPU_DRIVEN_GRID_SPACING - half_extent;
+            let z = (index / GPU_DRIVEN_GRID_SIZE) as f32 * GPU_DRIVEN_GRID_SPACING - half_extent;
+            if x.abs() < 1.5 && z.abs() < 1.5 {
+                return None;
+            }
+            let radius = 0.08 + 0.1 * random(index * 4);
+            Some(GpuInstance {
+                sphere: [x, -0.6 + radius, z, radius],
+                color: [
+                    0.5 + 0.5 * random(index * 4 + 1),
+                    0.5 + 0.5 * random(index * 4 + 2),
+                    0.5 + 0.5 * random(index * 4 + 3),
+                    1.0,
+                ],
+                material: material_index,
+                padding: [0; 3],
+            })
+        })
+        .collect()
+}
+
+/// Create a unit sphere centered on the origin, of `rings` rings from pole
+/// to pole and `segments` segments around its axis.
+///
+/// # Returns
+///
+/// The vertices, whose texture coordinates wrap once around the sphere, and
+/// the indices of its triangles.
+fn create_sphere(rings: u32, segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(((rings + 1) * (segments + 1)) as usize);
+    for ring in 0..=rings {
+        let v = ring as f32 / rings as f32;
+        let polar = std::f32::consts::PI * v;
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let azimuth = 2.0 * std::f32::consts::PI * u;
+            let normal = [
+                polar.sin() * azimuth.cos(),
+                polar.cos(),
+                -polar.sin() * azimuth.sin(),
+            ];
+            vertices.push(Vertex {
+                pos: normal,
+                color: [1.0; 3],
+                coords: [u, v],
+                normal,
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity((rings * segments * 6) as usize);
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let first = ring * (segments + 1) + segment;
+            let below = first + segments + 1;
+            indices.extend_from_slice(&[first, below, below + 1, below + 1, first + 1, first]);
+        }
+    }
+    (vertices, indices)
+}
+
+/// Create a unit cube centered on the origin, with flat normals.
+///
+/// # Returns
+///
+/// The 24 vertices, 4 per face, and the indices of its 12 triangles.
+fn create_cube() -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for axis in 0..3 {
+        for &side in &[-1.0_f32, 1.0] {
+            let mut normal = [0.0; 3];
+            normal[axis] = side;
+            // The two other axes, ordered so the face is counter clockwise seen from outside
+            let (u, v) = if side > 0.0 {
+                ((axis + 1) % 3, (axis + 2) % 3)
+            } else {
+                ((axis + 2) % 3, (axis + 1) % 3)
+            };
+            let first = vertices.len() as u32;
+            for &(s, t) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let mut pos = [0.0; 3];
+                pos[axis] = side * 0.5;
+                pos[u] = s * 0.5;
+                pos[v] = t * 0.5;
+                vertices.push(Vertex {
+                    pos,
+                    color: [1.0; 3],
+                    coords: [(s + 1.0) * 0.5, (1.0 - t) * 0.5],
+                    normal,
+                });
+            }
+            indices.extend_from_slice(&[first, first + 1, first + 2, first + 2, first + 3, first]);
+        }
+    }
+    (vertices, indices)
+}
+
+/// Compute smooth vertex normals by averaging the normals of the faces
+/// sharing each vertex.
+///
+/// # Returns
+///
+/// The normals as a flat array of 3 components per vertex.
+fn compute_normals(positions: &[f32], indices: &[u32]) -> Vec<f32> {
+    let position = |index: u32| {
+        let index = index as usize * 3;
+        Vector3::new(positions[index], positions[index + 1], positions[index + 2])
+    };
+
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); positions.len() / 3];
+    for triangle in indices.chunks(3) {
+        let (a, b, c) = (
+            position(triangle[0]),
+            position(triangle[1]),
+            position(triangle[2]),
+        );
+        // Not normalized so larger faces contribute more
+        let face_normal = (b - a).cross(c - a);
+        for index in triangle {
+            normals[*index as usize] += face_normal;
+        }
+    }
+
+    normals
+        .iter()
+        .flat_map(|normal| {
+            let normal = if normal.magnitude2() > 0.0 {
+                normal.normalize()
+            } else {
+                Vector3::unit_y()
+            };
+            vec![normal.x, normal.y, normal.z]
+        })
+        .collect()
+}
+
+/// Create the application, or exit if its resources could not be allocated.
+fn create_app(base: Option<AppBase>) -> VulkanApp {
+    match VulkanApp::new(base) {
+        Ok(app) => app,
+        Err(error) => {
+            log::error!("Failed to create the application. Cause: {}", error);
+            std::process::exit(1);
+        }
     }
 }
 
 fn main() {
     env_logger::init();
-    VulkanApp::new().run()
+    let mut app = create_app(None);
+    if let Some(count) = VulkanApp::get_sort_check_count() {
+        let matches = app.run_sort_check(count);
+        drop(app);
+        if !matches {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let headless_output = VulkanApp::get_headless_output();
+
+    let (input_recorder, input_playback) = VulkanApp::get_input_recording();
+    app.input_recorder = input_recorder;
+    app.input_playback = input_playback;
+    loop {
+        let result = match headless_output.as_ref() {
+            Some(path) => app.run_headless(path),
+            None => app.run(),
+        };
+        match result {
+            RunResult::Exit => break,
+            RunResult::DeviceLost => log::error!("Device lost. Recreating the device."),
+            RunResult::OutOfMemory(error) => {
+                // The half created resources cannot be destroyed safely
+                log::error!("Failed to recreate the resources. Cause: {}", error);
+                std::process::exit(1);
+            }
+        }
+        // The logical device and everything created with it are recreated and
+        // the assets are reloaded from disk. The instance, the window, the view
+        // and the settings are kept.
+        let base = app.take_base();
+        let state = app.take_state();
+        // The old app must be destroyed first since a window can only have one swapchain
+        drop(app);
+        app = create_app(Some(base));
+        app.restore_state(state);
+    }
 }
@@ -0,0 +1,479 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    gpu_device::Device,
+    lifetime,
+    ray_tracing::{self, AccelerationStructureHandle},
+    shader_variant::{DOUBLE_SIDED, LIGHTMAP, RAY_QUERY_SHADOWS, SHADOWS, SPOT_SHADOWS},
+    std140::std140_struct,
+    texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::mem::size_of;
+
+/// Maximum number of material instances stored in the uniform buffer.
+pub const MAX_MATERIAL_INSTANCES: u32 = 256;
+
+/// Index of the descriptor set of the material instances in the pipeline layout
+/// of the model.
+///
+/// Must match the set of `MaterialParameters` in `material.glsl`.
+pub const MATERIAL_SET: u32 = 1;
+
+const PARAMETERS_BINDING: u32 = 0;
+
+/// Binding of the lightmap when the base material has the `LIGHTMAP` keyword.
+///
+/// Must match `lightmap` in `material.glsl`.
+const LIGHTMAP_BINDING: u32 = 1;
+
+/// Binding of the shadow map when the base material has the `SHADOWS` keyword.
+///
+/// Must match `shadowMap` in `shadow.glsl`.
+const SHADOW_MAP_BINDING: u32 = 2;
+
+/// Binding of the shadow atlas when the base material has the `SPOT_SHADOWS` keyword.
+///
+/// Must match `shadowAtlas` in `spot_shadow.glsl`.
+const SHADOW_ATLAS_BINDING: u32 = 3;
+
+/// Binding of the top level acceleration structure when the base material has
+/// the `RAY_QUERY_SHADOWS` keyword.
+///
+/// Must match `topLevel` in `ray_query_shadow.glsl`.
+const TOP_LEVEL_BINDING: u32 = 4;
+
+std140_struct! {
+    /// Parameters of a material instance overriding the ones of its base material.
+    ///
+    /// Must match `MaterialParameters` in `material.glsl`.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct MaterialParameters {
+        /// Color multiplied with the color of the base material.
+        pub tint: [f32; 4],
+        /// Roughness of the surface between 0 and 1.
+        ///
+        /// It is not used by the shaders yet since the model is not lit.
+        pub roughness: f32,
+        /// Factor of the color of the surface added to it as emitted light.
+        pub emissive_strength: f32,
+        padding: [f32; 2],
+    }
+}
+
+impl MaterialParameters {
+    pub fn new(tint: [f32; 4], roughness: f32, emissive_strength: f32) -> Self {
+        MaterialParameters {
+            tint,
+            roughness,
+            emissive_strength,
+            padding: [0.0; 2],
+        }
+    }
+
+    /// Get the parameters of a preset from its name.
+    ///
+    /// Presets are `default`, `tinted`, `glowing` and `glossy`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "tinted" => Some(Self::new([1.0, 0.6, 0.4, 1.0], 0.5, 0.0)),
+            "glowing" => Some(Self::new([1.0, 1.0, 1.0, 1.0], 0.5, 1.5)),
+            "glossy" => Some(Self::new([1.0, 1.0, 1.0, 1.0], 0.1, 0.0)),
+            _ => None,
+        }
+    }
+}
+
+impl Default for MaterialParameters {
+    fn default() -> Self {
+        Self::new([1.0; 4], 0.5, 0.0)
+    }
+}
+
+/// Faces of the triangles culled by a base material.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    Back,
+    Front,
+    /// Draw both faces, for double-sided materials like foliage or cloth.
+    None,
+}
+
+impl CullMode {
+    /// Get the mode from its name.
+    ///
+    /// Modes are `back`, `front` and `none`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "back" => Some(CullMode::Back),
+            "front" => Some(CullMode::Front),
+            "none" => Some(CullMode::None),
+            _ => None,
+        }
+    }
+
+    pub fn flags(self) -> vk::CullModeFlags {
+        match self {
+            CullMode::Back => vk::CullModeFlags::BACK,
+            CullMode::Front => vk::CullModeFlags::FRONT,
+            CullMode::None => vk::CullModeFlags::NONE,
+        }
+    }
+}
+
+/// Culling of the faces of a base material and winding of its front faces.
+///
+/// Since it changes the rasterization state, it is shared by all the instances
+/// of the base material like its keywords.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaceCulling {
+    pub cull_mode: CullMode,
+    /// Winding of the front faces in framebuffer space.
+    pub front_face: vk::FrontFace,
+}
+
+impl FaceCulling {
+    /// Check if both faces are drawn.
+    pub fn is_double_sided(self) -> bool {
+        self.cull_mode == CullMode::None
+    }
+}
+
+impl Default for FaceCulling {
+    fn default() -> Self {
+        FaceCulling {
+            cull_mode: CullMode::Back,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        }
+    }
+}
+
+/// Handle of a material instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialInstance(u32);
+
+impl MaterialInstance {
+    /// Get the index of the instance in the uniform buffer.
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+/// Get the stride between two instances in the uniform buffer.
+///
+/// `min_offset_alignment` is the `minUniformBufferOffsetAlignment` limit of the device.
+pub fn instance_stride(min_offset_alignment: vk::DeviceSize) -> vk::DeviceSize {
+    let size = size_of::<MaterialParameters>() as vk::DeviceSize;
+    let alignment = min_offset_alignment.max(1);
+    size.div_ceil(alignment) * alignment
+}
+
+/// Get the size of the uniform buffer holding the parameters of the instances.
+pub fn uniform_buffer_size(min_offset_alignment: vk::DeviceSize) -> vk::DeviceSize {
+    instance_stride(min_offset_alignment) * MAX_MATERIAL_INSTANCES as vk::DeviceSize
+}
+
+/// Instances of the base material of the model.
+///
+/// All instances share the pipeline and the descriptor set layouts of the base
+/// material. Only their parameters differ, and they are all stored in a single
+/// uniform buffer bound as a dynamic uniform buffer. Drawing with an instance
+/// only binds the set with the offset of its parameters, so hundreds of
+/// variants need neither their own pipeline nor their own descriptor set.
+pub struct MaterialInstances {
+    keywords: Vec<&'static str>,
+    culling: FaceCulling,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    uniform_buffer: Buffer,
+    stride: vk::DeviceSize,
+    parameters: Vec<MaterialParameters>,
+}
+
+impl MaterialInstances {
+    /// Create the descriptor set of the material instances.
+    ///
+    /// `keywords` are the shader keywords enabled by the base material, like
+    /// `ALPHA_TEST`. They are shared by all instances since they change the
+    /// pipeline. With `LIGHTMAP`, the set also holds the lightmap, which must be
+    /// given with `set_lightmap` before drawing, with `SHADOWS` the shadow
+    /// map given with `set_shadow_map`, with `SPOT_SHADOWS` the shadow atlas
+    /// given with `set_shadow_atlas` and with `RAY_QUERY_SHADOWS` the top level
+    /// acceleration structure given with `set_top_level`.
+    ///
+    /// `culling` is also shared by all instances. A double-sided base material
+    /// gets the `DOUBLE_SIDED` keyword so its back faces are lit like its front
+    /// faces.
+    ///
+    /// `uniform_buffer` must be a host visible and coherent buffer of at least
+    /// `uniform_buffer_size(min_offset_alignment)` bytes.
+    pub fn new(
+        device: &Device,
+        mut keywords: Vec<&'static str>,
+        culling: FaceCulling,
+        uniform_buffer: Buffer,
+        min_offset_alignment: vk::DeviceSize,
+    ) -> Self {
+        if culling.is_double_sided() {
+            keywords.push(DOUBLE_SIDED);
+        }
+        let stride = instance_stride(min_offset_alignment);
+        let texture_bindings = Self::texture_bindings(&keywords);
+        let ray_query = keywords.contains(&RAY_QUERY_SHADOWS);
+        let layout = Self::create_descriptor_set_layout(device, &texture_bindings, ray_query);
+        let pool = Self::create_descriptor_pool(device, texture_bindings.len() as _, ray_query);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer)
+            .offset(0)
+            .range(size_of::<MaterialParameters>() as _)
+            .build();
+        let buffer_infos = [buffer_info];
+        let descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(PARAMETERS_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .buffer_info(&buffer_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        MaterialInstances {
+            keywords,
+            culling,
+            layout,
+            pool,
+            set,
+            uniform_buffer,
+            stride,
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Get the bindings of the textures needed by `keywords`.
+    fn texture_bindings(keywords: &[&'static str]) -> Vec<u32> {
+        [
+            (LIGHTMAP, LIGHTMAP_BINDING),
+            (SHADOWS, SHADOW_MAP_BINDING),
+            (SPOT_SHADOWS, SHADOW_ATLAS_BINDING),
+        ]
+        .iter()
+        .filter(|(keyword, _)| keywords.contains(keyword))
+        .map(|(_, binding)| *binding)
+        .collect()
+    }
+
+    fn create_descriptor_set_layout(
+        device: &Device,
+        texture_bindings: &[u32],
+        ray_query: bool,
+    ) -> vk::DescriptorSetLayout {
+        let binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(PARAMETERS_BINDING)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let mut bindings = vec![binding];
+        bindings.extend(texture_bindings.iter().map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(*binding)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        }));
+        if ray_query {
+            bindings.push(
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(TOP_LEVEL_BINDING)
+                    .descriptor_count(1)
+                    .descriptor_type(ray_tracing::acceleration_structure_descriptor_type())
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+            );
+        }
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(
+        device: &Device,
+        texture_count: u32,
+        ray_query: bool,
+    ) -> vk::DescriptorPool {
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
+        };
+        let mut pool_sizes = vec![pool_size];
+        if texture_count > 0 {
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: texture_count,
+            });
+        }
+        if ray_query {
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: ray_tracing::acceleration_structure_descriptor_type(),
+                descriptor_count: 1,
+            });
+        }
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl MaterialInstances {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the shader keywords of the base material.
+    pub fn keywords(&self) -> &[&'static str] {
+        &self.keywords
+    }
+
+    /// Get the face culling of the base material.
+    pub fn culling(&self) -> FaceCulling {
+        self.culling
+    }
+
+    /// Bind `lightmap` in the set of the instances.
+    ///
+    /// The base material must have the `LIGHTMAP` keyword and the set must not
+    /// be in use.
+    pub fn set_lightmap(&self, device: &Device, lightmap: Texture) {
+        self.write_texture(device, LIGHTMAP_BINDING, lightmap);
+    }
+
+    /// Bind `shadow_map` in the set of the instances.
+    ///
+    /// The base material must have the `SHADOWS` keyword and the set must not
+    /// be in use.
+    pub fn set_shadow_map(&self, device: &Device, shadow_map: Texture) {
+        self.write_texture(device, SHADOW_MAP_BINDING, shadow_map);
+    }
+
+    /// Bind `shadow_atlas` in the set of the instances.
+    ///
+    /// The base material must have the `SPOT_SHADOWS` keyword and the set must
+    /// not be in use.
+    pub fn set_shadow_atlas(&self, device: &Device, shadow_atlas: Texture) {
+        self.write_texture(device, SHADOW_ATLAS_BINDING, shadow_atlas);
+    }
+
+    /// Bind `top_level` in the set of the instances.
+    ///
+    /// The base material must have the `RAY_QUERY_SHADOWS` keyword and the set
+    /// must not be in use.
+    pub fn set_top_level(&self, device: &Device, top_level: AccelerationStructureHandle) {
+        ray_tracing::write_acceleration_structure(
+            device,
+            &[self.set],
+            TOP_LEVEL_BINDING,
+            top_level,
+        );
+    }
+
+    fn write_texture(&self, device: &Device, binding: u32, texture: Texture) {
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build();
+        let image_infos = [image_info];
+        let descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+    }
+
+    /// Get the parameters of `instance`.
+    pub fn parameters(&self, instance: MaterialInstance) -> MaterialParameters {
+        self.parameters[instance.0 as usize]
+    }
+
+    /// Add an instance of the base material using `parameters`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the uniform buffer is full.
+    pub fn add_instance(
+        &mut self,
+        device: &Device,
+        parameters: MaterialParameters,
+    ) -> MaterialInstance {
+        if self.parameters.len() as u32 == MAX_MATERIAL_INSTANCES {
+            panic!("Material instance buffer is full.");
+        }
+        let instance = MaterialInstance(self.parameters.len() as _);
+        self.parameters.push(parameters);
+        self.write_parameters(device, instance);
+        instance
+    }
+
+    fn write_parameters(&self, device: &Device, instance: MaterialInstance) {
+        let offset = self.offset(instance) as vk::DeviceSize;
+        self.uniform_buffer
+            .write(device, offset, &[self.parameters[instance.0 as usize]]);
+    }
+
+    /// Get the dynamic offset of the parameters of `instance` in the uniform buffer.
+    fn offset(&self, instance: MaterialInstance) -> u32 {
+        (self.stride * instance.0 as vk::DeviceSize) as _
+    }
+
+    /// Bind the parameters of `instance` with `encoder` for the draws using `pipeline_layout`.
+    ///
+    /// The descriptor set of the instances must be part of `pipeline_layout`
+    /// at `MATERIAL_SET`.
+    pub fn cmd_bind(
+        &self,
+        encoder: &mut CommandEncoder,
+        pipeline_layout: vk::PipelineLayout,
+        instance: MaterialInstance,
+    ) {
+        encoder.bind_descriptor_sets(
+            pipeline_layout,
+            MATERIAL_SET,
+            &[self.set],
+            &[self.offset(instance)],
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            self.uniform_buffer.destroy(device);
+        }
+    }
+}
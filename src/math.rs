@@ -1,5 +1,9 @@
+mod backend;
+
 use cgmath::prelude::*;
-use cgmath::{BaseFloat, Matrix4, Rad};
+use cgmath::{BaseFloat, Matrix4, Point3, Quaternion, Rad, Vector3, Vector4};
+// Shadowed by `Transform` below
+use cgmath::Transform as _;
 
 /// Perspective matrix that is suitable for Vulkan.
 ///
@@ -33,13 +37,71 @@ where
     let c3r2 = -(far * near) / (far - near);
     let c3r3 = S::zero();
 
-    #[cfg_attr(rustfmt, rustfmt::skip)]
-    Matrix4::new(
+    #[rustfmt::skip]
+    let matrix = Matrix4::new(
         c0r0, c0r1, c0r2, c0r3,
         c1r0, c1r1, c1r2, c1r3,
         c2r0, c2r1, c2r2, c2r3,
         c3r0, c3r1, c3r2, c3r3,
-    )
+    );
+    matrix
+}
+
+/// Orthographic matrix that is suitable for Vulkan.
+///
+/// Like `perspective`, it inverts the projected y-axis and maps the depth
+/// between `near` and `far` to 0..1.
+pub fn orthographic<S: BaseFloat>(
+    left: S,
+    right: S,
+    bottom: S,
+    top: S,
+    near: S,
+    far: S,
+) -> Matrix4<S> {
+    let two = S::one() + S::one();
+
+    let c0r0 = two / (right - left);
+    let c1r1 = -two / (top - bottom);
+    let c2r2 = -S::one() / (far - near);
+    let c3r0 = -(right + left) / (right - left);
+    let c3r1 = (top + bottom) / (top - bottom);
+    let c3r2 = -near / (far - near);
+
+    #[rustfmt::skip]
+    let matrix = Matrix4::new(
+        c0r0, S::zero(), S::zero(), S::zero(),
+        S::zero(), c1r1, S::zero(), S::zero(),
+        S::zero(), S::zero(), c2r2, S::zero(),
+        c3r0, c3r1, c3r2, S::one(),
+    );
+    matrix
+}
+
+/// Replace the near plane of the perspective `proj` by `clip_plane`.
+///
+/// `clip_plane` holds the coefficients of the plane in view space. The side of
+/// its normal is kept and the camera must be behind it. The far plane is
+/// tilted so the depth stays between 0 and 1, which costs some precision.
+pub fn oblique_projection(proj: Matrix4<f32>, clip_plane: Vector4<f32>) -> Matrix4<f32> {
+    let inverse = proj.invert().unwrap();
+    // Corner of the frustum the furthest from the plane, on the far plane
+    let clip_space_plane = inverse.transpose() * clip_plane;
+    let corner = inverse
+        * Vector4::new(
+            clip_space_plane.x.signum(),
+            clip_space_plane.y.signum(),
+            1.0,
+            1.0,
+        );
+    // Scale the plane so the corner keeps a depth of 1, where z == w
+    let plane = clip_plane * (proj.row(3).dot(corner) / clip_plane.dot(corner));
+    let mut proj = proj;
+    proj.x.z = plane.x;
+    proj.y.z = plane.y;
+    proj.z.z = plane.z;
+    proj.w.z = plane.w;
+    proj
 }
 
 /// Clamp `value` between `min` and `max`.
@@ -51,3 +113,399 @@ pub fn clamp<T: PartialOrd>(value: T, min: T, max: T) -> T {
         value
     }
 }
+
+/// Translation, rotation and scale of an object.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    /// Get the matrix applying the scale, then the rotation, then the translation.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        backend::compose(self.translation, self.rotation, self.scale)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Axis aligned bounding box.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// Create an empty box that can be grown with `expand`.
+    pub fn empty() -> Self {
+        Aabb {
+            min: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Point3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    /// Grow the box so it contains `point`.
+    pub fn expand(&mut self, point: Point3<f32>) {
+        self.min = Point3::new(
+            self.min.x.min(point.x),
+            self.min.y.min(point.y),
+            self.min.z.min(point.z),
+        );
+        self.max = Point3::new(
+            self.max.x.max(point.x),
+            self.max.y.max(point.y),
+            self.max.z.max(point.z),
+        );
+    }
+
+    /// Check if the box contains no point, like the one of `empty`.
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Get the box containing both this one and `other`.
+    pub fn union(&self, other: &Aabb) -> Self {
+        Aabb {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Get the radius of the sphere centered on the box going through its corners.
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).magnitude() * 0.5
+    }
+
+    /// Get the eight corners of the box.
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// Get the box containing this one once transformed by `transform`.
+    pub fn transform(&self, transform: Matrix4<f32>) -> Self {
+        let mut aabb = Aabb::empty();
+        self.corners()
+            .iter()
+            .for_each(|corner| aabb.expand(transform.transform_point(*corner)));
+        aabb
+    }
+
+    /// Intersect `ray` with the box using the slab method.
+    ///
+    /// # Returns
+    ///
+    /// The distance along the ray at which it enters the box, or 0 if its
+    /// origin is inside.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = f32::MAX;
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        if near <= far {
+            Some(near)
+        } else {
+            None
+        }
+    }
+}
+
+/// Plane containing the points `p` verifying `normal.dot(p) + distance == 0`.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Create the plane going through `point` with `normal`.
+    pub fn from_point_normal(point: Point3<f32>, normal: Vector3<f32>) -> Self {
+        Plane {
+            normal,
+            distance: -normal.dot(point.to_vec()),
+        }
+    }
+
+    /// Create a plane from its equation coefficients and normalize it.
+    pub fn from_coefficients(coefficients: Vector4<f32>) -> Self {
+        let length = coefficients.truncate().magnitude();
+        Plane {
+            normal: coefficients.truncate() / length,
+            distance: coefficients.w / length,
+        }
+    }
+
+    /// Get the coefficients of the equation of the plane.
+    pub fn coefficients(&self) -> Vector4<f32> {
+        self.normal.extend(self.distance)
+    }
+
+    /// Get the reflection through the plane, whose normal must be normalized.
+    pub fn reflection(&self) -> Matrix4<f32> {
+        let n = self.normal;
+        let column = |axis: Vector3<f32>| (axis - n * (2.0 * n.dot(axis))).extend(0.0);
+        Matrix4::from_cols(
+            column(Vector3::unit_x()),
+            column(Vector3::unit_y()),
+            column(Vector3::unit_z()),
+            (n * (-2.0 * self.distance)).extend(1.0),
+        )
+    }
+
+    /// Get the distance of `point` to the plane, positive on the side of the normal.
+    ///
+    /// The distance is scaled by the length of the normal if it is not normalized.
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+
+    /// Intersect `ray` with the plane.
+    ///
+    /// # Returns
+    ///
+    /// The distance along the ray, or `None` if the ray is parallel to the
+    /// plane or points away from it.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<f32> {
+        let denominator = ray.direction.dot(self.normal);
+        if denominator.abs() < f32::EPSILON {
+            return None;
+        }
+        let distance = -self.signed_distance(ray.origin) / denominator;
+        if distance < 0.0 {
+            None
+        } else {
+            Some(distance)
+        }
+    }
+}
+
+/// Volume seen by a camera, bounded by six planes whose normals point inside.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far planes.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extract the planes of the frustum of `view_proj`.
+    ///
+    /// `view_proj` must map depth to 0..1 like `perspective` does.
+    pub fn from_view_proj(view_proj: Matrix4<f32>) -> Self {
+        let row = |i| view_proj.row(i);
+        let plane = Plane::from_coefficients;
+        Frustum {
+            planes: [
+                plane(row(3) + row(0)),
+                plane(row(3) - row(0)),
+                plane(row(3) + row(1)),
+                plane(row(3) - row(1)),
+                plane(row(2)),
+                plane(row(3) - row(2)),
+            ],
+        }
+    }
+
+    /// Check if `aabb` is at least partially inside the frustum.
+    ///
+    /// The test is conservative: boxes close to the corners of the frustum
+    /// might be reported as visible.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| backend::furthest_corner_distance(plane, aabb) >= 0.0)
+    }
+}
+
+/// Half line starting at `origin`.
+///
+/// The direction is not necessarily normalized so a ray can be transformed to
+/// object space while keeping the distances of its world space version.
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// Unproject `cursor`, in pixels from the top left corner of a viewport of
+    /// size `viewport_size`, into a world space ray starting on the near plane.
+    ///
+    /// `inverse_view_proj` must map depth from 0..1 like the inverse of `perspective` does.
+    pub fn from_cursor(
+        cursor: [f32; 2],
+        viewport_size: [f32; 2],
+        inverse_view_proj: Matrix4<f32>,
+    ) -> Self {
+        let x = cursor[0] / viewport_size[0] * 2.0 - 1.0;
+        let y = cursor[1] / viewport_size[1] * 2.0 - 1.0;
+        let unproject = |depth| {
+            let point = inverse_view_proj * Vector4::new(x, y, depth, 1.0);
+            Point3::from_homogeneous(point)
+        };
+        let near = unproject(0.0);
+        let far = unproject(1.0);
+        Ray {
+            origin: near,
+            direction: (far - near).normalize(),
+        }
+    }
+
+    /// Get the point at `distance` along the ray.
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    pub fn transform(&self, transform: Matrix4<f32>) -> Self {
+        Ray {
+            origin: transform.transform_point(self.origin),
+            direction: transform.transform_vector(self.direction),
+        }
+    }
+}
+
+/// Closest intersection of a ray with a surface.
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    /// Distance along the ray.
+    pub distance: f32,
+    pub point: Point3<f32>,
+    /// Normal of the surface, facing the origin of the ray.
+    pub normal: Vector3<f32>,
+}
+
+/// Intersect `ray` with the triangle `a`, `b`, `c` using the Möller-Trumbore algorithm.
+///
+/// Both faces of the triangle can be hit.
+pub fn intersect_triangle(
+    ray: &Ray,
+    a: Point3<f32>,
+    b: Point3<f32>,
+    c: Point3<f32>,
+) -> Option<RayHit> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        // Ray parallel to the triangle
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+
+    let s = ray.origin - a;
+    let u = s.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(q) * inverse_determinant;
+    if distance < 0.0 {
+        return None;
+    }
+
+    let normal = edge1.cross(edge2).normalize();
+    let normal = if normal.dot(ray.direction) > 0.0 {
+        -normal
+    } else {
+        normal
+    };
+    Some(RayHit {
+        distance,
+        point: ray.at(distance),
+        normal,
+    })
+}
+
+/// Triangles of a mesh kept on the CPU for ray casts.
+pub struct CollisionMesh {
+    positions: Vec<Point3<f32>>,
+    indices: Vec<u32>,
+    bounds: Aabb,
+}
+
+impl CollisionMesh {
+    pub fn new(positions: Vec<Point3<f32>>, indices: Vec<u32>) -> Self {
+        let mut bounds = Aabb::empty();
+        positions
+            .iter()
+            .for_each(|position| bounds.expand(*position));
+        CollisionMesh {
+            positions,
+            indices,
+            bounds,
+        }
+    }
+
+    /// Get the bounds of the mesh in object space.
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// Intersect the world space `ray` with the mesh placed in the world by `transform`.
+    ///
+    /// The ray is moved to object space and tested against the bounds of the
+    /// mesh before its triangles.
+    pub fn intersect_ray(&self, ray: &Ray, transform: Matrix4<f32>) -> Option<RayHit> {
+        let local_ray = ray.transform(transform.invert()?);
+        self.bounds.intersect_ray(&local_ray)?;
+
+        let hit = self
+            .indices
+            .chunks_exact(3)
+            .filter_map(|triangle| {
+                let vertex = |i: usize| self.positions[triangle[i] as usize];
+                intersect_triangle(&local_ray, vertex(0), vertex(1), vertex(2))
+            })
+            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())?;
+
+        // The distance is the same in both spaces since the direction was
+        // transformed without being normalized.
+        let normal_matrix = transform.invert()?.transpose();
+        Some(RayHit {
+            distance: hit.distance,
+            point: ray.at(hit.distance),
+            normal: normal_matrix.transform_vector(hit.normal).normalize(),
+        })
+    }
+}
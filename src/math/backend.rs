@@ -0,0 +1,61 @@
+//! Hot paths of the math module.
+//!
+//! They use the SIMD types of glam when the `glam` feature is enabled and
+//! cgmath otherwise. Both versions take and return cgmath types so the rest
+//! of the app does not depend on the backend.
+
+use super::{Aabb, Plane};
+use cgmath::{Matrix4, Quaternion, Vector3};
+
+/// Compose the matrix applying `scale`, then `rotation`, then `translation`.
+#[cfg(feature = "glam")]
+pub fn compose(
+    translation: Vector3<f32>,
+    rotation: Quaternion<f32>,
+    scale: Vector3<f32>,
+) -> Matrix4<f32> {
+    let rotation = glam::Quat::from_xyzw(rotation.v.x, rotation.v.y, rotation.v.z, rotation.s);
+    let matrix = glam::Mat4::from_scale_rotation_translation(
+        glam::Vec3::new(scale.x, scale.y, scale.z),
+        rotation,
+        glam::Vec3::new(translation.x, translation.y, translation.z),
+    );
+    matrix.to_cols_array_2d().into()
+}
+
+/// Compose the matrix applying `scale`, then `rotation`, then `translation`.
+#[cfg(not(feature = "glam"))]
+pub fn compose(
+    translation: Vector3<f32>,
+    rotation: Quaternion<f32>,
+    scale: Vector3<f32>,
+) -> Matrix4<f32> {
+    Matrix4::from_translation(translation)
+        * Matrix4::from(rotation)
+        * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)
+}
+
+/// Get the signed distance to `plane` of the corner of `aabb` the furthest
+/// along its normal.
+#[cfg(feature = "glam")]
+pub fn furthest_corner_distance(plane: &Plane, aabb: &Aabb) -> f32 {
+    let normal = glam::Vec3A::new(plane.normal.x, plane.normal.y, plane.normal.z);
+    let min = glam::Vec3A::new(aabb.min.x, aabb.min.y, aabb.min.z);
+    let max = glam::Vec3A::new(aabb.max.x, aabb.max.y, aabb.max.z);
+    let corner = glam::Vec3A::select(normal.cmpgt(glam::Vec3A::ZERO), max, min);
+    normal.dot(corner) + plane.distance
+}
+
+/// Get the signed distance to `plane` of the corner of `aabb` the furthest
+/// along its normal.
+#[cfg(not(feature = "glam"))]
+pub fn furthest_corner_distance(plane: &Plane, aabb: &Aabb) -> f32 {
+    let select = |axis: usize| {
+        if plane.normal[axis] > 0.0 {
+            aabb.max[axis]
+        } else {
+            aabb.min[axis]
+        }
+    };
+    plane.signed_distance(cgmath::Point3::new(select(0), select(1), select(2)))
+}
@@ -0,0 +1,225 @@
+use crate::{context::*, gpu_device::GpuDevice};
+use ash::{version::InstanceV1_1, vk, Instance};
+use std::{collections::HashMap, ffi::CStr, sync::Mutex};
+
+/// Ratio of the budget of a heap above which allocations log a warning.
+const BUDGET_WARNING_RATIO: f64 = 0.9;
+
+/// Memory allocated from a device with `allocate` and not freed yet.
+///
+/// Each device has its own, which its clones share, so buffers and textures
+/// can be freed with only the device at hand.
+#[derive(Default)]
+pub struct MemoryUsage {
+    state: Mutex<MemoryUsageState>,
+}
+
+#[derive(Default)]
+struct MemoryUsageState {
+    /// Running total of the memory allocated on each heap.
+    allocated: [vk::DeviceSize; vk::MAX_MEMORY_HEAPS],
+    /// Heap and size of each allocation, to update the totals when it is freed.
+    allocations: HashMap<vk::DeviceMemory, (u32, vk::DeviceSize)>,
+}
+
+impl MemoryUsage {
+    /// Get the memory allocated on the heap `heap_index`.
+    pub fn allocated(&self, heap_index: u32) -> vk::DeviceSize {
+        self.state.lock().unwrap().allocated[heap_index as usize]
+    }
+
+    fn add(&self, memory: vk::DeviceMemory, heap_index: u32, size: vk::DeviceSize) {
+        let mut state = self.state.lock().unwrap();
+        state.allocated[heap_index as usize] += size;
+        state.allocations.insert(memory, (heap_index, size));
+    }
+
+    fn remove(&self, memory: vk::DeviceMemory) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((heap_index, size)) = state.allocations.remove(&memory) {
+            state.allocated[heap_index as usize] -= size;
+        }
+    }
+}
+
+/// Get the device extensions required to query the memory budget.
+pub fn required_device_extensions() -> [&'static CStr; 1] {
+    [vk::ExtMemoryBudgetFn::name()]
+}
+
+/// Check if `device` supports memory budget queries.
+///
+/// Vulkan 1.1 must be supported by the instance and the device since the
+/// budget is queried using `vkGetPhysicalDeviceMemoryProperties2`.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    required_device_extensions()
+        .iter()
+        .all(|ext| is_device_extension_supported(instance, device, ext))
+}
+
+/// Memory usage of a heap.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    /// Memory allocated by the app on the heap.
+    pub allocated: vk::DeviceSize,
+    /// Memory used by the process on the heap as reported by the driver.
+    ///
+    /// It is `allocated` if `VK_EXT_memory_budget` is not enabled.
+    pub usage: vk::DeviceSize,
+    /// Memory the process can use on the heap.
+    ///
+    /// It is the size of the heap if `VK_EXT_memory_budget` is not enabled.
+    pub budget: vk::DeviceSize,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum AllocationError {
+    /// The allocation would exceed the budget of the heap.
+    OverBudget {
+        heap_index: u32,
+        size: vk::DeviceSize,
+        stats: HeapStats,
+    },
+    AllocationFailed(vk::Result),
+}
+
+impl std::error::Error for AllocationError {}
+
+impl std::fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllocationError::OverBudget {
+                heap_index,
+                size,
+                stats,
+            } => write!(
+                f,
+                "OverBudget: {} bytes requested on heap {} using {} of {} bytes",
+                size, heap_index, stats.usage, stats.budget
+            ),
+            AllocationError::AllocationFailed(result) => write!(f, "AllocationFailed: {}", result),
+        }
+    }
+}
+
+/// Get the memory usage of each heap of the device.
+pub fn memory_stats(vk_context: &VkContext) -> Vec<HeapStats> {
+    let properties = vk_context.get_mem_properties();
+    let budget = if vk_context.is_memory_budget_enabled() {
+        Some(get_memory_budget(vk_context))
+    } else {
+        None
+    };
+
+    let memory_usage = vk_context.device().memory_usage();
+    (0..properties.memory_heap_count as usize)
+        .map(|index| {
+            let allocated = memory_usage.allocated(index as u32);
+            match budget {
+                Some(budget) => HeapStats {
+                    allocated,
+                    usage: budget.heap_usage[index],
+                    budget: budget.heap_budget[index],
+                },
+                None => HeapStats {
+                    allocated,
+                    usage: allocated,
+                    budget: properties.memory_heaps[index].size,
+                },
+            }
+        })
+        .collect()
+}
+
+fn get_memory_budget(vk_context: &VkContext) -> vk::PhysicalDeviceMemoryBudgetPropertiesEXT {
+    let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceMemoryProperties2::builder()
+        .push_next(&mut budget)
+        .build();
+    unsafe {
+        vk_context
+            .instance()
+            .get_physical_device_memory_properties2(vk_context.physical_device(), &mut properties)
+    };
+    budget
+}
+
+/// Allocate memory of `memory_type_index` matching `requirements`.
+///
+/// A warning is logged when the allocation gets the usage of the heap near its budget.
+///
+/// # Errors
+///
+/// Fail without allocating if it would exceed the budget of the heap, or if
+/// the allocation itself fails.
+pub fn allocate(
+    vk_context: &VkContext,
+    requirements: vk::MemoryRequirements,
+    memory_type_index: u32,
+) -> Result<vk::DeviceMemory, AllocationError> {
+    let heap_index =
+        vk_context.get_mem_properties().memory_types[memory_type_index as usize].heap_index;
+    let stats = memory_stats(vk_context)[heap_index as usize];
+    let flags = vk_context
+        .buffer_device_address()
+        .map_or_else(vk::MemoryAllocateFlags::empty, |address| {
+            address.memory_allocate_flags()
+        });
+    allocate_from_heap(
+        vk_context.device(),
+        (requirements, flags),
+        memory_type_index,
+        heap_index,
+        stats,
+    )
+}
+
+/// Allocate memory of `memory_type_index` with `flags`, on `heap_index` whose
+/// usage is `stats`.
+///
+/// See `allocate`, which queries the heap and its usage.
+pub fn allocate_from_heap(
+    device: &impl GpuDevice,
+    (requirements, flags): (vk::MemoryRequirements, vk::MemoryAllocateFlags),
+    memory_type_index: u32,
+    heap_index: u32,
+    stats: HeapStats,
+) -> Result<vk::DeviceMemory, AllocationError> {
+    let size = requirements.size;
+    let usage = stats.usage + size;
+    if usage > stats.budget {
+        return Err(AllocationError::OverBudget {
+            heap_index,
+            size,
+            stats,
+        });
+    }
+    if usage as f64 > stats.budget as f64 * BUDGET_WARNING_RATIO {
+        log::warn!(
+            "Memory heap {} is nearly full: {} of {} bytes used.",
+            heap_index,
+            usage,
+            stats.budget
+        );
+    }
+
+    let mut flags_info = vk::MemoryAllocateFlagsInfo::builder().flags(flags).build();
+    let mut alloc_info_builder = vk::MemoryAllocateInfo::builder()
+        .allocation_size(size)
+        .memory_type_index(memory_type_index);
+    if !flags.is_empty() {
+        alloc_info_builder = alloc_info_builder.push_next(&mut flags_info);
+    }
+    let alloc_info = alloc_info_builder.build();
+    let memory = unsafe { device.allocate_memory(&alloc_info) }
+        .map_err(AllocationError::AllocationFailed)?;
+
+    device.memory_usage().add(memory, heap_index, size);
+    Ok(memory)
+}
+
+/// Free `memory` allocated with `allocate`.
+pub fn free(device: &impl GpuDevice, memory: vk::DeviceMemory) {
+    device.memory_usage().remove(memory);
+    unsafe { device.free_memory(memory) };
+}
@@ -0,0 +1,412 @@
+use crate::{
+    buffer::Buffer,
+    context::*,
+    gpu_device::Device,
+    lifetime,
+    meshlet::{MAX_MESHLET_TRIANGLES, MAX_MESHLET_VERTICES},
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Instance,
+};
+use std::{
+    ffi::{c_void, CStr},
+    mem, ptr,
+};
+
+// Extension of the EXT mesh shaders, which is not known by this version of ash.
+const MESH_SHADER: &[u8] = b"VK_EXT_mesh_shader\0";
+const SPIRV_1_4: &[u8] = b"VK_KHR_spirv_1_4\0";
+
+// Raw values of the structure types of the EXT mesh shaders.
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_FEATURES: i32 = 1_000_328_000;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_PROPERTIES: i32 = 1_000_328_001;
+
+/// Number of meshlets processed by each task shader workgroup.
+///
+/// Must match the workgroup size of `shader.task`.
+pub const MESHLETS_PER_TASK: u32 = 32;
+
+const VERTICES_BINDING: u32 = 0;
+const MESHLETS_BINDING: u32 = 1;
+const VERTEX_INDICES_BINDING: u32 = 2;
+const PRIMITIVE_INDICES_BINDING: u32 = 3;
+
+/// Get the device extensions required for the mesh shader path.
+///
+/// The EXT mesh shaders are compiled to SPIR-V 1.4.
+pub fn required_device_extensions() -> [&'static CStr; 3] {
+    [
+        CStr::from_bytes_with_nul(MESH_SHADER).unwrap(),
+        CStr::from_bytes_with_nul(SPIRV_1_4).unwrap(),
+        vk::KhrShaderFloatControlsFn::name(),
+    ]
+}
+
+/// Check if `device` supports task and mesh shaders, with enough outputs for
+/// our meshlets.
+///
+/// Vulkan 1.1 must be supported by the instance and the device since the
+/// features are queried using `vkGetPhysicalDeviceFeatures2`.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let extensions_supported = required_device_extensions()
+        .iter()
+        .all(|ext| is_device_extension_supported(instance, device, ext));
+    if !extensions_supported {
+        return false;
+    }
+
+    let mut mesh_shader_features = PhysicalDeviceMeshShaderFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut mesh_shader_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { get_physical_device_features2(instance, device, &mut features) };
+    if mesh_shader_features.task_shader != vk::TRUE || mesh_shader_features.mesh_shader != vk::TRUE
+    {
+        return false;
+    }
+
+    let properties = unsafe { MeshShaderProperties::query(instance, device) };
+    properties.max_mesh_output_vertices as usize >= MAX_MESHLET_VERTICES
+        && properties.max_mesh_output_primitives as usize >= MAX_MESHLET_TRIANGLES
+}
+
+/// Build the mesh shader features to enable at device creation.
+pub fn required_features() -> PhysicalDeviceMeshShaderFeatures {
+    PhysicalDeviceMeshShaderFeatures {
+        task_shader: vk::TRUE,
+        mesh_shader: vk::TRUE,
+        ..Default::default()
+    }
+}
+
+/// Limits of the task and mesh shaders of a device.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshShaderProperties {
+    /// Maximum number of task shader workgroups in each dimension of a draw.
+    pub max_task_work_group_count: [u32; 3],
+    /// Maximum number of vertices output by a mesh shader workgroup.
+    pub max_mesh_output_vertices: u32,
+    /// Maximum number of primitives output by a mesh shader workgroup.
+    pub max_mesh_output_primitives: u32,
+}
+
+impl MeshShaderProperties {
+    /// Query the mesh shader properties of `device`.
+    ///
+    /// # Safety
+    ///
+    /// Vulkan 1.1 must be supported by the instance and the device.
+    pub unsafe fn query(instance: &Instance, device: vk::PhysicalDevice) -> Self {
+        let mut mesh_shader_properties = PhysicalDeviceMeshShaderProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2 {
+            p_next: &mut mesh_shader_properties as *mut _ as *mut c_void,
+            ..Default::default()
+        };
+        get_physical_device_properties2(instance, device, &mut properties);
+
+        MeshShaderProperties {
+            max_task_work_group_count: mesh_shader_properties.max_task_work_group_count,
+            max_mesh_output_vertices: mesh_shader_properties.max_mesh_output_vertices,
+            max_mesh_output_primitives: mesh_shader_properties.max_mesh_output_primitives,
+        }
+    }
+}
+
+/// Buffers holding the meshlets of a mesh.
+#[derive(Clone, Copy)]
+pub struct MeshletBuffers {
+    pub meshlets: Buffer,
+    pub vertex_indices: Buffer,
+    pub primitive_indices: Buffer,
+    pub meshlet_count: u32,
+}
+
+/// Geometry processing using task and mesh shaders.
+///
+/// The task shader culls the meshlets against the view frustum and
+/// emits one mesh shader workgroup per visible meshlet. The mesh shader
+/// then reads the vertices and triangles of the meshlet from storage
+/// buffers bound in set 2.
+///
+/// `VK_EXT_mesh_shader` is not exposed by our version of Ash so its draw
+/// command is loaded by hand. Its shader stages have the same values as the
+/// ones of `VK_NV_mesh_shader`, so the NV flags of ash are used for them.
+pub struct MeshShading {
+    properties: MeshShaderProperties,
+    cmd_draw_mesh_tasks: PfnCmdDrawMeshTasks,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    meshlet_buffers: MeshletBuffers,
+}
+
+impl MeshShading {
+    /// Create the descriptor set containing the vertices and the meshlets.
+    ///
+    /// `vertex_buffer` must have been created with the `STORAGE_BUFFER` usage.
+    /// It is not owned by the returned object.
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        vertex_buffer: Buffer,
+        meshlet_buffers: MeshletBuffers,
+    ) -> Self {
+        let properties = unsafe { MeshShaderProperties::query(instance, physical_device) };
+        let cmd_draw_mesh_tasks = unsafe {
+            let name = CStr::from_bytes_with_nul(b"vkCmdDrawMeshTasksEXT\0").unwrap();
+            let function = instance
+                .get_device_proc_addr(device.handle(), name.as_ptr())
+                .expect("Failed to load vkCmdDrawMeshTasksEXT.");
+            mem::transmute::<VoidFunction, PfnCmdDrawMeshTasks>(function)
+        };
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let buffers = [
+            (VERTICES_BINDING, vertex_buffer.buffer),
+            (MESHLETS_BINDING, meshlet_buffers.meshlets.buffer),
+            (
+                VERTEX_INDICES_BINDING,
+                meshlet_buffers.vertex_indices.buffer,
+            ),
+            (
+                PRIMITIVE_INDICES_BINDING,
+                meshlet_buffers.primitive_indices.buffer,
+            ),
+        ];
+        let buffer_infos = buffers
+            .iter()
+            .map(|(_, buffer)| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(*buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+        let descriptor_writes = buffers
+            .iter()
+            .zip(buffer_infos.iter())
+            .map(|((binding, _), buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(*binding)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        MeshShading {
+            properties,
+            cmd_draw_mesh_tasks,
+            layout,
+            pool,
+            set,
+            meshlet_buffers,
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, stage_flags| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .stage_flags(stage_flags)
+                .build()
+        };
+        let bindings = [
+            binding(VERTICES_BINDING, vk::ShaderStageFlags::MESH_NV),
+            binding(
+                MESHLETS_BINDING,
+                vk::ShaderStageFlags::TASK_NV | vk::ShaderStageFlags::MESH_NV,
+            ),
+            binding(VERTEX_INDICES_BINDING, vk::ShaderStageFlags::MESH_NV),
+            binding(PRIMITIVE_INDICES_BINDING, vk::ShaderStageFlags::MESH_NV),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 4,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl MeshShading {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn set(&self) -> vk::DescriptorSet {
+        self.set
+    }
+
+    /// Record the draw of all the meshlets in `command_buffer`.
+    ///
+    /// The task workgroups overflowing the first dimension of the draw are
+    /// laid out in rows, which the task shader flattens back.
+    pub fn cmd_draw_meshlets(&self, command_buffer: vk::CommandBuffer) {
+        let meshlet_count = self.meshlet_buffers.meshlet_count;
+        let task_count = meshlet_count.div_ceil(MESHLETS_PER_TASK);
+        let row_size = task_count.clamp(1, self.properties.max_task_work_group_count[0]);
+        let row_count = task_count.div_ceil(row_size);
+        unsafe { (self.cmd_draw_mesh_tasks)(command_buffer, row_size, row_count, 1) };
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.meshlet_buffers.meshlets.destroy(device);
+        self.meshlet_buffers.vertex_indices.destroy(device);
+        self.meshlet_buffers.primitive_indices.destroy(device);
+    }
+}
+
+/// `VkPhysicalDeviceMeshShaderFeaturesEXT`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalDeviceMeshShaderFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    pub task_shader: vk::Bool32,
+    pub mesh_shader: vk::Bool32,
+    multiview_mesh_shader: vk::Bool32,
+    primitive_fragment_shading_rate_mesh_shader: vk::Bool32,
+    mesh_shader_queries: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceMeshShaderFeatures {
+    fn default() -> Self {
+        PhysicalDeviceMeshShaderFeatures {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_FEATURES,
+            ),
+            p_next: ptr::null_mut(),
+            task_shader: vk::FALSE,
+            mesh_shader: vk::FALSE,
+            multiview_mesh_shader: vk::FALSE,
+            primitive_fragment_shading_rate_mesh_shader: vk::FALSE,
+            mesh_shader_queries: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceMeshShaderFeatures {}
+
+/// `VkPhysicalDeviceMeshShaderPropertiesEXT`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PhysicalDeviceMeshShaderProperties {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    max_task_work_group_total_count: u32,
+    max_task_work_group_count: [u32; 3],
+    max_task_work_group_invocations: u32,
+    max_task_work_group_size: [u32; 3],
+    max_task_payload_size: u32,
+    max_task_shared_memory_size: u32,
+    max_task_payload_and_shared_memory_size: u32,
+    max_mesh_work_group_total_count: u32,
+    max_mesh_work_group_count: [u32; 3],
+    max_mesh_work_group_invocations: u32,
+    max_mesh_work_group_size: [u32; 3],
+    max_mesh_shared_memory_size: u32,
+    max_mesh_payload_and_shared_memory_size: u32,
+    max_mesh_output_memory_size: u32,
+    max_mesh_payload_and_output_memory_size: u32,
+    max_mesh_output_components: u32,
+    max_mesh_output_vertices: u32,
+    max_mesh_output_primitives: u32,
+    max_mesh_output_layers: u32,
+    max_mesh_multiview_view_count: u32,
+    mesh_output_per_vertex_granularity: u32,
+    mesh_output_per_primitive_granularity: u32,
+    max_preferred_task_work_group_invocations: u32,
+    max_preferred_mesh_work_group_invocations: u32,
+    prefers_local_invocation_vertex_output: vk::Bool32,
+    prefers_local_invocation_primitive_output: vk::Bool32,
+    prefers_compact_vertex_output: vk::Bool32,
+    prefers_compact_primitive_output: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceMeshShaderProperties {
+    fn default() -> Self {
+        PhysicalDeviceMeshShaderProperties {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_PROPERTIES,
+            ),
+            p_next: ptr::null_mut(),
+            max_task_work_group_total_count: 0,
+            max_task_work_group_count: [0; 3],
+            max_task_work_group_invocations: 0,
+            max_task_work_group_size: [0; 3],
+            max_task_payload_size: 0,
+            max_task_shared_memory_size: 0,
+            max_task_payload_and_shared_memory_size: 0,
+            max_mesh_work_group_total_count: 0,
+            max_mesh_work_group_count: [0; 3],
+            max_mesh_work_group_invocations: 0,
+            max_mesh_work_group_size: [0; 3],
+            max_mesh_shared_memory_size: 0,
+            max_mesh_payload_and_shared_memory_size: 0,
+            max_mesh_output_memory_size: 0,
+            max_mesh_payload_and_output_memory_size: 0,
+            max_mesh_output_components: 0,
+            max_mesh_output_vertices: 0,
+            max_mesh_output_primitives: 0,
+            max_mesh_output_layers: 0,
+            max_mesh_multiview_view_count: 0,
+            mesh_output_per_vertex_granularity: 0,
+            mesh_output_per_primitive_granularity: 0,
+            max_preferred_task_work_group_invocations: 0,
+            max_preferred_mesh_work_group_invocations: 0,
+            prefers_local_invocation_vertex_output: vk::FALSE,
+            prefers_local_invocation_primitive_output: vk::FALSE,
+            prefers_compact_vertex_output: vk::FALSE,
+            prefers_compact_primitive_output: vk::FALSE,
+        }
+    }
+}
+
+type VoidFunction = unsafe extern "system" fn() -> c_void;
+type PfnCmdDrawMeshTasks = unsafe extern "system" fn(
+    command_buffer: vk::CommandBuffer,
+    group_count_x: u32,
+    group_count_y: u32,
+    group_count_z: u32,
+);
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+/// Maximum number of vertices referenced by a meshlet.
+pub const MAX_MESHLET_VERTICES: usize = 64;
+
+/// Maximum number of triangles in a meshlet.
+pub const MAX_MESHLET_TRIANGLES: usize = 126;
+
+/// Meshlet as laid out in the meshlet storage buffer.
+///
+/// `vertex_offset` indexes the meshlet vertex indices and `primitive_offset`
+/// indexes the bytes of the primitive indices.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Meshlet {
+    /// Bounding sphere of the meshlet. The radius is in the w component.
+    pub bounds: [f32; 4],
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    pub primitive_offset: u32,
+    pub primitive_count: u32,
+}
+
+/// Meshlets built from an indexed triangle list.
+pub struct Meshlets {
+    pub meshlets: Vec<Meshlet>,
+    /// Indices into the vertex buffer of the vertices of each meshlet.
+    pub vertex_indices: Vec<u32>,
+    /// Indices into the meshlet vertices of the triangles of each meshlet.
+    ///
+    /// The length is a multiple of 4 so it can be read as an array of u32.
+    pub primitive_indices: Vec<u8>,
+}
+
+/// Split the triangles described by `indices` into meshlets.
+///
+/// Triangles are added in order to the current meshlet until it reaches
+/// either `MAX_MESHLET_VERTICES` vertices or `MAX_MESHLET_TRIANGLES` triangles.
+pub fn build_meshlets(positions: &[[f32; 3]], indices: &[u32]) -> Meshlets {
+    let mut meshlets = Vec::new();
+    let mut vertex_indices = Vec::new();
+    let mut primitive_indices = Vec::new();
+
+    let mut local_indices = HashMap::new();
+    let mut meshlet = empty_meshlet(0, 0);
+
+    for triangle in indices.chunks(3) {
+        let new_vertex_count = triangle
+            .iter()
+            .filter(|index| !local_indices.contains_key(*index))
+            .count();
+        if meshlet.vertex_count as usize + new_vertex_count > MAX_MESHLET_VERTICES
+            || meshlet.primitive_count as usize == MAX_MESHLET_TRIANGLES
+        {
+            meshlet.bounds =
+                compute_bounds(positions, &vertex_indices[meshlet.vertex_offset as _..]);
+            meshlets.push(meshlet);
+            local_indices.clear();
+            meshlet = empty_meshlet(vertex_indices.len() as _, primitive_indices.len() as _);
+        }
+
+        for index in triangle {
+            let local_index = *local_indices.entry(*index).or_insert_with(|| {
+                vertex_indices.push(*index);
+                meshlet.vertex_count += 1;
+                meshlet.vertex_count - 1
+            });
+            primitive_indices.push(local_index as u8);
+        }
+        meshlet.primitive_count += 1;
+    }
+
+    if meshlet.primitive_count > 0 {
+        meshlet.bounds = compute_bounds(positions, &vertex_indices[meshlet.vertex_offset as _..]);
+        meshlets.push(meshlet);
+    }
+
+    while primitive_indices.len() % 4 != 0 {
+        primitive_indices.push(0);
+    }
+
+    log::debug!(
+        "Built {} meshlets from {} triangles.",
+        meshlets.len(),
+        indices.len() / 3
+    );
+
+    Meshlets {
+        meshlets,
+        vertex_indices,
+        primitive_indices,
+    }
+}
+
+fn empty_meshlet(vertex_offset: u32, primitive_offset: u32) -> Meshlet {
+    Meshlet {
+        bounds: [0.0; 4],
+        vertex_offset,
+        vertex_count: 0,
+        primitive_offset,
+        primitive_count: 0,
+    }
+}
+
+/// Compute a bounding sphere centered on the center of the bounding box
+/// of the vertices.
+fn compute_bounds(positions: &[[f32; 3]], vertex_indices: &[u32]) -> [f32; 4] {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for index in vertex_indices {
+        let position = positions[*index as usize];
+        for i in 0..3 {
+            min[i] = min[i].min(position[i]);
+            max[i] = max[i].max(position[i]);
+        }
+    }
+
+    let center = [
+        (min[0] + max[0]) * 0.5,
+        (min[1] + max[1]) * 0.5,
+        (min[2] + max[2]) * 0.5,
+    ];
+    let radius = vertex_indices
+        .iter()
+        .map(|index| {
+            let position = positions[*index as usize];
+            let x = position[0] - center[0];
+            let y = position[1] - center[1];
+            let z = position[2] - center[2];
+            (x * x + y * y + z * z).sqrt()
+        })
+        .fold(0.0, f32::max);
+
+    [center[0], center[1], center[2], radius]
+}
@@ -0,0 +1,308 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    frame_layout::{write_model_texture, FRAME_SET},
+    gpu_device::Device,
+    lifetime,
+    math::{self, Plane},
+    texture::Texture,
+    UniformBufferObject,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Point3, Vector3};
+use std::mem::size_of;
+
+/// Stencil value written where the mirror is visible, where the reflection is drawn.
+pub const MIRROR_STENCIL_REFERENCE: u32 = 2;
+
+/// Rectangle of the scene reflecting it.
+#[derive(Clone, Copy, Debug)]
+pub struct MirrorQuad {
+    pub center: Point3<f32>,
+    /// Normal of the reflecting face.
+    pub normal: Vector3<f32>,
+    /// Direction of the height of the rectangle, orthogonal to the normal.
+    pub up: Vector3<f32>,
+    pub half_width: f32,
+    pub half_height: f32,
+}
+
+impl MirrorQuad {
+    /// Vertical mirror standing behind the model, facing it.
+    pub fn behind_model() -> Self {
+        MirrorQuad {
+            center: Point3::new(0.0, 0.5, -1.5),
+            normal: Vector3::unit_z(),
+            up: Vector3::unit_y(),
+            half_width: 1.5,
+            half_height: 1.0,
+        }
+    }
+
+    /// Get the plane of the mirror.
+    pub fn plane(&self) -> Plane {
+        Plane::from_point_normal(self.center, self.normal)
+    }
+
+    /// Get the positions of the two triangles of the quad, counter clockwise
+    /// when seen from the reflecting face.
+    pub fn triangles(&self) -> [Point3<f32>; 6] {
+        let right = self.up.cross(self.normal) * self.half_width;
+        let up = self.up * self.half_height;
+        let corner = |x: f32, y: f32| self.center + right * x + up * y;
+        [
+            corner(-1.0, -1.0),
+            corner(1.0, -1.0),
+            corner(1.0, 1.0),
+            corner(1.0, 1.0),
+            corner(-1.0, 1.0),
+            corner(-1.0, -1.0),
+        ]
+    }
+}
+
+/// Pipelines drawing the mirror and the reflection in it.
+#[derive(Clone, Copy, Debug)]
+pub struct MirrorPipelines {
+    /// Pipeline writing `MIRROR_STENCIL_REFERENCE` in the stencil of the
+    /// pixels of the mirror, without writing the color or the depth.
+    pub mask: vk::Pipeline,
+    /// Pipeline drawing the model from the reflected camera where the stencil
+    /// is `MIRROR_STENCIL_REFERENCE`, with clockwise front faces.
+    pub reflection: vk::Pipeline,
+    /// Pipeline blending the tint of the mirror over the reflection and
+    /// writing its depth.
+    pub surface: vk::Pipeline,
+}
+
+/// Planar mirror reflecting the model.
+///
+/// The model is drawn a second time from the camera reflected through the
+/// plane of the mirror, masked by the stencil so the reflection only shows
+/// where the mirror is visible. The near plane of the reflected camera is
+/// replaced by the plane of the mirror with an oblique projection, so the
+/// geometry behind the mirror does not end up in the reflection.
+///
+/// The reflected camera has its own uniform buffers in place of the frame
+/// uniform buffers, so the mirror has its own set 0 per swapchain image.
+pub struct Mirror {
+    quad: MirrorQuad,
+    /// Triangles of the quad, as vertices of the model.
+    vertex_buffer: Buffer,
+    extent: vk::Extent2D,
+    uniform_buffers: Vec<Buffer>,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    pipelines: Option<MirrorPipelines>,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Mirror {
+    /// Create the descriptor sets of the reflected camera.
+    ///
+    /// `vertex_buffer` holds the `triangles` of `quad` as vertices of the model.
+    /// `frame_layout` is the layout of the frame descriptor sets and `texture` the
+    /// texture of the model. There must be one uniform buffer per swapchain
+    /// image, host visible and coherent.
+    ///
+    /// The pipelines must be set using `set_pipelines` before recording draws.
+    pub fn new(
+        device: &Device,
+        (quad, vertex_buffer): (MirrorQuad, Buffer),
+        frame_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        let set_count = uniform_buffers.len();
+        let pool = Self::create_descriptor_pool(device, set_count as _);
+        let sets = {
+            let layouts = vec![frame_layout; set_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let texture_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build()];
+        for (set, buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let buffer_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size_of::<UniformBufferObject>() as _)
+                .build()];
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&buffer_infos)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&texture_infos)
+                    .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        Mirror {
+            quad,
+            vertex_buffer,
+            extent: vk::Extent2D::default(),
+            uniform_buffers,
+            pool,
+            sets,
+            pipelines: None,
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, set_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: set_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: set_count,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(set_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Mirror {
+    /// Set the pipelines, their shared layout and the extent of the target.
+    ///
+    /// The mirror takes ownership of the pipelines and their layout.
+    pub fn set_pipelines(
+        &mut self,
+        pipelines: MirrorPipelines,
+        pipeline_layout: vk::PipelineLayout,
+        extent: vk::Extent2D,
+    ) {
+        self.pipelines = Some(pipelines);
+        self.pipeline_layout = pipeline_layout;
+        self.extent = extent;
+    }
+
+    /// Write the uniform buffer of the reflected camera for the swapchain image `image_index`.
+    ///
+    /// `frame_ubo` contains the model transform, the camera and the fog of the
+    /// main view.
+    pub fn update(&self, device: &Device, image_index: usize, frame_ubo: UniformBufferObject) {
+        let plane = self.quad.plane();
+        let reflection = plane.reflection();
+        let view = frame_ubo.view * reflection;
+        let clip_plane = view.invert().unwrap().transpose() * plane.coefficients();
+        let proj = math::oblique_projection(frame_ubo.proj, clip_plane);
+        let camera_position = Point3::from_homogeneous(frame_ubo.camera_position.into());
+        let ubo = frame_ubo.with_camera(view, proj, reflection.transform_point(camera_position));
+        self.uniform_buffers[image_index].write(device, 0, &[ubo]);
+    }
+
+    /// Record the reflection and the mirror of the swapchain image `image_index` with `encoder`.
+    ///
+    /// The mirror is masked in the stencil, the model is drawn from the
+    /// reflected camera inside the mask, then the depth of the reflection is
+    /// cleared and the mirror is blended over it with its own depth. It must be
+    /// recorded in the first subpass of the main render pass before the opaque
+    /// geometry, and the frame set must be bound again after it.
+    pub fn cmd_draw(
+        &self,
+        encoder: &mut CommandEncoder,
+        image_index: usize,
+        (vertex_buffer, index_buffer, index_count): (Buffer, Buffer, u32),
+    ) {
+        let pipelines = self.pipelines.expect("Mirror pipelines not set");
+        let device = encoder.device();
+        let command_buffer = encoder.command_buffer();
+
+        encoder.bind_descriptor_sets(
+            self.pipeline_layout,
+            FRAME_SET,
+            &self.sets[image_index..=image_index],
+            &[],
+        );
+        encoder.bind_vertex_buffers(0, &[self.vertex_buffer.buffer], &[0]);
+        encoder.bind_pipeline(pipelines.mask);
+        unsafe { device.cmd_draw(command_buffer, 6, 1, 0, 0) };
+
+        encoder.bind_pipeline(pipelines.reflection);
+        encoder.bind_vertex_buffers(0, &[vertex_buffer.buffer], &[0]);
+        encoder.bind_index_buffer(index_buffer.buffer, 0, vk::IndexType::UINT32);
+        unsafe { device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0) };
+
+        // Nothing else is drawn yet, so the whole depth can be cleared
+        let clear_attachments = [vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        }];
+        let clear_rects = [vk::ClearRect {
+            rect: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            },
+            base_array_layer: 0,
+            layer_count: 1,
+        }];
+        unsafe { device.cmd_clear_attachments(command_buffer, &clear_attachments, &clear_rects) };
+
+        encoder.bind_pipeline(pipelines.surface);
+        encoder.bind_vertex_buffers(0, &[self.vertex_buffer.buffer], &[0]);
+        unsafe { device.cmd_draw(command_buffer, 6, 1, 0, 0) };
+    }
+
+    /// Destroy the pipelines and their layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipelines(&mut self, device: &Device) {
+        unsafe {
+            if let Some(pipelines) = self.pipelines.take() {
+                lifetime::destroy_pipeline(device, pipelines.mask);
+                lifetime::destroy_pipeline(device, pipelines.reflection);
+                lifetime::destroy_pipeline(device, pipelines.surface);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    /// Replace the texture of the model by `texture` after it was reloaded.
+    ///
+    /// The sets must not be in use.
+    pub fn set_texture(&self, device: &Device, texture: Texture) {
+        write_model_texture(device, &self.sets, texture);
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipelines(device);
+        lifetime::destroy_descriptor_pool(device, self.pool);
+        self.vertex_buffer.destroy(device);
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
@@ -0,0 +1,432 @@
+use crate::{
+    buffer::Buffer, gpu_device::Device, lifetime, render_target::RenderTarget,
+    std140::std140_struct, texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Matrix4, Vector4};
+use std::mem::size_of;
+
+/// Format of the velocity render target.
+///
+/// Velocities are stored in texture coordinates per frame.
+pub const VELOCITY_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+const UNIFORMS_BINDING: u32 = 0;
+const HDR_COLOR_BINDING: u32 = 1;
+const VELOCITY_BINDING: u32 = 2;
+
+std140_struct! {
+    /// Uniforms of the velocity and motion blur shaders as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct MotionBlurUniforms {
+        pub view_proj: Matrix4<f32>,
+        pub previous_view_proj: Matrix4<f32>,
+        pub model: Matrix4<f32>,
+        pub previous_model: Matrix4<f32>,
+        /// Transform from the current clip space of the far plane to the previous one.
+        ///
+        /// It only contains the rotation of the camera since the sky is infinitely far.
+        pub sky_reprojection: Matrix4<f32>,
+        /// Fraction of the frame during which the shutter is open.
+        pub intensity: f32,
+    }
+}
+
+/// Matrices of the previous frame.
+#[derive(Clone, Copy)]
+struct PreviousFrame {
+    view: Matrix4<f32>,
+    proj: Matrix4<f32>,
+    model: Matrix4<f32>,
+}
+
+/// Motion blur computed from a per-pixel velocity buffer.
+///
+/// Before the main render pass, the velocity pass writes the screen space
+/// motion of each pixel since the previous frame in the velocity target. The
+/// background gets the motion due to the rotation of the camera and the model
+/// is drawn with its current and previous transforms so both the motion of the
+/// camera and of the model are captured. The velocity target is exposed so
+/// other temporal effects can reuse it.
+///
+/// After the main render pass, the blur pass averages the HDR image along the
+/// velocity of each pixel into its own HDR target, which is then tonemapped.
+pub struct MotionBlur {
+    intensity: f32,
+    previous_frame: Option<PreviousFrame>,
+    velocity_target: RenderTarget,
+    blur_target: RenderTarget,
+    sampler: vk::Sampler,
+    uniform_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    sky_velocity_pipeline: vk::Pipeline,
+    velocity_pipeline: vk::Pipeline,
+    blur_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl MotionBlur {
+    /// Create the descriptor sets of the velocity and blur passes.
+    ///
+    /// `velocity_target` must have a `VELOCITY_FORMAT` color attachment and a
+    /// depth attachment. `blur_target` must have a single HDR color attachment.
+    /// Uniform buffers must be host visible and coherent, one per swapchain image.
+    ///
+    /// The HDR image must be set using `set_hdr_texture` and the pipelines using
+    /// `set_pipelines` before recording passes.
+    pub fn new(
+        device: &Device,
+        intensity: f32,
+        velocity_target: RenderTarget,
+        blur_target: RenderTarget,
+        uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        let image_count = uniform_buffers.len();
+        let sampler = Self::create_sampler(device);
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        for (set, buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let buffer_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size_of::<MotionBlurUniforms>() as _)
+                .build()];
+            let descriptor_writes = [vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(UNIFORMS_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_infos)
+                .build()];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        MotionBlur {
+            intensity,
+            previous_frame: None,
+            velocity_target,
+            blur_target,
+            sampler,
+            uniform_buffers,
+            layout,
+            pool,
+            sets,
+            sky_velocity_pipeline: vk::Pipeline::null(),
+            velocity_pipeline: vk::Pipeline::null(),
+            blur_pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type, stage_flags| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(stage_flags)
+                .build()
+        };
+        let bindings = [
+            binding(
+                UNIFORMS_BINDING,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                HDR_COLOR_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                VELOCITY_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count * 2,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl MotionBlur {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn velocity_render_pass(&self) -> vk::RenderPass {
+        self.velocity_target.render_pass()
+    }
+
+    pub fn blur_render_pass(&self) -> vk::RenderPass {
+        self.blur_target.render_pass()
+    }
+
+    /// Get the velocity texture.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the velocity pass.
+    pub fn velocity(&self) -> Texture {
+        self.velocity_target.color(0)
+    }
+
+    /// Get the blurred HDR texture.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the blur pass.
+    pub fn output(&self) -> Texture {
+        self.blur_target.color(0)
+    }
+
+    /// Get the velocity and blur targets so they can be resized.
+    ///
+    /// `set_hdr_texture` must be called after resizing them.
+    pub fn targets_mut(&mut self) -> (&mut RenderTarget, &mut RenderTarget) {
+        (&mut self.velocity_target, &mut self.blur_target)
+    }
+
+    /// Set the HDR image to blur.
+    ///
+    /// It must be in `hdr_layout` and readable by fragment shaders when the
+    /// blur pass begins. It also updates the descriptors of the velocity texture.
+    pub fn set_hdr_texture(&self, device: &Device, (hdr, hdr_layout): (Texture, vk::ImageLayout)) {
+        let hdr_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(hdr_layout)
+            .image_view(hdr.view)
+            .sampler(self.sampler)
+            .build()];
+        let velocity = self.velocity();
+        let velocity_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(velocity.view)
+            .sampler(velocity.sampler.unwrap())
+            .build()];
+        let descriptor_writes = self
+            .sets
+            .iter()
+            .flat_map(|set| {
+                let write = |binding, image_infos| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*set)
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(image_infos)
+                        .build()
+                };
+                vec![
+                    write(HDR_COLOR_BINDING, &hdr_infos),
+                    write(VELOCITY_BINDING, &velocity_infos),
+                ]
+            })
+            .collect::<Vec<_>>();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    /// Set the pipelines writing the velocity of the background and of the
+    /// model, and the pipeline blurring the HDR image.
+    ///
+    /// They all use `pipeline_layout`. The motion blur takes ownership of the
+    /// pipelines and their layout.
+    pub fn set_pipelines(
+        &mut self,
+        sky_velocity_pipeline: vk::Pipeline,
+        velocity_pipeline: vk::Pipeline,
+        blur_pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        self.sky_velocity_pipeline = sky_velocity_pipeline;
+        self.velocity_pipeline = velocity_pipeline;
+        self.blur_pipeline = blur_pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index` and keep
+    /// the matrices for the next frame.
+    ///
+    /// The first frame has no motion.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        image_index: usize,
+        view: Matrix4<f32>,
+        proj: Matrix4<f32>,
+        model: Matrix4<f32>,
+    ) {
+        let current_frame = PreviousFrame { view, proj, model };
+        let previous_frame = self.previous_frame.unwrap_or(current_frame);
+        self.previous_frame = Some(current_frame);
+
+        let rotation_view_proj = |frame: PreviousFrame| {
+            let mut view = frame.view;
+            view.w = Vector4::new(0.0, 0.0, 0.0, 1.0);
+            frame.proj * view
+        };
+        let uniforms = MotionBlurUniforms {
+            view_proj: proj * view,
+            previous_view_proj: previous_frame.proj * previous_frame.view,
+            model,
+            previous_model: previous_frame.model,
+            sky_reprojection: rotation_view_proj(previous_frame)
+                * rotation_view_proj(current_frame).invert().unwrap(),
+            intensity: self.intensity,
+        };
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+    }
+
+    /// Record the velocity pass of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// It must be recorded outside of any render pass, before the blur pass.
+    /// Only the model is drawn so objects in front of it do not hide its motion.
+    pub fn cmd_render_velocity(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        vertex_buffer: Buffer,
+        index_buffer: Buffer,
+        index_count: u32,
+    ) {
+        self.velocity_target.cmd_begin(device, command_buffer);
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.sets[image_index..=image_index],
+                &[],
+            );
+
+            // The background first, with a full screen triangle
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.sky_velocity_pipeline,
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.velocity_pipeline,
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+            device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+        }
+        self.velocity_target.cmd_end(device, command_buffer);
+    }
+
+    /// Record the blur pass of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// It must be recorded outside of any render pass, after the main render
+    /// pass and the velocity pass.
+    pub fn cmd_blur(&self, device: &Device, command_buffer: vk::CommandBuffer, image_index: usize) {
+        self.blur_target.cmd_begin(device, command_buffer);
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.blur_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.sets[image_index..=image_index],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+        self.blur_target.cmd_end(device, command_buffer);
+    }
+
+    /// Destroy the pipelines and their layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipelines(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.sky_velocity_pipeline);
+            lifetime::destroy_pipeline(device, self.velocity_pipeline);
+            lifetime::destroy_pipeline(device, self.blur_pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.sky_velocity_pipeline = vk::Pipeline::null();
+        self.velocity_pipeline = vk::Pipeline::null();
+        self.blur_pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipelines(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        self.velocity_target.destroy(device);
+        self.blur_target.destroy(device);
+    }
+}
@@ -0,0 +1,436 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    frame_layout::PASS_SET,
+    geometry_pool::{GeometryBlock, GeometryPool},
+    gpu_device::Device,
+    lifetime,
+    material::{MaterialInstance, MaterialInstances},
+    math::{CollisionMesh, Transform},
+    render_list::RenderList,
+    scene::{Entity, Layers, MeshHandle, Scene},
+    std140::std140_struct,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{Deg, Matrix4, Quaternion, Rotation3, Vector3};
+use std::{collections::VecDeque, mem::size_of, sync::Arc};
+
+/// Index of the pipeline of the meshes drawn by `ObjectRenderer`.
+pub const OBJECT_PIPELINE: u16 = 1;
+
+/// Maximum number of objects drawn per frame.
+const MAX_OBJECTS: u32 = 256;
+
+/// Number of meshes the geometry pool can hold.
+const MAX_MESHES: u32 = 16;
+
+/// Maximum number of vertices of a mesh.
+const MESH_VERTICES: u32 = 4096;
+
+/// Maximum number of indices of a mesh.
+const MESH_INDICES: u32 = 3 * MESH_VERTICES;
+
+std140_struct! {
+    /// Uniforms of an object as laid out in the uniform buffer.
+    ///
+    /// Must match `ObjectUniforms` in `object.vert` and `object.frag`.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct ObjectUniforms {
+        pub model: Matrix4<f32>,
+        pub tint: [f32; 4],
+        pub emissive_strength: f32,
+    }
+}
+
+/// Meshes spawned and despawned at runtime with `Scene::spawn` and `Scene::despawn`.
+///
+/// The meshes are uploaded once in blocks of a `GeometryPool`. Since command
+/// buffers are recorded once, each of the `MAX_OBJECTS` slots is drawn with
+/// its own indirect draw, with the uniforms of the slot bound at their
+/// dynamic offset. Every frame, `update` writes the objects of the render
+/// list in the slots of the swapchain image, so spawning or despawning an
+/// object only changes what is written the next frame: nothing is allocated
+/// from the driver, no descriptor set is written and no command buffer is
+/// recorded again. Objects past `MAX_OBJECTS` are not drawn.
+pub struct ObjectRenderer {
+    pool: GeometryPool,
+    /// Block of each mesh, indexed by the index of the block.
+    blocks: Vec<Option<GeometryBlock>>,
+    uniform_buffers: Vec<Buffer>,
+    indirect_buffers: Vec<Buffer>,
+    stride: vk::DeviceSize,
+    layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    /// Number of objects written by the last update.
+    object_count: usize,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl ObjectRenderer {
+    /// Create the descriptor sets of the objects.
+    ///
+    /// The buffers of `pool` must have been created for the sizes returned by
+    /// `ObjectRenderer::pool_blocks`. `uniform_buffers` must be host visible
+    /// and coherent buffers of `ObjectRenderer::uniform_buffer_size` bytes and
+    /// `indirect_buffers` of `ObjectRenderer::indirect_buffer_size()` bytes,
+    /// one per swapchain image.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(
+        device: &Device,
+        pool: GeometryPool,
+        (uniform_buffers, indirect_buffers): (Vec<Buffer>, Vec<Buffer>),
+        min_offset_alignment: vk::DeviceSize,
+    ) -> Self {
+        let image_count = uniform_buffers.len();
+        let layout = Self::create_descriptor_set_layout(device);
+        let descriptor_pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        sets.iter()
+            .zip(uniform_buffers.iter())
+            .for_each(|(set, buffer)| {
+                let buffer_info = vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer.buffer)
+                    .offset(0)
+                    .range(size_of::<ObjectUniforms>() as _)
+                    .build();
+                let buffer_infos = [buffer_info];
+                let ubo_write = vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                    .buffer_info(&buffer_infos)
+                    .build();
+                unsafe { device.update_descriptor_sets(&[ubo_write], &[]) };
+            });
+
+        ObjectRenderer {
+            pool,
+            blocks: Vec::new(),
+            uniform_buffers,
+            indirect_buffers,
+            stride: object_stride(min_offset_alignment),
+            layout,
+            descriptor_pool,
+            sets,
+            object_count: 0,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [ubo_binding];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, size: u32) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: size,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(size)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    /// Number of blocks of the pool, and number of vertices and indices of each block.
+    pub fn pool_blocks() -> (u32, u32, u32) {
+        (MAX_MESHES, MESH_VERTICES, MESH_INDICES)
+    }
+
+    /// Size in bytes of each uniform buffer.
+    ///
+    /// `min_offset_alignment` is the `minUniformBufferOffsetAlignment` limit of the device.
+    pub fn uniform_buffer_size(min_offset_alignment: vk::DeviceSize) -> vk::DeviceSize {
+        object_stride(min_offset_alignment) * MAX_OBJECTS as vk::DeviceSize
+    }
+
+    /// Size in bytes of each indirect buffer.
+    pub fn indirect_buffer_size() -> vk::DeviceSize {
+        (size_of::<vk::DrawIndexedIndirectCommand>() as u32 * MAX_OBJECTS) as _
+    }
+}
+
+impl ObjectRenderer {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the number of objects drawn by the last frame, before culling.
+    pub fn object_count(&self) -> usize {
+        self.object_count
+    }
+
+    /// Upload a mesh to spawn objects drawing it.
+    ///
+    /// `collision_mesh` holds the triangles of `vertices` and `indices` for
+    /// picking. Return `None` if the pool is full.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the mesh is larger than a block of the pool.
+    pub fn add_mesh<V: Copy>(
+        &mut self,
+        device: &Device,
+        vertices: &[V],
+        indices: &[u32],
+        collision_mesh: CollisionMesh,
+    ) -> Option<MeshHandle> {
+        let block = self.pool.allocate()?;
+        self.pool.write(device, block, vertices, indices);
+        let index = block.index as usize;
+        if index >= self.blocks.len() {
+            self.blocks.resize(index + 1, None);
+        }
+        self.blocks[index] = Some(block);
+        Some(MeshHandle {
+            mesh: block.index,
+            index_count: indices.len() as _,
+            pipeline: OBJECT_PIPELINE,
+            collision_mesh: Arc::new(collision_mesh),
+        })
+    }
+
+    /// Give the block of `mesh` back to the pool.
+    ///
+    /// The objects drawing the mesh must be despawned first. The block is
+    /// only reused once the frames in flight are done with it.
+    pub fn remove_mesh(&mut self, mesh: &MeshHandle) {
+        if let Some(block) = self.blocks[mesh.mesh as usize].take() {
+            self.pool.free(block);
+        }
+    }
+
+    /// Set the pipeline used to draw the objects.
+    ///
+    /// The renderer takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the uniforms and the draws of the objects of `list` drawn with
+    /// `OBJECT_PIPELINE` and with one of `layers`, culled against its frustum,
+    /// with the parameters of their material from `material_instances`.
+    ///
+    /// They are written in the buffers of the swapchain image `image_index`,
+    /// so it must be called once per frame, after waiting for its fence and
+    /// before submitting its command buffer.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        image_index: usize,
+        (list, layers): (&RenderList, Layers),
+        material_instances: &MaterialInstances,
+    ) {
+        self.pool.begin_frame();
+
+        let blocks = &self.blocks;
+        let objects = list
+            .draws_in(layers)
+            .filter(|draw| draw.pipeline == OBJECT_PIPELINE)
+            .filter_map(|draw| Some((draw, blocks.get(draw.mesh as usize).copied()??)))
+            .take(MAX_OBJECTS as _)
+            .collect::<Vec<_>>();
+        self.object_count = objects.len();
+
+        let mut draws = vec![vk::DrawIndexedIndirectCommand::default(); MAX_OBJECTS as usize];
+        let buffer = self.uniform_buffers[image_index];
+        let size = self.stride * MAX_OBJECTS as vk::DeviceSize;
+        unsafe {
+            let data_ptr = device
+                .map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap() as *mut u8;
+            for (slot, (draw, block)) in objects.into_iter().enumerate() {
+                let parameters = material_instances.parameters(draw.material);
+                let uniforms = ObjectUniforms {
+                    model: draw.model,
+                    tint: parameters.tint,
+                    emissive_strength: parameters.emissive_strength,
+                };
+                let slot_ptr = data_ptr.add(slot * self.stride as usize);
+                (slot_ptr as *mut ObjectUniforms).write_unaligned(uniforms);
+
+                draws[slot] = vk::DrawIndexedIndirectCommand {
+                    index_count: draw.index_count,
+                    instance_count: list.frustum.intersects_aabb(&draw.bounds) as _,
+                    first_index: block.first_index,
+                    vertex_offset: block.vertex_offset,
+                    first_instance: 0,
+                };
+            }
+            device.unmap_memory(buffer.memory);
+        }
+
+        self.indirect_buffers[image_index].write(device, 0, &draws);
+    }
+
+    /// Record the draws of the slots of the swapchain image `image_index` with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    ///
+    /// Each slot is drawn with its own indirect draw so the `multiDrawIndirect`
+    /// and `drawIndirectFirstInstance` features are not required.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder, image_index: usize) {
+        let draw_stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[self.pool.vertex_buffer().buffer], &[0]);
+        encoder.bind_index_buffer(self.pool.index_buffer().buffer, 0, vk::IndexType::UINT32);
+        for slot in 0..MAX_OBJECTS {
+            encoder.bind_descriptor_sets(
+                self.pipeline_layout,
+                PASS_SET,
+                &[self.sets[image_index]],
+                &[(self.stride * slot as vk::DeviceSize) as _],
+            );
+            unsafe {
+                encoder.device().cmd_draw_indexed_indirect(
+                    encoder.command_buffer(),
+                    self.indirect_buffers[image_index].buffer,
+                    (slot * draw_stride) as _,
+                    1,
+                    draw_stride,
+                )
+            };
+        }
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.descriptor_pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.pool.destroy(device);
+        self.uniform_buffers
+            .iter_mut()
+            .chain(self.indirect_buffers.iter_mut())
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
+
+/// Get the stride between two objects in the uniform buffers.
+///
+/// `min_offset_alignment` is the `minUniformBufferOffsetAlignment` limit of the device.
+pub fn object_stride(min_offset_alignment: vk::DeviceSize) -> vk::DeviceSize {
+    let size = size_of::<ObjectUniforms>() as vk::DeviceSize;
+    let alignment = min_offset_alignment.max(1);
+    size.div_ceil(alignment) * alignment
+}
+
+/// Number of frames an object of the demo lives.
+const DEMO_OBJECT_LIFETIME: u32 = 120;
+
+/// Demo spawning an object every frame and despawning it `DEMO_OBJECT_LIFETIME`
+/// frames later.
+///
+/// The objects rise along a helix around the model while spinning, cycling
+/// through the materials.
+pub struct ObjectDemo {
+    mesh: MeshHandle,
+    materials: Vec<MaterialInstance>,
+    /// Objects alive and the frame they were spawned at, the oldest first.
+    objects: VecDeque<(Entity, u32)>,
+    frame: u32,
+}
+
+impl ObjectDemo {
+    /// Create the demo spawning objects drawing `mesh` with `materials`, which must not be empty.
+    pub fn new(mesh: MeshHandle, materials: Vec<MaterialInstance>) -> Self {
+        ObjectDemo {
+            mesh,
+            materials,
+            objects: VecDeque::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn mesh(&self) -> &MeshHandle {
+        &self.mesh
+    }
+
+    /// Spawn the object of the frame, move the others and despawn the oldest ones.
+    ///
+    /// Return the entities despawned.
+    pub fn update(&mut self, scene: &mut Scene) -> Vec<Entity> {
+        let mut despawned = Vec::new();
+        while let Some(&(entity, spawn_frame)) = self.objects.front() {
+            if self.frame - spawn_frame < DEMO_OBJECT_LIFETIME {
+                break;
+            }
+            scene.despawn(entity);
+            despawned.push(entity);
+            self.objects.pop_front();
+        }
+
+        let material = self.materials[self.frame as usize % self.materials.len()];
+        let entity = scene.spawn(&self.mesh, material, Transform::default());
+        self.objects.push_back((entity, self.frame));
+
+        for &(entity, spawn_frame) in &self.objects {
+            if let Some(transform) = scene.get_mut::<Transform>(entity) {
+                *transform = demo_transform(spawn_frame, self.frame - spawn_frame);
+            }
+        }
+        self.frame += 1;
+        despawned
+    }
+}
+
+/// Get the transform of the object of the demo spawned at `spawn_frame` after `age` frames.
+fn demo_transform(spawn_frame: u32, age: u32) -> Transform {
+    let progress = age as f32 / DEMO_OBJECT_LIFETIME as f32;
+    let angle = Deg(spawn_frame as f32 * 17.0 + progress * 90.0);
+    let (sin, cos) = (angle.0.to_radians().sin(), angle.0.to_radians().cos());
+    let radius = 1.5;
+    Transform {
+        translation: Vector3::new(radius * sin, -0.5 + 2.0 * progress, radius * cos),
+        rotation: Quaternion::from_angle_y(Deg(age as f32 * 6.0)),
+        // The objects grow when spawned and shrink before being despawned
+        scale: Vector3::new(1.0, 1.0, 1.0) * 0.08 * (progress * (1.0 - progress) * 4.0).sqrt(),
+    }
+}
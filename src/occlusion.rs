@@ -0,0 +1,428 @@
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    context::*,
+    gpu_device::Device,
+    lifetime,
+    math::Aabb,
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Instance,
+};
+use std::{
+    ffi::{c_void, CStr},
+    mem::{self, size_of},
+};
+
+/// Number of vertices of the proxy of an object, a box made of 12 triangles.
+const PROXY_VERTEX_COUNT: u32 = 36;
+
+/// Get the device extensions required for conditional rendering.
+pub fn required_device_extensions() -> [&'static CStr; 1] {
+    [vk::ExtConditionalRenderingFn::name()]
+}
+
+/// Check if `device` supports conditional rendering.
+///
+/// Vulkan 1.1 must be supported by the instance and the device since the
+/// features are queried using `vkGetPhysicalDeviceFeatures2`.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let extensions_supported = required_device_extensions()
+        .iter()
+        .all(|ext| is_device_extension_supported(instance, device, ext));
+    if !extensions_supported {
+        return false;
+    }
+
+    let mut conditional_rendering_features =
+        vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut conditional_rendering_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { get_physical_device_features2(instance, device, &mut features) };
+
+    conditional_rendering_features.conditional_rendering == vk::TRUE
+}
+
+/// Build the conditional rendering features to enable at device creation.
+pub fn required_features() -> vk::PhysicalDeviceConditionalRenderingFeaturesEXT {
+    vk::PhysicalDeviceConditionalRenderingFeaturesEXT::builder()
+        .conditional_rendering(true)
+        .build()
+}
+
+/// Get the vertex binding of the proxies, a single position.
+pub fn proxy_binding_description() -> vk::VertexInputBindingDescription {
+    vk::VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(size_of::<[f32; 3]>() as _)
+        .input_rate(vk::VertexInputRate::VERTEX)
+        .build()
+}
+
+pub fn proxy_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 1] {
+    [vk::VertexInputAttributeDescription::builder()
+        .binding(0)
+        .location(0)
+        .format(vk::Format::R32G32B32_SFLOAT)
+        .offset(0)
+        .build()]
+}
+
+/// How the draws of the occluded objects are skipped.
+pub enum OcclusionPredicate {
+    /// The results are copied in a buffer predicating the draws with
+    /// `VK_EXT_conditional_rendering`, one `u32` per object.
+    ConditionalRendering { buffer: Buffer },
+    /// The results are read back on the cpu which writes the instance count of
+    /// indirect draws, one `vk::DrawIndexedIndirectCommand` per object in a
+    /// buffer per swapchain image.
+    Readback { draw_buffers: Vec<Buffer> },
+}
+
+/// Occlusion queries skipping the draws of expensive objects hidden by the scene.
+///
+/// After the opaque geometry, the bounding box of each object is drawn as a
+/// proxy without writing the color or the depth, inside an occlusion query
+/// counting the samples passing the depth test. The next frames, the draw of
+/// an object whose proxy had no visible sample is skipped. The boxes are in
+/// the space of the model and drawn with its transform, without culling so an
+/// object stays visible when the camera is inside its box.
+///
+/// With conditional rendering, the results are copied after the render pass
+/// in a buffer predicating the draws of the next frame. Otherwise they are
+/// read back by `update` once the commands of the swapchain image executed and
+/// the draws are indirect, so an object appearing from behind an occluder is
+/// only drawn a few frames late.
+pub struct OcclusionQueries {
+    query_pool: vk::QueryPool,
+    object_count: u32,
+    draws: Vec<vk::DrawIndexedIndirectCommand>,
+    proxy_buffer: Buffer,
+    /// Loader of `VK_EXT_conditional_rendering` and the predicate buffer.
+    conditional_rendering: Option<(vk::ExtConditionalRenderingFn, Buffer)>,
+    draw_buffers: Vec<Buffer>,
+    /// Whether the queries of each swapchain image were submitted since they
+    /// were last read.
+    submitted: Vec<bool>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl OcclusionQueries {
+    /// Create the queries of the objects drawn by `draws`, bounded by `bounds`.
+    ///
+    /// `proxy_buffer` must be a host visible and coherent vertex buffer of
+    /// `OcclusionQueries::proxy_buffer_size(bounds)` bytes. The buffers of
+    /// `predicate` must be host visible and coherent too, the predicate
+    /// buffer of `OcclusionQueries::predicate_buffer_size(bounds)` bytes with
+    /// the `CONDITIONAL_RENDERING_EXT` and `TRANSFER_DST` usages, and the draw
+    /// buffers of `OcclusionQueries::draw_buffer_size(bounds)` bytes with the
+    /// `INDIRECT_BUFFER` usage.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording commands.
+    pub fn new(
+        instance: &Instance,
+        device: &Device,
+        (bounds, draws): (&[Aabb], &[vk::DrawIndexedIndirectCommand]),
+        proxy_buffer: Buffer,
+        image_count: usize,
+        predicate: OcclusionPredicate,
+    ) -> Self {
+        let vertices = bounds.iter().flat_map(box_vertices).collect::<Vec<_>>();
+        proxy_buffer.write(device, 0, &vertices);
+
+        // Everything is drawn until the first results
+        let (conditional_rendering, draw_buffers) = match predicate {
+            OcclusionPredicate::ConditionalRendering { buffer } => {
+                buffer.write(device, 0, &vec![1u32; bounds.len()]);
+                let conditional_rendering_fn = vk::ExtConditionalRenderingFn::load(|name| unsafe {
+                    mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+                });
+                (Some((conditional_rendering_fn, buffer)), Vec::new())
+            }
+            OcclusionPredicate::Readback { draw_buffers } => {
+                draw_buffers
+                    .iter()
+                    .for_each(|buffer| buffer.write(device, 0, draws));
+                (None, draw_buffers)
+            }
+        };
+
+        let object_count = bounds.len() as u32;
+        let pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(object_count * image_count as u32)
+            .build();
+        let query_pool = unsafe { device.create_query_pool(&pool_info, None).unwrap() };
+
+        OcclusionQueries {
+            query_pool,
+            object_count,
+            draws: draws.to_vec(),
+            proxy_buffer,
+            conditional_rendering,
+            draw_buffers,
+            submitted: vec![false; image_count],
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    pub fn proxy_buffer_size(bounds: &[Aabb]) -> vk::DeviceSize {
+        (bounds.len() * PROXY_VERTEX_COUNT as usize * size_of::<[f32; 3]>()) as _
+    }
+
+    pub fn predicate_buffer_size(bounds: &[Aabb]) -> vk::DeviceSize {
+        (bounds.len() * size_of::<u32>()) as _
+    }
+
+    pub fn draw_buffer_size(bounds: &[Aabb]) -> vk::DeviceSize {
+        (bounds.len() * size_of::<vk::DrawIndexedIndirectCommand>()) as _
+    }
+}
+
+impl OcclusionQueries {
+    /// Set the pipeline drawing the proxies.
+    ///
+    /// The occlusion queries take ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Read the results of the queries of the swapchain image `image_index`
+    /// and get the number of objects occluded.
+    ///
+    /// The commands of the image must have completed. Without conditional
+    /// rendering, the instance counts of its draws are written from them.
+    pub fn update(&mut self, device: &Device, image_index: usize) -> u32 {
+        if !mem::replace(&mut self.submitted[image_index], true) {
+            return 0;
+        }
+
+        let mut samples = vec![0u32; self.object_count as usize];
+        let first_query = image_index as u32 * self.object_count;
+        if unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                first_query,
+                self.object_count,
+                &mut samples,
+                vk::QueryResultFlags::empty(),
+            )
+        }
+        .is_err()
+        {
+            return 0;
+        }
+
+        if self.conditional_rendering.is_none() {
+            let draws = self
+                .draws
+                .iter()
+                .zip(&samples)
+                .map(|(draw, &samples)| vk::DrawIndexedIndirectCommand {
+                    instance_count: if samples > 0 { draw.instance_count } else { 0 },
+                    ..*draw
+                })
+                .collect::<Vec<_>>();
+            self.draw_buffers[image_index].write(device, 0, &draws);
+        }
+
+        samples.iter().filter(|&&samples| samples == 0).count() as _
+    }
+
+    /// Record the reset of the queries of the swapchain image `image_index`.
+    ///
+    /// It must be recorded before the render pass drawing the proxies.
+    pub fn cmd_reset(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        unsafe {
+            device.cmd_reset_query_pool(
+                command_buffer,
+                self.query_pool,
+                image_index as u32 * self.object_count,
+                self.object_count,
+            )
+        };
+    }
+
+    /// Record the draw of `object` unless it was occluded.
+    ///
+    /// The vertex and index buffers of the object must be bound.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder, image_index: usize, object: u32) {
+        let device = encoder.device();
+        let command_buffer = encoder.command_buffer();
+        match &self.conditional_rendering {
+            Some((conditional_rendering, buffer)) => {
+                let draw = self.draws[object as usize];
+                let begin_info = vk::ConditionalRenderingBeginInfoEXT::builder()
+                    .buffer(buffer.buffer)
+                    .offset((object as usize * size_of::<u32>()) as _)
+                    .build();
+                unsafe {
+                    conditional_rendering
+                        .cmd_begin_conditional_rendering_ext(command_buffer, &begin_info);
+                    device.cmd_draw_indexed(
+                        command_buffer,
+                        draw.index_count,
+                        draw.instance_count,
+                        draw.first_index,
+                        draw.vertex_offset,
+                        draw.first_instance,
+                    );
+                    conditional_rendering.cmd_end_conditional_rendering_ext(command_buffer);
+                }
+            }
+            None => {
+                let stride = size_of::<vk::DrawIndexedIndirectCommand>();
+                unsafe {
+                    device.cmd_draw_indexed_indirect(
+                        command_buffer,
+                        self.draw_buffers[image_index].buffer,
+                        (object as usize * stride) as _,
+                        1,
+                        stride as _,
+                    )
+                };
+            }
+        }
+    }
+
+    /// Record the draw of the proxies in their queries.
+    ///
+    /// It must be recorded after the opaque geometry, in the first subpass of
+    /// the main render pass.
+    pub fn cmd_draw_proxies(&self, encoder: &mut CommandEncoder, image_index: usize) {
+        let device = encoder.device();
+        let command_buffer = encoder.command_buffer();
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[self.proxy_buffer.buffer], &[0]);
+        for object in 0..self.object_count {
+            let query = image_index as u32 * self.object_count + object;
+            unsafe {
+                device.cmd_begin_query(
+                    command_buffer,
+                    self.query_pool,
+                    query,
+                    vk::QueryControlFlags::empty(),
+                );
+                device.cmd_draw(
+                    command_buffer,
+                    PROXY_VERTEX_COUNT,
+                    1,
+                    object * PROXY_VERTEX_COUNT,
+                    0,
+                );
+                device.cmd_end_query(command_buffer, self.query_pool, query);
+            }
+        }
+    }
+
+    /// Record the copy of the results in the predicate buffer.
+    ///
+    /// It must be recorded after the render pass drawing the proxies. It does
+    /// nothing without conditional rendering.
+    pub fn cmd_copy_results(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let buffer = match &self.conditional_rendering {
+            Some((_, buffer)) => buffer,
+            None => return,
+        };
+        unsafe {
+            // The predicate buffer was read by the draws of this frame
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(
+                    Access::new(
+                        vk::PipelineStageFlags::CONDITIONAL_RENDERING_EXT,
+                        vk::AccessFlags::CONDITIONAL_RENDERING_READ_EXT,
+                    ),
+                    Access::transfer_write(),
+                )],
+            );
+            device.cmd_copy_query_pool_results(
+                command_buffer,
+                self.query_pool,
+                image_index as u32 * self.object_count,
+                self.object_count,
+                buffer.buffer,
+                0,
+                size_of::<u32>() as _,
+                vk::QueryResultFlags::WAIT,
+            );
+            // And will be read by the draws of the next one
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(
+                    Access::transfer_write(),
+                    Access::new(
+                        vk::PipelineStageFlags::CONDITIONAL_RENDERING_EXT,
+                        vk::AccessFlags::CONDITIONAL_RENDERING_READ_EXT,
+                    ),
+                )],
+            );
+        }
+    }
+
+    /// Destroy the pipeline drawing the proxies.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe { device.destroy_query_pool(self.query_pool, None) };
+        self.proxy_buffer.destroy(device);
+        if let Some((_, buffer)) = self.conditional_rendering.as_mut() {
+            buffer.destroy(device);
+        }
+        self.draw_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
+
+/// Get the vertices of the 12 triangles of the faces of `aabb`.
+fn box_vertices(aabb: &Aabb) -> Vec<[f32; 3]> {
+    let corner = |i: usize| {
+        [
+            if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+            if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+            if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+        ]
+    };
+    // Two triangles per face, the proxies are drawn without culling
+    const FACES: [[usize; 4]; 6] = [
+        [0, 2, 6, 4],
+        [1, 5, 7, 3],
+        [0, 4, 5, 1],
+        [2, 3, 7, 6],
+        [0, 1, 3, 2],
+        [4, 6, 7, 5],
+    ];
+    FACES
+        .iter()
+        .flat_map(|face| [face[0], face[1], face[2], face[0], face[2], face[3]])
+        .map(corner)
+        .collect()
+}
@@ -0,0 +1,36 @@
+use ash::vk;
+
+/// Stencil value written where the outlined mesh is drawn.
+pub const OUTLINE_STENCIL_REFERENCE: u32 = 1;
+
+/// Get the stencil state of the pipeline drawing the outlined mesh.
+///
+/// It writes `OUTLINE_STENCIL_REFERENCE` where the mesh passes the depth test.
+pub fn outlined_stencil() -> vk::StencilOpState {
+    vk::StencilOpState {
+        fail_op: vk::StencilOp::KEEP,
+        pass_op: vk::StencilOp::REPLACE,
+        depth_fail_op: vk::StencilOp::KEEP,
+        compare_op: vk::CompareOp::ALWAYS,
+        compare_mask: 0xff,
+        write_mask: 0xff,
+        reference: OUTLINE_STENCIL_REFERENCE,
+    }
+}
+
+/// Get the stencil state of the pipeline drawing the outline.
+///
+/// The outline is the outlined mesh drawn again inflated, only where the mesh
+/// itself did not write `OUTLINE_STENCIL_REFERENCE`, so only the border around
+/// the mesh remains. It does not write the stencil.
+pub fn outline_stencil() -> vk::StencilOpState {
+    vk::StencilOpState {
+        fail_op: vk::StencilOp::KEEP,
+        pass_op: vk::StencilOp::KEEP,
+        depth_fail_op: vk::StencilOp::KEEP,
+        compare_op: vk::CompareOp::NOT_EQUAL,
+        compare_mask: 0xff,
+        write_mask: 0,
+        reference: OUTLINE_STENCIL_REFERENCE,
+    }
+}
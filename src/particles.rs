@@ -0,0 +1,597 @@
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    frame_layout::PASS_SET,
+    gpu_device::Device,
+    gpu_sort::GpuSort,
+    lifetime,
+    readback::ReadbackBuffer,
+    std140::std140_struct,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::Point3;
+use std::mem::size_of;
+
+/// Maximum number of living particles.
+pub const PARTICLE_CAPACITY: u32 = 4096;
+
+/// Size in bytes of a particle, a position and age then a velocity and lifetime.
+///
+/// Must match `Particle` in `particle.glsl`.
+const PARTICLE_SIZE: u32 = 32;
+
+const UNIFORMS_BINDING: u32 = 0;
+const PARTICLES_BINDING: u32 = 1;
+const OUTPUT_BINDING: u32 = 2;
+const COUNTERS_BINDING: u32 = 3;
+const SORT_KEYS_BINDING: u32 = 4;
+const SORT_VALUES_BINDING: u32 = 5;
+
+const DRAW_PARTICLES_BINDING: u32 = 0;
+const DRAW_INDICES_BINDING: u32 = 1;
+
+std140_struct! {
+    /// Uniforms of the particle shader as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct ParticleUniforms {
+        /// Position of the center of the emitter and its radius.
+        pub emitter: [f32; 4],
+        pub camera_position: [f32; 4],
+        /// Time in seconds since the last frame.
+        pub time_delta: f32,
+        pub time: f32,
+        /// Particles emitted per second.
+        pub emission_rate: f32,
+        pub capacity: u32,
+    }
+}
+
+/// Counters written by the particle shader, followed by the indirect
+/// dispatches and draw it writes.
+///
+/// Must match `Counters` in `particles.comp`.
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(dead_code)]
+#[repr(C)]
+pub struct ParticleCounters {
+    pub alive_count: u32,
+    pub output_count: u32,
+    pub emit_count: u32,
+    pub emit_carry: f32,
+    /// Dispatch of the update, padded to 16 bytes.
+    pub update_dispatch: [u32; 4],
+    /// Dispatch of the emission, padded to 16 bytes.
+    pub emit_dispatch: [u32; 4],
+    pub draw: vk::DrawIndirectCommand,
+}
+
+/// Stage of the simulation, the specialization constant 0 of `particles.comp`.
+///
+/// Must match the `STAGE_*` constants of `particles.comp`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParticleStage {
+    /// Count the emitted particles and write the indirect dispatches.
+    Prepare = 0,
+    /// Age and move the living particles and compact them.
+    Update = 1,
+    /// Append the emitted particles.
+    Emit = 2,
+    /// Write the number of living particles and the indirect draw.
+    Finalize = 3,
+}
+
+impl ParticleStage {
+    /// All the stages, in the order they are recorded.
+    pub const ALL: [ParticleStage; 4] = [
+        ParticleStage::Prepare,
+        ParticleStage::Update,
+        ParticleStage::Emit,
+        ParticleStage::Finalize,
+    ];
+}
+
+/// Particles emitted, simulated, sorted and drawn on the GPU.
+///
+/// The CPU only writes the emitter and the time step. A first compute stage
+/// counts the particles to emit and writes the indirect dispatches of the
+/// update and the emission, so their size comes from the counts written on
+/// the GPU without reading them back. The update compacts the living
+/// particles in the output buffer, where the emission appends the new ones,
+/// then the output is copied back for the next frame.
+///
+/// Each particle gets its distance to the camera as key, and `GpuSort` sorts
+/// their indices back to front. The quads are then drawn with an indirect
+/// draw whose instance count is the number of living particles.
+pub struct Particles {
+    particles: Buffer,
+    output: Buffer,
+    counters: Buffer,
+    uniform_buffers: Vec<Buffer>,
+    /// Number of living particles of each swapchain image.
+    alive_counts: ReadbackBuffer<u32>,
+    sort: GpuSort,
+    layout: vk::DescriptorSetLayout,
+    draw_layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    draw_set: vk::DescriptorSet,
+    /// Compute pipelines of each `ParticleStage`.
+    pipelines: Option<[vk::Pipeline; 4]>,
+    pipeline_layout: vk::PipelineLayout,
+    draw_pipeline: vk::Pipeline,
+    draw_pipeline_layout: vk::PipelineLayout,
+}
+
+impl Particles {
+    /// Create the descriptor sets of the simulation and the draw.
+    ///
+    /// `particles` and `output` must be storage buffers of
+    /// `Particles::particle_buffer_size()` bytes, `particles` being a transfer
+    /// destination and `output` a transfer source. `counters` must be a storage,
+    /// indirect and transfer source buffer of a `ParticleCounters`, cleared
+    /// with `cmd_reset`. `sort` must have a capacity of `PARTICLE_CAPACITY`.
+    /// Uniform buffers must be host visible and coherent, one per swapchain
+    /// image, and `alive_counts` reads back a single `u32` per swapchain image.
+    ///
+    /// The pipelines must be set using `set_pipelines` and `set_draw_pipeline`
+    /// before recording.
+    pub fn new(
+        device: &Device,
+        (particles, output, counters): (Buffer, Buffer, Buffer),
+        sort: GpuSort,
+        (uniform_buffers, alive_counts): (Vec<Buffer>, ReadbackBuffer<u32>),
+    ) -> Self {
+        let image_count = uniform_buffers.len();
+        let layout = Self::create_descriptor_set_layout(device);
+        let draw_layout = Self::create_draw_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+        let draw_set = {
+            let layouts = [draw_layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let buffer_info = |buffer: &Buffer| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()]
+        };
+        let particles_infos = buffer_info(&particles);
+        let output_infos = buffer_info(&output);
+        let counters_infos = buffer_info(&counters);
+        let keys_infos = buffer_info(&sort.keys());
+        let values_infos = buffer_info(&sort.values());
+        let write = |set, binding, descriptor_type, buffer_infos: &[vk::DescriptorBufferInfo]| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(descriptor_type)
+                .buffer_info(buffer_infos)
+                .build()
+        };
+        let storage = vk::DescriptorType::STORAGE_BUFFER;
+        for (set, uniform_buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let uniforms_infos = buffer_info(uniform_buffer);
+            let descriptor_writes = [
+                write(
+                    *set,
+                    UNIFORMS_BINDING,
+                    vk::DescriptorType::UNIFORM_BUFFER,
+                    &uniforms_infos,
+                ),
+                write(*set, PARTICLES_BINDING, storage, &particles_infos),
+                write(*set, OUTPUT_BINDING, storage, &output_infos),
+                write(*set, COUNTERS_BINDING, storage, &counters_infos),
+                write(*set, SORT_KEYS_BINDING, storage, &keys_infos),
+                write(*set, SORT_VALUES_BINDING, storage, &values_infos),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+        let descriptor_writes = [
+            write(draw_set, DRAW_PARTICLES_BINDING, storage, &particles_infos),
+            write(draw_set, DRAW_INDICES_BINDING, storage, &values_infos),
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Particles {
+            particles,
+            output,
+            counters,
+            uniform_buffers,
+            alive_counts,
+            sort,
+            layout,
+            draw_layout,
+            pool,
+            sets,
+            draw_set,
+            pipelines: None,
+            pipeline_layout: vk::PipelineLayout::null(),
+            draw_pipeline: vk::Pipeline::null(),
+            draw_pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Size in bytes of the particle buffers.
+    pub fn particle_buffer_size() -> vk::DeviceSize {
+        (PARTICLE_SIZE * PARTICLE_CAPACITY) as _
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        };
+        let storage = vk::DescriptorType::STORAGE_BUFFER;
+        let bindings = [
+            binding(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER),
+            binding(PARTICLES_BINDING, storage),
+            binding(OUTPUT_BINDING, storage),
+            binding(COUNTERS_BINDING, storage),
+            binding(SORT_KEYS_BINDING, storage),
+            binding(SORT_VALUES_BINDING, storage),
+        ];
+        create_descriptor_set_layout(device, &bindings)
+    }
+
+    fn create_draw_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .build()
+        };
+        let bindings = [
+            binding(DRAW_PARTICLES_BINDING),
+            binding(DRAW_INDICES_BINDING),
+        ];
+        create_descriptor_set_layout(device, &bindings)
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: image_count * 5 + 2,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count + 1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Particles {
+    /// Get the layout of the descriptor sets of the simulation.
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the layout of the descriptor set of the draw, bound to `PASS_SET`.
+    pub fn draw_layout(&self) -> vk::DescriptorSetLayout {
+        self.draw_layout
+    }
+
+    pub fn sort_mut(&mut self) -> &mut GpuSort {
+        &mut self.sort
+    }
+
+    /// Set the compute pipelines of each `ParticleStage`, in the order of `ParticleStage::ALL`.
+    ///
+    /// All use `pipeline_layout`. The particles take ownership of the
+    /// pipelines and their layout.
+    pub fn set_pipelines(&mut self, pipelines: [vk::Pipeline; 4], layout: vk::PipelineLayout) {
+        self.pipelines = Some(pipelines);
+        self.pipeline_layout = layout;
+    }
+
+    /// Set the pipeline drawing the particles.
+    ///
+    /// The particles take ownership of the pipeline and its layout.
+    pub fn set_draw_pipeline(&mut self, pipeline: vk::Pipeline, layout: vk::PipelineLayout) {
+        self.draw_pipeline = pipeline;
+        self.draw_pipeline_layout = layout;
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index` and get
+    /// the number of particles living the last time its commands were
+    /// executed, or 0 before they were.
+    ///
+    /// The particles are emitted around `emitter` at `emission_rate` per
+    /// second. `time_delta` is the time in seconds since the last frame.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        image_index: usize,
+        (emitter, emission_rate): (Point3<f32>, f32),
+        camera_position: Point3<f32>,
+        (time, time_delta): (f32, f32),
+    ) -> u32 {
+        let uniforms = ParticleUniforms {
+            emitter: [emitter.x, emitter.y, emitter.z, 0.15],
+            camera_position: [camera_position.x, camera_position.y, camera_position.z, 1.0],
+            time_delta,
+            time,
+            emission_rate,
+            capacity: PARTICLE_CAPACITY,
+        };
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+
+        self.alive_counts
+            .read(device, image_index)
+            .map_or(0, |counts| counts[0])
+    }
+
+    /// Record the clear of the counters in `command_buffer`, so no particle is alive.
+    pub fn cmd_reset(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_fill_buffer(
+                command_buffer,
+                self.counters.buffer,
+                0,
+                size_of::<ParticleCounters>() as _,
+                0,
+            )
+        };
+    }
+
+    /// Record the simulation and the sort of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// It must be recorded outside of any render pass, before the render
+    /// pass drawing the particles.
+    pub fn cmd_simulate(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let pipelines = self.pipelines.expect("Particle pipelines not set");
+        let compute = vk::PipelineStageFlags::COMPUTE_SHADER;
+        let transfer = vk::PipelineStageFlags::TRANSFER;
+        let shader_access = vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE;
+
+        unsafe {
+            // The previous frame might still be drawing the particles
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(
+                    Access::new(
+                        vk::PipelineStageFlags::DRAW_INDIRECT
+                            | vk::PipelineStageFlags::VERTEX_SHADER
+                            | compute
+                            | transfer,
+                        vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                    Access::new(
+                        compute | transfer,
+                        shader_access | vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                )],
+            );
+            // The keys past the living particles sort last
+            device.cmd_fill_buffer(
+                command_buffer,
+                self.sort.keys().buffer,
+                0,
+                vk::WHOLE_SIZE,
+                u32::MAX,
+            );
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &self.sets[image_index..=image_index],
+                &[],
+            );
+            for (stage, pipeline) in ParticleStage::ALL.iter().zip(&pipelines) {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, *pipeline);
+                match stage {
+                    ParticleStage::Prepare | ParticleStage::Finalize => {
+                        device.cmd_dispatch(command_buffer, 1, 1, 1)
+                    }
+                    // Sized from the counts of the prepare stage
+                    ParticleStage::Update => device.cmd_dispatch_indirect(
+                        command_buffer,
+                        self.counters.buffer,
+                        offset_of_update_dispatch(),
+                    ),
+                    ParticleStage::Emit => device.cmd_dispatch_indirect(
+                        command_buffer,
+                        self.counters.buffer,
+                        offset_of_emit_dispatch(),
+                    ),
+                }
+                match stage {
+                    ParticleStage::Prepare => cmd_barriers(
+                        device,
+                        command_buffer,
+                        &[Barrier::memory(
+                            Access::new(
+                                compute | transfer,
+                                vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+                            ),
+                            Access::new(
+                                vk::PipelineStageFlags::DRAW_INDIRECT | compute,
+                                vk::AccessFlags::INDIRECT_COMMAND_READ | shader_access,
+                            ),
+                        )],
+                    ),
+                    // The update and the emission only append with atomics
+                    ParticleStage::Update => {}
+                    ParticleStage::Emit => cmd_barriers(
+                        device,
+                        command_buffer,
+                        &[Barrier::memory(
+                            Access::new(compute, vk::AccessFlags::SHADER_WRITE),
+                            Access::new(compute, shader_access),
+                        )],
+                    ),
+                    ParticleStage::Finalize => cmd_barriers(
+                        device,
+                        command_buffer,
+                        &[Barrier::memory(
+                            Access::new(compute, vk::AccessFlags::SHADER_WRITE),
+                            Access::new(
+                                compute | transfer,
+                                shader_access | vk::AccessFlags::TRANSFER_READ,
+                            ),
+                        )],
+                    ),
+                }
+            }
+
+            // The output is the input of the next frame
+            let region = vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size: Self::particle_buffer_size(),
+            };
+            device.cmd_copy_buffer(
+                command_buffer,
+                self.output.buffer,
+                self.particles.buffer,
+                &[region],
+            );
+        }
+        self.alive_counts.cmd_copy(
+            device,
+            command_buffer,
+            image_index,
+            (self.counters.buffer, 0),
+        );
+
+        self.sort
+            .cmd_sort(device, command_buffer, PARTICLE_CAPACITY);
+
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::memory(
+                Access::new(
+                    compute | transfer,
+                    vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                Access::indirect_read().and(Access::new(
+                    vk::PipelineStageFlags::VERTEX_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                )),
+            )],
+        );
+    }
+
+    /// Record the draw of the particles with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`. It must be
+    /// recorded after the opaque geometry since the particles are blended.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder) {
+        encoder.bind_pipeline(self.draw_pipeline);
+        encoder.bind_descriptor_sets(self.draw_pipeline_layout, PASS_SET, &[self.draw_set], &[]);
+        unsafe {
+            encoder.device().cmd_draw_indirect(
+                encoder.command_buffer(),
+                self.counters.buffer,
+                offset_of_draw(),
+                1,
+                size_of::<vk::DrawIndirectCommand>() as _,
+            )
+        };
+    }
+
+    /// Destroy the draw pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_draw_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.draw_pipeline);
+            device.destroy_pipeline_layout(self.draw_pipeline_layout, None);
+        }
+        self.draw_pipeline = vk::Pipeline::null();
+        self.draw_pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_draw_pipeline(device);
+        unsafe {
+            if let Some(pipelines) = self.pipelines.take() {
+                pipelines
+                    .iter()
+                    .for_each(|pipeline| lifetime::destroy_pipeline(device, *pipeline));
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            device.destroy_descriptor_set_layout(self.draw_layout, None);
+        }
+        self.sort.destroy(device);
+        self.particles.destroy(device);
+        self.output.destroy(device);
+        self.counters.destroy(device);
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        self.alive_counts.destroy(device);
+    }
+}
+
+/// Offset of the indirect dispatch of the update in the counters buffer.
+fn offset_of_update_dispatch() -> vk::DeviceSize {
+    (size_of::<u32>() * 4) as _
+}
+
+/// Offset of the indirect dispatch of the emission in the counters buffer.
+fn offset_of_emit_dispatch() -> vk::DeviceSize {
+    offset_of_update_dispatch() + size_of::<[u32; 4]>() as vk::DeviceSize
+}
+
+/// Offset of the indirect draw in the counters buffer.
+fn offset_of_draw() -> vk::DeviceSize {
+    offset_of_emit_dispatch() + size_of::<[u32; 4]>() as vk::DeviceSize
+}
+
+fn create_descriptor_set_layout(
+    device: &Device,
+    bindings: &[vk::DescriptorSetLayoutBinding],
+) -> vk::DescriptorSetLayout {
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(bindings)
+        .build();
+
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    }
+}
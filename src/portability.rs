@@ -0,0 +1,61 @@
+use crate::context::*;
+use ash::{version::EntryV1_0, vk, Entry, Instance};
+use std::ffi::CStr;
+
+/// `VK_KHR_portability_enumeration`, which is not known by this version of ash.
+///
+/// Without it, the loader does not list devices only implementing a subset of
+/// Vulkan, like MoltenVK.
+const PORTABILITY_ENUMERATION: &[u8] = b"VK_KHR_portability_enumeration\0";
+
+/// `VK_KHR_portability_subset`, which must be enabled when a device exposes it.
+const PORTABILITY_SUBSET: &[u8] = b"VK_KHR_portability_subset\0";
+
+/// Raw value of `VK_INSTANCE_CREATE_ENUMERATE_PORTABILITY_BIT_KHR`.
+const ENUMERATE_PORTABILITY: vk::Flags = 0x1;
+
+fn portability_enumeration_name() -> &'static CStr {
+    CStr::from_bytes_with_nul(PORTABILITY_ENUMERATION).unwrap()
+}
+
+fn portability_subset_name() -> &'static CStr {
+    CStr::from_bytes_with_nul(PORTABILITY_SUBSET).unwrap()
+}
+
+/// Check if the loader can enumerate portability devices.
+pub fn is_enumeration_supported(entry: &Entry) -> bool {
+    let extension_props = entry.enumerate_instance_extension_properties().unwrap();
+    extension_props.iter().any(|ext| {
+        let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+        name == portability_enumeration_name()
+    })
+}
+
+/// Get the instance extensions required to enumerate portability devices.
+///
+/// `VK_KHR_get_physical_device_properties2` is required by the portability
+/// subset and is only part of the core api from Vulkan 1.1.
+pub fn required_instance_extensions(api_version: u32) -> Vec<&'static CStr> {
+    let mut extensions = vec![portability_enumeration_name()];
+    if api_version < ash::vk_make_version!(1, 1, 0) {
+        extensions.push(vk::KhrGetPhysicalDeviceProperties2Fn::name());
+    }
+    extensions
+}
+
+/// Get the flags of the instance enumerating portability devices.
+pub fn instance_create_flags() -> vk::InstanceCreateFlags {
+    vk::InstanceCreateFlags::from_raw(ENUMERATE_PORTABILITY)
+}
+
+/// Check if `device` only implements a subset of Vulkan.
+///
+/// If it does, the extensions returned by `required_device_extensions` must be enabled.
+pub fn is_subset_device(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    is_device_extension_supported(instance, device, portability_subset_name())
+}
+
+/// Get the device extensions required on portability devices.
+pub fn required_device_extensions() -> [&'static CStr; 1] {
+    [portability_subset_name()]
+}
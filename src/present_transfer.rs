@@ -0,0 +1,169 @@
+use crate::{
+    barrier::{self, Access, Barrier},
+    gpu_device::Device,
+};
+use ash::{prelude::VkResult, version::DeviceV1_0, vk};
+
+/// Transfer of the ownership of the swapchain images from the graphics queue
+/// family rendering them to the present queue family, when they differ.
+///
+/// The images are exclusive to one queue family at a time, so each frame
+/// releases its image at the end of its command buffer and a command buffer
+/// of the present queue acquires it before it is presented. The content of
+/// the images is discarded when they are rendered again, so they are not
+/// transferred back.
+pub struct PresentTransfer {
+    queue_families: (u32, u32),
+    images: Vec<vk::Image>,
+    command_pool: vk::CommandPool,
+    acquires: Vec<PresentAcquire>,
+}
+
+/// Command buffer acquiring a swapchain image on the present queue, and the
+/// semaphore it signals once the image can be presented.
+#[derive(Clone, Copy, Debug)]
+pub struct PresentAcquire {
+    command_buffer: vk::CommandBuffer,
+    semaphore: vk::Semaphore,
+}
+
+impl PresentTransfer {
+    /// Record the acquisitions of `images` by `present_family`, from `graphics_family`.
+    pub fn new(
+        device: &Device,
+        (graphics_family, present_family): (u32, u32),
+        images: &[vk::Image],
+    ) -> Self {
+        let queue_families = (graphics_family, present_family);
+        let command_pool = {
+            let pool_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(present_family)
+                .build();
+            unsafe { device.create_command_pool(&pool_info, None).unwrap() }
+        };
+        let command_buffers = {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(images.len() as _)
+                .build();
+            unsafe { device.allocate_command_buffers(&allocate_info).unwrap() }
+        };
+
+        // The frames of an image only reuse its command buffer once presented
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
+            .build();
+        let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
+        let acquires = images
+            .iter()
+            .zip(command_buffers)
+            .map(|(image, command_buffer)| unsafe {
+                device
+                    .begin_command_buffer(command_buffer, &begin_info)
+                    .unwrap();
+                barrier::cmd_barriers(
+                    device,
+                    command_buffer,
+                    &[transfer_barrier(*image).acquire(queue_families)],
+                );
+                device.end_command_buffer(command_buffer).unwrap();
+                PresentAcquire {
+                    command_buffer,
+                    semaphore: device.create_semaphore(&semaphore_info, None).unwrap(),
+                }
+            })
+            .collect();
+
+        PresentTransfer {
+            queue_families,
+            images: images.to_vec(),
+            command_pool,
+            acquires,
+        }
+    }
+
+    /// Release the swapchain image `index` to the present queue family.
+    ///
+    /// It must be recorded on the graphics queue after the last pass writing
+    /// the image, which leaves it in the present layout.
+    pub fn cmd_release(&self, device: &Device, command_buffer: vk::CommandBuffer, index: usize) {
+        barrier::cmd_barriers(
+            device,
+            command_buffer,
+            &[transfer_barrier(self.images[index]).release(self.queue_families)],
+        );
+    }
+
+    /// Get the acquisition of the swapchain image `index`.
+    pub fn acquire(&self, index: usize) -> PresentAcquire {
+        self.acquires[index]
+    }
+
+    /// Destroy the command buffers and the semaphores.
+    ///
+    /// The frames using them must be done.
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            self.acquires
+                .drain(..)
+                .for_each(|acquire| device.destroy_semaphore(acquire.semaphore, None));
+            device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
+
+impl PresentAcquire {
+    /// Submit the acquisition to `present_queue` once `render_finished` is signaled.
+    ///
+    /// Return the semaphore the presentation must wait for.
+    ///
+    /// # Safety
+    ///
+    /// The command buffer releasing the image must have been submitted to
+    /// the graphics queue, signaling `render_finished`.
+    pub unsafe fn submit(
+        self,
+        device: &Device,
+        present_queue: vk::Queue,
+        render_finished: vk::Semaphore,
+    ) -> VkResult<vk::Semaphore> {
+        let wait_semaphores = [render_finished];
+        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+        let command_buffers = [self.command_buffer];
+        let signal_semaphores = [self.semaphore];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores)
+            .build();
+        device.queue_submit(present_queue, &[submit_info], vk::Fence::null())?;
+        Ok(self.semaphore)
+    }
+}
+
+/// Barrier to a swapchain image written by color attachments and left in the
+/// present layout, which only the presentation reads.
+fn transfer_barrier(image: vk::Image) -> Barrier {
+    let range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    Barrier::image(
+        image,
+        range,
+        (
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        ),
+        Access::color_attachment(),
+        Access::new(
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::AccessFlags::empty(),
+        ),
+    )
+}
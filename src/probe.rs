@@ -0,0 +1,643 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    frame_layout::OBJECT_SET,
+    gpu_device::Device,
+    lifetime, math,
+    std140::std140_struct,
+    texture::{Texture, TextureDesc},
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Deg, Matrix4, Point3};
+use std::mem::size_of;
+
+/// Width and height in pixels of the faces of the probes.
+pub const PROBE_SIZE: u32 = 64;
+
+/// Number of mip levels of the faces, down to a single texel.
+pub const PROBE_MIP_LEVELS: u32 = PROBE_SIZE.trailing_zeros() + 1;
+
+/// Format of the faces of the probes.
+pub const PROBE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Number of probes blended for an object.
+///
+/// Must match `ProbeUniforms` in `probe.glsl`.
+pub const BLENDED_PROBE_COUNT: usize = 2;
+
+const FACE_COUNT: u32 = 6;
+
+/// Forward and up directions of the cameras capturing the faces, in the order
+/// of the layers of a probe.
+///
+/// Must match `FACE_FORWARDS` and `FACE_UPS` in `probe.glsl`.
+const FACE_DIRECTIONS: [([f32; 3], [f32; 3]); FACE_COUNT as usize] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+];
+
+/// Near and far planes of the cameras capturing the faces.
+const CAPTURE_DEPTH_RANGE: (f32, f32) = (0.05, 20.0);
+
+/// Color of the background of the captures, where nothing is drawn.
+const CAPTURE_CLEAR_COLOR: [f32; 4] = [0.45, 0.55, 0.65, 1.0];
+
+const UNIFORMS_BINDING: u32 = 0;
+const FACES_BINDING: u32 = 1;
+
+std140_struct! {
+    /// Probes blended for the object as laid out in the uniform buffer.
+    #[derive(Clone, Copy, Debug, Default)]
+    #[allow(dead_code)]
+    pub struct ProbeUniforms {
+        /// Index of the blended probes.
+        pub indices: [u32; 2],
+        /// Weight of each blended probe, all zero when the object is out of
+        /// reach of every probe.
+        pub weights: [f32; 2],
+    }
+}
+
+/// Reflection probe placed in the scene.
+#[derive(Clone, Copy, Debug)]
+pub struct ReflectionProbe {
+    pub position: Point3<f32>,
+    /// Distance from the probe at which its influence fades out.
+    pub radius: f32,
+}
+
+impl ReflectionProbe {
+    /// Get the view and projection matrices capturing the face `face` of the probe.
+    pub fn face_camera(&self, face: usize) -> (Matrix4<f32>, Matrix4<f32>) {
+        let (forward, up) = FACE_DIRECTIONS[face];
+        let view = Matrix4::look_at_dir(self.position, forward.into(), up.into());
+        let (near, far) = CAPTURE_DEPTH_RANGE;
+        let proj = math::perspective(Deg(90.0), 1.0, near, far);
+        (view, proj)
+    }
+}
+
+/// Pick the probes to blend at `position` and their weights.
+///
+/// The closest `BLENDED_PROBE_COUNT` probes within reach of the position are
+/// blended, each weighted by how close the position is to it relative to its
+/// radius. The weights are normalized.
+pub fn blend_probes(probes: &[ReflectionProbe], position: Point3<f32>) -> ProbeUniforms {
+    let mut candidates = probes
+        .iter()
+        .enumerate()
+        .map(|(index, probe)| {
+            (
+                index,
+                1.0 - probe.position.distance(position) / probe.radius,
+            )
+        })
+        .filter(|(_, weight)| *weight > 0.0)
+        .collect::<Vec<_>>();
+    candidates.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+    candidates.truncate(BLENDED_PROBE_COUNT);
+
+    let total_weight = candidates.iter().map(|(_, weight)| weight).sum::<f32>();
+    let mut uniforms = ProbeUniforms::default();
+    for (slot, (index, weight)) in candidates.into_iter().enumerate() {
+        uniforms.indices[slot] = index as _;
+        uniforms.weights[slot] = weight / total_weight;
+    }
+    uniforms
+}
+
+/// Local reflection probes captured at load time and blended per object.
+///
+/// Each probe is a cubemap stored as six layers of a 2D texture array, in the
+/// order of `FACE_DIRECTIONS`, so all the probes are sampled through a single
+/// descriptor. The shader picks the face and the texel from the reflected
+/// direction itself.
+///
+/// The faces are captured once with `cmd_capture`, then prefiltered by
+/// downsampling them along the mip chain so rough materials sample blurrier
+/// reflections from the smaller levels. The targets of the capture are only
+/// needed until then and are destroyed with `destroy_capture_targets`.
+///
+/// The probes blended for the object and their weights are written in a
+/// uniform buffer per swapchain image, bound with the faces at `OBJECT_SET`.
+pub struct ReflectionProbes {
+    probes: Vec<ReflectionProbe>,
+    faces: Texture,
+    uniform_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    depth: Option<Texture>,
+    render_pass: vk::RenderPass,
+    face_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl ReflectionProbes {
+    /// Create the targets of the capture and the descriptor sets of the probes.
+    ///
+    /// `faces` must be created from `faces_desc(probes.len())` and `depth` from
+    /// `depth_desc`. Uniform buffers must be host visible and coherent, there
+    /// is one per swapchain image.
+    pub fn new(
+        device: &Device,
+        probes: Vec<ReflectionProbe>,
+        mut faces: Texture,
+        (depth, depth_format): (Texture, vk::Format),
+        uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        faces.sampler = Some(Self::create_sampler(device));
+        let render_pass = Self::create_render_pass(device, depth_format);
+        let face_views = (0..probes.len() as u32 * FACE_COUNT)
+            .map(|layer| Self::create_face_view(device, faces.image, layer))
+            .collect::<Vec<_>>();
+        let framebuffers = face_views
+            .iter()
+            .map(|view| {
+                let attachments = [*view, depth.view];
+                let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass)
+                    .attachments(&attachments)
+                    .width(PROBE_SIZE)
+                    .height(PROBE_SIZE)
+                    .layers(1)
+                    .build();
+                unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+            })
+            .collect();
+
+        let image_count = uniform_buffers.len();
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let faces_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(faces.view)
+            .sampler(faces.sampler.unwrap())
+            .build()];
+        for (set, buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let uniforms_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size_of::<ProbeUniforms>() as _)
+                .build()];
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(UNIFORMS_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&uniforms_infos)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(FACES_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&faces_infos)
+                    .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        ReflectionProbes {
+            probes,
+            faces,
+            uniform_buffers,
+            layout,
+            pool,
+            sets,
+            depth: Some(depth),
+            render_pass,
+            face_views,
+            framebuffers,
+        }
+    }
+
+    /// Describe the texture array holding the faces of `probe_count` probes.
+    pub fn faces_desc(probe_count: usize) -> TextureDesc {
+        TextureDesc {
+            mip_levels: PROBE_MIP_LEVELS,
+            ..TextureDesc::new_2d_array(
+                Self::extent(),
+                probe_count as u32 * FACE_COUNT,
+                PROBE_FORMAT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::SAMPLED,
+            )
+        }
+    }
+
+    /// Describe the depth texture shared by the captures of the faces.
+    pub fn depth_desc(format: vk::Format) -> TextureDesc {
+        TextureDesc::new_2d(
+            Self::extent(),
+            format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+    }
+
+    /// Get the size of a face.
+    pub fn extent() -> vk::Extent2D {
+        vk::Extent2D {
+            width: PROBE_SIZE,
+            height: PROBE_SIZE,
+        }
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .min_lod(0.0)
+            .max_lod(PROBE_MIP_LEVELS as _)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    /// Create the render pass capturing a face.
+    ///
+    /// The face is left in the `TRANSFER_SRC_OPTIMAL` layout to build the mip
+    /// chain from it.
+    fn create_render_pass(device: &Device, depth_format: vk::Format) -> vk::RenderPass {
+        let attachment_descs = [
+            vk::AttachmentDescription::builder()
+                .format(PROBE_FORMAT)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .build(),
+            vk::AttachmentDescription::builder()
+                .format(depth_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .build(),
+        ];
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+        let subpass_descs = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build()];
+
+        // The capture of the previous face might still be writing the depth.
+        let begin_dep = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build();
+        // The mip chain is then downsampled from the face.
+        let end_dep = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build();
+        let subpass_deps = [begin_dep, end_dep];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descs)
+            .subpasses(&subpass_descs)
+            .dependencies(&subpass_deps)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+
+    /// Create a view of the first mip level of `layer` to render into it.
+    fn create_face_view(device: &Device, image: vk::Image, layer: u32) -> vk::ImageView {
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(PROBE_FORMAT)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: layer,
+                layer_count: 1,
+            })
+            .build();
+
+        unsafe { device.create_image_view(&view_info, None).unwrap() }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        };
+        let bindings = [
+            binding(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER),
+            binding(FACES_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    /// Create a pool for `image_count` sets.
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl ReflectionProbes {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the render pass capturing the faces.
+    ///
+    /// It must not be used after `destroy_capture_targets`.
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn probes(&self) -> &[ReflectionProbe] {
+        &self.probes
+    }
+
+    /// Record the capture of all the faces in `command_buffer` and build their mip chains.
+    ///
+    /// `draw_face` records the draws of the scene seen from a face in the
+    /// command buffer, given the index of the face across all the probes,
+    /// `probe * 6 + face`. After the capture, the faces are in the
+    /// `SHADER_READ_ONLY_OPTIMAL` layout.
+    pub fn cmd_capture<F: FnMut(vk::CommandBuffer, usize)>(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        mut draw_face: F,
+    ) {
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: CAPTURE_CLEAR_COLOR,
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        for (index, framebuffer) in self.framebuffers.iter().enumerate() {
+            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(self.render_pass)
+                .framebuffer(*framebuffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: Self::extent(),
+                })
+                .clear_values(&clear_values)
+                .build();
+            unsafe {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                )
+            };
+            draw_face(command_buffer, index);
+            unsafe { device.cmd_end_render_pass(command_buffer) };
+        }
+
+        self.cmd_prefilter(device, command_buffer);
+    }
+
+    /// Record the downsampling of each mip level of the faces from the previous one.
+    fn cmd_prefilter(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let layer_count = self.probes.len() as u32 * FACE_COUNT;
+        let levels = |base_mip_level, level_count| vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level,
+            level_count,
+            base_array_layer: 0,
+            layer_count,
+        };
+        let barrier = |range, (old_layout, src_access_mask), (new_layout, dst_access_mask)| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.faces.image)
+                .subresource_range(range)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .build()
+        };
+        let layers = |mip_level| vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level,
+            base_array_layer: 0,
+            layer_count,
+        };
+        let corner = |level: u32| vk::Offset3D {
+            x: (PROBE_SIZE >> level) as _,
+            y: (PROBE_SIZE >> level) as _,
+            z: 1,
+        };
+
+        for level in 1..PROBE_MIP_LEVELS {
+            let barriers = [barrier(
+                levels(level, 1),
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty()),
+                (
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            )];
+            let blit = vk::ImageBlit::builder()
+                .src_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, corner(level - 1)])
+                .src_subresource(layers(level - 1))
+                .dst_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, corner(level)])
+                .dst_subresource(layers(level))
+                .build();
+            // The level is the source of the next one once written.
+            let read_barriers = [barrier(
+                levels(level, 1),
+                (
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_READ,
+                ),
+            )];
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &barriers,
+                );
+                device.cmd_blit_image(
+                    command_buffer,
+                    self.faces.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.faces.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit],
+                    vk::Filter::LINEAR,
+                );
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &read_barriers,
+                );
+            }
+        }
+
+        let barriers = [barrier(
+            levels(0, PROBE_MIP_LEVELS),
+            (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::SHADER_READ,
+            ),
+        )];
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            )
+        };
+    }
+
+    /// Destroy the render pass, the framebuffers and the depth of the capture.
+    ///
+    /// It must be called once the capture is complete.
+    pub fn destroy_capture_targets(&mut self, device: &Device) {
+        unsafe {
+            self.framebuffers
+                .drain(..)
+                .for_each(|framebuffer| device.destroy_framebuffer(framebuffer, None));
+            self.face_views
+                .drain(..)
+                .for_each(|view| device.destroy_image_view(view, None));
+            device.destroy_render_pass(self.render_pass, None);
+        }
+        self.render_pass = vk::RenderPass::null();
+        if let Some(mut depth) = self.depth.take() {
+            depth.destroy(device);
+        }
+    }
+
+    /// Write the probes blended for the object at `position` for the swapchain image `image_index`.
+    pub fn update(&self, device: &Device, image_index: usize, position: Point3<f32>) {
+        self.uniform_buffers[image_index].write(device, 0, &[blend_probes(&self.probes, position)]);
+    }
+
+    /// Bind the probes of the swapchain image `image_index` at `OBJECT_SET` with `encoder`.
+    pub fn cmd_bind(
+        &self,
+        encoder: &mut CommandEncoder,
+        pipeline_layout: vk::PipelineLayout,
+        image_index: usize,
+    ) {
+        encoder.bind_descriptor_sets(pipeline_layout, OBJECT_SET, &[self.sets[image_index]], &[]);
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_capture_targets(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        self.faces.destroy(device);
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Weight of the last frame in the average time of the scopes.
+const AVERAGE_WEIGHT: f32 = 0.05;
+
+/// Time spent in the scopes of the current frame and the average of the
+/// previous ones.
+///
+/// It is global so scopes can be timed anywhere without passing a profiler
+/// around, including from the render thread.
+static PROFILER: Mutex<Profiler> = Mutex::new(Profiler {
+    current: Vec::new(),
+    averages: Vec::new(),
+});
+
+struct Profiler {
+    /// Time and number of calls of each scope in the current frame, in the
+    /// order they were first entered.
+    current: Vec<(&'static str, Duration, u32)>,
+    averages: Vec<ScopeTiming>,
+}
+
+/// Average time spent per frame in a scope.
+#[derive(Clone, Copy, Debug)]
+pub struct ScopeTiming {
+    pub name: &'static str,
+    /// Average time in milliseconds.
+    pub milliseconds: f32,
+    /// Number of times the scope was entered in the last frame.
+    pub calls: u32,
+}
+
+/// Time the rest of the enclosing block under `name`.
+///
+/// The time of a scope entered several times in a frame is summed. Nested
+/// scopes are timed independently, so the time of the inner one is also
+/// part of the outer one.
+macro_rules! profile_scope {
+    ($name:expr) => {
+        let _profile_scope = $crate::profiler::ScopeTimer::new($name);
+    };
+}
+
+pub(crate) use profile_scope;
+
+/// Guard adding the time since its creation to its scope when dropped.
+pub struct ScopeTimer {
+    name: &'static str,
+    start: Instant,
+}
+
+impl ScopeTimer {
+    pub fn new(name: &'static str) -> Self {
+        ScopeTimer {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ScopeTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let mut profiler = PROFILER.lock().unwrap();
+        match profiler
+            .current
+            .iter_mut()
+            .find(|(name, _, _)| *name == self.name)
+        {
+            Some((_, time, calls)) => {
+                *time += elapsed;
+                *calls += 1;
+            }
+            None => profiler.current.push((self.name, elapsed, 1)),
+        }
+    }
+}
+
+/// Add the time of the scopes of the frame that just ended to their average.
+///
+/// A scope not entered during the frame counts as zero.
+pub fn end_frame() {
+    let mut profiler = PROFILER.lock().unwrap();
+    let Profiler { current, averages } = &mut *profiler;
+    for timing in averages.iter_mut() {
+        timing.milliseconds *= 1.0 - AVERAGE_WEIGHT;
+        timing.calls = 0;
+    }
+    for (name, time, calls) in current.drain(..) {
+        let milliseconds = time.as_secs_f32() * 1000.0;
+        match averages.iter_mut().find(|timing| timing.name == name) {
+            Some(timing) => {
+                timing.milliseconds += milliseconds * AVERAGE_WEIGHT;
+                timing.calls = calls;
+            }
+            None => averages.push(ScopeTiming {
+                name,
+                milliseconds,
+                calls,
+            }),
+        }
+    }
+}
+
+/// Get the average time of the scopes, in the order they were first entered.
+pub fn report() -> Report {
+    Report(PROFILER.lock().unwrap().averages.clone())
+}
+
+/// Average times of the scopes, displayed one scope per line.
+#[derive(Clone, Debug)]
+pub struct Report(pub Vec<ScopeTiming>);
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, timing) in self.0.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {:.2} ms", timing.name, timing.milliseconds)?;
+            if timing.calls > 1 {
+                write!(f, " ({} calls)", timing.calls)?;
+            }
+        }
+        Ok(())
+    }
+}
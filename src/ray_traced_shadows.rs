@@ -0,0 +1,424 @@
+use crate::{
+    buffer::Buffer,
+    gpu_device::Device,
+    lifetime,
+    ray_tracing::*,
+    std140::std140_struct,
+    texture::Texture,
+    tracked_image::{ImageState, TrackedImage},
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+use std::mem::size_of;
+
+const UNIFORMS_BINDING: u32 = 0;
+const TOP_LEVEL_BINDING: u32 = 1;
+const DEPTH_BINDING: u32 = 2;
+const HDR_COLOR_BINDING: u32 = 3;
+
+/// Parameters of the ray-traced shadows.
+#[derive(Clone, Copy, Debug)]
+pub struct RayTracedShadowParameters {
+    /// Fraction of the color removed where the sun is occluded.
+    pub darkness: f32,
+    /// Distance in world units the rays start from the surface towards the
+    /// sun, so they do not hit the triangles they start from.
+    pub bias: f32,
+}
+
+impl Default for RayTracedShadowParameters {
+    fn default() -> Self {
+        RayTracedShadowParameters {
+            darkness: 0.6,
+            bias: 0.02,
+        }
+    }
+}
+
+std140_struct! {
+    /// Uniforms of the ray-traced shadows raygen shader as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct RayTracedShadowUniforms {
+        /// Transform from clip space to the space of the model.
+        pub inverse_model_view_proj: Matrix4<f32>,
+        /// Direction pointing towards the sun in the space of the model.
+        pub sun_direction: [f32; 4],
+        pub darkness: f32,
+        /// Bias in the space of the model.
+        pub bias: f32,
+    }
+}
+
+/// Shadows of the model cast by the sun, traced from the depth buffer.
+///
+/// After the main render pass, the raygen shader reconstructs the position
+/// of each pixel from the depth buffer and traces a ray towards the sun
+/// against the `AccelerationStructures` of the model. Where it hits, the
+/// HDR image is darkened in place, so the post-processing passes see the
+/// shadows like the raster ones.
+///
+/// The top level structure has the model with an identity transform, the
+/// rays are moved to its space instead so it is never built again.
+pub struct RayTracedShadows {
+    ray_tracing: RayTracing,
+    parameters: RayTracedShadowParameters,
+    extent: vk::Extent2D,
+    hdr_image: vk::Image,
+    depth: (vk::Image, vk::ImageAspectFlags),
+    sampler: vk::Sampler,
+    uniform_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    shader_binding_table: Option<ShaderBindingTable>,
+}
+
+impl RayTracedShadows {
+    /// Create the descriptor sets of the shadows tracing rays against `top_level`.
+    ///
+    /// Uniform buffers must be host visible and coherent, one per swapchain image.
+    ///
+    /// The images must be set using `set_images` and the pipeline using
+    /// `set_pipeline` before recording the pass.
+    pub fn new(
+        device: &Device,
+        ray_tracing: &RayTracing,
+        parameters: RayTracedShadowParameters,
+        top_level: AccelerationStructureHandle,
+        uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        let image_count = uniform_buffers.len();
+        let sampler = Self::create_sampler(device);
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        for (set, buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let buffer_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size_of::<RayTracedShadowUniforms>() as _)
+                .build()];
+            let descriptor_writes = [vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(UNIFORMS_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_infos)
+                .build()];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+        write_acceleration_structure(device, &sets, TOP_LEVEL_BINDING, top_level);
+
+        RayTracedShadows {
+            ray_tracing: *ray_tracing,
+            parameters,
+            extent: vk::Extent2D::default(),
+            hdr_image: vk::Image::null(),
+            depth: (vk::Image::null(), vk::ImageAspectFlags::DEPTH),
+            sampler,
+            uniform_buffers,
+            layout,
+            pool,
+            sets,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            shader_binding_table: None,
+        }
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(vk::ShaderStageFlags::RAYGEN_NV)
+                .build()
+        };
+        let bindings = [
+            binding(UNIFORMS_BINDING, vk::DescriptorType::UNIFORM_BUFFER),
+            binding(TOP_LEVEL_BINDING, acceleration_structure_descriptor_type()),
+            binding(DEPTH_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            binding(HDR_COLOR_BINDING, vk::DescriptorType::STORAGE_IMAGE),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: acceleration_structure_descriptor_type(),
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: image_count,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl RayTracedShadows {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Set the HDR image the shadows are applied to and the depth buffer
+    /// they are traced from, both of `extent`.
+    ///
+    /// `hdr` must have the `STORAGE` usage and be in `GENERAL` after the main
+    /// render pass. `depth` is the depth buffer of the main render pass, of
+    /// aspect `depth_aspect`, with the `SAMPLED` usage.
+    pub fn set_images(
+        &mut self,
+        device: &Device,
+        hdr: &Texture,
+        (depth, depth_aspect): (&Texture, vk::ImageAspectFlags),
+        extent: vk::Extent2D,
+    ) {
+        self.extent = extent;
+        self.hdr_image = hdr.image;
+        self.depth = (depth.image, depth_aspect);
+
+        let depth_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(depth.view)
+            .sampler(self.sampler)
+            .build()];
+        let hdr_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(hdr.view)
+            .build()];
+        let descriptor_writes = self
+            .sets
+            .iter()
+            .flat_map(|set| {
+                vec![
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*set)
+                        .dst_binding(DEPTH_BINDING)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&depth_infos)
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*set)
+                        .dst_binding(HDR_COLOR_BINDING)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&hdr_infos)
+                        .build(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    /// Trace the rays against `top_level` instead, after the model changed.
+    ///
+    /// The command buffers must be recorded again.
+    pub fn set_top_level(&self, device: &Device, top_level: AccelerationStructureHandle) {
+        write_acceleration_structure(device, &self.sets, TOP_LEVEL_BINDING, top_level);
+    }
+
+    /// Set the ray tracing pipeline and its shader binding table.
+    pub fn set_pipeline(
+        &mut self,
+        pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+        shader_binding_table: ShaderBindingTable,
+    ) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+        self.shader_binding_table = Some(shader_binding_table);
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index`.
+    ///
+    /// `model` is the transform of the model the structures were built from,
+    /// and `sun_direction` points towards the sun in world space.
+    pub fn update(
+        &self,
+        device: &Device,
+        image_index: usize,
+        inverse_view_proj: Matrix4<f32>,
+        model: Matrix4<f32>,
+        sun_direction: Vector3<f32>,
+    ) {
+        let world_to_model = model.invert().unwrap_or_else(Matrix4::identity);
+        let sun_direction = (world_to_model * sun_direction.extend(0.0)).truncate();
+        // The model is scaled uniformly
+        let scale = sun_direction.magnitude();
+        let uniforms = RayTracedShadowUniforms {
+            inverse_model_view_proj: world_to_model * inverse_view_proj,
+            sun_direction: (sun_direction / scale).extend(0.0).into(),
+            darkness: self.parameters.darkness,
+            bias: self.parameters.bias * scale,
+        };
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+    }
+
+    /// Record the shadows of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// It must be recorded right after the main render pass, whose depth
+    /// buffer is then in `DEPTH_STENCIL_ATTACHMENT_OPTIMAL` and the HDR image
+    /// in `GENERAL`. They are left in the same layouts with the same last
+    /// accesses, so the next passes do not see the difference.
+    pub fn cmd_trace(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let range = |aspect_mask| vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let depth_state = ImageState {
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            stage: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        };
+        let hdr_state = ImageState {
+            layout: vk::ImageLayout::GENERAL,
+            stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        };
+        let (depth_image, depth_aspect) = self.depth;
+        let mut depth = TrackedImage::new(depth_image, range(depth_aspect), depth_state);
+        let mut hdr = TrackedImage::new(
+            self.hdr_image,
+            range(vk::ImageAspectFlags::COLOR),
+            hdr_state,
+        );
+        depth.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+            vk::AccessFlags::SHADER_READ,
+        );
+        hdr.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_NV,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_NV,
+                self.pipeline_layout,
+                0,
+                &[self.sets[image_index]],
+                &[],
+            );
+        }
+        self.ray_tracing.cmd_trace_rays(
+            command_buffer,
+            self.shader_binding_table.as_ref().unwrap(),
+            self.extent,
+        );
+
+        depth.transition_to(
+            device,
+            command_buffer,
+            depth_state.layout,
+            depth_state.stage,
+            depth_state.access,
+        );
+        hdr.transition_to(
+            device,
+            command_buffer,
+            hdr_state.layout,
+            hdr_state.stage,
+            hdr_state.access,
+        );
+    }
+
+    /// Destroy the pipeline, its layout and its shader binding table.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+        if let Some(mut shader_binding_table) = self.shader_binding_table.take() {
+            shader_binding_table.destroy(device);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
@@ -0,0 +1,1453 @@
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    context::*,
+    gpu_device::Device,
+    lifetime::{self, ObjectKind},
+    memory::AllocationError,
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Instance,
+};
+use cgmath::Matrix4;
+use std::{
+    ffi::{c_void, CStr},
+    mem::{self, size_of},
+    ptr,
+};
+
+// Extensions of the KHR ray tracing, which are not known by this version of ash.
+const ACCELERATION_STRUCTURE: &[u8] = b"VK_KHR_acceleration_structure\0";
+const RAY_TRACING_PIPELINE: &[u8] = b"VK_KHR_ray_tracing_pipeline\0";
+const DEFERRED_HOST_OPERATIONS: &[u8] = b"VK_KHR_deferred_host_operations\0";
+const BUFFER_DEVICE_ADDRESS: &[u8] = b"VK_KHR_buffer_device_address\0";
+const SPIRV_1_4: &[u8] = b"VK_KHR_spirv_1_4\0";
+const RAY_QUERY: &[u8] = b"VK_KHR_ray_query\0";
+
+// Raw values of the structure types of the KHR ray tracing.
+const STRUCTURE_TYPE_ACCELERATION_STRUCTURE_BUILD_GEOMETRY_INFO: i32 = 1_000_150_000;
+const STRUCTURE_TYPE_ACCELERATION_STRUCTURE_DEVICE_ADDRESS_INFO: i32 = 1_000_150_002;
+const STRUCTURE_TYPE_ACCELERATION_STRUCTURE_GEOMETRY_INSTANCES_DATA: i32 = 1_000_150_004;
+const STRUCTURE_TYPE_ACCELERATION_STRUCTURE_GEOMETRY_TRIANGLES_DATA: i32 = 1_000_150_005;
+const STRUCTURE_TYPE_ACCELERATION_STRUCTURE_GEOMETRY: i32 = 1_000_150_006;
+const STRUCTURE_TYPE_WRITE_DESCRIPTOR_SET_ACCELERATION_STRUCTURE: i32 = 1_000_150_007;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_ACCELERATION_STRUCTURE_FEATURES: i32 = 1_000_150_013;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_ACCELERATION_STRUCTURE_PROPERTIES: i32 = 1_000_150_014;
+const STRUCTURE_TYPE_RAY_TRACING_PIPELINE_CREATE_INFO: i32 = 1_000_150_015;
+const STRUCTURE_TYPE_RAY_TRACING_SHADER_GROUP_CREATE_INFO: i32 = 1_000_150_016;
+const STRUCTURE_TYPE_ACCELERATION_STRUCTURE_CREATE_INFO: i32 = 1_000_150_017;
+const STRUCTURE_TYPE_ACCELERATION_STRUCTURE_BUILD_SIZES_INFO: i32 = 1_000_150_020;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_BUFFER_DEVICE_ADDRESS_FEATURES: i32 = 1_000_257_000;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_RAY_TRACING_PIPELINE_FEATURES: i32 = 1_000_347_000;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_RAY_TRACING_PIPELINE_PROPERTIES: i32 = 1_000_347_001;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_RAY_QUERY_FEATURES: i32 = 1_000_348_013;
+
+// Raw values of the enums and flags not shared with `VK_NV_ray_tracing`. The
+// others have the same values, so the NV types of ash are used for them.
+const GEOMETRY_TYPE_INSTANCES: i32 = 1_000_150_000;
+const BUILD_MODE_BUILD: i32 = 0;
+const BUILD_TYPE_DEVICE: i32 = 1;
+const DESCRIPTOR_TYPE_ACCELERATION_STRUCTURE: i32 = 1_000_150_000;
+const BUFFER_USAGE_BUILD_INPUT_READ_ONLY: vk::Flags = 0x8_0000;
+const BUFFER_USAGE_ACCELERATION_STRUCTURE_STORAGE: vk::Flags = 0x10_0000;
+const MEMORY_ALLOCATE_DEVICE_ADDRESS: vk::Flags = 0x2;
+
+fn name(extension: &'static [u8]) -> &'static CStr {
+    CStr::from_bytes_with_nul(extension).unwrap()
+}
+
+/// Get the device extensions required by the acceleration structures and the
+/// ray tracing pipelines, with the ones they depend on.
+pub fn required_device_extensions() -> [&'static CStr; 7] {
+    [
+        name(ACCELERATION_STRUCTURE),
+        name(RAY_TRACING_PIPELINE),
+        name(DEFERRED_HOST_OPERATIONS),
+        name(BUFFER_DEVICE_ADDRESS),
+        name(SPIRV_1_4),
+        vk::KhrShaderFloatControlsFn::name(),
+        vk::ExtDescriptorIndexingFn::name(),
+    ]
+}
+
+/// Check if `device` supports the acceleration structures and the ray tracing pipelines.
+///
+/// Vulkan 1.1 must be supported by the instance and the device since the
+/// features are queried using `vkGetPhysicalDeviceFeatures2`.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let extensions_supported = required_device_extensions()
+        .iter()
+        .all(|ext| is_device_extension_supported(instance, device, ext));
+    if !extensions_supported {
+        return false;
+    }
+
+    let mut ray_tracing_features = RayTracingFeatures::default();
+    ray_tracing_features.ray_tracing_pipeline.p_next =
+        &mut ray_tracing_features.buffer_device_address as *mut _ as *mut c_void;
+    ray_tracing_features.acceleration_structure.p_next =
+        &mut ray_tracing_features.ray_tracing_pipeline as *mut _ as *mut c_void;
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut ray_tracing_features.acceleration_structure as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { get_physical_device_features2(instance, device, &mut features) };
+
+    ray_tracing_features
+        .acceleration_structure
+        .acceleration_structure
+        == vk::TRUE
+        && ray_tracing_features
+            .ray_tracing_pipeline
+            .ray_tracing_pipeline
+            == vk::TRUE
+        && ray_tracing_features
+            .buffer_device_address
+            .buffer_device_address
+            == vk::TRUE
+}
+
+/// Get the device extension of the ray queries, on top of the ones of the ray tracing.
+pub fn ray_query_device_extensions() -> [&'static CStr; 1] {
+    [name(RAY_QUERY)]
+}
+
+/// Check if `device` supports the ray queries, which trace rays from any shader
+/// without a ray tracing pipeline.
+///
+/// The ray tracing must be supported too.
+pub fn is_ray_query_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    if !is_device_extension_supported(instance, device, name(RAY_QUERY)) {
+        return false;
+    }
+
+    let mut ray_query_features = PhysicalDeviceRayQueryFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut ray_query_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { get_physical_device_features2(instance, device, &mut features) };
+
+    ray_query_features.ray_query == vk::TRUE
+}
+
+/// Build the ray query features to enable at device creation.
+pub fn ray_query_features() -> PhysicalDeviceRayQueryFeatures {
+    PhysicalDeviceRayQueryFeatures {
+        ray_query: vk::TRUE,
+        ..Default::default()
+    }
+}
+
+/// Build the ray tracing features to enable at device creation.
+///
+/// Each structure must be pushed to the device create info.
+pub fn required_features() -> RayTracingFeatures {
+    let mut features = RayTracingFeatures::default();
+    features.acceleration_structure.acceleration_structure = vk::TRUE;
+    features.ray_tracing_pipeline.ray_tracing_pipeline = vk::TRUE;
+    features.buffer_device_address.buffer_device_address = vk::TRUE;
+    features
+}
+
+/// Get the usage of the buffers the acceleration structures are built from.
+///
+/// The usage needed to get their address is included.
+pub fn build_input_usage() -> vk::BufferUsageFlags {
+    vk::BufferUsageFlags::from_raw(BUFFER_USAGE_BUILD_INPUT_READ_ONLY)
+        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS_EXT
+}
+
+/// Get the flags of the allocations of the buffers whose address is queried
+/// with `VK_KHR_buffer_device_address`.
+pub fn memory_allocate_flags() -> vk::MemoryAllocateFlags {
+    vk::MemoryAllocateFlags::from_raw(MEMORY_ALLOCATE_DEVICE_ADDRESS)
+}
+
+/// Get the type of the descriptors of the acceleration structures.
+pub fn acceleration_structure_descriptor_type() -> vk::DescriptorType {
+    vk::DescriptorType::from_raw(DESCRIPTOR_TYPE_ACCELERATION_STRUCTURE)
+}
+
+/// Write `acceleration_structure` to `binding` of each of the `sets`.
+pub fn write_acceleration_structure(
+    device: &Device,
+    sets: &[vk::DescriptorSet],
+    binding: u32,
+    acceleration_structure: AccelerationStructureHandle,
+) {
+    let handles = [acceleration_structure];
+    let mut structure_writes = sets
+        .iter()
+        .map(|_| WriteDescriptorSetAccelerationStructure {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_WRITE_DESCRIPTOR_SET_ACCELERATION_STRUCTURE,
+            ),
+            p_next: ptr::null(),
+            acceleration_structure_count: handles.len() as _,
+            p_acceleration_structures: handles.as_ptr(),
+        })
+        .collect::<Vec<_>>();
+    let descriptor_writes = sets
+        .iter()
+        .zip(structure_writes.iter_mut())
+        .map(|(set, structure_write)| {
+            let mut write = vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(acceleration_structure_descriptor_type())
+                .push_next(structure_write)
+                .build();
+            // The count is not set by the builder for the extension structures
+            write.descriptor_count = handles.len() as _;
+            write
+        })
+        .collect::<Vec<_>>();
+    unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+}
+
+/// Properties of the ray tracing pipelines and the acceleration structures of a device.
+#[derive(Clone, Copy, Debug)]
+pub struct RayTracingProperties {
+    /// Size in bytes of the handle of a shader group.
+    pub shader_group_handle_size: u32,
+    /// Alignment of the records in the shader binding table.
+    pub shader_group_handle_alignment: u32,
+    /// Alignment of the start of each region of the shader binding table.
+    pub shader_group_base_alignment: u32,
+    /// Alignment of the scratch memory of the builds.
+    pub min_scratch_offset_alignment: u32,
+}
+
+impl RayTracingProperties {
+    /// Query the ray tracing properties of `device`.
+    ///
+    /// # Safety
+    ///
+    /// Vulkan 1.1 must be supported by the instance and the device.
+    pub unsafe fn query(instance: &Instance, device: vk::PhysicalDevice) -> Self {
+        let mut acceleration_structure_properties =
+            PhysicalDeviceAccelerationStructureProperties::default();
+        let mut pipeline_properties = PhysicalDeviceRayTracingPipelineProperties {
+            p_next: &mut acceleration_structure_properties as *mut _ as *mut c_void,
+            ..Default::default()
+        };
+        let mut properties = vk::PhysicalDeviceProperties2 {
+            p_next: &mut pipeline_properties as *mut _ as *mut c_void,
+            ..Default::default()
+        };
+        get_physical_device_properties2(instance, device, &mut properties);
+
+        RayTracingProperties {
+            shader_group_handle_size: pipeline_properties.shader_group_handle_size,
+            shader_group_handle_alignment: pipeline_properties.shader_group_handle_alignment,
+            shader_group_base_alignment: pipeline_properties.shader_group_base_alignment,
+            min_scratch_offset_alignment: acceleration_structure_properties
+                .min_acceleration_structure_scratch_offset_alignment,
+        }
+    }
+}
+
+/// Loader for the functions of `VK_KHR_acceleration_structure` and
+/// `VK_KHR_ray_tracing_pipeline`, with the ray tracing properties of the device.
+#[derive(Clone, Copy)]
+pub struct RayTracing {
+    handle: vk::Device,
+    properties: RayTracingProperties,
+    create_acceleration_structure: PfnCreateAccelerationStructure,
+    destroy_acceleration_structure: PfnDestroyAccelerationStructure,
+    get_acceleration_structure_build_sizes: PfnGetAccelerationStructureBuildSizes,
+    cmd_build_acceleration_structures: PfnCmdBuildAccelerationStructures,
+    get_acceleration_structure_device_address: PfnGetAccelerationStructureDeviceAddress,
+    create_ray_tracing_pipelines: PfnCreateRayTracingPipelines,
+    get_ray_tracing_shader_group_handles: PfnGetRayTracingShaderGroupHandles,
+    cmd_trace_rays: PfnCmdTraceRays,
+}
+
+impl RayTracing {
+    /// Load the functions for `device`, created from `physical_device` with the
+    /// extensions and the features of the ray tracing enabled.
+    pub fn new(instance: &Instance, physical_device: vk::PhysicalDevice, device: &Device) -> Self {
+        let load = |name: &[u8]| unsafe {
+            let name = CStr::from_bytes_with_nul(name).unwrap();
+            instance
+                .get_device_proc_addr(device.handle(), name.as_ptr())
+                .expect("Failed to load a ray tracing function.")
+        };
+        unsafe {
+            RayTracing {
+                handle: device.handle(),
+                properties: RayTracingProperties::query(instance, physical_device),
+                create_acceleration_structure: mem::transmute::<
+                    VoidFunction,
+                    PfnCreateAccelerationStructure,
+                >(load(
+                    b"vkCreateAccelerationStructureKHR\0",
+                )),
+                destroy_acceleration_structure: mem::transmute::<
+                    VoidFunction,
+                    PfnDestroyAccelerationStructure,
+                >(load(
+                    b"vkDestroyAccelerationStructureKHR\0",
+                )),
+                get_acceleration_structure_build_sizes: mem::transmute::<
+                    VoidFunction,
+                    PfnGetAccelerationStructureBuildSizes,
+                >(load(
+                    b"vkGetAccelerationStructureBuildSizesKHR\0",
+                )),
+                cmd_build_acceleration_structures: mem::transmute::<
+                    VoidFunction,
+                    PfnCmdBuildAccelerationStructures,
+                >(load(
+                    b"vkCmdBuildAccelerationStructuresKHR\0",
+                )),
+                get_acceleration_structure_device_address: mem::transmute::<
+                    VoidFunction,
+                    PfnGetAccelerationStructureDeviceAddress,
+                >(load(
+                    b"vkGetAccelerationStructureDeviceAddressKHR\0",
+                )),
+                create_ray_tracing_pipelines: mem::transmute::<
+                    VoidFunction,
+                    PfnCreateRayTracingPipelines,
+                >(load(
+                    b"vkCreateRayTracingPipelinesKHR\0",
+                )),
+                get_ray_tracing_shader_group_handles: mem::transmute::<
+                    VoidFunction,
+                    PfnGetRayTracingShaderGroupHandles,
+                >(load(
+                    b"vkGetRayTracingShaderGroupHandlesKHR\0",
+                )),
+                cmd_trace_rays: mem::transmute::<VoidFunction, PfnCmdTraceRays>(load(
+                    b"vkCmdTraceRaysKHR\0",
+                )),
+            }
+        }
+    }
+
+    /// Create a ray tracing pipeline with `stages` and the shader `groups` referencing them.
+    ///
+    /// The pipeline does not trace rays recursively from its hit shaders.
+    pub fn create_pipeline(
+        &self,
+        device: &Device,
+        stages: &[vk::PipelineShaderStageCreateInfo],
+        groups: &[ShaderGroup],
+        layout: vk::PipelineLayout,
+    ) -> vk::Pipeline {
+        let groups = groups
+            .iter()
+            .map(|group| {
+                let (ty, general, closest_hit, any_hit) = match *group {
+                    ShaderGroup::General(stage) => (
+                        vk::RayTracingShaderGroupTypeNV::GENERAL,
+                        stage,
+                        vk::SHADER_UNUSED_NV,
+                        vk::SHADER_UNUSED_NV,
+                    ),
+                    ShaderGroup::TrianglesHit {
+                        closest_hit,
+                        any_hit,
+                    } => (
+                        vk::RayTracingShaderGroupTypeNV::TRIANGLES_HIT_GROUP,
+                        vk::SHADER_UNUSED_NV,
+                        closest_hit.unwrap_or(vk::SHADER_UNUSED_NV),
+                        any_hit.unwrap_or(vk::SHADER_UNUSED_NV),
+                    ),
+                };
+                RayTracingShaderGroupCreateInfo {
+                    s_type: vk::StructureType::from_raw(
+                        STRUCTURE_TYPE_RAY_TRACING_SHADER_GROUP_CREATE_INFO,
+                    ),
+                    p_next: ptr::null(),
+                    ty,
+                    general_shader: general,
+                    closest_hit_shader: closest_hit,
+                    any_hit_shader: any_hit,
+                    intersection_shader: vk::SHADER_UNUSED_NV,
+                    p_shader_group_capture_replay_handle: ptr::null(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let pipeline_info = RayTracingPipelineCreateInfo {
+            s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_RAY_TRACING_PIPELINE_CREATE_INFO),
+            p_next: ptr::null(),
+            flags: vk::PipelineCreateFlags::empty(),
+            stage_count: stages.len() as _,
+            p_stages: stages.as_ptr(),
+            group_count: groups.len() as _,
+            p_groups: groups.as_ptr(),
+            max_pipeline_ray_recursion_depth: 1,
+            p_library_info: ptr::null(),
+            p_library_interface: ptr::null(),
+            p_dynamic_state: ptr::null(),
+            layout,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: -1,
+        };
+        let mut pipeline = vk::Pipeline::null();
+        let result = unsafe {
+            (self.create_ray_tracing_pipelines)(
+                self.handle,
+                0,
+                vk::PipelineCache::null(),
+                1,
+                &pipeline_info,
+                ptr::null(),
+                &mut pipeline,
+            )
+        };
+        assert_eq!(
+            result,
+            vk::Result::SUCCESS,
+            "Failed to create ray tracing pipeline."
+        );
+        lifetime::track(device, ObjectKind::Pipeline, pipeline, "ray tracing");
+        pipeline
+    }
+
+    /// Record the rays traced by the raygen shader of `shader_binding_table`,
+    /// one per pixel of `extent`.
+    ///
+    /// The ray tracing pipeline the table was created from must be bound.
+    pub fn cmd_trace_rays(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        shader_binding_table: &ShaderBindingTable,
+        extent: vk::Extent2D,
+    ) {
+        let callable = StridedDeviceAddressRegion::default();
+        unsafe {
+            (self.cmd_trace_rays)(
+                command_buffer,
+                &shader_binding_table.raygen,
+                &shader_binding_table.miss,
+                &shader_binding_table.hit,
+                &callable,
+                extent.width,
+                extent.height,
+                1,
+            )
+        };
+    }
+
+    fn create_acceleration_structure(
+        &self,
+        ty: vk::AccelerationStructureTypeNV,
+        buffer: &Buffer,
+        size: vk::DeviceSize,
+    ) -> AccelerationStructureHandle {
+        let create_info = AccelerationStructureCreateInfo {
+            s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_ACCELERATION_STRUCTURE_CREATE_INFO),
+            p_next: ptr::null(),
+            create_flags: 0,
+            buffer: buffer.buffer,
+            offset: 0,
+            size,
+            ty,
+            device_address: 0,
+        };
+        let mut handle = AccelerationStructureHandle::default();
+        let result = unsafe {
+            (self.create_acceleration_structure)(
+                self.handle,
+                &create_info,
+                ptr::null(),
+                &mut handle,
+            )
+        };
+        assert_eq!(
+            result,
+            vk::Result::SUCCESS,
+            "Failed to create acceleration structure."
+        );
+        handle
+    }
+
+    fn get_build_sizes(
+        &self,
+        build_info: &AccelerationStructureBuildGeometryInfo,
+        primitive_count: u32,
+    ) -> AccelerationStructureBuildSizesInfo {
+        let mut sizes = AccelerationStructureBuildSizesInfo::default();
+        unsafe {
+            (self.get_acceleration_structure_build_sizes)(
+                self.handle,
+                BUILD_TYPE_DEVICE,
+                build_info,
+                &primitive_count,
+                &mut sizes,
+            )
+        };
+        sizes
+    }
+
+    fn get_device_address(&self, handle: AccelerationStructureHandle) -> vk::DeviceAddress {
+        let address_info = AccelerationStructureDeviceAddressInfo {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_ACCELERATION_STRUCTURE_DEVICE_ADDRESS_INFO,
+            ),
+            p_next: ptr::null(),
+            acceleration_structure: handle,
+        };
+        unsafe { (self.get_acceleration_structure_device_address)(self.handle, &address_info) }
+    }
+}
+
+/// Shader group of a ray tracing pipeline, referencing its stages by index.
+#[derive(Clone, Copy, Debug)]
+pub enum ShaderGroup {
+    /// Raygen, miss or callable shader.
+    General(u32),
+    /// Shaders run when a ray hits a triangle, which just hits it without any.
+    #[allow(dead_code)]
+    TrianglesHit {
+        closest_hit: Option<u32>,
+        any_hit: Option<u32>,
+    },
+}
+
+/// Handles of the shader groups of a ray tracing pipeline, in the regions
+/// `vkCmdTraceRaysKHR` reads them from.
+pub struct ShaderBindingTable {
+    buffer: Buffer,
+    raygen: StridedDeviceAddressRegion,
+    miss: StridedDeviceAddressRegion,
+    hit: StridedDeviceAddressRegion,
+}
+
+impl ShaderBindingTable {
+    /// Create the table of `pipeline`, whose groups are the raygen group then
+    /// `miss_count` miss groups and `hit_count` hit groups.
+    ///
+    /// `create_buffer` creates a host visible buffer of the given size and usage.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the buffer cannot be created.
+    pub fn new(
+        device: &Device,
+        ray_tracing: &RayTracing,
+        pipeline: vk::Pipeline,
+        (miss_count, hit_count): (u32, u32),
+        create_buffer: impl FnOnce(
+            vk::DeviceSize,
+            vk::BufferUsageFlags,
+        ) -> Result<Buffer, AllocationError>,
+    ) -> Result<Self, AllocationError> {
+        let properties = ray_tracing.properties;
+        let handle_size = properties.shader_group_handle_size as vk::DeviceSize;
+        let stride = align(
+            handle_size,
+            properties.shader_group_handle_alignment as vk::DeviceSize,
+        );
+        let base_alignment = properties.shader_group_base_alignment as vk::DeviceSize;
+        // The raygen region has a single record whose size is its stride
+        let region_sizes = [
+            align(stride, base_alignment),
+            align(stride * miss_count as vk::DeviceSize, base_alignment),
+            align(stride * hit_count as vk::DeviceSize, base_alignment),
+        ];
+        let group_count = 1 + miss_count + hit_count;
+        let mut handles = vec![0u8; (handle_size * group_count as vk::DeviceSize) as usize];
+        let result = unsafe {
+            (ray_tracing.get_ray_tracing_shader_group_handles)(
+                ray_tracing.handle,
+                pipeline,
+                0,
+                group_count,
+                handles.len(),
+                handles.as_mut_ptr() as *mut c_void,
+            )
+        };
+        assert_eq!(
+            result,
+            vk::Result::SUCCESS,
+            "Failed to get shader group handles."
+        );
+
+        // The base of the buffer is aligned up, so it is allocated a bit larger
+        let buffer = create_buffer(
+            region_sizes.iter().sum::<vk::DeviceSize>() + base_alignment,
+            vk::BufferUsageFlags::RAY_TRACING_NV | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS_EXT,
+        )?;
+        let buffer_address = buffer
+            .device_address()
+            .expect("The shader binding table has no address");
+        let base_offset = align(buffer_address, base_alignment) - buffer_address;
+
+        let mut data = vec![0u8; (buffer.size - base_offset) as usize];
+        let mut region_offset = 0;
+        let mut group = 0;
+        let mut regions = [StridedDeviceAddressRegion::default(); 3];
+        for (region_index, count) in [1, miss_count, hit_count].iter().enumerate() {
+            for record in 0..*count as vk::DeviceSize {
+                let handle = &handles[(group * handle_size) as usize..][..handle_size as usize];
+                let offset = (region_offset + record * stride) as usize;
+                data[offset..offset + handle.len()].copy_from_slice(handle);
+                group += 1;
+            }
+            if *count > 0 {
+                regions[region_index] = StridedDeviceAddressRegion {
+                    device_address: buffer_address + base_offset + region_offset,
+                    stride,
+                    size: region_sizes[region_index],
+                };
+            }
+            region_offset += region_sizes[region_index];
+        }
+        buffer.write(device, base_offset, &data);
+
+        let [raygen, miss, hit] = regions;
+        Ok(ShaderBindingTable {
+            buffer,
+            raygen,
+            miss,
+            hit,
+        })
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.buffer.destroy(device);
+    }
+}
+
+/// Triangles of a mesh to build a bottom level acceleration structure from.
+///
+/// Both buffers must have the `build_input_usage`. The position of each
+/// vertex must be its first three floats, and the indices must be `u32`.
+#[derive(Clone, Copy)]
+pub struct TriangleMesh {
+    pub vertex_buffer: Buffer,
+    pub vertex_stride: vk::DeviceSize,
+    pub vertex_count: u32,
+    pub index_buffer: Buffer,
+    pub index_count: u32,
+}
+
+/// Acceleration structure and the buffer it is stored in.
+struct AccelerationStructure {
+    handle: AccelerationStructureHandle,
+    buffer: Buffer,
+    /// Address the instances of a top level structure reference it with.
+    device_address: vk::DeviceAddress,
+}
+
+impl AccelerationStructure {
+    fn destroy(&mut self, device: &Device, ray_tracing: &RayTracing) {
+        unsafe {
+            (ray_tracing.destroy_acceleration_structure)(
+                ray_tracing.handle,
+                self.handle,
+                ptr::null(),
+            )
+        };
+        self.buffer.destroy(device);
+    }
+}
+
+/// Bottom level acceleration structures of meshes and the top level structure
+/// with an instance of each of them.
+///
+/// They are built once, from meshes which do not change. The triangles are
+/// opaque and hit from both sides.
+pub struct AccelerationStructures {
+    meshes: Vec<TriangleMesh>,
+    bottom_levels: Vec<AccelerationStructure>,
+    top_level: AccelerationStructure,
+    instance_buffer: Buffer,
+    /// Offset in the scratch buffer of the build of each bottom level structure.
+    scratch_offsets: Vec<vk::DeviceSize>,
+}
+
+impl AccelerationStructures {
+    /// Create the bottom level structures of `meshes` and the top level one,
+    /// with an instance of each mesh placed by its transform.
+    ///
+    /// `create_buffer` creates a buffer of the given size, usage and memory
+    /// properties. The structures are built by recording `cmd_build` with
+    /// the returned scratch buffer, which can be destroyed once it completed.
+    ///
+    /// # Errors
+    ///
+    /// Fail if a buffer cannot be created, nothing is created then.
+    pub fn new(
+        device: &Device,
+        ray_tracing: &RayTracing,
+        meshes: &[(TriangleMesh, Matrix4<f32>)],
+        mut create_buffer: impl FnMut(
+            vk::DeviceSize,
+            vk::BufferUsageFlags,
+            vk::MemoryPropertyFlags,
+        ) -> Result<Buffer, AllocationError>,
+    ) -> Result<(Self, Buffer), AllocationError> {
+        let scratch_alignment = ray_tracing.properties.min_scratch_offset_alignment as _;
+        let mut bottom_levels: Vec<AccelerationStructure> = Vec::with_capacity(meshes.len());
+        let mut scratch_offsets = Vec::with_capacity(meshes.len());
+        let mut bottom_levels_scratch_size = 0;
+        let destroy_all = |bottom_levels: &mut Vec<AccelerationStructure>| {
+            bottom_levels
+                .iter_mut()
+                .for_each(|bottom_level| bottom_level.destroy(device, ray_tracing))
+        };
+
+        for (mesh, _) in meshes {
+            let geometries = [triangles_geometry(mesh)];
+            let build_info = build_geometry_info(
+                vk::AccelerationStructureTypeNV::BOTTOM_LEVEL,
+                &geometries,
+                AccelerationStructureHandle::default(),
+                0,
+            );
+            let sizes = ray_tracing.get_build_sizes(&build_info, mesh.index_count / 3);
+            match Self::create_acceleration_structure(
+                ray_tracing,
+                vk::AccelerationStructureTypeNV::BOTTOM_LEVEL,
+                sizes.acceleration_structure_size,
+                &mut create_buffer,
+            ) {
+                Ok(bottom_level) => bottom_levels.push(bottom_level),
+                Err(error) => {
+                    destroy_all(&mut bottom_levels);
+                    return Err(error);
+                }
+            }
+            scratch_offsets.push(bottom_levels_scratch_size);
+            bottom_levels_scratch_size += align(sizes.build_scratch_size, scratch_alignment);
+        }
+
+        let instances = meshes
+            .iter()
+            .zip(bottom_levels.iter())
+            .enumerate()
+            .map(|(index, ((_, transform), bottom_level))| {
+                AccelerationStructureInstance::new(
+                    *transform,
+                    index as _,
+                    bottom_level.device_address,
+                )
+            })
+            .collect::<Vec<_>>();
+        let instance_buffer = create_buffer(
+            (instances.len().max(1) * size_of::<AccelerationStructureInstance>()) as _,
+            build_input_usage(),
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+        let mut instance_buffer = match instance_buffer {
+            Ok(instance_buffer) => instance_buffer,
+            Err(error) => {
+                destroy_all(&mut bottom_levels);
+                return Err(error);
+            }
+        };
+        instance_buffer.write(device, 0, &instances);
+
+        let geometries = [instances_geometry(&instance_buffer)];
+        let build_info = build_geometry_info(
+            vk::AccelerationStructureTypeNV::TOP_LEVEL,
+            &geometries,
+            AccelerationStructureHandle::default(),
+            0,
+        );
+        let sizes = ray_tracing.get_build_sizes(&build_info, instances.len() as _);
+        let top_level = Self::create_acceleration_structure(
+            ray_tracing,
+            vk::AccelerationStructureTypeNV::TOP_LEVEL,
+            sizes.acceleration_structure_size,
+            &mut create_buffer,
+        );
+        let mut top_level = match top_level {
+            Ok(top_level) => top_level,
+            Err(error) => {
+                instance_buffer.destroy(device);
+                destroy_all(&mut bottom_levels);
+                return Err(error);
+            }
+        };
+
+        // The top level is built after the bottom levels, reusing their scratch memory.
+        // The base of the buffer is aligned up, so it is allocated a bit larger
+        let scratch_size = bottom_levels_scratch_size.max(sizes.build_scratch_size);
+        let scratch = create_buffer(
+            scratch_size + scratch_alignment,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS_EXT,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        let scratch = match scratch {
+            Ok(scratch) => scratch,
+            Err(error) => {
+                top_level.destroy(device, ray_tracing);
+                instance_buffer.destroy(device);
+                destroy_all(&mut bottom_levels);
+                return Err(error);
+            }
+        };
+
+        let acceleration_structures = AccelerationStructures {
+            meshes: meshes.iter().map(|(mesh, _)| *mesh).collect(),
+            bottom_levels,
+            top_level,
+            instance_buffer,
+            scratch_offsets,
+        };
+        Ok((acceleration_structures, scratch))
+    }
+
+    fn create_acceleration_structure(
+        ray_tracing: &RayTracing,
+        ty: vk::AccelerationStructureTypeNV,
+        size: vk::DeviceSize,
+        create_buffer: &mut impl FnMut(
+            vk::DeviceSize,
+            vk::BufferUsageFlags,
+            vk::MemoryPropertyFlags,
+        ) -> Result<Buffer, AllocationError>,
+    ) -> Result<AccelerationStructure, AllocationError> {
+        let buffer = create_buffer(
+            size,
+            vk::BufferUsageFlags::from_raw(BUFFER_USAGE_ACCELERATION_STRUCTURE_STORAGE)
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS_EXT,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let handle = ray_tracing.create_acceleration_structure(ty, &buffer, size);
+        Ok(AccelerationStructure {
+            handle,
+            buffer,
+            device_address: ray_tracing.get_device_address(handle),
+        })
+    }
+
+    /// Get the top level structure to bind to the shaders tracing rays.
+    pub fn top_level(&self) -> AccelerationStructureHandle {
+        self.top_level.handle
+    }
+
+    /// Record the build of the bottom level structures then the top level one.
+    ///
+    /// `scratch` is the buffer returned by `new`. The structures can be used
+    /// by the ray tracing shaders once the build completed.
+    pub fn cmd_build(
+        &self,
+        device: &Device,
+        ray_tracing: &RayTracing,
+        command_buffer: vk::CommandBuffer,
+        scratch: &Buffer,
+    ) {
+        let scratch_alignment = ray_tracing.properties.min_scratch_offset_alignment as _;
+        let scratch_address = align(
+            scratch
+                .device_address()
+                .expect("The scratch buffer has no address"),
+            scratch_alignment,
+        );
+
+        let geometries = self
+            .meshes
+            .iter()
+            .map(|mesh| [triangles_geometry(mesh)])
+            .collect::<Vec<_>>();
+        let build_infos = geometries
+            .iter()
+            .zip(self.bottom_levels.iter())
+            .zip(self.scratch_offsets.iter())
+            .map(|((geometries, bottom_level), scratch_offset)| {
+                build_geometry_info(
+                    vk::AccelerationStructureTypeNV::BOTTOM_LEVEL,
+                    geometries,
+                    bottom_level.handle,
+                    scratch_address + scratch_offset,
+                )
+            })
+            .collect::<Vec<_>>();
+        let ranges = self
+            .meshes
+            .iter()
+            .map(|mesh| AccelerationStructureBuildRangeInfo {
+                primitive_count: mesh.index_count / 3,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let range_ptrs = ranges
+            .iter()
+            .map(|range| range as *const _)
+            .collect::<Vec<_>>();
+        let build_write = Access::new(
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_NV,
+            vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_NV,
+        );
+        let build_read = Access::new(
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_NV,
+            vk::AccessFlags::ACCELERATION_STRUCTURE_READ_NV
+                | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_NV,
+        );
+        unsafe {
+            if !build_infos.is_empty() {
+                (ray_tracing.cmd_build_acceleration_structures)(
+                    command_buffer,
+                    build_infos.len() as _,
+                    build_infos.as_ptr(),
+                    range_ptrs.as_ptr(),
+                );
+            }
+            // The instances reference the bottom levels and the top level
+            // build reuses the scratch memory
+            cmd_barriers(
+                device,
+                command_buffer,
+                &[Barrier::memory(build_write, build_read)],
+            );
+
+            let geometries = [instances_geometry(&self.instance_buffer)];
+            let build_info = build_geometry_info(
+                vk::AccelerationStructureTypeNV::TOP_LEVEL,
+                &geometries,
+                self.top_level.handle,
+                scratch_address,
+            );
+            let range = AccelerationStructureBuildRangeInfo {
+                primitive_count: self.meshes.len() as _,
+                ..Default::default()
+            };
+            let range_ptrs = [&range as *const _];
+            (ray_tracing.cmd_build_acceleration_structures)(
+                command_buffer,
+                1,
+                &build_info,
+                range_ptrs.as_ptr(),
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, ray_tracing: &RayTracing) {
+        self.top_level.destroy(device, ray_tracing);
+        self.bottom_levels
+            .iter_mut()
+            .for_each(|bottom_level| bottom_level.destroy(device, ray_tracing));
+        self.instance_buffer.destroy(device);
+    }
+}
+
+/// Round `value` up to a multiple of `alignment`, which is a power of two.
+fn align(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    if alignment == 0 {
+        value
+    } else {
+        (value + alignment - 1) & !(alignment - 1)
+    }
+}
+
+fn triangles_geometry(mesh: &TriangleMesh) -> AccelerationStructureGeometry {
+    let address = |buffer: &Buffer| {
+        buffer
+            .device_address()
+            .expect("The geometry buffers have no address")
+    };
+    AccelerationStructureGeometry {
+        s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_ACCELERATION_STRUCTURE_GEOMETRY),
+        p_next: ptr::null(),
+        geometry_type: vk::GeometryTypeNV::TRIANGLES,
+        geometry: AccelerationStructureGeometryData {
+            triangles: AccelerationStructureGeometryTrianglesData {
+                s_type: vk::StructureType::from_raw(
+                    STRUCTURE_TYPE_ACCELERATION_STRUCTURE_GEOMETRY_TRIANGLES_DATA,
+                ),
+                p_next: ptr::null(),
+                vertex_format: vk::Format::R32G32B32_SFLOAT,
+                vertex_data: address(&mesh.vertex_buffer),
+                vertex_stride: mesh.vertex_stride,
+                max_vertex: mesh.vertex_count.saturating_sub(1),
+                index_type: vk::IndexType::UINT32,
+                index_data: address(&mesh.index_buffer),
+                transform_data: 0,
+            },
+        },
+        flags: vk::GeometryFlagsNV::OPAQUE,
+    }
+}
+
+fn instances_geometry(instance_buffer: &Buffer) -> AccelerationStructureGeometry {
+    AccelerationStructureGeometry {
+        s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_ACCELERATION_STRUCTURE_GEOMETRY),
+        p_next: ptr::null(),
+        geometry_type: vk::GeometryTypeNV::from_raw(GEOMETRY_TYPE_INSTANCES),
+        geometry: AccelerationStructureGeometryData {
+            instances: AccelerationStructureGeometryInstancesData {
+                s_type: vk::StructureType::from_raw(
+                    STRUCTURE_TYPE_ACCELERATION_STRUCTURE_GEOMETRY_INSTANCES_DATA,
+                ),
+                p_next: ptr::null(),
+                array_of_pointers: vk::FALSE,
+                data: instance_buffer
+                    .device_address()
+                    .expect("The instance buffer has no address"),
+            },
+        },
+        flags: vk::GeometryFlagsNV::OPAQUE,
+    }
+}
+
+/// Describe the build of `dst` from `geometries`, which must outlive the description.
+fn build_geometry_info(
+    ty: vk::AccelerationStructureTypeNV,
+    geometries: &[AccelerationStructureGeometry],
+    dst: AccelerationStructureHandle,
+    scratch_address: vk::DeviceAddress,
+) -> AccelerationStructureBuildGeometryInfo {
+    AccelerationStructureBuildGeometryInfo {
+        s_type: vk::StructureType::from_raw(
+            STRUCTURE_TYPE_ACCELERATION_STRUCTURE_BUILD_GEOMETRY_INFO,
+        ),
+        p_next: ptr::null(),
+        ty,
+        flags: vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE,
+        mode: BUILD_MODE_BUILD,
+        src_acceleration_structure: AccelerationStructureHandle::default(),
+        dst_acceleration_structure: dst,
+        geometry_count: geometries.len() as _,
+        p_geometries: geometries.as_ptr(),
+        pp_geometries: ptr::null(),
+        scratch_data: scratch_address,
+    }
+}
+
+type VoidFunction = unsafe extern "system" fn() -> c_void;
+type PfnCreateAccelerationStructure = unsafe extern "system" fn(
+    vk::Device,
+    *const AccelerationStructureCreateInfo,
+    *const vk::AllocationCallbacks,
+    *mut AccelerationStructureHandle,
+) -> vk::Result;
+type PfnDestroyAccelerationStructure = unsafe extern "system" fn(
+    vk::Device,
+    AccelerationStructureHandle,
+    *const vk::AllocationCallbacks,
+);
+type PfnGetAccelerationStructureBuildSizes = unsafe extern "system" fn(
+    vk::Device,
+    i32,
+    *const AccelerationStructureBuildGeometryInfo,
+    *const u32,
+    *mut AccelerationStructureBuildSizesInfo,
+);
+type PfnCmdBuildAccelerationStructures = unsafe extern "system" fn(
+    vk::CommandBuffer,
+    u32,
+    *const AccelerationStructureBuildGeometryInfo,
+    *const *const AccelerationStructureBuildRangeInfo,
+);
+type PfnGetAccelerationStructureDeviceAddress = unsafe extern "system" fn(
+    vk::Device,
+    *const AccelerationStructureDeviceAddressInfo,
+) -> vk::DeviceAddress;
+type PfnCreateRayTracingPipelines = unsafe extern "system" fn(
+    vk::Device,
+    u64,
+    vk::PipelineCache,
+    u32,
+    *const RayTracingPipelineCreateInfo,
+    *const vk::AllocationCallbacks,
+    *mut vk::Pipeline,
+) -> vk::Result;
+type PfnGetRayTracingShaderGroupHandles =
+    unsafe extern "system" fn(vk::Device, vk::Pipeline, u32, u32, usize, *mut c_void) -> vk::Result;
+type PfnCmdTraceRays = unsafe extern "system" fn(
+    vk::CommandBuffer,
+    *const StridedDeviceAddressRegion,
+    *const StridedDeviceAddressRegion,
+    *const StridedDeviceAddressRegion,
+    *const StridedDeviceAddressRegion,
+    u32,
+    u32,
+    u32,
+);
+
+/// `VkAccelerationStructureKHR`.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccelerationStructureHandle(u64);
+
+/// Features of the ray tracing to query and enable at device creation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RayTracingFeatures {
+    pub acceleration_structure: PhysicalDeviceAccelerationStructureFeatures,
+    pub ray_tracing_pipeline: PhysicalDeviceRayTracingPipelineFeatures,
+    pub buffer_device_address: PhysicalDeviceBufferDeviceAddressFeatures,
+}
+
+/// `VkPhysicalDeviceAccelerationStructureFeaturesKHR`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalDeviceAccelerationStructureFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    pub acceleration_structure: vk::Bool32,
+    acceleration_structure_capture_replay: vk::Bool32,
+    acceleration_structure_indirect_build: vk::Bool32,
+    acceleration_structure_host_commands: vk::Bool32,
+    descriptor_binding_acceleration_structure_update_after_bind: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceAccelerationStructureFeatures {
+    fn default() -> Self {
+        PhysicalDeviceAccelerationStructureFeatures {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_ACCELERATION_STRUCTURE_FEATURES,
+            ),
+            p_next: ptr::null_mut(),
+            acceleration_structure: vk::FALSE,
+            acceleration_structure_capture_replay: vk::FALSE,
+            acceleration_structure_indirect_build: vk::FALSE,
+            acceleration_structure_host_commands: vk::FALSE,
+            descriptor_binding_acceleration_structure_update_after_bind: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceAccelerationStructureFeatures {}
+
+/// `VkPhysicalDeviceRayTracingPipelineFeaturesKHR`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalDeviceRayTracingPipelineFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    pub ray_tracing_pipeline: vk::Bool32,
+    ray_tracing_pipeline_shader_group_handle_capture_replay: vk::Bool32,
+    ray_tracing_pipeline_shader_group_handle_capture_replay_mixed: vk::Bool32,
+    ray_tracing_pipeline_trace_rays_indirect: vk::Bool32,
+    ray_traversal_primitive_culling: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceRayTracingPipelineFeatures {
+    fn default() -> Self {
+        PhysicalDeviceRayTracingPipelineFeatures {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_RAY_TRACING_PIPELINE_FEATURES,
+            ),
+            p_next: ptr::null_mut(),
+            ray_tracing_pipeline: vk::FALSE,
+            ray_tracing_pipeline_shader_group_handle_capture_replay: vk::FALSE,
+            ray_tracing_pipeline_shader_group_handle_capture_replay_mixed: vk::FALSE,
+            ray_tracing_pipeline_trace_rays_indirect: vk::FALSE,
+            ray_traversal_primitive_culling: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceRayTracingPipelineFeatures {}
+
+/// `VkPhysicalDeviceRayQueryFeaturesKHR`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalDeviceRayQueryFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    pub ray_query: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceRayQueryFeatures {
+    fn default() -> Self {
+        PhysicalDeviceRayQueryFeatures {
+            s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_PHYSICAL_DEVICE_RAY_QUERY_FEATURES),
+            p_next: ptr::null_mut(),
+            ray_query: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceRayQueryFeatures {}
+
+/// `VkPhysicalDeviceBufferDeviceAddressFeaturesKHR`.
+///
+/// Its layout is the one of the EXT features known by ash, with another
+/// structure type.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalDeviceBufferDeviceAddressFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    pub buffer_device_address: vk::Bool32,
+    buffer_device_address_capture_replay: vk::Bool32,
+    buffer_device_address_multi_device: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceBufferDeviceAddressFeatures {
+    fn default() -> Self {
+        PhysicalDeviceBufferDeviceAddressFeatures {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_BUFFER_DEVICE_ADDRESS_FEATURES,
+            ),
+            p_next: ptr::null_mut(),
+            buffer_device_address: vk::FALSE,
+            buffer_device_address_capture_replay: vk::FALSE,
+            buffer_device_address_multi_device: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceBufferDeviceAddressFeatures {}
+
+#[repr(C)]
+struct PhysicalDeviceRayTracingPipelineProperties {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    shader_group_handle_size: u32,
+    max_ray_recursion_depth: u32,
+    max_shader_group_stride: u32,
+    shader_group_base_alignment: u32,
+    shader_group_handle_capture_replay_size: u32,
+    max_ray_dispatch_invocation_count: u32,
+    shader_group_handle_alignment: u32,
+    max_ray_hit_attribute_size: u32,
+}
+
+impl Default for PhysicalDeviceRayTracingPipelineProperties {
+    fn default() -> Self {
+        PhysicalDeviceRayTracingPipelineProperties {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_RAY_TRACING_PIPELINE_PROPERTIES,
+            ),
+            p_next: ptr::null_mut(),
+            shader_group_handle_size: 0,
+            max_ray_recursion_depth: 0,
+            max_shader_group_stride: 0,
+            shader_group_base_alignment: 0,
+            shader_group_handle_capture_replay_size: 0,
+            max_ray_dispatch_invocation_count: 0,
+            shader_group_handle_alignment: 0,
+            max_ray_hit_attribute_size: 0,
+        }
+    }
+}
+
+#[repr(C)]
+struct PhysicalDeviceAccelerationStructureProperties {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    max_geometry_count: u64,
+    max_instance_count: u64,
+    max_primitive_count: u64,
+    max_per_stage_descriptor_acceleration_structures: u32,
+    max_per_stage_descriptor_update_after_bind_acceleration_structures: u32,
+    max_descriptor_set_acceleration_structures: u32,
+    max_descriptor_set_update_after_bind_acceleration_structures: u32,
+    min_acceleration_structure_scratch_offset_alignment: u32,
+}
+
+impl Default for PhysicalDeviceAccelerationStructureProperties {
+    fn default() -> Self {
+        PhysicalDeviceAccelerationStructureProperties {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_ACCELERATION_STRUCTURE_PROPERTIES,
+            ),
+            p_next: ptr::null_mut(),
+            max_geometry_count: 0,
+            max_instance_count: 0,
+            max_primitive_count: 0,
+            max_per_stage_descriptor_acceleration_structures: 0,
+            max_per_stage_descriptor_update_after_bind_acceleration_structures: 0,
+            max_descriptor_set_acceleration_structures: 0,
+            max_descriptor_set_update_after_bind_acceleration_structures: 0,
+            min_acceleration_structure_scratch_offset_alignment: 0,
+        }
+    }
+}
+
+#[repr(C)]
+struct AccelerationStructureCreateInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    create_flags: vk::Flags,
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    ty: vk::AccelerationStructureTypeNV,
+    device_address: vk::DeviceAddress,
+}
+
+// The addresses of the geometry are device addresses, never host ones, so
+// the unions holding either are replaced by the device address.
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct AccelerationStructureGeometryTrianglesData {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    vertex_format: vk::Format,
+    vertex_data: vk::DeviceAddress,
+    vertex_stride: vk::DeviceSize,
+    max_vertex: u32,
+    index_type: vk::IndexType,
+    index_data: vk::DeviceAddress,
+    transform_data: vk::DeviceAddress,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct AccelerationStructureGeometryInstancesData {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    array_of_pointers: vk::Bool32,
+    data: vk::DeviceAddress,
+}
+
+/// `VkAccelerationStructureGeometryDataKHR`, whose AABBs are smaller than the
+/// triangles and never used.
+#[repr(C)]
+#[derive(Clone, Copy)]
+union AccelerationStructureGeometryData {
+    triangles: AccelerationStructureGeometryTrianglesData,
+    instances: AccelerationStructureGeometryInstancesData,
+}
+
+#[repr(C)]
+struct AccelerationStructureGeometry {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    geometry_type: vk::GeometryTypeNV,
+    geometry: AccelerationStructureGeometryData,
+    flags: vk::GeometryFlagsNV,
+}
+
+#[repr(C)]
+struct AccelerationStructureBuildGeometryInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    ty: vk::AccelerationStructureTypeNV,
+    flags: vk::BuildAccelerationStructureFlagsNV,
+    mode: i32,
+    src_acceleration_structure: AccelerationStructureHandle,
+    dst_acceleration_structure: AccelerationStructureHandle,
+    geometry_count: u32,
+    p_geometries: *const AccelerationStructureGeometry,
+    pp_geometries: *const *const AccelerationStructureGeometry,
+    scratch_data: vk::DeviceAddress,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct AccelerationStructureBuildRangeInfo {
+    primitive_count: u32,
+    primitive_offset: u32,
+    first_vertex: u32,
+    transform_offset: u32,
+}
+
+#[repr(C)]
+struct AccelerationStructureBuildSizesInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    acceleration_structure_size: vk::DeviceSize,
+    update_scratch_size: vk::DeviceSize,
+    build_scratch_size: vk::DeviceSize,
+}
+
+impl Default for AccelerationStructureBuildSizesInfo {
+    fn default() -> Self {
+        AccelerationStructureBuildSizesInfo {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_ACCELERATION_STRUCTURE_BUILD_SIZES_INFO,
+            ),
+            p_next: ptr::null(),
+            acceleration_structure_size: 0,
+            update_scratch_size: 0,
+            build_scratch_size: 0,
+        }
+    }
+}
+
+#[repr(C)]
+struct AccelerationStructureDeviceAddressInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    acceleration_structure: AccelerationStructureHandle,
+}
+
+/// `VkAccelerationStructureInstanceKHR`, whose bit fields are packed by hand.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct AccelerationStructureInstance {
+    /// First three rows of the transform.
+    transform: [[f32; 4]; 3],
+    /// Custom index in the low 24 bits and visibility mask in the high 8 bits.
+    custom_index_and_mask: u32,
+    /// Offset of the hit group in the low 24 bits and flags in the high 8 bits.
+    hit_group_offset_and_flags: u32,
+    acceleration_structure_reference: u64,
+}
+
+impl AccelerationStructureInstance {
+    /// Create an instance of the bottom level at `reference`, visible to all
+    /// rays and using the first hit group.
+    fn new(transform: Matrix4<f32>, custom_index: u32, reference: vk::DeviceAddress) -> Self {
+        let row = |row: usize| {
+            [
+                transform[0][row],
+                transform[1][row],
+                transform[2][row],
+                transform[3][row],
+            ]
+        };
+        let flags = vk::GeometryInstanceFlagsNV::TRIANGLE_CULL_DISABLE.as_raw();
+        AccelerationStructureInstance {
+            transform: [row(0), row(1), row(2)],
+            custom_index_and_mask: (custom_index & 0xff_ffff) | 0xff << 24,
+            hit_group_offset_and_flags: flags << 24,
+            acceleration_structure_reference: reference,
+        }
+    }
+}
+
+#[repr(C)]
+struct RayTracingShaderGroupCreateInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    ty: vk::RayTracingShaderGroupTypeNV,
+    general_shader: u32,
+    closest_hit_shader: u32,
+    any_hit_shader: u32,
+    intersection_shader: u32,
+    p_shader_group_capture_replay_handle: *const c_void,
+}
+
+#[repr(C)]
+struct RayTracingPipelineCreateInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: vk::PipelineCreateFlags,
+    stage_count: u32,
+    p_stages: *const vk::PipelineShaderStageCreateInfo,
+    group_count: u32,
+    p_groups: *const RayTracingShaderGroupCreateInfo,
+    max_pipeline_ray_recursion_depth: u32,
+    p_library_info: *const c_void,
+    p_library_interface: *const c_void,
+    p_dynamic_state: *const vk::PipelineDynamicStateCreateInfo,
+    layout: vk::PipelineLayout,
+    base_pipeline_handle: vk::Pipeline,
+    base_pipeline_index: i32,
+}
+
+/// `VkStridedDeviceAddressRegionKHR`, empty by default.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct StridedDeviceAddressRegion {
+    device_address: vk::DeviceAddress,
+    stride: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+/// `VkWriteDescriptorSetAccelerationStructureKHR`.
+#[repr(C)]
+struct WriteDescriptorSetAccelerationStructure {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    acceleration_structure_count: u32,
+    p_acceleration_structures: *const AccelerationStructureHandle,
+}
+
+unsafe impl vk::ExtendsWriteDescriptorSet for WriteDescriptorSetAccelerationStructure {}
@@ -0,0 +1,107 @@
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    gpu_device::Device,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::{
+    marker::PhantomData,
+    mem::{self, size_of},
+};
+
+/// Ring of host visible buffers the GPU copies results into, read by the CPU
+/// a few frames later.
+///
+/// Each slot is written by the commands of a swapchain image and read the
+/// next time they are about to be submitted, after the fence of the frame was
+/// waited for. The copy of the previous submission of the slot has completed
+/// by then so reading never stalls the queue, and the results are as many
+/// frames old as there are slots.
+pub struct ReadbackBuffer<T> {
+    buffers: Vec<Buffer>,
+    len: usize,
+    /// Whether the copy of each slot was submitted since it was last read.
+    submitted: Vec<bool>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> ReadbackBuffer<T> {
+    /// Create a ring reading back `len` values per slot, one slot per buffer.
+    ///
+    /// `buffers` must be host visible and coherent transfer destinations of
+    /// `ReadbackBuffer::<T>::buffer_size(len)` bytes, usually one per
+    /// swapchain image.
+    pub fn new(buffers: Vec<Buffer>, len: usize) -> Self {
+        let slot_count = buffers.len();
+        ReadbackBuffer {
+            buffers,
+            len,
+            submitted: vec![false; slot_count],
+            _marker: PhantomData,
+        }
+    }
+
+    /// Size in bytes of each buffer reading back `len` values.
+    pub fn buffer_size(len: usize) -> vk::DeviceSize {
+        (size_of::<T>() * len.max(1)) as _
+    }
+}
+
+impl<T: Copy> ReadbackBuffer<T> {
+    /// Record the copy of the values at `src_offset` in `src` to the buffer of `slot`.
+    ///
+    /// The writes of `src` must have been made visible to transfers. The copy
+    /// is made visible to the host.
+    pub fn cmd_copy(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        (src, src_offset): (vk::Buffer, vk::DeviceSize),
+    ) {
+        let region = vk::BufferCopy {
+            src_offset,
+            dst_offset: 0,
+            size: Self::buffer_size(self.len),
+        };
+        unsafe {
+            device.cmd_copy_buffer(command_buffer, src, self.buffers[slot].buffer, &[region])
+        };
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::memory(
+                Access::transfer_write(),
+                Access::host_read(),
+            )],
+        );
+    }
+
+    /// Read the values copied by the last submission of the commands of `slot`.
+    ///
+    /// It must be called before each submission of the commands of the slot,
+    /// once they completed. Get `None` the first time since nothing was
+    /// copied yet.
+    pub fn read(&mut self, device: &Device, slot: usize) -> Option<Vec<T>> {
+        if !mem::replace(&mut self.submitted[slot], true) {
+            return None;
+        }
+
+        let buffer = self.buffers[slot];
+        let size = Self::buffer_size(self.len);
+        unsafe {
+            let data_ptr = device
+                .map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap();
+            let values = std::slice::from_raw_parts(data_ptr as *const T, self.len).to_vec();
+            device.unmap_memory(buffer.memory);
+            Some(values)
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
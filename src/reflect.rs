@@ -0,0 +1,685 @@
+use crate::{fs, gpu_device::Device};
+use ash::{version::DeviceV1_0, vk};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const HEADER_SIZE: usize = 5;
+
+// Opcodes of the instructions read by the reflection.
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT_TRUE: u32 = 48;
+const OP_SPEC_CONSTANT_OP: u32 = 52;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Decorations.
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes.
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+const STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER: u32 = 5349;
+
+// Image dimensions.
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// Descriptor used by a shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    /// Type of the descriptor.
+    ///
+    /// Uniform buffers are never reported as dynamic since the shader does not
+    /// know how they are bound.
+    pub descriptor_type: vk::DescriptorType,
+    /// Number of descriptors in the binding, 0 for runtime sized arrays.
+    pub count: u32,
+}
+
+/// Numeric type of the components of a vertex input or attribute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumericType {
+    Float,
+    Int,
+    Uint,
+}
+
+/// Input variable of a vertex shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub numeric_type: NumericType,
+}
+
+/// Error while reflecting a shader or validating its interface.
+#[derive(Debug)]
+pub enum ReflectError {
+    /// The code is not a SPIR-V module.
+    InvalidModule,
+    /// The module has no entry point or an unknown execution model.
+    UnknownStage,
+    /// The descriptor array at `set` and `binding` is sized by a specialization
+    /// constant, whose value is only known when the pipeline is created.
+    SpecializedDescriptorCount { set: u32, binding: u32 },
+    /// The push constant block has an array sized by a specialization constant.
+    SpecializedPushConstantSize,
+    /// No vertex attribute is provided for the input of the vertex shader at a location.
+    MissingVertexAttribute(u32),
+    /// The vertex attribute at `location` does not have the numeric type of the input.
+    VertexAttributeMismatch {
+        location: u32,
+        expected: NumericType,
+        found: NumericType,
+    },
+}
+
+impl Error for ReflectError {}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReflectError::InvalidModule => write!(f, "Invalid SPIR-V module"),
+            ReflectError::UnknownStage => write!(f, "Unknown shader stage"),
+            ReflectError::SpecializedDescriptorCount { set, binding } => write!(
+                f,
+                "Descriptor array at set {} and binding {} is sized by a specialization constant",
+                set, binding
+            ),
+            ReflectError::SpecializedPushConstantSize => write!(
+                f,
+                "Push constant block has an array sized by a specialization constant"
+            ),
+            ReflectError::MissingVertexAttribute(location) => {
+                write!(f, "No vertex attribute at location {}", location)
+            }
+            ReflectError::VertexAttributeMismatch {
+                location,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Vertex attribute at location {} is {:?} instead of {:?}",
+                location, found, expected
+            ),
+        }
+    }
+}
+
+/// Type declared in a module, only keeping what the reflection needs.
+#[derive(Clone, Debug)]
+enum Type {
+    Scalar {
+        width: u32,
+        numeric_type: NumericType,
+    },
+    Vector {
+        component: u32,
+        count: u32,
+    },
+    Matrix {
+        columns: u32,
+    },
+    Image {
+        dim: u32,
+        sampled: u32,
+    },
+    Sampler,
+    SampledImage,
+    Array {
+        element: u32,
+        length: u32,
+    },
+    RuntimeArray {
+        element: u32,
+    },
+    Struct {
+        members: Vec<u32>,
+    },
+    Pointer {
+        storage_class: u32,
+        pointee: u32,
+    },
+}
+
+/// Descriptors, push constants and vertex inputs of a shader module.
+///
+/// The interface is read from the SPIR-V code so the descriptor set layouts,
+/// the push constant ranges and the vertex input of a pipeline can be derived
+/// from its shaders, or checked against them, instead of being maintained by
+/// hand alongside the GLSL sources.
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<DescriptorBinding>,
+    /// Range of the push constants read by the shader.
+    pub push_constants: Option<vk::PushConstantRange>,
+    /// Inputs of the shader if it is a vertex shader.
+    pub vertex_inputs: Vec<VertexInput>,
+}
+
+impl ShaderReflection {
+    /// Reflect the interface of the SPIR-V module `code`.
+    ///
+    /// Only the first entry point of the module is considered.
+    pub fn new(code: &[u32]) -> Result<Self, ReflectError> {
+        if code.len() < HEADER_SIZE || code[0] != SPIRV_MAGIC {
+            return Err(ReflectError::InvalidModule);
+        }
+
+        let mut stage = None;
+        let mut types = HashMap::new();
+        let mut constants = HashMap::new();
+        let mut spec_constants = HashSet::new();
+        let mut variables = Vec::new();
+        let mut decorations = HashMap::<(u32, u32), u32>::new();
+        let mut member_decorations = HashMap::<(u32, u32, u32), u32>::new();
+
+        let mut words = &code[HEADER_SIZE..];
+        while !words.is_empty() {
+            let word_count = (words[0] >> 16) as usize;
+            let opcode = words[0] & 0xFFFF;
+            if word_count == 0 || word_count > words.len() {
+                return Err(ReflectError::InvalidModule);
+            }
+            let operands = &words[1..word_count];
+            words = &words[word_count..];
+
+            let operand = |index: usize| {
+                operands
+                    .get(index)
+                    .copied()
+                    .ok_or(ReflectError::InvalidModule)
+            };
+            match opcode {
+                OP_ENTRY_POINT if stage.is_none() => {
+                    stage = Some(execution_model_stage(operand(0)?)?);
+                }
+                OP_TYPE_INT => {
+                    let numeric_type = if operand(2)? == 1 {
+                        NumericType::Int
+                    } else {
+                        NumericType::Uint
+                    };
+                    let scalar = Type::Scalar {
+                        width: operand(1)?,
+                        numeric_type,
+                    };
+                    types.insert(operand(0)?, scalar);
+                }
+                OP_TYPE_FLOAT => {
+                    let scalar = Type::Scalar {
+                        width: operand(1)?,
+                        numeric_type: NumericType::Float,
+                    };
+                    types.insert(operand(0)?, scalar);
+                }
+                OP_TYPE_VECTOR => {
+                    let vector = Type::Vector {
+                        component: operand(1)?,
+                        count: operand(2)?,
+                    };
+                    types.insert(operand(0)?, vector);
+                }
+                OP_TYPE_MATRIX => {
+                    types.insert(
+                        operand(0)?,
+                        Type::Matrix {
+                            columns: operand(2)?,
+                        },
+                    );
+                }
+                OP_TYPE_IMAGE => {
+                    let image = Type::Image {
+                        dim: operand(2)?,
+                        sampled: operand(6)?,
+                    };
+                    types.insert(operand(0)?, image);
+                }
+                OP_TYPE_SAMPLER => {
+                    types.insert(operand(0)?, Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    types.insert(operand(0)?, Type::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    let array = Type::Array {
+                        element: operand(1)?,
+                        length: operand(2)?,
+                    };
+                    types.insert(operand(0)?, array);
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    types.insert(
+                        operand(0)?,
+                        Type::RuntimeArray {
+                            element: operand(1)?,
+                        },
+                    );
+                }
+                OP_TYPE_STRUCT => {
+                    let members = operands.get(1..).unwrap_or(&[]).to_vec();
+                    types.insert(operand(0)?, Type::Struct { members });
+                }
+                OP_TYPE_POINTER => {
+                    let pointer = Type::Pointer {
+                        storage_class: operand(1)?,
+                        pointee: operand(2)?,
+                    };
+                    types.insert(operand(0)?, pointer);
+                }
+                OP_CONSTANT => {
+                    constants.insert(operand(1)?, operand(2)?);
+                }
+                OP_SPEC_CONSTANT_TRUE..=OP_SPEC_CONSTANT_OP => {
+                    spec_constants.insert(operand(1)?);
+                }
+                OP_VARIABLE => {
+                    variables.push((operand(0)?, operand(1)?, operand(2)?));
+                }
+                OP_DECORATE => {
+                    let value = operands.get(2).copied().unwrap_or(0);
+                    decorations.insert((operand(0)?, operand(1)?), value);
+                }
+                OP_MEMBER_DECORATE => {
+                    let value = operands.get(3).copied().unwrap_or(0);
+                    member_decorations.insert((operand(0)?, operand(1)?, operand(2)?), value);
+                }
+                _ => {}
+            }
+        }
+
+        let stage = stage.ok_or(ReflectError::UnknownStage)?;
+        let module = Module {
+            types,
+            constants,
+            spec_constants,
+            decorations,
+            member_decorations,
+        };
+
+        let mut bindings = Vec::new();
+        let mut push_constants = None;
+        let mut vertex_inputs = Vec::new();
+        for (pointer_type, id, storage_class) in variables {
+            let pointee = match module.types.get(&pointer_type) {
+                Some(Type::Pointer { pointee, .. }) => *pointee,
+                _ => return Err(ReflectError::InvalidModule),
+            };
+            match storage_class {
+                STORAGE_CLASS_UNIFORM_CONSTANT
+                | STORAGE_CLASS_UNIFORM
+                | STORAGE_CLASS_STORAGE_BUFFER => {
+                    if let Some(binding) = module.descriptor_binding(id, pointee, storage_class)? {
+                        bindings.push(binding);
+                    }
+                }
+                STORAGE_CLASS_PUSH_CONSTANT => {
+                    if module.has_specialized_array(pointee) {
+                        return Err(ReflectError::SpecializedPushConstantSize);
+                    }
+                    push_constants = module.push_constant_range(pointee, stage);
+                }
+                STORAGE_CLASS_INPUT if stage == vk::ShaderStageFlags::VERTEX => {
+                    let is_built_in = module.decorations.contains_key(&(id, DECORATION_BUILT_IN));
+                    let location = module.decorations.get(&(id, DECORATION_LOCATION));
+                    let numeric_type = module.numeric_type(pointee);
+                    if let (false, Some(&location), Some(numeric_type)) =
+                        (is_built_in, location, numeric_type)
+                    {
+                        vertex_inputs.push(VertexInput {
+                            location,
+                            numeric_type,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        vertex_inputs.sort_by_key(|input| input.location);
+
+        Ok(ShaderReflection {
+            stage,
+            bindings,
+            push_constants,
+            vertex_inputs,
+        })
+    }
+}
+
+impl ShaderReflection {
+    /// Check that `attributes` provide all the inputs of the vertex shader.
+    ///
+    /// The number of components may differ since missing ones are filled by the
+    /// device. Attributes with a format unknown to `format_numeric_type` are only
+    /// checked for presence.
+    pub fn validate_vertex_input(
+        &self,
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Result<(), ReflectError> {
+        for input in &self.vertex_inputs {
+            let attribute = attributes
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+                .ok_or(ReflectError::MissingVertexAttribute(input.location))?;
+            match format_numeric_type(attribute.format) {
+                Some(found) if found != input.numeric_type => {
+                    return Err(ReflectError::VertexAttributeMismatch {
+                        location: input.location,
+                        expected: input.numeric_type,
+                        found,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reflect the interface of the shader at `path`, in the assets.
+///
+/// # Panics
+///
+/// Panic if the shader cannot be reflected.
+pub fn reflect_shader(path: &str) -> ShaderReflection {
+    let code = ash::util::read_spv(&mut fs::load(path)).unwrap();
+    ShaderReflection::new(&code)
+        .unwrap_or_else(|error| panic!("Failed to reflect shader {}: {}", path, error))
+}
+
+/// Create the layout of descriptor set `set` used by `shaders`.
+pub fn create_set_layout(
+    device: &Device,
+    shaders: &[ShaderReflection],
+    set: u32,
+) -> vk::DescriptorSetLayout {
+    let bindings = set_layout_bindings(shaders, set);
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&bindings)
+        .build();
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    }
+}
+
+/// Build the layout bindings of descriptor set `set` used by `shaders`.
+///
+/// The stages of a binding are all the stages using it.
+pub fn set_layout_bindings(
+    shaders: &[ShaderReflection],
+    set: u32,
+) -> Vec<vk::DescriptorSetLayoutBinding> {
+    let mut bindings = Vec::<vk::DescriptorSetLayoutBinding>::new();
+    for shader in shaders {
+        for binding in shader.bindings.iter().filter(|binding| binding.set == set) {
+            match bindings
+                .iter_mut()
+                .find(|layout_binding| layout_binding.binding == binding.binding)
+            {
+                Some(layout_binding) => layout_binding.stage_flags |= shader.stage,
+                None => bindings.push(
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(binding.binding)
+                        .descriptor_type(binding.descriptor_type)
+                        .descriptor_count(binding.count)
+                        .stage_flags(shader.stage)
+                        .build(),
+                ),
+            }
+        }
+    }
+    bindings.sort_by_key(|binding| binding.binding);
+    bindings
+}
+
+/// Get the push constant ranges of the pipeline made of `shaders`.
+///
+/// The ranges of the shaders of the same stage are merged since a stage can
+/// only be part of one range.
+pub fn push_constant_ranges(shaders: &[ShaderReflection]) -> Vec<vk::PushConstantRange> {
+    let mut ranges = Vec::<vk::PushConstantRange>::new();
+    for range in shaders.iter().filter_map(|shader| shader.push_constants) {
+        match ranges
+            .iter_mut()
+            .find(|merged| merged.stage_flags == range.stage_flags)
+        {
+            Some(merged) => {
+                let end = (merged.offset + merged.size).max(range.offset + range.size);
+                merged.offset = merged.offset.min(range.offset);
+                merged.size = end - merged.offset;
+            }
+            None => ranges.push(range),
+        }
+    }
+    ranges
+}
+
+/// Declarations of a module needed to reflect its variables.
+struct Module {
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Ids of the specialization constants, whose values are not known.
+    spec_constants: HashSet<u32>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+}
+
+impl Module {
+    /// Get the binding of the descriptor `variable`.
+    ///
+    /// Return `None` if the variable is not bound to a descriptor.
+    fn descriptor_binding(
+        &self,
+        variable: u32,
+        pointee: u32,
+        storage_class: u32,
+    ) -> Result<Option<DescriptorBinding>, ReflectError> {
+        let set = self.decorations.get(&(variable, DECORATION_DESCRIPTOR_SET));
+        let binding = self.decorations.get(&(variable, DECORATION_BINDING));
+        let (set, binding, pointee_type) = match (set, binding, self.types.get(&pointee)) {
+            (Some(&set), Some(&binding), Some(pointee_type)) => (set, binding, pointee_type),
+            _ => return Ok(None),
+        };
+
+        let (element, count) = match pointee_type {
+            Type::Array { element, length } => match self.constants.get(length) {
+                Some(&length) => (*element, length),
+                None => return Err(ReflectError::SpecializedDescriptorCount { set, binding }),
+            },
+            Type::RuntimeArray { element } => (*element, 0),
+            _ => (pointee, 1),
+        };
+        let element_type = match self.types.get(&element) {
+            Some(element_type) => element_type,
+            None => return Ok(None),
+        };
+        let descriptor_type = match (storage_class, element_type) {
+            (STORAGE_CLASS_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_CLASS_UNIFORM, _) => {
+                if self
+                    .decorations
+                    .contains_key(&(element, DECORATION_BUFFER_BLOCK))
+                {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                }
+            }
+            (_, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (_, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (_, Type::Image { dim, sampled }) => match (*dim, *sampled) {
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            _ => return Ok(None),
+        };
+
+        Ok(Some(DescriptorBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+        }))
+    }
+
+    /// Check whether the type `id` has an array sized by a specialization constant.
+    fn has_specialized_array(&self, id: u32) -> bool {
+        match self.types.get(&id) {
+            Some(Type::Array { element, length }) => {
+                self.spec_constants.contains(length) || self.has_specialized_array(*element)
+            }
+            Some(Type::Struct { members }) => members
+                .iter()
+                .any(|member| self.has_specialized_array(*member)),
+            _ => false,
+        }
+    }
+
+    /// Get the range covered by the members of the push constant block `block`.
+    fn push_constant_range(
+        &self,
+        block: u32,
+        stage: vk::ShaderStageFlags,
+    ) -> Option<vk::PushConstantRange> {
+        let members = match self.types.get(&block)? {
+            Type::Struct { members } => members,
+            _ => return None,
+        };
+        let (start, end) = members
+            .iter()
+            .enumerate()
+            .filter_map(|(index, member)| {
+                let offset =
+                    *self
+                        .member_decorations
+                        .get(&(block, index as _, DECORATION_OFFSET))?;
+                let matrix_stride =
+                    self.member_decorations
+                        .get(&(block, index as _, DECORATION_MATRIX_STRIDE));
+                let size = self.size(*member, matrix_stride.copied())?;
+                Some((offset, offset + size))
+            })
+            .fold(None, |range, (start, end)| match range {
+                None => Some((start, end)),
+                Some((range_start, range_end)) => {
+                    Some((start.min(range_start), end.max(range_end)))
+                }
+            })?;
+        Some(vk::PushConstantRange {
+            stage_flags: stage,
+            offset: start,
+            size: end - start,
+        })
+    }
+
+    /// Get the size in bytes of a value of type `id` in a block.
+    fn size(&self, id: u32, matrix_stride: Option<u32>) -> Option<u32> {
+        match self.types.get(&id)? {
+            Type::Scalar { width, .. } => Some(width / 8),
+            Type::Vector { component, count } => Some(self.size(*component, None)? * count),
+            Type::Matrix { columns } => Some(matrix_stride? * columns),
+            Type::Array { length, .. } => {
+                let stride = *self.decorations.get(&(id, DECORATION_ARRAY_STRIDE))?;
+                Some(stride * self.constants.get(length)?)
+            }
+            Type::Struct { members } => {
+                let last = members.len().checked_sub(1)?;
+                let offset = *self
+                    .member_decorations
+                    .get(&(id, last as _, DECORATION_OFFSET))?;
+                let matrix_stride = self
+                    .member_decorations
+                    .get(&(id, last as _, DECORATION_MATRIX_STRIDE))
+                    .copied();
+                Some(offset + self.size(members[last], matrix_stride)?)
+            }
+            Type::Pointer {
+                storage_class: STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER,
+                ..
+            } => Some(8),
+            _ => None,
+        }
+    }
+
+    /// Get the numeric type of the components of a vertex input of type `id`.
+    fn numeric_type(&self, id: u32) -> Option<NumericType> {
+        match self.types.get(&id)? {
+            Type::Scalar { numeric_type, .. } => Some(*numeric_type),
+            Type::Vector { component, .. } => self.numeric_type(*component),
+            _ => None,
+        }
+    }
+}
+
+fn execution_model_stage(execution_model: u32) -> Result<vk::ShaderStageFlags, ReflectError> {
+    match execution_model {
+        0 => Ok(vk::ShaderStageFlags::VERTEX),
+        1 => Ok(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+        2 => Ok(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+        3 => Ok(vk::ShaderStageFlags::GEOMETRY),
+        4 => Ok(vk::ShaderStageFlags::FRAGMENT),
+        5 => Ok(vk::ShaderStageFlags::COMPUTE),
+        5267 => Ok(vk::ShaderStageFlags::TASK_NV),
+        5268 => Ok(vk::ShaderStageFlags::MESH_NV),
+        // The EXT mesh shader stages have the same flags as the NV ones
+        5364 => Ok(vk::ShaderStageFlags::TASK_NV),
+        5365 => Ok(vk::ShaderStageFlags::MESH_NV),
+        _ => Err(ReflectError::UnknownStage),
+    }
+}
+
+/// Get the numeric type of the common vertex attribute formats.
+fn format_numeric_type(format: vk::Format) -> Option<NumericType> {
+    match format {
+        vk::Format::R32_SFLOAT
+        | vk::Format::R32G32_SFLOAT
+        | vk::Format::R32G32B32_SFLOAT
+        | vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM => Some(NumericType::Float),
+        vk::Format::R32_SINT
+        | vk::Format::R32G32_SINT
+        | vk::Format::R32G32B32_SINT
+        | vk::Format::R32G32B32A32_SINT => Some(NumericType::Int),
+        vk::Format::R32_UINT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32B32_UINT
+        | vk::Format::R32G32B32A32_UINT => Some(NumericType::Uint),
+        _ => None,
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{
+    camera::Camera,
+    material::MaterialInstance,
+    math::{Aabb, Frustum, Transform},
+    scene::{Entity, Layers, Light, LightKind, MeshRenderer, Scene},
+};
+use cgmath::{prelude::*, Matrix4, Point3, Rad, Vector3};
+
+/// Key ordering the draws so the state changes between them are minimized.
+///
+/// The opaque draws come first, sorted by pipeline, then material, then mesh,
+/// so the draws sharing a pipeline and a material follow each other. The
+/// transparent draws come last, sorted back to front by their view depth
+/// since their blending depends on the order, then by pipeline and material.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DrawKey(u64);
+
+impl DrawKey {
+    const TRANSPARENT_BIT: u64 = 1 << 63;
+
+    /// Create the key of an opaque draw.
+    ///
+    /// Only the low 24 bits of `material` and the low 23 bits of `mesh` are used.
+    pub fn opaque(pipeline: u16, material: u32, mesh: u32) -> Self {
+        DrawKey(
+            (pipeline as u64) << 47
+                | (material as u64 & 0xff_ffff) << 23
+                | (mesh as u64 & 0x7f_ffff),
+        )
+    }
+
+    /// Create the key of a transparent draw at `depth` from the camera.
+    ///
+    /// Only the low 16 bits of `material` are used.
+    pub fn transparent(depth: f32, pipeline: u16, material: u32) -> Self {
+        // The bits of a positive float are ordered like the float, so inverting
+        // them sorts the furthest draws first
+        let depth = !depth.max(0.0).to_bits() as u64 & 0x7fff_ffff;
+        DrawKey(
+            Self::TRANSPARENT_BIT
+                | depth << 32
+                | (pipeline as u64) << 16
+                | (material as u64 & 0xffff),
+        )
+    }
+}
+
+/// Mesh to draw with the transform it had when the scene was extracted.
+#[derive(Clone, Copy, Debug)]
+pub struct DrawItem {
+    pub entity: Entity,
+    pub model: Matrix4<f32>,
+    pub key: DrawKey,
+    /// Index of the pipeline drawing the mesh.
+    pub pipeline: u16,
+    /// Index of the geometry of the mesh.
+    pub mesh: u32,
+    pub index_count: u32,
+    pub material: MaterialInstance,
+    /// World space bounds of the mesh.
+    pub bounds: Aabb,
+    pub layers: Layers,
+}
+
+/// Pass drawing the scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// Views of the cameras.
+    Main,
+    /// Shadow maps of the lights.
+    Shadow,
+    /// Reflections of the water, the mirror and the reflection probes.
+    Reflection,
+}
+
+/// Layers drawn by each pass.
+///
+/// By default the shadows only have the geometry of the scene, and the
+/// reflections everything but the editor helpers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassLayers {
+    pub main: Layers,
+    pub shadow: Layers,
+    pub reflection: Layers,
+}
+
+impl PassLayers {
+    pub fn get(&self, pass: Pass) -> Layers {
+        match pass {
+            Pass::Main => self.main,
+            Pass::Shadow => self.shadow,
+            Pass::Reflection => self.reflection,
+        }
+    }
+
+    pub fn get_mut(&mut self, pass: Pass) -> &mut Layers {
+        match pass {
+            Pass::Main => &mut self.main,
+            Pass::Shadow => &mut self.shadow,
+            Pass::Reflection => &mut self.reflection,
+        }
+    }
+
+    /// Whether `pass` draws something of `layers`.
+    pub fn draws(&self, pass: Pass, layers: Layers) -> bool {
+        self.get(pass).intersects(layers)
+    }
+}
+
+impl Default for PassLayers {
+    fn default() -> Self {
+        PassLayers {
+            main: Layers::ALL,
+            shadow: Layers::ALL.without(Layers::BACKGROUND | Layers::GIZMOS),
+            reflection: Layers::ALL.without(Layers::GIZMOS),
+        }
+    }
+}
+
+/// Spot light with the transform it had when the scene was extracted.
+#[derive(Clone, Copy, Debug)]
+pub struct SpotLightItem {
+    pub entity: Entity,
+    pub position: Point3<f32>,
+    /// Axis of the cone, pointing away from the light.
+    pub direction: Vector3<f32>,
+    pub range: f32,
+    /// Half angle of the cone.
+    pub angle: Rad<f32>,
+    pub softness: f32,
+    pub shadow_samples: u32,
+}
+
+/// Get the spot lights of `scene`, placed by the transform of their entity.
+pub fn extract_spot_lights(scene: &Scene) -> Vec<SpotLightItem> {
+    scene
+        .iter::<Light>()
+        .filter_map(|(entity, light)| match light.kind {
+            LightKind::Spot { range, angle } => {
+                let transform = scene.get::<Transform>(entity);
+                Some(SpotLightItem {
+                    entity,
+                    position: transform.map_or_else(Point3::origin, |transform| {
+                        Point3::from_vec(transform.translation)
+                    }),
+                    direction: transform.map_or(-Vector3::unit_y(), |transform| {
+                        transform.rotation.rotate_vector(-Vector3::unit_y())
+                    }),
+                    range,
+                    angle,
+                    softness: light.softness,
+                    shadow_samples: light.shadow_samples,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Snapshot of the scene state needed to render a frame.
+///
+/// It is extracted from the scene once per frame, after the scene is updated
+/// and before the uniform buffers are written. Rendering only reads the list
+/// so the scene can change while a frame is being prepared.
+#[derive(Clone, Debug)]
+pub struct RenderList {
+    pub view: Matrix4<f32>,
+    pub proj: Matrix4<f32>,
+    /// Frustum of `proj * view`, used to cull what is drawn.
+    pub frustum: Frustum,
+    pub camera_position: Point3<f32>,
+    /// Direction pointing towards the sun.
+    pub sun_direction: Vector3<f32>,
+    /// Softness of the shadows of the sun.
+    pub sun_softness: f32,
+    /// Number of rays traced towards the sun by the ray query shadows.
+    pub sun_shadow_samples: u32,
+    pub light_count: u32,
+    pub spot_lights: Vec<SpotLightItem>,
+    /// Draws sorted by their key.
+    pub draws: Vec<DrawItem>,
+}
+
+impl RenderList {
+    /// Extract the meshes of `scene` seen from `camera` with `view` and `proj`,
+    /// and the direction of the `sun` light.
+    pub fn extract(
+        scene: &Scene,
+        camera: Entity,
+        sun: Entity,
+        (view, proj): (Matrix4<f32>, Matrix4<f32>),
+    ) -> Self {
+        let mut draws = scene
+            .meshes()
+            .map(|(entity, transform, mesh)| {
+                let model = transform.matrix();
+                DrawItem {
+                    entity,
+                    model,
+                    key: draw_key(mesh, view * model),
+                    pipeline: mesh.pipeline,
+                    mesh: mesh.mesh,
+                    index_count: mesh.index_count,
+                    material: mesh.material,
+                    bounds: mesh.collision_mesh.bounds().transform(model),
+                    layers: mesh.layers,
+                }
+            })
+            .collect::<Vec<_>>();
+        draws.sort_by_key(|draw| draw.key);
+        RenderList {
+            view,
+            proj,
+            frustum: Frustum::from_view_proj(proj * view),
+            camera_position: scene.get::<Camera>(camera).unwrap().position(),
+            sun_direction: scene.get::<Light>(sun).unwrap().direction,
+            sun_softness: scene.get::<Light>(sun).unwrap().softness,
+            sun_shadow_samples: scene.get::<Light>(sun).unwrap().shadow_samples,
+            light_count: scene.iter::<Light>().count() as _,
+            spot_lights: extract_spot_lights(scene),
+            draws,
+        }
+    }
+
+    /// Get the draw of `entity` if it is part of the list.
+    pub fn draw(&self, entity: Entity) -> Option<&DrawItem> {
+        self.draws.iter().find(|draw| draw.entity == entity)
+    }
+
+    /// Get the draws with one of `layers`, in the order of their key.
+    pub fn draws_in(&self, layers: Layers) -> impl Iterator<Item = &DrawItem> {
+        self.draws
+            .iter()
+            .filter(move |draw| draw.layers.intersects(layers))
+    }
+}
+
+fn draw_key(mesh: &MeshRenderer, model_view: Matrix4<f32>) -> DrawKey {
+    let material = mesh.material.index();
+    if mesh.transparent {
+        // The camera looks towards -z in view space
+        DrawKey::transparent(-model_view.w.z, mesh.pipeline, material)
+    } else {
+        DrawKey::opaque(mesh.pipeline, material, mesh.mesh)
+    }
+}
+
+impl Default for RenderList {
+    fn default() -> Self {
+        RenderList {
+            view: Matrix4::identity(),
+            proj: Matrix4::identity(),
+            frustum: Frustum::from_view_proj(Matrix4::identity()),
+            camera_position: Point3::origin(),
+            sun_direction: Vector3::unit_y(),
+            sun_softness: 0.0,
+            sun_shadow_samples: 1,
+            light_count: 0,
+            spot_lights: Vec::new(),
+            draws: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use crate::gpu_device::Device;
+use crate::texture::{Texture, TextureDesc};
+use ash::{version::DeviceV1_0, vk};
+
+/// Formats of the attachments of a render target.
+#[derive(Clone, Debug)]
+pub struct RenderTargetDesc {
+    pub color_formats: Vec<vk::Format>,
+    pub depth_format: Option<vk::Format>,
+    /// Keep the depth so it can be sampled after the pass, it is discarded otherwise.
+    pub sample_depth: bool,
+}
+
+impl RenderTargetDesc {
+    /// Describe the color textures of a target of `extent`.
+    ///
+    /// They can also be copied from, like the history of the reflections.
+    pub fn color_texture_descs(&self, extent: vk::Extent2D) -> Vec<TextureDesc> {
+        self.color_formats
+            .iter()
+            .map(|format| {
+                TextureDesc::new_2d(
+                    extent,
+                    *format,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC,
+                )
+            })
+            .collect()
+    }
+
+    /// Describe the depth texture of a target of `extent`.
+    pub fn depth_texture_desc(&self, extent: vk::Extent2D) -> Option<TextureDesc> {
+        let usage = if self.sample_depth {
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+        } else {
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+        };
+        self.depth_format
+            .map(|format| TextureDesc::new_2d(extent, format, usage))
+    }
+}
+
+/// Offscreen images rendered by a render pass then sampled by later passes.
+///
+/// The target owns its textures, render pass and framebuffer. Color textures
+/// and the sampled depth are left in a read only layout at the end of the pass
+/// and each has a linear clamped sampler.
+pub struct RenderTarget {
+    desc: RenderTargetDesc,
+    extent: vk::Extent2D,
+    colors: Vec<Texture>,
+    depth: Option<Texture>,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+}
+
+impl RenderTarget {
+    /// Create the render pass and the framebuffer of the target.
+    ///
+    /// `colors` and `depth` must be created from `desc.color_texture_descs(extent)`
+    /// and `desc.depth_texture_desc(extent)`.
+    pub fn new(
+        device: &Device,
+        desc: RenderTargetDesc,
+        extent: vk::Extent2D,
+        colors: Vec<Texture>,
+        depth: Option<Texture>,
+    ) -> Self {
+        let render_pass = Self::create_render_pass(device, &desc);
+        let mut target = RenderTarget {
+            desc,
+            extent,
+            colors: Vec::new(),
+            depth: None,
+            render_pass,
+            framebuffer: vk::Framebuffer::null(),
+        };
+        target.set_textures(device, extent, colors, depth);
+        target
+    }
+
+    fn create_render_pass(device: &Device, desc: &RenderTargetDesc) -> vk::RenderPass {
+        let mut attachment_descs = desc
+            .color_formats
+            .iter()
+            .map(|format| {
+                vk::AttachmentDescription::builder()
+                    .format(*format)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        if let Some(format) = desc.depth_format {
+            let (store_op, final_layout) = if desc.sample_depth {
+                (
+                    vk::AttachmentStoreOp::STORE,
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                )
+            } else {
+                (
+                    vk::AttachmentStoreOp::DONT_CARE,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                )
+            };
+            attachment_descs.push(
+                vk::AttachmentDescription::builder()
+                    .format(format)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(store_op)
+                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(final_layout)
+                    .build(),
+            );
+        }
+
+        let color_attachment_refs = (0..desc.color_formats.len())
+            .map(|index| {
+                vk::AttachmentReference::builder()
+                    .attachment(index as _)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(desc.color_formats.len() as _)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let mut subpass_desc = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs);
+        if desc.depth_format.is_some() {
+            subpass_desc = subpass_desc.depth_stencil_attachment(&depth_attachment_ref);
+        }
+        let subpass_descs = [subpass_desc.build()];
+
+        // The previous frame might still be sampling the target or writing its depth.
+        let begin_dep = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build();
+        // Later passes sample the target once it is rendered.
+        let end_dep = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+        let subpass_deps = [begin_dep, end_dep];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descs)
+            .subpasses(&subpass_descs)
+            .dependencies(&subpass_deps)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    /// Give the samplers to the textures and create the framebuffer.
+    fn set_textures(
+        &mut self,
+        device: &Device,
+        extent: vk::Extent2D,
+        mut colors: Vec<Texture>,
+        mut depth: Option<Texture>,
+    ) {
+        colors
+            .iter_mut()
+            .for_each(|color| color.sampler = Some(Self::create_sampler(device)));
+        if let Some(depth) = depth.as_mut().filter(|_| self.desc.sample_depth) {
+            depth.sampler = Some(Self::create_sampler(device));
+        }
+
+        let attachments = colors
+            .iter()
+            .chain(depth.iter())
+            .map(|texture| texture.view)
+            .collect::<Vec<_>>();
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1)
+            .build();
+        self.framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() };
+        self.extent = extent;
+        self.colors = colors;
+        self.depth = depth;
+    }
+}
+
+impl RenderTarget {
+    pub fn desc(&self) -> &RenderTargetDesc {
+        &self.desc
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    /// Get the color texture `index` with its sampler.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the pass.
+    pub fn color(&self, index: usize) -> Texture {
+        self.colors[index]
+    }
+
+    /// Get the depth texture.
+    ///
+    /// It has a sampler and is in the `DEPTH_STENCIL_READ_ONLY_OPTIMAL` layout
+    /// after the pass if the depth is sampled.
+    #[allow(dead_code)]
+    pub fn depth(&self) -> Option<Texture> {
+        self.depth
+    }
+
+    /// Replace the textures by `colors` and `depth` of `extent`.
+    ///
+    /// They must be created like the textures given to `new`. The previous
+    /// textures are destroyed so the target must not be in use, and the
+    /// descriptor sets sampling them must be updated.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        extent: vk::Extent2D,
+        colors: Vec<Texture>,
+        depth: Option<Texture>,
+    ) {
+        self.destroy_textures(device);
+        self.set_textures(device, extent, colors, depth);
+    }
+
+    /// Begin the render pass of the target in `command_buffer`, clearing it.
+    ///
+    /// Colors are cleared to opaque black and the depth to 1.
+    pub fn cmd_begin(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let mut clear_values = vec![
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            };
+            self.colors.len()
+        ];
+        if self.depth.is_some() {
+            clear_values.push(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            });
+        }
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&clear_values)
+            .build();
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            )
+        };
+    }
+
+    pub fn cmd_end(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+    }
+
+    fn destroy_textures(&mut self, device: &Device) {
+        unsafe { device.destroy_framebuffer(self.framebuffer, None) };
+        self.colors
+            .iter_mut()
+            .chain(self.depth.iter_mut())
+            .for_each(|texture| texture.destroy(device));
+        self.framebuffer = vk::Framebuffer::null();
+        self.colors.clear();
+        self.depth = None;
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_textures(device);
+        unsafe { device.destroy_render_pass(self.render_pass, None) };
+    }
+}
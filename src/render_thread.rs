@@ -0,0 +1,277 @@
+use crate::{
+    gpu_device::Device, present_transfer::PresentAcquire, profiler::profile_scope,
+    render_list::RenderList, submit_batch::SubmitBatch,
+};
+use ash::{extensions::khr::Swapchain, prelude::VkResult, vk};
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+};
+
+/// Frame prepared by the main thread, to submit and present on the render thread.
+pub struct RenderFrame {
+    /// Scene state the frame was prepared from.
+    ///
+    /// The render thread keeps it until the frame is presented, while the main
+    /// thread extracts the next frame into its own list.
+    pub render_list: RenderList,
+    /// Commands of the frame, waiting for the swapchain image to be acquired
+    /// and signaling `render_finished`.
+    pub batch: SubmitBatch,
+    /// Semaphore signaled when the frame is rendered.
+    pub render_finished: vk::Semaphore,
+    /// Acquisition of the image by the present queue, if it is of another
+    /// queue family, submitted once the frame is rendered.
+    pub present_acquire: Option<PresentAcquire>,
+    pub swapchain: vk::SwapchainKHR,
+    pub image_index: u32,
+}
+
+/// Swapchain image acquired by the render thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcquiredImage {
+    Image(u32),
+    /// The swapchain is suboptimal or out of date and must be recreated.
+    OutOfDate,
+}
+
+enum Request {
+    /// Acquire the next image of the swapchain, signaling the semaphore.
+    Acquire(vk::SwapchainKHR, vk::Semaphore),
+    Present(Box<RenderFrame>),
+}
+
+/// The two slots exchanged between the main thread and the render thread.
+#[derive(Default)]
+struct Slots {
+    /// Request of the main thread not yet taken by the render thread.
+    request: Option<Request>,
+    /// Image acquired by the render thread not yet taken by the main thread.
+    acquired: Option<VkResult<AcquiredImage>>,
+    /// Set while the render thread handles a request.
+    busy: bool,
+    stop: bool,
+}
+
+/// Slots with the condition notified whenever one of them changes.
+#[derive(Default)]
+struct Exchange {
+    slots: Mutex<Slots>,
+    changed: Condvar,
+}
+
+impl Exchange {
+    /// Wait until `condition` holds for the slots and lock them.
+    fn wait_until<F: Fn(&Slots) -> bool>(&self, condition: F) -> MutexGuard<'_, Slots> {
+        let mut slots = self.slots.lock().unwrap();
+        while !condition(&slots) {
+            slots = self.changed.wait(slots).unwrap();
+        }
+        slots
+    }
+}
+
+/// Thread owning the queues, acquiring the swapchain images, submitting the
+/// frames and presenting them.
+///
+/// The main thread updates the scene and extracts it into a `RenderList`,
+/// gets the image acquired by the render thread, writes the uniform buffers
+/// of the frame, then hands the frame over and moves on to the next one while
+/// it is submitted and presented. Each slot of the exchange holds a single
+/// value so the main thread is never more than one frame ahead.
+///
+/// The render thread only uses the queues and the swapchain while it handles
+/// a request, so the main thread can use them between `acquire` and `present`.
+/// Present errors are kept until the next `acquire`, which reports them.
+pub struct RenderThread {
+    exchange: Arc<Exchange>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RenderThread {
+    /// Start the thread submitting to `graphics_queue` and presenting with `present_queue`.
+    pub fn new(
+        instance: &ash::Instance,
+        device: &Device,
+        (graphics_queue, present_queue): (vk::Queue, vk::Queue),
+    ) -> Self {
+        let device = device.clone();
+        let swapchain = Swapchain::new(instance, device.raw());
+        let exchange = Arc::new(Exchange::default());
+        let thread_exchange = Arc::clone(&exchange);
+        let handle = thread::Builder::new()
+            .name("render".to_owned())
+            .spawn(move || {
+                log::debug!("Render thread started.");
+                let exchange = thread_exchange;
+                // Swapchain presented to last and the result, until the next acquire
+                let mut last_present = None;
+                loop {
+                    let request = {
+                        let mut slots =
+                            exchange.wait_until(|slots| slots.request.is_some() || slots.stop);
+                        match slots.request.take() {
+                            Some(request) => {
+                                slots.busy = true;
+                                request
+                            }
+                            None => break,
+                        }
+                    };
+                    exchange.changed.notify_all();
+
+                    let acquired = match request {
+                        Request::Acquire(swapchain_khr, semaphore) => Some(acquire_next_image(
+                            &swapchain,
+                            (swapchain_khr, semaphore),
+                            last_present.take(),
+                        )),
+                        Request::Present(frame) => {
+                            let swapchain_khr = frame.swapchain;
+                            let result = submit_and_present(
+                                &device,
+                                &swapchain,
+                                (graphics_queue, present_queue),
+                                *frame,
+                            );
+                            last_present = Some((swapchain_khr, result));
+                            None
+                        }
+                    };
+
+                    let mut slots = exchange.slots.lock().unwrap();
+                    if acquired.is_some() {
+                        slots.acquired = acquired;
+                    }
+                    slots.busy = false;
+                    drop(slots);
+                    exchange.changed.notify_all();
+                }
+                log::debug!("Render thread stopped.");
+            })
+            .unwrap();
+
+        RenderThread {
+            exchange,
+            handle: Some(handle),
+        }
+    }
+
+    /// Acquire the next image of `swapchain` once the previous frame is presented.
+    ///
+    /// `semaphore` is signaled when the image can be rendered to.
+    ///
+    /// # Errors
+    ///
+    /// Return the error of the presentation of the previous frame, or of the
+    /// acquisition, like `ERROR_DEVICE_LOST`.
+    pub fn acquire(
+        &self,
+        swapchain: vk::SwapchainKHR,
+        semaphore: vk::Semaphore,
+    ) -> VkResult<AcquiredImage> {
+        profile_scope!("acquire");
+        self.send(Request::Acquire(swapchain, semaphore));
+        let mut slots = self.exchange.wait_until(|slots| slots.acquired.is_some());
+        slots.acquired.take().unwrap()
+    }
+
+    /// Hand `frame` over to be submitted and presented.
+    ///
+    /// It only blocks while the previous request has not been taken by the
+    /// render thread.
+    pub fn present(&self, frame: RenderFrame) {
+        self.send(Request::Present(Box::new(frame)));
+    }
+
+    /// Wait for the render thread to handle the requests it was sent.
+    ///
+    /// It is only needed before waiting for the device to be idle, since the
+    /// frames in flight are submitted by the render thread.
+    pub fn finish(&self) {
+        profile_scope!("finish_render_thread");
+        let _slots = self
+            .exchange
+            .wait_until(|slots| slots.request.is_none() && !slots.busy);
+    }
+
+    fn send(&self, request: Request) {
+        let mut slots = self.exchange.wait_until(|slots| slots.request.is_none());
+        slots.request = Some(request);
+        drop(slots);
+        self.exchange.changed.notify_all();
+    }
+}
+
+impl Drop for RenderThread {
+    /// Stop the thread once the frames already sent are presented.
+    fn drop(&mut self) {
+        self.finish();
+        self.exchange.slots.lock().unwrap().stop = true;
+        self.exchange.changed.notify_all();
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+    }
+}
+
+/// Acquire the next image of `swapchain_khr` unless presenting the previous
+/// frame to it failed or found it out of date.
+fn acquire_next_image(
+    swapchain: &Swapchain,
+    (swapchain_khr, semaphore): (vk::SwapchainKHR, vk::Semaphore),
+    last_present: Option<(vk::SwapchainKHR, VkResult<bool>)>,
+) -> VkResult<AcquiredImage> {
+    match last_present {
+        Some((_, Err(error))) => return Err(error),
+        // A swapchain recreated since is not out of date
+        Some((presented, Ok(true))) if presented == swapchain_khr => {
+            return Ok(AcquiredImage::OutOfDate)
+        }
+        _ => {}
+    }
+    let result = unsafe {
+        swapchain.acquire_next_image(swapchain_khr, u64::MAX, semaphore, vk::Fence::null())
+    };
+    match result {
+        Ok((image_index, _)) => Ok(AcquiredImage::Image(image_index)),
+        Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(AcquiredImage::OutOfDate),
+        Err(error) => Err(error),
+    }
+}
+
+/// Submit `frame` and present its image.
+///
+/// Return `Ok(true)` when the swapchain is suboptimal or out of date.
+fn submit_and_present(
+    device: &Device,
+    swapchain: &Swapchain,
+    (graphics_queue, present_queue): (vk::Queue, vk::Queue),
+    frame: RenderFrame,
+) -> VkResult<bool> {
+    {
+        profile_scope!("submit");
+        log::trace!(
+            "Submitting frame with {} draws.",
+            frame.render_list.draws.len()
+        );
+        unsafe { frame.batch.submit(device, graphics_queue)? };
+    }
+
+    let signal_semaphores = match frame.present_acquire {
+        Some(acquire) => [unsafe { acquire.submit(device, present_queue, frame.render_finished)? }],
+        None => [frame.render_finished],
+    };
+    let swapchains = [frame.swapchain];
+    let images_indices = [frame.image_index];
+    let present_info = vk::PresentInfoKHR::builder()
+        .wait_semaphores(&signal_semaphores)
+        .swapchains(&swapchains)
+        .image_indices(&images_indices)
+        .build();
+    match unsafe { swapchain.queue_present(present_queue, &present_info) } {
+        Ok(is_suboptimal) => Ok(is_suboptimal),
+        Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(true),
+        Err(error) => Err(error),
+    }
+}
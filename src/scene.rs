@@ -0,0 +1,298 @@
+use crate::{
+    camera::Camera,
+    material::MaterialInstance,
+    math::{Aabb, CollisionMesh, Ray, RayHit, Transform},
+};
+use cgmath::{Rad, Vector3};
+use std::{ops::BitOr, sync::Arc};
+
+/// Handle of an entity of the scene.
+///
+/// An entity is only an index in the storages of the components.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Entity(u32);
+
+/// Mesh drawn at the transform of its entity.
+pub struct MeshRenderer {
+    pub index_count: u32,
+    /// Triangles of the mesh used to pick the entity, shared by the entities
+    /// drawing the same mesh.
+    pub collision_mesh: Arc<CollisionMesh>,
+    /// Index of the pipeline drawing the mesh.
+    pub pipeline: u16,
+    pub material: MaterialInstance,
+    /// Index of the geometry of the mesh, shared by the entities drawing the same one.
+    pub mesh: u32,
+    /// Whether the mesh is blended, and then drawn back to front after the opaque ones.
+    pub transparent: bool,
+    /// Layers of the mesh, only drawn by the passes drawing one of them.
+    pub layers: Layers,
+}
+
+/// Bitmask of layers, filtering what each pass draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Layers(pub u32);
+
+impl Layers {
+    /// Geometry of the scene.
+    pub const DEFAULT: Layers = Layers(1);
+    /// What is drawn behind the scene, like the sky, the gradient or the grid.
+    pub const BACKGROUND: Layers = Layers(1 << 1);
+    /// Editor helpers, like the gizmos, the debug draws and the debug views.
+    pub const GIZMOS: Layers = Layers(1 << 2);
+    pub const ALL: Layers = Layers(!0);
+
+    /// Whether `self` and `other` have a layer in common.
+    pub fn intersects(self, other: Layers) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Get the layers of `self` which are not in `other`.
+    pub fn without(self, other: Layers) -> Layers {
+        Layers(self.0 & !other.0)
+    }
+}
+
+impl BitOr for Layers {
+    type Output = Layers;
+
+    fn bitor(self, other: Layers) -> Layers {
+        Layers(self.0 | other.0)
+    }
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Layers::DEFAULT
+    }
+}
+
+/// Mesh uploaded once and drawn by any number of entities spawned with `Scene::spawn`.
+#[derive(Clone)]
+pub struct MeshHandle {
+    /// Index of the geometry of the mesh.
+    pub mesh: u32,
+    pub index_count: u32,
+    /// Index of the pipeline drawing the mesh.
+    pub pipeline: u16,
+    pub collision_mesh: Arc<CollisionMesh>,
+}
+
+/// Shape of the light emitted by a `Light`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// Parallel rays coming from `Light::direction`, like the sun.
+    Directional,
+    /// Light emitted in all directions from the position of the entity, up to `radius`.
+    Point { radius: f32 },
+    /// Cone of light emitted from the position of the entity along the -y axis
+    /// of its transform, up to `range`. `angle` is the half angle of the cone.
+    Spot { range: f32, angle: Rad<f32> },
+}
+
+/// Light of the scene.
+///
+/// Point and spot lights are placed by the transform of their entity.
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Direction pointing towards a directional light.
+    pub direction: Vector3<f32>,
+    /// Size of the emitter widening the penumbrae of the soft shadows: the
+    /// radius in world units of a spot or point light, the tangent of the
+    /// angular radius of a directional light.
+    pub softness: f32,
+    /// Number of rays traced towards the emitter per fragment by the ray query
+    /// shadows, spread over its size. A single ray casts hard shadows.
+    pub shadow_samples: u32,
+}
+
+/// Components of one type indexed by entity.
+pub struct Storage<T> {
+    components: Vec<Option<T>>,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Storage {
+            components: Vec::new(),
+        }
+    }
+}
+
+impl<T> Storage<T> {
+    fn insert(&mut self, entity: Entity, component: T) {
+        let index = entity.0 as usize;
+        if index >= self.components.len() {
+            self.components.resize_with(index + 1, || None);
+        }
+        self.components[index] = Some(component);
+    }
+
+    fn get(&self, entity: Entity) -> Option<&T> {
+        self.components.get(entity.0 as usize)?.as_ref()
+    }
+
+    fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.components.get_mut(entity.0 as usize)?.as_mut()
+    }
+
+    fn remove(&mut self, entity: Entity) -> Option<T> {
+        self.components.get_mut(entity.0 as usize)?.take()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.components
+            .iter()
+            .enumerate()
+            .filter_map(|(index, component)| Some((Entity(index as _), component.as_ref()?)))
+    }
+}
+
+/// Type that can be attached to an entity.
+pub trait Component: Sized + 'static {
+    fn storage(scene: &Scene) -> &Storage<Self>;
+
+    fn storage_mut(scene: &mut Scene) -> &mut Storage<Self>;
+}
+
+macro_rules! impl_component {
+    ($component:ty, $field:ident) => {
+        impl Component for $component {
+            fn storage(scene: &Scene) -> &Storage<Self> {
+                &scene.$field
+            }
+
+            fn storage_mut(scene: &mut Scene) -> &mut Storage<Self> {
+                &mut scene.$field
+            }
+        }
+    };
+}
+
+impl_component!(Transform, transforms);
+impl_component!(MeshRenderer, mesh_renderers);
+impl_component!(Light, lights);
+impl_component!(Camera, cameras);
+
+/// Entities of the scene and their components.
+///
+/// Each component type has its own storage so systems only iterate over
+/// the components they use.
+#[derive(Default)]
+pub struct Scene {
+    entity_count: u32,
+    /// Entities despawned, whose index is reused by the next ones spawned.
+    free_entities: Vec<Entity>,
+    transforms: Storage<Transform>,
+    mesh_renderers: Storage<MeshRenderer>,
+    lights: Storage<Light>,
+    cameras: Storage<Camera>,
+}
+
+impl Scene {
+    /// Create an entity without components.
+    pub fn spawn_empty(&mut self) -> Entity {
+        self.free_entities.pop().unwrap_or_else(|| {
+            let entity = Entity(self.entity_count);
+            self.entity_count += 1;
+            entity
+        })
+    }
+
+    /// Create an entity drawing `mesh` with `material` at `transform`.
+    pub fn spawn(
+        &mut self,
+        mesh: &MeshHandle,
+        material: MaterialInstance,
+        transform: Transform,
+    ) -> Entity {
+        let entity = self.spawn_empty();
+        self.insert(entity, transform);
+        self.insert(
+            entity,
+            MeshRenderer {
+                index_count: mesh.index_count,
+                collision_mesh: Arc::clone(&mesh.collision_mesh),
+                pipeline: mesh.pipeline,
+                material,
+                mesh: mesh.mesh,
+                transparent: false,
+                layers: Layers::DEFAULT,
+            },
+        );
+        entity
+    }
+
+    /// Remove `entity` and all its components.
+    ///
+    /// Its index is reused by the next entity spawned, so its handle must not
+    /// be used anymore.
+    pub fn despawn(&mut self, entity: Entity) {
+        self.transforms.remove(entity);
+        self.mesh_renderers.remove(entity);
+        self.lights.remove(entity);
+        self.cameras.remove(entity);
+        self.free_entities.push(entity);
+    }
+
+    /// Get the number of entities spawned and not despawned.
+    pub fn entity_count(&self) -> usize {
+        self.entity_count as usize - self.free_entities.len()
+    }
+
+    /// Attach `component` to `entity`, replacing the previous one of the same type.
+    pub fn insert<C: Component>(&mut self, entity: Entity, component: C) {
+        C::storage_mut(self).insert(entity, component);
+    }
+
+    pub fn get<C: Component>(&self, entity: Entity) -> Option<&C> {
+        C::storage(self).get(entity)
+    }
+
+    pub fn get_mut<C: Component>(&mut self, entity: Entity) -> Option<&mut C> {
+        C::storage_mut(self).get_mut(entity)
+    }
+
+    /// Iterate over the entities having a component of type `C`.
+    pub fn iter<C: Component>(&self) -> impl Iterator<Item = (Entity, &C)> {
+        C::storage(self).iter()
+    }
+
+    /// Iterate over the meshes of the scene with the transform of their entity.
+    pub fn meshes(&self) -> impl Iterator<Item = (Entity, &Transform, &MeshRenderer)> {
+        self.iter::<MeshRenderer>()
+            .filter_map(move |(entity, mesh)| {
+                self.get::<Transform>(entity)
+                    .map(|transform| (entity, transform, mesh))
+            })
+    }
+
+    /// Get the world space bounds of the mesh of `entity`, if it has one.
+    pub fn mesh_bounds(&self, entity: Entity) -> Option<Aabb> {
+        let transform = self.get::<Transform>(entity)?;
+        let mesh = self.get::<MeshRenderer>(entity)?;
+        Some(mesh.collision_mesh.bounds().transform(transform.matrix()))
+    }
+
+    /// Get the world space bounds of all the meshes, or `None` if there are none.
+    pub fn bounds(&self) -> Option<Aabb> {
+        let bounds = self
+            .meshes()
+            .map(|(_, transform, mesh)| mesh.collision_mesh.bounds().transform(transform.matrix()))
+            .fold(Aabb::empty(), |bounds, mesh_bounds| {
+                bounds.union(&mesh_bounds)
+            });
+        Some(bounds).filter(|bounds| !bounds.is_empty())
+    }
+
+    /// Intersect the world space `ray` with the meshes and get the closest hit.
+    pub fn pick(&self, ray: &Ray) -> Option<(Entity, RayHit)> {
+        self.meshes()
+            .filter_map(|(entity, transform, mesh)| {
+                let hit = mesh.collision_mesh.intersect_ray(ray, transform.matrix())?;
+                Some((entity, hit))
+            })
+            .min_by(|(_, a), (_, b)| a.distance.partial_cmp(&b.distance).unwrap())
+    }
+}
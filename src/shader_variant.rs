@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap, error::Error, ffi::OsStr, fmt, fs, io, path::Path, process::Command,
+};
+
+/// Keyword discarding the fragments whose alpha is below 0.5.
+pub const ALPHA_TEST: &str = "ALPHA_TEST";
+
+/// Keyword blending the bloom chain with the HDR image in the tonemapper.
+pub const BLOOM: &str = "BLOOM";
+
+/// Keyword writing the distances to the clip planes of the frame uniforms in
+/// `gl_ClipDistance`, which requires the `shaderClipDistance` device feature.
+pub const CLIP_PLANES: &str = "CLIP_PLANES";
+
+/// Keyword flipping the normal of the back faces, for the materials drawn
+/// without culling.
+pub const DOUBLE_SIDED: &str = "DOUBLE_SIDED";
+
+/// Keyword multiplying the color by the lightmap bound with the material and
+/// passing the lightmap coordinates through the vertex shader.
+pub const LIGHTMAP: &str = "LIGHTMAP";
+
+/// Keyword multiplying the color by the ambient light of the frame uniforms,
+/// evaluated from spherical harmonics in the direction of the normal.
+pub const SH_AMBIENT: &str = "SH_AMBIENT";
+
+/// Keyword darkening the color in the shadow map of the sun bound with the material.
+pub const SHADOWS: &str = "SHADOWS";
+
+/// Keyword adding the light of the spot lights shadowed in the shadow atlas
+/// bound with the material.
+pub const SPOT_SHADOWS: &str = "SPOT_SHADOWS";
+
+/// Keyword tracing the shadows of the sun, and of the spot lights with
+/// `SPOT_SHADOWS`, with ray queries against the acceleration structure bound
+/// with the material, which requires `VK_KHR_ray_query` and SPIR-V 1.4.
+pub const RAY_QUERY_SHADOWS: &str = "RAY_QUERY_SHADOWS";
+
+/// Directory of the GLSL sources, relative to the working directory.
+const SOURCE_DIR: &str = "assets/shaders";
+
+/// Directory of the compiled variants, relative to `SOURCE_DIR`.
+const VARIANT_DIR: &str = "variants";
+
+/// Error while compiling a shader variant.
+#[derive(Debug)]
+pub enum VariantError {
+    Io(io::Error),
+    /// glslangValidator failed, with its output.
+    Compilation(String),
+}
+
+impl Error for VariantError {}
+
+impl fmt::Display for VariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VariantError::Io(error) => write!(f, "{}", error),
+            VariantError::Compilation(output) => write!(f, "Compilation failed: {}", output),
+        }
+    }
+}
+
+impl From<io::Error> for VariantError {
+    fn from(error: io::Error) -> Self {
+        VariantError::Io(error)
+    }
+}
+
+/// Compiled variants of the shaders.
+///
+/// A variant is a shader compiled with some keywords defined as preprocessor
+/// macros, so a material can enable optional features like the alpha test
+/// without branching in the shader. Only the variants actually requested are
+/// compiled, the first time they are requested. They are written next to the
+/// shaders in `variants/` and only compiled again when their source is newer.
+///
+/// The shader without keywords is the one compiled by the build script.
+#[derive(Default)]
+pub struct ShaderVariants {
+    paths: HashMap<(String, Vec<String>), String>,
+}
+
+impl ShaderVariants {
+    /// Get the path of the compiled variant of `shader` with `keywords`,
+    /// compiling it if needed.
+    ///
+    /// `shader` is the name of the GLSL source in the shaders directory, like
+    /// `material.frag`. The returned path can be passed to `fs::load`.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if glslangValidator cannot be run or fails. Variants
+    /// cannot be compiled on Android since the sources are not packaged.
+    pub fn path(&mut self, shader: &str, keywords: &[&str]) -> Result<String, VariantError> {
+        let mut keywords = keywords.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        keywords.sort();
+        keywords.dedup();
+        if keywords.is_empty() {
+            return Ok(format!("shaders/{}.spv", shader));
+        }
+
+        let key = (shader.to_owned(), keywords);
+        if let Some(path) = self.paths.get(&key) {
+            return Ok(path.clone());
+        }
+
+        let (shader, keywords) = &key;
+        let output_name = format!("{}.{}.spv", shader, keywords.join("."));
+        let output = Path::new(VARIANT_DIR).join(&output_name);
+        if !is_up_to_date(shader, &Path::new(SOURCE_DIR).join(&output)) {
+            compile(shader, keywords, &output)?;
+        }
+
+        let path = format!("shaders/{}/{}", VARIANT_DIR, output_name);
+        self.paths.insert(key, path.clone());
+        Ok(path)
+    }
+}
+
+/// Check if `output` is newer than `shader` and the files it can include.
+fn is_up_to_date(shader: &str, output: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    let output = match modified(output) {
+        Ok(output) => output,
+        Err(_) => return false,
+    };
+    let entries = match fs::read_dir(SOURCE_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name() == Some(OsStr::new(shader))
+                || path.extension() == Some(OsStr::new("glsl"))
+        })
+        .all(|path| modified(&path).is_ok_and(|source| source <= output))
+}
+
+fn compile(shader: &str, keywords: &[String], output: &Path) -> Result<(), VariantError> {
+    log::debug!("Compiling shader {} with {:?}.", shader, keywords);
+    fs::create_dir_all(Path::new(SOURCE_DIR).join(VARIANT_DIR))?;
+    let mut command = Command::new("glslangValidator");
+    command.current_dir(SOURCE_DIR).arg("-V");
+    // Ray queries need SPIR-V 1.4
+    if keywords.iter().any(|keyword| keyword == RAY_QUERY_SHADOWS) {
+        command.arg("--target-env").arg("spirv1.4");
+    }
+    let result = command
+        .args(keywords.iter().map(|keyword| format!("-D{}", keyword)))
+        .arg(shader)
+        .arg("-o")
+        .arg(output)
+        .output()?;
+    if result.status.success() {
+        Ok(())
+    } else {
+        Err(VariantError::Compilation(
+            String::from_utf8_lossy(&result.stdout).into_owned(),
+        ))
+    }
+}
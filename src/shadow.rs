@@ -0,0 +1,524 @@
+use crate::{
+    blur::GaussianBlur,
+    buffer::Buffer,
+    frame_layout::FRAME_SET,
+    gpu_device::Device,
+    lifetime,
+    math::{self, Aabb},
+    render_target::RenderTarget,
+    texture::{Texture, TextureDesc},
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Matrix4, Point3, Vector3, Vector4};
+
+/// Width and height in texels of the shadow map.
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+/// Format of the shadow map, storing the depth of the closest caster.
+///
+/// The depth is written to a color attachment since the pipelines always have one.
+pub const SHADOW_MAP_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+/// Format of the shadow map of the variance filter, storing the depth of the
+/// casters and its square.
+pub const SHADOW_MOMENTS_FORMAT: vk::Format = vk::Format::R32G32_SFLOAT;
+
+/// Distance in texels between the samples of the blur of the moments.
+const MOMENTS_BLUR_SPREAD: f32 = 1.5;
+
+/// Distance added in front of and behind the casters so the depth range does not clip them.
+const DEPTH_MARGIN: f32 = 0.1;
+
+/// Depth bias of the casters drawn in the shadow maps, reducing the acne on
+/// the surfaces facing away from the light.
+pub const CASTER_DEPTH_BIAS: DepthBias = DepthBias {
+    constant_factor: 1.25,
+    slope_factor: 1.75,
+};
+
+/// Depth bias added by the rasterizer to the depth of the fragments of a pipeline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthBias {
+    /// Constant bias, in units of the minimum resolvable difference of the depth attachment.
+    pub constant_factor: f32,
+    /// Bias proportional to the slope of the depth of the polygon.
+    pub slope_factor: f32,
+}
+
+impl DepthBias {
+    /// Get the bias with both factors multiplied by `factor`.
+    pub fn scaled(self, factor: f32) -> Self {
+        DepthBias {
+            constant_factor: self.constant_factor * factor,
+            slope_factor: self.slope_factor * factor,
+        }
+    }
+
+    /// Set the bias in `command_buffer` for the next draws.
+    ///
+    /// The bound pipeline must have a dynamic depth bias.
+    pub fn cmd_set(self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_set_depth_bias(command_buffer, self.constant_factor, 0.0, self.slope_factor)
+        };
+    }
+}
+
+/// Filter of the shadow maps.
+///
+/// Must match the `SHADOW_FILTER_*` constants in `shadow_filter.glsl`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowFilter {
+    /// Fixed 3x3 percentage closer filtering.
+    Pcf = 0,
+    /// Percentage closer soft shadows: the filter radius grows with the
+    /// distance between the receiver and its blockers, and with the softness
+    /// of the light.
+    Pcss = 1,
+    /// Variance shadow maps: the moments of the depth are blurred after the
+    /// shadow pass and bounded with Chebyshev's inequality, which gives cheap
+    /// large penumbrae but leaks light where casters overlap.
+    Variance = 2,
+}
+
+impl ShadowFilter {
+    /// Get the filter from its name, as used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pcf" => Some(ShadowFilter::Pcf),
+            "pcss" => Some(ShadowFilter::Pcss),
+            "vsm" | "variance" => Some(ShadowFilter::Variance),
+            _ => None,
+        }
+    }
+}
+
+/// Orthographic projection of a directional light fitted by `fit_light_view_proj`.
+#[derive(Clone, Copy, Debug)]
+pub struct LightProjection {
+    pub view_proj: Matrix4<f32>,
+    /// Width in world units of the projection, along its x axis.
+    pub width: f32,
+    /// Depth in world units of the projection, along the light.
+    pub depth: f32,
+}
+
+impl LightProjection {
+    /// Get the width in shadow map coordinates of the penumbra per unit of
+    /// normalized depth between a receiver and its blocker.
+    ///
+    /// `softness` is the tangent of the angular radius of the light.
+    pub fn penumbra_scale(&self, softness: f32) -> f32 {
+        softness * self.depth / self.width
+    }
+}
+
+/// Fit an orthographic projection looking along `-light_direction` around the
+/// part of `scene_bounds` seen by the camera of `camera_view_proj`.
+///
+/// The bounds perpendicular to the light cover the bounding sphere of the
+/// camera frustum, whose size does not change when the camera turns, and
+/// their origin is snapped to the texels of the shadow map so they do not
+/// shimmer when the camera moves. Along the axes where the scene is smaller
+/// than the sphere, the bounds of the scene are used instead since they do
+/// not move. The depth range covers the whole scene so casters outside of the
+/// frustum still cast their shadows in it.
+///
+/// `camera_view_proj` must map depth to 0..1 like `math::perspective` does.
+pub fn fit_light_view_proj(
+    light_direction: Vector3<f32>,
+    camera_view_proj: Matrix4<f32>,
+    scene_bounds: &Aabb,
+) -> LightProjection {
+    let light_direction = light_direction.normalize();
+    let up = if light_direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let light_view = Matrix4::look_at_dir(Point3::origin(), -light_direction, up);
+
+    // Bounding sphere of the frustum in light space
+    let inverse_view_proj = camera_view_proj.invert().unwrap();
+    let corners = (0..8)
+        .map(|i| {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if i & 4 == 0 { 0.0 } else { 1.0 };
+            Point3::from_homogeneous(inverse_view_proj * Vector4::new(x, y, z, 1.0))
+        })
+        .collect::<Vec<_>>();
+    let center = Point3::centroid(&corners);
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0.0, f32::max);
+    let center = light_view.transform_point(center);
+
+    let scene = scene_bounds.transform(light_view);
+    let texel_size = 2.0 * radius / SHADOW_MAP_SIZE as f32;
+    let fit = |center: f32, scene_min: f32, scene_max: f32| {
+        if scene_max - scene_min < 2.0 * radius {
+            (scene_min, scene_max)
+        } else {
+            let min = ((center - radius) / texel_size).floor() * texel_size;
+            (min, min + 2.0 * radius)
+        }
+    };
+    let (left, right) = fit(center.x, scene.min.x, scene.max.x);
+    let (bottom, top) = fit(center.y, scene.min.y, scene.max.y);
+
+    // The light looks along -z
+    let near = -scene.max.z - DEPTH_MARGIN;
+    let far = -scene.min.z + DEPTH_MARGIN;
+
+    LightProjection {
+        view_proj: math::orthographic(left, right, bottom, top, near, far) * light_view,
+        width: right - left,
+        depth: far - near,
+    }
+}
+
+/// Shadow map of the sun.
+///
+/// Before the main render pass, the model is drawn from the sun in the
+/// shadow map with the frame set, which holds the matrix fitted by
+/// `fit_light_view_proj`. The `SHADOWS` variant of the material then compares
+/// the depth of its fragments with the map. Only the model casts and receives
+/// shadows.
+pub struct ShadowMap {
+    caster_bounds: Aabb,
+    color: Texture,
+    depth: Texture,
+    /// Blur of the moments of the variance filter.
+    blur: Option<GaussianBlur>,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl ShadowMap {
+    /// Create the render pass and the framebuffer of the shadow map.
+    ///
+    /// `color` must be created from `color_desc` and `depth` from `depth_desc`.
+    /// `caster_bounds` are the bounds of the model in model space.
+    ///
+    /// With `blur_targets`, the map stores the moments of the depth for the
+    /// variance filter and they are blurred through the targets, which must
+    /// have a single `SHADOW_MOMENTS_FORMAT` color attachment of the size of
+    /// the map.
+    ///
+    /// The pipelines must be set using `set_pipeline`, and the one of the blur,
+    /// before recording the pass.
+    pub fn new(
+        device: &Device,
+        caster_bounds: Aabb,
+        mut color: Texture,
+        (depth, depth_format): (Texture, vk::Format),
+        blur_targets: Option<(RenderTarget, RenderTarget)>,
+    ) -> Self {
+        // The moments are blurred with linear fetches
+        let (format, filter) = if blur_targets.is_some() {
+            (SHADOW_MOMENTS_FORMAT, vk::Filter::LINEAR)
+        } else {
+            (SHADOW_MAP_FORMAT, vk::Filter::NEAREST)
+        };
+        color.sampler = Some(create_sampler(device, filter));
+        let blur = blur_targets.map(|targets| GaussianBlur::new(device, color, targets));
+        let render_pass = create_render_pass(device, format, depth_format);
+        let framebuffer = {
+            let attachments = [color.view, depth.view];
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&attachments)
+                .width(SHADOW_MAP_SIZE)
+                .height(SHADOW_MAP_SIZE)
+                .layers(1)
+                .build();
+            unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+        };
+
+        ShadowMap {
+            caster_bounds,
+            color,
+            depth,
+            blur,
+            render_pass,
+            framebuffer,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Describe the map filtered with `filter`.
+    pub fn color_desc(filter: ShadowFilter) -> TextureDesc {
+        let format = if filter == ShadowFilter::Variance {
+            SHADOW_MOMENTS_FORMAT
+        } else {
+            SHADOW_MAP_FORMAT
+        };
+        TextureDesc::new_2d(
+            Self::extent(),
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    pub fn depth_desc(format: vk::Format) -> TextureDesc {
+        TextureDesc::new_2d(
+            Self::extent(),
+            format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+    }
+
+    pub fn extent() -> vk::Extent2D {
+        vk::Extent2D {
+            width: SHADOW_MAP_SIZE,
+            height: SHADOW_MAP_SIZE,
+        }
+    }
+}
+
+impl ShadowMap {
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    /// Whether the map stores the moments of the depth for the variance filter.
+    pub fn stores_moments(&self) -> bool {
+        self.blur.is_some()
+    }
+
+    /// Get the depth bias of the pipeline drawing the casters.
+    ///
+    /// The moments are not biased since the variance filter does not compare depths.
+    pub fn caster_depth_bias(&self) -> Option<DepthBias> {
+        if self.stores_moments() {
+            None
+        } else {
+            Some(CASTER_DEPTH_BIAS)
+        }
+    }
+
+    /// Get the blur of the moments of the variance filter.
+    pub fn blur_mut(&mut self) -> Option<&mut GaussianBlur> {
+        self.blur.as_mut()
+    }
+
+    /// Get the shadow map with its sampler, or the blurred moments.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the pass.
+    pub fn texture(&self) -> Texture {
+        self.blur.as_ref().map_or(self.color, GaussianBlur::output)
+    }
+
+    /// Replace the bounds of the model in model space after it was reloaded.
+    pub fn set_caster_bounds(&mut self, caster_bounds: Aabb) {
+        self.caster_bounds = caster_bounds;
+    }
+
+    /// Get the projection of the sun for the frame.
+    ///
+    /// `model` places the model in the world, and `light_direction` points
+    /// toward the sun.
+    pub fn light_projection(
+        &self,
+        light_direction: Vector3<f32>,
+        camera_view_proj: Matrix4<f32>,
+        model: Matrix4<f32>,
+    ) -> LightProjection {
+        fit_light_view_proj(
+            light_direction,
+            camera_view_proj,
+            &self.caster_bounds.transform(model),
+        )
+    }
+
+    /// Set the pipeline drawing the casters, whose layout only has the frame set.
+    ///
+    /// The shadow map takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the shadow pass in `command_buffer`.
+    ///
+    /// `frame_set` is the frame set of the swapchain image. It must be
+    /// recorded outside of any render pass, before the main render pass.
+    pub fn cmd_render(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame_set: vk::DescriptorSet,
+        (vertex_buffer, index_buffer, index_count): (Buffer, Buffer, u32),
+    ) {
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [1.0, 1.0, 0.0, 0.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: Self::extent(),
+            })
+            .clear_values(&clear_values)
+            .build();
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                FRAME_SET,
+                &[frame_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+            device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+            device.cmd_end_render_pass(command_buffer);
+        }
+
+        if let Some(blur) = self.blur.as_ref() {
+            blur.cmd_blur(device, command_buffer, MOMENTS_BLUR_SPREAD);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_framebuffer(self.framebuffer, None);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+        if let Some(blur) = self.blur.as_mut() {
+            blur.destroy(device);
+        }
+        self.color.destroy(device);
+        self.depth.destroy(device);
+    }
+}
+
+/// Create the sampler of a shadow map, whose texels are fetched unless its
+/// moments are filtered.
+pub fn create_sampler(device: &Device, filter: vk::Filter) -> vk::Sampler {
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(filter)
+        .min_filter(filter)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .build();
+
+    unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+}
+
+/// Create the render pass drawing the casters in a shadow map of `format`.
+///
+/// The map is left in the `SHADER_READ_ONLY_OPTIMAL` layout for the main pass.
+pub fn create_render_pass(
+    device: &Device,
+    format: vk::Format,
+    depth_format: vk::Format,
+) -> vk::RenderPass {
+    let attachment_descs = [
+        vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build(),
+        vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build(),
+    ];
+    let color_attachment_refs = [vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build()];
+    let depth_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .build();
+    let subpass_descs = [vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs)
+        .depth_stencil_attachment(&depth_attachment_ref)
+        .build()];
+
+    // The previous frame might still be sampling the map or writing its depth.
+    let begin_dep = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        )
+        .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        )
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
+        .build();
+    // The main pass then samples the map.
+    let end_dep = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+        .build();
+    let subpass_deps = [begin_dep, end_dep];
+
+    let render_pass_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachment_descs)
+        .subpasses(&subpass_descs)
+        .dependencies(&subpass_deps)
+        .build();
+
+    unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+}
@@ -0,0 +1,446 @@
+use crate::{
+    buffer::Buffer,
+    frame_layout::FRAME_SET,
+    gpu_device::Device,
+    lifetime, math,
+    render_list::SpotLightItem,
+    scene::Entity,
+    shadow::{self, CASTER_DEPTH_BIAS, SHADOW_MAP_FORMAT},
+    std140::std140_struct,
+    texture::{Texture, TextureDesc},
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Matrix4, Point3, Rad, Vector3};
+
+/// Width and height in texels of the shadow atlas.
+pub const ATLAS_SIZE: u32 = 4096;
+
+/// Maximum number of spot lights casting shadows in the atlas.
+///
+/// Must match `MAX_SHADOWED_SPOT_LIGHTS` in `frame.glsl`.
+pub const MAX_SHADOWED_SPOT_LIGHTS: usize = 32;
+
+/// Sizes in texels of the smallest and the largest tiles of a light.
+const MIN_TILE_SIZE: u32 = 128;
+const MAX_TILE_SIZE: u32 = 1024;
+
+/// Texels of the tile of a spot light per world unit of the radius of the base of its cone.
+const TEXELS_PER_UNIT: f32 = 512.0;
+
+/// Distance from a spot light to the near plane of its projection.
+const NEAR: f32 = 0.05;
+
+/// Square tile of the shadow atlas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasTile {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+impl AtlasTile {
+    /// Get the viewport drawing the casters in the tile.
+    pub fn viewport(&self) -> vk::Viewport {
+        vk::Viewport {
+            x: self.x as _,
+            y: self.y as _,
+            width: self.size as _,
+            height: self.size as _,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    pub fn scissor(&self) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: self.x as _,
+                y: self.y as _,
+            },
+            extent: vk::Extent2D {
+                width: self.size,
+                height: self.size,
+            },
+        }
+    }
+
+    /// Get the scale in xy and the offset in zw from the 0..1 coordinates of
+    /// the tile to the ones of an atlas of `atlas_size` texels.
+    pub fn uv_transform(&self, atlas_size: u32) -> [f32; 4] {
+        let atlas_size = atlas_size as f32;
+        let scale = self.size as f32 / atlas_size;
+        [
+            scale,
+            scale,
+            self.x as f32 / atlas_size,
+            self.y as f32 / atlas_size,
+        ]
+    }
+
+    /// Split the tile in its four quadrants.
+    fn split(&self) -> [AtlasTile; 4] {
+        let size = self.size / 2;
+        let quadrant = |x, y| AtlasTile {
+            x: self.x + x * size,
+            y: self.y + y * size,
+            size,
+        };
+        [
+            quadrant(0, 0),
+            quadrant(1, 0),
+            quadrant(0, 1),
+            quadrant(1, 1),
+        ]
+    }
+}
+
+/// Allocator packing square tiles whose size is a power of two in a square atlas.
+///
+/// The atlas is split like a quadtree: a tile is taken from the smallest free
+/// tile large enough, whose other quadrants stay free, so tiles allocated from
+/// the largest to the smallest leave no hole.
+pub struct ShadowAtlasAllocator {
+    free_tiles: Vec<AtlasTile>,
+}
+
+impl ShadowAtlasAllocator {
+    /// Create the allocator of an atlas of `size` texels, which must be a power of two.
+    pub fn new(size: u32) -> Self {
+        assert!(
+            size.is_power_of_two(),
+            "The atlas size must be a power of two."
+        );
+        ShadowAtlasAllocator {
+            free_tiles: vec![AtlasTile { x: 0, y: 0, size }],
+        }
+    }
+}
+
+impl ShadowAtlasAllocator {
+    /// Allocate a tile of at least `size` texels, rounded up to a power of two.
+    ///
+    /// Return `None` if no free tile is large enough.
+    pub fn allocate(&mut self, size: u32) -> Option<AtlasTile> {
+        let size = size.max(1).next_power_of_two();
+        let index = self
+            .free_tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, tile)| tile.size >= size)
+            .min_by_key(|(_, tile)| (tile.size, tile.y, tile.x))
+            .map(|(index, _)| index)?;
+        let mut tile = self.free_tiles.swap_remove(index);
+        while tile.size > size {
+            let [first, rest @ ..] = tile.split();
+            self.free_tiles.extend_from_slice(&rest);
+            tile = first;
+        }
+        Some(tile)
+    }
+}
+
+/// Get the size of the tile of a spot light, proportional to the radius of the
+/// base of its cone.
+pub fn tile_size(range: f32, angle: Rad<f32>) -> u32 {
+    let texels = (range * angle.0.tan() * TEXELS_PER_UNIT) as u32;
+    math::clamp(texels.next_power_of_two(), MIN_TILE_SIZE, MAX_TILE_SIZE)
+}
+
+/// Get the view projection of `light`, whose frustum encloses its cone.
+pub fn spot_light_view_proj(light: &SpotLightItem) -> Matrix4<f32> {
+    let up = if light.direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let view = Matrix4::look_at_dir(light.position, light.direction, up);
+    let fovy = math::clamp(light.angle * 2.0, Rad(0.01), Rad(3.1));
+    math::perspective(fovy, 1.0, NEAR, light.range.max(2.0 * NEAR)) * view
+}
+
+std140_struct! {
+    /// Spot lights casting shadows in the atlas, in the frame uniform buffer.
+    ///
+    /// The arrays are indexed by the slot of the light in the atlas. Must match
+    /// `SpotLights` in `frame.glsl`.
+    #[derive(Clone, Copy)]
+    pub struct SpotLightsUniform {
+        pub view_projs: [Matrix4<f32>; MAX_SHADOWED_SPOT_LIGHTS],
+        /// Scale and offset from the coordinates of the light to its tile.
+        pub atlas_transforms: [[f32; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+        /// Position of the light in xyz and its range in w.
+        pub positions: [[f32; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+        /// Axis of the cone in xyz and the cosine of its half angle in w.
+        pub directions: [[f32; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+        /// Softness of the light in x and its number of shadow rays in y, the rest is unused.
+        pub softness: [[f32; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+        pub count: u32,
+        // Pad the struct to the size std140 reserves for it
+        _padding: f32,
+        _padding_2: [f32; 2],
+    }
+}
+
+impl Default for SpotLightsUniform {
+    fn default() -> Self {
+        SpotLightsUniform {
+            view_projs: [Matrix4::identity(); MAX_SHADOWED_SPOT_LIGHTS],
+            atlas_transforms: [[0.0; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+            positions: [[0.0; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+            directions: [[0.0; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+            softness: [[0.0; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+            count: 0,
+            _padding: 0.0,
+            _padding_2: [0.0; 2],
+        }
+    }
+}
+
+/// Shadow atlas of the spot lights.
+///
+/// The tiles are allocated once when the atlas is created, the larger the
+/// cone of a light the larger its tile, so the recorded command buffers draw
+/// the casters of each light with the viewport and the scissor of its tile.
+/// The light matrices and the transforms to the tiles are written each frame
+/// in the frame uniform buffer, so the lights can move. The `SPOT_SHADOWS`
+/// variant of the material then adds the light of each spot light, shadowed
+/// by its tile. Only the model casts and receives shadows.
+pub struct ShadowAtlas {
+    /// Light of each slot of the uniforms, with its tile.
+    slots: Vec<(Entity, AtlasTile)>,
+    color: Texture,
+    depth: Texture,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl ShadowAtlas {
+    /// Allocate the tiles of `spot_lights` and create the framebuffer of the atlas.
+    ///
+    /// `color` must be created from `color_desc` and `depth` from `depth_desc`.
+    /// The lights beyond `MAX_SHADOWED_SPOT_LIGHTS`, or without room left in
+    /// the atlas even for the smallest tile, cast no shadow.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording the pass.
+    pub fn new(
+        device: &Device,
+        spot_lights: &[SpotLightItem],
+        mut color: Texture,
+        (depth, depth_format): (Texture, vk::Format),
+    ) -> Self {
+        let slots = Self::allocate_tiles(spot_lights);
+        log::debug!(
+            "Allocated {} of {} spot light shadows in the atlas.",
+            slots.len(),
+            spot_lights.len()
+        );
+
+        color.sampler = Some(shadow::create_sampler(device, vk::Filter::NEAREST));
+        let render_pass = shadow::create_render_pass(device, SHADOW_MAP_FORMAT, depth_format);
+        let framebuffer = {
+            let attachments = [color.view, depth.view];
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&attachments)
+                .width(ATLAS_SIZE)
+                .height(ATLAS_SIZE)
+                .layers(1)
+                .build();
+            unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+        };
+
+        ShadowAtlas {
+            slots,
+            color,
+            depth,
+            render_pass,
+            framebuffer,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Allocate the tiles from the largest to the smallest, halving the ones
+    /// that do not fit.
+    fn allocate_tiles(spot_lights: &[SpotLightItem]) -> Vec<(Entity, AtlasTile)> {
+        let mut lights = spot_lights
+            .iter()
+            .map(|light| (light.entity, tile_size(light.range, light.angle)))
+            .collect::<Vec<_>>();
+        lights.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+        let mut allocator = ShadowAtlasAllocator::new(ATLAS_SIZE);
+        let mut slots = Vec::new();
+        for (entity, size) in lights {
+            if slots.len() == MAX_SHADOWED_SPOT_LIGHTS {
+                log::warn!("Too many spot lights, only {} cast shadows.", slots.len());
+                break;
+            }
+            let tile = std::iter::successors(Some(size), |size| Some(size / 2))
+                .take_while(|size| *size >= MIN_TILE_SIZE)
+                .find_map(|size| allocator.allocate(size));
+            match tile {
+                Some(tile) => slots.push((entity, tile)),
+                None => log::warn!("The shadow atlas is full, a spot light casts no shadow."),
+            }
+        }
+        slots
+    }
+
+    pub fn color_desc() -> TextureDesc {
+        TextureDesc::new_2d(
+            Self::extent(),
+            SHADOW_MAP_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    pub fn depth_desc(format: vk::Format) -> TextureDesc {
+        TextureDesc::new_2d(
+            Self::extent(),
+            format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+    }
+
+    pub fn extent() -> vk::Extent2D {
+        vk::Extent2D {
+            width: ATLAS_SIZE,
+            height: ATLAS_SIZE,
+        }
+    }
+}
+
+impl ShadowAtlas {
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    /// Get the atlas with its sampler.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the pass.
+    pub fn texture(&self) -> Texture {
+        self.color
+    }
+
+    /// Get the uniforms of the lights of the atlas, placed as in `spot_lights`.
+    ///
+    /// The slots whose light is not in `spot_lights` have no range so they do
+    /// not light anything.
+    pub fn uniform(&self, spot_lights: &[SpotLightItem]) -> SpotLightsUniform {
+        let mut uniform = SpotLightsUniform {
+            count: self.slots.len() as _,
+            ..Default::default()
+        };
+        for (slot, (entity, tile)) in self.slots.iter().enumerate() {
+            let light = match spot_lights.iter().find(|light| light.entity == *entity) {
+                Some(light) => light,
+                None => continue,
+            };
+            let Point3 { x, y, z } = light.position;
+            let direction = light.direction.normalize();
+            uniform.view_projs[slot] = spot_light_view_proj(light);
+            uniform.atlas_transforms[slot] = tile.uv_transform(ATLAS_SIZE);
+            uniform.positions[slot] = [x, y, z, light.range];
+            uniform.directions[slot] = [direction.x, direction.y, direction.z, light.angle.0.cos()];
+            uniform.softness[slot] = [light.softness, light.shadow_samples as _, 0.0, 0.0];
+        }
+        uniform
+    }
+
+    /// Set the pipeline drawing the casters, whose layout only has the frame set.
+    ///
+    /// Its viewport, scissor and depth bias must be dynamic. The shadow atlas takes
+    /// ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Record the pass drawing the casters in the tile of each light in `command_buffer`.
+    ///
+    /// The slot of the light is given as the first instance of its draw. The
+    /// depth bias grows on the smaller tiles, whose texels cover more of the scene.
+    /// `frame_set` is the frame set of the swapchain image. It must be
+    /// recorded outside of any render pass, before the main render pass.
+    pub fn cmd_render(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame_set: vk::DescriptorSet,
+        (vertex_buffer, index_buffer, index_count): (Buffer, Buffer, u32),
+    ) {
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [1.0, 0.0, 0.0, 0.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: Self::extent(),
+            })
+            .clear_values(&clear_values)
+            .build();
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                FRAME_SET,
+                &[frame_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+            for (slot, (_, tile)) in self.slots.iter().enumerate() {
+                device.cmd_set_viewport(command_buffer, 0, &[tile.viewport()]);
+                device.cmd_set_scissor(command_buffer, 0, &[tile.scissor()]);
+                CASTER_DEPTH_BIAS
+                    .scaled(MAX_TILE_SIZE as f32 / tile.size as f32)
+                    .cmd_set(device, command_buffer);
+                device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, slot as _);
+            }
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_framebuffer(self.framebuffer, None);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+        self.color.destroy(device);
+        self.depth.destroy(device);
+    }
+}
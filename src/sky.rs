@@ -0,0 +1,353 @@
+use crate::{
+    buffer::Buffer, command_encoder::CommandEncoder, frame_layout::PASS_SET, gpu_device::Device,
+    lifetime, math, std140::std140_struct,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{InnerSpace, Vector3};
+use std::{f32::consts::PI, mem::size_of};
+
+/// Duration in seconds of a full day when the time of day is animated.
+pub const DAY_DURATION: f32 = 60.0;
+
+/// Lowest elevation of the sun in degrees.
+///
+/// The Preetham model is not valid when the sun is below the horizon.
+const MIN_SUN_ELEVATION: f32 = 2.0;
+
+/// Highest elevation of the sun in degrees when the time of day is animated.
+const MAX_SUN_ELEVATION: f32 = 70.0;
+
+/// Scale applied to the luminance before tone mapping.
+///
+/// Must match `EXPOSURE` in `sky.frag`.
+const EXPOSURE: f32 = 0.08;
+
+/// Parameters of the sky model.
+#[derive(Clone, Copy, Debug)]
+pub struct SkyParameters {
+    /// Angle in degrees between the sun and the horizon.
+    pub sun_elevation: f32,
+    /// Angle in degrees of the sun around the vertical axis.
+    pub sun_azimuth: f32,
+    /// Haziness of the atmosphere, from 2 (clear sky) to 10 (hazy sky).
+    pub turbidity: f32,
+    /// Move the sun across the sky over `DAY_DURATION` seconds.
+    pub animate_time_of_day: bool,
+}
+
+impl Default for SkyParameters {
+    fn default() -> Self {
+        SkyParameters {
+            sun_elevation: 30.0,
+            sun_azimuth: 45.0,
+            turbidity: 3.0,
+            animate_time_of_day: false,
+        }
+    }
+}
+
+impl SkyParameters {
+    /// Get the direction pointing toward the sun at `time` seconds.
+    pub fn sun_direction(&self, time: f32) -> Vector3<f32> {
+        let elevation = if self.animate_time_of_day {
+            (time / DAY_DURATION * 2.0 * PI).sin() * MAX_SUN_ELEVATION
+        } else {
+            self.sun_elevation
+        };
+        let elevation = elevation.max(MIN_SUN_ELEVATION).to_radians();
+        let azimuth = self.sun_azimuth.to_radians();
+        Vector3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        )
+    }
+}
+
+std140_struct! {
+    /// Uniforms of the sky shader as laid out in the uniform buffer.
+    ///
+    /// The Perez coefficients and the zenith values hold the luminance Y and
+    /// the chromaticities x and y in their first three components.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct SkyUniforms {
+        pub sun_direction: [f32; 4],
+        pub perez: [[f32; 4]; 5],
+        /// Zenith values divided by the Perez function at the zenith.
+        pub zenith: [f32; 4],
+    }
+}
+
+impl SkyUniforms {
+    /// Compute the coefficients of the Preetham sky model.
+    pub fn new(sun_direction: Vector3<f32>, turbidity: f32) -> Self {
+        let t = turbidity;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.0193 * t - 0.2592,
+                -0.0167 * t - 0.2608,
+                0.0,
+            ],
+            [
+                -0.3554 * t + 0.4275,
+                -0.0665 * t + 0.0008,
+                -0.0950 * t + 0.0092,
+                0.0,
+            ],
+            [
+                -0.0227 * t + 5.3251,
+                -0.0004 * t + 0.2125,
+                -0.0079 * t + 0.2102,
+                0.0,
+            ],
+            [
+                0.1206 * t - 2.5771,
+                -0.0641 * t - 0.8989,
+                -0.0441 * t - 1.6537,
+                0.0,
+            ],
+            [
+                -0.0670 * t + 0.3703,
+                -0.0033 * t + 0.0452,
+                -0.0109 * t + 0.0529,
+                0.0,
+            ],
+        ];
+
+        let theta_s = sun_direction.y.acos();
+        let (theta, theta2, theta3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta + 0.26688);
+
+        // Normalize so the Perez function gives the zenith values at the zenith.
+        let mut zenith = [zenith_luminance, zenith_x, zenith_y, 0.0];
+        for (i, value) in zenith.iter_mut().take(3).enumerate() {
+            *value /= perez_function(perez, i, 0.0, theta_s);
+        }
+
+        SkyUniforms {
+            sun_direction: sun_direction.extend(0.0).into(),
+            perez,
+            zenith,
+        }
+    }
+
+    /// Get the linear RGB color of the sky in `direction` as `sky.frag` draws it,
+    /// without the sun disk.
+    ///
+    /// The model is not defined below the horizon so the horizon color is repeated.
+    pub fn radiance(&self, direction: Vector3<f32>) -> [f32; 3] {
+        let sun_direction = Vector3::new(
+            self.sun_direction[0],
+            self.sun_direction[1],
+            self.sun_direction[2],
+        );
+        let theta = direction.y.max(0.01).acos();
+        let gamma = math::clamp(direction.dot(sun_direction), -1.0, 1.0).acos();
+
+        let [luminance, x, y] = [0, 1, 2].map(|channel| {
+            self.zenith[channel] * perez_function(self.perez, channel, theta, gamma)
+        });
+        let luminance = 1.0 - (-luminance * EXPOSURE).exp();
+        let (cx, cz) = (x / y * luminance, (1.0 - x - y) / y * luminance);
+        [
+            (3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+            (-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+            (0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+        ]
+    }
+}
+
+/// Evaluate the Perez function of `channel` for a view direction at `theta`
+/// from the zenith and `gamma` from the sun.
+fn perez_function(perez: [[f32; 4]; 5], channel: usize, theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = [
+        perez[0][channel],
+        perez[1][channel],
+        perez[2][channel],
+        perez[3][channel],
+        perez[4][channel],
+    ];
+    (1.0 + a * (b / theta.cos()).exp())
+        * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+}
+
+/// Procedural sky drawn in the background using the Preetham model.
+///
+/// The sky is drawn first with a full screen triangle without depth test.
+/// It reads the camera from the frame uniforms and has its own uniform
+/// buffers, bound in set 1, rewritten by `update` before the frame is submitted.
+pub struct Sky {
+    parameters: SkyParameters,
+    uniform_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Sky {
+    /// Create the descriptor sets of the sky.
+    ///
+    /// `uniform_buffers` must be host visible and coherent buffers of at least
+    /// the size of `SkyUniforms`, one per swapchain image.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(device: &Device, parameters: SkyParameters, uniform_buffers: Vec<Buffer>) -> Self {
+        let image_count = uniform_buffers.len();
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        sets.iter()
+            .zip(uniform_buffers.iter())
+            .for_each(|(set, buffer)| {
+                let buffer_info = vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer.buffer)
+                    .offset(0)
+                    .range(size_of::<SkyUniforms>() as _)
+                    .build();
+                let buffer_infos = [buffer_info];
+                let ubo_write = vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&buffer_infos)
+                    .build();
+                unsafe { device.update_descriptor_sets(&[ubo_write], &[]) };
+            });
+
+        Sky {
+            parameters,
+            uniform_buffers,
+            layout,
+            pool,
+            sets,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [ubo_binding];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, size: u32) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: size,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(size)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Sky {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn parameters(&self) -> SkyParameters {
+        self.parameters
+    }
+
+    /// Set the pipeline used to draw the sky.
+    ///
+    /// The sky takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index`.
+    ///
+    /// `time` is the time in seconds since the start of the application.
+    pub fn update(&self, device: &Device, image_index: usize, time: f32) {
+        let uniforms = SkyUniforms::new(
+            self.parameters.sun_direction(time),
+            self.parameters.turbidity,
+        );
+
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+    }
+
+    /// Record the draw of the sky of the swapchain image `image_index` with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    /// It must be recorded before any other draw of the render pass.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder, image_index: usize) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_descriptor_sets(
+            self.pipeline_layout,
+            PASS_SET,
+            &[self.sets[image_index]],
+            &[],
+        );
+        unsafe {
+            encoder
+                .device()
+                .cmd_draw(encoder.command_buffer(), 3, 1, 0, 0)
+        };
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
@@ -0,0 +1,418 @@
+use crate::{
+    buffer::Buffer, command_encoder::CommandEncoder, frame_layout::PASS_SET, gpu_device::Device,
+    lifetime, texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Matrix4, Point3, Vector3};
+use std::{cmp::Ordering, mem::size_of};
+
+/// Maximum number of sprites drawn in a frame.
+pub const MAX_SPRITES: usize = 1024;
+
+/// Width and height in pixels of each tile of the generated atlas.
+pub const ATLAS_TILE_SIZE: u32 = 64;
+
+/// Tiles of the generated atlas.
+pub const LIGHT_TILE: u32 = 0;
+pub const PARTICLE_TILE: u32 = 1;
+pub const GRASS_TILE: u32 = 2;
+pub const FLOWER_TILE: u32 = 3;
+
+/// Grid of tiles packed in a single texture.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureAtlas {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl TextureAtlas {
+    /// Get the texture coordinates of the top left and bottom right corners of `tile`.
+    ///
+    /// Tiles are numbered row by row from the top left corner.
+    pub fn region(&self, tile: u32) -> ([f32; 2], [f32; 2]) {
+        let (column, row) = (tile % self.columns, tile / self.columns);
+        let (width, height) = (1.0 / self.columns as f32, 1.0 / self.rows as f32);
+        let min = [column as f32 * width, row as f32 * height];
+        (min, [min[0] + width, min[1] + height])
+    }
+}
+
+/// Atlas of the texture generated by `generate_sprite_atlas`.
+pub const SPRITE_ATLAS: TextureAtlas = TextureAtlas {
+    columns: 2,
+    rows: 2,
+};
+
+/// Generate the RGBA8 pixels of the sprite atlas.
+///
+/// It contains a light gizmo, a particle, a grass tuft and a flower.
+pub fn generate_sprite_atlas() -> Vec<u8> {
+    let tile_size = ATLAS_TILE_SIZE as usize;
+    let width = tile_size * SPRITE_ATLAS.columns as usize;
+    let height = tile_size * SPRITE_ATLAS.rows as usize;
+    let mut pixels = vec![0; width * height * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let tile = (y / tile_size) as u32 * SPRITE_ATLAS.columns + (x / tile_size) as u32;
+            // Coordinates in -1..1 with y pointing up
+            let u = ((x % tile_size) as f32 + 0.5) / tile_size as f32 * 2.0 - 1.0;
+            let v = 1.0 - ((y % tile_size) as f32 + 0.5) / tile_size as f32 * 2.0;
+            let color = tile_color(tile, u, v);
+            let index = (y * width + x) * 4;
+            for (i, component) in color.iter().enumerate() {
+                pixels[index + i] = (component.clamp(0.0, 1.0) * 255.0) as u8;
+            }
+        }
+    }
+    pixels
+}
+
+fn tile_color(tile: u32, u: f32, v: f32) -> [f32; 4] {
+    let radius = (u * u + v * v).sqrt();
+    let angle = v.atan2(u);
+    match tile {
+        LIGHT_TILE => {
+            let glow = (-radius * radius * 8.0).exp();
+            let rays = (4.0 * angle).cos().abs().powf(16.0) * (1.0 - radius).max(0.0);
+            [1.0, 0.9, 0.6, glow.max(rays)]
+        }
+        PARTICLE_TILE => {
+            let alpha = (1.0 - radius).max(0.0);
+            [1.0, 1.0, 1.0, alpha * alpha]
+        }
+        GRASS_TILE => {
+            // Blades bending away from the center, thinning toward their tip
+            let height = (v + 1.0) * 0.5;
+            let blade = [-0.6f32, -0.3, 0.0, 0.25, 0.55]
+                .iter()
+                .enumerate()
+                .any(|(i, base)| {
+                    let bend = if *base < 0.0 { -0.3 } else { 0.3 };
+                    let center = base + bend * height * height;
+                    let tip = 0.7 + 0.3 * ((i * 7 % 5) as f32 / 4.0);
+                    (u - center).abs() < 0.08 * (1.0 - height / tip) && height < tip
+                });
+            let alpha = if blade { 1.0 } else { 0.0 };
+            [0.2 + 0.4 * height, 0.5 + 0.3 * height, 0.1, alpha]
+        }
+        FLOWER_TILE => {
+            let (pu, pv) = (u, v - 0.35);
+            let petal_radius = (pu * pu + pv * pv).sqrt();
+            let petal_angle = pv.atan2(pu);
+            if petal_radius < 0.12 {
+                [1.0, 0.8, 0.1, 1.0]
+            } else if petal_radius < 0.35 + 0.12 * (5.0 * petal_angle).cos() {
+                [0.9, 0.3, 0.6, 1.0]
+            } else if u.abs() < 0.04 && v < 0.35 {
+                [0.2, 0.5, 0.1, 1.0]
+            } else {
+                [0.0, 0.0, 0.0, 0.0]
+            }
+        }
+        _ => [0.0, 0.0, 0.0, 0.0],
+    }
+}
+
+/// Textured quad facing the camera.
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    /// World position of the center of the quad.
+    pub position: Point3<f32>,
+    /// Width and height of the quad in world units.
+    pub size: [f32; 2],
+    /// Tile of the atlas drawn on the quad.
+    pub tile: u32,
+    /// Color multiplied with the atlas.
+    pub color: [f32; 4],
+    /// Keep the quad vertical instead of fully facing the camera, for vegetation.
+    pub upright: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub struct SpriteVertex {
+    pub pos: [f32; 3],
+    pub coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl SpriteVertex {
+    pub fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<SpriteVertex>() as _)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        let position_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let coords_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(12)
+            .build();
+        let color_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(20)
+            .build();
+        [position_desc, coords_desc, color_desc]
+    }
+}
+
+/// Batches sprites into a single draw.
+///
+/// The quads are built on the CPU by `update` and written in the vertex
+/// buffer of the swapchain image, along with the indirect draw reading them.
+/// The command buffers can then be recorded once while the sprites change
+/// every frame.
+///
+/// The quads are sorted back to front since they are alpha blended.
+pub struct SpriteRenderer {
+    atlas: TextureAtlas,
+    texture: Texture,
+    vertex_buffers: Vec<Buffer>,
+    indirect_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl SpriteRenderer {
+    /// Create the descriptor set containing the atlas.
+    ///
+    /// `vertex_buffers` and `indirect_buffers` must be host visible and coherent
+    /// buffers of at least `SpriteRenderer::vertex_buffer_size()` and
+    /// `SpriteRenderer::indirect_buffer_size()` bytes, one per swapchain image.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(
+        device: &Device,
+        atlas: TextureAtlas,
+        texture: Texture,
+        vertex_buffers: Vec<Buffer>,
+        indirect_buffers: Vec<Buffer>,
+    ) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build();
+        let image_infos = [image_info];
+        let atlas_write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[atlas_write], &[]) };
+
+        // Nothing is drawn until the first update
+        indirect_buffers
+            .iter()
+            .for_each(|buffer| buffer.write(device, 0, &[draw_command(0)]));
+
+        SpriteRenderer {
+            atlas,
+            texture,
+            vertex_buffers,
+            indirect_buffers,
+            layout,
+            pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Size in bytes of each vertex buffer.
+    pub fn vertex_buffer_size() -> vk::DeviceSize {
+        (size_of::<SpriteVertex>() * 6 * MAX_SPRITES) as _
+    }
+
+    /// Size in bytes of each indirect buffer.
+    pub fn indirect_buffer_size() -> vk::DeviceSize {
+        size_of::<vk::DrawIndirectCommand>() as _
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let atlas_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [atlas_binding];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl SpriteRenderer {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Set the pipeline used to draw the sprites.
+    ///
+    /// The renderer takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Build the quads of `sprites` facing the camera of `view` and write them
+    /// in the buffers of the swapchain image `image_index`.
+    ///
+    /// At most `MAX_SPRITES` sprites are drawn.
+    pub fn update(
+        &self,
+        device: &Device,
+        image_index: usize,
+        view: Matrix4<f32>,
+        camera_position: Point3<f32>,
+        sprites: &[Sprite],
+    ) {
+        let camera_right = Vector3::new(view.x.x, view.y.x, view.z.x);
+        let camera_up = Vector3::new(view.x.y, view.y.y, view.z.y);
+
+        let mut sorted = sprites.iter().take(MAX_SPRITES).collect::<Vec<_>>();
+        sorted.sort_by(|a, b| {
+            let distance_a = a.position.distance2(camera_position);
+            let distance_b = b.position.distance2(camera_position);
+            distance_b
+                .partial_cmp(&distance_a)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let mut vertices = Vec::with_capacity(sorted.len() * 6);
+        for sprite in sorted {
+            let up = if sprite.upright {
+                Vector3::unit_y()
+            } else {
+                camera_up
+            };
+            let right = camera_right * sprite.size[0] * 0.5;
+            let up = up * sprite.size[1] * 0.5;
+            let (min, max) = self.atlas.region(sprite.tile);
+            let corner = |x: f32, y: f32, coords: [f32; 2]| SpriteVertex {
+                pos: (sprite.position + right * x + up * y).into(),
+                coords,
+                color: sprite.color,
+            };
+            let top_left = corner(-1.0, 1.0, min);
+            let top_right = corner(1.0, 1.0, [max[0], min[1]]);
+            let bottom_left = corner(-1.0, -1.0, [min[0], max[1]]);
+            let bottom_right = corner(1.0, -1.0, max);
+            vertices.extend_from_slice(&[
+                top_left,
+                bottom_left,
+                top_right,
+                top_right,
+                bottom_left,
+                bottom_right,
+            ]);
+        }
+
+        if !vertices.is_empty() {
+            self.vertex_buffers[image_index].write(device, 0, &vertices);
+        }
+        self.indirect_buffers[image_index].write(device, 0, &[draw_command(vertices.len() as _)]);
+    }
+
+    /// Record the draw of the sprites of the swapchain image `image_index` with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder, image_index: usize) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[self.vertex_buffers[image_index].buffer], &[0]);
+        encoder.bind_descriptor_sets(self.pipeline_layout, PASS_SET, &[self.set], &[]);
+        unsafe {
+            encoder.device().cmd_draw_indirect(
+                encoder.command_buffer(),
+                self.indirect_buffers[image_index].buffer,
+                0,
+                1,
+                size_of::<vk::DrawIndirectCommand>() as _,
+            )
+        };
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.texture.destroy(device);
+        self.vertex_buffers
+            .iter_mut()
+            .chain(self.indirect_buffers.iter_mut())
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
+
+fn draw_command(vertex_count: u32) -> vk::DrawIndirectCommand {
+    vk::DrawIndirectCommand {
+        vertex_count,
+        instance_count: 1,
+        first_vertex: 0,
+        first_instance: 0,
+    }
+}
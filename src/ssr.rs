@@ -0,0 +1,529 @@
+use crate::{
+    buffer::Buffer,
+    frame_layout::PASS_SET,
+    gpu_device::Device,
+    lifetime,
+    render_target::RenderTarget,
+    std140::std140_struct,
+    texture::Texture,
+    tracked_image::{ImageState, TrackedImage},
+};
+use ash::{version::DeviceV1_0, vk};
+use std::mem::size_of;
+
+const UNIFORMS_BINDING: u32 = 0;
+const HDR_COLOR_BINDING: u32 = 1;
+const DEPTH_BINDING: u32 = 2;
+const DEPTH_PYRAMID_BINDING: u32 = 3;
+const VELOCITY_BINDING: u32 = 4;
+const HISTORY_BINDING: u32 = 5;
+
+/// Parameters of the screen-space reflections.
+#[derive(Clone, Copy, Debug)]
+pub struct SsrParameters {
+    /// Maximum number of cells visited by a ray, on any level of the pyramid.
+    pub max_steps: u32,
+    /// Length of the rays in view space.
+    pub max_distance: f32,
+    /// Depth behind a surface where a ray still hits it, in view space.
+    pub thickness: f32,
+    /// Roughness of all the surfaces, there is no material information after
+    /// the main render pass. Rough surfaces reflect less.
+    pub roughness: f32,
+    /// Factor applied to the reflections before blending them with the image.
+    pub intensity: f32,
+}
+
+impl Default for SsrParameters {
+    fn default() -> Self {
+        SsrParameters {
+            max_steps: 64,
+            max_distance: 20.0,
+            thickness: 0.2,
+            roughness: 0.3,
+            intensity: 1.0,
+        }
+    }
+}
+
+std140_struct! {
+    /// Uniforms of the screen-space reflections shader as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct SsrUniforms {
+        pub max_steps: u32,
+        pub max_distance: f32,
+        pub thickness: f32,
+        pub roughness: f32,
+        pub intensity: f32,
+        /// 0 until the history holds a reflected frame.
+        pub history_valid: u32,
+    }
+}
+
+/// Reflections of what is visible on screen, traced through the depth pyramid.
+///
+/// After the main render pass and the reduction of its depth, each pixel
+/// reconstructs its position and normal from the depth buffer and marches the
+/// reflected ray through the closest depths of the `DepthPyramid`, skipping
+/// the empty cells of the higher levels. The color at the hit is read from the
+/// output of the previous frame, where the velocity buffer of the motion blur
+/// says the surface was, so reflective surfaces also reflect each other after
+/// a few frames. Rays leaving the screen or missing fall back to the
+/// reflection probes blended for the model, if any.
+///
+/// The output is copied to the history at the end of the pass.
+pub struct ScreenSpaceReflections {
+    parameters: SsrParameters,
+    /// Whether the history was written since it was created.
+    history_valid: bool,
+    target: RenderTarget,
+    history: Option<Texture>,
+    sampler: vk::Sampler,
+    uniform_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl ScreenSpaceReflections {
+    /// Create the descriptor sets of the reflections pass.
+    ///
+    /// `target` must have a single HDR color attachment. Uniform buffers must
+    /// be host visible and coherent, one per swapchain image.
+    ///
+    /// The inputs must be set using `set_inputs` and the pipeline using
+    /// `set_pipeline` before recording the pass.
+    pub fn new(
+        device: &Device,
+        parameters: SsrParameters,
+        target: RenderTarget,
+        uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        let image_count = uniform_buffers.len();
+        let sampler = Self::create_sampler(device);
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        for (set, buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let buffer_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size_of::<SsrUniforms>() as _)
+                .build()];
+            let descriptor_writes = [vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(UNIFORMS_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_infos)
+                .build()];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        ScreenSpaceReflections {
+            parameters,
+            history_valid: false,
+            target,
+            history: None,
+            sampler,
+            uniform_buffers,
+            layout,
+            pool,
+            sets,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let image_binding = |binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        };
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(UNIFORMS_BINDING)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            image_binding(HDR_COLOR_BINDING),
+            image_binding(DEPTH_BINDING),
+            image_binding(DEPTH_PYRAMID_BINDING),
+            image_binding(VELOCITY_BINDING),
+            image_binding(HISTORY_BINDING),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count * 5,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl ScreenSpaceReflections {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.target.render_pass()
+    }
+
+    /// Get the HDR texture with the reflections.
+    ///
+    /// It is in the `SHADER_READ_ONLY_OPTIMAL` layout after the pass.
+    pub fn output(&self) -> Texture {
+        self.target.color(0)
+    }
+
+    /// Get the target so it can be resized.
+    ///
+    /// `set_inputs` must be called after resizing it.
+    pub fn target_mut(&mut self) -> &mut RenderTarget {
+        &mut self.target
+    }
+
+    /// Set the images the reflections are traced in and the history they are read from.
+    ///
+    /// `hdr` must be in `hdr_layout` and readable by fragment shaders when the
+    /// pass begins, like `depth` in `DEPTH_STENCIL_READ_ONLY_OPTIMAL`,
+    /// `pyramid` in `GENERAL` and `velocity` in `SHADER_READ_ONLY_OPTIMAL`.
+    /// `depth` is the depth buffer of the main render pass and `pyramid` its
+    /// `DepthPyramid`.
+    ///
+    /// `history` must be of the size and format of the target with the
+    /// `TRANSFER_DST` and `SAMPLED` usages, and be in `SHADER_READ_ONLY_OPTIMAL`.
+    /// The previous history is destroyed so the pass must not be in use.
+    pub fn set_inputs(
+        &mut self,
+        device: &Device,
+        (hdr, hdr_layout): (Texture, vk::ImageLayout),
+        depth: &Texture,
+        pyramid: Texture,
+        velocity: Texture,
+        history: Texture,
+    ) {
+        if let Some(mut previous) = self.history.replace(history) {
+            previous.destroy(device);
+        }
+        self.history_valid = false;
+
+        let image_info = |view, layout, sampler| {
+            [vk::DescriptorImageInfo::builder()
+                .image_layout(layout)
+                .image_view(view)
+                .sampler(sampler)
+                .build()]
+        };
+        let inputs = [
+            (
+                HDR_COLOR_BINDING,
+                image_info(hdr.view, hdr_layout, self.sampler),
+            ),
+            (
+                DEPTH_BINDING,
+                image_info(
+                    depth.view,
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                    self.sampler,
+                ),
+            ),
+            (
+                DEPTH_PYRAMID_BINDING,
+                image_info(
+                    pyramid.view,
+                    vk::ImageLayout::GENERAL,
+                    pyramid.sampler.unwrap(),
+                ),
+            ),
+            (
+                VELOCITY_BINDING,
+                image_info(
+                    velocity.view,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    self.sampler,
+                ),
+            ),
+            (
+                HISTORY_BINDING,
+                image_info(
+                    history.view,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    self.sampler,
+                ),
+            ),
+        ];
+        let descriptor_writes = self
+            .sets
+            .iter()
+            .flat_map(|set| {
+                inputs.iter().map(move |(binding, image_infos)| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*set)
+                        .dst_binding(*binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(image_infos)
+                        .build()
+                })
+            })
+            .collect::<Vec<_>>();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index`.
+    ///
+    /// The frame it is written for copies its output to the history, so the
+    /// next ones trace the reflections.
+    pub fn update(&mut self, device: &Device, image_index: usize) {
+        let parameters = self.parameters;
+        let uniforms = SsrUniforms {
+            max_steps: parameters.max_steps,
+            max_distance: parameters.max_distance,
+            thickness: parameters.thickness,
+            roughness: parameters.roughness,
+            intensity: parameters.intensity,
+            history_valid: self.history_valid as u32,
+        };
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+        self.history_valid = true;
+    }
+
+    /// Clear the history set with `set_inputs` to black and leave it in
+    /// `SHADER_READ_ONLY_OPTIMAL`.
+    ///
+    /// It must be recorded before the first pass using the history.
+    pub fn cmd_clear_history(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let history = self.history.as_ref().unwrap();
+        let range = history_range();
+        let mut image = TrackedImage::new(history.image, range, ImageState::undefined());
+        image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+        unsafe {
+            device.cmd_clear_color_image(
+                command_buffer,
+                history.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+                &[range],
+            )
+        };
+        image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+    }
+
+    /// Record the reflections pass of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`, and the
+    /// reflection probes in the object set if the pipeline falls back to them.
+    /// It must be recorded outside of any render pass, after the reduction of
+    /// the depth pyramid.
+    pub fn cmd_draw(&self, device: &Device, command_buffer: vk::CommandBuffer, image_index: usize) {
+        self.target.cmd_begin(device, command_buffer);
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                PASS_SET,
+                &[self.sets[image_index]],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+        self.target.cmd_end(device, command_buffer);
+        self.cmd_copy_history(device, command_buffer);
+    }
+
+    /// Copy the output of the pass to the history read by the next frame.
+    fn cmd_copy_history(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let output = self.target.color(0);
+        let history = self.history.as_ref().unwrap();
+        let range = history_range();
+        let mut output_image = TrackedImage::new(
+            output.image,
+            range,
+            ImageState {
+                layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            },
+        );
+        let mut history_image = TrackedImage::new(
+            history.image,
+            range,
+            ImageState::for_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        );
+        output_image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+        history_image.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let extent = self.target.extent();
+        let region = vk::ImageCopy::builder()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .build();
+        unsafe {
+            device.cmd_copy_image(
+                command_buffer,
+                output.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                history.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            )
+        };
+
+        for image in [&mut output_image, &mut history_image].iter_mut() {
+            image.transition_to(
+                device,
+                command_buffer,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            );
+        }
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        if let Some(mut history) = self.history.take() {
+            history.destroy(device);
+        }
+        self.target.destroy(device);
+    }
+}
+
+/// Get the subresource range of the single level of the output and the history.
+fn history_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
@@ -0,0 +1,127 @@
+use cgmath::{Matrix4, Vector2, Vector3, Vector4};
+
+/// Type that can be a member of a uniform block laid out with the std140 rules.
+///
+/// Implemented for the scalars, vectors and matrices matching a GLSL type, and
+/// for the structs declared with `std140_struct!`.
+pub trait Std140: Copy {
+    /// Alignment of the type in a std140 block.
+    const ALIGNMENT: usize;
+    /// Space taken by the type in a std140 block, before the next member.
+    const SIZE: usize;
+}
+
+macro_rules! impl_std140 {
+    ($($ty:ty => ($alignment:expr, $size:expr)),* $(,)?) => {
+        $(
+            impl Std140 for $ty {
+                const ALIGNMENT: usize = $alignment;
+                const SIZE: usize = $size;
+            }
+        )*
+    };
+}
+
+impl_std140! {
+    f32 => (4, 4),
+    u32 => (4, 4),
+    i32 => (4, 4),
+    [f32; 2] => (8, 8),
+    [u32; 2] => (8, 8),
+    Vector2<f32> => (8, 8),
+    [f32; 3] => (16, 12),
+    Vector3<f32> => (16, 12),
+    [f32; 4] => (16, 16),
+    [u32; 4] => (16, 16),
+    Vector4<f32> => (16, 16),
+    Matrix4<f32> => (16, 64),
+}
+
+/// Arrays of vec4, whose stride is already the one of std140 arrays.
+impl<const N: usize> Std140 for [[f32; 4]; N] {
+    const ALIGNMENT: usize = 16;
+    const SIZE: usize = 16 * N;
+}
+
+impl<const N: usize> Std140 for [[u32; 4]; N] {
+    const ALIGNMENT: usize = 16;
+    const SIZE: usize = 16 * N;
+}
+
+impl<const N: usize> Std140 for [Matrix4<f32>; N] {
+    const ALIGNMENT: usize = 16;
+    const SIZE: usize = 64 * N;
+}
+
+/// Round `size` up to the alignment of std140 structs.
+pub const fn struct_size(size: usize) -> usize {
+    size.div_ceil(16) * 16
+}
+
+/// Declare a struct written to a uniform buffer and check its layout.
+///
+/// The struct is declared with `#[repr(C)]` so its members are not reordered,
+/// and it fails to compile if a member is not aligned as std140 requires or
+/// overlaps the space std140 reserves for the previous one. For example, a
+/// `[f32; 3]` must start on 16 bytes, and a `[f32; 2]` following a `[f32; 3]`
+/// must be padded to the next 16 bytes while a `f32` can directly follow it.
+///
+/// All members must implement `Std140`, and the struct then implements it too
+/// so it can be nested in another one.
+macro_rules! std140_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::std140::Std140 for $name {
+            const ALIGNMENT: usize = 16;
+            const SIZE: usize = $crate::std140::struct_size(::std::mem::size_of::<$name>());
+        }
+
+        const _: () = {
+            $(
+                assert!(
+                    ::std::mem::offset_of!($name, $field)
+                        % <$ty as $crate::std140::Std140>::ALIGNMENT
+                        == 0,
+                    concat!(
+                        stringify!($name),
+                        "::",
+                        stringify!($field),
+                        " is not aligned as std140 requires"
+                    )
+                );
+            )*
+            let offsets: &[usize] = &[$(::std::mem::offset_of!($name, $field)),*];
+            let sizes: &[usize] = &[$(<$ty as $crate::std140::Std140>::SIZE),*];
+            let mut i = 1;
+            while i < offsets.len() {
+                assert!(
+                    offsets[i - 1] + sizes[i - 1] <= offsets[i],
+                    concat!(
+                        "A member of ",
+                        stringify!($name),
+                        " overlaps the space std140 reserves for the previous one"
+                    )
+                );
+                i += 1;
+            }
+        };
+    };
+}
+
+pub(crate) use std140_struct;
@@ -0,0 +1,575 @@
+use crate::{
+    buffer::Buffer,
+    context::*,
+    frame_layout::{write_model_texture, FRAME_SET, PASS_SET},
+    gpu_device::Device,
+    lifetime,
+    std140::std140_struct,
+    texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk, Instance};
+use cgmath::{Matrix4, Vector3};
+use std::{ffi::c_void, mem::size_of};
+
+/// Number of views rendered by the stereo pass, one per eye.
+///
+/// Must match the size of the arrays of `stereo.vert`.
+pub const EYE_COUNT: u32 = 2;
+
+/// Distance between the eyes in world units.
+pub const EYE_SEPARATION: f32 = 0.06;
+
+const UNIFORMS_BINDING: u32 = 0;
+const TEXTURE_BINDING: u32 = 1;
+const EYES_BINDING: u32 = 0;
+
+/// Check if `device` supports rendering several views in one pass.
+///
+/// Vulkan 1.1 must be supported by the instance and the device since
+/// multiview is a core feature of Vulkan 1.1.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut multiview_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { get_physical_device_features2(instance, device, &mut features) };
+
+    multiview_features.multiview == vk::TRUE
+}
+
+/// Build the multiview features to enable at device creation.
+pub fn required_features() -> vk::PhysicalDeviceMultiviewFeatures {
+    vk::PhysicalDeviceMultiviewFeatures::builder()
+        .multiview(true)
+        .build()
+}
+
+/// Get the size of the image of each eye for a screen of `extent`.
+///
+/// Eyes are displayed side by side so each covers half of the screen.
+pub fn eye_extent(extent: vk::Extent2D) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width / 2).max(1),
+        height: extent.height,
+    }
+}
+
+std140_struct! {
+    /// Uniforms of the stereo shaders as laid out in the uniform buffer.
+    #[derive(Clone, Copy)]
+    #[allow(dead_code)]
+    pub struct StereoUniforms {
+        pub model: Matrix4<f32>,
+        /// View matrix of each eye, indexed by `gl_ViewIndex`.
+        pub views: [Matrix4<f32>; EYE_COUNT as usize],
+        /// Projection matrix of each eye, indexed by `gl_ViewIndex`.
+        pub projs: [Matrix4<f32>; EYE_COUNT as usize],
+    }
+}
+
+impl StereoUniforms {
+    /// Derive the matrices of both eyes from the matrices of the main camera.
+    ///
+    /// The eyes are moved apart along the horizontal axis of the camera and
+    /// look in the same direction. Since each eye covers half of the width of
+    /// the screen, its horizontal field of view is half of the main one.
+    pub fn new(model: Matrix4<f32>, view: Matrix4<f32>, proj: Matrix4<f32>) -> Self {
+        let offset = Vector3::new(EYE_SEPARATION * 0.5, 0.0, 0.0);
+        let eye_proj = Matrix4::from_nonuniform_scale(2.0, 1.0, 1.0) * proj;
+        StereoUniforms {
+            model,
+            views: [
+                Matrix4::from_translation(offset) * view,
+                Matrix4::from_translation(-offset) * view,
+            ],
+            projs: [eye_proj; EYE_COUNT as usize],
+        }
+    }
+}
+
+/// Stereo rendering of the model using multiview.
+///
+/// Before the main render pass, the model is rendered once in a multiview
+/// render pass which broadcasts the draw to both layers of a layered color
+/// and depth attachment. The vertex shader selects the matrices of the eye
+/// using `gl_ViewIndex`. The eyes are then drawn side by side over the main
+/// view, at the end of the first subpass of the main render pass.
+///
+/// The eye pass uses its own uniform buffers in place of the frame uniform
+/// buffers, so it has its own set 0. The composite pass samples the layered
+/// color attachment in its own set.
+pub struct Stereo {
+    extent: vk::Extent2D,
+    color: Texture,
+    depth: Texture,
+    sampler: vk::Sampler,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    uniform_buffers: Vec<Buffer>,
+    composite_layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    eye_sets: Vec<vk::DescriptorSet>,
+    composite_set: vk::DescriptorSet,
+    eye_pipeline: vk::Pipeline,
+    eye_pipeline_layout: vk::PipelineLayout,
+    composite_pipeline: vk::Pipeline,
+    composite_pipeline_layout: vk::PipelineLayout,
+}
+
+impl Stereo {
+    /// Create the render pass of the eyes and the descriptor sets of both passes.
+    ///
+    /// `frame_layout` is the layout of the frame descriptor sets and `texture` the
+    /// texture of the model. `color` and `depth` are the attachments of the eyes,
+    /// 2D arrays of `EYE_COUNT` layers of `extent`. Uniform buffers must be host
+    /// visible and coherent, one per swapchain image.
+    ///
+    /// The pipelines must be set using `set_pipelines` before recording draws.
+    pub fn new(
+        device: &Device,
+        frame_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        (color, color_format): (Texture, vk::Format),
+        (depth, depth_format): (Texture, vk::Format),
+        extent: vk::Extent2D,
+        uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        let image_count = uniform_buffers.len();
+        let sampler = Self::create_sampler(device);
+        let render_pass = Self::create_render_pass(device, color_format, depth_format);
+        let composite_layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let allocate_sets = |layout, count| {
+            let layouts = vec![layout; count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+        let eye_sets = allocate_sets(frame_layout, image_count);
+        let composite_set = allocate_sets(composite_layout, 1)[0];
+
+        let texture_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build()];
+        for (set, buffer) in eye_sets.iter().zip(uniform_buffers.iter()) {
+            let buffer_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size_of::<StereoUniforms>() as _)
+                .build()];
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(UNIFORMS_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&buffer_infos)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(TEXTURE_BINDING)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&texture_infos)
+                    .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        let mut stereo = Stereo {
+            extent,
+            color,
+            depth,
+            sampler,
+            render_pass,
+            framebuffer: vk::Framebuffer::null(),
+            uniform_buffers,
+            composite_layout,
+            pool,
+            eye_sets,
+            composite_set,
+            eye_pipeline: vk::Pipeline::null(),
+            eye_pipeline_layout: vk::PipelineLayout::null(),
+            composite_pipeline: vk::Pipeline::null(),
+            composite_pipeline_layout: vk::PipelineLayout::null(),
+        };
+        stereo.set_textures(device, extent, color, depth);
+        stereo
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    fn create_render_pass(
+        device: &Device,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+    ) -> vk::RenderPass {
+        let attachment_descs = [
+            vk::AttachmentDescription::builder()
+                .format(color_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+            vk::AttachmentDescription::builder()
+                .format(depth_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .build(),
+        ];
+
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+        let subpass_descs = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build()];
+
+        // The previous frame might still be sampling the eyes or writing their depth.
+        let begin_dep = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build();
+        // The main render pass samples the eyes once they are rendered.
+        let end_dep = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+        let subpass_deps = [begin_dep, end_dep];
+
+        // Each draw of the subpass is broadcast to all the eyes
+        let view_masks = [(1 << EYE_COUNT) - 1];
+        // The eyes see mostly the same geometry so the driver may render them together
+        let correlation_masks = [(1 << EYE_COUNT) - 1];
+        let mut multiview_info = vk::RenderPassMultiviewCreateInfo::builder()
+            .view_masks(&view_masks)
+            .correlation_masks(&correlation_masks)
+            .build();
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descs)
+            .subpasses(&subpass_descs)
+            .dependencies(&subpass_deps)
+            .push_next(&mut multiview_info)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(EYES_BINDING)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count + 1,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count + 1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    /// Create the framebuffer of the eyes and bind their color in the composite set.
+    fn set_textures(
+        &mut self,
+        device: &Device,
+        extent: vk::Extent2D,
+        color: Texture,
+        depth: Texture,
+    ) {
+        let attachments = [color.view, depth.view];
+        // With multiview, the layers are selected by the view mask, not the framebuffer
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1)
+            .build();
+        self.framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() };
+
+        let image_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(color.view)
+            .sampler(self.sampler)
+            .build()];
+        let descriptor_writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.composite_set)
+            .dst_binding(EYES_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build()];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        self.extent = extent;
+        self.color = color;
+        self.depth = depth;
+    }
+}
+
+impl Stereo {
+    pub fn composite_layout(&self) -> vk::DescriptorSetLayout {
+        self.composite_layout
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    /// Get the size of the image of each eye.
+    pub fn eye_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Get the layered color attachment of the eyes and its extent.
+    #[cfg(feature = "openxr")]
+    pub fn eyes(&self) -> (vk::Image, vk::Extent2D) {
+        (self.color.image, self.extent)
+    }
+
+    /// Replace the attachments of the eyes by `color` and `depth` of `extent`.
+    ///
+    /// They must be created like the textures given to `new`. The previous
+    /// textures are destroyed so the eyes must not be in use.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        extent: vk::Extent2D,
+        color: Texture,
+        depth: Texture,
+    ) {
+        self.destroy_textures(device);
+        self.set_textures(device, extent, color, depth);
+    }
+
+    /// Set the pipelines used to draw the eyes and to draw them on screen.
+    ///
+    /// The eye pipeline must be created for the render pass of the eyes. The
+    /// stereo takes ownership of the pipelines and their layouts.
+    pub fn set_pipelines(
+        &mut self,
+        eye: (vk::Pipeline, vk::PipelineLayout),
+        composite: (vk::Pipeline, vk::PipelineLayout),
+    ) {
+        let (eye_pipeline, eye_pipeline_layout) = eye;
+        self.eye_pipeline = eye_pipeline;
+        self.eye_pipeline_layout = eye_pipeline_layout;
+        let (composite_pipeline, composite_pipeline_layout) = composite;
+        self.composite_pipeline = composite_pipeline;
+        self.composite_pipeline_layout = composite_pipeline_layout;
+    }
+
+    /// Write the uniform buffer of the swapchain image `image_index`.
+    pub fn update(&self, device: &Device, image_index: usize, uniforms: StereoUniforms) {
+        self.uniform_buffers[image_index].write(device, 0, &[uniforms]);
+    }
+
+    /// Record the eye pass of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// It must be recorded outside of any render pass, before the main render pass.
+    pub fn cmd_render_eyes(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        vertex_buffer: Buffer,
+        index_buffer: Buffer,
+        index_count: u32,
+    ) {
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&clear_values)
+            .build();
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.eye_pipeline,
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.eye_pipeline_layout,
+                FRAME_SET,
+                &self.eye_sets[image_index..=image_index],
+                &[],
+            );
+            // A single draw renders both eyes
+            device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    /// Record the draw of the eyes side by side in `command_buffer`.
+    ///
+    /// It must be recorded in the first subpass of the main render pass, after
+    /// the main view.
+    pub fn cmd_draw_composite(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.composite_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.composite_pipeline_layout,
+                PASS_SET,
+                &[self.composite_set],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    /// Destroy the pipelines and their layouts.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipelines(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.eye_pipeline);
+            device.destroy_pipeline_layout(self.eye_pipeline_layout, None);
+            lifetime::destroy_pipeline(device, self.composite_pipeline);
+            device.destroy_pipeline_layout(self.composite_pipeline_layout, None);
+        }
+        self.eye_pipeline = vk::Pipeline::null();
+        self.eye_pipeline_layout = vk::PipelineLayout::null();
+        self.composite_pipeline = vk::Pipeline::null();
+        self.composite_pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    fn destroy_textures(&mut self, device: &Device) {
+        unsafe { device.destroy_framebuffer(self.framebuffer, None) };
+        self.framebuffer = vk::Framebuffer::null();
+        self.color.destroy(device);
+        self.depth.destroy(device);
+    }
+
+    /// Replace the texture of the model by `texture` after it was reloaded.
+    ///
+    /// The sets must not be in use.
+    pub fn set_texture(&self, device: &Device, texture: Texture) {
+        write_model_texture(device, &self.eye_sets, texture);
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipelines(device);
+        self.destroy_textures(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.composite_layout, None);
+            device.destroy_render_pass(self.render_pass, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
@@ -0,0 +1,566 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    geometry_pool::{GeometryBlock, GeometryPool},
+    gpu_device::Device,
+    lifetime,
+    math::{clamp, Aabb, Frustum},
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Point3, Vector3};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    fs,
+    io::{self, ErrorKind},
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// Number of chunks along each side of the streamed world.
+const GRID_SIZE: u32 = 128;
+
+/// Width and depth of a chunk in world units.
+const CHUNK_SIZE: f32 = 1.0;
+
+/// Number of quads along each side of a chunk.
+const CHUNK_QUADS: u32 = 16;
+
+const CHUNK_VERTICES: u32 = (CHUNK_QUADS + 1) * (CHUNK_QUADS + 1);
+const CHUNK_INDICES: u32 = CHUNK_QUADS * CHUNK_QUADS * 6;
+
+/// Horizontal distance to the camera within which chunks are loaded.
+const LOAD_DISTANCE: f32 = 6.0;
+
+/// Horizontal distance to the camera beyond which chunks are unloaded.
+///
+/// It is larger than `LOAD_DISTANCE` so the chunks at the border are not
+/// loaded and unloaded again each time the camera moves a bit.
+const UNLOAD_DISTANCE: f32 = 8.0;
+
+/// Number of blocks of the geometry pool, more than the chunks within `UNLOAD_DISTANCE`.
+const BLOCK_COUNT: u32 = 256;
+
+/// Altitude of the streamed ground, below the other objects.
+const ELEVATION: f32 = -2.5;
+
+/// First bytes of the chunk files.
+const CHUNK_MAGIC: &[u8; 4] = b"VTC1";
+
+/// Position of a chunk in the grid.
+type ChunkCoords = (u32, u32);
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub struct StreamedVertex {
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl StreamedVertex {
+    pub fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<StreamedVertex>() as _)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        let attribute = |location, offset| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(location)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(offset)
+                .build()
+        };
+        [attribute(0, 0), attribute(1, 12), attribute(2, 24)]
+    }
+}
+
+/// Geometry of a chunk, in world space.
+struct ChunkMesh {
+    vertices: Vec<StreamedVertex>,
+    indices: Vec<u32>,
+    bounds: Aabb,
+}
+
+/// Chunk whose geometry is in the pool.
+struct ResidentChunk {
+    block: GeometryBlock,
+    index_count: u32,
+    bounds: Aabb,
+}
+
+/// Ground of a world too large to keep in memory, streamed around the camera.
+///
+/// The world is a grid of chunks stored in compressed files on disk: the
+/// positions are quantized to 16 bits in the bounds of the chunk, the normals
+/// and colors to 8 bits and the indices are delta encoded as variable length
+/// integers, about a third of the size of the vertices and indices. Missing
+/// files are generated and written the first time they are loaded.
+///
+/// Each frame, `update` requests the chunks closer to the camera than
+/// `LOAD_DISTANCE` to a loading thread which reads and decodes them. Loaded
+/// chunks are written in a block of a `GeometryPool`, and the chunks further
+/// than `UNLOAD_DISTANCE` free their block. Since command buffers are recorded
+/// once, each block is drawn with an indirect draw written by `update`, with
+/// no instance when the block is unused or its chunk is outside the frustum.
+pub struct MeshStreamer {
+    pool: GeometryPool,
+    resident: HashMap<ChunkCoords, ResidentChunk>,
+    /// Chunks requested to the loading thread and not received yet.
+    pending: HashSet<ChunkCoords>,
+    indirect_buffers: Vec<Buffer>,
+    requests: Option<Sender<ChunkCoords>>,
+    loaded: Receiver<(ChunkCoords, ChunkMesh)>,
+    handle: Option<JoinHandle<()>>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl MeshStreamer {
+    /// Start the thread loading the chunks stored in `directory`.
+    ///
+    /// The buffers of `pool` must have been created for the sizes returned by
+    /// `MeshStreamer::pool_blocks`, and `indirect_buffers` must be host visible
+    /// and coherent buffers of `MeshStreamer::indirect_buffer_size()` bytes, one
+    /// per swapchain image.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(directory: PathBuf, pool: GeometryPool, indirect_buffers: Vec<Buffer>) -> Self {
+        let (requests, receiver) = mpsc::channel::<ChunkCoords>();
+        let (sender, loaded) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("streaming".to_owned())
+            .spawn(move || {
+                log::debug!("Streaming thread started.");
+                if let Err(error) = fs::create_dir_all(&directory) {
+                    log::error!("Failed to create {}. Cause: {}", directory.display(), error);
+                }
+                for coords in receiver {
+                    let mesh = match load_chunk(&directory, coords) {
+                        Ok(mesh) => mesh,
+                        Err(error) => {
+                            log::warn!("Failed to load chunk {:?}. Cause: {}", coords, error);
+                            generate_chunk(coords)
+                        }
+                    };
+                    if sender.send((coords, mesh)).is_err() {
+                        break;
+                    }
+                }
+                log::debug!("Streaming thread stopped.");
+            })
+            .unwrap();
+
+        MeshStreamer {
+            pool,
+            resident: HashMap::new(),
+            pending: HashSet::new(),
+            indirect_buffers,
+            requests: Some(requests),
+            loaded,
+            handle: Some(handle),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Number of blocks of the pool, and number of vertices and indices of each block.
+    pub fn pool_blocks() -> (u32, u32, u32) {
+        (BLOCK_COUNT, CHUNK_VERTICES, CHUNK_INDICES)
+    }
+
+    /// Size in bytes of each indirect buffer.
+    pub fn indirect_buffer_size() -> vk::DeviceSize {
+        (size_of::<vk::DrawIndexedIndirectCommand>() as u32 * BLOCK_COUNT) as _
+    }
+}
+
+impl MeshStreamer {
+    /// Get the number of chunks in the pool and the number being loaded.
+    pub fn chunk_counts(&self) -> (usize, usize) {
+        (self.resident.len(), self.pending.len())
+    }
+
+    /// Set the pipeline used to draw the chunks.
+    ///
+    /// The streamer takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Upload the loaded chunks, stream the chunks around `camera_position`
+    /// and cull the resident ones against `frustum`.
+    ///
+    /// The draws are written in the indirect buffer of the swapchain image
+    /// `image_index`, so it must be called once per frame, after waiting for
+    /// its fence and before submitting its command buffer.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        image_index: usize,
+        camera_position: Point3<f32>,
+        frustum: &Frustum,
+    ) {
+        self.pool.begin_frame();
+
+        // Chunks unloaded while they were loading are not pending anymore
+        for (coords, mesh) in self.loaded.try_iter() {
+            if !self.pending.remove(&coords) {
+                continue;
+            }
+            // When the pool is full, the chunk is requested again later
+            if let Some(block) = self.pool.allocate() {
+                self.pool
+                    .write(device, block, &mesh.vertices, &mesh.indices);
+                let chunk = ResidentChunk {
+                    block,
+                    index_count: mesh.indices.len() as _,
+                    bounds: mesh.bounds,
+                };
+                self.resident.insert(coords, chunk);
+            }
+        }
+
+        let distance = |coords| horizontal_distance(chunk_center(coords), camera_position);
+        let pool = &mut self.pool;
+        self.resident.retain(|coords, chunk| {
+            let keep = distance(*coords) <= UNLOAD_DISTANCE;
+            if !keep {
+                pool.free(chunk.block);
+            }
+            keep
+        });
+        self.pending
+            .retain(|coords| distance(*coords) <= UNLOAD_DISTANCE);
+
+        // Request the closest chunks first, as long as the pool can hold them
+        let mut requested = chunks_around(camera_position, LOAD_DISTANCE)
+            .filter(|coords| !self.resident.contains_key(coords) && !self.pending.contains(coords))
+            .collect::<Vec<_>>();
+        requested.sort_by(|a, b| distance(*a).partial_cmp(&distance(*b)).unwrap());
+        let available = self
+            .pool
+            .free_block_count()
+            .saturating_sub(self.pending.len());
+        if let Some(requests) = self.requests.as_ref() {
+            for coords in requested.into_iter().take(available) {
+                if requests.send(coords).is_ok() {
+                    self.pending.insert(coords);
+                }
+            }
+        }
+
+        let mut draws = vec![vk::DrawIndexedIndirectCommand::default(); BLOCK_COUNT as usize];
+        for chunk in self.resident.values() {
+            draws[chunk.block.index as usize] = vk::DrawIndexedIndirectCommand {
+                index_count: chunk.index_count,
+                instance_count: frustum.intersects_aabb(&chunk.bounds) as _,
+                first_index: chunk.block.first_index,
+                vertex_offset: chunk.block.vertex_offset,
+                first_instance: 0,
+            };
+        }
+        self.indirect_buffers[image_index].write(device, 0, &draws);
+    }
+
+    /// Record the draws of the blocks of the swapchain image `image_index` with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    ///
+    /// Each block is drawn with its own indirect draw so the `multiDrawIndirect`
+    /// feature is not required.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder, image_index: usize) {
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[self.pool.vertex_buffer().buffer], &[0]);
+        encoder.bind_index_buffer(self.pool.index_buffer().buffer, 0, vk::IndexType::UINT32);
+        for i in 0..BLOCK_COUNT {
+            unsafe {
+                encoder.device().cmd_draw_indexed_indirect(
+                    encoder.command_buffer(),
+                    self.indirect_buffers[image_index].buffer,
+                    (i * stride) as _,
+                    1,
+                    stride,
+                )
+            };
+        }
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    /// Stop the loading thread and destroy the pool and the indirect buffers.
+    pub fn destroy(&mut self, device: &Device) {
+        // Closing the channel stops the thread after its current chunk
+        self.requests.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        self.destroy_pipeline(device);
+        self.pool.destroy(device);
+        self.indirect_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
+
+/// Get the world space position of the corner of the chunk at `coords` with
+/// the smallest coordinates.
+fn chunk_origin((x, z): ChunkCoords) -> (f32, f32) {
+    let half_size = GRID_SIZE as f32 * CHUNK_SIZE * 0.5;
+    (
+        x as f32 * CHUNK_SIZE - half_size,
+        z as f32 * CHUNK_SIZE - half_size,
+    )
+}
+
+fn chunk_center(coords: ChunkCoords) -> Point3<f32> {
+    let (x, z) = chunk_origin(coords);
+    Point3::new(x + CHUNK_SIZE * 0.5, ELEVATION, z + CHUNK_SIZE * 0.5)
+}
+
+fn horizontal_distance(a: Point3<f32>, b: Point3<f32>) -> f32 {
+    Vector3::new(a.x - b.x, 0.0, a.z - b.z).magnitude()
+}
+
+/// Get the coordinates of the chunks of the grid whose center is within
+/// `distance` of `position`, ignoring the altitude.
+fn chunks_around(position: Point3<f32>, distance: f32) -> impl Iterator<Item = ChunkCoords> {
+    let half_size = GRID_SIZE as f32 * CHUNK_SIZE * 0.5;
+    let range = |center: f32| {
+        let min = ((center - distance + half_size) / CHUNK_SIZE)
+            .floor()
+            .max(0.0) as u32;
+        let max = ((center + distance + half_size) / CHUNK_SIZE)
+            .ceil()
+            .max(0.0) as u32;
+        min..max.min(GRID_SIZE)
+    };
+    let (xs, zs) = (range(position.x), range(position.z));
+    xs.flat_map(move |x| zs.clone().map(move |z| (x, z)))
+        .filter(move |coords| horizontal_distance(chunk_center(*coords), position) <= distance)
+}
+
+/// Get the height of the ground at `(x, z)` and its gradient.
+fn height(x: f32, z: f32) -> (f32, [f32; 2]) {
+    let (a, b) = (0.6 * (0.35 * x).sin(), (0.25 * z).cos());
+    let c = 0.2 * (1.3 * x + 0.9 * z).sin();
+    let dc = 0.2 * (1.3 * x + 0.9 * z).cos();
+    (
+        ELEVATION + a * b + c,
+        [
+            0.6 * 0.35 * (0.35 * x).cos() * b + 1.3 * dc,
+            -a * 0.25 * (0.25 * z).sin() + 0.9 * dc,
+        ],
+    )
+}
+
+/// Generate the geometry of the chunk at `coords` from `height`.
+fn generate_chunk(coords: ChunkCoords) -> ChunkMesh {
+    let (origin_x, origin_z) = chunk_origin(coords);
+    let step = CHUNK_SIZE / CHUNK_QUADS as f32;
+    let mut bounds = Aabb::empty();
+    let mut vertices = Vec::with_capacity(CHUNK_VERTICES as _);
+    for j in 0..=CHUNK_QUADS {
+        for i in 0..=CHUNK_QUADS {
+            let (x, z) = (origin_x + i as f32 * step, origin_z + j as f32 * step);
+            let (y, [dx, dz]) = height(x, z);
+            let normal = Vector3::new(-dx, 1.0, -dz).normalize();
+            // From grass in the valleys to dry soil on the hills
+            let t = clamp((y - ELEVATION + 0.8) / 1.6, 0.0, 1.0);
+            let color = [0.25 + 0.3 * t, 0.45 - 0.05 * t, 0.15 + 0.1 * t];
+            bounds.expand(Point3::new(x, y, z));
+            vertices.push(StreamedVertex {
+                pos: [x, y, z],
+                normal: normal.into(),
+                color,
+            });
+        }
+    }
+
+    let row = CHUNK_QUADS + 1;
+    let indices = (0..CHUNK_QUADS)
+        .flat_map(|j| (0..CHUNK_QUADS).map(move |i| j * row + i))
+        .flat_map(|first| {
+            vec![
+                first,
+                first + row,
+                first + 1,
+                first + 1,
+                first + row,
+                first + row + 1,
+            ]
+        })
+        .collect();
+
+    ChunkMesh {
+        vertices,
+        indices,
+        bounds,
+    }
+}
+
+fn chunk_path(directory: &Path, (x, z): ChunkCoords) -> PathBuf {
+    directory.join(format!("chunk_{}_{}.bin", x, z))
+}
+
+/// Read the chunk at `coords` from `directory`, or generate and write it if it
+/// is not there yet.
+fn load_chunk(directory: &Path, coords: ChunkCoords) -> io::Result<ChunkMesh> {
+    let path = chunk_path(directory, coords);
+    match fs::read(&path) {
+        Ok(bytes) => decode_chunk(&bytes)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "corrupted chunk")),
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            let mesh = generate_chunk(coords);
+            fs::write(&path, encode_chunk(&mesh))?;
+            Ok(mesh)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Encode `mesh` in the compressed format of the chunk files.
+///
+/// After the magic, the vertex and index counts and the bounds, each vertex
+/// has a position quantized to 16 bits per axis in the bounds, a normal
+/// quantized to 8 bits per axis and a color of 8 bits per channel. The
+/// indices are stored as the zigzag encoded difference with the previous
+/// index, in LEB128.
+fn encode_chunk(mesh: &ChunkMesh) -> Vec<u8> {
+    let mut bytes = CHUNK_MAGIC.to_vec();
+    bytes.extend_from_slice(&(mesh.vertices.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(mesh.indices.len() as u32).to_le_bytes());
+    let (min, max) = (mesh.bounds.min, mesh.bounds.max);
+    for value in &[min.x, min.y, min.z, max.x, max.y, max.z] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let extent = [max.x - min.x, max.y - min.y, max.z - min.z];
+    let origin = [min.x, min.y, min.z];
+    for vertex in &mesh.vertices {
+        for axis in 0..3 {
+            let t = (vertex.pos[axis] - origin[axis]) / extent[axis].max(f32::EPSILON);
+            let quantized = (t * u16::MAX as f32).round() as u16;
+            bytes.extend_from_slice(&quantized.to_le_bytes());
+        }
+        for axis in 0..3 {
+            bytes.push((vertex.normal[axis] * 127.0).round() as i8 as u8);
+        }
+        for channel in 0..3 {
+            bytes.push((vertex.color[channel] * 255.0).round() as u8);
+        }
+    }
+
+    let mut previous = 0i64;
+    for &index in &mesh.indices {
+        let delta = index as i64 - previous;
+        previous = index as i64;
+        let mut zigzag = ((delta << 1) ^ (delta >> 63)) as u64;
+        loop {
+            let byte = (zigzag & 0x7f) as u8;
+            zigzag >>= 7;
+            if zigzag == 0 {
+                bytes.push(byte);
+                break;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+    bytes
+}
+
+/// Decode a chunk encoded by `encode_chunk`, or get `None` if `bytes` is not a valid chunk.
+fn decode_chunk(bytes: &[u8]) -> Option<ChunkMesh> {
+    let mut cursor = bytes.strip_prefix(&CHUNK_MAGIC[..])?;
+    let mut take = |count: usize| {
+        if cursor.len() < count {
+            return None;
+        }
+        let (taken, rest) = cursor.split_at(count);
+        cursor = rest;
+        Some(taken)
+    };
+    let read_u32 = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+    let read_f32 = |bytes: &[u8]| f32::from_le_bytes(bytes.try_into().unwrap());
+
+    let vertex_count = read_u32(take(4)?) as usize;
+    let index_count = read_u32(take(4)?) as usize;
+    if vertex_count > CHUNK_VERTICES as usize || index_count > CHUNK_INDICES as usize {
+        return None;
+    }
+    let mut bounds_values = [0.0; 6];
+    for value in bounds_values.iter_mut() {
+        *value = read_f32(take(4)?);
+    }
+    let [min_x, min_y, min_z, max_x, max_y, max_z] = bounds_values;
+    let bounds = Aabb {
+        min: Point3::new(min_x, min_y, min_z),
+        max: Point3::new(max_x, max_y, max_z),
+    };
+
+    let origin = [min_x, min_y, min_z];
+    let extent = [max_x - min_x, max_y - min_y, max_z - min_z];
+    let mut vertices = Vec::with_capacity(vertex_count);
+    for _ in 0..vertex_count {
+        let vertex = take(12)?;
+        let mut pos = [0.0; 3];
+        let mut normal = [0.0; 3];
+        let mut color = [0.0; 3];
+        for axis in 0..3 {
+            let quantized = u16::from_le_bytes([vertex[axis * 2], vertex[axis * 2 + 1]]);
+            pos[axis] = origin[axis] + quantized as f32 / u16::MAX as f32 * extent[axis];
+            normal[axis] = vertex[6 + axis] as i8 as f32 / 127.0;
+            color[axis] = vertex[9 + axis] as f32 / 255.0;
+        }
+        vertices.push(StreamedVertex { pos, normal, color });
+    }
+
+    let mut indices = Vec::with_capacity(index_count);
+    let mut previous = 0i64;
+    for _ in 0..index_count {
+        let mut zigzag = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = take(1)?[0];
+            zigzag |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if shift > 35 {
+                return None;
+            }
+        }
+        let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+        previous += delta;
+        if previous < 0 || previous >= vertex_count as i64 {
+            return None;
+        }
+        indices.push(previous as u32);
+    }
+
+    Some(ChunkMesh {
+        vertices,
+        indices,
+        bounds,
+    })
+}
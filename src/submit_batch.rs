@@ -0,0 +1,107 @@
+use crate::{gpu_device::Device, sync2};
+use ash::{prelude::VkResult, version::DeviceV1_0, vk};
+
+/// Command buffers of a frame and the semaphores they wait for and signal,
+/// submitted to the queue at once.
+///
+/// The passes and uploads of the frame add their command buffers in the
+/// order they must execute instead of submitting them themselves, so the
+/// whole frame is a single `vkQueueSubmit` signaling the fence of the frame.
+#[derive(Debug, Default)]
+pub struct SubmitBatch {
+    fence: vk::Fence,
+    wait_semaphores: Vec<(vk::Semaphore, vk::PipelineStageFlags)>,
+    command_buffers: Vec<vk::CommandBuffer>,
+    signal_semaphores: Vec<(vk::Semaphore, vk::PipelineStageFlags)>,
+}
+
+/// What was submitted for a frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SubmitStats {
+    pub command_buffers: usize,
+    pub wait_semaphores: usize,
+    pub signal_semaphores: usize,
+}
+
+impl SubmitBatch {
+    /// Start the batch of a frame whose submission signals `fence`.
+    ///
+    /// `fence` must have been waited for and reset.
+    pub fn new(fence: vk::Fence) -> Self {
+        SubmitBatch {
+            fence,
+            ..Default::default()
+        }
+    }
+
+    /// Get the fence signaled once the commands of the batch completed.
+    pub fn fence(&self) -> vk::Fence {
+        self.fence
+    }
+
+    /// Make the commands of the batch at `stage` wait for `semaphore`.
+    pub fn wait(&mut self, semaphore: vk::Semaphore, stage: vk::PipelineStageFlags) {
+        self.wait_semaphores.push((semaphore, stage));
+    }
+
+    /// Add `command_buffer` after the command buffers already in the batch.
+    pub fn push(&mut self, command_buffer: vk::CommandBuffer) {
+        self.command_buffers.push(command_buffer);
+    }
+
+    /// Signal `semaphore` once the commands of the batch are done with `stage`.
+    pub fn signal(&mut self, semaphore: vk::Semaphore, stage: vk::PipelineStageFlags) {
+        self.signal_semaphores.push((semaphore, stage));
+    }
+
+    /// Check if the commands submitted with the batch of a previous frame
+    /// whose fence was `fence` completed, without waiting.
+    ///
+    /// The fence of this frame was waited for before it was reset, so the
+    /// last batch submitted with it completed. A null fence has nothing to
+    /// wait for.
+    pub fn is_complete(&self, device: &Device, fence: vk::Fence) -> bool {
+        fence == vk::Fence::null()
+            || fence == self.fence
+            || unsafe { device.wait_for_fences(&[fence], true, 0) }.is_ok()
+    }
+
+    /// Wait for the commands submitted with the batch of a previous frame
+    /// whose fence was `fence`. See `is_complete`.
+    ///
+    /// A lost device is not reported since nothing completes anymore, the
+    /// wait for the fence of the next frame reports it.
+    pub fn wait_complete(&self, device: &Device, fence: vk::Fence) {
+        if self.is_complete(device, fence) {
+            return;
+        }
+        match unsafe { device.wait_for_fences(&[fence], true, u64::MAX) } {
+            Ok(()) | Err(vk::Result::ERROR_DEVICE_LOST) => {}
+            Err(error) => panic!("Error while waiting for a fence. Cause: {}", error),
+        }
+    }
+
+    pub fn stats(&self) -> SubmitStats {
+        SubmitStats {
+            command_buffers: self.command_buffers.len(),
+            wait_semaphores: self.wait_semaphores.len(),
+            signal_semaphores: self.signal_semaphores.len(),
+        }
+    }
+
+    /// Submit the batch to `queue` with a single submission.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as `vkQueueSubmit`.
+    pub unsafe fn submit(&self, device: &Device, queue: vk::Queue) -> VkResult<()> {
+        sync2::queue_submit(
+            device,
+            queue,
+            &self.wait_semaphores,
+            &self.command_buffers,
+            &self.signal_semaphores,
+            self.fence,
+        )
+    }
+}
@@ -3,7 +3,6 @@ use ash::version::{EntryV1_0, InstanceV1_0};
 use ash::vk;
 use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
 use std::os::raw::c_char;
-use winit::Window;
 
 #[derive(Copy, Clone, Debug)]
 pub enum SurfaceError {
@@ -27,23 +26,32 @@ impl std::fmt::Display for SurfaceError {
 /// Get required instance extensions.
 /// This is windows specific.
 #[cfg(target_os = "windows")]
-pub fn required_extension_names() -> Vec<*const c_char> {
+pub fn required_extension_names<W: HasRawWindowHandle>(_window: &W) -> Vec<*const c_char> {
     use ash::extensions::khr::Win32Surface;
     vec![Surface::name().as_ptr(), Win32Surface::name().as_ptr()]
 }
 
 /// Get required instance extensions.
 /// This is linux specific.
+///
+/// The surface extension matches the display server `window` is displayed on,
+/// which is only known at runtime.
 #[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
-pub fn required_extension_names() -> Vec<*const c_char> {
-    use ash::extensions::khr::XlibSurface;
-    vec![Surface::name().as_ptr(), XlibSurface::name().as_ptr()]
+pub fn required_extension_names<W: HasRawWindowHandle>(window: &W) -> Vec<*const c_char> {
+    use ash::extensions::khr::{WaylandSurface, XcbSurface, XlibSurface};
+
+    let platform_surface = match window.raw_window_handle() {
+        RawWindowHandle::Wayland(_) => WaylandSurface::name(),
+        RawWindowHandle::Xcb(_) => XcbSurface::name(),
+        _ => XlibSurface::name(),
+    };
+    vec![Surface::name().as_ptr(), platform_surface.as_ptr()]
 }
 
 /// Get required instance extensions.
 /// This is macos specific.
 #[cfg(target_os = "macos")]
-pub fn required_extension_names() -> Vec<*const c_char> {
+pub fn required_extension_names<W: HasRawWindowHandle>(_window: &W) -> Vec<*const c_char> {
     use ash::extensions::mvk::MacOSSurface;
     vec![Surface::name().as_ptr(), MacOSSurface::name().as_ptr()]
 }
@@ -51,7 +59,7 @@ pub fn required_extension_names() -> Vec<*const c_char> {
 /// Get required instance extensions.
 /// This is android specific.
 #[cfg(target_os = "android")]
-pub fn required_extension_names() -> Vec<*const c_char> {
+pub fn required_extension_names<W: HasRawWindowHandle>(_window: &W) -> Vec<*const c_char> {
     use ash::extensions::khr::AndroidSurface;
     vec![Surface::name().as_ptr(), AndroidSurface::name().as_ptr()]
 }
@@ -59,10 +67,10 @@ pub fn required_extension_names() -> Vec<*const c_char> {
 /// Create the surface.
 /// This is windows specific.
 #[cfg(target_os = "windows")]
-pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
+pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0, W: HasRawWindowHandle>(
     entry: &E,
     instance: &I,
-    window: &Window,
+    window: &W,
 ) -> Result<vk::SurfaceKHR, SurfaceError> {
     use ash::extensions::khr::Win32Surface;
 
@@ -84,17 +92,17 @@ pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
 /// Create the surface.
 /// This is linux specific.
 #[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
-pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
+pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0, W: HasRawWindowHandle>(
     entry: &E,
     instance: &I,
-    window: &Window,
+    window: &W,
 ) -> Result<vk::SurfaceKHR, SurfaceError> {
-    use ash::extensions::khr::XlibSurface;
+    use ash::extensions::khr::{WaylandSurface, XcbSurface, XlibSurface};
     use std::ffi::c_void;
 
-    log::debug!("Creating linux surface");
     match window.raw_window_handle() {
         RawWindowHandle::Xlib(handle) => {
+            log::debug!("Creating xlib surface");
             let create_info = vk::XlibSurfaceCreateInfoKHR::builder()
                 .window(handle.window)
                 .dpy(handle.display as *mut *const c_void);
@@ -103,6 +111,26 @@ pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
                 .create_xlib_surface(&create_info, None)
                 .map_err(|e| SurfaceError::SurfaceCreationError(e))
         }
+        RawWindowHandle::Xcb(handle) => {
+            log::debug!("Creating xcb surface");
+            let create_info = vk::XcbSurfaceCreateInfoKHR::builder()
+                .window(handle.window)
+                .connection(handle.connection as *mut *const c_void);
+            let surface_loader = XcbSurface::new(entry, instance);
+            surface_loader
+                .create_xcb_surface(&create_info, None)
+                .map_err(SurfaceError::SurfaceCreationError)
+        }
+        RawWindowHandle::Wayland(handle) => {
+            log::debug!("Creating wayland surface");
+            let create_info = vk::WaylandSurfaceCreateInfoKHR::builder()
+                .display(handle.display)
+                .surface(handle.surface);
+            let surface_loader = WaylandSurface::new(entry, instance);
+            surface_loader
+                .create_wayland_surface(&create_info, None)
+                .map_err(SurfaceError::SurfaceCreationError)
+        }
         _ => Err(SurfaceError::WindowNotSupportedError),
     }
 }
@@ -110,10 +138,10 @@ pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
 /// Create the surface.
 /// This is macos specific.
 #[cfg(target_os = "macos")]
-pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
+pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0, W: HasRawWindowHandle>(
     entry: &E,
     instance: &I,
-    window: &Window,
+    window: &W,
 ) -> Result<vk::SurfaceKHR, SurfaceError> {
     use ash::extensions::mvk::MacOSSurface;
 
@@ -133,10 +161,10 @@ pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
 /// Create the surface.
 /// This is android specific.
 #[cfg(target_os = "android")]
-pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
+pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0, W: HasRawWindowHandle>(
     entry: &E,
     instance: &I,
-    window: &Window,
+    window: &W,
 ) -> Result<vk::SurfaceKHR, SurfaceError> {
     use ash::extensions::khr::AndroidSurface;
 
@@ -50,25 +50,33 @@ impl SwapchainSupportDetails {
 
     /// Choose the swapchain surface format.
     ///
-    /// Will choose B8G8R8A8_UNORM/SRGB_NONLINEAR if possible or
+    /// Will choose B8G8R8A8_SRGB/SRGB_NONLINEAR if possible so the linear colors
+    /// written by the shaders are encoded when stored, then R8G8B8A8_SRGB, or
     /// the first available otherwise.
     fn choose_swapchain_surface_format(
         available_formats: &[vk::SurfaceFormatKHR],
     ) -> vk::SurfaceFormatKHR {
         if available_formats.len() == 1 && available_formats[0].format == vk::Format::UNDEFINED {
             return vk::SurfaceFormatKHR {
-                format: vk::Format::B8G8R8A8_UNORM,
+                format: vk::Format::B8G8R8A8_SRGB,
                 color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
             };
         }
 
-        *available_formats
-            .iter()
-            .find(|format| {
-                format.format == vk::Format::B8G8R8A8_UNORM
-                    && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        let find = |format| {
+            available_formats.iter().find(|available| {
+                available.format == format
+                    && available.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            })
+        };
+        *find(vk::Format::B8G8R8A8_SRGB)
+            .or_else(|| find(vk::Format::R8G8B8A8_SRGB))
+            .unwrap_or_else(|| {
+                log::warn!(
+                    "No sRGB swapchain format available, colors will not be encoded to sRGB."
+                );
+                &available_formats[0]
             })
-            .unwrap_or(&available_formats[0])
     }
 
     /// Choose the swapchain present mode.
@@ -96,7 +104,7 @@ impl SwapchainSupportDetails {
         capabilities: vk::SurfaceCapabilitiesKHR,
         preferred_dimensions: [u32; 2],
     ) -> vk::Extent2D {
-        if capabilities.current_extent.width != std::u32::MAX {
+        if capabilities.current_extent.width != u32::MAX {
             return capabilities.current_extent;
         }
 
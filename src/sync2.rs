@@ -0,0 +1,396 @@
+use crate::{context::*, gpu_device::Device};
+use ash::{
+    prelude::VkResult,
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Instance,
+};
+use std::{
+    ffi::{c_void, CStr},
+    mem, ptr,
+    sync::Mutex,
+};
+
+/// `VK_KHR_synchronization2`, which is not known by this version of ash.
+const SYNCHRONIZATION_2: &[u8] = b"VK_KHR_synchronization2\0";
+
+// Raw values of the structure types of `VK_KHR_synchronization2`.
+const STRUCTURE_TYPE_MEMORY_BARRIER_2: i32 = 1_000_314_000;
+const STRUCTURE_TYPE_BUFFER_MEMORY_BARRIER_2: i32 = 1_000_314_001;
+const STRUCTURE_TYPE_IMAGE_MEMORY_BARRIER_2: i32 = 1_000_314_002;
+const STRUCTURE_TYPE_DEPENDENCY_INFO: i32 = 1_000_314_003;
+const STRUCTURE_TYPE_SUBMIT_INFO_2: i32 = 1_000_314_004;
+const STRUCTURE_TYPE_SEMAPHORE_SUBMIT_INFO: i32 = 1_000_314_005;
+const STRUCTURE_TYPE_COMMAND_BUFFER_SUBMIT_INFO: i32 = 1_000_314_006;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES: i32 = 1_000_314_007;
+
+/// Functions of `VK_KHR_synchronization2` loaded for the device, if enabled.
+///
+/// It is global because barriers are recorded with only the device at hand.
+static SYNCHRONIZATION_2_FN: Mutex<Option<Synchronization2Fn>> = Mutex::new(None);
+
+fn synchronization_2_name() -> &'static CStr {
+    CStr::from_bytes_with_nul(SYNCHRONIZATION_2).unwrap()
+}
+
+/// Get the device extensions required to use synchronization2.
+pub fn required_device_extensions() -> [&'static CStr; 1] {
+    [synchronization_2_name()]
+}
+
+/// Check if `device` supports synchronization2.
+///
+/// Vulkan 1.1 must be supported by the instance and the device since the
+/// features are queried using `vkGetPhysicalDeviceFeatures2`.
+pub fn is_supported(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let extensions_supported = required_device_extensions()
+        .iter()
+        .all(|ext| is_device_extension_supported(instance, device, ext));
+    if !extensions_supported {
+        return false;
+    }
+
+    let mut synchronization_2_features = PhysicalDeviceSynchronization2Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut synchronization_2_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { get_physical_device_features2(instance, device, &mut features) };
+
+    synchronization_2_features.synchronization2 == vk::TRUE
+}
+
+/// Build the synchronization2 features to enable at device creation.
+pub fn required_features() -> PhysicalDeviceSynchronization2Features {
+    PhysicalDeviceSynchronization2Features {
+        synchronization2: vk::TRUE,
+        ..Default::default()
+    }
+}
+
+/// Load the functions of synchronization2 for `device`, which must have been
+/// created with the extension and its features enabled.
+///
+/// Barriers and submissions use them until `disable` is called.
+pub fn enable(instance: &Instance, device: &Device) {
+    let load = |name: &[u8]| unsafe {
+        let name = CStr::from_bytes_with_nul(name).unwrap();
+        instance
+            .get_device_proc_addr(device.handle(), name.as_ptr())
+            .expect("Failed to load a synchronization2 function.")
+    };
+    let synchronization_2_fn = unsafe {
+        Synchronization2Fn {
+            cmd_pipeline_barrier2: mem::transmute::<VoidFunction, PfnCmdPipelineBarrier2>(load(
+                b"vkCmdPipelineBarrier2KHR\0",
+            )),
+            queue_submit2: mem::transmute::<VoidFunction, PfnQueueSubmit2>(load(
+                b"vkQueueSubmit2KHR\0",
+            )),
+        }
+    };
+    *SYNCHRONIZATION_2_FN.lock().unwrap() = Some(synchronization_2_fn);
+}
+
+/// Go back to the Vulkan 1.0 barriers and submissions, before the device is destroyed.
+pub fn disable() {
+    SYNCHRONIZATION_2_FN.lock().unwrap().take();
+}
+
+fn loaded_fn() -> Option<Synchronization2Fn> {
+    *SYNCHRONIZATION_2_FN.lock().unwrap()
+}
+
+type VoidFunction = unsafe extern "system" fn() -> c_void;
+type PfnCmdPipelineBarrier2 = unsafe extern "system" fn(vk::CommandBuffer, *const DependencyInfo);
+type PfnQueueSubmit2 =
+    unsafe extern "system" fn(vk::Queue, u32, *const SubmitInfo2, vk::Fence) -> vk::Result;
+
+#[derive(Clone, Copy)]
+struct Synchronization2Fn {
+    cmd_pipeline_barrier2: PfnCmdPipelineBarrier2,
+    queue_submit2: PfnQueueSubmit2,
+}
+
+/// `VkPhysicalDeviceSynchronization2FeaturesKHR`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalDeviceSynchronization2Features {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    pub synchronization2: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceSynchronization2Features {
+    fn default() -> Self {
+        PhysicalDeviceSynchronization2Features {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES,
+            ),
+            p_next: ptr::null_mut(),
+            synchronization2: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceSynchronization2Features {}
+
+// The stage and access flags of synchronization2 are 64 bits, their lower
+// 32 bits having the values of the Vulkan 1.0 flags.
+
+#[repr(C)]
+struct MemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+}
+
+#[repr(C)]
+struct BufferMemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+#[repr(C)]
+struct ImageMemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+}
+
+#[repr(C)]
+struct DependencyInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    dependency_flags: vk::DependencyFlags,
+    memory_barrier_count: u32,
+    p_memory_barriers: *const MemoryBarrier2,
+    buffer_memory_barrier_count: u32,
+    p_buffer_memory_barriers: *const BufferMemoryBarrier2,
+    image_memory_barrier_count: u32,
+    p_image_memory_barriers: *const ImageMemoryBarrier2,
+}
+
+#[repr(C)]
+struct SemaphoreSubmitInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    semaphore: vk::Semaphore,
+    value: u64,
+    stage_mask: u64,
+    device_index: u32,
+}
+
+#[repr(C)]
+struct CommandBufferSubmitInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    command_buffer: vk::CommandBuffer,
+    device_mask: u32,
+}
+
+#[repr(C)]
+struct SubmitInfo2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: vk::Flags,
+    wait_semaphore_info_count: u32,
+    p_wait_semaphore_infos: *const SemaphoreSubmitInfo,
+    command_buffer_info_count: u32,
+    p_command_buffer_infos: *const CommandBufferSubmitInfo,
+    signal_semaphore_info_count: u32,
+    p_signal_semaphore_infos: *const SemaphoreSubmitInfo,
+}
+
+fn stage_mask2(stage: vk::PipelineStageFlags) -> u64 {
+    u64::from(stage.as_raw())
+}
+
+fn access_mask2(access: vk::AccessFlags) -> u64 {
+    u64::from(access.as_raw())
+}
+
+/// Stages and accesses on both sides of a barrier.
+pub type BarrierMasks = (
+    (vk::PipelineStageFlags, vk::AccessFlags),
+    (vk::PipelineStageFlags, vk::AccessFlags),
+);
+
+/// Record the barriers with `vkCmdPipelineBarrier2KHR`, each with its own
+/// stages, if synchronization2 is enabled.
+///
+/// Return `false` without recording anything otherwise.
+pub fn cmd_pipeline_barrier2(
+    command_buffer: vk::CommandBuffer,
+    memory_barriers: &[BarrierMasks],
+    buffer_barriers: &[(BarrierMasks, vk::BufferMemoryBarrier)],
+    image_barriers: &[(BarrierMasks, vk::ImageMemoryBarrier)],
+) -> bool {
+    let synchronization_2_fn = match loaded_fn() {
+        Some(synchronization_2_fn) => synchronization_2_fn,
+        None => return false,
+    };
+
+    let memory_barriers = memory_barriers
+        .iter()
+        .map(
+            |((src_stage, src_access), (dst_stage, dst_access))| MemoryBarrier2 {
+                s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_MEMORY_BARRIER_2),
+                p_next: ptr::null(),
+                src_stage_mask: stage_mask2(*src_stage),
+                src_access_mask: access_mask2(*src_access),
+                dst_stage_mask: stage_mask2(*dst_stage),
+                dst_access_mask: access_mask2(*dst_access),
+            },
+        )
+        .collect::<Vec<_>>();
+    let buffer_barriers = buffer_barriers
+        .iter()
+        .map(
+            |(((src_stage, _), (dst_stage, _)), barrier)| BufferMemoryBarrier2 {
+                s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_BUFFER_MEMORY_BARRIER_2),
+                p_next: ptr::null(),
+                src_stage_mask: stage_mask2(*src_stage),
+                src_access_mask: access_mask2(barrier.src_access_mask),
+                dst_stage_mask: stage_mask2(*dst_stage),
+                dst_access_mask: access_mask2(barrier.dst_access_mask),
+                src_queue_family_index: barrier.src_queue_family_index,
+                dst_queue_family_index: barrier.dst_queue_family_index,
+                buffer: barrier.buffer,
+                offset: barrier.offset,
+                size: barrier.size,
+            },
+        )
+        .collect::<Vec<_>>();
+    let image_barriers = image_barriers
+        .iter()
+        .map(
+            |(((src_stage, _), (dst_stage, _)), barrier)| ImageMemoryBarrier2 {
+                s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_IMAGE_MEMORY_BARRIER_2),
+                p_next: ptr::null(),
+                src_stage_mask: stage_mask2(*src_stage),
+                src_access_mask: access_mask2(barrier.src_access_mask),
+                dst_stage_mask: stage_mask2(*dst_stage),
+                dst_access_mask: access_mask2(barrier.dst_access_mask),
+                old_layout: barrier.old_layout,
+                new_layout: barrier.new_layout,
+                src_queue_family_index: barrier.src_queue_family_index,
+                dst_queue_family_index: barrier.dst_queue_family_index,
+                image: barrier.image,
+                subresource_range: barrier.subresource_range,
+            },
+        )
+        .collect::<Vec<_>>();
+    let dependency_info = DependencyInfo {
+        s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_DEPENDENCY_INFO),
+        p_next: ptr::null(),
+        dependency_flags: vk::DependencyFlags::empty(),
+        memory_barrier_count: memory_barriers.len() as _,
+        p_memory_barriers: memory_barriers.as_ptr(),
+        buffer_memory_barrier_count: buffer_barriers.len() as _,
+        p_buffer_memory_barriers: buffer_barriers.as_ptr(),
+        image_memory_barrier_count: image_barriers.len() as _,
+        p_image_memory_barriers: image_barriers.as_ptr(),
+    };
+    unsafe { (synchronization_2_fn.cmd_pipeline_barrier2)(command_buffer, &dependency_info) };
+    true
+}
+
+/// Submit `command_buffers` to `queue`, waiting for and signaling semaphores
+/// at the given stages, and signal `fence` once they completed.
+///
+/// Without synchronization2, the semaphores are signaled once all the
+/// commands completed whatever their stage.
+///
+/// # Safety
+///
+/// Same requirements as `vkQueueSubmit`.
+pub unsafe fn queue_submit(
+    device: &Device,
+    queue: vk::Queue,
+    wait_semaphores: &[(vk::Semaphore, vk::PipelineStageFlags)],
+    command_buffers: &[vk::CommandBuffer],
+    signal_semaphores: &[(vk::Semaphore, vk::PipelineStageFlags)],
+    fence: vk::Fence,
+) -> VkResult<()> {
+    let synchronization_2_fn = match loaded_fn() {
+        Some(synchronization_2_fn) => synchronization_2_fn,
+        None => {
+            let (wait_semaphores, wait_stages): (Vec<_>, Vec<_>) =
+                wait_semaphores.iter().copied().unzip();
+            let signal_semaphores = signal_semaphores
+                .iter()
+                .map(|(semaphore, _)| *semaphore)
+                .collect::<Vec<_>>();
+            let submit_info = vk::SubmitInfo::builder()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(command_buffers)
+                .signal_semaphores(&signal_semaphores)
+                .build();
+            return device.queue_submit(queue, &[submit_info], fence);
+        }
+    };
+
+    let semaphore_info =
+        |(semaphore, stage): &(vk::Semaphore, vk::PipelineStageFlags)| SemaphoreSubmitInfo {
+            s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_SEMAPHORE_SUBMIT_INFO),
+            p_next: ptr::null(),
+            semaphore: *semaphore,
+            value: 0,
+            stage_mask: stage_mask2(*stage),
+            device_index: 0,
+        };
+    let wait_infos = wait_semaphores
+        .iter()
+        .map(semaphore_info)
+        .collect::<Vec<_>>();
+    let signal_infos = signal_semaphores
+        .iter()
+        .map(semaphore_info)
+        .collect::<Vec<_>>();
+    let command_buffer_infos = command_buffers
+        .iter()
+        .map(|command_buffer| CommandBufferSubmitInfo {
+            s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_COMMAND_BUFFER_SUBMIT_INFO),
+            p_next: ptr::null(),
+            command_buffer: *command_buffer,
+            device_mask: 0,
+        })
+        .collect::<Vec<_>>();
+    let submit_info = SubmitInfo2 {
+        s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_SUBMIT_INFO_2),
+        p_next: ptr::null(),
+        flags: 0,
+        wait_semaphore_info_count: wait_infos.len() as _,
+        p_wait_semaphore_infos: wait_infos.as_ptr(),
+        command_buffer_info_count: command_buffer_infos.len() as _,
+        p_command_buffer_infos: command_buffer_infos.as_ptr(),
+        signal_semaphore_info_count: signal_infos.len() as _,
+        p_signal_semaphore_infos: signal_infos.as_ptr(),
+    };
+    match (synchronization_2_fn.queue_submit2)(queue, 1, &submit_info, fence) {
+        vk::Result::SUCCESS => Ok(()),
+        error => Err(error),
+    }
+}
@@ -0,0 +1,632 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    frame_layout::PASS_SET,
+    gpu_device::Device,
+    lifetime,
+    math::{Aabb, Frustum},
+    texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Point3, Vector3};
+use image::GrayImage;
+use std::mem::size_of;
+
+/// Number of quads along each side of a chunk.
+///
+/// Must be a multiple of `1 << (LOD_COUNT - 1)`.
+pub const CHUNK_QUADS: u32 = 32;
+
+/// Number of levels of detail of each chunk.
+///
+/// Level `n` skips `2^n - 1` vertices out of `2^n` along each side.
+pub const LOD_COUNT: usize = 4;
+
+/// Width and depth of the terrain in world units.
+pub const TERRAIN_SIZE: f32 = 16.0;
+
+/// Height in world units of the white pixels of the height map.
+pub const TERRAIN_HEIGHT: f32 = 2.0;
+
+/// Altitude of the black pixels of the height map.
+pub const TERRAIN_ELEVATION: f32 = -1.5;
+
+/// Number of texture layers blended using the splat map.
+pub const LAYER_COUNT: usize = 4;
+
+/// Width and height in pixels of the generated layer textures.
+pub const LAYER_TEXTURE_SIZE: u32 = 64;
+
+/// Depth of the skirts hiding the cracks between chunks of different levels of detail.
+const SKIRT_DEPTH: f32 = 0.25;
+
+/// Distance to the camera at which chunks switch to the second level of detail.
+///
+/// The distance doubles for each subsequent level.
+const LOD_DISTANCE: f32 = 2.0;
+
+/// Base colors of the grass, dirt, rock and snow layers.
+const LAYER_COLORS: [[f32; 3]; LAYER_COUNT] = [
+    [0.25, 0.4, 0.15],
+    [0.45, 0.35, 0.25],
+    [0.45, 0.45, 0.45],
+    [0.95, 0.95, 0.97],
+];
+
+const SPLAT_MAP_BINDING: u32 = 0;
+const LAYERS_BINDING: u32 = 1;
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub struct TerrainVertex {
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub coords: [f32; 2],
+}
+
+impl TerrainVertex {
+    pub fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<TerrainVertex>() as _)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        let position_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let normal_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(12)
+            .build();
+        let coords_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(24)
+            .build();
+        [position_desc, normal_desc, coords_desc]
+    }
+}
+
+/// Square piece of the terrain, culled and drawn on its own.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainChunk {
+    /// Bounds of the chunk, skirts included.
+    pub bounds: Aabb,
+    /// Index of the first vertex of the chunk in the vertex buffer.
+    pub vertex_offset: i32,
+}
+
+/// Range of the index buffer used to draw a chunk at a given level of detail.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LodRange {
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+/// Terrain geometry built on the cpu from a height map.
+///
+/// Every chunk has the same vertex layout: a grid of `CHUNK_QUADS + 1`
+/// vertices per side followed by the vertices of the four skirts. Thus the
+/// indices of each level of detail are shared by all the chunks, which only
+/// differ by their vertex offset.
+pub struct TerrainMesh {
+    pub vertices: Vec<TerrainVertex>,
+    pub indices: Vec<u32>,
+    pub chunks: Vec<TerrainChunk>,
+    pub lods: [LodRange; LOD_COUNT],
+    heights: Vec<f32>,
+    normals: Vec<Vector3<f32>>,
+    grid_size: u32,
+}
+
+impl TerrainMesh {
+    /// Build the chunks of the terrain from `height_map`.
+    ///
+    /// The height map is resampled so each side contains a whole number of chunks.
+    pub fn from_height_map(height_map: &GrayImage) -> Self {
+        let (width, height) = height_map.dimensions();
+        let chunks_per_side = ((width.max(height) - 1) / CHUNK_QUADS).max(1);
+        let grid_size = chunks_per_side * CHUNK_QUADS + 1;
+
+        let heights = (0..grid_size * grid_size)
+            .map(|i| {
+                let u = (i % grid_size) as f32 / (grid_size - 1) as f32;
+                let v = (i / grid_size) as f32 / (grid_size - 1) as f32;
+                sample_height(height_map, u, v)
+            })
+            .collect::<Vec<_>>();
+
+        let spacing = TERRAIN_SIZE / (grid_size - 1) as f32;
+        let height_at = |x: i64, z: i64| {
+            let x = x.max(0).min(grid_size as i64 - 1) as u32;
+            let z = z.max(0).min(grid_size as i64 - 1) as u32;
+            heights[(z * grid_size + x) as usize] * TERRAIN_HEIGHT
+        };
+        let normals = (0..grid_size * grid_size)
+            .map(|i| {
+                let x = (i % grid_size) as i64;
+                let z = (i / grid_size) as i64;
+                let dx = (height_at(x + 1, z) - height_at(x - 1, z)) / (2.0 * spacing);
+                let dz = (height_at(x, z + 1) - height_at(x, z - 1)) / (2.0 * spacing);
+                Vector3::new(-dx, 1.0, -dz).normalize()
+            })
+            .collect::<Vec<_>>();
+
+        let mut mesh = TerrainMesh {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            chunks: Vec::new(),
+            lods: Default::default(),
+            heights,
+            normals,
+            grid_size,
+        };
+
+        for chunk_z in 0..chunks_per_side {
+            for chunk_x in 0..chunks_per_side {
+                let chunk = mesh.build_chunk(chunk_x * CHUNK_QUADS, chunk_z * CHUNK_QUADS);
+                mesh.chunks.push(chunk);
+            }
+        }
+
+        for lod in 0..LOD_COUNT {
+            let lod_indices = build_lod_indices(lod);
+            mesh.lods[lod] = LodRange {
+                first_index: mesh.indices.len() as _,
+                index_count: lod_indices.len() as _,
+            };
+            mesh.indices.extend(lod_indices);
+        }
+
+        log::debug!(
+            "Built terrain of {} chunks from a {}x{} height map.",
+            mesh.chunks.len(),
+            width,
+            height
+        );
+
+        mesh
+    }
+
+    /// Push the vertices of the chunk whose first grid vertex is (`first_x`, `first_z`).
+    fn build_chunk(&mut self, first_x: u32, first_z: u32) -> TerrainChunk {
+        let vertex_offset = self.vertices.len() as i32;
+        let mut bounds = Aabb::empty();
+
+        let vertices_per_side = CHUNK_QUADS + 1;
+        for z in 0..vertices_per_side {
+            for x in 0..vertices_per_side {
+                let vertex = self.grid_vertex(first_x + x, first_z + z);
+                bounds.expand(Point3::from(vertex.pos));
+                self.vertices.push(vertex);
+            }
+        }
+
+        for edge in 0..4 {
+            for k in 0..vertices_per_side {
+                let (x, z) = edge_vertex(edge, k);
+                let mut vertex = self.grid_vertex(first_x + x, first_z + z);
+                vertex.pos[1] -= SKIRT_DEPTH;
+                bounds.expand(Point3::from(vertex.pos));
+                self.vertices.push(vertex);
+            }
+        }
+
+        TerrainChunk {
+            bounds,
+            vertex_offset,
+        }
+    }
+
+    fn grid_vertex(&self, x: u32, z: u32) -> TerrainVertex {
+        let index = (z * self.grid_size + x) as usize;
+        let u = x as f32 / (self.grid_size - 1) as f32;
+        let v = z as f32 / (self.grid_size - 1) as f32;
+        TerrainVertex {
+            pos: [
+                (u - 0.5) * TERRAIN_SIZE,
+                TERRAIN_ELEVATION + self.heights[index] * TERRAIN_HEIGHT,
+                (v - 0.5) * TERRAIN_SIZE,
+            ],
+            normal: self.normals[index].into(),
+            coords: [u, v],
+        }
+    }
+
+    /// Width and height in pixels of the splat map.
+    pub fn splat_map_size(&self) -> u32 {
+        self.grid_size
+    }
+
+    /// Generate the RGBA8 splat map of the terrain.
+    ///
+    /// Each channel holds the weight of a layer: grass in the valleys, dirt
+    /// higher, rock on the slopes and snow on the peaks.
+    pub fn generate_splat_map(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.heights.len() * 4);
+        for (height, normal) in self.heights.iter().zip(self.normals.iter()) {
+            let rock = smoothstep(0.25, 0.45, 1.0 - normal.y);
+            let snow = (1.0 - rock) * smoothstep(0.7, 0.8, *height);
+            let dirt = (1.0 - rock - snow) * smoothstep(0.35, 0.5, *height);
+            let grass = 1.0 - rock - snow - dirt;
+            for weight in &[grass, dirt, rock, snow] {
+                pixels.push((weight.clamp(0.0, 1.0) * 255.0).round() as u8);
+            }
+        }
+        pixels
+    }
+}
+
+/// Bilinearly sample `height_map` at normalized coordinates.
+fn sample_height(height_map: &GrayImage, u: f32, v: f32) -> f32 {
+    let (width, height) = height_map.dimensions();
+    let x = u * (width - 1) as f32;
+    let y = v * (height - 1) as f32;
+    let x0 = x.floor() as u32;
+    let y0 = y.floor() as u32;
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+    let tx = x - x0 as f32;
+    let ty = y - y0 as f32;
+
+    let texel = |x, y| f32::from(height_map.get_pixel(x, y)[0]) / 255.0;
+    let top = texel(x0, y0) * (1.0 - tx) + texel(x1, y0) * tx;
+    let bottom = texel(x0, y1) * (1.0 - tx) + texel(x1, y1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+/// Get the grid coordinates of the `k`th vertex of `edge` in a chunk.
+///
+/// Edges are ordered as follows: near row, right column, far row and left column.
+fn edge_vertex(edge: u32, k: u32) -> (u32, u32) {
+    match edge {
+        0 => (k, 0),
+        1 => (CHUNK_QUADS, k),
+        2 => (k, CHUNK_QUADS),
+        _ => (0, k),
+    }
+}
+
+/// Build the triangle list of a chunk at level of detail `lod`.
+///
+/// Each skirt is a strip of vertical quads joining the vertices of an
+/// edge of the grid to their copies lowered by `SKIRT_DEPTH`.
+fn build_lod_indices(lod: usize) -> Vec<u32> {
+    let step = 1 << lod;
+    let vertices_per_side = CHUNK_QUADS + 1;
+    let mut indices = Vec::new();
+
+    for z in (0..CHUNK_QUADS).step_by(step as _) {
+        for x in (0..CHUNK_QUADS).step_by(step as _) {
+            let top_left = z * vertices_per_side + x;
+            let top_right = top_left + step;
+            let bottom_left = top_left + step * vertices_per_side;
+            let bottom_right = bottom_left + step;
+            indices.extend_from_slice(&[top_left, bottom_left, top_right]);
+            indices.extend_from_slice(&[top_right, bottom_left, bottom_right]);
+        }
+    }
+
+    let grid_vertex_count = vertices_per_side * vertices_per_side;
+    for edge in 0..4 {
+        let skirt_offset = grid_vertex_count + edge * vertices_per_side;
+        for k in (0..CHUNK_QUADS).step_by(step as _) {
+            let grid_index = |k| {
+                let (x, z) = edge_vertex(edge, k);
+                z * vertices_per_side + x
+            };
+            let top_0 = grid_index(k);
+            let top_1 = grid_index(k + step);
+            let bottom_0 = skirt_offset + k;
+            let bottom_1 = skirt_offset + k + step;
+            indices.extend_from_slice(&[top_0, bottom_0, top_1]);
+            indices.extend_from_slice(&[top_1, bottom_0, bottom_1]);
+        }
+    }
+
+    indices
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Generate the RGBA8 texture of the layer `layer` of the terrain.
+///
+/// It is made of the base color of the layer with some per-pixel noise.
+pub fn generate_layer_texture(layer: usize) -> Vec<u8> {
+    let size = LAYER_TEXTURE_SIZE;
+    let color = LAYER_COLORS[layer];
+    let mut pixels = Vec::with_capacity((size * size * 4) as _);
+    for y in 0..size {
+        for x in 0..size {
+            let noise = hash(x, y, layer as u32) as f32 / u32::MAX as f32;
+            let intensity = 0.85 + 0.3 * noise;
+            for channel in &color {
+                pixels.push(((channel * intensity).clamp(0.0, 1.0) * 255.0) as u8);
+            }
+            pixels.push(255);
+        }
+    }
+    pixels
+}
+
+fn hash(x: u32, y: u32, seed: u32) -> u32 {
+    let mut h = x
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add(y.wrapping_mul(0xd816_3841))
+        .wrapping_add(seed.wrapping_mul(0xcb1a_b31f));
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+/// Textures sampled by the terrain.
+#[derive(Clone, Copy)]
+pub struct TerrainTextures {
+    pub splat_map: Texture,
+    pub layers: [Texture; LAYER_COUNT],
+}
+
+/// Terrain made of chunks culled against the view frustum and drawn with a
+/// level of detail depending on their distance to the camera.
+///
+/// Since command buffers are recorded once, each chunk is drawn with an
+/// indirect draw reading its parameters from a host visible buffer. There
+/// is one buffer per swapchain image, rewritten by `update_draws` before
+/// the frame is submitted. Culled chunks are drawn with no instance.
+///
+/// The splat map and the layer textures are bound in set 1.
+pub struct Terrain {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    chunks: Vec<TerrainChunk>,
+    lods: [LodRange; LOD_COUNT],
+    textures: TerrainTextures,
+    indirect_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Terrain {
+    /// Create the descriptor set containing the terrain textures.
+    ///
+    /// `indirect_buffers` must be host visible and coherent buffers of at least
+    /// `Terrain::indirect_buffer_size(mesh)` bytes, one per swapchain image.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(
+        device: &Device,
+        mesh: &TerrainMesh,
+        vertex_buffer: Buffer,
+        index_buffer: Buffer,
+        textures: TerrainTextures,
+        indirect_buffers: Vec<Buffer>,
+    ) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let image_info = |texture: &Texture| {
+            vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(texture.view)
+                .sampler(texture.sampler.unwrap())
+                .build()
+        };
+        let splat_map_infos = [image_info(&textures.splat_map)];
+        let layer_infos = textures.layers.iter().map(image_info).collect::<Vec<_>>();
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(SPLAT_MAP_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&splat_map_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(LAYERS_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&layer_infos)
+                .build(),
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Terrain {
+            vertex_buffer,
+            index_buffer,
+            chunks: mesh.chunks.clone(),
+            lods: mesh.lods,
+            textures,
+            indirect_buffers,
+            layout,
+            pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Size in bytes of each indirect buffer.
+    pub fn indirect_buffer_size(mesh: &TerrainMesh) -> vk::DeviceSize {
+        (size_of::<vk::DrawIndexedIndirectCommand>() * mesh.chunks.len()) as _
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let splat_map_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(SPLAT_MAP_BINDING)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let layers_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(LAYERS_BINDING)
+            .descriptor_count(LAYER_COUNT as _)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [splat_map_binding, layers_binding];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1 + LAYER_COUNT as u32,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Terrain {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the bounds of the chunks, in the order of their indirect draws.
+    pub fn chunk_bounds(&self) -> Vec<Aabb> {
+        self.chunks.iter().map(|chunk| chunk.bounds).collect()
+    }
+
+    /// Get the indirect buffers of the draws, one per swapchain image.
+    pub fn indirect_buffers(&self) -> &[Buffer] {
+        &self.indirect_buffers
+    }
+
+    /// Set the pipeline used to draw the terrain.
+    ///
+    /// The terrain takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Cull the chunks against `frustum` and select the level
+    /// of detail of the visible ones.
+    ///
+    /// The draws are written in the indirect buffer of the swapchain image
+    /// `image_index`, so it must be called before submitting its command buffer.
+    pub fn update_draws(
+        &self,
+        device: &Device,
+        image_index: usize,
+        frustum: &Frustum,
+        camera_position: Point3<f32>,
+    ) {
+        let draws = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let visible = frustum.intersects_aabb(&chunk.bounds);
+                let distance = chunk.bounds.center().distance(camera_position);
+                let lod = if distance < LOD_DISTANCE {
+                    0
+                } else {
+                    (distance / LOD_DISTANCE).log2() as usize + 1
+                };
+                let lod = self.lods[lod.min(LOD_COUNT - 1)];
+                vk::DrawIndexedIndirectCommand {
+                    index_count: lod.index_count,
+                    instance_count: visible as _,
+                    first_index: lod.first_index,
+                    vertex_offset: chunk.vertex_offset,
+                    first_instance: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.indirect_buffers[image_index].write(device, 0, &draws);
+    }
+
+    /// Record the draws of the chunks of the swapchain image `image_index` with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    ///
+    /// Each chunk is drawn with its own indirect draw so the `multiDrawIndirect`
+    /// feature is not required.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder, image_index: usize) {
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_vertex_buffers(0, &[self.vertex_buffer.buffer], &[0]);
+        encoder.bind_index_buffer(self.index_buffer.buffer, 0, vk::IndexType::UINT32);
+        encoder.bind_descriptor_sets(self.pipeline_layout, PASS_SET, &[self.set], &[]);
+        for i in 0..self.chunks.len() {
+            unsafe {
+                encoder.device().cmd_draw_indexed_indirect(
+                    encoder.command_buffer(),
+                    self.indirect_buffers[image_index].buffer,
+                    (i as u32 * stride) as _,
+                    1,
+                    stride,
+                )
+            };
+        }
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.textures.splat_map.destroy(device);
+        self.textures
+            .layers
+            .iter_mut()
+            .for_each(|layer| layer.destroy(device));
+        self.indirect_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        self.index_buffer.destroy(device);
+        self.vertex_buffer.destroy(device);
+    }
+}
@@ -0,0 +1,18 @@
+//! Unit tests of the logic that does not need a GPU.
+//!
+//! The code creating and destroying resources runs against `MockDevice`,
+//! which records the calls made through `GpuDevice`.
+//!
+//! Run them with `cargo test --bins`.
+
+mod aabb;
+mod barrier;
+mod console;
+mod descriptor_allocator;
+mod frustum;
+mod geometry_pool;
+mod mock_device;
+mod reflect;
+mod resources;
+mod std140;
+mod transient;
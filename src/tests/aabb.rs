@@ -0,0 +1,150 @@
+use crate::math::{Aabb, Ray};
+use cgmath::{Deg, Matrix4, Point3, Vector3};
+
+fn assert_point_eq(actual: Point3<f32>, expected: Point3<f32>) {
+    assert!(
+        (actual.x - expected.x).abs() < 1e-5
+            && (actual.y - expected.y).abs() < 1e-5
+            && (actual.z - expected.z).abs() < 1e-5,
+        "{:?} != {:?}",
+        actual,
+        expected
+    );
+}
+
+fn unit_box() -> Aabb {
+    Aabb {
+        min: Point3::new(0.0, 0.0, 0.0),
+        max: Point3::new(1.0, 2.0, 3.0),
+    }
+}
+
+#[test]
+fn empty_box_grows_to_contain_points() {
+    let mut aabb = Aabb::empty();
+    assert!(aabb.is_empty());
+
+    aabb.expand(Point3::new(1.0, -2.0, 3.0));
+    assert!(!aabb.is_empty());
+    assert_point_eq(aabb.min, Point3::new(1.0, -2.0, 3.0));
+    assert_point_eq(aabb.max, Point3::new(1.0, -2.0, 3.0));
+
+    aabb.expand(Point3::new(-1.0, 2.0, 0.0));
+    assert_point_eq(aabb.min, Point3::new(-1.0, -2.0, 0.0));
+    assert_point_eq(aabb.max, Point3::new(1.0, 2.0, 3.0));
+}
+
+#[test]
+fn union_with_empty_box_is_unchanged() {
+    let union = unit_box().union(&Aabb::empty());
+    assert_point_eq(union.min, unit_box().min);
+    assert_point_eq(union.max, unit_box().max);
+}
+
+#[test]
+fn union_contains_both_boxes() {
+    let other = Aabb {
+        min: Point3::new(-1.0, 1.0, 1.0),
+        max: Point3::new(0.5, 4.0, 2.0),
+    };
+    let union = unit_box().union(&other);
+    assert_point_eq(union.min, Point3::new(-1.0, 0.0, 0.0));
+    assert_point_eq(union.max, Point3::new(1.0, 4.0, 3.0));
+}
+
+#[test]
+fn center_and_radius() {
+    let aabb = Aabb {
+        min: Point3::new(-1.0, -1.0, -1.0),
+        max: Point3::new(3.0, 1.0, 1.0),
+    };
+    assert_point_eq(aabb.center(), Point3::new(1.0, 0.0, 0.0));
+    assert!((aabb.radius() - 6.0f32.sqrt()).abs() < 1e-5);
+}
+
+#[test]
+fn corners_span_the_box() {
+    let corners = unit_box().corners();
+    let mut aabb = Aabb::empty();
+    corners.iter().for_each(|corner| aabb.expand(*corner));
+    assert_point_eq(aabb.min, unit_box().min);
+    assert_point_eq(aabb.max, unit_box().max);
+    for (i, a) in corners.iter().enumerate() {
+        assert!(
+            corners[i + 1..].iter().all(|b| a != b),
+            "Duplicate corner {:?}",
+            a
+        );
+    }
+}
+
+#[test]
+fn transform_by_translation_moves_the_box() {
+    let aabb = unit_box().transform(Matrix4::from_translation(Vector3::new(1.0, -1.0, 2.0)));
+    assert_point_eq(aabb.min, Point3::new(1.0, -1.0, 2.0));
+    assert_point_eq(aabb.max, Point3::new(2.0, 1.0, 5.0));
+}
+
+#[test]
+fn transform_by_rotation_swaps_the_axes() {
+    // A quarter turn around y maps x to -z and z to x
+    let aabb = unit_box().transform(Matrix4::from_angle_y(Deg(90.0)));
+    assert_point_eq(aabb.min, Point3::new(0.0, 0.0, -1.0));
+    assert_point_eq(aabb.max, Point3::new(3.0, 2.0, 0.0));
+}
+
+#[test]
+fn transform_by_rotation_grows_the_box() {
+    let aabb = Aabb {
+        min: Point3::new(-1.0, -1.0, -1.0),
+        max: Point3::new(1.0, 1.0, 1.0),
+    };
+    let rotated = aabb.transform(Matrix4::from_angle_z(Deg(45.0)));
+    let half_diagonal = 2.0f32.sqrt();
+    assert_point_eq(
+        rotated.min,
+        Point3::new(-half_diagonal, -half_diagonal, -1.0),
+    );
+    assert_point_eq(rotated.max, Point3::new(half_diagonal, half_diagonal, 1.0));
+}
+
+#[test]
+fn transform_by_negative_scale_keeps_min_below_max() {
+    let aabb = unit_box().transform(Matrix4::from_nonuniform_scale(-2.0, 1.0, 0.5));
+    assert_point_eq(aabb.min, Point3::new(-2.0, 0.0, 0.0));
+    assert_point_eq(aabb.max, Point3::new(0.0, 2.0, 1.5));
+}
+
+#[test]
+fn ray_enters_the_box() {
+    let ray = Ray {
+        origin: Point3::new(0.5, 1.0, -2.0),
+        direction: Vector3::new(0.0, 0.0, 1.0),
+    };
+    let distance = unit_box().intersect_ray(&ray).unwrap();
+    assert!((distance - 2.0).abs() < 1e-5);
+}
+
+#[test]
+fn ray_from_inside_the_box_hits_at_its_origin() {
+    let ray = Ray {
+        origin: Point3::new(0.5, 1.0, 1.0),
+        direction: Vector3::new(1.0, 0.0, 0.0),
+    };
+    assert_eq!(unit_box().intersect_ray(&ray), Some(0.0));
+}
+
+#[test]
+fn ray_misses_the_box() {
+    let beside = Ray {
+        origin: Point3::new(2.0, 1.0, -2.0),
+        direction: Vector3::new(0.0, 0.0, 1.0),
+    };
+    assert_eq!(unit_box().intersect_ray(&beside), None);
+
+    let away = Ray {
+        origin: Point3::new(0.5, 1.0, -2.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert_eq!(unit_box().intersect_ray(&away), None);
+}
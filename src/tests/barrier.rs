@@ -0,0 +1,197 @@
+use crate::barrier::{Access, Barrier};
+use ash::vk::{self, Handle};
+
+const GRAPHICS_FAMILY: u32 = 0;
+const PRESENT_FAMILY: u32 = 2;
+
+fn color_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+fn image() -> vk::Image {
+    vk::Image::from_raw(1)
+}
+
+fn buffer() -> vk::Buffer {
+    vk::Buffer::from_raw(2)
+}
+
+#[test]
+fn only_the_writes_are_made_available() {
+    let barrier = Barrier::memory(
+        Access::color_attachment().and(Access::transfer_read()),
+        Access::fragment_read(),
+    );
+
+    let (src, dst) = barrier.masks();
+    assert_eq!(
+        src.stage,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER
+    );
+    assert_eq!(src.access, vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+    assert_eq!(dst, Access::fragment_read());
+    assert!(barrier.buffer_barrier().is_none());
+    assert!(barrier.image_barrier().is_none());
+}
+
+#[test]
+fn buffer_read_to_write_only_waits_for_the_reads() {
+    let barrier = Barrier::buffer_read_to_write(
+        buffer(),
+        vk::PipelineStageFlags::VERTEX_SHADER,
+        Access::transfer_write(),
+    );
+
+    let (src, dst) = barrier.masks();
+    assert_eq!(src.stage, vk::PipelineStageFlags::VERTEX_SHADER);
+    assert!(src.access.is_empty());
+    assert_eq!(dst, Access::transfer_write());
+
+    let buffer_barrier = barrier.buffer_barrier().unwrap();
+    assert_eq!(buffer_barrier.buffer, buffer());
+    assert!(buffer_barrier.src_access_mask.is_empty());
+    assert_eq!(
+        buffer_barrier.dst_access_mask,
+        vk::AccessFlags::TRANSFER_WRITE
+    );
+    assert_eq!(buffer_barrier.size, vk::WHOLE_SIZE);
+    assert_eq!(
+        buffer_barrier.src_queue_family_index,
+        vk::QUEUE_FAMILY_IGNORED
+    );
+    assert_eq!(
+        buffer_barrier.dst_queue_family_index,
+        vk::QUEUE_FAMILY_IGNORED
+    );
+}
+
+#[test]
+fn image_sampled_to_attachment_waits_for_the_shaders() {
+    let barrier = Barrier::image_sampled_to_attachment(image(), color_range());
+
+    let (src, dst) = barrier.masks();
+    assert_eq!(
+        src.stage,
+        vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER
+    );
+    assert!(src.access.is_empty());
+    assert_eq!(dst, Access::color_attachment());
+
+    let image_barrier = barrier.image_barrier().unwrap();
+    assert_eq!(image_barrier.image, image());
+    assert_eq!(
+        image_barrier.old_layout,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    );
+    assert_eq!(
+        image_barrier.new_layout,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    );
+    assert!(image_barrier.src_access_mask.is_empty());
+    assert_eq!(
+        image_barrier.dst_access_mask,
+        vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+    );
+    assert_eq!(
+        image_barrier.src_queue_family_index,
+        vk::QUEUE_FAMILY_IGNORED
+    );
+    assert_eq!(
+        image_barrier.dst_queue_family_index,
+        vk::QUEUE_FAMILY_IGNORED
+    );
+}
+
+#[test]
+fn image_attachment_to_sampled_makes_the_writes_visible() {
+    let barrier = Barrier::image_attachment_to_sampled(image(), color_range());
+
+    let (src, dst) = barrier.masks();
+    assert_eq!(src.stage, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+    assert_eq!(src.access, vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+    assert_eq!(dst, Access::fragment_read());
+
+    let image_barrier = barrier.image_barrier().unwrap();
+    assert_eq!(
+        image_barrier.old_layout,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    );
+    assert_eq!(
+        image_barrier.new_layout,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    );
+    assert_eq!(
+        image_barrier.src_access_mask,
+        vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+    );
+    assert_eq!(image_barrier.dst_access_mask, vk::AccessFlags::SHADER_READ);
+}
+
+#[test]
+fn release_and_acquire_transfer_the_ownership() {
+    let barrier = Barrier::image(
+        image(),
+        color_range(),
+        (
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        ),
+        Access::color_attachment(),
+        Access::fragment_read(),
+    );
+    let families = (GRAPHICS_FAMILY, PRESENT_FAMILY);
+
+    let release = barrier.release(families);
+    let (src, dst) = release.masks();
+    assert_eq!(src.stage, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+    assert_eq!(src.access, vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+    assert_eq!(dst.stage, vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+    assert!(dst.access.is_empty());
+
+    let acquire = barrier.acquire(families);
+    let (src, dst) = acquire.masks();
+    assert_eq!(src, Access::none());
+    assert_eq!(dst, Access::fragment_read());
+
+    // Both halves must describe the same transfer
+    for image_barrier in [release, acquire]
+        .iter()
+        .map(|barrier| barrier.image_barrier().unwrap())
+    {
+        assert_eq!(image_barrier.src_queue_family_index, GRAPHICS_FAMILY);
+        assert_eq!(image_barrier.dst_queue_family_index, PRESENT_FAMILY);
+        assert_eq!(image_barrier.old_layout, vk::ImageLayout::PRESENT_SRC_KHR);
+        assert_eq!(image_barrier.new_layout, vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+}
+
+#[test]
+fn buffer_ownership_transfer_keeps_the_buffer() {
+    let barrier = Barrier::buffer(buffer(), Access::compute_write(), Access::indirect_read());
+
+    let buffer_barrier = barrier
+        .release((GRAPHICS_FAMILY, PRESENT_FAMILY))
+        .buffer_barrier()
+        .unwrap();
+    assert_eq!(buffer_barrier.buffer, buffer());
+    assert_eq!(buffer_barrier.src_queue_family_index, GRAPHICS_FAMILY);
+    assert_eq!(buffer_barrier.dst_queue_family_index, PRESENT_FAMILY);
+    assert_eq!(
+        buffer_barrier.src_access_mask,
+        vk::AccessFlags::SHADER_WRITE
+    );
+    assert!(buffer_barrier.dst_access_mask.is_empty());
+}
+
+#[test]
+#[should_panic]
+fn memory_barriers_cannot_transfer_ownership() {
+    Barrier::memory(Access::compute_write(), Access::compute_read())
+        .release((GRAPHICS_FAMILY, PRESENT_FAMILY));
+}
@@ -0,0 +1,160 @@
+use crate::console::{CVarValue, Console, ConsoleAction};
+use ash::vk;
+
+fn create_console() -> Console {
+    let mut console = Console::default();
+    console.register_cvar("r.normals", "", CVarValue::Bool(false));
+    console.register_cvar("r.samples", "", CVarValue::Int(1));
+    console.register_cvar("r.fog.density", "", CVarValue::Float(0.15));
+    console.register_clamped_cvar(
+        "time.scale",
+        "",
+        CVarValue::Float(1.0),
+        (CVarValue::Float(0.1), CVarValue::Float(4.0)),
+    );
+    console.register_clamped_cvar(
+        "gizmo.mode",
+        "",
+        CVarValue::Int(0),
+        (CVarValue::Int(0), CVarValue::Int(2)),
+    );
+    console.register_command("step", "");
+    console
+}
+
+/// Get the lines of the output of `console`.
+fn output(console: &Console) -> Vec<String> {
+    let text = console.text(vk::Extent2D {
+        width: 800,
+        height: 600,
+    });
+    let mut lines = text.text.lines().map(str::to_owned).collect::<Vec<_>>();
+    // The input
+    lines.pop();
+    lines
+}
+
+#[test]
+fn booleans_are_parsed_from_numbers_and_words() {
+    let mut console = create_console();
+    for &(text, value) in &[
+        ("1", true),
+        ("0", false),
+        ("true", true),
+        ("false", false),
+        ("on", true),
+        ("off", false),
+    ] {
+        let action = console.execute(&format!("r.normals {}", text));
+        assert_eq!(action, Some(ConsoleAction::CVarChanged("r.normals")));
+        assert_eq!(console.get("r.normals"), CVarValue::Bool(value));
+    }
+}
+
+#[test]
+fn numbers_are_parsed_as_the_type_of_the_variable() {
+    let mut console = create_console();
+    console.execute("r.samples 4");
+    assert_eq!(console.get("r.samples"), CVarValue::Int(4));
+    console.execute("r.fog.density 0.5");
+    assert_eq!(console.get("r.fog.density"), CVarValue::Float(0.5));
+    console.execute("r.fog.density 2");
+    assert_eq!(console.get("r.fog.density"), CVarValue::Float(2.0));
+}
+
+#[test]
+fn invalid_value_leaves_the_variable_unchanged() {
+    let mut console = create_console();
+    for line in &["r.samples 1.5", "r.normals yes", "r.fog.density fast"] {
+        assert_eq!(console.execute(line), None);
+    }
+    assert_eq!(console.get("r.samples"), CVarValue::Int(1));
+    assert_eq!(console.get("r.normals"), CVarValue::Bool(false));
+    assert_eq!(console.get("r.fog.density"), CVarValue::Float(0.15));
+    assert_eq!(
+        output(&console).last().unwrap(),
+        "Invalid value fast for r.fog.density."
+    );
+
+    assert_eq!(console.execute("r.samples 2 4"), None);
+    assert_eq!(console.get("r.samples"), CVarValue::Int(1));
+    assert_eq!(
+        output(&console).last().unwrap(),
+        "r.samples takes a single value."
+    );
+}
+
+#[test]
+fn variable_without_value_is_printed() {
+    let mut console = create_console();
+    assert_eq!(console.execute("r.fog.density"), None);
+    assert_eq!(
+        output(&console),
+        ["> r.fog.density", "r.fog.density = 0.15"]
+    );
+}
+
+#[test]
+fn values_are_clamped_to_the_range_of_the_variable() {
+    let mut console = create_console();
+    let action = console.execute("time.scale 10");
+    assert_eq!(action, Some(ConsoleAction::CVarChanged("time.scale")));
+    assert_eq!(console.get("time.scale"), CVarValue::Float(4.0));
+    assert_eq!(
+        output(&console),
+        [
+            "> time.scale 10",
+            "time.scale ranges from 0.1 to 4.",
+            "time.scale = 4",
+        ]
+    );
+
+    console.execute("time.scale 0");
+    assert_eq!(console.get("time.scale"), CVarValue::Float(0.1));
+    console.execute("gizmo.mode -1");
+    assert_eq!(console.get("gizmo.mode"), CVarValue::Int(0));
+    console.execute("gizmo.mode 2");
+    assert_eq!(console.get("gizmo.mode"), CVarValue::Int(2));
+    assert_eq!(output(&console).last().unwrap(), "gizmo.mode = 2");
+}
+
+#[test]
+fn unknown_command_is_reported() {
+    let mut console = create_console();
+    assert_eq!(console.execute("scene.load sponza.ron"), None);
+    assert_eq!(
+        output(&console).last().unwrap(),
+        "Unknown command or variable scene.load."
+    );
+    assert_eq!(console.execute("   "), None);
+}
+
+#[test]
+fn registered_command_is_returned_with_its_arguments() {
+    let mut console = create_console();
+    assert_eq!(
+        console.execute("step 2 frames"),
+        Some(ConsoleAction::Command(
+            "step",
+            vec!["2".to_owned(), "frames".to_owned()]
+        ))
+    );
+}
+
+#[test]
+fn typed_line_is_executed_and_recalled() {
+    let mut console = create_console();
+    for c in "r.samplez\u{8}s 8\n".chars() {
+        console.handle_char(c);
+    }
+    assert_eq!(console.get("r.samples"), CVarValue::Int(8));
+
+    console.recall_previous();
+    assert!(console
+        .text(vk::Extent2D {
+            width: 800,
+            height: 600,
+        })
+        .text
+        .ends_with("> r.samples 8_"));
+}
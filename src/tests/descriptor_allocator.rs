@@ -0,0 +1,89 @@
+use super::mock_device::{Call, MockDevice};
+use crate::{descriptor_allocator::DescriptorAllocator, lifetime};
+use ash::vk;
+
+fn create_allocator(sets_per_pool: u32) -> DescriptorAllocator {
+    let set_size = vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::UNIFORM_BUFFER,
+        descriptor_count: 1,
+    };
+    DescriptorAllocator::new(&[set_size], sets_per_pool)
+}
+
+/// Get the pool created by `call`.
+fn created_pool(call: Call) -> vk::DescriptorPool {
+    match call {
+        Call::CreateDescriptorPool(pool, _) => pool,
+        call => panic!("Unexpected {:?}", call),
+    }
+}
+
+#[test]
+fn sets_are_allocated_from_the_pool_until_it_is_full() {
+    let device = MockDevice::new();
+    let layouts = [MockDevice::handle(); 3];
+    let mut allocator = create_allocator(4);
+    assert_eq!(allocator.allocate(&device, &layouts).len(), 3);
+    let calls = device.take_calls();
+    let pool = created_pool(calls[0]);
+    assert_eq!(
+        calls,
+        [
+            Call::CreateDescriptorPool(pool, 4),
+            Call::AllocateDescriptorSets(pool, 3),
+        ]
+    );
+
+    allocator.allocate(&device, &layouts[..1]);
+    assert_eq!(device.take_calls(), [Call::AllocateDescriptorSets(pool, 1)]);
+    allocator.destroy(&device);
+}
+
+#[test]
+fn full_pool_grows_into_a_pool_twice_as_large() {
+    let device = MockDevice::new();
+    let layouts = [MockDevice::handle(); 4];
+    let mut allocator = create_allocator(4);
+    allocator.allocate(&device, &layouts);
+    let first_pool = created_pool(device.take_calls()[0]);
+
+    assert_eq!(allocator.allocate(&device, &layouts[..1]).len(), 1);
+    let calls = device.take_calls();
+    let second_pool = created_pool(calls[1]);
+    assert_eq!(
+        calls,
+        [
+            Call::AllocateDescriptorSets(first_pool, 1),
+            Call::CreateDescriptorPool(second_pool, 8),
+            Call::AllocateDescriptorSets(second_pool, 1),
+        ]
+    );
+
+    allocator.destroy(&device);
+    assert_eq!(
+        device.take_calls(),
+        [
+            Call::DestroyDescriptorPool(first_pool),
+            Call::DestroyDescriptorPool(second_pool),
+        ]
+    );
+    assert_eq!(lifetime::report_leaks(&device), 0);
+}
+
+#[test]
+fn large_allocation_gets_a_pool_large_enough() {
+    let device = MockDevice::new();
+    let layouts = [MockDevice::handle(); 5];
+    let mut allocator = create_allocator(2);
+    assert_eq!(allocator.allocate(&device, &layouts).len(), 5);
+    let calls = device.take_calls();
+    let pool = created_pool(calls[0]);
+    assert_eq!(
+        calls,
+        [
+            Call::CreateDescriptorPool(pool, 5),
+            Call::AllocateDescriptorSets(pool, 5),
+        ]
+    );
+    allocator.destroy(&device);
+}
@@ -0,0 +1,123 @@
+use crate::math::{orthographic, perspective, Aabb, Frustum};
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+
+/// Frustum of a camera at the origin looking down -z, seeing from 1 to 100.
+fn camera_frustum() -> Frustum {
+    let view = Matrix4::look_at_dir(
+        Point3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        Vector3::unit_y(),
+    );
+    let proj = perspective(Deg(90.0), 1.0, 1.0, 100.0);
+    Frustum::from_view_proj(proj * view)
+}
+
+/// Cube of side 2 centered on `center`.
+fn cube(center: Point3<f32>) -> Aabb {
+    Aabb {
+        min: center - Vector3::new(1.0, 1.0, 1.0),
+        max: center + Vector3::new(1.0, 1.0, 1.0),
+    }
+}
+
+#[test]
+fn planes_are_normalized_and_face_inside() {
+    let frustum = camera_frustum();
+    let inside = Point3::new(0.0, 0.0, -10.0);
+    for plane in &frustum.planes {
+        assert!((plane.normal.magnitude() - 1.0).abs() < 1e-5);
+        assert!(plane.signed_distance(inside) > 0.0, "{:?}", plane);
+    }
+}
+
+#[test]
+fn near_and_far_planes_are_at_their_distance() {
+    let frustum = camera_frustum();
+    let [.., near, far] = frustum.planes;
+    assert!(near.signed_distance(Point3::new(0.0, 0.0, -1.0)).abs() < 1e-4);
+    assert!(far.signed_distance(Point3::new(0.0, 0.0, -100.0)).abs() < 1e-2);
+}
+
+#[test]
+fn side_planes_follow_the_field_of_view() {
+    // With a field of view of 90 degrees the sides are at 45 degrees. The
+    // bottom and top planes are swapped in view space since `perspective`
+    // inverts the y-axis.
+    let frustum = camera_frustum();
+    let [left, right, bottom, top, ..] = frustum.planes;
+    for (plane, edge) in [
+        (left, Point3::new(-10.0, 0.0, -10.0)),
+        (right, Point3::new(10.0, 0.0, -10.0)),
+        (bottom, Point3::new(0.0, 10.0, -10.0)),
+        (top, Point3::new(0.0, -10.0, -10.0)),
+    ]
+    .iter()
+    {
+        assert!(plane.signed_distance(*edge).abs() < 1e-4, "{:?}", plane);
+    }
+}
+
+#[test]
+fn box_in_front_of_the_camera_is_visible() {
+    assert!(camera_frustum().intersects_aabb(&cube(Point3::new(0.0, 0.0, -10.0))));
+}
+
+#[test]
+fn box_behind_the_camera_is_culled() {
+    assert!(!camera_frustum().intersects_aabb(&cube(Point3::new(0.0, 0.0, 10.0))));
+}
+
+#[test]
+fn box_beside_the_frustum_is_culled() {
+    let frustum = camera_frustum();
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(20.0, 0.0, -10.0))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(-20.0, 0.0, -10.0))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(0.0, 20.0, -10.0))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(0.0, -20.0, -10.0))));
+}
+
+#[test]
+fn box_beyond_the_far_plane_is_culled() {
+    assert!(!camera_frustum().intersects_aabb(&cube(Point3::new(0.0, 0.0, -102.0))));
+}
+
+#[test]
+fn box_crossing_a_plane_is_visible() {
+    let frustum = camera_frustum();
+    assert!(frustum.intersects_aabb(&cube(Point3::new(0.0, 0.0, -0.5))));
+    assert!(frustum.intersects_aabb(&cube(Point3::new(10.5, 0.0, -10.0))));
+    assert!(frustum.intersects_aabb(&cube(Point3::new(0.0, 0.0, -100.5))));
+}
+
+#[test]
+fn box_containing_the_frustum_is_visible() {
+    let aabb = Aabb {
+        min: Point3::new(-1000.0, -1000.0, -1000.0),
+        max: Point3::new(1000.0, 1000.0, 1000.0),
+    };
+    assert!(camera_frustum().intersects_aabb(&aabb));
+}
+
+#[test]
+fn orthographic_frustum_is_a_box() {
+    let proj = orthographic(-2.0, 2.0, -1.0, 1.0, 0.0, 10.0);
+    let frustum = Frustum::from_view_proj(proj);
+    assert!(frustum.intersects_aabb(&cube(Point3::new(2.5, 0.0, -5.0))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(3.5, 0.0, -5.0))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(0.0, 2.5, -5.0))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(0.0, 0.0, 2.0))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(0.0, 0.0, -12.0))));
+}
+
+#[test]
+fn identity_frustum_is_the_clip_volume() {
+    let frustum = Frustum::from_view_proj(Matrix4::identity());
+    let clip_volume = Aabb {
+        min: Point3::new(-1.0, -1.0, 0.0),
+        max: Point3::new(1.0, 1.0, 1.0),
+    };
+    assert!(frustum.intersects_aabb(&clip_volume));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(0.0, 0.0, -1.5))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(0.0, 0.0, 2.5))));
+    assert!(!frustum.intersects_aabb(&cube(Point3::new(2.5, 0.0, 0.5))));
+}
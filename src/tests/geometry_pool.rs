@@ -0,0 +1,82 @@
+use super::mock_device::MockDevice;
+use crate::{
+    buffer::Buffer,
+    geometry_pool::{GeometryBlock, GeometryPool},
+};
+
+const VERTEX_SIZE: u32 = 12;
+const BLOCK_VERTICES: u32 = 64;
+const BLOCK_INDICES: u32 = 96;
+
+/// Create a pool of `block_count` blocks, which are never written.
+fn create_pool(block_count: u32, latency: usize) -> GeometryPool {
+    let buffer = || Buffer::new(MockDevice::handle(), MockDevice::handle(), 0, None);
+    GeometryPool::new(
+        (buffer(), buffer()),
+        VERTEX_SIZE,
+        (block_count, BLOCK_VERTICES, BLOCK_INDICES),
+        latency,
+    )
+}
+
+#[test]
+fn blocks_are_allocated_from_the_start() {
+    let mut pool = create_pool(3, 2);
+    assert_eq!(pool.free_block_count(), 3);
+    assert_eq!(
+        pool.allocate(),
+        Some(GeometryBlock {
+            index: 0,
+            vertex_offset: 0,
+            first_index: 0,
+        })
+    );
+    assert_eq!(
+        pool.allocate(),
+        Some(GeometryBlock {
+            index: 1,
+            vertex_offset: BLOCK_VERTICES as _,
+            first_index: BLOCK_INDICES,
+        })
+    );
+    assert_eq!(pool.free_block_count(), 1);
+}
+
+#[test]
+fn full_pool_returns_none() {
+    let mut pool = create_pool(2, 2);
+    assert!(pool.allocate().is_some());
+    assert!(pool.allocate().is_some());
+    assert_eq!(pool.allocate(), None);
+}
+
+#[test]
+fn freed_block_is_reused_after_the_frames_in_flight() {
+    let mut pool = create_pool(1, 2);
+    let block = pool.allocate().unwrap();
+    pool.free(block);
+    assert_eq!(pool.allocate(), None, "Freed in the current frame");
+
+    pool.begin_frame();
+    assert_eq!(pool.allocate(), None, "Still drawn by the previous frame");
+
+    pool.begin_frame();
+    assert_eq!(pool.free_block_count(), 1);
+    assert_eq!(pool.allocate(), Some(block));
+}
+
+#[test]
+fn blocks_freed_in_different_frames_are_reused_in_order() {
+    let mut pool = create_pool(2, 2);
+    let first = pool.allocate().unwrap();
+    let second = pool.allocate().unwrap();
+    pool.free(first);
+    pool.begin_frame();
+    pool.free(second);
+
+    pool.begin_frame();
+    assert_eq!(pool.allocate(), Some(first));
+    assert_eq!(pool.allocate(), None);
+    pool.begin_frame();
+    assert_eq!(pool.allocate(), Some(second));
+}
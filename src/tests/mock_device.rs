@@ -0,0 +1,238 @@
+//! Device recording the calls made through `GpuDevice`, without a Vulkan driver.
+
+use crate::{gpu_device::GpuDevice, lifetime::ObjectRegistry, memory::MemoryUsage};
+use ash::{
+    prelude::VkResult,
+    vk::{self, Handle},
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::c_void,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Next handle created by a mock device.
+///
+/// It is global so the handles of different devices differ, and a handle
+/// given to the wrong device is not mistaken for one of its own.
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+/// Call made to a `MockDevice`, with the handles it created or received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Call {
+    CreateBuffer(vk::Buffer),
+    GetBufferMemoryRequirements(vk::Buffer),
+    BindBufferMemory(vk::Buffer, vk::DeviceMemory),
+    DestroyBuffer(vk::Buffer),
+    CreateImage(vk::Image),
+    GetImageMemoryRequirements(vk::Image),
+    BindImageMemory(vk::Image, vk::DeviceMemory),
+    DestroyImage(vk::Image),
+    DestroyImageView(vk::ImageView),
+    DestroySampler(vk::Sampler),
+    AllocateMemory(vk::DeviceMemory),
+    FreeMemory(vk::DeviceMemory),
+    MapMemory(vk::DeviceMemory),
+    UnmapMemory(vk::DeviceMemory),
+    /// Pool created and its maximum number of sets.
+    CreateDescriptorPool(vk::DescriptorPool, u32),
+    /// Pool allocated from and the number of sets requested.
+    AllocateDescriptorSets(vk::DescriptorPool, usize),
+    DestroyDescriptorPool(vk::DescriptorPool),
+}
+
+/// Device whose objects are only handles, recording the calls made to it.
+pub struct MockDevice {
+    /// Requirements of all the buffers and images.
+    pub requirements: vk::MemoryRequirements,
+    /// Make the allocations fail as if the device was out of memory.
+    pub out_of_memory: bool,
+    calls: RefCell<Vec<Call>>,
+    memory_usage: MemoryUsage,
+    objects: ObjectRegistry,
+    /// Bytes of the allocated memory, which can be mapped.
+    memory: RefCell<HashMap<vk::DeviceMemory, Vec<u8>>>,
+    /// Number of sets which can still be allocated from each descriptor pool.
+    descriptor_pools: RefCell<HashMap<vk::DescriptorPool, u32>>,
+}
+
+impl MockDevice {
+    pub fn new() -> Self {
+        MockDevice {
+            requirements: vk::MemoryRequirements {
+                size: 1024,
+                alignment: 256,
+                memory_type_bits: 1,
+            },
+            out_of_memory: false,
+            calls: RefCell::new(Vec::new()),
+            memory_usage: MemoryUsage::default(),
+            objects: ObjectRegistry::default(),
+            memory: RefCell::new(HashMap::new()),
+            descriptor_pools: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Get a copy of the bytes of `memory`.
+    pub fn memory_bytes(&self, memory: vk::DeviceMemory) -> Vec<u8> {
+        self.memory.borrow()[&memory].clone()
+    }
+
+    /// Create a handle which was not created by another call.
+    pub fn handle<H: Handle>() -> H {
+        H::from_raw(NEXT_HANDLE.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Take the calls recorded since the previous call to `take_calls`.
+    pub fn take_calls(&self) -> Vec<Call> {
+        self.calls.take()
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.borrow_mut().push(call);
+    }
+}
+
+impl GpuDevice for MockDevice {
+    fn memory_usage(&self) -> &MemoryUsage {
+        &self.memory_usage
+    }
+
+    fn objects(&self) -> &ObjectRegistry {
+        &self.objects
+    }
+
+    unsafe fn create_buffer(&self, _info: &vk::BufferCreateInfo) -> VkResult<vk::Buffer> {
+        let buffer = Self::handle();
+        self.record(Call::CreateBuffer(buffer));
+        Ok(buffer)
+    }
+
+    unsafe fn get_buffer_memory_requirements(&self, buffer: vk::Buffer) -> vk::MemoryRequirements {
+        self.record(Call::GetBufferMemoryRequirements(buffer));
+        self.requirements
+    }
+
+    unsafe fn bind_buffer_memory(
+        &self,
+        buffer: vk::Buffer,
+        memory: vk::DeviceMemory,
+        _offset: vk::DeviceSize,
+    ) -> VkResult<()> {
+        self.record(Call::BindBufferMemory(buffer, memory));
+        Ok(())
+    }
+
+    unsafe fn destroy_buffer(&self, buffer: vk::Buffer) {
+        self.record(Call::DestroyBuffer(buffer));
+    }
+
+    unsafe fn create_image(&self, _info: &vk::ImageCreateInfo) -> VkResult<vk::Image> {
+        let image = Self::handle();
+        self.record(Call::CreateImage(image));
+        Ok(image)
+    }
+
+    unsafe fn get_image_memory_requirements(&self, image: vk::Image) -> vk::MemoryRequirements {
+        self.record(Call::GetImageMemoryRequirements(image));
+        self.requirements
+    }
+
+    unsafe fn bind_image_memory(
+        &self,
+        image: vk::Image,
+        memory: vk::DeviceMemory,
+        _offset: vk::DeviceSize,
+    ) -> VkResult<()> {
+        self.record(Call::BindImageMemory(image, memory));
+        Ok(())
+    }
+
+    unsafe fn destroy_image(&self, image: vk::Image) {
+        self.record(Call::DestroyImage(image));
+    }
+
+    unsafe fn destroy_image_view(&self, view: vk::ImageView) {
+        self.record(Call::DestroyImageView(view));
+    }
+
+    unsafe fn destroy_sampler(&self, sampler: vk::Sampler) {
+        self.record(Call::DestroySampler(sampler));
+    }
+
+    unsafe fn allocate_memory(&self, info: &vk::MemoryAllocateInfo) -> VkResult<vk::DeviceMemory> {
+        if self.out_of_memory {
+            return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        }
+        let memory = Self::handle();
+        self.record(Call::AllocateMemory(memory));
+        let bytes = vec![0; info.allocation_size as usize];
+        self.memory.borrow_mut().insert(memory, bytes);
+        Ok(memory)
+    }
+
+    unsafe fn free_memory(&self, memory: vk::DeviceMemory) {
+        self.record(Call::FreeMemory(memory));
+        self.memory.borrow_mut().remove(&memory);
+    }
+
+    unsafe fn map_memory(
+        &self,
+        memory: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> VkResult<*mut c_void> {
+        self.record(Call::MapMemory(memory));
+        let mut allocations = self.memory.borrow_mut();
+        let bytes = allocations
+            .get_mut(&memory)
+            .ok_or(vk::Result::ERROR_MEMORY_MAP_FAILED)?;
+        let end = match size {
+            vk::WHOLE_SIZE => bytes.len(),
+            size => (offset + size) as usize,
+        };
+        assert!(end <= bytes.len(), "Mapping past the end of {:?}", memory);
+        // The bytes are not moved until the memory is freed
+        Ok(bytes.as_mut_ptr().add(offset as usize) as *mut c_void)
+    }
+
+    unsafe fn unmap_memory(&self, memory: vk::DeviceMemory) {
+        self.record(Call::UnmapMemory(memory));
+    }
+
+    unsafe fn create_descriptor_pool(
+        &self,
+        info: &vk::DescriptorPoolCreateInfo,
+    ) -> VkResult<vk::DescriptorPool> {
+        let pool = Self::handle();
+        self.record(Call::CreateDescriptorPool(pool, info.max_sets));
+        self.descriptor_pools
+            .borrow_mut()
+            .insert(pool, info.max_sets);
+        Ok(pool)
+    }
+
+    unsafe fn allocate_descriptor_sets(
+        &self,
+        info: &vk::DescriptorSetAllocateInfo,
+    ) -> VkResult<Vec<vk::DescriptorSet>> {
+        let count = info.descriptor_set_count;
+        self.record(Call::AllocateDescriptorSets(
+            info.descriptor_pool,
+            count as _,
+        ));
+        let mut pools = self.descriptor_pools.borrow_mut();
+        let free_sets = pools.get_mut(&info.descriptor_pool).unwrap();
+        if count > *free_sets {
+            return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY);
+        }
+        *free_sets -= count;
+        Ok((0..count).map(|_| Self::handle()).collect())
+    }
+
+    unsafe fn destroy_descriptor_pool(&self, pool: vk::DescriptorPool) {
+        self.record(Call::DestroyDescriptorPool(pool));
+        self.descriptor_pools.borrow_mut().remove(&pool);
+    }
+}
@@ -0,0 +1,421 @@
+use crate::reflect::{
+    push_constant_ranges, set_layout_bindings, DescriptorBinding, NumericType, ReflectError,
+    ShaderReflection, VertexInput,
+};
+use ash::vk;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const EXECUTION_MODEL_VERTEX: u32 = 0;
+const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+
+// Ids of the types shared by the modules.
+const FLOAT: u32 = 1;
+const INT: u32 = 2;
+const UINT: u32 = 3;
+const VEC2: u32 = 4;
+const VEC3: u32 = 5;
+const IVEC2: u32 = 6;
+const IMAGE_2D: u32 = 7;
+const SAMPLED_IMAGE: u32 = 8;
+/// First id free for the tests.
+const FIRST_ID: u32 = 20;
+
+/// Assemble a SPIR-V module from its instructions, as opcodes and operands.
+///
+/// The module has an entry point of `execution_model` and declares the
+/// shared types, with the ids of the constants above.
+fn module(execution_model: u32, instructions: &[(u32, &[u32])]) -> Vec<u32> {
+    // "main", zero terminated
+    let main = [u32::from_le_bytes(*b"main"), 0];
+    let entry_point = [&[execution_model, FIRST_ID - 1][..], &main].concat();
+    let shared_types: &[(u32, &[u32])] = &[
+        (OP_ENTRY_POINT, &entry_point),
+        (OP_TYPE_FLOAT, &[FLOAT, 32]),
+        (OP_TYPE_INT, &[INT, 32, 1]),
+        (OP_TYPE_INT, &[UINT, 32, 0]),
+        (OP_TYPE_VECTOR, &[VEC2, FLOAT, 2]),
+        (OP_TYPE_VECTOR, &[VEC3, FLOAT, 3]),
+        (OP_TYPE_VECTOR, &[IVEC2, INT, 2]),
+        (OP_TYPE_IMAGE, &[IMAGE_2D, FLOAT, 1, 0, 0, 0, 1, 0]),
+        (OP_TYPE_SAMPLED_IMAGE, &[SAMPLED_IMAGE, IMAGE_2D]),
+    ];
+    let mut words = vec![0x0723_0203, 0x0001_0000, 0, 100, 0];
+    for (opcode, operands) in shared_types.iter().chain(instructions) {
+        words.push(((operands.len() as u32 + 1) << 16) | opcode);
+        words.extend_from_slice(operands);
+    }
+    words
+}
+
+/// Instructions declaring the variable `id` of type `pointee` in `storage_class`,
+/// bound at `set` and `binding`.
+fn descriptor(
+    id: u32,
+    pointee: u32,
+    storage_class: u32,
+    (set, binding): (u32, u32),
+) -> Vec<(u32, Vec<u32>)> {
+    vec![
+        (OP_TYPE_POINTER, vec![id + 1, storage_class, pointee]),
+        (OP_VARIABLE, vec![id + 1, id, storage_class]),
+        (OP_DECORATE, vec![id, DECORATION_DESCRIPTOR_SET, set]),
+        (OP_DECORATE, vec![id, DECORATION_BINDING, binding]),
+    ]
+}
+
+/// Borrow the operands of `instructions` for `module`.
+fn borrow(instructions: &[(u32, Vec<u32>)]) -> Vec<(u32, &[u32])> {
+    instructions
+        .iter()
+        .map(|(opcode, operands)| (*opcode, operands.as_slice()))
+        .collect()
+}
+
+/// Fragment shader sampling `texture` at set 0 and binding 1, reading a
+/// uniform block at binding 0 and writing a buffer block at set 1.
+fn fragment_shader() -> Vec<u32> {
+    let block = FIRST_ID;
+    let buffer_block = FIRST_ID + 1;
+    let mut instructions = vec![
+        (OP_TYPE_STRUCT, vec![block, VEC3]),
+        (OP_MEMBER_DECORATE, vec![block, 0, DECORATION_OFFSET, 0]),
+        (OP_TYPE_STRUCT, vec![buffer_block, FLOAT]),
+        (OP_DECORATE, vec![buffer_block, DECORATION_BUFFER_BLOCK]),
+    ];
+    instructions.extend(descriptor(
+        30,
+        SAMPLED_IMAGE,
+        STORAGE_CLASS_UNIFORM_CONSTANT,
+        (0, 1),
+    ));
+    instructions.extend(descriptor(32, block, STORAGE_CLASS_UNIFORM, (0, 0)));
+    instructions.extend(descriptor(34, buffer_block, STORAGE_CLASS_UNIFORM, (1, 0)));
+    module(EXECUTION_MODEL_FRAGMENT, &borrow(&instructions))
+}
+
+/// Instructions of a push constant block with a `vec2` at `offset`.
+fn push_constants(offset: u32) -> Vec<(u32, Vec<u32>)> {
+    let block = 40;
+    vec![
+        (OP_TYPE_STRUCT, vec![block, VEC2]),
+        (
+            OP_MEMBER_DECORATE,
+            vec![block, 0, DECORATION_OFFSET, offset],
+        ),
+        (
+            OP_TYPE_POINTER,
+            vec![block + 1, STORAGE_CLASS_PUSH_CONSTANT, block],
+        ),
+        (
+            OP_VARIABLE,
+            vec![block + 1, block + 2, STORAGE_CLASS_PUSH_CONSTANT],
+        ),
+    ]
+}
+
+/// Get the stages, offset and size of `range`, which is not comparable.
+fn range(range: vk::PushConstantRange) -> (vk::ShaderStageFlags, u32, u32) {
+    (range.stage_flags, range.offset, range.size)
+}
+
+#[test]
+fn invalid_modules_are_rejected() {
+    assert!(matches!(
+        ShaderReflection::new(&[]),
+        Err(ReflectError::InvalidModule)
+    ));
+    let mut code = fragment_shader();
+    code[0] = 0;
+    assert!(matches!(
+        ShaderReflection::new(&code),
+        Err(ReflectError::InvalidModule)
+    ));
+    // The word count of the last instruction goes past the end
+    let mut code = fragment_shader();
+    code.pop();
+    assert!(matches!(
+        ShaderReflection::new(&code),
+        Err(ReflectError::InvalidModule)
+    ));
+}
+
+#[test]
+fn module_without_entry_point_has_no_stage() {
+    let code = module(EXECUTION_MODEL_FRAGMENT, &[]);
+    let without_entry_point = [&code[..5], &code[10..]].concat();
+    assert!(matches!(
+        ShaderReflection::new(&without_entry_point),
+        Err(ReflectError::UnknownStage)
+    ));
+}
+
+#[test]
+fn descriptors_are_sorted_by_set_and_binding() {
+    let shader = ShaderReflection::new(&fragment_shader()).unwrap();
+    assert_eq!(shader.stage, vk::ShaderStageFlags::FRAGMENT);
+    let binding = |set, binding, descriptor_type| DescriptorBinding {
+        set,
+        binding,
+        descriptor_type,
+        count: 1,
+    };
+    assert_eq!(
+        shader.bindings,
+        [
+            binding(0, 0, vk::DescriptorType::UNIFORM_BUFFER),
+            binding(0, 1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            binding(1, 0, vk::DescriptorType::STORAGE_BUFFER),
+        ]
+    );
+    assert!(shader.push_constants.is_none());
+    assert!(shader.vertex_inputs.is_empty());
+}
+
+#[test]
+fn descriptor_arrays_have_their_length_as_count() {
+    let (length, array, runtime_array) = (FIRST_ID, FIRST_ID + 1, FIRST_ID + 2);
+    let mut instructions = vec![
+        (OP_CONSTANT, vec![UINT, length, 4]),
+        (OP_TYPE_ARRAY, vec![array, SAMPLED_IMAGE, length]),
+        (OP_TYPE_RUNTIME_ARRAY, vec![runtime_array, SAMPLED_IMAGE]),
+    ];
+    instructions.extend(descriptor(
+        30,
+        array,
+        STORAGE_CLASS_UNIFORM_CONSTANT,
+        (0, 0),
+    ));
+    instructions.extend(descriptor(
+        32,
+        runtime_array,
+        STORAGE_CLASS_UNIFORM_CONSTANT,
+        (0, 1),
+    ));
+    let shader =
+        ShaderReflection::new(&module(EXECUTION_MODEL_FRAGMENT, &borrow(&instructions))).unwrap();
+    let counts = shader
+        .bindings
+        .iter()
+        .map(|binding| binding.count)
+        .collect::<Vec<_>>();
+    assert_eq!(counts, [4, 0]);
+}
+
+#[test]
+fn descriptor_array_sized_by_specialization_constant_is_an_error() {
+    let (length, array) = (FIRST_ID, FIRST_ID + 1);
+    let mut instructions = vec![
+        (OP_SPEC_CONSTANT, vec![UINT, length, 4]),
+        (OP_TYPE_ARRAY, vec![array, SAMPLED_IMAGE, length]),
+    ];
+    instructions.extend(descriptor(
+        30,
+        array,
+        STORAGE_CLASS_UNIFORM_CONSTANT,
+        (1, 2),
+    ));
+    let code = module(EXECUTION_MODEL_FRAGMENT, &borrow(&instructions));
+    assert!(matches!(
+        ShaderReflection::new(&code),
+        Err(ReflectError::SpecializedDescriptorCount { set: 1, binding: 2 })
+    ));
+}
+
+#[test]
+fn push_constant_range_covers_the_members() {
+    let instructions = push_constants(8);
+    let shader =
+        ShaderReflection::new(&module(EXECUTION_MODEL_FRAGMENT, &borrow(&instructions))).unwrap();
+    assert_eq!(
+        shader.push_constants.map(range),
+        Some((vk::ShaderStageFlags::FRAGMENT, 8, 8))
+    );
+}
+
+#[test]
+fn push_constant_array_sized_by_specialization_constant_is_an_error() {
+    let (length, array, block) = (FIRST_ID, FIRST_ID + 1, FIRST_ID + 2);
+    let instructions = vec![
+        (OP_SPEC_CONSTANT, vec![UINT, length, 4]),
+        (OP_TYPE_ARRAY, vec![array, FLOAT, length]),
+        (OP_DECORATE, vec![array, DECORATION_ARRAY_STRIDE, 4]),
+        (OP_TYPE_STRUCT, vec![block, array]),
+        (OP_MEMBER_DECORATE, vec![block, 0, DECORATION_OFFSET, 0]),
+        (
+            OP_TYPE_POINTER,
+            vec![30, STORAGE_CLASS_PUSH_CONSTANT, block],
+        ),
+        (OP_VARIABLE, vec![30, 31, STORAGE_CLASS_PUSH_CONSTANT]),
+    ];
+    let code = module(EXECUTION_MODEL_FRAGMENT, &borrow(&instructions));
+    assert!(matches!(
+        ShaderReflection::new(&code),
+        Err(ReflectError::SpecializedPushConstantSize)
+    ));
+}
+
+/// Vertex shader with a `vec3` input at location 0, an `ivec2` at location 2
+/// and a built-in input.
+fn vertex_shader() -> Vec<u32> {
+    let input = |id, pointee, location: Option<u32>| {
+        let mut instructions = vec![
+            (OP_TYPE_POINTER, vec![id + 1, STORAGE_CLASS_INPUT, pointee]),
+            (OP_VARIABLE, vec![id + 1, id, STORAGE_CLASS_INPUT]),
+        ];
+        instructions.push(match location {
+            Some(location) => (OP_DECORATE, vec![id, DECORATION_LOCATION, location]),
+            // gl_VertexIndex
+            None => (OP_DECORATE, vec![id, DECORATION_BUILT_IN, 42]),
+        });
+        instructions
+    };
+    let instructions = [
+        input(FIRST_ID, VEC3, Some(0)),
+        input(FIRST_ID + 2, IVEC2, Some(2)),
+        input(FIRST_ID + 4, INT, None),
+    ]
+    .concat();
+    module(EXECUTION_MODEL_VERTEX, &borrow(&instructions))
+}
+
+fn attribute(location: u32, format: vk::Format) -> vk::VertexInputAttributeDescription {
+    vk::VertexInputAttributeDescription {
+        location,
+        binding: 0,
+        format,
+        offset: 0,
+    }
+}
+
+#[test]
+fn vertex_inputs_skip_the_built_ins() {
+    let shader = ShaderReflection::new(&vertex_shader()).unwrap();
+    assert_eq!(shader.stage, vk::ShaderStageFlags::VERTEX);
+    assert_eq!(
+        shader.vertex_inputs,
+        [
+            VertexInput {
+                location: 0,
+                numeric_type: NumericType::Float,
+            },
+            VertexInput {
+                location: 2,
+                numeric_type: NumericType::Int,
+            },
+        ]
+    );
+}
+
+#[test]
+fn vertex_input_is_validated_against_the_attributes() {
+    let shader = ShaderReflection::new(&vertex_shader()).unwrap();
+    // Missing components are filled by the device
+    let position = attribute(0, vk::Format::R32G32_SFLOAT);
+    shader
+        .validate_vertex_input(&[position, attribute(2, vk::Format::R32G32_SINT)])
+        .unwrap();
+
+    assert!(matches!(
+        shader.validate_vertex_input(&[position]),
+        Err(ReflectError::MissingVertexAttribute(2))
+    ));
+    assert!(matches!(
+        shader.validate_vertex_input(&[position, attribute(2, vk::Format::R32G32_SFLOAT)]),
+        Err(ReflectError::VertexAttributeMismatch {
+            location: 2,
+            expected: NumericType::Int,
+            found: NumericType::Float,
+        })
+    ));
+    // Unknown formats are only checked for presence
+    shader
+        .validate_vertex_input(&[position, attribute(2, vk::Format::R16G16_SINT)])
+        .unwrap();
+}
+
+#[test]
+fn layout_bindings_have_the_stages_of_all_the_shaders() {
+    let fragment = ShaderReflection::new(&fragment_shader()).unwrap();
+    let mut vertex = fragment.clone();
+    vertex.stage = vk::ShaderStageFlags::VERTEX;
+    vertex.bindings.remove(1);
+
+    let bindings = set_layout_bindings(&[vertex, fragment], 0);
+    let bindings = bindings
+        .iter()
+        .map(|binding| {
+            (
+                binding.binding,
+                binding.descriptor_type,
+                binding.stage_flags,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        bindings,
+        [
+            (
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            ),
+            (
+                1,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+        ]
+    );
+}
+
+#[test]
+fn push_constant_ranges_of_the_same_stage_are_merged() {
+    let shader = |offset, stage| {
+        let instructions = push_constants(offset);
+        let mut shader =
+            ShaderReflection::new(&module(EXECUTION_MODEL_FRAGMENT, &borrow(&instructions)))
+                .unwrap();
+        shader.push_constants.as_mut().unwrap().stage_flags = stage;
+        shader
+    };
+    let ranges = push_constant_ranges(&[
+        shader(16, vk::ShaderStageFlags::COMPUTE),
+        shader(0, vk::ShaderStageFlags::COMPUTE),
+        shader(4, vk::ShaderStageFlags::VERTEX),
+    ]);
+    assert_eq!(
+        ranges.into_iter().map(range).collect::<Vec<_>>(),
+        [
+            (vk::ShaderStageFlags::COMPUTE, 0, 24),
+            (vk::ShaderStageFlags::VERTEX, 4, 8),
+        ]
+    );
+}
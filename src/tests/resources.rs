@@ -0,0 +1,231 @@
+use super::mock_device::{Call, MockDevice};
+use crate::{
+    buffer::Buffer,
+    gpu_device::GpuDevice,
+    lifetime,
+    memory::{self, AllocationError, HeapStats},
+    texture::{self, Texture},
+};
+use ash::vk;
+
+/// Usage of a heap of 1 MiB with 512 KiB used.
+const HEAP_STATS: HeapStats = HeapStats {
+    allocated: 512 * 1024,
+    usage: 512 * 1024,
+    budget: 1024 * 1024,
+};
+
+fn allocate(
+    device: &MockDevice,
+    requirements: vk::MemoryRequirements,
+) -> Result<vk::DeviceMemory, AllocationError> {
+    memory::allocate_from_heap(
+        device,
+        (requirements, vk::MemoryAllocateFlags::empty()),
+        0,
+        0,
+        HEAP_STATS,
+    )
+}
+
+fn buffer_info() -> vk::BufferCreateInfo {
+    vk::BufferCreateInfo::builder()
+        .size(1000)
+        .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+        .build()
+}
+
+fn image_info() -> vk::ImageCreateInfo {
+    vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D {
+            width: 16,
+            height: 16,
+            depth: 1,
+        })
+        .format(vk::Format::R8G8B8A8_UNORM)
+        .build()
+}
+
+#[test]
+fn buffer_is_created_bound_and_destroyed() {
+    let device = MockDevice::new();
+    let mut buffer = Buffer::create(&device, &buffer_info(), |requirements| {
+        allocate(&device, requirements)
+    })
+    .unwrap();
+    assert_eq!(buffer.size, device.requirements.size);
+    assert_eq!(buffer.device_address(), None);
+    assert_eq!(
+        device.take_calls(),
+        [
+            Call::CreateBuffer(buffer.buffer),
+            Call::GetBufferMemoryRequirements(buffer.buffer),
+            Call::AllocateMemory(buffer.memory),
+            Call::BindBufferMemory(buffer.buffer, buffer.memory),
+        ]
+    );
+
+    buffer.destroy(&device);
+    assert_eq!(
+        device.take_calls(),
+        [
+            Call::DestroyBuffer(buffer.buffer),
+            Call::FreeMemory(buffer.memory),
+        ]
+    );
+}
+
+#[test]
+fn buffer_over_budget_is_destroyed() {
+    let mut device = MockDevice::new();
+    device.requirements.size = 1024 * 1024;
+    let result = Buffer::create(&device, &buffer_info(), |requirements| {
+        allocate(&device, requirements)
+    });
+    assert!(matches!(result, Err(AllocationError::OverBudget { .. })));
+
+    // The memory is not allocated and the buffer not bound
+    let calls = device.take_calls();
+    let buffer = match calls[0] {
+        Call::CreateBuffer(buffer) => buffer,
+        call => panic!("Unexpected {:?}", call),
+    };
+    assert_eq!(
+        calls,
+        [
+            Call::CreateBuffer(buffer),
+            Call::GetBufferMemoryRequirements(buffer),
+            Call::DestroyBuffer(buffer),
+        ]
+    );
+}
+
+#[test]
+fn buffer_is_destroyed_when_the_device_is_out_of_memory() {
+    let mut device = MockDevice::new();
+    device.out_of_memory = true;
+    let result = Buffer::create(&device, &buffer_info(), |requirements| {
+        allocate(&device, requirements)
+    });
+    assert!(matches!(
+        result,
+        Err(AllocationError::AllocationFailed(
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+        ))
+    ));
+    let calls = device.take_calls();
+    assert!(matches!(calls.last(), Some(Call::DestroyBuffer(_))));
+    assert_eq!(calls.len(), 3);
+}
+
+#[test]
+fn memory_and_objects_are_counted_per_device() {
+    let device = MockDevice::new();
+    let other_device = MockDevice::new();
+    let mut buffer = Buffer::create(&device, &buffer_info(), |requirements| {
+        allocate(&device, requirements)
+    })
+    .unwrap();
+    assert_eq!(device.memory_usage().allocated(0), buffer.size);
+    assert_eq!(other_device.memory_usage().allocated(0), 0);
+    assert_eq!(lifetime::report_leaks(&other_device), 0);
+
+    buffer.destroy(&device);
+    assert_eq!(device.memory_usage().allocated(0), 0);
+    assert_eq!(lifetime::report_leaks(&device), 0);
+}
+
+#[test]
+fn leaked_buffer_is_reported_once() {
+    let device = MockDevice::new();
+    Buffer::create(&device, &buffer_info(), |requirements| {
+        allocate(&device, requirements)
+    })
+    .unwrap();
+    assert_eq!(lifetime::report_leaks(&device), 1);
+    assert_eq!(lifetime::report_leaks(&device), 0);
+}
+
+#[test]
+fn buffer_write_copies_at_offset() {
+    let device = MockDevice::new();
+    let buffer = Buffer::create(&device, &buffer_info(), |requirements| {
+        allocate(&device, requirements)
+    })
+    .unwrap();
+    device.take_calls();
+
+    buffer.write(&device, 8, &[1u32, 2]);
+    assert_eq!(
+        device.take_calls(),
+        [
+            Call::MapMemory(buffer.memory),
+            Call::UnmapMemory(buffer.memory)
+        ]
+    );
+    let bytes = device.memory_bytes(buffer.memory);
+    assert_eq!(&bytes[..8], &[0; 8]);
+    assert_eq!(&bytes[8..16], &[1, 0, 0, 0, 2, 0, 0, 0]);
+
+    // Nothing is mapped for empty data
+    buffer.write::<u32>(&device, 0, &[]);
+    assert!(device.take_calls().is_empty());
+}
+
+#[test]
+fn texture_is_created_bound_and_destroyed() {
+    let device = MockDevice::new();
+    let (image, memory) = texture::create_image(&device, &image_info(), |requirements| {
+        allocate(&device, requirements)
+    })
+    .unwrap();
+    assert_eq!(
+        device.take_calls(),
+        [
+            Call::CreateImage(image),
+            Call::GetImageMemoryRequirements(image),
+            Call::AllocateMemory(memory),
+            Call::BindImageMemory(image, memory),
+        ]
+    );
+
+    let view = MockDevice::handle();
+    let sampler = MockDevice::handle();
+    let mut texture = Texture::new(image, memory, view, Some(sampler));
+    texture.destroy(&device);
+    assert_eq!(
+        device.take_calls(),
+        [
+            Call::DestroySampler(sampler),
+            Call::DestroyImageView(view),
+            Call::DestroyImage(image),
+            Call::FreeMemory(memory),
+        ]
+    );
+    assert_eq!(texture.sampler, None);
+}
+
+#[test]
+fn image_over_budget_is_destroyed() {
+    let mut device = MockDevice::new();
+    device.requirements.size = 1024 * 1024;
+    let result = texture::create_image(&device, &image_info(), |requirements| {
+        allocate(&device, requirements)
+    });
+    assert!(matches!(result, Err(AllocationError::OverBudget { .. })));
+
+    let calls = device.take_calls();
+    let image = match calls[0] {
+        Call::CreateImage(image) => image,
+        call => panic!("Unexpected {:?}", call),
+    };
+    assert_eq!(
+        calls,
+        [
+            Call::CreateImage(image),
+            Call::GetImageMemoryRequirements(image),
+            Call::DestroyImage(image),
+        ]
+    );
+}
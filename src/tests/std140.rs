@@ -0,0 +1,83 @@
+use crate::std140::{std140_struct, struct_size, Std140};
+use cgmath::{Matrix4, Vector3, Vector4};
+use std::mem::{offset_of, size_of};
+
+std140_struct! {
+    /// A `vec3` followed by a `float` packed in its last 4 bytes.
+    #[derive(Clone, Copy)]
+    struct PackedVec3 {
+        position: [f32; 3],
+        intensity: f32,
+    }
+}
+
+std140_struct! {
+    /// A `vec3` followed by a `vec2` which must start on the next 16 bytes.
+    #[derive(Clone, Copy)]
+    struct PaddedVec3 {
+        position: Vector3<f32>,
+        _padding: f32,
+        uv: [f32; 2],
+    }
+}
+
+std140_struct! {
+    /// A struct nested in another one, which must start on 16 bytes.
+    #[derive(Clone, Copy)]
+    struct Nested {
+        scale: f32,
+        _padding: f32,
+        _padding_2: [f32; 2],
+        inner: PackedVec3,
+        color: Vector4<f32>,
+        transforms: [Matrix4<f32>; 2],
+    }
+}
+
+#[test]
+fn vectors_have_the_std140_alignment() {
+    assert_eq!(<f32 as Std140>::ALIGNMENT, 4);
+    assert_eq!(<[f32; 2] as Std140>::ALIGNMENT, 8);
+    assert_eq!(<Vector3<f32> as Std140>::ALIGNMENT, 16);
+    assert_eq!(<Vector3<f32> as Std140>::SIZE, 12);
+    assert_eq!(<Vector4<f32> as Std140>::ALIGNMENT, 16);
+    assert_eq!(<Matrix4<f32> as Std140>::SIZE, 64);
+}
+
+#[test]
+fn arrays_have_a_stride_of_16_bytes() {
+    assert_eq!(<[[f32; 4]; 3] as Std140>::SIZE, 48);
+    assert_eq!(<[[u32; 4]; 5] as Std140>::ALIGNMENT, 16);
+    assert_eq!(<[Matrix4<f32>; 2] as Std140>::SIZE, 128);
+}
+
+#[test]
+fn struct_size_is_rounded_up_to_16_bytes() {
+    assert_eq!(struct_size(0), 0);
+    assert_eq!(struct_size(4), 16);
+    assert_eq!(struct_size(16), 16);
+    assert_eq!(struct_size(17), 32);
+}
+
+#[test]
+fn float_is_packed_after_vec3() {
+    assert_eq!(offset_of!(PackedVec3, intensity), 12);
+    assert_eq!(<PackedVec3 as Std140>::SIZE, 16);
+}
+
+#[test]
+fn vec2_is_padded_after_vec3() {
+    assert_eq!(offset_of!(PaddedVec3, uv), 16);
+    assert_eq!(size_of::<PaddedVec3>(), 24);
+    // The size of a struct is rounded up to its alignment
+    assert_eq!(<PaddedVec3 as Std140>::SIZE, 32);
+    assert_eq!(<PaddedVec3 as Std140>::ALIGNMENT, 16);
+}
+
+#[test]
+fn nested_struct_is_aligned_on_16_bytes() {
+    assert_eq!(offset_of!(Nested, inner), 16);
+    assert_eq!(offset_of!(Nested, color), 32);
+    assert_eq!(offset_of!(Nested, transforms), 48);
+    assert_eq!(<Nested as Std140>::SIZE, 176);
+}
@@ -0,0 +1,123 @@
+use super::mock_device::{Call, MockDevice};
+use crate::{
+    buffer::Buffer,
+    memory::{self, HeapStats},
+    transient::{TransientBufferAllocator, TRANSIENT_BUFFER_SIZE},
+};
+use ash::vk;
+
+/// Heap large enough for the buffers of all the images.
+const HEAP_STATS: HeapStats = HeapStats {
+    allocated: 0,
+    usage: 0,
+    budget: 1024 * 1024 * 1024,
+};
+
+/// Create an allocator with a buffer for each of `image_count` images.
+fn create_allocator(
+    device: &mut MockDevice,
+    image_count: usize,
+) -> (TransientBufferAllocator, Vec<Buffer>) {
+    device.requirements.size = TRANSIENT_BUFFER_SIZE;
+    let buffer_info = vk::BufferCreateInfo::builder()
+        .size(TRANSIENT_BUFFER_SIZE)
+        .usage(TransientBufferAllocator::usage())
+        .build();
+    let buffers = (0..image_count)
+        .map(|_| {
+            Buffer::create(&*device, &buffer_info, |requirements| {
+                memory::allocate_from_heap(
+                    &*device,
+                    (requirements, vk::MemoryAllocateFlags::empty()),
+                    0,
+                    0,
+                    HEAP_STATS,
+                )
+            })
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let allocator = TransientBufferAllocator::new(&*device, buffers.clone());
+    device.take_calls();
+    (allocator, buffers)
+}
+
+fn offset(
+    allocator: &mut TransientBufferAllocator,
+    size: vk::DeviceSize,
+    alignment: vk::DeviceSize,
+) -> vk::DeviceSize {
+    allocator.allocate(size, alignment).unwrap().offset
+}
+
+#[test]
+fn allocations_are_aligned() {
+    let mut device = MockDevice::new();
+    let (mut allocator, _) = create_allocator(&mut device, 1);
+    assert_eq!(offset(&mut allocator, 3, 1), 0);
+    assert_eq!(offset(&mut allocator, 4, 16), 16);
+    // Vertices of 12 bytes are aligned on their stride
+    assert_eq!(offset(&mut allocator, 1, 12), 24);
+    assert_eq!(offset(&mut allocator, 1, 12), 36);
+    assert_eq!(offset(&mut allocator, 1, 0), 37);
+    allocator.destroy(&device);
+}
+
+#[test]
+fn full_buffer_returns_none() {
+    let mut device = MockDevice::new();
+    let (mut allocator, _) = create_allocator(&mut device, 1);
+    assert_eq!(offset(&mut allocator, TRANSIENT_BUFFER_SIZE - 12, 1), 0);
+    assert!(allocator.allocate(16, 1).is_none());
+    // 8 bytes are left but not at an offset aligned on 16
+    assert!(allocator.allocate(8, 16).is_none());
+    assert_eq!(offset(&mut allocator, 8, 8), TRANSIENT_BUFFER_SIZE - 8);
+    assert!(allocator.allocate(1, 1).is_none());
+    allocator.destroy(&device);
+}
+
+#[test]
+fn begin_frame_releases_the_allocations() {
+    let mut device = MockDevice::new();
+    let (mut allocator, _) = create_allocator(&mut device, 2);
+    assert_eq!(offset(&mut allocator, TRANSIENT_BUFFER_SIZE, 1), 0);
+    assert!(allocator.allocate(1, 1).is_none());
+
+    allocator.begin_frame(1);
+    assert_eq!(offset(&mut allocator, 16, 1), 0);
+    allocator.begin_frame(0);
+    assert_eq!(offset(&mut allocator, TRANSIENT_BUFFER_SIZE, 1), 0);
+    allocator.destroy(&device);
+}
+
+#[test]
+fn push_writes_in_the_buffer_of_the_current_image() {
+    let mut device = MockDevice::new();
+    let (mut allocator, buffers) = create_allocator(&mut device, 2);
+    allocator.begin_frame(1);
+    assert_eq!(allocator.buffer(1), buffers[1].buffer);
+    allocator.push(&[7u8], 1).unwrap();
+    let allocation = allocator.push(&[1u32, 2], 4).unwrap();
+    assert_eq!((allocation.offset, allocation.size), (4, 8));
+    // The buffers stay mapped from creation
+    assert!(device.take_calls().is_empty());
+
+    let bytes = device.memory_bytes(buffers[1].memory);
+    assert_eq!(&bytes[..12], &[7, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+    assert!(device.memory_bytes(buffers[0].memory)[..12]
+        .iter()
+        .all(|byte| *byte == 0));
+
+    allocator.destroy(&device);
+    assert_eq!(
+        device.take_calls(),
+        buffers
+            .iter()
+            .flat_map(|buffer| vec![
+                Call::UnmapMemory(buffer.memory),
+                Call::DestroyBuffer(buffer.buffer),
+                Call::FreeMemory(buffer.memory),
+            ])
+            .collect::<Vec<_>>()
+    );
+}
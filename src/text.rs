@@ -0,0 +1,524 @@
+use crate::{buffer::Buffer, gpu_device::Device, lifetime, texture::Texture};
+use ash::{version::DeviceV1_0, vk};
+use rusttype::{point, Font, Scale};
+use std::{collections::HashMap, mem::size_of};
+
+/// Height in pixels at which the glyphs are baked in the atlas.
+pub const BAKE_SIZE: f32 = 32.0;
+
+/// Distance in pixels encoded around the outlines of the glyphs.
+const SDF_SPREAD: i32 = 4;
+
+/// Width in pixels of the atlas. Its height depends on the baked glyphs.
+const ATLAS_WIDTH: u32 = 512;
+
+/// Maximum number of characters drawn in a frame.
+pub const MAX_TEXT_CHARS: usize = 4096;
+
+/// Character drawn in place of the characters missing from the atlas.
+const FALLBACK_CHAR: char = '?';
+
+/// Placement of a glyph in the atlas.
+///
+/// Offsets and sizes are in pixels at `BAKE_SIZE`.
+#[derive(Clone, Copy, Debug)]
+struct GlyphInfo {
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    /// Offset of the top left corner of the quad from the pen on the baseline.
+    offset: [f32; 2],
+    size: [f32; 2],
+    advance: f32,
+}
+
+/// Signed distance field of the printable ASCII and Latin-1 characters of a font.
+///
+/// Each texel stores the distance to the closest outline, remapped so 0.5 is
+/// on the outline and values above are inside. Text can then be drawn crisp at
+/// any size from a single bake.
+pub struct FontAtlas {
+    font: Font<'static>,
+    glyphs: HashMap<char, GlyphInfo>,
+    extent: vk::Extent2D,
+    ascent: f32,
+    line_height: f32,
+}
+
+impl FontAtlas {
+    /// Bake the atlas of the TrueType font in `font_data`.
+    ///
+    /// # Returns
+    ///
+    /// The atlas and its RGBA8 pixels, the distance being stored in every channel.
+    pub fn bake(font_data: Vec<u8>) -> (Self, Vec<u8>) {
+        let font = Font::from_bytes(font_data).expect("Failed to parse font");
+        let scale = Scale::uniform(BAKE_SIZE);
+        let v_metrics = font.v_metrics(scale);
+
+        let chars = (32..127u32)
+            .chain(160..256)
+            .filter_map(std::char::from_u32)
+            .collect::<Vec<_>>();
+
+        // Pack the distance fields in rows
+        let mut fields = Vec::with_capacity(chars.len());
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for c in chars {
+            let glyph = font.glyph(c).scaled(scale).positioned(point(0.0, 0.0));
+            let advance = glyph.unpositioned().h_metrics().advance_width;
+            let (width, height, offset, field) = match glyph.pixel_bounding_box() {
+                Some(bounds) => {
+                    let (width, height) = (bounds.width(), bounds.height());
+                    let mut coverage = vec![0.0; (width * height) as usize];
+                    glyph.draw(|x, y, value| {
+                        coverage[(y as i32 * width + x as i32) as usize] = value;
+                    });
+                    let offset = [
+                        (bounds.min.x - SDF_SPREAD) as f32,
+                        (bounds.min.y - SDF_SPREAD) as f32,
+                    ];
+                    let field = compute_distance_field(&coverage, width, height);
+                    (
+                        width + 2 * SDF_SPREAD,
+                        height + 2 * SDF_SPREAD,
+                        offset,
+                        field,
+                    )
+                }
+                None => (0, 0, [0.0, 0.0], Vec::new()),
+            };
+
+            if x + width > ATLAS_WIDTH as i32 {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            fields.push((c, [x, y], [width, height], offset, advance, field));
+            x += width;
+            row_height = row_height.max(height);
+        }
+        let extent = vk::Extent2D {
+            width: ATLAS_WIDTH,
+            height: (y + row_height) as _,
+        };
+
+        let mut pixels = vec![0; (extent.width * extent.height * 4) as usize];
+        let mut glyphs = HashMap::new();
+        for (c, position, size, offset, advance, field) in fields {
+            for row in 0..size[1] {
+                for column in 0..size[0] {
+                    let value = field[(row * size[0] + column) as usize];
+                    let index = ((position[1] + row) * ATLAS_WIDTH as i32 + position[0] + column)
+                        as usize
+                        * 4;
+                    pixels[index..index + 4].copy_from_slice(&[value; 4]);
+                }
+            }
+
+            let uv = |x: i32, y: i32| {
+                [
+                    x as f32 / extent.width as f32,
+                    y as f32 / extent.height as f32,
+                ]
+            };
+            glyphs.insert(
+                c,
+                GlyphInfo {
+                    uv_min: uv(position[0], position[1]),
+                    uv_max: uv(position[0] + size[0], position[1] + size[1]),
+                    offset,
+                    size: [size[0] as f32, size[1] as f32],
+                    advance,
+                },
+            );
+        }
+
+        let atlas = FontAtlas {
+            font,
+            glyphs,
+            extent,
+            ascent: v_metrics.ascent,
+            line_height: v_metrics.ascent - v_metrics.descent + v_metrics.line_gap,
+        };
+        (atlas, pixels)
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Lay out `text` and append its quads to `vertices`.
+    ///
+    /// Positions are in pixels from the top left corner of the screen.
+    pub fn layout(&self, text: &Text, vertices: &mut Vec<TextVertex>) {
+        let scale = text.size / BAKE_SIZE;
+        let mut pen = [text.position[0], text.position[1] + self.ascent * scale];
+        let mut previous = None;
+        for c in text.text.chars() {
+            if c == '\n' {
+                pen = [text.position[0], pen[1] + self.line_height * scale];
+                previous = None;
+                continue;
+            }
+            let (c, glyph) = match self.glyphs.get(&c) {
+                Some(glyph) => (c, glyph),
+                None => (FALLBACK_CHAR, &self.glyphs[&FALLBACK_CHAR]),
+            };
+            if let Some(previous) = previous {
+                pen[0] += self
+                    .font
+                    .pair_kerning(Scale::uniform(BAKE_SIZE), previous, c)
+                    * scale;
+            }
+            previous = Some(c);
+
+            if glyph.size[0] > 0.0 {
+                let min = [
+                    pen[0] + glyph.offset[0] * scale,
+                    pen[1] + glyph.offset[1] * scale,
+                ];
+                let max = [
+                    min[0] + glyph.size[0] * scale,
+                    min[1] + glyph.size[1] * scale,
+                ];
+                let corner = |x: usize, y: usize| TextVertex {
+                    pos: [[min[0], max[0]][x], [min[1], max[1]][y]],
+                    coords: [
+                        [glyph.uv_min[0], glyph.uv_max[0]][x],
+                        [glyph.uv_min[1], glyph.uv_max[1]][y],
+                    ],
+                    color: text.color,
+                };
+                vertices.extend_from_slice(&[
+                    corner(0, 0),
+                    corner(0, 1),
+                    corner(1, 0),
+                    corner(1, 0),
+                    corner(0, 1),
+                    corner(1, 1),
+                ]);
+            }
+            pen[0] += glyph.advance * scale;
+        }
+    }
+}
+
+/// Compute the distance field of a glyph from its `coverage`.
+///
+/// The field has a border of `SDF_SPREAD` pixels on each side.
+fn compute_distance_field(coverage: &[f32], width: i32, height: i32) -> Vec<u8> {
+    let is_inside = |x: i32, y: i32| {
+        x >= 0 && y >= 0 && x < width && y < height && coverage[(y * width + x) as usize] >= 0.5
+    };
+
+    let (field_width, field_height) = (width + 2 * SDF_SPREAD, height + 2 * SDF_SPREAD);
+    let mut field = Vec::with_capacity((field_width * field_height) as usize);
+    for y in -SDF_SPREAD..height + SDF_SPREAD {
+        for x in -SDF_SPREAD..width + SDF_SPREAD {
+            let inside = is_inside(x, y);
+
+            // Closest pixel on the other side of the outline
+            let mut closest = SDF_SPREAD as f32;
+            for dy in -SDF_SPREAD..=SDF_SPREAD {
+                for dx in -SDF_SPREAD..=SDF_SPREAD {
+                    if is_inside(x + dx, y + dy) != inside {
+                        let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                        closest = closest.min(distance);
+                    }
+                }
+            }
+
+            let distance = if inside { closest } else { -closest };
+            let value = 0.5 + distance / (2.0 * SDF_SPREAD as f32);
+            field.push((value.clamp(0.0, 1.0) * 255.0) as u8);
+        }
+    }
+    field
+}
+
+/// String drawn in screen space.
+#[derive(Clone, Debug)]
+pub struct Text {
+    pub text: String,
+    /// Position in pixels of the top left corner of the text.
+    pub position: [f32; 2],
+    /// Height in pixels of a line.
+    pub size: f32,
+    pub color: [f32; 4],
+}
+
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub struct TextVertex {
+    pub pos: [f32; 2],
+    pub coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl TextVertex {
+    pub fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<TextVertex>() as _)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        let position_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(0)
+            .build();
+        let coords_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(8)
+            .build();
+        let color_desc = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(16)
+            .build();
+        [position_desc, coords_desc, color_desc]
+    }
+}
+
+/// Draws screen space text on top of the scene.
+///
+/// Like the sprites, the quads are built on the CPU by `update` and written
+/// in the vertex buffer of the swapchain image along with the indirect draw
+/// reading them.
+pub struct TextRenderer {
+    font: FontAtlas,
+    texture: Texture,
+    vertex_buffers: Vec<Buffer>,
+    indirect_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl TextRenderer {
+    /// Create the descriptor set containing the atlas of `font`.
+    ///
+    /// `texture` must contain the pixels returned by `FontAtlas::bake`.
+    /// `vertex_buffers` and `indirect_buffers` must be host visible and coherent
+    /// buffers of at least `TextRenderer::vertex_buffer_size()` and
+    /// `TextRenderer::indirect_buffer_size()` bytes, one per swapchain image.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(
+        device: &Device,
+        font: FontAtlas,
+        texture: Texture,
+        vertex_buffers: Vec<Buffer>,
+        indirect_buffers: Vec<Buffer>,
+    ) -> Self {
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build();
+        let image_infos = [image_info];
+        let atlas_write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[atlas_write], &[]) };
+
+        // Nothing is drawn until the first update
+        indirect_buffers
+            .iter()
+            .for_each(|buffer| buffer.write(device, 0, &[draw_command(0)]));
+
+        TextRenderer {
+            font,
+            texture,
+            vertex_buffers,
+            indirect_buffers,
+            layout,
+            pool,
+            set,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    /// Size in bytes of each vertex buffer.
+    pub fn vertex_buffer_size() -> vk::DeviceSize {
+        (size_of::<TextVertex>() * 6 * MAX_TEXT_CHARS) as _
+    }
+
+    /// Size in bytes of each indirect buffer.
+    pub fn indirect_buffer_size() -> vk::DeviceSize {
+        size_of::<vk::DrawIndirectCommand>() as _
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let atlas_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let bindings = [atlas_binding];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl TextRenderer {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Set the pipeline used to draw the text.
+    ///
+    /// The renderer takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Lay out `texts` on a screen of size `extent` and write the quads in the
+    /// buffers of the swapchain image `image_index`.
+    ///
+    /// At most `MAX_TEXT_CHARS` characters are drawn.
+    pub fn update(
+        &self,
+        device: &Device,
+        image_index: usize,
+        extent: vk::Extent2D,
+        texts: &[Text],
+    ) {
+        let mut vertices = Vec::new();
+        texts
+            .iter()
+            .for_each(|text| self.font.layout(text, &mut vertices));
+        vertices.truncate(MAX_TEXT_CHARS * 6);
+
+        // From pixels to normalized device coordinates
+        vertices.iter_mut().for_each(|vertex| {
+            vertex.pos = [
+                vertex.pos[0] / extent.width as f32 * 2.0 - 1.0,
+                vertex.pos[1] / extent.height as f32 * 2.0 - 1.0,
+            ];
+        });
+
+        if !vertices.is_empty() {
+            self.vertex_buffers[image_index].write(device, 0, &vertices);
+        }
+        self.indirect_buffers[image_index].write(device, 0, &[draw_command(vertices.len() as _)]);
+    }
+
+    /// Record the draw of the text in the command buffer of the swapchain image `image_index`.
+    ///
+    /// It must be recorded in the second subpass of the main render pass,
+    /// after the decals.
+    pub fn cmd_draw(&self, device: &Device, command_buffer: vk::CommandBuffer, image_index: usize) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[self.vertex_buffers[image_index].buffer],
+                &[0],
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            device.cmd_draw_indirect(
+                command_buffer,
+                self.indirect_buffers[image_index].buffer,
+                0,
+                1,
+                size_of::<vk::DrawIndirectCommand>() as _,
+            );
+        }
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.texture.destroy(device);
+        self.vertex_buffers
+            .iter_mut()
+            .chain(self.indirect_buffers.iter_mut())
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
+
+fn draw_command(vertex_count: u32) -> vk::DrawIndirectCommand {
+    vk::DrawIndirectCommand {
+        vertex_count,
+        instance_count: 1,
+        first_vertex: 0,
+        first_instance: 0,
+    }
+}
@@ -1,4 +1,9 @@
-use ash::{version::DeviceV1_0, vk, Device};
+use crate::{
+    gpu_device::GpuDevice,
+    lifetime::{self, ObjectKind},
+    memory::{self, AllocationError},
+};
+use ash::vk;
 
 #[derive(Clone, Copy)]
 pub struct Texture {
@@ -23,14 +28,204 @@ impl Texture {
         }
     }
 
-    pub fn destroy(&mut self, device: &Device) {
+    pub fn destroy(&mut self, device: &impl GpuDevice) {
         unsafe {
             if let Some(sampler) = self.sampler.take() {
-                device.destroy_sampler(sampler, None);
+                device.destroy_sampler(sampler);
             }
-            device.destroy_image_view(self.view, None);
-            device.destroy_image(self.image, None);
-            device.free_memory(self.memory, None);
+            device.destroy_image_view(self.view);
         }
+        lifetime::destroy_image(device, self.image);
+        memory::free(device, self.memory);
+    }
+}
+
+/// Create an image from `image_info` and bind it to the memory `allocate`
+/// returns for its requirements.
+///
+/// # Errors
+///
+/// Fail if the memory cannot be allocated, nothing is created then.
+pub fn create_image(
+    device: &impl GpuDevice,
+    image_info: &vk::ImageCreateInfo,
+    allocate: impl FnOnce(vk::MemoryRequirements) -> Result<vk::DeviceMemory, AllocationError>,
+) -> Result<(vk::Image, vk::DeviceMemory), AllocationError> {
+    let image = unsafe { device.create_image(image_info).unwrap() };
+    let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+    let memory = match allocate(mem_requirements) {
+        Ok(memory) => memory,
+        Err(error) => {
+            unsafe { device.destroy_image(image) };
+            return Err(error);
+        }
+    };
+
+    unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+    lifetime::track(
+        device,
+        ObjectKind::Image,
+        image,
+        format_args!(
+            "{}x{} {:?}",
+            image_info.extent.width, image_info.extent.height, image_info.format
+        ),
+    );
+    Ok((image, memory))
+}
+
+/// Color space in which the values of a texture are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Colors authored for display like albedo maps, decoded to linear when sampled.
+    Srgb,
+    /// Data like normal, height or roughness maps, sampled as is.
+    Linear,
+}
+
+impl ColorSpace {
+    /// Get the format of RGBA8 textures in this color space.
+    pub fn rgba8_format(self) -> vk::Format {
+        match self {
+            ColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
+/// Dimensionality of a texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureKind {
+    Texture2D,
+    /// 2D texture with several layers, sampled with a `sampler2DArray`.
+    Texture2DArray,
+    /// 3D texture, like a color lookup table.
+    Texture3D,
+}
+
+/// Description of a texture to create.
+///
+/// Storage images used by compute shaders are described with the `STORAGE`
+/// usage, the format must then support `STORAGE_IMAGE` with optimal tiling.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureDesc {
+    /// Depth must be 1 for 2D textures.
+    pub extent: vk::Extent3D,
+    pub format: vk::Format,
+    pub mip_levels: u32,
+    /// Must be 1 for 2D and 3D textures.
+    pub layers: u32,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    pub kind: TextureKind,
+}
+
+impl TextureDesc {
+    /// Describe a 2D texture without mipmaps nor multisampling.
+    pub fn new_2d(extent: vk::Extent2D, format: vk::Format, usage: vk::ImageUsageFlags) -> Self {
+        TextureDesc {
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            format,
+            mip_levels: 1,
+            layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            usage,
+            kind: TextureKind::Texture2D,
+        }
+    }
+
+    /// Describe a 2D texture array of `layers` layers without mipmaps.
+    pub fn new_2d_array(
+        extent: vk::Extent2D,
+        layers: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Self {
+        TextureDesc {
+            layers,
+            kind: TextureKind::Texture2DArray,
+            ..Self::new_2d(extent, format, usage)
+        }
+    }
+
+    /// Describe a 3D texture without mipmaps.
+    pub fn new_3d(extent: vk::Extent3D, format: vk::Format, usage: vk::ImageUsageFlags) -> Self {
+        TextureDesc {
+            extent,
+            format,
+            mip_levels: 1,
+            layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            usage,
+            kind: TextureKind::Texture3D,
+        }
+    }
+}
+
+impl TextureDesc {
+    /// Get the info to create the image with optimal tiling.
+    pub fn image_create_info(&self) -> vk::ImageCreateInfo {
+        let image_type = match self.kind {
+            TextureKind::Texture2D | TextureKind::Texture2DArray => vk::ImageType::TYPE_2D,
+            TextureKind::Texture3D => vk::ImageType::TYPE_3D,
+        };
+        vk::ImageCreateInfo::builder()
+            .image_type(image_type)
+            .extent(self.extent)
+            .mip_levels(self.mip_levels)
+            .array_layers(self.layers)
+            .format(self.format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(self.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(self.samples)
+            .flags(vk::ImageCreateFlags::empty())
+            .build()
+    }
+
+    /// Get the aspect of the view of the texture.
+    ///
+    /// Depth/stencil textures are viewed through their depth aspect.
+    pub fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        match self.format {
+            vk::Format::D16_UNORM
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D32_SFLOAT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT => vk::ImageAspectFlags::DEPTH,
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    /// Get the range covering all the mip levels and layers of the texture.
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: self.aspect_mask(),
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: self.layers,
+        }
+    }
+
+    /// Get the info to create a view of the whole `image`.
+    pub fn view_create_info(&self, image: vk::Image) -> vk::ImageViewCreateInfo {
+        let view_type = match self.kind {
+            TextureKind::Texture2D => vk::ImageViewType::TYPE_2D,
+            TextureKind::Texture2DArray => vk::ImageViewType::TYPE_2D_ARRAY,
+            TextureKind::Texture3D => vk::ImageViewType::TYPE_3D,
+        };
+        vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(view_type)
+            .format(self.format)
+            .subresource_range(self.subresource_range())
+            .build()
     }
 }
@@ -0,0 +1,697 @@
+#[cfg(feature = "debug-markers")]
+use crate::debug_markers;
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    gpu_device::Device,
+    submit_batch::SubmitBatch,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::{
+    convert::TryInto,
+    fs::{self, File},
+    io::{self, ErrorKind, Read},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// First bytes of the level files.
+const LEVEL_MAGIC: &[u8; 4] = b"VTM1";
+
+/// Size in bytes of the header of the level files: the magic, the width and the height.
+const LEVEL_HEADER_SIZE: usize = 12;
+
+/// Largest width or height of the levels uploaded when the texture is created.
+const INITIAL_LEVEL_SIZE: u32 = 128;
+
+/// Size in bytes of the staging buffer, the most uploaded each frame.
+pub const TEXTURE_STREAMING_STAGING_SIZE: vk::DeviceSize = 4 << 20;
+
+/// Get the number of levels of the mip chain of a texture of `extent`.
+///
+/// Like the textures with generated mipmaps, it stops at the level whose
+/// smallest side is one texel.
+pub fn mip_level_count(extent: vk::Extent2D) -> u32 {
+    32 - extent.width.min(extent.height).leading_zeros()
+}
+
+/// Make sure the levels of the mip chain of the texture `name` are stored in
+/// `directory` and get the extent of its first level.
+///
+/// If they are missing, the texture is loaded with `load_source`, which
+/// returns its extent and its sRGB RGBA8 pixels, and its levels are computed
+/// and written, one file per level.
+pub fn prepare_mip_files<F>(
+    directory: &Path,
+    name: &str,
+    load_source: F,
+) -> io::Result<vk::Extent2D>
+where
+    F: FnOnce() -> (vk::Extent2D, Vec<u8>),
+{
+    match read_level_header(&level_path(directory, name, 0)) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        result => return result,
+    }
+
+    log::info!(
+        "Writing the mip chain of {} to {}.",
+        name,
+        directory.display()
+    );
+    fs::create_dir_all(directory)?;
+    let (extent, pixels) = load_source();
+    let mut level = (extent, pixels);
+    for i in 0..mip_level_count(extent) {
+        if i > 0 {
+            level = downsample(level.0, &level.1);
+        }
+        write_level(&level_path(directory, name, i), level.0, &level.1)?;
+    }
+    Ok(extent)
+}
+
+fn level_path(directory: &Path, name: &str, level: u32) -> PathBuf {
+    directory.join(format!("{}.mip{}", name, level))
+}
+
+fn read_level_header(path: &Path) -> io::Result<vk::Extent2D> {
+    let mut header = [0; LEVEL_HEADER_SIZE];
+    File::open(path)?.read_exact(&mut header)?;
+    decode_level_header(&header)
+}
+
+fn decode_level_header(header: &[u8]) -> io::Result<vk::Extent2D> {
+    if header.len() < LEVEL_HEADER_SIZE || &header[..4] != LEVEL_MAGIC {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Not a texture level file",
+        ));
+    }
+    let read_u32 =
+        |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+    Ok(vk::Extent2D {
+        width: read_u32(4),
+        height: read_u32(8),
+    })
+}
+
+/// Read the pixels of the level file at `path`, checking it has `extent`.
+fn read_level(path: &Path, extent: vk::Extent2D) -> io::Result<Vec<u8>> {
+    let mut bytes = fs::read(path)?;
+    let size = (extent.width * extent.height * 4) as usize;
+    let header = decode_level_header(&bytes)?;
+    if (header.width, header.height) != (extent.width, extent.height)
+        || bytes.len() != LEVEL_HEADER_SIZE + size
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Unexpected texture level size",
+        ));
+    }
+    bytes.drain(..LEVEL_HEADER_SIZE);
+    Ok(bytes)
+}
+
+fn write_level(path: &Path, extent: vk::Extent2D, pixels: &[u8]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(LEVEL_HEADER_SIZE + pixels.len());
+    bytes.extend_from_slice(LEVEL_MAGIC);
+    bytes.extend_from_slice(&extent.width.to_le_bytes());
+    bytes.extend_from_slice(&extent.height.to_le_bytes());
+    bytes.extend_from_slice(pixels);
+    fs::write(path, bytes)
+}
+
+/// Compute the next level of the sRGB RGBA8 `pixels` of `extent` with a box filter.
+///
+/// The colors are averaged in linear space, like the blits generating the
+/// mipmaps of sRGB textures.
+fn downsample(extent: vk::Extent2D, pixels: &[u8]) -> (vk::Extent2D, Vec<u8>) {
+    let to_linear = (0..=255u8)
+        .map(|value| {
+            let value = value as f32 / 255.0;
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        })
+        .collect::<Vec<_>>();
+    let to_srgb = |value: f32| {
+        let value = if value <= 0.003_130_8 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        };
+        (value * 255.0).round() as u8
+    };
+
+    let next = vk::Extent2D {
+        width: (extent.width / 2).max(1),
+        height: (extent.height / 2).max(1),
+    };
+    let mut next_pixels = Vec::with_capacity((next.width * next.height * 4) as usize);
+    for y in 0..next.height {
+        for x in 0..next.width {
+            let texel = |dx: u32, dy: u32| {
+                let sx = (x * 2 + dx).min(extent.width - 1);
+                let sy = (y * 2 + dy).min(extent.height - 1);
+                ((sy * extent.width + sx) * 4) as usize
+            };
+            let texels = [texel(0, 0), texel(1, 0), texel(0, 1), texel(1, 1)];
+            for channel in 0..3 {
+                let sum = texels
+                    .iter()
+                    .map(|texel| to_linear[pixels[texel + channel] as usize])
+                    .sum::<f32>();
+                next_pixels.push(to_srgb(sum * 0.25));
+            }
+            let alpha = texels
+                .iter()
+                .map(|texel| pixels[texel + 3] as u32)
+                .sum::<u32>();
+            next_pixels.push(((alpha + 2) / 4) as u8);
+        }
+    }
+    (next, next_pixels)
+}
+
+/// Level being uploaded a band of rows at a time.
+struct LevelUpload {
+    level: u32,
+    pixels: Vec<u8>,
+    /// Number of rows already uploaded.
+    rows: u32,
+}
+
+/// Mip chain of a texture uploaded progressively, coarser levels first.
+///
+/// The levels are stored in files on disk. Only the levels no larger than
+/// `INITIAL_LEVEL_SIZE` are uploaded when the texture is created, and the
+/// finer levels are filled with them upsampled, so the texture is usable right
+/// away without decoding nor uploading its full resolution.
+///
+/// Each frame `update` compares the size the texture covers on screen to its
+/// resident levels. If a finer level is needed it is requested to a loading
+/// thread which reads its file, then uploaded across frames in bands of at
+/// most `TEXTURE_STREAMING_STAGING_SIZE` bytes. Once complete it is upsampled
+/// again to the finer levels.
+///
+/// The image and its memory are owned by the texture, which keeps its whole
+/// mip chain allocated since descriptor sets and recorded command buffers
+/// refer to its view. The uploads are added to the submission of the frames,
+/// ahead of their commands.
+pub struct TextureStreamer {
+    image: vk::Image,
+    extent: vk::Extent2D,
+    mip_levels: u32,
+    /// Finest level uploaded, the finer levels hold it upsampled.
+    resident_level: u32,
+    /// Finest level requested to the loading thread.
+    requested_level: u32,
+    upload: Option<LevelUpload>,
+    staging: Buffer,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    /// Fence of the frame of the last upload, signaled when the staging
+    /// buffer can be written again.
+    upload_fence: vk::Fence,
+    requests: Option<Sender<u32>>,
+    loaded: Receiver<(u32, io::Result<Vec<u8>>)>,
+    handle: Option<JoinHandle<()>>,
+    directory: PathBuf,
+    name: String,
+}
+
+impl TextureStreamer {
+    /// Start the thread loading the levels of the texture `name` stored in
+    /// `directory` with `prepare_mip_files`.
+    ///
+    /// `image` must have `extent` and `mip_level_count(extent)` levels, the
+    /// `TRANSFER_SRC` and `TRANSFER_DST` usages and a format supporting linear
+    /// blits. `staging` must be a host visible and coherent transfer source of
+    /// `TEXTURE_STREAMING_STAGING_SIZE` bytes.
+    ///
+    /// The initial levels must then be uploaded with
+    /// `cmd_upload_initial_levels`.
+    pub fn new(
+        device: &Device,
+        queue_family_index: u32,
+        (directory, name): (PathBuf, &str),
+        (image, extent): (vk::Image, vk::Extent2D),
+        staging: Buffer,
+    ) -> Self {
+        let mip_levels = mip_level_count(extent);
+        let initial_level = (0..mip_levels)
+            .find(|level| extent.width.max(extent.height) >> level <= INITIAL_LEVEL_SIZE)
+            .unwrap_or(mip_levels - 1);
+
+        let (requests, receiver) = mpsc::channel::<u32>();
+        let (sender, loaded) = mpsc::channel();
+        let thread_directory = directory.clone();
+        let thread_name = name.to_owned();
+        let handle = thread::Builder::new()
+            .name("texture streaming".to_owned())
+            .spawn(move || {
+                for level in receiver {
+                    let path = level_path(&thread_directory, &thread_name, level);
+                    let pixels = read_level(&path, level_extent(extent, level));
+                    if sender.send((level, pixels)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+
+        let command_pool = {
+            let pool_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(queue_family_index)
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .build();
+            unsafe { device.create_command_pool(&pool_info, None).unwrap() }
+        };
+        let command_buffer = {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1)
+                .build();
+            unsafe { device.allocate_command_buffers(&allocate_info).unwrap()[0] }
+        };
+
+        TextureStreamer {
+            image,
+            extent,
+            mip_levels,
+            resident_level: initial_level,
+            requested_level: initial_level,
+            upload: None,
+            staging,
+            command_pool,
+            command_buffer,
+            upload_fence: vk::Fence::null(),
+            requests: Some(requests),
+            loaded,
+            handle: Some(handle),
+            directory,
+            name: name.to_owned(),
+        }
+    }
+
+    /// Read the initial levels, one after the other, to upload with
+    /// `cmd_upload_initial_levels`.
+    pub fn read_initial_levels(&self) -> io::Result<Vec<u8>> {
+        let mut pixels = Vec::new();
+        for level in self.resident_level..self.mip_levels {
+            let path = level_path(&self.directory, &self.name, level);
+            pixels.extend(read_level(&path, level_extent(self.extent, level))?);
+        }
+        Ok(pixels)
+    }
+}
+
+impl TextureStreamer {
+    /// Get the finest level uploaded and the number of levels.
+    pub fn resident_levels(&self) -> (u32, u32) {
+        (self.resident_level, self.mip_levels)
+    }
+
+    /// Record the upload of the initial levels from `staging`, filled with
+    /// `read_initial_levels`, and their upsampling to the finer levels.
+    ///
+    /// The image is left in the `SHADER_READ_ONLY_OPTIMAL` layout.
+    pub fn cmd_upload_initial_levels(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        staging: Buffer,
+    ) {
+        let initial_level = self.resident_level;
+        let mut offset = 0;
+        let regions = (initial_level..self.mip_levels)
+            .map(|level| {
+                let extent = level_extent(self.extent, level);
+                let region = copy_region(offset, level, (0, extent.height), extent.width);
+                offset += (extent.width * extent.height * 4) as vk::DeviceSize;
+                region
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            self.cmd_transition(
+                device,
+                command_buffer,
+                (0, self.mip_levels),
+                (
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                ),
+                (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+                (
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                ),
+            );
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+            if initial_level + 1 < self.mip_levels {
+                self.cmd_transition(
+                    device,
+                    command_buffer,
+                    (initial_level + 1, self.mip_levels),
+                    (
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                    (
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    (
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    ),
+                );
+            }
+            self.cmd_upsample(device, command_buffer, initial_level);
+        }
+    }
+
+    /// Stream the levels needed by a texture covering `coverage` pixels on
+    /// screen along its largest side, and upload the next band of the level
+    /// being streamed.
+    ///
+    /// It must be called once per frame, the upload being added to `batch`
+    /// before the commands of the frame.
+    pub fn update(&mut self, device: &Device, batch: &mut SubmitBatch, coverage: f32) {
+        // The staging buffer is still being read by the last upload
+        if !batch.is_complete(device, self.upload_fence) {
+            return;
+        }
+
+        // Request the next finer level once the previous one is uploaded
+        let target_level = self.target_level(coverage);
+        if self.upload.is_none()
+            && self.requested_level == self.resident_level
+            && target_level < self.resident_level
+        {
+            self.requested_level -= 1;
+            if let Some(requests) = self.requests.as_ref() {
+                let _ = requests.send(self.requested_level);
+            }
+        }
+        if self.upload.is_none() {
+            match self.loaded.try_recv() {
+                Ok((level, Ok(pixels))) => {
+                    self.upload = Some(LevelUpload {
+                        level,
+                        pixels,
+                        rows: 0,
+                    })
+                }
+                Ok((level, Err(error))) => {
+                    log::warn!(
+                        "Failed to load level {} of {}. Cause: {}",
+                        level,
+                        self.name,
+                        error
+                    );
+                    // Stop streaming, the texture keeps its current levels
+                    self.requests.take();
+                }
+                Err(_) => {}
+            }
+        }
+
+        if self.upload.is_some() {
+            self.upload_fence = batch.fence();
+            self.upload_band(device, batch);
+        }
+    }
+
+    /// Get the finest level needed to draw the texture over `coverage` pixels.
+    fn target_level(&self, coverage: f32) -> u32 {
+        let size = self.extent.width.max(self.extent.height) as f32;
+        let level = (size / coverage.max(1.0)).log2().floor().max(0.0) as u32;
+        level.min(self.mip_levels - 1)
+    }
+
+    /// Upload the next band of rows of the level being streamed and, if it
+    /// is complete, upsample it to the finer levels.
+    fn upload_band(&mut self, device: &Device, batch: &mut SubmitBatch) {
+        let upload = self.upload.as_mut().unwrap();
+        let level = upload.level;
+        let extent = level_extent(self.extent, level);
+        let row_size = extent.width * 4;
+        let band_rows = ((TEXTURE_STREAMING_STAGING_SIZE / row_size as vk::DeviceSize) as u32)
+            .max(1)
+            .min(extent.height - upload.rows);
+        let start = (upload.rows * row_size) as usize;
+        let end = start + (band_rows * row_size) as usize;
+        self.staging.write(device, 0, &upload.pixels[start..end]);
+        let region = copy_region(0, level, (upload.rows, band_rows), extent.width);
+        upload.rows += band_rows;
+        let complete = upload.rows == extent.height;
+
+        let command_buffer = self.command_buffer;
+        unsafe {
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build();
+            device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .unwrap();
+            #[cfg(feature = "debug-markers")]
+            debug_markers::cmd_begin_label(
+                command_buffer,
+                &format!("Stream level {} of {}", level, self.name),
+                debug_markers::UPLOAD_COLOR,
+            );
+
+            // Previous frames might still sample the levels
+            let first_level = if complete { 0 } else { level };
+            self.cmd_transition(
+                device,
+                command_buffer,
+                (first_level, level + 1),
+                (
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                ),
+                (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+                (
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::PipelineStageFlags::TRANSFER,
+                ),
+            );
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                self.staging.buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+            if complete {
+                self.cmd_upsample(device, command_buffer, level);
+            } else {
+                self.cmd_transition(
+                    device,
+                    command_buffer,
+                    (level, level + 1),
+                    (
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                    (
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    (
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    ),
+                );
+            }
+            #[cfg(feature = "debug-markers")]
+            debug_markers::cmd_end_label(command_buffer);
+            device.end_command_buffer(command_buffer).unwrap();
+        }
+        batch.push(command_buffer);
+
+        if complete {
+            log::debug!("Level {} of {} streamed.", level, self.name);
+            self.resident_level = level;
+            self.upload = None;
+        }
+    }
+
+    /// Record the blits of `level` to each finer level, in turn.
+    ///
+    /// The levels up to `level` must be in the `TRANSFER_DST_OPTIMAL` layout,
+    /// and are left in the `SHADER_READ_ONLY_OPTIMAL` layout.
+    unsafe fn cmd_upsample(&self, device: &Device, command_buffer: vk::CommandBuffer, level: u32) {
+        for source in (1..=level).rev() {
+            self.cmd_transition(
+                device,
+                command_buffer,
+                (source, source + 1),
+                (
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                ),
+                (
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::TRANSFER_READ,
+                ),
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                ),
+            );
+            let subresource = |mip_level| vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level,
+                base_array_layer: 0,
+                layer_count: 1,
+            };
+            let offsets = |extent: vk::Extent2D| {
+                [
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: extent.width as _,
+                        y: extent.height as _,
+                        z: 1,
+                    },
+                ]
+            };
+            let blit = vk::ImageBlit::builder()
+                .src_subresource(subresource(source))
+                .src_offsets(offsets(level_extent(self.extent, source)))
+                .dst_subresource(subresource(source - 1))
+                .dst_offsets(offsets(level_extent(self.extent, source - 1)))
+                .build();
+            device.cmd_blit_image(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        let read_only = |levels, old_layout, src_access| {
+            self.cmd_transition(
+                device,
+                command_buffer,
+                levels,
+                (old_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                (src_access, vk::AccessFlags::SHADER_READ),
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                ),
+            )
+        };
+        if level > 0 {
+            read_only(
+                (1, level + 1),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+            );
+        }
+        read_only(
+            (0, 1),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+    }
+
+    /// Record the transition of the levels from `first_level` up to
+    /// `end_level` excluded.
+    fn cmd_transition(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (first_level, end_level): (u32, u32),
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+        (src_access_mask, dst_access_mask): (vk::AccessFlags, vk::AccessFlags),
+        (src_stage, dst_stage): (vk::PipelineStageFlags, vk::PipelineStageFlags),
+    ) {
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: first_level,
+            level_count: end_level - first_level,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::image(
+                self.image,
+                range,
+                (old_layout, new_layout),
+                Access::new(src_stage, src_access_mask),
+                Access::new(dst_stage, dst_access_mask),
+            )],
+        );
+    }
+
+    /// Stop the loading thread and destroy the upload objects.
+    ///
+    /// The image is destroyed with its texture.
+    pub fn destroy(&mut self, device: &Device) {
+        // Closing the channel stops the thread after its current level
+        self.requests.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        unsafe { device.destroy_command_pool(self.command_pool, None) };
+        self.staging.destroy(device);
+    }
+}
+
+fn level_extent(extent: vk::Extent2D, level: u32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+    }
+}
+
+/// Build the copy of `row_count` rows of `level` from `first_row`, of `width`
+/// texels, from `offset` in a staging buffer.
+fn copy_region(
+    offset: vk::DeviceSize,
+    level: u32,
+    (first_row, row_count): (u32, u32),
+    width: u32,
+) -> vk::BufferImageCopy {
+    vk::BufferImageCopy {
+        buffer_offset: offset,
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: level,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D {
+            x: 0,
+            y: first_row as _,
+            z: 0,
+        },
+        image_extent: vk::Extent3D {
+            width,
+            height: row_count,
+            depth: 1,
+        },
+    }
+}
@@ -0,0 +1,99 @@
+use winit::VirtualKeyCode;
+
+/// Slowest speed of the scene time relative to the real time.
+pub const MIN_TIME_SCALE: f32 = 0.1;
+
+/// Fastest speed of the scene time relative to the real time.
+pub const MAX_TIME_SCALE: f32 = 4.0;
+
+/// Time animating the scene, which can be paused, stepped and slowed down or sped up.
+///
+/// The time advances by the duration of each frame multiplied by the scale,
+/// so the animations and the particles follow it while the camera and the UI,
+/// which use the duration of the frames, do not.
+///
+/// The keys are P to pause or resume, `.` to advance the paused time by a
+/// frame, and `[` and `]` to halve and double the scale.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeController {
+    time: f32,
+    scale: f32,
+    paused: bool,
+    /// Advance the paused time by the next frame.
+    step_requested: bool,
+}
+
+impl TimeController {
+    /// Create a controller starting at `time` seconds, running at the real speed.
+    pub fn new(time: f32) -> Self {
+        TimeController {
+            time,
+            scale: 1.0,
+            paused: false,
+            step_requested: false,
+        }
+    }
+
+    /// Get the time in seconds animating the scene.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Set the speed of the time, clamped between `MIN_TIME_SCALE` and `MAX_TIME_SCALE`.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+        log::debug!("Time scale: {}", self.scale);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.step_requested = false;
+        log::debug!("Time paused: {}", self.paused);
+    }
+
+    /// Advance the time by the next frame if it is paused.
+    pub fn step(&mut self) {
+        if self.paused {
+            self.step_requested = true;
+        }
+    }
+
+    /// Get the scene time elapsed during the next frame if it lasts `delta` seconds.
+    pub fn scaled_delta(&self, delta: f32) -> f32 {
+        if self.paused && !self.step_requested {
+            0.0
+        } else {
+            delta * self.scale
+        }
+    }
+
+    /// Advance the time after a frame of `delta` seconds.
+    pub fn advance(&mut self, delta: f32) {
+        self.time += self.scaled_delta(delta);
+        self.step_requested = false;
+    }
+
+    /// Apply the action bound to `key`.
+    ///
+    /// # Returns
+    ///
+    /// Whether `key` is bound to an action.
+    pub fn handle_key(&mut self, key: VirtualKeyCode) -> bool {
+        match key {
+            VirtualKeyCode::P => self.set_paused(!self.paused),
+            VirtualKeyCode::Period => self.step(),
+            VirtualKeyCode::LBracket => self.set_scale(self.scale * 0.5),
+            VirtualKeyCode::RBracket => self.set_scale(self.scale * 2.0),
+            _ => return false,
+        }
+        true
+    }
+}
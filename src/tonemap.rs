@@ -0,0 +1,372 @@
+use crate::{
+    buffer::Buffer, exposure::ExposureData, gpu_device::Device, lifetime,
+    swapchain::SwapchainProperties, texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::mem::size_of;
+
+const HDR_COLOR_BINDING: u32 = 0;
+const EXPOSURE_BINDING: u32 = 1;
+const COLOR_LUT_BINDING: u32 = 2;
+const BLOOM_BINDING: u32 = 3;
+
+/// Pass mapping the exposed HDR image to the swapchain images.
+///
+/// It draws a full screen triangle sampling the HDR image, or the output of
+/// the previous post processing pass, multiplies it by
+/// the exposure computed on the gpu and applies a filmic curve. The result is
+/// then graded with a 3D color lookup table. With the `BLOOM` variant of the
+/// shader, the bloom chain is blended with the HDR image before the exposure.
+/// The pass is left open after the triangle so overlays can be drawn without
+/// tonemapping. The HDR image is sampled bilinearly, so it is upscaled when
+/// the scene is rendered at a smaller render scale.
+pub struct Tonemapper {
+    sampler: vk::Sampler,
+    color_lut: Texture,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    render_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+    extent: vk::Extent2D,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Tonemapper {
+    /// Create the descriptor set reading the exposure from `exposure_buffer`.
+    ///
+    /// `color_lut` is a sampled 3D texture in the `SHADER_READ_ONLY_OPTIMAL`
+    /// layout, it is owned by the tonemapper. Use a neutral LUT to disable
+    /// color grading.
+    ///
+    /// The targets must be set using `set_targets` and the pipeline using
+    /// `set_pipeline` before recording the pass.
+    pub fn new(device: &Device, exposure_buffer: Buffer, color_lut: Texture) -> Self {
+        let sampler = Self::create_sampler(device);
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let buffer_infos = [vk::DescriptorBufferInfo::builder()
+            .buffer(exposure_buffer.buffer)
+            .offset(0)
+            .range(size_of::<ExposureData>() as _)
+            .build()];
+        let lut_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(color_lut.view)
+            .sampler(color_lut.sampler.unwrap())
+            .build()];
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(EXPOSURE_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(COLOR_LUT_BINDING)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&lut_infos)
+                .build(),
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Tonemapper {
+            sampler,
+            color_lut,
+            layout,
+            pool,
+            set,
+            render_pass: vk::RenderPass::null(),
+            framebuffers: Vec::new(),
+            extent: vk::Extent2D::default(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(HDR_COLOR_BINDING)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(EXPOSURE_BINDING)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(COLOR_LUT_BINDING)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(BLOOM_BINDING)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 3,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    fn create_render_pass(
+        device: &Device,
+        format: vk::Format,
+        final_layout: vk::ImageLayout,
+    ) -> vk::RenderPass {
+        // The triangle covers the whole target so it does not need to be cleared
+        let attachment_descs = [vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(final_layout)
+            .build()];
+
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let subpass_descs = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .build()];
+
+        let subpass_deps = [vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )
+            .build()];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descs)
+            .subpasses(&subpass_descs)
+            .dependencies(&subpass_deps)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+}
+
+impl Tonemapper {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the render pass of the tonemapper.
+    ///
+    /// It is only valid after `set_targets`.
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    /// Create the render pass and the framebuffers writing to `views`.
+    ///
+    /// `views` are the views of the swapchain images, described by `properties`
+    /// and left in `final_layout` at the end of the pass. `hdr` must be in
+    /// `hdr_layout` when the pass begins.
+    pub fn set_targets(
+        &mut self,
+        device: &Device,
+        (hdr, hdr_layout): (Texture, vk::ImageLayout),
+        properties: SwapchainProperties,
+        final_layout: vk::ImageLayout,
+        views: &[vk::ImageView],
+    ) {
+        let extent = properties.extent;
+        let render_pass = Self::create_render_pass(device, properties.format.format, final_layout);
+        let framebuffers = views
+            .iter()
+            .map(|view| {
+                let attachments = [*view];
+                let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass)
+                    .attachments(&attachments)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1)
+                    .build();
+                unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+            })
+            .collect();
+
+        let image_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(hdr_layout)
+            .image_view(hdr.view)
+            .sampler(self.sampler)
+            .build()];
+        let descriptor_writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(HDR_COLOR_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build()];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        self.render_pass = render_pass;
+        self.framebuffers = framebuffers;
+        self.extent = extent;
+    }
+
+    /// Set the bloom chain read by the `BLOOM` variant of the shader.
+    ///
+    /// `chain` must be in the `GENERAL` layout when the pass begins.
+    pub fn set_bloom(&mut self, device: &Device, chain: Texture) {
+        let image_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(chain.view)
+            .sampler(chain.sampler.unwrap())
+            .build()];
+        let descriptor_writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(BLOOM_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build()];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = layout;
+    }
+
+    /// Begin the pass writing to the swapchain image `image_index` and tonemap the HDR image.
+    ///
+    /// The exposure must have been computed before. Overlays can then be drawn
+    /// before ending the pass with `cmd_end`.
+    pub fn cmd_begin(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffers[image_index])
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .build();
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        };
+    }
+
+    pub fn cmd_end(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+    }
+
+    /// Destroy the pipeline and the targets, which depend on the swapchain.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.framebuffers
+                .iter()
+                .for_each(|framebuffer| device.destroy_framebuffer(*framebuffer, None));
+            device.destroy_render_pass(self.render_pass, None);
+        }
+        self.framebuffers.clear();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.color_lut.destroy(device);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{barrier::write_accesses, debug::is_validation_enabled, gpu_device::Device};
+use ash::{version::DeviceV1_0, vk};
+
+/// Layout of an image and the last accesses to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    /// Stages of the last accesses.
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+impl ImageState {
+    /// State of an image whose content is not needed.
+    pub fn undefined() -> Self {
+        ImageState {
+            layout: vk::ImageLayout::UNDEFINED,
+            stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+            access: vk::AccessFlags::empty(),
+        }
+    }
+
+    /// Get the usual stage and access of an image in `layout`.
+    ///
+    /// # Panics
+    ///
+    /// Panic if `layout` is not supported.
+    pub fn for_layout(layout: vk::ImageLayout) -> Self {
+        let (stage, access) = match layout {
+            vk::ImageLayout::UNDEFINED => return Self::undefined(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            _ => panic!("Unsupported image layout {:?}.", layout),
+        };
+        ImageState {
+            layout,
+            stage,
+            access,
+        }
+    }
+}
+
+/// Image keeping track of its layout and of the last accesses to it while
+/// commands are recorded.
+///
+/// Transitions emit the barriers needed from the current state so they do
+/// not have to be written by hand for each pass. The state is only valid
+/// for the command buffer being recorded, so images written by a render pass
+/// are tracked from the final layout of the pass.
+pub struct TrackedImage {
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    state: ImageState,
+}
+
+impl TrackedImage {
+    pub fn new(
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        state: ImageState,
+    ) -> Self {
+        TrackedImage {
+            image,
+            subresource_range,
+            state,
+        }
+    }
+}
+
+impl TrackedImage {
+    /// Check that the image is in `layout`.
+    ///
+    /// It only checks when validation layers are enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panic if the layout does not match.
+    pub fn assert_layout(&self, layout: vk::ImageLayout) {
+        if is_validation_enabled() {
+            assert_eq!(
+                self.state.layout, layout,
+                "Image {:?} is not in the expected layout",
+                self.image
+            );
+        }
+    }
+
+    /// Record the barrier making the image usable in `layout` by `access` at `stage`.
+    ///
+    /// Nothing is recorded when the layout does not change and neither the
+    /// previous accesses nor the new ones write to the image. The accesses
+    /// are then merged so the next write waits for all of them.
+    ///
+    /// # Panics
+    ///
+    /// Panic if validation layers are enabled and `layout` is `UNDEFINED` or
+    /// `PREINITIALIZED`, which cannot be transitioned to.
+    pub fn transition_to(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+        stage: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+    ) {
+        if is_validation_enabled() {
+            assert!(
+                layout != vk::ImageLayout::UNDEFINED && layout != vk::ImageLayout::PREINITIALIZED,
+                "Image {:?} cannot be transitioned to {:?}",
+                self.image,
+                layout
+            );
+        }
+
+        let is_write = |access: vk::AccessFlags| access.intersects(write_accesses());
+        if layout == self.state.layout && !is_write(self.state.access) && !is_write(access) {
+            self.state.stage |= stage;
+            self.state.access |= access;
+            return;
+        }
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(self.state.layout)
+            .new_layout(layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(self.subresource_range)
+            .src_access_mask(self.state.access & write_accesses())
+            .dst_access_mask(access)
+            .build();
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                self.state.stage,
+                stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            )
+        };
+
+        self.state = ImageState {
+            layout,
+            stage,
+            access,
+        };
+    }
+}
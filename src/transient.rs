@@ -0,0 +1,125 @@
+use crate::{buffer::Buffer, gpu_device::GpuDevice};
+use ash::vk;
+use std::mem::size_of_val;
+
+/// Size in bytes of the ring buffer of each swapchain image.
+pub const TRANSIENT_BUFFER_SIZE: vk::DeviceSize = 4 * 1024 * 1024;
+
+/// Slice of a transient buffer only valid during the frame it was allocated for.
+///
+/// It lies in the buffer of the swapchain image of that frame.
+#[derive(Clone, Copy, Debug)]
+pub struct TransientAllocation {
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+/// Allocator of short-lived data written by the cpu each frame.
+///
+/// Each swapchain image has a host visible buffer that stays mapped. Allocations
+/// are slices taken one after the other from the buffer of the current image and
+/// are all released at once by `begin_frame`, once the frame that last used the
+/// image is done. Nothing is allocated from the driver after creation.
+pub struct TransientBufferAllocator {
+    buffers: Vec<Buffer>,
+    mapped_buffers: Vec<*mut u8>,
+    image_index: usize,
+    offset: vk::DeviceSize,
+}
+
+impl TransientBufferAllocator {
+    /// Create the allocator.
+    ///
+    /// `buffers` must be host visible and coherent buffers of
+    /// `TRANSIENT_BUFFER_SIZE` bytes with `TransientBufferAllocator::usage()`,
+    /// one per swapchain image.
+    pub fn new(device: &impl GpuDevice, buffers: Vec<Buffer>) -> Self {
+        let mapped_buffers = buffers
+            .iter()
+            .map(|buffer| unsafe {
+                device.map_memory(buffer.memory, 0, vk::WHOLE_SIZE).unwrap() as *mut u8
+            })
+            .collect();
+
+        TransientBufferAllocator {
+            buffers,
+            mapped_buffers,
+            image_index: 0,
+            offset: 0,
+        }
+    }
+
+    /// Usages of the buffers.
+    pub fn usage() -> vk::BufferUsageFlags {
+        vk::BufferUsageFlags::UNIFORM_BUFFER
+            | vk::BufferUsageFlags::VERTEX_BUFFER
+            | vk::BufferUsageFlags::INDEX_BUFFER
+    }
+
+    /// Get the buffer of the swapchain image `image_index`.
+    ///
+    /// Draws can bind it once when recorded and reach the data of each frame
+    /// through offsets written in indirect commands.
+    pub fn buffer(&self, image_index: usize) -> vk::Buffer {
+        self.buffers[image_index].buffer
+    }
+
+    /// Release the allocations of the swapchain image `image_index` and allocate from its buffer.
+    ///
+    /// The fence of the last frame rendered to the image must be signaled.
+    pub fn begin_frame(&mut self, image_index: usize) {
+        self.image_index = image_index;
+        self.offset = 0;
+    }
+
+    /// Allocate `size` bytes at an offset multiple of `alignment` in the buffer of the current image.
+    ///
+    /// `alignment` does not need to be a power of two, so vertices can be
+    /// aligned on their stride and drawn with a first vertex instead of an offset.
+    /// Uniform data must be aligned on `minUniformBufferOffsetAlignment`. An
+    /// alignment of 0 is treated as 1.
+    ///
+    /// Return `None` if the buffer is full.
+    pub fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<TransientAllocation> {
+        let alignment = alignment.max(1);
+        let offset = match self.offset % alignment {
+            0 => self.offset,
+            remainder => self.offset + alignment - remainder,
+        };
+        if offset + size > TRANSIENT_BUFFER_SIZE {
+            return None;
+        }
+        self.offset = offset + size;
+        Some(TransientAllocation { offset, size })
+    }
+
+    /// Allocate and write `data`.
+    ///
+    /// Return `None` if the buffer is full.
+    pub fn push<T: Copy>(
+        &mut self,
+        data: &[T],
+        alignment: vk::DeviceSize,
+    ) -> Option<TransientAllocation> {
+        let allocation = self.allocate(size_of_val(data) as _, alignment)?;
+        unsafe {
+            let dst = self.mapped_buffers[self.image_index].add(allocation.offset as _);
+            std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, dst, allocation.size as _);
+        }
+        Some(allocation)
+    }
+
+    /// Unmap and destroy the buffers.
+    ///
+    /// The frames using them must be done.
+    pub fn destroy(&mut self, device: &impl GpuDevice) {
+        self.buffers.iter_mut().for_each(|buffer| {
+            unsafe { device.unmap_memory(buffer.memory) };
+            buffer.destroy(device);
+        });
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{
+    buffer::Buffer,
+    frame_layout::{write_model_texture, FRAME_SET},
+    gpu_device::Device,
+    lifetime, math,
+    texture::Texture,
+    UniformBufferObject,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{Deg, Matrix4, Point3, Vector3};
+use std::mem::size_of;
+
+/// Region of a target covered by a viewport, in fractions of the target size.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewportRegion {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRegion {
+    /// Get the rectangle covered by the region in a target of `extent`.
+    pub fn rect(&self, extent: vk::Extent2D) -> vk::Rect2D {
+        let width = extent.width as f32;
+        let height = extent.height as f32;
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: (self.x * width) as _,
+                y: (self.y * height) as _,
+            },
+            extent: vk::Extent2D {
+                width: ((self.width * width) as u32).max(1),
+                height: ((self.height * height) as u32).max(1),
+            },
+        }
+    }
+
+    /// Get the viewport covering the region in a target of `extent`.
+    pub fn viewport(&self, extent: vk::Extent2D) -> vk::Viewport {
+        let rect = self.rect(extent);
+        vk::Viewport {
+            x: rect.offset.x as _,
+            y: rect.offset.y as _,
+            width: rect.extent.width as _,
+            height: rect.extent.height as _,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    fn aspect(&self, extent: vk::Extent2D) -> f32 {
+        let rect = self.rect(extent);
+        rect.extent.width as f32 / rect.extent.height as f32
+    }
+}
+
+/// Camera of a scene view.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewCamera {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub fov: Deg<f32>,
+}
+
+impl ViewCamera {
+    /// Camera looking down at the origin from `height`, with -z at the top.
+    pub fn top_down(height: f32) -> Self {
+        ViewCamera {
+            eye: Point3::new(0.0, height, 0.0),
+            target: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::new(0.0, 0.0, -1.0),
+            fov: Deg(45.0),
+        }
+    }
+}
+
+/// Additional view of the scene drawn in a region of the main render pass.
+#[derive(Clone, Copy, Debug)]
+pub struct SecondaryView {
+    pub region: ViewportRegion,
+    pub camera: ViewCamera,
+    /// Color the region is cleared to before the model is drawn.
+    pub clear_color: [f32; 4],
+}
+
+impl SecondaryView {
+    /// Top-down view of the model in the top right corner of the screen.
+    pub fn minimap() -> Self {
+        SecondaryView {
+            region: ViewportRegion {
+                x: 0.72,
+                y: 0.03,
+                width: 0.25,
+                height: 0.25,
+            },
+            camera: ViewCamera::top_down(4.0),
+            clear_color: [0.05, 0.05, 0.05, 1.0],
+        }
+    }
+}
+
+/// Views of the scene drawn after the main view, like a minimap inset.
+///
+/// Each view renders the model again from its own camera into its region of
+/// the color attachment of the main render pass. Viewport and scissor are
+/// dynamic so one pipeline draws all the views, and the region is cleared
+/// before drawing so the main view does not show through.
+///
+/// Each view has its own uniform buffers in place of the frame uniform
+/// buffers, so it has its own set 0 per swapchain image.
+pub struct SecondaryViews {
+    views: Vec<SecondaryView>,
+    extent: vk::Extent2D,
+    uniform_buffers: Vec<Buffer>,
+    pool: vk::DescriptorPool,
+    /// Sets of each view for each swapchain image, views first.
+    sets: Vec<vk::DescriptorSet>,
+    image_count: usize,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl SecondaryViews {
+    /// Create the descriptor sets of each view.
+    ///
+    /// `frame_layout` is the layout of the frame descriptor sets and `texture` the
+    /// texture of the model. There must be one uniform buffer per view and per
+    /// swapchain image, host visible and coherent, ordered by view first.
+    ///
+    /// The pipeline must be set using `set_pipeline` before recording draws.
+    pub fn new(
+        device: &Device,
+        views: Vec<SecondaryView>,
+        frame_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        let set_count = uniform_buffers.len();
+        let image_count = set_count / views.len();
+        let pool = Self::create_descriptor_pool(device, set_count as _);
+        let sets = {
+            let layouts = vec![frame_layout; set_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let texture_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build()];
+        for (set, buffer) in sets.iter().zip(uniform_buffers.iter()) {
+            let buffer_infos = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size_of::<UniformBufferObject>() as _)
+                .build()];
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&buffer_infos)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&texture_infos)
+                    .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        SecondaryViews {
+            views,
+            extent: vk::Extent2D::default(),
+            uniform_buffers,
+            pool,
+            sets,
+            image_count,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, set_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: set_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: set_count,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(set_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl SecondaryViews {
+    /// Set the pipeline drawing the model and the extent of the target.
+    ///
+    /// The pipeline must have a dynamic viewport and scissor. The views take
+    /// ownership of the pipeline and its layout.
+    pub fn set_pipeline(
+        &mut self,
+        pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+        extent: vk::Extent2D,
+    ) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+        self.extent = extent;
+    }
+
+    /// Write the uniform buffers of the views for the swapchain image `image_index`.
+    ///
+    /// `frame_ubo` contains the model transform and the fog of the main view,
+    /// the cameras of the views replace its camera.
+    pub fn update(&self, device: &Device, image_index: usize, frame_ubo: UniformBufferObject) {
+        for (index, view) in self.views.iter().enumerate() {
+            let camera = view.camera;
+            let view_matrix = Matrix4::look_at(camera.eye, camera.target, camera.up);
+            let proj = math::perspective(camera.fov, view.region.aspect(self.extent), 0.1, 10.0);
+            let region = view.region.rect(self.extent).extent;
+            let ubo = UniformBufferObject {
+                screen_size: [region.width as _, region.height as _],
+                ..frame_ubo.with_camera(view_matrix, proj, camera.eye)
+            };
+            let buffer = self.uniform_buffers[self.set_index(index, image_index)];
+            buffer.write(device, 0, &[ubo]);
+        }
+    }
+
+    fn set_index(&self, view_index: usize, image_index: usize) -> usize {
+        view_index * self.image_count + image_index
+    }
+
+    /// Record the draws of the views of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// It must be recorded in the first subpass of the main render pass, after
+    /// the main view.
+    pub fn cmd_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        vertex_buffer: Buffer,
+        index_buffer: Buffer,
+        index_count: u32,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+        }
+
+        for (index, view) in self.views.iter().enumerate() {
+            let rect = view.region.rect(self.extent);
+            let clear_attachments = [
+                vk::ClearAttachment {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    color_attachment: 0,
+                    clear_value: vk::ClearValue {
+                        color: vk::ClearColorValue {
+                            float32: view.clear_color,
+                        },
+                    },
+                },
+                vk::ClearAttachment {
+                    aspect_mask: vk::ImageAspectFlags::DEPTH,
+                    color_attachment: 0,
+                    clear_value: vk::ClearValue {
+                        depth_stencil: vk::ClearDepthStencilValue {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
+                    },
+                },
+            ];
+            let clear_rects = [vk::ClearRect {
+                rect,
+                base_array_layer: 0,
+                layer_count: 1,
+            }];
+            let set_index = self.set_index(index, image_index);
+            unsafe {
+                device.cmd_clear_attachments(command_buffer, &clear_attachments, &clear_rects);
+                device.cmd_set_viewport(command_buffer, 0, &[view.region.viewport(self.extent)]);
+                device.cmd_set_scissor(command_buffer, 0, &[rect]);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    FRAME_SET,
+                    &self.sets[set_index..=set_index],
+                    &[],
+                );
+                device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+            }
+        }
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    /// Replace the texture of the model by `texture` after it was reloaded.
+    ///
+    /// The sets must not be in use.
+    pub fn set_texture(&self, device: &Device, texture: Texture) {
+        write_model_texture(device, &self.sets, texture);
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        lifetime::destroy_descriptor_pool(device, self.pool);
+        self.uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+    }
+}
@@ -0,0 +1,1022 @@
+#[cfg(feature = "debug-markers")]
+use crate::debug_markers;
+use crate::{
+    barrier::{cmd_barriers, Access, Barrier},
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    frame_layout::PASS_SET,
+    gpu_device::Device,
+    lifetime,
+    readback::ReadbackBuffer,
+    submit_batch::SubmitBatch,
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk, Instance,
+};
+use std::{mem::size_of, ptr};
+
+/// Width and height in texels of the virtual texture.
+pub const VIRTUAL_TEXTURE_SIZE: u32 = 8192;
+
+pub const VIRTUAL_TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// Number of pages of memory shared by the virtual pages.
+pub const PAGE_CACHE_SIZE: u32 = 256;
+
+/// Maximum number of pages bound and uploaded each frame.
+pub const MAX_PAGE_UPLOADS: u32 = 16;
+
+/// Maximum number of levels with pages, the size of the level offsets of the page table.
+///
+/// Must match `MAX_LEVELS` in `virtual_texture.frag`.
+const MAX_LEVELS: usize = 16;
+
+/// Number of cells of the pattern along each side of the texture.
+const PATTERN_CELLS: u32 = 32;
+
+const TEXTURE_BINDING: u32 = 0;
+const PAGE_TABLE_BINDING: u32 = 1;
+const FEEDBACK_BINDING: u32 = 2;
+
+/// Check that `device` can bind the pages of the virtual texture on the
+/// queue family `queue_family_index` and write the feedback from fragment
+/// shaders.
+pub fn is_supported(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    queue_family_index: u32,
+) -> bool {
+    let features = unsafe { instance.get_physical_device_features(device) };
+    if features.sparse_binding != vk::TRUE
+        || features.sparse_residency_image2_d != vk::TRUE
+        || features.fragment_stores_and_atomics != vk::TRUE
+    {
+        return false;
+    }
+
+    let queue_families = unsafe { instance.get_physical_device_queue_family_properties(device) };
+    let sparse_queue = queue_families
+        .get(queue_family_index as usize)
+        .filter(|family| family.queue_flags.contains(vk::QueueFlags::SPARSE_BINDING))
+        .is_some();
+
+    let mut count = 0;
+    unsafe {
+        instance
+            .fp_v1_0()
+            .get_physical_device_sparse_image_format_properties(
+                device,
+                VIRTUAL_TEXTURE_FORMAT,
+                vk::ImageType::TYPE_2D,
+                vk::SampleCountFlags::TYPE_1,
+                usage(),
+                vk::ImageTiling::OPTIMAL,
+                &mut count,
+                ptr::null_mut(),
+            )
+    };
+    sparse_queue && count > 0
+}
+
+/// Usages of the image of the virtual texture.
+pub fn usage() -> vk::ImageUsageFlags {
+    vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST
+}
+
+/// Number of levels of the virtual texture, down to one texel.
+pub fn mip_levels() -> u32 {
+    32 - VIRTUAL_TEXTURE_SIZE.leading_zeros()
+}
+
+/// Page granularity and mip tail of the sparse image.
+#[derive(Clone, Copy, Debug)]
+pub struct SparseLayout {
+    /// Width and height in texels of a page.
+    pub page_size: u32,
+    /// Size in bytes of the memory of a page.
+    pub page_bytes: vk::DeviceSize,
+    /// First level of the mip tail, the levels too small to be split in pages.
+    pub tail_first_lod: u32,
+    pub tail_size: vk::DeviceSize,
+    pub tail_offset: vk::DeviceSize,
+}
+
+impl SparseLayout {
+    /// Query the layout of the sparse `image` whose memory requirements are `requirements`.
+    ///
+    /// Get `None` if its pages are not square or if it has no mip tail, which
+    /// is always resident and sampled when no page is.
+    pub fn query(
+        device: &Device,
+        image: vk::Image,
+        requirements: vk::MemoryRequirements,
+    ) -> Option<Self> {
+        let sparse_requirements = unsafe {
+            let mut count = 0;
+            device.fp_v1_0().get_image_sparse_memory_requirements(
+                device.handle(),
+                image,
+                &mut count,
+                ptr::null_mut(),
+            );
+            let mut sparse_requirements =
+                vec![vk::SparseImageMemoryRequirements::default(); count as usize];
+            device.fp_v1_0().get_image_sparse_memory_requirements(
+                device.handle(),
+                image,
+                &mut count,
+                sparse_requirements.as_mut_ptr(),
+            );
+            sparse_requirements
+        };
+        let color = sparse_requirements.iter().find(|requirements| {
+            requirements
+                .format_properties
+                .aspect_mask
+                .contains(vk::ImageAspectFlags::COLOR)
+        })?;
+
+        let granularity = color.format_properties.image_granularity;
+        let tail_first_lod = color.image_mip_tail_first_lod;
+        if granularity.width != granularity.height
+            || tail_first_lod >= mip_levels()
+            || tail_first_lod as usize > MAX_LEVELS
+        {
+            return None;
+        }
+        Some(SparseLayout {
+            page_size: granularity.width,
+            page_bytes: requirements.alignment,
+            tail_first_lod,
+            tail_size: color.image_mip_tail_size,
+            tail_offset: color.image_mip_tail_offset,
+        })
+    }
+
+    /// Get the number of pages along each side of `level`.
+    fn level_pages(&self, level: u32) -> u32 {
+        ((VIRTUAL_TEXTURE_SIZE >> level).max(1) / self.page_size).max(1)
+    }
+
+    /// Get the index of the first page of each level with pages.
+    fn level_offsets(&self) -> [u32; MAX_LEVELS] {
+        let mut offsets = [0; MAX_LEVELS];
+        let mut offset = 0;
+        for level in 0..self.tail_first_lod {
+            offsets[level as usize] = offset;
+            offset += self.level_pages(level) * self.level_pages(level);
+        }
+        offsets
+    }
+
+    /// Get the number of pages of all the levels.
+    pub fn page_count(&self) -> u32 {
+        (0..self.tail_first_lod)
+            .map(|level| self.level_pages(level) * self.level_pages(level))
+            .sum()
+    }
+
+    /// Size in bytes of the texels of a page.
+    pub fn page_texel_bytes(&self) -> vk::DeviceSize {
+        (self.page_size * self.page_size * 4) as _
+    }
+
+    /// Size in bytes of each page table.
+    pub fn page_table_size(&self) -> vk::DeviceSize {
+        (size_of::<u32>() * (3 + MAX_LEVELS + self.page_count() as usize)) as _
+    }
+
+    /// Size in bytes of each feedback buffer.
+    pub fn feedback_size(&self) -> vk::DeviceSize {
+        (size_of::<u32>() as u32 * self.page_count()) as _
+    }
+
+    /// Size in bytes of each staging buffer.
+    pub fn staging_size(&self) -> vk::DeviceSize {
+        self.page_texel_bytes() * MAX_PAGE_UPLOADS as vk::DeviceSize
+    }
+}
+
+/// Level and position in pages of a virtual page.
+#[derive(Clone, Copy, Debug)]
+struct PageCoords {
+    level: u32,
+    x: u32,
+    y: u32,
+}
+
+/// Page of the texture to write to the cache.
+struct PageUpload {
+    page: u32,
+    slot: u32,
+}
+
+/// Texture of `VIRTUAL_TEXTURE_SIZE` texels per side whose pages are only
+/// resident when they are sampled, using sparse residency.
+///
+/// The image is created with the sparse residency flag and no memory, except
+/// for its mip tail. Its pages share `PAGE_CACHE_SIZE` pages of memory. The
+/// fragment shader drawing the texture writes in a feedback buffer the page
+/// it needs at the level of detail of each fragment, and samples the finest
+/// resident level, or the mip tail. The feedback is read back a few frames
+/// later without stalling, and `update` binds the missing pages with
+/// `vkQueueBindSparse`, coarser levels first, then uploads their texels. When
+/// the cache is full, the least recently requested pages are evicted and
+/// their memory reused once the frames in flight are done with them.
+///
+/// The residency of the pages is written in a page table per swapchain image
+/// read by the fragment shader, so the shader never samples a page without
+/// memory and the `shaderResourceResidency` feature is not needed.
+pub struct VirtualTexture {
+    image: vk::Image,
+    view: vk::ImageView,
+    sampler: vk::Sampler,
+    page_memory: vk::DeviceMemory,
+    tail_memory: vk::DeviceMemory,
+    sparse: SparseLayout,
+    page_coords: Vec<PageCoords>,
+    /// Cache slot of each virtual page, if resident.
+    pages: Vec<Option<u32>>,
+    /// Frame each virtual page was last requested.
+    last_used: Vec<u64>,
+    free_slots: Vec<u32>,
+    /// Evicted pages still bound to their slot, with the number of frames
+    /// before they are unbound.
+    retired: Vec<(u32, u32, usize)>,
+    frame: u64,
+    page_tables: Vec<Buffer>,
+    feedback_buffers: Vec<Buffer>,
+    feedback: ReadbackBuffer<u32>,
+    staging_buffers: Vec<Buffer>,
+    command_pool: vk::CommandPool,
+    upload_command_buffers: Vec<vk::CommandBuffer>,
+    /// Fences of the frames of the last uploads of each swapchain image.
+    upload_fences: Vec<vk::Fence>,
+    /// Semaphore signaled by the binds and waited for by the uploads.
+    bind_semaphore: vk::Semaphore,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl VirtualTexture {
+    /// Create the page tables and the objects uploading the pages.
+    ///
+    /// `image` must be a sparse resident image of `VIRTUAL_TEXTURE_SIZE`
+    /// texels per side with `mip_levels()` levels, `VIRTUAL_TEXTURE_FORMAT`
+    /// and `usage()`, and `sparse` its layout. `page_memory` must hold
+    /// `PAGE_CACHE_SIZE` pages and `tail_memory` the mip tail. The page tables
+    /// must be host visible and coherent storage buffers, the feedback
+    /// buffers storage buffers with the `TRANSFER_SRC` and `TRANSFER_DST`
+    /// usages, and the staging buffers host visible and coherent transfer
+    /// sources, of the sizes given by `sparse`, one of each per swapchain
+    /// image. `feedback` reads back a `u32` per page.
+    ///
+    /// The mip tail must be bound with `bind_mip_tail` and uploaded with
+    /// `cmd_upload_mip_tail`, and the pipeline set with `set_pipeline`,
+    /// before recording draws.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        queue_family_index: u32,
+        (image, view, sampler): (vk::Image, vk::ImageView, vk::Sampler),
+        (page_memory, tail_memory): (vk::DeviceMemory, vk::DeviceMemory),
+        sparse: SparseLayout,
+        (page_tables, feedback_buffers, feedback): (Vec<Buffer>, Vec<Buffer>, ReadbackBuffer<u32>),
+        staging_buffers: Vec<Buffer>,
+    ) -> Self {
+        let image_count = page_tables.len();
+        let page_coords = (0..sparse.tail_first_lod)
+            .flat_map(|level| {
+                let pages = sparse.level_pages(level);
+                (0..pages * pages).map(move |i| PageCoords {
+                    level,
+                    x: i % pages,
+                    y: i / pages,
+                })
+            })
+            .collect::<Vec<_>>();
+        let page_count = page_coords.len();
+
+        let command_pool = {
+            let pool_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(queue_family_index)
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .build();
+            unsafe { device.create_command_pool(&pool_info, None).unwrap() }
+        };
+        let upload_command_buffers = {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(image_count as _)
+                .build();
+            unsafe { device.allocate_command_buffers(&allocate_info).unwrap() }
+        };
+        let upload_fences = vec![vk::Fence::null(); image_count];
+        let bind_semaphore = {
+            let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
+            unsafe { device.create_semaphore(&semaphore_info, None).unwrap() }
+        };
+
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let sets = {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+        let image_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(view)
+            .sampler(sampler)
+            .build()];
+        for (i, set) in sets.iter().enumerate() {
+            let buffer_info = |buffer: Buffer| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer.buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()]
+            };
+            let page_table_infos = buffer_info(page_tables[i]);
+            let feedback_infos = buffer_info(feedback_buffers[i]);
+            let write = |binding, descriptor_type| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(descriptor_type)
+            };
+            let descriptor_writes = [
+                write(TEXTURE_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&image_infos)
+                    .build(),
+                write(PAGE_TABLE_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&page_table_infos)
+                    .build(),
+                write(FEEDBACK_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&feedback_infos)
+                    .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        VirtualTexture {
+            image,
+            view,
+            sampler,
+            page_memory,
+            tail_memory,
+            sparse,
+            page_coords,
+            pages: vec![None; page_count],
+            last_used: vec![0; page_count],
+            free_slots: (0..PAGE_CACHE_SIZE).rev().collect(),
+            retired: Vec::new(),
+            frame: 0,
+            page_tables,
+            feedback_buffers,
+            feedback,
+            staging_buffers,
+            command_pool,
+            upload_command_buffers,
+            upload_fences,
+            bind_semaphore,
+            layout,
+            pool,
+            sets,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type, stage_flags| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(stage_flags)
+                .build()
+        };
+        let fragment = vk::ShaderStageFlags::FRAGMENT;
+        let bindings = [
+            binding(
+                TEXTURE_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                fragment,
+            ),
+            binding(
+                PAGE_TABLE_BINDING,
+                vk::DescriptorType::STORAGE_BUFFER,
+                fragment,
+            ),
+            binding(
+                FEEDBACK_BINDING,
+                vk::DescriptorType::STORAGE_BUFFER,
+                fragment,
+            ),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: image_count * 2,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    /// Generate the texels of the levels of the mip tail, one after the other.
+    pub fn mip_tail_texels(sparse: &SparseLayout) -> Vec<u8> {
+        (sparse.tail_first_lod..mip_levels())
+            .flat_map(|level| {
+                let size = (VIRTUAL_TEXTURE_SIZE >> level).max(1);
+                generate_texels(level, (0, 0), (size, size))
+            })
+            .collect()
+    }
+}
+
+impl VirtualTexture {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    /// Get the number of resident pages.
+    pub fn resident_count(&self) -> usize {
+        self.pages.iter().filter(|page| page.is_some()).count()
+    }
+
+    /// Set the pipeline drawing the textured plane.
+    ///
+    /// The virtual texture takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Bind the memory of the mip tail on `queue` and wait for it.
+    pub fn bind_mip_tail(&self, device: &Device, queue: vk::Queue) {
+        let binds = [vk::SparseMemoryBind {
+            resource_offset: self.sparse.tail_offset,
+            size: self.sparse.tail_size,
+            memory: self.tail_memory,
+            memory_offset: 0,
+            flags: vk::SparseMemoryBindFlags::empty(),
+        }];
+        let opaque_binds = [vk::SparseImageOpaqueMemoryBindInfo::builder()
+            .image(self.image)
+            .binds(&binds)
+            .build()];
+        let bind_info = vk::BindSparseInfo::builder()
+            .image_opaque_binds(&opaque_binds)
+            .build();
+        queue_bind_sparse(device, queue, &bind_info);
+        unsafe { device.queue_wait_idle(queue).unwrap() };
+    }
+
+    /// Record the transition of the image to the `GENERAL` layout it stays in
+    /// and the upload of the mip tail from `staging`, filled with
+    /// `VirtualTexture::mip_tail_texels`.
+    pub fn cmd_upload_mip_tail(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        staging: Buffer,
+    ) {
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels(),
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let mut offset = 0;
+        let regions = (self.sparse.tail_first_lod..mip_levels())
+            .map(|level| {
+                let size = (VIRTUAL_TEXTURE_SIZE >> level).max(1);
+                let region = copy_region(offset, level, (0, 0), (size, size));
+                offset += (size * size * 4) as vk::DeviceSize;
+                region
+            })
+            .collect::<Vec<_>>();
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::image(
+                self.image,
+                range,
+                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL),
+                Access::none(),
+                Access::transfer_write(),
+            )],
+        );
+        unsafe {
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.buffer,
+                self.image,
+                vk::ImageLayout::GENERAL,
+                &regions,
+            )
+        };
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::image(
+                self.image,
+                range,
+                (vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL),
+                Access::transfer_write(),
+                Access::fragment_read(),
+            )],
+        );
+    }
+
+    /// Bind and upload the pages requested by the feedback of the swapchain
+    /// image `image_index` and write its page table.
+    ///
+    /// It must be called once per frame, after waiting for its fence and
+    /// before submitting `batch`. The binds are submitted to `queue` right
+    /// away and the uploads are added to `batch`, waiting for them.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: vk::Queue,
+        batch: &mut SubmitBatch,
+        image_index: usize,
+    ) {
+        self.frame += 1;
+        let frame = self.frame;
+        let latency = self.page_tables.len();
+
+        // Unbind the pages evicted long enough ago that no frame samples them
+        let mut unbound = Vec::new();
+        self.retired.retain_mut(|(page, slot, frames)| {
+            if *frames == 0 {
+                unbound.push((*page, *slot));
+                return false;
+            }
+            *frames -= 1;
+            true
+        });
+
+        // The feedback is as old as the frames in flight
+        let mut requested = Vec::new();
+        if let Some(flags) = self.feedback.read(device, image_index) {
+            for (page, _) in flags.iter().enumerate().filter(|(_, flag)| **flag != 0) {
+                // The coarser pages are requested too so the fallback improves quickly
+                let mut page = Some(page as u32);
+                while let Some(current) = page {
+                    if self.last_used[current as usize] == frame {
+                        break;
+                    }
+                    self.last_used[current as usize] = frame;
+                    requested.push(current);
+                    page = self.parent(current);
+                }
+            }
+        }
+        let retiring = |page| self.retired.iter().any(|(retired, _, _)| *retired == page);
+        let mut missing = requested
+            .into_iter()
+            .filter(|page| self.pages[*page as usize].is_none() && !retiring(*page))
+            .collect::<Vec<_>>();
+        missing.sort_by_key(|page| std::cmp::Reverse(self.page_coords[*page as usize].level));
+        missing.truncate(MAX_PAGE_UPLOADS as _);
+
+        let mut uploads = Vec::new();
+        for page in missing {
+            match self.free_slots.pop() {
+                Some(slot) => {
+                    self.pages[page as usize] = Some(slot);
+                    uploads.push(PageUpload { page, slot });
+                }
+                // The page is bound once the evicted slot is free again
+                None if self.evict_least_recently_used(latency) => {}
+                None => break,
+            }
+        }
+        // The memory of the unbound pages is reused from the next frame
+        self.free_slots
+            .extend(unbound.iter().map(|(_, slot)| *slot));
+
+        self.bind_pages(device, queue, &unbound, &uploads);
+        if !uploads.is_empty() {
+            self.upload_pages(device, batch, image_index, &uploads);
+        }
+        self.write_page_table(device, image_index);
+    }
+
+    /// Get the page of the next level covering `page`, unless it is in the mip tail.
+    fn parent(&self, page: u32) -> Option<u32> {
+        let coords = self.page_coords[page as usize];
+        let level = coords.level + 1;
+        if level >= self.sparse.tail_first_lod {
+            return None;
+        }
+        let offsets = self.sparse.level_offsets();
+        let pages = self.sparse.level_pages(level);
+        Some(offsets[level as usize] + (coords.y / 2) * pages + coords.x / 2)
+    }
+
+    /// Evict the resident page requested the longest ago, unless it was
+    /// requested this frame, or get `false` if every page is in use.
+    ///
+    /// The frames recorded from now on see the page as not resident, but it
+    /// stays bound until the frames in flight are done with it, then its slot
+    /// is freed.
+    fn evict_least_recently_used(&mut self, latency: usize) -> bool {
+        let frame = self.frame;
+        let least_recently_used = self
+            .pages
+            .iter()
+            .enumerate()
+            .filter_map(|(page, slot)| slot.map(|slot| (page, slot)))
+            .filter(|(page, _)| self.last_used[*page] < frame)
+            .min_by_key(|(page, _)| self.last_used[*page]);
+        match least_recently_used {
+            Some((page, slot)) => {
+                self.pages[page] = None;
+                self.retired.push((page as u32, slot, latency));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Bind the memory of `uploads` to their pages and unbind `unbound`.
+    fn bind_pages(
+        &self,
+        device: &Device,
+        queue: vk::Queue,
+        unbound: &[(u32, u32)],
+        uploads: &[PageUpload],
+    ) {
+        let binds = unbound
+            .iter()
+            .map(|(page, _)| self.page_bind(*page, None))
+            .chain(
+                uploads
+                    .iter()
+                    .map(|upload| self.page_bind(upload.page, Some(upload.slot))),
+            )
+            .collect::<Vec<_>>();
+        if binds.is_empty() {
+            return;
+        }
+
+        let image_binds = [vk::SparseImageMemoryBindInfo::builder()
+            .image(self.image)
+            .binds(&binds)
+            .build()];
+        let signal_semaphores = [self.bind_semaphore];
+        let mut bind_info_builder = vk::BindSparseInfo::builder().image_binds(&image_binds);
+        // The uploads wait for the binds
+        if !uploads.is_empty() {
+            bind_info_builder = bind_info_builder.signal_semaphores(&signal_semaphores);
+        }
+        queue_bind_sparse(device, queue, &bind_info_builder.build());
+    }
+
+    /// Build the bind of the memory of `slot` to `page`, or its unbind.
+    fn page_bind(&self, page: u32, slot: Option<u32>) -> vk::SparseImageMemoryBind {
+        let PageCoords { level, x, y } = self.page_coords[page as usize];
+        let page_size = self.sparse.page_size;
+        let level_size = (VIRTUAL_TEXTURE_SIZE >> level).max(1);
+        vk::SparseImageMemoryBind {
+            subresource: vk::ImageSubresource {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level,
+                array_layer: 0,
+            },
+            offset: vk::Offset3D {
+                x: (x * page_size) as _,
+                y: (y * page_size) as _,
+                z: 0,
+            },
+            extent: vk::Extent3D {
+                width: page_size.min(level_size),
+                height: page_size.min(level_size),
+                depth: 1,
+            },
+            memory: slot.map_or(vk::DeviceMemory::null(), |_| self.page_memory),
+            memory_offset: slot.map_or(0, |slot| slot as vk::DeviceSize * self.sparse.page_bytes),
+            flags: vk::SparseMemoryBindFlags::empty(),
+        }
+    }
+
+    /// Generate the texels of `uploads` in the staging buffer of the swapchain
+    /// image `image_index` and add their copy to the image to `batch`.
+    fn upload_pages(
+        &mut self,
+        device: &Device,
+        batch: &mut SubmitBatch,
+        image_index: usize,
+        uploads: &[PageUpload],
+    ) {
+        // Only waits if the uploads of the image are still running, which
+        // they should not be after a whole swapchain of frames
+        batch.wait_complete(device, self.upload_fences[image_index]);
+        self.upload_fences[image_index] = batch.fence();
+
+        let page_size = self.sparse.page_size;
+        let texel_bytes = self.sparse.page_texel_bytes();
+        let staging = self.staging_buffers[image_index];
+        let regions = uploads
+            .iter()
+            .enumerate()
+            .map(|(i, upload)| {
+                let PageCoords { level, x, y } = self.page_coords[upload.page as usize];
+                let level_size = (VIRTUAL_TEXTURE_SIZE >> level).max(1);
+                let extent = (page_size.min(level_size), page_size.min(level_size));
+                let origin = (x * page_size, y * page_size);
+                let offset = i as vk::DeviceSize * texel_bytes;
+                staging.write(device, offset, &generate_texels(level, origin, extent));
+                copy_region(offset, level, origin, extent)
+            })
+            .collect::<Vec<_>>();
+
+        let command_buffer = self.upload_command_buffers[image_index];
+        unsafe {
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build();
+            device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .unwrap();
+            #[cfg(feature = "debug-markers")]
+            debug_markers::cmd_begin_label(
+                command_buffer,
+                "Upload virtual texture pages",
+                debug_markers::UPLOAD_COLOR,
+            );
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.buffer,
+                self.image,
+                vk::ImageLayout::GENERAL,
+                &regions,
+            );
+        }
+        // The commands of the frame sample the pages
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::memory(
+                Access::transfer_write(),
+                Access::fragment_read(),
+            )],
+        );
+        #[cfg(feature = "debug-markers")]
+        debug_markers::cmd_end_label(command_buffer);
+        unsafe { device.end_command_buffer(command_buffer).unwrap() };
+
+        batch.wait(self.bind_semaphore, vk::PipelineStageFlags::TRANSFER);
+        batch.push(command_buffer);
+    }
+
+    /// Write the levels and the residency of the pages in the page table of
+    /// the swapchain image `image_index`.
+    ///
+    /// Must match `PageTable` in `virtual_texture.frag`.
+    fn write_page_table(&self, device: &Device, image_index: usize) {
+        let header = [
+            VIRTUAL_TEXTURE_SIZE,
+            self.sparse.page_size,
+            self.sparse.tail_first_lod,
+        ];
+        let data = header
+            .iter()
+            .copied()
+            .chain(self.sparse.level_offsets().iter().copied())
+            .chain(self.pages.iter().map(|slot| slot.is_some() as u32))
+            .collect::<Vec<_>>();
+        self.page_tables[image_index].write(device, 0, &data);
+    }
+
+    /// Record the clear of the feedback of the swapchain image `image_index`.
+    ///
+    /// It must be recorded before the render pass drawing the texture.
+    pub fn cmd_clear_feedback(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let feedback_buffer = self.feedback_buffers[image_index].buffer;
+        // The previous frame of the image copied the feedback
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::buffer_read_to_write(
+                feedback_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                Access::transfer_write(),
+            )],
+        );
+        unsafe { device.cmd_fill_buffer(command_buffer, feedback_buffer, 0, vk::WHOLE_SIZE, 0) };
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::buffer(
+                feedback_buffer,
+                Access::transfer_write(),
+                Access::new(
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::AccessFlags::SHADER_WRITE,
+                ),
+            )],
+        );
+    }
+
+    /// Record the draw of the textured plane with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder, image_index: usize) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_descriptor_sets(
+            self.pipeline_layout,
+            PASS_SET,
+            &self.sets[image_index..=image_index],
+            &[],
+        );
+        unsafe {
+            encoder
+                .device()
+                .cmd_draw(encoder.command_buffer(), 6, 1, 0, 0)
+        };
+    }
+
+    /// Record the copy of the feedback of the swapchain image `image_index`
+    /// to its readback buffer.
+    ///
+    /// It must be recorded after the render pass drawing the texture.
+    pub fn cmd_read_feedback(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let feedback_buffer = self.feedback_buffers[image_index].buffer;
+        cmd_barriers(
+            device,
+            command_buffer,
+            &[Barrier::buffer(
+                feedback_buffer,
+                Access::new(
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::AccessFlags::SHADER_WRITE,
+                ),
+                Access::transfer_read(),
+            )],
+        );
+        self.feedback
+            .cmd_copy(device, command_buffer, image_index, (feedback_buffer, 0));
+    }
+
+    /// Destroy the pipeline and its layout.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipeline(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipeline(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+            device.destroy_semaphore(self.bind_semaphore, None);
+            device.destroy_command_pool(self.command_pool, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_image_view(self.view, None);
+            lifetime::destroy_image(device, self.image);
+        }
+        crate::memory::free(device, self.page_memory);
+        crate::memory::free(device, self.tail_memory);
+        self.page_tables
+            .iter_mut()
+            .chain(self.feedback_buffers.iter_mut())
+            .chain(self.staging_buffers.iter_mut())
+            .for_each(|buffer| buffer.destroy(device));
+        self.feedback.destroy(device);
+    }
+}
+
+/// Build the copy of the texels at `offset` in a staging buffer to the
+/// region of `level` at `origin` of size `extent`.
+fn copy_region(
+    offset: vk::DeviceSize,
+    level: u32,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+) -> vk::BufferImageCopy {
+    vk::BufferImageCopy {
+        buffer_offset: offset,
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: level,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D {
+            x: x as _,
+            y: y as _,
+            z: 0,
+        },
+        image_extent: vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        },
+    }
+}
+
+/// Generate the RGBA texels of the region of `level` at `origin` of size `extent`.
+///
+/// The texture is a grid of `PATTERN_CELLS` cells of pseudo random colors
+/// with finer lines visible up close, the same at every level so the levels
+/// blend seamlessly.
+fn generate_texels(level: u32, (x0, y0): (u32, u32), (width, height): (u32, u32)) -> Vec<u8> {
+    let level_size = (VIRTUAL_TEXTURE_SIZE >> level).max(1) as f32;
+    let mut texels = Vec::with_capacity((width * height * 4) as usize);
+    for y in y0..y0 + height {
+        for x in x0..x0 + width {
+            let u = (x as f32 + 0.5) / level_size;
+            let v = (y as f32 + 0.5) / level_size;
+            let (cell_x, cell_y) = (
+                (u * PATTERN_CELLS as f32) as u32,
+                (v * PATTERN_CELLS as f32) as u32,
+            );
+            let hash = (cell_x.wrapping_mul(73_856_093) ^ cell_y.wrapping_mul(19_349_663))
+                .wrapping_mul(2_654_435_761);
+            let color = [
+                96 + (hash >> 24) as u8 / 2,
+                96 + (hash >> 16) as u8 / 2,
+                96 + (hash >> 8) as u8 / 2,
+            ];
+
+            // Lines every 1/16th of a cell, fading with the level
+            let line = |t: f32| (t * (PATTERN_CELLS * 16) as f32).fract() < 0.06;
+            let shade = if line(u) || line(v) {
+                0.5 + 0.5 * (level as f32 / 6.0).min(1.0)
+            } else {
+                1.0
+            };
+            texels.extend(color.iter().map(|c| (*c as f32 * shade) as u8));
+            texels.push(255);
+        }
+    }
+    texels
+}
+
+/// Submit `bind_info` to `queue`, which ash does not wrap.
+fn queue_bind_sparse(device: &Device, queue: vk::Queue, bind_info: &vk::BindSparseInfo) {
+    let result = unsafe {
+        device
+            .fp_v1_0()
+            .queue_bind_sparse(queue, 1, bind_info, vk::Fence::null())
+    };
+    if result != vk::Result::SUCCESS {
+        panic!("Failed to bind sparse memory. Cause: {}", result);
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+/// Time between two checks of the watched files.
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// Watch files for changes on a thread checking their modification time.
+///
+/// A change is only reported once the modification time stays the same
+/// between two checks, so a file still being written by an editor or an
+/// exporter is not read half written.
+pub struct FileWatcher {
+    changes: Receiver<PathBuf>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileWatcher {
+    /// Start watching `paths`, relative to the assets directory.
+    ///
+    /// Files that cannot be read are ignored until they can.
+    pub fn new(paths: &[&str]) -> Self {
+        let paths = paths
+            .iter()
+            .map(|path| Path::new("assets").join(path))
+            .collect::<Vec<_>>();
+        let (sender, changes) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let thread = thread::Builder::new()
+            .name("file watcher".to_owned())
+            .spawn(move || {
+                log::debug!("File watcher thread started.");
+                // Last modification time seen and whether it was reported
+                let mut states = paths
+                    .iter()
+                    .map(|path| (modified(path), true))
+                    .collect::<Vec<_>>();
+                while !thread_stop.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                    for (path, (last_modified, reported)) in paths.iter().zip(states.iter_mut()) {
+                        let modified = modified(path);
+                        if modified != *last_modified {
+                            *last_modified = modified;
+                            *reported = false;
+                        } else if !*reported && modified.is_some() {
+                            *reported = true;
+                            if sender.send(path.clone()).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            })
+            .unwrap();
+
+        FileWatcher {
+            changes,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Get the files that changed since the last call, waiting up to
+    /// `timeout` for the first one.
+    pub fn wait_changed_files(&self, timeout: Duration) -> Vec<PathBuf> {
+        match self.changes.recv_timeout(timeout) {
+            Ok(path) => std::iter::once(path)
+                .chain(self.changes.try_iter())
+                .collect(),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => Vec::new(),
+        }
+    }
+}
+
+/// Load the watched files on a thread when they change.
+///
+/// The files are decoded off the main thread, which only swaps the loaded
+/// assets in. A file failing to load, like a model still being exported, is
+/// skipped until it changes again.
+pub struct AssetLoader<T> {
+    loaded: Receiver<T>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> AssetLoader<T> {
+    /// Start watching `paths`, relative to the assets directory, and loading
+    /// them with `load` when they change.
+    ///
+    /// `load` is given the path relative to the assets directory.
+    pub fn new<F>(paths: &[&str], load: F) -> Self
+    where
+        F: Fn(&Path) -> T + Send + 'static,
+    {
+        let watcher = FileWatcher::new(paths);
+        let (sender, loaded) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let thread = thread::Builder::new()
+            .name("asset loader".to_owned())
+            .spawn(move || {
+                log::debug!("Asset loader thread started.");
+                while !thread_stop.load(Ordering::Relaxed) {
+                    let timeout = Duration::from_millis(POLL_INTERVAL_MS);
+                    for path in watcher.wait_changed_files(timeout) {
+                        let path = path.strip_prefix("assets").unwrap_or(&path);
+                        log::info!("Reloading {}.", path.display());
+                        match panic::catch_unwind(AssertUnwindSafe(|| load(path))) {
+                            Ok(asset) => {
+                                if sender.send(asset).is_err() {
+                                    return;
+                                }
+                            }
+                            Err(_) => log::error!("Failed to reload {}.", path.display()),
+                        }
+                    }
+                }
+            })
+            .unwrap();
+
+        AssetLoader {
+            loaded,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Get the assets loaded since the last call, without waiting.
+    pub fn loaded_assets(&self) -> Vec<T> {
+        self.loaded.try_iter().collect()
+    }
+}
+
+impl<T> Drop for AssetLoader<T> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,390 @@
+use crate::{
+    buffer::Buffer,
+    command_encoder::CommandEncoder,
+    frame_layout::{write_model_texture, FRAME_SET, PASS_SET},
+    gpu_device::Device,
+    lifetime,
+    render_target::RenderTarget,
+    texture::Texture,
+    UniformBufferObject,
+};
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{prelude::*, Matrix4, Point3, Vector3};
+use std::mem::size_of;
+
+/// Altitude of the water plane.
+///
+/// Must match `WATER_LEVEL` in `water.vert`.
+pub const WATER_LEVEL: f32 = -0.05;
+
+/// Width and height in pixels of the reflection render target.
+pub const REFLECTION_SIZE: u32 = 512;
+
+/// Width and height in pixels of the generated normal map.
+pub const NORMAL_MAP_SIZE: u32 = 128;
+
+const REFLECTION_MAP_BINDING: u32 = 0;
+const NORMAL_MAP_BINDING: u32 = 1;
+
+/// Build the matrix mirroring the scene relative to the water plane.
+pub fn reflection_matrix() -> Matrix4<f32> {
+    Matrix4::from_translation(Vector3::new(0.0, 2.0 * WATER_LEVEL, 0.0))
+        * Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0)
+}
+
+/// Generate a tileable RGBA8 tangent space normal map of `NORMAL_MAP_SIZE`
+/// x `NORMAL_MAP_SIZE` pixels made of a few sine waves.
+pub fn generate_normal_map() -> Vec<u8> {
+    // Frequencies are whole numbers of periods so the map tiles.
+    let waves = [
+        (3.0, 1.0, 0.0, 0.3),
+        (-2.0, 5.0, 1.3, 0.2),
+        (7.0, 4.0, 2.1, 0.1),
+        (-9.0, -11.0, 0.7, 0.05),
+    ];
+
+    let size = NORMAL_MAP_SIZE as usize;
+    let mut pixels = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let u = x as f32 / size as f32 * std::f32::consts::PI * 2.0;
+            let v = y as f32 / size as f32 * std::f32::consts::PI * 2.0;
+
+            let (mut du, mut dv) = (0.0, 0.0);
+            for (ku, kv, phase, amplitude) in waves.iter() {
+                let slope = amplitude * (ku * u + kv * v + phase).cos();
+                du += slope * ku;
+                dv += slope * kv;
+            }
+
+            let normal = Vector3::new(-du * 0.1, -dv * 0.1, 1.0);
+            let length = (normal.x * normal.x + normal.y * normal.y + normal.z * normal.z).sqrt();
+            for component in &[normal.x, normal.y, normal.z] {
+                pixels.push(((component / length * 0.5 + 0.5) * 255.0) as u8);
+            }
+            pixels.push(255);
+        }
+    }
+    pixels
+}
+
+/// Water plane reflecting the model.
+///
+/// Before the main render pass, the model is rendered in an offscreen
+/// render target using a camera mirrored relative to the water plane. The
+/// water shader then samples that reflection at the screen position of the
+/// fragment, distorted by an animated normal map, and blends it with the
+/// color of the water using the Fresnel factor.
+///
+/// The reflection pass uses its own uniform buffers in place of the frame
+/// uniform buffers, so it has its own set 0. The reflection and the normal map
+/// are bound in set 1, the camera and the time come from the frame uniforms.
+pub struct Water {
+    target: RenderTarget,
+    normal_map: Texture,
+    reflection_uniform_buffers: Vec<Buffer>,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    reflection_sets: Vec<vk::DescriptorSet>,
+    water_sets: Vec<vk::DescriptorSet>,
+    reflection_pipeline: vk::Pipeline,
+    reflection_pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl Water {
+    /// Create the descriptor sets of both passes.
+    ///
+    /// `frame_layout` is the layout of the frame descriptor sets and `texture` the
+    /// texture of the model. `target` is the render target of the reflection, with
+    /// a color and a depth attachment. Uniform buffers must be host visible and
+    /// coherent. There is one of each per swapchain image.
+    ///
+    /// The pipelines must be set using `set_pipelines` before recording draws.
+    pub fn new(
+        device: &Device,
+        frame_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        target: RenderTarget,
+        normal_map: Texture,
+        reflection_uniform_buffers: Vec<Buffer>,
+    ) -> Self {
+        let image_count = reflection_uniform_buffers.len();
+        let layout = Self::create_descriptor_set_layout(device);
+        let pool = Self::create_descriptor_pool(device, image_count as _);
+        let allocate_sets = |layout| {
+            let layouts = vec![layout; image_count];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+        let reflection_sets = allocate_sets(frame_layout);
+        let water_sets = allocate_sets(layout);
+
+        let image_info = |texture: &Texture| {
+            [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(texture.view)
+                .sampler(texture.sampler.unwrap())
+                .build()]
+        };
+        let buffer_info = |buffer: &Buffer, size| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size as _)
+                .build()]
+        };
+        let texture_infos = image_info(&texture);
+        let reflection_map_infos = image_info(&target.color(0));
+        let normal_map_infos = image_info(&normal_map);
+        for i in 0..image_count {
+            let reflection_ubo_infos = buffer_info(
+                &reflection_uniform_buffers[i],
+                size_of::<UniformBufferObject>(),
+            );
+            let write = |set, binding, descriptor_type| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(descriptor_type)
+            };
+            let descriptor_writes = [
+                write(reflection_sets[i], 0, vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&reflection_ubo_infos)
+                    .build(),
+                write(
+                    reflection_sets[i],
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .image_info(&texture_infos)
+                .build(),
+                write(
+                    water_sets[i],
+                    REFLECTION_MAP_BINDING,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .image_info(&reflection_map_infos)
+                .build(),
+                write(
+                    water_sets[i],
+                    NORMAL_MAP_BINDING,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .image_info(&normal_map_infos)
+                .build(),
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        Water {
+            target,
+            normal_map,
+            reflection_uniform_buffers,
+            layout,
+            pool,
+            reflection_sets,
+            water_sets,
+            reflection_pipeline: vk::Pipeline::null(),
+            reflection_pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let binding = |binding, descriptor_type| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(descriptor_type)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        };
+        let bindings = [
+            binding(
+                REFLECTION_MAP_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            ),
+            binding(
+                NORMAL_MAP_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            ),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    /// Create a pool for `image_count` reflection sets and `image_count` water sets.
+    fn create_descriptor_pool(device: &Device, image_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: image_count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_count * 3,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(image_count * 2)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+}
+
+impl Water {
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.target.render_pass()
+    }
+
+    /// Get the size of the reflection render target.
+    pub fn reflection_extent(&self) -> vk::Extent2D {
+        self.target.extent()
+    }
+
+    /// Set the pipelines used to draw the reflection of the model and the water plane.
+    ///
+    /// The water takes ownership of the pipelines and their layouts.
+    pub fn set_pipelines(
+        &mut self,
+        reflection: (vk::Pipeline, vk::PipelineLayout),
+        water: (vk::Pipeline, vk::PipelineLayout),
+    ) {
+        let (reflection_pipeline, reflection_pipeline_layout) = reflection;
+        self.reflection_pipeline = reflection_pipeline;
+        self.reflection_pipeline_layout = reflection_pipeline_layout;
+        let (pipeline, pipeline_layout) = water;
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Write the uniform buffers of the swapchain image `image_index`.
+    ///
+    /// `frame_ubo` contains the matrices of the main camera. The reflection
+    /// camera is derived from it.
+    pub fn update(&self, device: &Device, image_index: usize, frame_ubo: UniformBufferObject) {
+        let camera_position = Point3::from_homogeneous(frame_ubo.camera_position.into());
+        let reflection_ubo = frame_ubo.with_camera(
+            frame_ubo.view * reflection_matrix(),
+            frame_ubo.proj,
+            reflection_matrix().transform_point(camera_position),
+        );
+        self.reflection_uniform_buffers[image_index].write(device, 0, &[reflection_ubo]);
+    }
+
+    /// Record the reflection pass of the swapchain image `image_index` in `command_buffer`.
+    ///
+    /// It must be recorded outside of any render pass, before the main render pass.
+    pub fn cmd_render_reflection(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        vertex_buffer: Buffer,
+        index_buffer: Buffer,
+        index_count: u32,
+    ) {
+        self.target.cmd_begin(device, command_buffer);
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.reflection_pipeline,
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.reflection_pipeline_layout,
+                FRAME_SET,
+                &self.reflection_sets[image_index..=image_index],
+                &[],
+            );
+            device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+        }
+        self.target.cmd_end(device, command_buffer);
+    }
+
+    /// Record the draw of the water plane of the swapchain image `image_index` with `encoder`.
+    ///
+    /// The frame set must be bound with `FrameLayout::cmd_bind`.
+    pub fn cmd_draw(&self, encoder: &mut CommandEncoder, image_index: usize) {
+        encoder.bind_pipeline(self.pipeline);
+        encoder.bind_descriptor_sets(
+            self.pipeline_layout,
+            PASS_SET,
+            &[self.water_sets[image_index]],
+            &[],
+        );
+        // The vertices of the plane are generated by the vertex shader.
+        unsafe {
+            encoder
+                .device()
+                .cmd_draw(encoder.command_buffer(), 6, 1, 0, 0)
+        };
+    }
+
+    /// Destroy the pipelines and their layouts.
+    ///
+    /// It must be called when the swapchain is recreated.
+    pub fn destroy_pipelines(&mut self, device: &Device) {
+        unsafe {
+            lifetime::destroy_pipeline(device, self.reflection_pipeline);
+            device.destroy_pipeline_layout(self.reflection_pipeline_layout, None);
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.reflection_pipeline = vk::Pipeline::null();
+        self.reflection_pipeline_layout = vk::PipelineLayout::null();
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+    }
+
+    /// Replace the texture of the model by `texture` after it was reloaded.
+    ///
+    /// The sets must not be in use.
+    pub fn set_texture(&self, device: &Device, texture: Texture) {
+        write_model_texture(device, &self.reflection_sets, texture);
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_pipelines(device);
+        unsafe {
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.reflection_uniform_buffers
+            .iter_mut()
+            .for_each(|buffer| buffer.destroy(device));
+        self.normal_map.destroy(device);
+        self.target.destroy(device);
+    }
+}
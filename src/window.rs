@@ -0,0 +1,500 @@
+use crate::{
+    buffer::Buffer,
+    frame_layout::{write_model_texture, FRAME_SET},
+    gpu_device::Device,
+    lifetime, math,
+    swapchain::SwapchainProperties,
+    texture::Texture,
+    viewport::ViewCamera,
+    UniformBufferObject,
+};
+use ash::{
+    extensions::khr::{Surface, Swapchain},
+    version::DeviceV1_0,
+    vk,
+};
+use cgmath::Matrix4;
+use std::mem::size_of;
+use winit::{Window, WindowId};
+
+/// Additional window with its own surface and swapchain, sharing the device of the app.
+///
+/// It renders the model from its own camera using the vertex, index and
+/// texture of the main window, like the view of a tool next to the main view.
+/// It has a single frame in flight, waited for before each frame, so it only
+/// needs one uniform buffer and one descriptor set.
+///
+/// The swapchain and everything depending on it are set with `set_targets`,
+/// `set_pipeline` and `record_command_buffers` and destroyed with
+/// `destroy_targets` when the window is resized.
+pub struct SecondaryWindow {
+    window: Window,
+    surface_khr: vk::SurfaceKHR,
+    camera: ViewCamera,
+    uniform_buffer: Buffer,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    image_available_semaphore: vk::Semaphore,
+    render_finished_semaphore: vk::Semaphore,
+    fence: vk::Fence,
+    swapchain: Option<(Swapchain, vk::SwapchainKHR)>,
+    extent: vk::Extent2D,
+    image_views: Vec<vk::ImageView>,
+    depth_texture: Option<Texture>,
+    render_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl SecondaryWindow {
+    /// Create the descriptor set and the synchronization objects of the window.
+    ///
+    /// `surface_khr` is the surface of `window`. `frame_layout` is the layout of
+    /// the frame descriptor sets and `texture` the texture of the model. The
+    /// uniform buffer must be host visible and coherent.
+    pub fn new(
+        device: &Device,
+        (window, surface_khr): (Window, vk::SurfaceKHR),
+        camera: ViewCamera,
+        frame_layout: vk::DescriptorSetLayout,
+        texture: Texture,
+        uniform_buffer: Buffer,
+    ) -> Self {
+        let pool = Self::create_descriptor_pool(device);
+        let set = {
+            let layouts = [frame_layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts)
+                .build();
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let buffer_infos = [vk::DescriptorBufferInfo::builder()
+            .buffer(uniform_buffer.buffer)
+            .offset(0)
+            .range(size_of::<UniformBufferObject>() as _)
+            .build()];
+        let texture_infos = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view)
+            .sampler(texture.sampler.unwrap())
+            .build()];
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&texture_infos)
+                .build(),
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
+        let fence_info = vk::FenceCreateInfo::builder()
+            .flags(vk::FenceCreateFlags::SIGNALED)
+            .build();
+        let (image_available_semaphore, render_finished_semaphore, fence) = unsafe {
+            (
+                device.create_semaphore(&semaphore_info, None).unwrap(),
+                device.create_semaphore(&semaphore_info, None).unwrap(),
+                device.create_fence(&fence_info, None).unwrap(),
+            )
+        };
+
+        SecondaryWindow {
+            window,
+            surface_khr,
+            camera,
+            uniform_buffer,
+            pool,
+            set,
+            image_available_semaphore,
+            render_finished_semaphore,
+            fence,
+            swapchain: None,
+            extent: vk::Extent2D::default(),
+            image_views: Vec::new(),
+            depth_texture: None,
+            render_pass: vk::RenderPass::null(),
+            framebuffers: Vec::new(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            command_buffers: Vec::new(),
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        lifetime::create_descriptor_pool(device, &pool_info).unwrap()
+    }
+
+    fn create_render_pass(
+        device: &Device,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+    ) -> vk::RenderPass {
+        let attachment_descs = [
+            vk::AttachmentDescription::builder()
+                .format(color_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                .build(),
+            vk::AttachmentDescription::builder()
+                .format(depth_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .build(),
+        ];
+
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+        let subpass_descs = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build()];
+
+        let subpass_deps = [vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build()];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descs)
+            .subpasses(&subpass_descs)
+            .dependencies(&subpass_deps)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+}
+
+impl SecondaryWindow {
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    pub fn surface_khr(&self) -> vk::SurfaceKHR {
+        self.surface_khr
+    }
+
+    /// Get the size of the window, which is 0 when it is minimized.
+    pub fn dimensions(&self) -> [u32; 2] {
+        self.window
+            .get_inner_size()
+            .map(|size| [size.width as u32, size.height as u32])
+            .unwrap_or([0, 0])
+    }
+
+    /// Get the render pass drawing to the swapchain images.
+    ///
+    /// It is only valid after `set_targets`.
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    /// Check if the swapchain is created so frames can be drawn.
+    pub fn has_targets(&self) -> bool {
+        self.swapchain.is_some()
+    }
+
+    /// Set the swapchain and create the render pass and the framebuffers.
+    ///
+    /// `image_views` are the views of the swapchain images, described by
+    /// `properties`. The depth texture must have the extent of the swapchain.
+    /// The window takes ownership of all of them.
+    pub fn set_targets(
+        &mut self,
+        device: &Device,
+        swapchain: (Swapchain, vk::SwapchainKHR),
+        properties: SwapchainProperties,
+        image_views: Vec<vk::ImageView>,
+        (depth_texture, depth_format): (Texture, vk::Format),
+    ) {
+        let extent = properties.extent;
+        let render_pass = Self::create_render_pass(device, properties.format.format, depth_format);
+        let framebuffers = image_views
+            .iter()
+            .map(|view| {
+                let attachments = [*view, depth_texture.view];
+                let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass)
+                    .attachments(&attachments)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1)
+                    .build();
+                unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+            })
+            .collect();
+
+        self.swapchain = Some(swapchain);
+        self.extent = extent;
+        self.image_views = image_views;
+        self.depth_texture = Some(depth_texture);
+        self.render_pass = render_pass;
+        self.framebuffers = framebuffers;
+    }
+
+    /// Set the pipeline drawing the model.
+    ///
+    /// The window takes ownership of the pipeline and its layout.
+    pub fn set_pipeline(&mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) {
+        self.pipeline = pipeline;
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    /// Allocate and record the command buffers drawing the model in each swapchain image.
+    pub fn record_command_buffers(
+        &mut self,
+        device: &Device,
+        command_pool: vk::CommandPool,
+        vertex_buffer: Buffer,
+        index_buffer: Buffer,
+        index_count: u32,
+    ) {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(self.framebuffers.len() as _)
+            .build();
+        let command_buffers = unsafe { device.allocate_command_buffers(&allocate_info).unwrap() };
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        for (buffer, framebuffer) in command_buffers.iter().zip(self.framebuffers.iter()) {
+            let buffer = *buffer;
+            let begin_info = vk::CommandBufferBeginInfo::builder().build();
+            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(self.render_pass)
+                .framebuffer(*framebuffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: self.extent,
+                })
+                .clear_values(&clear_values)
+                .build();
+            unsafe {
+                device.begin_command_buffer(buffer, &begin_info).unwrap();
+                device.cmd_begin_render_pass(
+                    buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                device.cmd_bind_pipeline(buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+                device.cmd_bind_vertex_buffers(buffer, 0, &[vertex_buffer.buffer], &[0]);
+                device.cmd_bind_index_buffer(buffer, index_buffer.buffer, 0, vk::IndexType::UINT32);
+                device.cmd_bind_descriptor_sets(
+                    buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    FRAME_SET,
+                    &[self.set],
+                    &[],
+                );
+                device.cmd_draw_indexed(buffer, index_count, 1, 0, 0, 0);
+                device.cmd_end_render_pass(buffer);
+                device.end_command_buffer(buffer).unwrap();
+            }
+        }
+        self.command_buffers = command_buffers;
+    }
+
+    /// Draw and present a frame.
+    ///
+    /// `frame_ubo` contains the model transform and the fog of the main window,
+    /// the camera of the window replaces its camera. The previous frame is
+    /// waited for first.
+    ///
+    /// Return whether the swapchain must be recreated.
+    ///
+    /// # Errors
+    ///
+    /// Fail with `ERROR_DEVICE_LOST` if the device is lost.
+    pub fn draw(
+        &self,
+        device: &Device,
+        (graphics_queue, present_queue): (vk::Queue, vk::Queue),
+        frame_ubo: UniformBufferObject,
+    ) -> Result<bool, vk::Result> {
+        let (swapchain, swapchain_khr) = self.swapchain.as_ref().unwrap();
+        let wait_fences = [self.fence];
+        unsafe { device.wait_for_fences(&wait_fences, true, u64::MAX)? };
+
+        let result = unsafe {
+            swapchain.acquire_next_image(
+                *swapchain_khr,
+                u64::MAX,
+                self.image_available_semaphore,
+                vk::Fence::null(),
+            )
+        };
+        let image_index = match result {
+            Ok((image_index, _)) => image_index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(true),
+            Err(vk::Result::ERROR_DEVICE_LOST) => return Err(vk::Result::ERROR_DEVICE_LOST),
+            Err(error) => panic!("Error while acquiring next image. Cause: {}", error),
+        };
+
+        self.update(device, frame_ubo);
+        unsafe { device.reset_fences(&wait_fences).unwrap() };
+
+        let wait_semaphores = [self.image_available_semaphore];
+        let signal_semaphores = [self.render_finished_semaphore];
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let command_buffers = [self.command_buffers[image_index as usize]];
+        let submit_infos = [vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores)
+            .build()];
+        unsafe { device.queue_submit(graphics_queue, &submit_infos, self.fence)? };
+
+        let swapchains = [*swapchain_khr];
+        let images_indices = [image_index];
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(&signal_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&images_indices)
+            .build();
+        match unsafe { swapchain.queue_present(present_queue, &present_info) } {
+            Ok(is_suboptimal) => Ok(is_suboptimal),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(true),
+            Err(vk::Result::ERROR_DEVICE_LOST) => Err(vk::Result::ERROR_DEVICE_LOST),
+            Err(error) => panic!("Failed to present queue. Cause: {}", error),
+        }
+    }
+
+    fn update(&self, device: &Device, frame_ubo: UniformBufferObject) {
+        let camera = self.camera;
+        let aspect = self.extent.width as f32 / self.extent.height as f32;
+        let view = Matrix4::look_at(camera.eye, camera.target, camera.up);
+        let proj = math::perspective(camera.fov, aspect, 0.1, 10.0);
+        let ubo = UniformBufferObject {
+            screen_size: [self.extent.width as _, self.extent.height as _],
+            ..frame_ubo.with_camera(view, proj, camera.eye)
+        };
+        self.uniform_buffer.write(device, 0, &[ubo]);
+    }
+
+    /// Destroy the swapchain and everything depending on it.
+    ///
+    /// The device must be idle.
+    pub fn destroy_targets(&mut self, device: &Device, command_pool: vk::CommandPool) {
+        unsafe {
+            if !self.command_buffers.is_empty() {
+                device.free_command_buffers(command_pool, &self.command_buffers);
+            }
+            lifetime::destroy_pipeline(device, self.pipeline);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.framebuffers
+                .iter()
+                .for_each(|framebuffer| device.destroy_framebuffer(*framebuffer, None));
+            device.destroy_render_pass(self.render_pass, None);
+            self.image_views
+                .iter()
+                .for_each(|view| device.destroy_image_view(*view, None));
+            if let Some((swapchain, swapchain_khr)) = self.swapchain.take() {
+                swapchain.destroy_swapchain(swapchain_khr, None);
+            }
+        }
+        if let Some(mut depth_texture) = self.depth_texture.take() {
+            depth_texture.destroy(device);
+        }
+        self.command_buffers.clear();
+        self.pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+        self.framebuffers.clear();
+        self.render_pass = vk::RenderPass::null();
+        self.image_views.clear();
+    }
+
+    /// Replace the texture of the model by `texture` after it was reloaded.
+    ///
+    /// The sets must not be in use.
+    pub fn set_texture(&self, device: &Device, texture: Texture) {
+        write_model_texture(device, &[self.set], texture);
+    }
+
+    /// Destroy the window and all its Vulkan objects.
+    ///
+    /// The device must be idle.
+    pub fn destroy(&mut self, device: &Device, surface: &Surface, command_pool: vk::CommandPool) {
+        self.destroy_targets(device, command_pool);
+        unsafe {
+            device.destroy_semaphore(self.image_available_semaphore, None);
+            device.destroy_semaphore(self.render_finished_semaphore, None);
+            device.destroy_fence(self.fence, None);
+            lifetime::destroy_descriptor_pool(device, self.pool);
+            surface.destroy_surface(self.surface_khr, None);
+        }
+        self.uniform_buffer.destroy(device);
+    }
+}
@@ -0,0 +1,572 @@
+use crate::{context::VkContext, gpu_device::Device, stereo::*};
+use ash::{
+    version::DeviceV1_0,
+    vk::{self, Handle},
+    Instance,
+};
+use cgmath::{prelude::*, Matrix4, Quaternion, Vector3};
+use openxr as xr;
+use std::ffi::CString;
+
+const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+/// Formats of the headset swapchain by order of preference.
+const PREFERRED_FORMATS: [vk::Format; 2] = [vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB];
+
+/// Distance in meters from the initial head position to the model.
+const MODEL_DISTANCE: f32 = 1.5;
+
+const NEAR: f32 = 0.1;
+const FAR: f32 = 10.0;
+
+#[derive(Debug)]
+pub enum XrError {
+    /// The OpenXR loader could not be loaded.
+    Load(xr::LoadError),
+    /// A call to the runtime failed.
+    Runtime(xr::sys::Result),
+    /// The runtime does not support `XR_KHR_vulkan_enable`.
+    VulkanNotSupported,
+    /// The runtime does not support any of `PREFERRED_FORMATS`.
+    NoSupportedFormat,
+}
+
+impl std::error::Error for XrError {}
+
+impl std::fmt::Display for XrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XrError::Load(error) => write!(f, "Failed to load OpenXR: {}", error),
+            XrError::Runtime(result) => write!(f, "OpenXR call failed: {}", result),
+            XrError::VulkanNotSupported => write!(f, "XR_KHR_vulkan_enable is not supported"),
+            XrError::NoSupportedFormat => write!(f, "No supported swapchain format"),
+        }
+    }
+}
+
+impl From<xr::sys::Result> for XrError {
+    fn from(result: xr::sys::Result) -> Self {
+        XrError::Runtime(result)
+    }
+}
+
+/// OpenXR instance and head mounted display the app renders to.
+///
+/// It must be created before Vulkan since the runtime decides the extensions
+/// to enable on the Vulkan instance and device and the physical device to use.
+pub struct XrRuntime {
+    instance: xr::Instance,
+    system: xr::SystemId,
+}
+
+impl XrRuntime {
+    /// Load the OpenXR loader and find a head mounted display.
+    ///
+    /// # Errors
+    ///
+    /// Fail if there is no runtime, if it does not support Vulkan or if no
+    /// headset is connected.
+    pub fn new() -> Result<Self, XrError> {
+        let entry = unsafe { xr::Entry::load() }.map_err(XrError::Load)?;
+        if !entry.enumerate_extensions()?.khr_vulkan_enable {
+            return Err(XrError::VulkanNotSupported);
+        }
+
+        let mut extensions = xr::ExtensionSet::default();
+        extensions.khr_vulkan_enable = true;
+        let instance = entry.create_instance(
+            &xr::ApplicationInfo {
+                application_name: "Vulkan tutorial with Ash",
+                application_version: 1,
+                engine_name: "No Engine",
+                engine_version: 1,
+            },
+            &extensions,
+            &[],
+        )?;
+        let properties = instance.properties()?;
+        log::debug!(
+            "OpenXR runtime: {} {}",
+            properties.runtime_name,
+            properties.runtime_version
+        );
+
+        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+        Ok(XrRuntime { instance, system })
+    }
+
+    /// Get the Vulkan instance extensions required by the runtime.
+    pub fn instance_extensions(&self) -> Result<Vec<CString>, XrError> {
+        let names = self
+            .instance
+            .vulkan_legacy_instance_extensions(self.system)?;
+        Ok(split_extension_names(&names))
+    }
+
+    /// Get the Vulkan device extensions required by the runtime.
+    pub fn device_extensions(&self) -> Result<Vec<CString>, XrError> {
+        let names = self.instance.vulkan_legacy_device_extensions(self.system)?;
+        Ok(split_extension_names(&names))
+    }
+
+    /// Get the physical device driving the headset.
+    pub fn physical_device(&self, vk_instance: &Instance) -> Result<vk::PhysicalDevice, XrError> {
+        let device = unsafe {
+            self.instance
+                .vulkan_legacy_graphics_device(self.system, vk_instance.handle().as_raw() as _)?
+        };
+        Ok(vk::PhysicalDevice::from_raw(device as _))
+    }
+}
+
+/// Split the space separated extension names returned by the runtime.
+fn split_extension_names(names: &str) -> Vec<CString> {
+    names
+        .split_whitespace()
+        .map(|name| CString::new(name).unwrap())
+        .collect()
+}
+
+/// Views of the headset for a frame being rendered.
+struct XrFrame {
+    display_time: xr::Time,
+    views: Vec<xr::View>,
+}
+
+/// Session rendering the eyes of the stereo pass to the headset.
+///
+/// Each frame, the eyes are rendered with the head pose predicted by the
+/// runtime using the multiview path of `Stereo`, then blitted to a layered
+/// swapchain of the runtime and submitted as a projection layer. The blit
+/// waits for the frame to be rendered, which keeps the synchronization simple
+/// at the cost of some latency.
+pub struct XrSession {
+    session: xr::Session<xr::Vulkan>,
+    frame_waiter: xr::FrameWaiter,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    space: xr::Space,
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    images: Vec<vk::Image>,
+    extent: vk::Extent2D,
+    event_storage: xr::EventDataBuffer,
+    is_running: bool,
+    frame: Option<XrFrame>,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+
+impl XrSession {
+    /// Create the session and the swapchain of the headset using the device of `vk_context`.
+    ///
+    /// The device must be the one returned by `XrRuntime::physical_device` and
+    /// have the extensions returned by `XrRuntime::device_extensions` enabled.
+    /// `queue_family_index` is the graphics queue family, whose first queue
+    /// is used to render. The command buffer copying the eyes to the headset
+    /// is allocated from `command_pool`.
+    ///
+    /// # Errors
+    ///
+    /// Fail if the runtime cannot create the session or the swapchain.
+    pub fn new(
+        runtime: &XrRuntime,
+        vk_context: &VkContext,
+        queue_family_index: u32,
+        command_pool: vk::CommandPool,
+    ) -> Result<Self, XrError> {
+        // The requirements must be queried before creating the session
+        let requirements = runtime
+            .instance
+            .graphics_requirements::<xr::Vulkan>(runtime.system)?;
+        log::debug!(
+            "OpenXR Vulkan requirements: {} to {}",
+            requirements.min_api_version_supported,
+            requirements.max_api_version_supported
+        );
+
+        let device = vk_context.device();
+        let (session, frame_waiter, frame_stream) = unsafe {
+            runtime.instance.create_session::<xr::Vulkan>(
+                runtime.system,
+                &xr::vulkan::SessionCreateInfo {
+                    instance: vk_context.instance().handle().as_raw() as _,
+                    physical_device: vk_context.physical_device().as_raw() as _,
+                    device: device.handle().as_raw() as _,
+                    queue_family_index,
+                    queue_index: 0,
+                },
+            )?
+        };
+        let space =
+            session.create_reference_space(xr::ReferenceSpaceType::LOCAL, xr::Posef::IDENTITY)?;
+
+        let format = session
+            .enumerate_swapchain_formats()?
+            .into_iter()
+            .map(|format| vk::Format::from_raw(format as _))
+            .filter(|format| PREFERRED_FORMATS.contains(format))
+            .min_by_key(|format| PREFERRED_FORMATS.iter().position(|f| f == format))
+            .ok_or(XrError::NoSupportedFormat)?;
+        let view = runtime
+            .instance
+            .enumerate_view_configuration_views(runtime.system, VIEW_TYPE)?[0];
+        let extent = vk::Extent2D {
+            width: view.recommended_image_rect_width,
+            height: view.recommended_image_rect_height,
+        };
+        log::debug!(
+            "Creating OpenXR swapchain.\n\tFormat: {:?}\n\tExtent: {:?}",
+            format,
+            extent
+        );
+        let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+            create_flags: xr::SwapchainCreateFlags::EMPTY,
+            usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                | xr::SwapchainUsageFlags::TRANSFER_DST,
+            format: format.as_raw() as _,
+            sample_count: 1,
+            width: extent.width,
+            height: extent.height,
+            face_count: 1,
+            array_size: EYE_COUNT,
+            mip_count: 1,
+        })?;
+        let images = swapchain
+            .enumerate_images()?
+            .into_iter()
+            .map(vk::Image::from_raw)
+            .collect();
+
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1)
+            .build();
+        let command_buffer = unsafe { device.allocate_command_buffers(&allocate_info).unwrap()[0] };
+        let fence_info = vk::FenceCreateInfo::builder().build();
+        let fence = unsafe { device.create_fence(&fence_info, None).unwrap() };
+
+        Ok(XrSession {
+            session,
+            frame_waiter,
+            frame_stream,
+            space,
+            swapchain,
+            images,
+            extent,
+            event_storage: xr::EventDataBuffer::new(),
+            is_running: false,
+            frame: None,
+            command_buffer,
+            fence,
+        })
+    }
+}
+
+impl XrSession {
+    /// Process the events of the runtime, starting and stopping the session.
+    ///
+    /// Return whether the session is still alive.
+    pub fn poll_events(&mut self, runtime: &XrRuntime) -> Result<bool, XrError> {
+        while let Some(event) = runtime.instance.poll_event(&mut self.event_storage)? {
+            match event {
+                xr::Event::SessionStateChanged(event) => {
+                    log::debug!("OpenXR session state: {:?}", event.state());
+                    match event.state() {
+                        xr::SessionState::READY => {
+                            self.session.begin(VIEW_TYPE)?;
+                            self.is_running = true;
+                        }
+                        xr::SessionState::STOPPING => {
+                            self.session.end()?;
+                            self.is_running = false;
+                        }
+                        xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => {
+                            return Ok(false)
+                        }
+                        _ => {}
+                    }
+                }
+                xr::Event::InstanceLossPending(_) => return Ok(false),
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// Wait for the runtime to be ready for a new frame and get the head pose.
+    ///
+    /// Nothing is done if the session is not running. If the runtime does not
+    /// need the frame, it is ended right away and `eye_uniforms` returns `None`.
+    pub fn begin_frame(&mut self) -> Result<(), XrError> {
+        self.frame = None;
+        if !self.is_running {
+            return Ok(());
+        }
+
+        let state = self.frame_waiter.wait()?;
+        self.frame_stream.begin()?;
+        if !state.should_render {
+            self.frame_stream.end(
+                state.predicted_display_time,
+                xr::EnvironmentBlendMode::OPAQUE,
+                &[],
+            )?;
+            return Ok(());
+        }
+
+        let (_, views) =
+            self.session
+                .locate_views(VIEW_TYPE, state.predicted_display_time, &self.space)?;
+        self.frame = Some(XrFrame {
+            display_time: state.predicted_display_time,
+            views,
+        });
+        Ok(())
+    }
+
+    /// Get the matrices of the eyes of the frame begun with `begin_frame`.
+    ///
+    /// The model is placed `MODEL_DISTANCE` in front of the initial head position.
+    pub fn eye_uniforms(&self, model: Matrix4<f32>) -> Option<StereoUniforms> {
+        let frame = self.frame.as_ref()?;
+        let model = Matrix4::from_translation(Vector3::new(0.0, 0.0, -MODEL_DISTANCE)) * model;
+        let mut uniforms = StereoUniforms {
+            model,
+            views: [Matrix4::identity(); EYE_COUNT as usize],
+            projs: [Matrix4::identity(); EYE_COUNT as usize],
+        };
+        for (eye, view) in frame.views.iter().take(EYE_COUNT as _).enumerate() {
+            uniforms.views[eye] = view_matrix(view.pose);
+            uniforms.projs[eye] = projection(view.fov);
+        }
+        Some(uniforms)
+    }
+
+    /// Copy the eyes to the headset swapchain and submit the frame.
+    ///
+    /// `eyes` is the layered color attachment of the stereo pass and its extent.
+    /// It must be in the `SHADER_READ_ONLY_OPTIMAL` layout once the work
+    /// previously submitted to `queue` is done. Nothing is done if no frame
+    /// was begun.
+    pub fn submit_frame(
+        &mut self,
+        device: &Device,
+        queue: vk::Queue,
+        eyes: (vk::Image, vk::Extent2D),
+    ) -> Result<(), XrError> {
+        let frame = match self.frame.take() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let image_index = self.swapchain.acquire_image()?;
+        self.swapchain.wait_image(xr::Duration::INFINITE)?;
+        self.record_blit(device, eyes, self.images[image_index as usize]);
+        let command_buffers = [self.command_buffer];
+        let submit_infos = [vk::SubmitInfo::builder()
+            .command_buffers(&command_buffers)
+            .build()];
+        let fences = [self.fence];
+        unsafe {
+            device
+                .queue_submit(queue, &submit_infos, self.fence)
+                .unwrap();
+            device.wait_for_fences(&fences, true, u64::MAX).unwrap();
+            device.reset_fences(&fences).unwrap();
+        }
+        self.swapchain.release_image()?;
+
+        let rect = xr::Rect2Di {
+            offset: xr::Offset2Di { x: 0, y: 0 },
+            extent: xr::Extent2Di {
+                width: self.extent.width as _,
+                height: self.extent.height as _,
+            },
+        };
+        let views = frame
+            .views
+            .iter()
+            .take(EYE_COUNT as _)
+            .enumerate()
+            .map(|(eye, view)| {
+                xr::CompositionLayerProjectionView::new()
+                    .pose(view.pose)
+                    .fov(view.fov)
+                    .sub_image(
+                        xr::SwapchainSubImage::new()
+                            .swapchain(&self.swapchain)
+                            .image_array_index(eye as _)
+                            .image_rect(rect),
+                    )
+            })
+            .collect::<Vec<_>>();
+        self.frame_stream.end(
+            frame.display_time,
+            xr::EnvironmentBlendMode::OPAQUE,
+            &[&xr::CompositionLayerProjection::new()
+                .space(&self.space)
+                .views(&views)],
+        )?;
+        Ok(())
+    }
+
+    /// Record the blit of both layers of `eyes` to `target` in the command buffer.
+    fn record_blit(
+        &self,
+        device: &Device,
+        (eyes, eyes_extent): (vk::Image, vk::Extent2D),
+        target: vk::Image,
+    ) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: EYE_COUNT,
+        };
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: EYE_COUNT,
+        };
+        let barrier = |image, old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(subresource_range)
+                .build()
+        };
+        let offsets = |extent: vk::Extent2D| {
+            [
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: extent.width as _,
+                    y: extent.height as _,
+                    z: 1,
+                },
+            ]
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+        let buffer = self.command_buffer;
+        unsafe {
+            device.begin_command_buffer(buffer, &begin_info).unwrap();
+            // The eyes are rendered by the previous submission on the queue
+            device.cmd_pipeline_barrier(
+                buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        eyes,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    barrier(
+                        target,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                ],
+            );
+            device.cmd_blit_image(
+                buffer,
+                eyes,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                target,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageBlit {
+                    src_subresource: subresource,
+                    src_offsets: offsets(eyes_extent),
+                    dst_subresource: subresource,
+                    dst_offsets: offsets(self.extent),
+                }],
+                vk::Filter::LINEAR,
+            );
+            // The runtime expects the swapchain images as color attachments
+            device.cmd_pipeline_barrier(
+                buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        eyes,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::empty(),
+                    ),
+                    barrier(
+                        target,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::COLOR_ATTACHMENT_READ,
+                    ),
+                ],
+            );
+            device.end_command_buffer(buffer).unwrap();
+        }
+    }
+
+    /// Destroy the Vulkan objects of the session.
+    ///
+    /// The OpenXR objects are destroyed when the session is dropped, which
+    /// must happen before the Vulkan device is destroyed.
+    pub fn destroy(&mut self, device: &Device, command_pool: vk::CommandPool) {
+        unsafe {
+            device.free_command_buffers(command_pool, &[self.command_buffer]);
+            device.destroy_fence(self.fence, None);
+        }
+    }
+}
+
+/// Build the view matrix of an eye at `pose` in the reference space.
+fn view_matrix(pose: xr::Posef) -> Matrix4<f32> {
+    let orientation = pose.orientation;
+    let position = pose.position;
+    let rotation = Quaternion::new(orientation.w, orientation.x, orientation.y, orientation.z);
+    let eye = Matrix4::from_translation(Vector3::new(position.x, position.y, position.z))
+        * Matrix4::from(rotation);
+    eye.invert().unwrap()
+}
+
+/// Build the projection matrix of an eye with the asymmetric field of view `fov`.
+///
+/// Like `math::perspective`, it inverts the y-axis and maps the depth to 0..1.
+fn projection(fov: xr::Fovf) -> Matrix4<f32> {
+    let left = fov.angle_left.tan();
+    let right = fov.angle_right.tan();
+    let up = fov.angle_up.tan();
+    let down = fov.angle_down.tan();
+    let width = right - left;
+    let height = up - down;
+
+    #[cfg_attr(rustfmt, rustfmt::skip)]
+    Matrix4::new(
+        2.0 / width, 0.0, 0.0, 0.0,
+        0.0, -2.0 / height, 0.0, 0.0,
+        (right + left) / width, -(up + down) / height, -FAR / (FAR - NEAR), -1.0,
+        0.0, 0.0, -(FAR * NEAR) / (FAR - NEAR), 0.0,
+    )
+}
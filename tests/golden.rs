@@ -0,0 +1,198 @@
+//! Golden image tests.
+//!
+//! Each test renders a fixed scene with the headless mode of the app and
+//! compares it with the reference image in `tests/golden`. When they differ,
+//! the rendered image and an image highlighting the differences are written
+//! in `target/golden`.
+//!
+//! They need a Vulkan device so they are ignored by default. Run them with:
+//!
+//! ```sh
+//! cargo test --test golden -- --ignored
+//! ```
+//!
+//! Set `UPDATE_GOLDEN=1` to write the rendered images as the new references.
+//! Without it, a scene whose reference is missing fails before being rendered.
+//! Until the references are committed in `tests/golden`, the scenes are
+//! skipped instead.
+
+use image::{Rgba, RgbaImage};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Maximum perceptual difference between two pixels considered equal, from 0 to 1.
+///
+/// It absorbs small variations between drivers, like rasterization or
+/// filtering precision.
+const PIXEL_THRESHOLD: f32 = 0.1;
+
+/// Maximum ratio of pixels allowed to differ.
+const MAX_DIFFERENT_PIXELS: f32 = 0.001;
+
+/// Maximum squared YIQ distance between two colors.
+const MAX_YIQ_DELTA: f32 = 35215.0;
+
+#[test]
+#[ignore]
+fn golden_model() {
+    check_scene("model", &[]);
+}
+
+#[test]
+#[ignore]
+fn golden_fog() {
+    check_scene("fog", &["--fog=exponential", "--height-fog"]);
+}
+
+#[test]
+#[ignore]
+fn golden_sky() {
+    check_scene("sky", &["--sky"]);
+}
+
+#[test]
+#[ignore]
+fn golden_decals() {
+    check_scene("decals", &["--decals"]);
+}
+
+#[test]
+#[ignore]
+fn golden_sprites() {
+    check_scene("sprites", &["--sprites", "--time=1.5"]);
+}
+
+/// Render the scene `name` with the extra arguments `args` and compare it with its reference.
+///
+/// # Panics
+///
+/// Panic if the reference of the scene is missing, unless the references are
+/// updated or none is committed yet.
+fn check_scene(name: &str, args: &[&str]) {
+    let reference_dir = manifest_dir().join("tests").join("golden");
+    let reference_path = reference_dir.join(format!("{}.png", name));
+    let update = std::env::var("UPDATE_GOLDEN").ok().as_deref() == Some("1");
+    if !update && !reference_dir.is_dir() {
+        eprintln!(
+            "Skipping {}: the golden references are not committed yet. Render them with \
+             UPDATE_GOLDEN=1 on a machine with a Vulkan device.",
+            name
+        );
+        return;
+    }
+    // Checked first so a missing reference is not mistaken for a rendering failure
+    assert!(
+        update || reference_path.is_file(),
+        "Missing reference {}. Render it with UPDATE_GOLDEN=1 on a machine with a Vulkan device \
+         and commit it.",
+        reference_path.display()
+    );
+
+    let output_dir = manifest_dir().join("target").join("golden");
+    std::fs::create_dir_all(&output_dir).unwrap();
+    let actual_path = output_dir.join(format!("{}.png", name));
+    render(&actual_path, args);
+    let actual = image::open(&actual_path).unwrap().to_rgba();
+
+    if update {
+        std::fs::create_dir_all(&reference_dir).unwrap();
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.to_rgba(),
+        Err(error) => panic!(
+            "Failed to open reference {}: {}",
+            reference_path.display(),
+            error
+        ),
+    };
+    assert_eq!(
+        actual.dimensions(),
+        reference.dimensions(),
+        "{} does not have the size of its reference",
+        name
+    );
+
+    let (different_pixels, diff) = compare(&actual, &reference);
+    let pixel_count = actual.width() * actual.height();
+    let ratio = different_pixels as f32 / pixel_count as f32;
+    if ratio > MAX_DIFFERENT_PIXELS {
+        let diff_path = output_dir.join(format!("{}.diff.png", name));
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "{} differs from its reference on {} pixels ({:.3}%). See {} and {}.",
+            name,
+            different_pixels,
+            ratio * 100.0,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+/// Run the app headless to render a frame at `path`.
+fn render(path: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_vulkan-tutorial-ash"))
+        .current_dir(manifest_dir())
+        .arg(format!("--headless={}", path.display()))
+        .arg("--deterministic")
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "Headless rendering failed: {}", status);
+}
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Compare `actual` and `reference` pixel by pixel.
+///
+/// # Returns
+///
+/// The number of different pixels and an image showing them in red over a
+/// faded grayscale version of the reference.
+fn compare(actual: &RgbaImage, reference: &RgbaImage) -> (u32, RgbaImage) {
+    let mut different_pixels = 0;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let actual = actual.get_pixel(x, y);
+        let reference = reference.get_pixel(x, y);
+        if color_delta(actual, reference) > PIXEL_THRESHOLD * PIXEL_THRESHOLD * MAX_YIQ_DELTA {
+            different_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let gray = (255.0 - 0.1 * (255.0 - luma(reference))) as u8;
+            Rgba([gray, gray, gray, 255])
+        }
+    });
+    (different_pixels, diff)
+}
+
+/// Get the squared perceptual distance between two colors.
+///
+/// Colors are blended on white then compared in the YIQ space, weighting
+/// luminance more than chrominance like the human eye does.
+fn color_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let (ya, ia, qa) = yiq(a);
+    let (yb, ib, qb) = yiq(b);
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+fn yiq(color: &Rgba<u8>) -> (f32, f32, f32) {
+    let alpha = f32::from(color[3]) / 255.0;
+    let blend = |channel: u8| 255.0 + (f32::from(channel) - 255.0) * alpha;
+    let (r, g, b) = (blend(color[0]), blend(color[1]), blend(color[2]));
+    (
+        0.2989 * r + 0.5866 * g + 0.1145 * b,
+        0.5960 * r - 0.2742 * g - 0.3218 * b,
+        0.2115 * r - 0.5226 * g + 0.3111 * b,
+    )
+}
+
+fn luma(color: &Rgba<u8>) -> f32 {
+    yiq(color).0
+}
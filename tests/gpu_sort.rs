@@ -0,0 +1,52 @@
+//! GPU sort tests.
+//!
+//! Each test runs the app with `--sort-check=<count>`, which sorts pseudo
+//! random keys on the GPU and compares them with a stable CPU sort, then
+//! exits with an error if they differ.
+//!
+//! They need a Vulkan device so they are ignored by default. Run them with:
+//!
+//! ```sh
+//! cargo test --test gpu_sort -- --ignored
+//! ```
+
+use std::{path::PathBuf, process::Command};
+
+#[test]
+#[ignore]
+fn sort_single_block() {
+    check_sort(200);
+}
+
+#[test]
+#[ignore]
+fn sort_partial_blocks() {
+    check_sort(1000);
+}
+
+#[test]
+#[ignore]
+fn sort_many_blocks() {
+    // More offsets than a work group scans at once
+    check_sort(100_000);
+}
+
+/// Sort `count` keys on the GPU and check that they match the CPU sort.
+fn check_sort(count: u32) {
+    let status = Command::new(env!("CARGO_BIN_EXE_vulkan-tutorial-ash"))
+        .current_dir(manifest_dir())
+        .arg("--headless")
+        .arg(format!("--sort-check={}", count))
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "GPU sort of {} keys differs from the CPU sort: {}",
+        count,
+        status
+    );
+}
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}